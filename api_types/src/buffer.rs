@@ -21,16 +21,26 @@ impl BufferCreateInfo {
 #[derive(Clone)]
 pub struct BufferWrapper {
     pub buffer: vk::Buffer,
-    pub create_info: vk::BufferCreateInfo
+    pub create_info: vk::BufferCreateInfo,
+    pub device_address: Option<vk::DeviceAddress>,
+    /// Suballocated anew every frame (see DynamicUploadBuffer), so a range bound one frame
+    /// holds something else the next
+    pub per_frame: bool
 }
 
 impl BufferWrapper {
     pub fn new(buffer: vk::Buffer, create_info: vk::BufferCreateInfo) -> BufferWrapper {
         BufferWrapper {
             buffer,
-            create_info
+            create_info,
+            device_address: None,
+            per_frame: false
         }
     }
 
     pub fn get(&self) -> vk::Buffer { self.buffer }
+
+    /// The buffer's GPU pointer, for buffers with SHADER_DEVICE_ADDRESS usage on devices with
+    /// NegotiatedFeature::BufferDeviceAddress enabled
+    pub fn get_device_address(&self) -> Option<vk::DeviceAddress> { self.device_address }
 }
\ No newline at end of file
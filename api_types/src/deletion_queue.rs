@@ -0,0 +1,58 @@
+use std::collections::VecDeque;
+use ash::vk;
+use gpu_allocator::vulkan::Allocation;
+use crate::image::ImageWrapper;
+
+/// A Vulkan object whose destruction has been deferred until the GPU is done with it
+pub enum DeferredDestruction {
+    Buffer(vk::Buffer),
+    Image(ImageWrapper),
+    ImageView(vk::ImageView),
+    Allocation(Allocation),
+    Memory(vk::DeviceMemory),
+    RenderPass(vk::RenderPass),
+    Pipeline(vk::Pipeline),
+    /// Also frees every command buffer allocated from the pool
+    CommandPool(vk::CommandPool),
+    /// Also frees every descriptor set allocated from the pool
+    DescriptorPool(vk::DescriptorPool)
+}
+
+/// Destructions queued in submission order along with the value of the frame
+/// that was being recorded when they were queued
+pub struct DeletionQueue {
+    pending: VecDeque<(u64, DeferredDestruction)>
+}
+
+impl DeletionQueue {
+    pub fn new() -> Self {
+        DeletionQueue {
+            pending: VecDeque::new()
+        }
+    }
+
+    pub fn push(&mut self, frame_value: u64, destruction: DeferredDestruction) {
+        self.pending.push_back((frame_value, destruction));
+    }
+
+    /// Removes every destruction queued at or before `completed_value`
+    pub fn drain_completed(&mut self, completed_value: u64) -> Vec<DeferredDestruction> {
+        let mut completed = Vec::new();
+        while let Some((frame_value, _)) = self.pending.front() {
+            if *frame_value > completed_value {
+                break;
+            }
+            completed.push(self.pending.pop_front().unwrap().1);
+        }
+
+        completed
+    }
+
+    pub fn drain_all(&mut self) -> Vec<DeferredDestruction> {
+        self.pending.drain(..).map(|(_, destruction)| destruction).collect()
+    }
+
+    pub fn len(&self) -> usize { self.pending.len() }
+
+    pub fn is_empty(&self) -> bool { self.pending.is_empty() }
+}
@@ -1,4 +1,5 @@
 use std::cell::RefCell;
+use std::collections::HashMap;
 use std::ffi::{CString};
 use core::ffi::c_void;
 use std::alloc::alloc;
@@ -11,7 +12,14 @@ use gpu_allocator::vulkan::*;
 use gpu_allocator::MemoryLocation;
 use log::trace;
 use crate::buffer::{BufferCreateInfo, BufferWrapper};
-use crate::image::{ImageCreateInfo, ImageType, ImageWrapper};
+use crate::image::{ImageCreateInfo, ImageWrapper};
+use crate::resource_state::{ResourceState, ResourceStateRegistry};
+use crate::deletion_queue::{DeferredDestruction, DeletionQueue};
+use crate::device_capabilities::{query_device_capabilities, DeviceFeatures, DeviceLimits, EnabledFeatures, NegotiatedFeature};
+use crate::format_selector::FormatSelector;
+use crate::name::Name;
+#[cfg(feature = "external-memory")]
+use crate::external_memory::{ExternalHandle, ExternalMemory};
 
 pub struct VulkanDebug {
     pub debug_utils: DebugUtils,
@@ -29,7 +37,10 @@ impl Debug for VulkanDebug {
 pub struct QueueFamilies {
     pub graphics: Option<u32>,
     pub compute: Option<u32>,
-    pub present: Option<u32>
+    pub present: Option<u32>,
+    /// A family which only supports transfers (and possibly sparse binding), usually backed
+    /// by a DMA engine which runs alongside graphics work. Not required
+    pub transfer: Option<u32>
 }
 
 impl QueueFamilies {
@@ -93,12 +104,31 @@ pub struct DeviceWrapper {
     queue_family_indices: QueueFamilies,
     allocator: Allocator,
     device: DeviceLifetime,
-    device_limits: vk::PhysicalDeviceLimits
+    device_limits: vk::PhysicalDeviceLimits,
+    features: DeviceFeatures,
+    limits: DeviceLimits,
+    enabled_features: EnabledFeatures,
+    formats: FormatSelector,
+    resource_states: ResourceStateRegistry,
+    deletion_queue: DeletionQueue,
+    // value of the frame currently being recorded; see advance_frame
+    frame_value: u64,
+    // see push_allocation_tag
+    allocation_tags: Vec<Name>,
+    tagged_allocations: HashMap<Name, vk::DeviceSize>,
+    // sizes of images and buffers created outside of any tag, by resource handle
+    untagged_allocations: HashMap<u64, vk::DeviceSize>,
+    #[cfg(feature = "external-memory")]
+    external_memory: ExternalMemory
 }
 
 impl Drop for DeviceWrapper {
     fn drop(&mut self) {
         unsafe {
+            // the device is expected to be idle by the time it's dropped
+            for destruction in self.deletion_queue.drain_all() {
+                self.destroy_deferred(destruction);
+            }
             if let Some(debug) = &self.debug {
                 debug.debug_utils.destroy_debug_utils_messenger(debug.debug_messenger, None);
             }
@@ -118,8 +148,13 @@ pub enum ResourceType {
 pub struct DeviceResource {
     pub allocation: Option<Allocation>,
     pub resource_type: Option<ResourceType>,
+    // dedicated memory allocated outside of gpu-allocator (i.e. for external memory)
+    pub device_memory: Option<vk::DeviceMemory>,
 
     handle: u64,
+    name: String,
+    // externally-owned resources only have their views destroyed
+    external: bool,
     device: Rc<RefCell<DeviceWrapper>>
 }
 
@@ -127,27 +162,92 @@ impl Debug for DeviceResource {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("DeviceResource")
             .field("handle", &self.handle)
+            .field("name", &self.name)
             .finish()
     }
 }
 
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ResourceKind {
+    Image,
+    Buffer
+}
+
+impl std::fmt::Display for ResourceKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ResourceKind::Image => write!(f, "an image"),
+            ResourceKind::Buffer => write!(f, "a buffer")
+        }
+    }
+}
+
+/// A resource which couldn't be resolved as the kind of resource it's used as, or at all.
+/// `pass` is the pass which asked for it, when the resolution happened on behalf of one
+#[derive(Clone, Debug)]
+pub struct ResourceError {
+    pub handle: u64,
+    pub name: String,
+    pub expected: Option<ResourceKind>,
+    // None when the resource is unresolved
+    pub found: Option<ResourceKind>,
+    pub pass: Option<String>
+}
+
+impl ResourceError {
+    pub fn in_pass(mut self, pass: &str) -> Self {
+        self.pass = Some(pass.to_string());
+        self
+    }
+}
+
+impl std::fmt::Display for ResourceError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if let Some(pass) = &self.pass {
+            write!(f, "Pass \"{}\": ", pass)?;
+        }
+        write!(f, "resource {} (\"{}\") ", self.handle, self.name)?;
+        match (self.found, self.expected) {
+            (Some(found), Some(expected)) => write!(f, "is {}, but is used as {}", found, expected),
+            (Some(found), None) => write!(f, "is {}", found),
+            (None, _) => write!(f, "is unresolved")
+        }
+    }
+}
+
+impl std::error::Error for ResourceError {}
+
 impl Drop for DeviceResource {
     fn drop(&mut self) {
-        if let Some(resource_type) = &mut self.resource_type {
+        // resources without a resource type own nothing, so they don't borrow the device
+        if let Some(resource_type) = self.resource_type.take() {
+            // in-flight command buffers may still reference this resource, so destruction
+            // is deferred until the frame being recorded has completed
+            let mut device = self.device.borrow_mut();
+            device.remove_resource_state(self.handle);
             match resource_type {
                 ResourceType::Buffer(buffer) => {
-                    log::trace!(target: "resource", "Destroying buffer: {}", self.handle);
-                    self.device.borrow_mut().destroy_buffer(buffer);
+                    if !self.external {
+                        log::trace!(target: "resource", "Destroying buffer: {}", self.handle);
+                        device.defer_destruction(DeferredDestruction::Buffer(buffer.buffer));
+                    }
                 },
                 ResourceType::Image(image) => {
-                    log::trace!(target: "resource", "Destroying image: {}", self.handle);
-                    self.device.borrow_mut().destroy_image(image);
+                    if self.external {
+                        log::trace!(target: "resource", "Releasing external image: {}", self.handle);
+                        device.defer_destruction(DeferredDestruction::ImageView(image.view));
+                    } else {
+                        log::trace!(target: "resource", "Destroying image: {}", self.handle);
+                        device.defer_destruction(DeferredDestruction::Image(image));
+                    }
                 }
             }
-        }
-        if let Some(alloc) = &mut self.allocation {
-            let moved = std::mem::replace(alloc, Allocation::default());
-            self.device.borrow_mut().free_allocation(moved);
+            if let Some(alloc) = self.allocation.take() {
+                device.defer_destruction(DeferredDestruction::Allocation(alloc));
+            }
+            if let Some(memory) = self.device_memory.take() {
+                device.defer_destruction(DeferredDestruction::Memory(memory));
+            }
         }
     }
 }
@@ -160,63 +260,80 @@ impl PartialEq<Self> for DeviceResource {
 impl Eq for DeviceResource {}
 
 impl DeviceResource {
-    pub fn get_image(&self) -> &ImageWrapper {
+    /// The kind of resource this is, or None while it's unresolved
+    pub fn get_kind(&self) -> Option<ResourceKind> {
         match &self.resource_type {
-            Some(resolved_resource) => {
-                match &resolved_resource {
-                    ResourceType::Image(image) => {
-                        image
-                    },
-                    _ => {
-                        panic!("Non-image resource type")
-                    }
-                }
-            },
-            None => {
-                panic!("Unresolved resource")
-            }
+            Some(ResourceType::Image(_)) => Some(ResourceKind::Image),
+            Some(ResourceType::Buffer(_)) => Some(ResourceKind::Buffer),
+            None => None
         }
     }
 
-    pub fn get_image_mut(&mut self) -> &mut ImageWrapper {
+    fn resource_error(&self, expected: Option<ResourceKind>) -> ResourceError {
+        ResourceError {
+            handle: self.handle,
+            name: self.name.clone(),
+            expected,
+            found: self.get_kind(),
+            pass: None
+        }
+    }
+
+    pub fn try_resolved(&self) -> Result<&ResourceType, ResourceError> {
+        self.resource_type.as_ref().ok_or_else(|| self.resource_error(None))
+    }
+
+    pub fn try_get_image(&self) -> Result<&ImageWrapper, ResourceError> {
+        match &self.resource_type {
+            Some(ResourceType::Image(image)) => Ok(image),
+            _ => Err(self.resource_error(Some(ResourceKind::Image)))
+        }
+    }
+
+    pub fn try_get_image_mut(&mut self) -> Result<&mut ImageWrapper, ResourceError> {
+        if !matches!(self.resource_type, Some(ResourceType::Image(_))) {
+            return Err(self.resource_error(Some(ResourceKind::Image)));
+        }
         match self.resource_type.as_mut() {
-            Some(resolved_resource) => {
-                match resolved_resource {
-                    ResourceType::Image(image) => {
-                        image
-                    },
-                    _ => {
-                        panic!("Non-image resource type")
-                    }
-                }
-            },
-            None => {
-                panic!("Unresolved resource")
-            }
+            Some(ResourceType::Image(image)) => Ok(image),
+            _ => unreachable!()
         }
     }
 
-    pub fn get_buffer(&self) -> &BufferWrapper {
+    pub fn try_get_buffer(&self) -> Result<&BufferWrapper, ResourceError> {
         match &self.resource_type {
-            Some(resolved_resource) => {
-                match &resolved_resource {
-                    ResourceType::Buffer(buffer) => {
-                       buffer
-                    },
-                    _ => {
-                        panic!("Non-buffer resource type")
-                    }
-                }
-            },
-            None => {
-                panic!("Unresolved resource")
-            }
+            Some(ResourceType::Buffer(buffer)) => Ok(buffer),
+            _ => Err(self.resource_error(Some(ResourceKind::Buffer)))
         }
     }
 
+    pub fn get_image(&self) -> &ImageWrapper {
+        self.try_get_image().unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    pub fn get_image_mut(&mut self) -> &mut ImageWrapper {
+        self.try_get_image_mut().unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    pub fn get_buffer(&self) -> &BufferWrapper {
+        self.try_get_buffer().unwrap_or_else(|error| panic!("{}", error))
+    }
+
     pub fn get_handle(&self) -> u64 {
         self.handle
     }
+
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn is_external(&self) -> bool {
+        self.external
+    }
+
+    pub fn get_device(&self) -> Rc<RefCell<DeviceWrapper>> {
+        self.device.clone()
+    }
 }
 
 // pub struct DeviceDescriptorSet {
@@ -269,17 +386,28 @@ impl DeviceWrapper {
         physical_device: &PhysicalDeviceWrapper,
         physical_device_properties: vk::PhysicalDeviceProperties,
         debug: Option<VulkanDebug>,
-        queue_family_indices: QueueFamilies) -> DeviceWrapper {
+        queue_family_indices: QueueFamilies,
+        enabled_features: EnabledFeatures) -> DeviceWrapper {
 
         let allocator = Allocator::new(&AllocatorCreateDesc {
             instance: instance.clone(),
             device: device.clone(),
             physical_device: physical_device.get(),
             debug_settings: Default::default(),
-            buffer_device_address: false, // https://registry.khronos.org/vulkan/specs/1.3-extensions/man/html/VkPhysicalDeviceBufferDeviceAddressFeaturesEXT.html
+            // allocates all memory with DEVICE_ADDRESS, as buffers may need it
+            buffer_device_address: enabled_features.is_enabled(NegotiatedFeature::BufferDeviceAddress),
             allocation_sizes: Default::default(), // TODO: optimize allocation block sizes?
         }).expect("Failed to create GPU memory allocator");
 
+        #[cfg(feature = "external-memory")]
+        let external_memory = ExternalMemory::new(instance, &device, physical_device.get());
+
+        let (features, limits) = query_device_capabilities(
+            instance,
+            physical_device.get(),
+            &physical_device_properties,
+            &queue_family_indices);
+
         DeviceWrapper {
             device: DeviceLifetime::new(device),
             debug,
@@ -287,6 +415,18 @@ impl DeviceWrapper {
             allocator,
             handle_generator: 0,
             device_limits: physical_device_properties.limits,
+            features,
+            limits,
+            enabled_features,
+            formats: FormatSelector::new(instance, physical_device.get()),
+            resource_states: ResourceStateRegistry::new(),
+            deletion_queue: DeletionQueue::new(),
+            frame_value: 0,
+            allocation_tags: Vec::new(),
+            tagged_allocations: HashMap::new(),
+            untagged_allocations: HashMap::new(),
+            #[cfg(feature = "external-memory")]
+            external_memory
         }
     }
     pub fn get(&self) -> &ash::Device {
@@ -294,11 +434,113 @@ impl DeviceWrapper {
     }
     pub fn get_queue_family_indices(&self) -> &QueueFamilies { &self.queue_family_indices }
 
+    pub fn get_device_limits(&self) -> &vk::PhysicalDeviceLimits { &self.device_limits }
+
+    /// Optional capabilities of the physical device, e.g. timestamp support on each queue
+    pub fn features(&self) -> &DeviceFeatures { &self.features }
+
+    /// Limits which passes and pipeline descriptions can adapt to at runtime
+    pub fn limits(&self) -> &DeviceLimits { &self.limits }
+
+    /// The negotiated features enabled when the device was created
+    pub fn enabled_features(&self) -> &EnabledFeatures { &self.enabled_features }
+
+    /// Picks image formats the device supports, see FormatSelector
+    pub fn formats(&self) -> &FormatSelector { &self.formats }
+
+    pub fn is_feature_enabled(&self, feature: NegotiatedFeature) -> bool {
+        self.enabled_features.is_enabled(feature)
+    }
+
+    /// Whether descriptors are written into descriptor buffers rather than descriptor sets
+    pub fn uses_descriptor_buffers(&self) -> bool {
+        self.is_feature_enabled(NegotiatedFeature::DescriptorBuffer)
+    }
+
     pub fn free_allocation(&mut self, allocation: Allocation) {
         self.allocator.free(allocation)
             .expect("Failed to free Device allocation");
     }
 
+    /// Queues an object for destruction once the frame currently being recorded has completed
+    pub fn defer_destruction(&mut self, destruction: DeferredDestruction) {
+        self.deletion_queue.push(self.frame_value, destruction);
+    }
+
+    /// Called when the frame currently being recorded is submitted. Returns the value
+    /// identifying that frame, to be passed to complete_frame once its fence has signaled
+    pub fn advance_frame(&mut self) -> u64 {
+        let submitted = self.frame_value;
+        self.frame_value += 1;
+        submitted
+    }
+
+    /// Destroys everything queued during or before the frame identified by `frame_value`
+    pub fn complete_frame(&mut self, frame_value: u64) {
+        for destruction in self.deletion_queue.drain_completed(frame_value) {
+            self.destroy_deferred(destruction);
+        }
+    }
+
+    /// Waits for the device to go idle and destroys everything queued by frames which have
+    /// been submitted, for callers that don't retire frames through fences (e.g. headless
+    /// tools, or before tearing down a window)
+    pub fn wait_idle(&mut self) {
+        unsafe {
+            self.device.get().device_wait_idle()
+                .expect("Failed to wait for device idle");
+        }
+        if let Some(submitted) = self.frame_value.checked_sub(1) {
+            self.complete_frame(submitted);
+        }
+    }
+
+    fn destroy_deferred(&mut self, destruction: DeferredDestruction) {
+        match destruction {
+            DeferredDestruction::Buffer(buffer) => {
+                unsafe {
+                    self.device.get().destroy_buffer(buffer, None);
+                }
+            },
+            DeferredDestruction::Image(image) => {
+                self.destroy_image(&image);
+            },
+            DeferredDestruction::ImageView(view) => {
+                unsafe {
+                    self.device.get().destroy_image_view(view, None);
+                }
+            },
+            DeferredDestruction::Allocation(allocation) => {
+                self.free_allocation(allocation);
+            },
+            DeferredDestruction::Memory(memory) => {
+                unsafe {
+                    self.device.get().free_memory(memory, None);
+                }
+            },
+            DeferredDestruction::RenderPass(renderpass) => {
+                unsafe {
+                    self.device.get().destroy_render_pass(renderpass, None);
+                }
+            },
+            DeferredDestruction::Pipeline(pipeline) => {
+                unsafe {
+                    self.device.get().destroy_pipeline(pipeline, None);
+                }
+            },
+            DeferredDestruction::CommandPool(command_pool) => {
+                unsafe {
+                    self.device.get().destroy_command_pool(command_pool, None);
+                }
+            },
+            DeferredDestruction::DescriptorPool(descriptor_pool) => {
+                unsafe {
+                    self.device.get().destroy_descriptor_pool(descriptor_pool, None);
+                }
+            }
+        }
+    }
+
     pub fn destroy_buffer(&mut self, buffer: &BufferWrapper) {
         unsafe {
             self.device.get().destroy_buffer(buffer.buffer, None);
@@ -325,12 +567,29 @@ impl DeviceWrapper {
         image_view_flags: vk::ImageViewCreateFlags,
         aspect_flags: vk::ImageAspectFlags,
         mip_levels: u32) -> vk::ImageView
+    {
+        self.create_layered_image_view(image, format, image_view_flags, aspect_flags, mip_levels, 1)
+    }
+
+    /// Views all `array_layers` of an image, as a 2D array view when there's more than one so
+    /// it can be rendered to with multiview or layered rendering
+    pub fn create_layered_image_view(
+        &self,
+        image: vk::Image,
+        format: vk::Format,
+        image_view_flags: vk::ImageViewCreateFlags,
+        aspect_flags: vk::ImageAspectFlags,
+        mip_levels: u32,
+        array_layers: u32) -> vk::ImageView
     {
         let create_info = vk::ImageViewCreateInfo {
             s_type: vk::StructureType::IMAGE_VIEW_CREATE_INFO,
             p_next: std::ptr::null(),
             flags: image_view_flags,
-            view_type: vk::ImageViewType::TYPE_2D,
+            view_type: match array_layers {
+                1 => vk::ImageViewType::TYPE_2D,
+                _ => vk::ImageViewType::TYPE_2D_ARRAY
+            },
             format,
             components: vk::ComponentMapping {
                 r: vk::ComponentSwizzle::IDENTITY,
@@ -343,7 +602,7 @@ impl DeviceWrapper {
                 base_mip_level: 0,
                 level_count: mip_levels,
                 base_array_layer: 0,
-                layer_count: 1
+                layer_count: array_layers
             },
             image: image
         };
@@ -356,6 +615,11 @@ impl DeviceWrapper {
 
     pub fn set_debug_name(&self, object_type: vk::ObjectType, handle: u64, name: &str)
     {
+        // objects are named every frame (descriptor sets, framebuffers), so skip building the
+        // name when there's no debug messenger to give it to
+        let Some(debug) = &self.debug else {
+            return;
+        };
         let c_name = CString::new(name)
             .expect("Failed to create C-name for debug object");
         let debug_info = DebugUtilsObjectNameInfoEXT::builder()
@@ -364,10 +628,8 @@ impl DeviceWrapper {
             .object_name(&c_name)
             .build();
         unsafe {
-            if let Some(debug) = &self.debug {
-                debug.debug_utils.debug_utils_set_object_name(self.device.get().handle(), &debug_info)
-                    .expect("Failed to set debug object name");
-            }
+            debug.debug_utils.debug_utils_set_object_name(self.device.get().handle(), &debug_info)
+                .expect("Failed to set debug object name");
         }
     }
 
@@ -393,6 +655,56 @@ impl DeviceWrapper {
         }).expect("Failed to allocate memory for Device resource")
     }
 
+    /// Attributes the allocation of a new image or buffer to the innermost allocation tag,
+    /// or keeps it by `handle` if there is none (see take_untagged_allocations)
+    fn track_allocation(&mut self, handle: u64, name: &str, size: vk::DeviceSize) {
+        match self.allocation_tags.last() {
+            Some(tag) => {
+                trace!(target: "resource", "Allocation of {} ({} bytes) tagged with {}", name, size, tag);
+                *self.tagged_allocations.entry(*tag).or_insert(0) += size;
+            },
+            None => {
+                self.untagged_allocations.insert(handle, size);
+            }
+        }
+    }
+
+    /// Attributes allocations made until the matching pop_allocation_tag to `tag`. The
+    /// framegraph tags each node's fill callback with the node's name. Tags nest, the innermost
+    /// receives the bytes
+    pub fn push_allocation_tag(&mut self, tag: impl Into<Name>) {
+        self.allocation_tags.push(tag.into());
+    }
+
+    pub fn pop_allocation_tag(&mut self) {
+        self.allocation_tags.pop().expect("No allocation tag to pop");
+    }
+
+    /// Bytes allocated under each tag since the last call
+    pub fn take_tagged_allocations(&mut self) -> HashMap<Name, vk::DeviceSize> {
+        std::mem::take(&mut self.tagged_allocations)
+    }
+
+    /// Bytes allocated for each image and buffer created outside of any tag since the last
+    /// call, by resource handle. The framegraph attributes them to the first node using them
+    pub fn take_untagged_allocations(&mut self) -> HashMap<u64, vk::DeviceSize> {
+        std::mem::take(&mut self.untagged_allocations)
+    }
+
+    pub fn get_resource_state(&self, handle: u64) -> Option<ResourceState> {
+        self.resource_states.get(handle)
+    }
+
+    /// Any code recording work against a resource outside of the framegraph must
+    /// update its state here so the next frame transitions from the correct layout
+    pub fn update_resource_state(&mut self, handle: u64, state: ResourceState) {
+        self.resource_states.update(handle, state);
+    }
+
+    pub fn remove_resource_state(&mut self, handle: u64) {
+        self.resource_states.remove(handle);
+    }
+
     pub fn generate_handle(
         &mut self
     ) -> u64 {
@@ -427,6 +739,7 @@ impl DeviceWrapper {
                 memory_requirements,
                 memory_location,
                 false);
+            device.borrow_mut().track_allocation(new_handle, image_desc.get_name(), allocation.size());
 
             unsafe {
                 device.borrow().get().bind_image_memory(
@@ -436,30 +749,18 @@ impl DeviceWrapper {
                     .expect("Failed to bind image to memory");
             }
 
-            let aspect_flags = match image_desc.get_image_type() {
-                ImageType::Color => {
-                    vk::ImageAspectFlags::COLOR
-                }
-                ImageType::Depth => {
-                    vk::ImageAspectFlags::DEPTH
-                }
-                ImageType::DepthStencil => {
-                    vk::ImageAspectFlags::STENCIL | vk::ImageAspectFlags::DEPTH
-                }
-                ImageType::Stencil => {
-                    vk::ImageAspectFlags::STENCIL
-                }
-            };
+            let aspect_flags = image_desc.get_image_type().get_aspect_flags();
 
-            let image_view = device.borrow().create_image_view(
+            let image_view = device.borrow().create_layered_image_view(
                 image,
                 // vk::Format::R8G8B8A8_SRGB,
                 image_desc.get_create_info().format,
                 vk::ImageViewCreateFlags::empty(),
                 aspect_flags,
-                1);
+                1,
+                create_info.array_layers.max(1));
             device.borrow().set_debug_name(vk::ObjectType::IMAGE_VIEW, image_view.as_raw(), image_desc.get_name());
-            let image_wrapper = ImageWrapper::new(
+            let mut image_wrapper = ImageWrapper::new(
                 image,
                 image_view,
                 create_info.initial_layout,
@@ -467,12 +768,17 @@ impl DeviceWrapper {
                 false, // Swapchain images only go through wrap_image
                 create_info.format,
                 None);
+            image_wrapper.array_layers = create_info.array_layers.max(1);
+            image_wrapper.mip_levels = create_info.mip_levels.max(1);
 
             device.borrow().set_image_name(&image_wrapper, image_desc.get_name());
             DeviceResource {
                 allocation: Some(allocation),
+                device_memory: None,
                 resource_type: Some(ResourceType::Image(image_wrapper)),
                 handle: new_handle,
+                name: image_desc.get_name().to_string(),
+                external: false,
                 device,
             }
         };
@@ -498,7 +804,7 @@ impl DeviceWrapper {
             image_aspect_flags,
             mip_levels);
 
-        let image_wrapper = ImageWrapper::new(
+        let mut image_wrapper = ImageWrapper::new(
             image,
             image_view,
             vk::ImageLayout::UNDEFINED,
@@ -506,20 +812,246 @@ impl DeviceWrapper {
             is_swapchain_image,
             format,
             None);
+        image_wrapper.mip_levels = mip_levels.max(1);
 
         DeviceResource {
             allocation: None,
+            device_memory: None,
             resource_type: Some(ResourceType::Image(image_wrapper)),
             handle: new_handle,
+            name: match is_swapchain_image {
+                true => "swapchain_image",
+                false => "wrapped_image"
+            }.to_string(),
+            external: false,
             device
         }
     }
 
+    /// Wraps an image owned by other code (e.g. a video decoder or another library).
+    /// The image itself is never destroyed by the wrapper, only the view created for it
+    pub fn import_image(
+        device: Rc<RefCell<DeviceWrapper>>,
+        image: vk::Image,
+        format: vk::Format,
+        image_aspect_flags: vk::ImageAspectFlags,
+        mip_levels: u32,
+        extent: vk::Extent3D,
+        layout: vk::ImageLayout,
+        name: &str
+    ) -> DeviceResource {
+        DeviceWrapper::import_layered_image(device, image, format, image_aspect_flags, mip_levels, 1, extent, layout, name)
+    }
+
+    /// Wraps an external image with `array_layers` layers, viewed as a 2D array when there's
+    /// more than one (e.g. an OpenXR swapchain image holding both eyes for multiview)
+    pub fn import_layered_image(
+        device: Rc<RefCell<DeviceWrapper>>,
+        image: vk::Image,
+        format: vk::Format,
+        image_aspect_flags: vk::ImageAspectFlags,
+        mip_levels: u32,
+        array_layers: u32,
+        extent: vk::Extent3D,
+        layout: vk::ImageLayout,
+        name: &str
+    ) -> DeviceResource {
+        let new_handle = device.borrow_mut().generate_handle();
+        log::trace!(target: "resource", "Importing image: {} -- {}", new_handle, name);
+
+        let array_layers = array_layers.max(1);
+        let image_view = device.borrow().create_layered_image_view(
+            image,
+            format,
+            vk::ImageViewCreateFlags::empty(),
+            image_aspect_flags,
+            mip_levels,
+            array_layers);
+        device.borrow().set_debug_name(vk::ObjectType::IMAGE_VIEW, image_view.as_raw(), name);
+
+        let mut image_wrapper = ImageWrapper::new(
+            image,
+            image_view,
+            layout,
+            extent,
+            false,
+            format,
+            None);
+        image_wrapper.array_layers = array_layers;
+        image_wrapper.mip_levels = mip_levels.max(1);
+        device.borrow().set_image_name(&image_wrapper, name);
+
+        DeviceResource {
+            allocation: None,
+            device_memory: None,
+            resource_type: Some(ResourceType::Image(image_wrapper)),
+            handle: new_handle,
+            name: name.to_string(),
+            external: true,
+            device
+        }
+    }
+
+    /// Wraps a buffer owned by other code. The buffer is never destroyed by the wrapper.
+    /// With descriptor buffers, uniform and storage buffers are bound by their device address,
+    /// so they must have been created with SHADER_DEVICE_ADDRESS usage
+    pub fn import_buffer(
+        device: Rc<RefCell<DeviceWrapper>>,
+        buffer: vk::Buffer,
+        create_info: vk::BufferCreateInfo,
+        name: &str
+    ) -> DeviceResource {
+        assert!(!create_info.usage.intersects(vk::BufferUsageFlags::UNIFORM_BUFFER | vk::BufferUsageFlags::STORAGE_BUFFER) ||
+                    !device.borrow().uses_descriptor_buffers() ||
+                    create_info.usage.contains(vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS),
+                "Imported buffer {} needs SHADER_DEVICE_ADDRESS usage to be bound through descriptor buffers", name);
+        let new_handle = device.borrow_mut().generate_handle();
+        log::trace!(target: "resource", "Importing buffer: {} -- {}", new_handle, name);
+
+        let mut buffer_wrapper = BufferWrapper::new(buffer, create_info);
+        buffer_wrapper.device_address = device.borrow().get_buffer_device_address(&buffer_wrapper);
+        device.borrow().set_buffer_name(&buffer_wrapper, name);
+
+        DeviceResource {
+            allocation: None,
+            device_memory: None,
+            resource_type: Some(ResourceType::Buffer(buffer_wrapper)),
+            handle: new_handle,
+            name: name.to_string(),
+            external: true,
+            device
+        }
+    }
+
+    #[cfg(feature = "external-memory")]
+    pub fn get_external_memory(&self) -> &ExternalMemory { &self.external_memory }
+
+    /// Creates an image backed by dedicated memory which can be shared with other APIs
+    /// or processes, either by exporting new memory or by importing `import_handle`.
+    /// Ownership of an imported handle is transferred to Vulkan
+    #[cfg(feature = "external-memory")]
+    pub fn create_external_image(
+        device: Rc<RefCell<DeviceWrapper>>,
+        image_desc: &ImageCreateInfo,
+        import_handle: Option<ExternalHandle>) -> DeviceResource {
+
+        let new_handle = device.borrow_mut().generate_handle();
+        log::trace!(target: "resource", "Creating external image: {} -- {}", new_handle, image_desc.get_name());
+
+        let handle_type = crate::external_memory::get_memory_handle_type();
+        let external_create_info = vk::ExternalMemoryImageCreateInfo::builder()
+            .handle_types(handle_type)
+            .build();
+        let mut create_info = image_desc.get_create_info().clone();
+        create_info.p_next = &external_create_info as *const vk::ExternalMemoryImageCreateInfo as *const c_void;
+
+        let image = unsafe {
+            device.borrow().get().create_image(&create_info, None)
+                .expect("Failed to create external image")
+        };
+
+        let memory_requirements = unsafe {
+            device.borrow().get().get_image_memory_requirements(image)
+        };
+        let memory_type_index = device.borrow().external_memory.find_memory_type(
+            &memory_requirements,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL);
+
+        // external memory must be a dedicated allocation, so this bypasses gpu-allocator
+        let mut dedicated_info = vk::MemoryDedicatedAllocateInfo::builder()
+            .image(image);
+        let memory = unsafe {
+            match import_handle {
+                None => {
+                    let mut export_info = vk::ExportMemoryAllocateInfo::builder()
+                        .handle_types(handle_type);
+                    let allocate_info = vk::MemoryAllocateInfo::builder()
+                        .allocation_size(memory_requirements.size)
+                        .memory_type_index(memory_type_index)
+                        .push_next(&mut export_info)
+                        .push_next(&mut dedicated_info);
+                    device.borrow().get().allocate_memory(&allocate_info, None)
+                        .expect("Failed to allocate exportable memory")
+                },
+                Some(handle) => {
+                    #[cfg(unix)]
+                    let mut import_info = vk::ImportMemoryFdInfoKHR::builder()
+                        .handle_type(handle_type)
+                        .fd(handle);
+                    #[cfg(windows)]
+                    let mut import_info = vk::ImportMemoryWin32HandleInfoKHR::builder()
+                        .handle_type(handle_type)
+                        .handle(handle);
+                    let allocate_info = vk::MemoryAllocateInfo::builder()
+                        .allocation_size(memory_requirements.size)
+                        .memory_type_index(memory_type_index)
+                        .push_next(&mut import_info)
+                        .push_next(&mut dedicated_info);
+                    device.borrow().get().allocate_memory(&allocate_info, None)
+                        .expect("Failed to import external memory")
+                }
+            }
+        };
+
+        unsafe {
+            device.borrow().get().bind_image_memory(image, memory, 0)
+                .expect("Failed to bind external image to memory");
+        }
+
+        let image_view = device.borrow().create_image_view(
+            image,
+            create_info.format,
+            vk::ImageViewCreateFlags::empty(),
+            image_desc.get_image_type().get_aspect_flags(),
+            create_info.mip_levels);
+        device.borrow().set_debug_name(vk::ObjectType::IMAGE_VIEW, image_view.as_raw(), image_desc.get_name());
+        let mut image_wrapper = ImageWrapper::new(
+            image,
+            image_view,
+            create_info.initial_layout,
+            create_info.extent,
+            false,
+            create_info.format,
+            None);
+        image_wrapper.mip_levels = create_info.mip_levels.max(1);
+        device.borrow().set_image_name(&image_wrapper, image_desc.get_name());
+
+        DeviceResource {
+            allocation: None,
+            device_memory: Some(memory),
+            resource_type: Some(ResourceType::Image(image_wrapper)),
+            handle: new_handle,
+            name: image_desc.get_name().to_string(),
+            external: false,
+            device
+        }
+    }
+
+    /// Exports a new native handle for a resource created with DeviceWrapper::create_external_image
+    #[cfg(feature = "external-memory")]
+    pub fn get_external_memory_handle(&self, resource: &DeviceResource) -> ExternalHandle {
+        let memory = resource.device_memory.expect("Resource was not created with external memory");
+        self.external_memory.get_memory_handle(memory)
+    }
+
     pub fn set_buffer_name(&self, buffer: &BufferWrapper, name: &str)
     {
         self.set_debug_name(vk::ObjectType::BUFFER, buffer.get().as_raw(), name);
     }
 
+    /// Queries the GPU pointer of a buffer created with SHADER_DEVICE_ADDRESS usage
+    fn get_buffer_device_address(&self, buffer: &BufferWrapper) -> Option<vk::DeviceAddress> {
+        if !buffer.create_info.usage.contains(vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS) ||
+            !self.is_feature_enabled(NegotiatedFeature::BufferDeviceAddress) {
+            return None;
+        }
+
+        let address_info = vk::BufferDeviceAddressInfo::builder()
+            .buffer(buffer.get())
+            .build();
+        Some(unsafe { self.get().get_buffer_device_address(&address_info) })
+    }
+
     pub fn create_buffer(
         device: Rc<RefCell<DeviceWrapper>>,
         buffer_desc: &BufferCreateInfo,
@@ -529,9 +1061,17 @@ impl DeviceWrapper {
             let new_handle = device.borrow_mut().generate_handle();
             log::trace!(target: "resource", "Creating buffer: {} -- {}", new_handle, buffer_desc.get_name());
 
-            let create_info = buffer_desc.get_create_info();
+            let mut create_info = *buffer_desc.get_create_info();
+            // descriptor buffers refer to uniform and storage buffers by their device address
+            if create_info.usage.intersects(vk::BufferUsageFlags::UNIFORM_BUFFER | vk::BufferUsageFlags::STORAGE_BUFFER) &&
+                device.borrow().uses_descriptor_buffers() {
+                create_info.usage |= vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS;
+            }
+            assert!(!create_info.usage.contains(vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS) ||
+                        device.borrow().is_feature_enabled(NegotiatedFeature::BufferDeviceAddress),
+                    "Buffer {} has SHADER_DEVICE_ADDRESS usage but BufferDeviceAddress isn't enabled", buffer_desc.get_name());
             let buffer = unsafe {
-                device.borrow().get().create_buffer(create_info, None)
+                device.borrow().get().create_buffer(&create_info, None)
                     .expect("Failed to create buffer")
             };
 
@@ -544,6 +1084,7 @@ impl DeviceWrapper {
                 memory_requirements,
                 memory_location,
                 true);
+            device.borrow_mut().track_allocation(new_handle, buffer_desc.get_name(), allocation.size());
 
             unsafe {
                 device.borrow().get().bind_buffer_memory(
@@ -553,12 +1094,17 @@ impl DeviceWrapper {
                     .expect("Failed to bind buffer to memory");
             }
 
-            let buffer_wrapper = BufferWrapper::new(buffer, buffer_desc.get_create_info().clone());
+            let mut buffer_wrapper = BufferWrapper::new(buffer, create_info);
+            // only valid once the buffer is bound to memory
+            buffer_wrapper.device_address = device.borrow().get_buffer_device_address(&buffer_wrapper);
             device.borrow().set_buffer_name(&buffer_wrapper, buffer_desc.get_name());
             DeviceResource {
                 allocation: Some(allocation),
+                device_memory: None,
                 resource_type: Some(ResourceType::Buffer(buffer_wrapper)),
                 handle: new_handle,
+                name: buffer_desc.get_name().to_string(),
+                external: false,
                 device
             }
         };
@@ -636,8 +1182,7 @@ impl DeviceWrapper {
     pub fn create_pipeline(
         device: Rc<RefCell<DeviceWrapper>>,
         create_info: &vk::GraphicsPipelineCreateInfo,
-        pipeline_layout: vk::PipelineLayout,
-        descriptor_set_layouts: Vec<vk::DescriptorSetLayout>,
+        layout: Rc<DevicePipelineLayout>,
         name: &str
     ) -> DevicePipeline {
         let pipeline = unsafe {
@@ -649,20 +1194,17 @@ impl DeviceWrapper {
         }[0];
 
         device.borrow().set_debug_name(vk::ObjectType::PIPELINE, pipeline.as_raw(), name);
-        device.borrow().set_debug_name(vk::ObjectType::PIPELINE_LAYOUT, pipeline_layout.as_raw(), &(name.to_owned() + "_layout"));
 
         DevicePipeline::new(
             pipeline,
-            pipeline_layout,
-            descriptor_set_layouts,
+            layout,
             device)
     }
 
     pub fn create_compute_pipeline(
         device: Rc<RefCell<DeviceWrapper>>,
         create_info: &vk::ComputePipelineCreateInfo,
-        pipeline_layout: vk::PipelineLayout,
-        descriptor_set_layouts: Vec<vk::DescriptorSetLayout>,
+        layout: Rc<DevicePipelineLayout>,
         name: &str
     ) -> DevicePipeline {
         let pipeline = unsafe {
@@ -674,13 +1216,53 @@ impl DeviceWrapper {
         }[0];
 
         device.borrow().set_debug_name(vk::ObjectType::PIPELINE, pipeline.as_raw(), name);
-        device.borrow().set_debug_name(vk::ObjectType::PIPELINE_LAYOUT, pipeline_layout.as_raw(), &(name.to_owned() + "_layout"));
 
         DevicePipeline::new(
             pipeline,
+            layout,
+            device)
+    }
+
+    pub fn create_descriptor_set_layout(
+        device: Rc<RefCell<DeviceWrapper>>,
+        create_info: &vk::DescriptorSetLayoutCreateInfo
+    ) -> DeviceDescriptorSetLayout {
+        let layout = unsafe {
+            device.borrow().get().create_descriptor_set_layout(create_info, None)
+                .expect("Failed to create descriptor set layout")
+        };
+
+        DeviceDescriptorSetLayout {
+            layout,
+            device
+        }
+    }
+
+    /// `descriptor_set_layouts` may contain `None` for sets the pipeline doesn't use, which
+    /// become null handles in the layout
+    pub fn create_pipeline_layout(
+        device: Rc<RefCell<DeviceWrapper>>,
+        descriptor_set_layouts: Vec<Option<Rc<DeviceDescriptorSetLayout>>>,
+        name: &str
+    ) -> DevicePipelineLayout {
+        let set_layout_handles: Vec<vk::DescriptorSetLayout> = descriptor_set_layouts.iter().map(|set_layout| {
+            set_layout.as_ref().map_or(vk::DescriptorSetLayout::null(), |set_layout| set_layout.layout)
+        }).collect();
+        let create_info = vk::PipelineLayoutCreateInfo::builder()
+            .set_layouts(&set_layout_handles);
+        let pipeline_layout = unsafe {
+            device.borrow().get().create_pipeline_layout(&create_info, None)
+                .expect("Failed to create pipeline layout")
+        };
+
+        device.borrow().set_debug_name(vk::ObjectType::PIPELINE_LAYOUT, pipeline_layout.as_raw(), name);
+
+        DevicePipelineLayout {
             pipeline_layout,
+            set_layout_handles,
             descriptor_set_layouts,
-            device)
+            device
+        }
     }
 
     pub fn create_renderpass(
@@ -717,6 +1299,21 @@ impl DeviceWrapper {
         }
     }
 
+    /// Like push_debug_label, without making a C string for the label
+    pub fn push_name_debug_label(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        label: Name) {
+        if let Some(debug) = &self.debug {
+            unsafe {
+                let debug_label = DebugUtilsLabelEXT::builder()
+                    .label_name(label.as_c_str())
+                    .build();
+                debug.debug_utils.cmd_begin_debug_utils_label(command_buffer, &debug_label);
+            }
+        }
+    }
+
     pub fn pop_debug_label(
         &self,
         command_buffer: vk::CommandBuffer) {
@@ -751,37 +1348,64 @@ impl DeviceShader {
     }
 }
 
+pub struct DeviceDescriptorSetLayout {
+    pub layout: vk::DescriptorSetLayout,
+    pub device: Rc<RefCell<DeviceWrapper>>
+}
+
+impl Drop for DeviceDescriptorSetLayout {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.borrow().get().destroy_descriptor_set_layout(self.layout, None);
+        }
+    }
+}
+
+/// A pipeline layout which may be shared by any number of pipelines. The pipeline manager's
+/// layout cache holds a reference to every layout it creates, so layouts (and their descriptor
+/// set layouts) live as long as the cache does, not just as long as their pipelines
+pub struct DevicePipelineLayout {
+    pub pipeline_layout: vk::PipelineLayout,
+    pub set_layout_handles: Vec<vk::DescriptorSetLayout>,
+    pub descriptor_set_layouts: Vec<Option<Rc<DeviceDescriptorSetLayout>>>,
+    pub device: Rc<RefCell<DeviceWrapper>>
+}
+
+impl Drop for DevicePipelineLayout {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.borrow().get().destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct DevicePipeline {
     pub pipeline: vk::Pipeline,
     pub pipeline_layout: vk::PipelineLayout,
     pub descriptor_set_layouts: Vec<vk::DescriptorSetLayout>,
+    pub layout: Rc<DevicePipelineLayout>,
     pub device: Rc<RefCell<DeviceWrapper>>
 }
 
 impl Drop for DevicePipeline {
     fn drop(&mut self) {
-        unsafe {
-            self.device.borrow().get().destroy_pipeline_layout(self.pipeline_layout, None);
-            self.device.borrow().get().destroy_pipeline(self.pipeline, None);
-            for dsl in &self.descriptor_set_layouts {
-                self.device.borrow().get().destroy_descriptor_set_layout(*dsl, None);
-            }
-        }
+        // invalidated pipelines may still be referenced by in-flight command buffers
+        self.device.borrow_mut().defer_destruction(DeferredDestruction::Pipeline(self.pipeline));
     }
 }
 
 impl DevicePipeline {
     pub fn new(
         pipeline: vk::Pipeline,
-        pipeline_layout: vk::PipelineLayout,
-        descriptor_set_layouts: Vec<vk::DescriptorSetLayout>,
+        layout: Rc<DevicePipelineLayout>,
         device: Rc<RefCell<DeviceWrapper>>) -> Self {
 
         DevicePipeline {
             pipeline,
-            pipeline_layout,
-            descriptor_set_layouts,
+            pipeline_layout: layout.pipeline_layout,
+            descriptor_set_layouts: layout.set_layout_handles.clone(),
+            layout,
             device
         }
     }
@@ -795,9 +1419,9 @@ pub struct DeviceRenderpass {
 
 impl Drop for DeviceRenderpass {
     fn drop(&mut self) {
-        unsafe {
-            self.device.borrow().get().destroy_render_pass(self.renderpass, None);
-        }
+        // renderpasses evicted from the renderpass manager may still be referenced by
+        // in-flight command buffers
+        self.device.borrow_mut().defer_destruction(DeferredDestruction::RenderPass(self.renderpass));
     }
 }
 
@@ -812,3 +1436,38 @@ impl DeviceRenderpass {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resource_error(expected: Option<ResourceKind>, found: Option<ResourceKind>) -> ResourceError {
+        ResourceError {
+            handle: 7,
+            name: "shadow_map".to_string(),
+            expected,
+            found,
+            pass: None
+        }
+    }
+
+    #[test]
+    fn resource_errors_name_the_pass() {
+        let error = resource_error(Some(ResourceKind::Image), Some(ResourceKind::Buffer)).in_pass("lighting");
+        assert_eq!(error.to_string(), "Pass \"lighting\": resource 7 (\"shadow_map\") is a buffer, but is used as an image");
+    }
+
+    #[test]
+    fn resource_errors_without_a_pass() {
+        let mismatched = resource_error(Some(ResourceKind::Buffer), Some(ResourceKind::Image));
+        assert_eq!(mismatched.to_string(), "resource 7 (\"shadow_map\") is an image, but is used as a buffer");
+        let unexpected = resource_error(None, Some(ResourceKind::Image));
+        assert_eq!(unexpected.to_string(), "resource 7 (\"shadow_map\") is an image");
+    }
+
+    #[test]
+    fn unresolved_resource_errors() {
+        let error = resource_error(Some(ResourceKind::Image), None).in_pass("present");
+        assert_eq!(error.to_string(), "Pass \"present\": resource 7 (\"shadow_map\") is unresolved");
+    }
+}
@@ -0,0 +1,203 @@
+use std::collections::HashSet;
+use ash::vk;
+use crate::device::QueueFamilies;
+
+/// Number of valid bits in timestamps written on each of the device's queues; 0 when the
+/// queue doesn't support timestamps
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct QueueTimestampSupport {
+    pub graphics: u32,
+    pub compute: u32,
+    pub present: u32,
+    /// 0 without a dedicated transfer family
+    pub transfer: u32
+}
+
+impl QueueTimestampSupport {
+    pub fn graphics_supported(&self) -> bool { self.graphics > 0 }
+
+    pub fn compute_supported(&self) -> bool { self.compute > 0 }
+
+    pub fn transfer_supported(&self) -> bool { self.transfer > 0 }
+}
+
+/// Optional capabilities of the physical device which passes may want to adapt to
+#[derive(Copy, Clone, Debug, Default)]
+pub struct DeviceFeatures {
+    pub timestamps: QueueTimestampSupport,
+    /// Timestamps are supported on every graphics and compute queue
+    pub timestamp_compute_and_graphics: bool,
+    pub subgroup_size: u32,
+    pub subgroup_stages: vk::ShaderStageFlags,
+    pub subgroup_operations: vk::SubgroupFeatureFlags,
+    pub sampler_anisotropy: bool,
+    pub geometry_shader: bool,
+    pub tessellation_shader: bool,
+    pub wide_lines: bool,
+    /// Pipeline features which are enabled on the logical device whenever supported
+    pub fill_mode_non_solid: bool,
+    pub depth_clamp: bool,
+    pub depth_bias_clamp: bool,
+    pub independent_blend: bool
+}
+
+/// The subset of vk::PhysicalDeviceLimits which passes and pipeline descriptions are likely
+/// to care about. The full set is still available from DeviceWrapper::get_device_limits
+#[derive(Copy, Clone, Debug, Default)]
+pub struct DeviceLimits {
+    pub max_bound_descriptor_sets: u32,
+    pub max_push_constants_size: u32,
+    pub max_image_dimension_1d: u32,
+    pub max_image_dimension_2d: u32,
+    pub max_image_dimension_3d: u32,
+    pub max_image_dimension_cube: u32,
+    pub max_image_array_layers: u32,
+    /// Sample counts usable by both color and depth framebuffer attachments
+    pub framebuffer_sample_counts: vk::SampleCountFlags,
+    pub sampled_image_sample_counts: vk::SampleCountFlags,
+    pub storage_image_sample_counts: vk::SampleCountFlags,
+    pub max_compute_work_group_count: [u32; 3],
+    pub max_compute_work_group_size: [u32; 3],
+    pub max_compute_work_group_invocations: u32,
+    /// The number of views a multiview renderpass can render at once; 0 without multiview
+    /// support
+    pub max_multiview_view_count: u32,
+    /// Nanoseconds per timestamp tick
+    pub timestamp_period: f32
+}
+
+impl DeviceLimits {
+    /// The highest sample count supported by framebuffer attachments
+    pub fn get_max_sample_count(&self) -> vk::SampleCountFlags {
+        let counts = self.framebuffer_sample_counts.as_raw();
+        if counts == 0 {
+            return vk::SampleCountFlags::TYPE_1;
+        }
+        vk::SampleCountFlags::from_raw(1 << (31 - counts.leading_zeros()))
+    }
+
+    /// Rounds `samples` down to the nearest sample count supported by framebuffer attachments
+    pub fn clamp_sample_count(&self, samples: vk::SampleCountFlags) -> vk::SampleCountFlags {
+        let mut count = samples.as_raw().max(1);
+        while count > 1 && !self.framebuffer_sample_counts.contains(vk::SampleCountFlags::from_raw(count)) {
+            count >>= 1;
+        }
+        vk::SampleCountFlags::from_raw(count)
+    }
+}
+
+pub fn query_device_capabilities(
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+    properties: &vk::PhysicalDeviceProperties,
+    queue_families: &QueueFamilies) -> (DeviceFeatures, DeviceLimits) {
+
+    let limits = &properties.limits;
+
+    let (core_features, subgroup_properties, multiview_properties, queue_family_properties) = unsafe {
+        let core_features = instance.get_physical_device_features(physical_device);
+
+        let mut subgroup_properties = vk::PhysicalDeviceSubgroupProperties::default();
+        let mut multiview_properties = vk::PhysicalDeviceMultiviewProperties::default();
+        let mut properties2 = vk::PhysicalDeviceProperties2::builder()
+            .push_next(&mut subgroup_properties)
+            .push_next(&mut multiview_properties);
+        instance.get_physical_device_properties2(physical_device, &mut properties2);
+
+        let queue_family_properties = instance.get_physical_device_queue_family_properties(physical_device);
+        (core_features, subgroup_properties, multiview_properties, queue_family_properties)
+    };
+
+    let timestamp_bits = |family: Option<u32>| {
+        family.and_then(|family| queue_family_properties.get(family as usize))
+            .map_or(0, |properties| properties.timestamp_valid_bits)
+    };
+
+    let features = DeviceFeatures {
+        timestamps: QueueTimestampSupport {
+            graphics: timestamp_bits(queue_families.graphics),
+            compute: timestamp_bits(queue_families.compute),
+            present: timestamp_bits(queue_families.present),
+            transfer: timestamp_bits(queue_families.transfer)
+        },
+        timestamp_compute_and_graphics: limits.timestamp_compute_and_graphics > 0,
+        subgroup_size: subgroup_properties.subgroup_size,
+        subgroup_stages: subgroup_properties.supported_stages,
+        subgroup_operations: subgroup_properties.supported_operations,
+        sampler_anisotropy: core_features.sampler_anisotropy > 0,
+        geometry_shader: core_features.geometry_shader > 0,
+        tessellation_shader: core_features.tessellation_shader > 0,
+        wide_lines: core_features.wide_lines > 0,
+        fill_mode_non_solid: core_features.fill_mode_non_solid > 0,
+        depth_clamp: core_features.depth_clamp > 0,
+        depth_bias_clamp: core_features.depth_bias_clamp > 0,
+        independent_blend: core_features.independent_blend > 0
+    };
+
+    let limits = DeviceLimits {
+        max_bound_descriptor_sets: limits.max_bound_descriptor_sets,
+        max_push_constants_size: limits.max_push_constants_size,
+        max_image_dimension_1d: limits.max_image_dimension1_d,
+        max_image_dimension_2d: limits.max_image_dimension2_d,
+        max_image_dimension_3d: limits.max_image_dimension3_d,
+        max_image_dimension_cube: limits.max_image_dimension_cube,
+        max_image_array_layers: limits.max_image_array_layers,
+        framebuffer_sample_counts: limits.framebuffer_color_sample_counts & limits.framebuffer_depth_sample_counts,
+        sampled_image_sample_counts: limits.sampled_image_color_sample_counts,
+        storage_image_sample_counts: limits.storage_image_sample_counts,
+        max_compute_work_group_count: limits.max_compute_work_group_count,
+        max_compute_work_group_size: limits.max_compute_work_group_size,
+        max_compute_work_group_invocations: limits.max_compute_work_group_invocations,
+        max_multiview_view_count: multiview_properties.max_multiview_view_count,
+        timestamp_period: limits.timestamp_period
+    };
+
+    (features, limits)
+}
+
+/// Device features which are negotiated when the logical device is created, either because
+/// they need an extension at the context's API version or because code paths depend on them
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum NegotiatedFeature {
+    HostQueryReset,
+    Synchronization2,
+    DynamicRendering,
+    /// Partially bound, runtime sized descriptor arrays of sampled images with non-uniform
+    /// indexing
+    DescriptorIndexing,
+    /// Present fences, so swapchains can be destroyed without waiting for the device to idle.
+    /// Unsupported by MoltenVK
+    SwapchainMaintenance1,
+    /// GPU pointers to buffers created with SHADER_DEVICE_ADDRESS usage, e.g. for bindless
+    /// vertex fetch. Not requested by default
+    BufferDeviceAddress,
+    /// Descriptors written straight into mapped buffers instead of allocated from pools. Only
+    /// enabled along with BufferDeviceAddress, which must also be requested. Not requested by
+    /// default
+    DescriptorBuffer,
+    /// Renderpasses which render to several array layers at once, one view per layer, e.g.
+    /// for stereo or single pass cubemap rendering. Core in Vulkan 1.1
+    Multiview
+}
+
+/// The negotiated features which were actually enabled on the logical device
+#[derive(Clone, Debug, Default)]
+pub struct EnabledFeatures {
+    features: HashSet<NegotiatedFeature>
+}
+
+impl EnabledFeatures {
+    pub fn new(features: HashSet<NegotiatedFeature>) -> Self {
+        EnabledFeatures {
+            features
+        }
+    }
+
+    pub fn is_enabled(&self, feature: NegotiatedFeature) -> bool {
+        self.features.contains(&feature)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &NegotiatedFeature> {
+        self.features.iter()
+    }
+}
@@ -0,0 +1,164 @@
+use std::fmt::{Debug, Formatter};
+use ash::vk;
+
+/// Native handle used to share memory and semaphores with other APIs or processes
+#[cfg(unix)]
+pub type ExternalHandle = std::os::raw::c_int;
+#[cfg(windows)]
+pub type ExternalHandle = vk::HANDLE;
+
+#[cfg(unix)]
+pub fn get_memory_handle_type() -> vk::ExternalMemoryHandleTypeFlags {
+    vk::ExternalMemoryHandleTypeFlags::OPAQUE_FD
+}
+
+#[cfg(windows)]
+pub fn get_memory_handle_type() -> vk::ExternalMemoryHandleTypeFlags {
+    vk::ExternalMemoryHandleTypeFlags::OPAQUE_WIN32
+}
+
+#[cfg(unix)]
+pub fn get_semaphore_handle_type() -> vk::ExternalSemaphoreHandleTypeFlags {
+    vk::ExternalSemaphoreHandleTypeFlags::OPAQUE_FD
+}
+
+#[cfg(windows)]
+pub fn get_semaphore_handle_type() -> vk::ExternalSemaphoreHandleTypeFlags {
+    vk::ExternalSemaphoreHandleTypeFlags::OPAQUE_WIN32
+}
+
+/// Device extensions required for external memory and semaphore interop
+#[cfg(unix)]
+pub fn get_external_memory_extensions() -> Vec<&'static std::ffi::CStr> {
+    vec![
+        ash::extensions::khr::ExternalMemoryFd::name(),
+        ash::extensions::khr::ExternalSemaphoreFd::name()
+    ]
+}
+
+#[cfg(windows)]
+pub fn get_external_memory_extensions() -> Vec<&'static std::ffi::CStr> {
+    vec![
+        ash::extensions::khr::ExternalMemoryWin32::name(),
+        ash::extensions::khr::ExternalSemaphoreWin32::name()
+    ]
+}
+
+pub struct ExternalMemory {
+    #[cfg(unix)]
+    memory_loader: ash::extensions::khr::ExternalMemoryFd,
+    #[cfg(unix)]
+    semaphore_loader: ash::extensions::khr::ExternalSemaphoreFd,
+    #[cfg(windows)]
+    memory_loader: ash::extensions::khr::ExternalMemoryWin32,
+    #[cfg(windows)]
+    semaphore_loader: ash::extensions::khr::ExternalSemaphoreWin32,
+    memory_properties: vk::PhysicalDeviceMemoryProperties
+}
+
+impl Debug for ExternalMemory {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ExternalMemory")
+            .finish()
+    }
+}
+
+impl ExternalMemory {
+    pub fn new(instance: &ash::Instance, device: &ash::Device, physical_device: vk::PhysicalDevice) -> Self {
+        let memory_properties = unsafe {
+            instance.get_physical_device_memory_properties(physical_device)
+        };
+
+        ExternalMemory {
+            memory_loader: {
+                #[cfg(unix)]
+                { ash::extensions::khr::ExternalMemoryFd::new(instance, device) }
+                #[cfg(windows)]
+                { ash::extensions::khr::ExternalMemoryWin32::new(instance, device) }
+            },
+            semaphore_loader: {
+                #[cfg(unix)]
+                { ash::extensions::khr::ExternalSemaphoreFd::new(instance, device) }
+                #[cfg(windows)]
+                { ash::extensions::khr::ExternalSemaphoreWin32::new(instance, device) }
+            },
+            memory_properties
+        }
+    }
+
+    pub fn find_memory_type(&self, requirements: &vk::MemoryRequirements, flags: vk::MemoryPropertyFlags) -> u32 {
+        (0..self.memory_properties.memory_type_count).find(|index| {
+            let supported = requirements.memory_type_bits & (1 << index) != 0;
+            supported && self.memory_properties.memory_types[*index as usize].property_flags.contains(flags)
+        }).expect("No suitable memory type for external memory")
+    }
+
+    /// Returns a new native handle for the memory. On unix the caller owns the
+    /// returned fd and is responsible for closing it
+    pub fn get_memory_handle(&self, memory: vk::DeviceMemory) -> ExternalHandle {
+        unsafe {
+            #[cfg(unix)]
+            {
+                let get_info = vk::MemoryGetFdInfoKHR::builder()
+                    .memory(memory)
+                    .handle_type(get_memory_handle_type());
+                self.memory_loader.get_memory_fd(&get_info)
+                    .expect("Failed to get external memory fd")
+            }
+            #[cfg(windows)]
+            {
+                let get_info = vk::MemoryGetWin32HandleInfoKHR::builder()
+                    .memory(memory)
+                    .handle_type(get_memory_handle_type());
+                self.memory_loader.get_memory_win32_handle(&get_info)
+                    .expect("Failed to get external memory win32 handle")
+            }
+        }
+    }
+
+    pub fn get_semaphore_handle(&self, semaphore: vk::Semaphore) -> ExternalHandle {
+        unsafe {
+            #[cfg(unix)]
+            {
+                let get_info = vk::SemaphoreGetFdInfoKHR::builder()
+                    .semaphore(semaphore)
+                    .handle_type(get_semaphore_handle_type());
+                self.semaphore_loader.get_semaphore_fd(&get_info)
+                    .expect("Failed to get external semaphore fd")
+            }
+            #[cfg(windows)]
+            {
+                let get_info = vk::SemaphoreGetWin32HandleInfoKHR::builder()
+                    .semaphore(semaphore)
+                    .handle_type(get_semaphore_handle_type());
+                self.semaphore_loader.get_semaphore_win32_handle(&get_info)
+                    .expect("Failed to get external semaphore win32 handle")
+            }
+        }
+    }
+
+    /// Replaces the payload of `semaphore` with the external one. On unix the fd is
+    /// owned by Vulkan after a successful import
+    pub fn import_semaphore_handle(&self, semaphore: vk::Semaphore, handle: ExternalHandle) {
+        unsafe {
+            #[cfg(unix)]
+            {
+                let import_info = vk::ImportSemaphoreFdInfoKHR::builder()
+                    .semaphore(semaphore)
+                    .handle_type(get_semaphore_handle_type())
+                    .fd(handle);
+                self.semaphore_loader.import_semaphore_fd(&import_info)
+                    .expect("Failed to import external semaphore fd");
+            }
+            #[cfg(windows)]
+            {
+                let import_info = vk::ImportSemaphoreWin32HandleInfoKHR::builder()
+                    .semaphore(semaphore)
+                    .handle_type(get_semaphore_handle_type())
+                    .handle(handle);
+                self.semaphore_loader.import_semaphore_win32_handle(&import_info)
+                    .expect("Failed to import external semaphore win32 handle");
+            }
+        }
+    }
+}
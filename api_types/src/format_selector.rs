@@ -0,0 +1,185 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+use ash::vk;
+
+/// Depth formats without a stencil aspect in order of preference. Every device supports
+/// D16_UNORM and at least one of the others as a depth attachment
+pub const DEPTH_FORMATS: &[vk::Format] = &[
+    vk::Format::D32_SFLOAT,
+    vk::Format::X8_D24_UNORM_PACK32,
+    vk::Format::D16_UNORM];
+
+/// Depth formats with a stencil aspect in order of preference. MoltenVK lacks
+/// D24_UNORM_S8_UINT on Apple silicon
+pub const DEPTH_STENCIL_FORMATS: &[vk::Format] = &[
+    vk::Format::D24_UNORM_S8_UINT,
+    vk::Format::D32_SFLOAT_S8_UINT,
+    vk::Format::D16_UNORM_S8_UINT];
+
+/// Floating point color formats in order of preference, for HDR targets. Every device supports
+/// the first for sampling, storage and rendering, but the second is there for any which don't
+pub const HDR_COLOR_FORMATS: &[vk::Format] = &[
+    vk::Format::R16G16B16A16_SFLOAT,
+    vk::Format::R32G32B32A32_SFLOAT];
+
+/// The format features an optimally tiled image needs to be created with `usage`. Transfers
+/// only require TRANSFER_SRC/DST; images which are blitted need BLIT_SRC/DST added to them
+pub fn required_features(usage: vk::ImageUsageFlags) -> vk::FormatFeatureFlags {
+    [
+        (vk::ImageUsageFlags::SAMPLED, vk::FormatFeatureFlags::SAMPLED_IMAGE),
+        (vk::ImageUsageFlags::STORAGE, vk::FormatFeatureFlags::STORAGE_IMAGE),
+        (vk::ImageUsageFlags::COLOR_ATTACHMENT, vk::FormatFeatureFlags::COLOR_ATTACHMENT),
+        (vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT, vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT),
+        (vk::ImageUsageFlags::TRANSFER_SRC, vk::FormatFeatureFlags::TRANSFER_SRC),
+        (vk::ImageUsageFlags::TRANSFER_DST, vk::FormatFeatureFlags::TRANSFER_DST)
+    ].iter()
+        .filter(|(image_usage, _)| usage.contains(*image_usage))
+        .fold(vk::FormatFeatureFlags::empty(), |features, (_, feature)| features | *feature)
+}
+
+/// Picks formats the physical device supports from lists of preferences, so passes can fall
+/// back to a format which is available everywhere rather than hardcode one which isn't. The
+/// properties of each format are queried the first time it's looked at
+pub struct FormatSelector {
+    instance: ash::Instance,
+    physical_device: vk::PhysicalDevice,
+    properties: RefCell<HashMap<vk::Format, vk::FormatProperties>>
+}
+
+impl Debug for FormatSelector {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FormatSelector")
+            .field("physical_device", &self.physical_device)
+            .field("queried_formats", &self.properties.borrow().len())
+            .finish()
+    }
+}
+
+impl FormatSelector {
+    pub fn new(instance: &ash::Instance, physical_device: vk::PhysicalDevice) -> Self {
+        FormatSelector {
+            instance: instance.clone(),
+            physical_device,
+            properties: RefCell::new(HashMap::new())
+        }
+    }
+
+    pub fn get_properties(&self, format: vk::Format) -> vk::FormatProperties {
+        *self.properties.borrow_mut().entry(format).or_insert_with(|| unsafe {
+            self.instance.get_physical_device_format_properties(self.physical_device, format)
+        })
+    }
+
+    /// Whether images of `format` with `tiling` support all of `features`
+    pub fn supports(
+        &self,
+        format: vk::Format,
+        tiling: vk::ImageTiling,
+        features: vk::FormatFeatureFlags) -> bool {
+
+        let properties = self.get_properties(format);
+        let supported = match tiling {
+            vk::ImageTiling::LINEAR => properties.linear_tiling_features,
+            _ => properties.optimal_tiling_features
+        };
+        supported.contains(features)
+    }
+
+    /// The first of `preferences` supporting `features` with optimal tiling
+    pub fn select(
+        &self,
+        preferences: &[vk::Format],
+        features: vk::FormatFeatureFlags) -> Option<vk::Format> {
+
+        preferences.iter()
+            .find(|format| self.supports(**format, vk::ImageTiling::OPTIMAL, features))
+            .copied()
+    }
+
+    /// The first of `preferences` which optimally tiled images with `usage` can be created
+    /// with, plus any `extra_features` such as BLIT_SRC
+    pub fn select_for_usage(
+        &self,
+        preferences: &[vk::Format],
+        usage: vk::ImageUsageFlags,
+        extra_features: vk::FormatFeatureFlags) -> Option<vk::Format> {
+
+        self.select(preferences, required_features(usage) | extra_features)
+    }
+
+    /// As select_for_usage, but panics naming `what` when none of `preferences` are supported.
+    /// Falling back past the first preference is logged, as it may cost precision or bandwidth
+    pub fn require(
+        &self,
+        preferences: &[vk::Format],
+        usage: vk::ImageUsageFlags,
+        extra_features: vk::FormatFeatureFlags,
+        what: &str) -> vk::Format {
+
+        let format = self.select_for_usage(preferences, usage, extra_features)
+            .unwrap_or_else(|| panic!(
+                "None of {:?} support {:?} for {} on this device",
+                preferences,
+                required_features(usage) | extra_features,
+                what));
+        if Some(&format) != preferences.first() {
+            log::info!("{} falls back to {:?} from {:?}", what, format, preferences[0]);
+        }
+        format
+    }
+
+    /// A depth format usable for `usage`, which includes DEPTH_STENCIL_ATTACHMENT. Formats with
+    /// a stencil aspect are only picked when `stencil` is set, as views of them can't be sampled
+    /// without picking an aspect
+    pub fn choose_depth_format(&self, stencil: bool, usage: vk::ImageUsageFlags) -> vk::Format {
+        let (preferences, what) = match stencil {
+            true => (DEPTH_STENCIL_FORMATS, "depth-stencil attachment"),
+            false => (DEPTH_FORMATS, "depth attachment")
+        };
+        self.require(
+            preferences,
+            usage | vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+            vk::FormatFeatureFlags::empty(),
+            what)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_usage_requires_no_features() {
+        assert_eq!(required_features(vk::ImageUsageFlags::empty()), vk::FormatFeatureFlags::empty());
+    }
+
+    #[test]
+    fn each_usage_requires_its_feature() {
+        assert_eq!(
+            required_features(vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::COLOR_ATTACHMENT),
+            vk::FormatFeatureFlags::SAMPLED_IMAGE | vk::FormatFeatureFlags::COLOR_ATTACHMENT);
+        assert_eq!(
+            required_features(vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT),
+            vk::FormatFeatureFlags::STORAGE_IMAGE | vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT);
+        assert_eq!(
+            required_features(vk::ImageUsageFlags::TRANSFER_SRC | vk::ImageUsageFlags::TRANSFER_DST),
+            vk::FormatFeatureFlags::TRANSFER_SRC | vk::FormatFeatureFlags::TRANSFER_DST);
+    }
+
+    #[test]
+    fn transfers_dont_require_blits() {
+        let features = required_features(vk::ImageUsageFlags::TRANSFER_SRC | vk::ImageUsageFlags::TRANSFER_DST);
+        assert!(!features.intersects(vk::FormatFeatureFlags::BLIT_SRC | vk::FormatFeatureFlags::BLIT_DST));
+    }
+
+    #[test]
+    fn usages_without_a_format_feature_are_ignored() {
+        assert_eq!(
+            required_features(vk::ImageUsageFlags::INPUT_ATTACHMENT | vk::ImageUsageFlags::TRANSIENT_ATTACHMENT),
+            vk::FormatFeatureFlags::empty());
+        assert_eq!(
+            required_features(vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSIENT_ATTACHMENT),
+            vk::FormatFeatureFlags::SAMPLED_IMAGE);
+    }
+}
@@ -1,12 +1,31 @@
 use ash::vk;
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum ImageType {
     Color,
     Depth,
     DepthStencil,
     Stencil
 }
+
+impl ImageType {
+    pub fn get_aspect_flags(&self) -> vk::ImageAspectFlags {
+        match self {
+            ImageType::Color => {
+                vk::ImageAspectFlags::COLOR
+            }
+            ImageType::Depth => {
+                vk::ImageAspectFlags::DEPTH
+            }
+            ImageType::DepthStencil => {
+                vk::ImageAspectFlags::STENCIL | vk::ImageAspectFlags::DEPTH
+            }
+            ImageType::Stencil => {
+                vk::ImageAspectFlags::STENCIL
+            }
+        }
+    }
+}
 pub struct ImageCreateInfo {
     create_info: vk::ImageCreateInfo,
     name: String,
@@ -41,7 +60,10 @@ pub struct ImageWrapper {
     pub extent: vk::Extent3D,
     pub sampler: Option<vk::Sampler>,
     pub is_swapchain_image: bool,
-    pub format: vk::Format
+    pub format: vk::Format,
+    /// The view covers every layer, as a 2D array view when there's more than one
+    pub array_layers: u32,
+    pub mip_levels: u32
 }
 
 impl ImageWrapper {
@@ -60,7 +82,9 @@ impl ImageWrapper {
             extent,
             sampler,
             format,
-            is_swapchain_image
+            is_swapchain_image,
+            array_layers: 1,
+            mip_levels: 1
         }
     }
 
@@ -1,9 +1,17 @@
 pub mod surface;
 pub mod device;
+pub mod device_capabilities;
+pub mod format_selector;
 pub mod instance;
 pub mod image;
 pub mod buffer;
 pub mod swapchain;
+pub mod resource_state;
+pub mod name;
+pub mod upload_buffer;
+pub mod deletion_queue;
+#[cfg(feature = "external-memory")]
+pub mod external_memory;
 pub fn add(left: u64, right: u64) -> u64 {
     left + right
 }
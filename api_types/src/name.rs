@@ -0,0 +1,176 @@
+use std::collections::HashMap;
+use std::ffi::CStr;
+use std::fmt::{Debug, Display, Formatter};
+use std::hash::{Hash, Hasher};
+use std::ops::Deref;
+use std::sync::{Mutex, OnceLock};
+
+// every interned string, keyed on itself without the nul terminator it's stored with so it can
+// also be used as a C string
+static INTERNED: OnceLock<Mutex<HashMap<&'static str, &'static str>>> = OnceLock::new();
+
+fn intern(name: &str) -> &'static str {
+    // checked before locking, so a bad name doesn't poison the interner
+    assert!(!name.contains('\0'), "Names can't contain nul characters: {:?}", name);
+    let mut interned = INTERNED.get_or_init(|| Mutex::new(HashMap::new()))
+        .lock()
+        .expect("Name interner was poisoned");
+    if let Some(existing) = interned.get(name) {
+        return *existing;
+    }
+
+    let terminated: &'static str = Box::leak(format!("{}\0", name).into_boxed_str());
+    interned.insert(&terminated[..name.len()], terminated);
+    terminated
+}
+
+/// An interned pass, group or tag name. Each distinct name is allocated once, the first time
+/// it's interned, and kept for the rest of the program, so copying a Name never allocates and
+/// comparing or hashing one only looks at its address. Names which change every frame (e.g. ones
+/// containing a frame number) shouldn't be interned
+#[derive(Copy, Clone)]
+pub struct Name(&'static str);
+
+impl Name {
+    pub fn new(name: &str) -> Self {
+        Name(intern(name))
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        &self.0[..self.0.len() - 1]
+    }
+
+    /// For debug labels and object names, without making a CString
+    pub fn as_c_str(&self) -> &'static CStr {
+        CStr::from_bytes_with_nul(self.0.as_bytes()).expect("Interned names are nul terminated")
+    }
+}
+
+impl Default for Name {
+    fn default() -> Self {
+        Name::new("")
+    }
+}
+
+impl PartialEq for Name {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self.0, other.0)
+    }
+}
+
+impl Eq for Name {}
+
+impl Hash for Name {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.as_ptr().hash(state);
+    }
+}
+
+impl PartialEq<str> for Name {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for Name {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl Deref for Name {
+    type Target = str;
+
+    fn deref(&self) -> &Self::Target {
+        self.as_str()
+    }
+}
+
+impl AsRef<str> for Name {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl Debug for Name {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl Display for Name {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl From<&str> for Name {
+    fn from(name: &str) -> Self {
+        Name::new(name)
+    }
+}
+
+impl From<String> for Name {
+    fn from(name: String) -> Self {
+        Name::new(&name)
+    }
+}
+
+impl From<&String> for Name {
+    fn from(name: &String) -> Self {
+        Name::new(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn equal_names_share_storage() {
+        let owned = String::from("shared_name");
+        let first = Name::new("shared_name");
+        let second = Name::from(&owned);
+        assert_eq!(first, second);
+        assert!(std::ptr::eq(first.as_str(), second.as_str()));
+        assert_ne!(first, Name::new("other_name"));
+    }
+
+    #[test]
+    fn names_compare_with_strs() {
+        let name = Name::new("compared_name");
+        assert_eq!(name, "compared_name");
+        assert_eq!(name.as_str(), "compared_name");
+        assert_eq!(name.to_string(), "compared_name");
+        assert_eq!(format!("{:?}", name), "\"compared_name\"");
+    }
+
+    #[test]
+    fn c_strs_are_nul_terminated() {
+        let name = Name::new("c_name");
+        let c_str = name.as_c_str();
+        assert_eq!(c_str.to_bytes(), b"c_name");
+        assert_eq!(c_str.to_bytes_with_nul(), b"c_name\0");
+    }
+
+    #[test]
+    fn default_is_empty() {
+        let name = Name::default();
+        assert_eq!(name, "");
+        assert_eq!(name, Name::new(""));
+        assert_eq!(name.as_c_str().to_bytes_with_nul(), b"\0");
+    }
+
+    #[test]
+    #[should_panic(expected = "Names can't contain nul characters")]
+    fn interior_nuls_are_rejected() {
+        Name::new("bad\0name");
+    }
+
+    #[test]
+    fn rejected_names_dont_poison_the_interner() {
+        let rejected = std::panic::catch_unwind(|| Name::new("also\0bad"));
+        assert!(rejected.is_err());
+        assert_eq!(Name::new("after_rejection"), "after_rejection");
+    }
+}
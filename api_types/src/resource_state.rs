@@ -0,0 +1,41 @@
+use std::collections::HashMap;
+use ash::vk;
+
+/// The most recent known usage of a resource, as of the end of the last recorded submission
+#[derive(Copy, Clone, Debug)]
+pub struct ResourceState {
+    pub access: vk::AccessFlags,
+    pub stage: vk::PipelineStageFlags,
+    pub layout: Option<vk::ImageLayout>
+}
+
+/// Persistent per-resource state shared by the framegraph and any manual submissions,
+/// keyed on DeviceResource handles. Entries are removed when their resource is destroyed
+#[derive(Debug, Default)]
+pub struct ResourceStateRegistry {
+    states: HashMap<u64, ResourceState>
+}
+
+impl ResourceStateRegistry {
+    pub fn new() -> Self {
+        ResourceStateRegistry {
+            states: HashMap::new()
+        }
+    }
+
+    pub fn get(&self, handle: u64) -> Option<ResourceState> {
+        self.states.get(&handle).copied()
+    }
+
+    pub fn update(&mut self, handle: u64, state: ResourceState) {
+        self.states.insert(handle, state);
+    }
+
+    pub fn remove(&mut self, handle: u64) {
+        self.states.remove(&handle);
+    }
+
+    pub fn len(&self) -> usize {
+        self.states.len()
+    }
+}
@@ -14,6 +14,7 @@ pub enum SwapchainStatus {
 
 pub struct NextImage {
     pub image: Option<Rc<RefCell<DeviceResource>>>,
+    pub index: u32,
     pub status: SwapchainStatus
 }
 
@@ -23,7 +24,9 @@ pub struct SwapchainWrapper {
     swapchain: vk::SwapchainKHR,
     images: Vec<Rc<RefCell<DeviceResource>>>,
     format: vk::Format,
+    color_space: vk::ColorSpaceKHR,
     extent: vk::Extent2D,
+    present_mode: vk::PresentModeKHR,
     present_fences: Vec<vk::Fence>
 }
 
@@ -41,7 +44,9 @@ impl SwapchainWrapper {
         swapchain: vk::SwapchainKHR,
         images: Vec<Rc<RefCell<DeviceResource>>>,
         format: vk::Format,
+        color_space: vk::ColorSpaceKHR,
         extent: vk::Extent2D,
+        present_mode: vk::PresentModeKHR,
         present_fences: Vec<vk::Fence>
     ) -> SwapchainWrapper {
         SwapchainWrapper {
@@ -50,7 +55,9 @@ impl SwapchainWrapper {
             swapchain,
             images,
             format,
+            color_space,
             extent,
+            present_mode,
             present_fences
         }
     }
@@ -61,14 +68,33 @@ impl SwapchainWrapper {
 
     pub fn get_format(&self) -> vk::Format { self.format }
 
+    pub fn get_color_space(&self) -> vk::ColorSpaceKHR { self.color_space }
+
     pub fn get_extent(&self) -> vk::Extent2D { self.extent }
 
+    pub fn get_present_mode(&self) -> vk::PresentModeKHR { self.present_mode }
+
     pub fn get_loader(&self) -> &ash::extensions::khr::Swapchain { &self.loader }
 
-    pub fn get_present_fence(&self, index: u32) -> vk::Fence {
-        self.present_fences[index as usize].clone()
+    /// None when swapchain_maintenance1 isn't enabled, so presents can't signal fences
+    pub fn get_present_fence(&self, index: u32) -> Option<vk::Fence> {
+        self.present_fences.get(index as usize).cloned()
+    }
+
+    /// The fences aren't destroyed with the swapchain; whoever created them stays responsible
+    /// for them, e.g. by returning them to a pool once the swapchain can be destroyed
+    pub fn get_present_fences(&self) -> &[vk::Fence] {
+        &self.present_fences
     }
 
+    /// False when swapchain_maintenance1 isn't enabled, in which case can_destroy can't tell
+    /// when the presentation engine is finished and the owner has to track it some other way
+    pub fn has_present_fences(&self) -> bool {
+        !self.present_fences.is_empty()
+    }
+
+    /// Whether every present fence has signaled, so the presentation engine has finished with
+    /// this swapchain. Always true without present fences (see has_present_fences)
     pub fn can_destroy(&self) -> bool {
         let mut can_destroy = true;
 
@@ -109,6 +135,7 @@ impl SwapchainWrapper {
                 };
                 NextImage {
                     image: Some(self.images[image_index as usize].clone()),
+                    index: image_index,
                     status,
                 }
             }
@@ -116,6 +143,7 @@ impl SwapchainWrapper {
                 log::trace!(target: "swapchain", "Error when obtaining next swapchain image: {}", e);
                 NextImage {
                     image: None,
+                    index: 0,
                     status: SwapchainStatus::Outdated
                 }
             }
@@ -150,9 +178,6 @@ impl SwapchainWrapper {
 impl Drop for SwapchainWrapper {
     fn drop(&mut self) {
         unsafe {
-            for fence in &self.present_fences {
-                self.device.borrow().get().destroy_fence(*fence, None);
-            }
             self.loader.destroy_swapchain(self.swapchain, None);
         }
     }
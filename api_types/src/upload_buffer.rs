@@ -0,0 +1,146 @@
+use std::cell::RefCell;
+use std::ffi::c_void;
+use std::fmt::{Debug, Formatter};
+use std::rc::Rc;
+use ash::vk;
+use gpu_allocator::MemoryLocation;
+use crate::buffer::BufferCreateInfo;
+use crate::device::{DeviceResource, DeviceWrapper, ResourceType};
+
+/// A single persistently mapped CpuToGpu buffer split into one region per frame in flight.
+/// Per-frame data (vertices, indices, uniforms) is sub-allocated linearly from the current
+/// region, and a region is only reset once the fence of the frame that last used it has signaled.
+pub struct DynamicUploadBuffer {
+    buffer: Rc<RefCell<DeviceResource>>,
+    mapped: *mut u8,
+    region_size: vk::DeviceSize,
+    region_count: u32,
+    current_region: u32,
+    cursor: vk::DeviceSize,
+    device: Rc<RefCell<DeviceWrapper>>
+}
+
+impl Debug for DynamicUploadBuffer {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DynamicUploadBuffer")
+            .field("region size", &self.region_size)
+            .field("region count", &self.region_count)
+            .field("current region", &self.current_region)
+            .field("cursor", &self.cursor)
+            .finish()
+    }
+}
+
+fn align_up(value: vk::DeviceSize, alignment: vk::DeviceSize) -> vk::DeviceSize {
+    if alignment <= 1 {
+        value
+    } else {
+        (value + alignment - 1) / alignment * alignment
+    }
+}
+
+impl DynamicUploadBuffer {
+    pub fn new(
+        device: Rc<RefCell<DeviceWrapper>>,
+        region_size: vk::DeviceSize,
+        region_count: u32,
+        name: &str) -> Self {
+        assert!(region_count > 0, "DynamicUploadBuffer requires at least one region");
+
+        // keep every region start aligned for any binding type
+        let region_size = align_up(
+            region_size,
+            device.borrow().get_device_limits().min_uniform_buffer_offset_alignment
+                .max(device.borrow().get_device_limits().min_storage_buffer_offset_alignment));
+
+        let create_info = BufferCreateInfo::new(
+            vk::BufferCreateInfo::builder()
+                .size(region_size * region_count as vk::DeviceSize)
+                .usage(vk::BufferUsageFlags::VERTEX_BUFFER |
+                    vk::BufferUsageFlags::INDEX_BUFFER |
+                    vk::BufferUsageFlags::UNIFORM_BUFFER |
+                    vk::BufferUsageFlags::STORAGE_BUFFER)
+                .sharing_mode(vk::SharingMode::EXCLUSIVE)
+                .build(),
+            name.to_string());
+
+        let mut buffer = DeviceWrapper::create_buffer(
+            device.clone(),
+            &create_info,
+            MemoryLocation::CpuToGpu);
+        if let Some(ResourceType::Buffer(buffer_wrapper)) = buffer.resource_type.as_mut() {
+            buffer_wrapper.per_frame = true;
+        }
+
+        let mapped = {
+            let allocation = buffer.allocation.as_ref().expect("DynamicUploadBuffer has no allocation");
+            // writes are never flushed, so the memory must be coherent
+            assert!(allocation.memory_properties().contains(vk::MemoryPropertyFlags::HOST_COHERENT),
+                "DynamicUploadBuffer requires host-coherent memory");
+            allocation.mapped_ptr().expect("DynamicUploadBuffer memory is not persistently mapped").as_ptr() as *mut u8
+        };
+
+        DynamicUploadBuffer {
+            buffer: Rc::new(RefCell::new(buffer)),
+            mapped,
+            region_size,
+            region_count,
+            current_region: 0,
+            cursor: 0,
+            device
+        }
+    }
+
+    /// Switches to the region for `frame_index` and resets it. `fence` must be the fence
+    /// signaled by the last submission which used this region; it is waited on before the
+    /// region is reused.
+    pub fn begin_frame(&mut self, frame_index: u32, fence: vk::Fence) {
+        unsafe {
+            self.device.borrow().get().wait_for_fences(
+                std::slice::from_ref(&fence),
+                true,
+                u64::MAX)
+                .expect("Failed to wait for DynamicUploadBuffer region fence");
+        }
+
+        self.current_region = frame_index % self.region_count;
+        self.cursor = 0;
+    }
+
+    /// Sub-allocates `size` bytes from the current frame's region. Returns the offset into
+    /// [`get_buffer`](Self::get_buffer) along with a pointer to the mapped memory at that offset.
+    pub fn allocate(&mut self, size: vk::DeviceSize, alignment: vk::DeviceSize) -> (vk::DeviceSize, *mut c_void) {
+        let region_offset = align_up(self.cursor, alignment);
+        assert!(region_offset + size <= self.region_size,
+            "DynamicUploadBuffer region exhausted; requested {} bytes with {} of {} used",
+            size, self.cursor, self.region_size);
+        self.cursor = region_offset + size;
+
+        let offset = self.current_region as vk::DeviceSize * self.region_size + region_offset;
+        let ptr = unsafe { self.mapped.add(offset as usize) } as *mut c_void;
+
+        (offset, ptr)
+    }
+
+    /// Copies `values` into the current region and returns their offset
+    pub fn push<T>(&mut self, values: &[T], alignment: vk::DeviceSize) -> vk::DeviceSize {
+        let size = std::mem::size_of_val(values) as vk::DeviceSize;
+        let alignment = alignment.max(std::mem::align_of::<T>() as vk::DeviceSize);
+        let (offset, ptr) = self.allocate(size, alignment);
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                values.as_ptr(),
+                ptr as *mut T,
+                values.len());
+        }
+
+        offset
+    }
+
+    /// Alignment required for uniform buffer offsets into this buffer
+    pub fn get_uniform_alignment(&self) -> vk::DeviceSize {
+        self.device.borrow().get_device_limits().min_uniform_buffer_offset_alignment
+    }
+
+    pub fn get_buffer(&self) -> Rc<RefCell<DeviceResource>> { self.buffer.clone() }
+}
@@ -0,0 +1,96 @@
+use std::ffi::CStr;
+use ash::vk;
+use serde::Deserialize;
+
+/// A physical device as reported by the Vulkan instance, for presenting a GPU selection to
+/// the user. `index` is the device's position in the instance's enumeration order, which is
+/// stable for a given system configuration
+#[derive(Clone, Debug)]
+pub struct AdapterInfo {
+    pub index: usize,
+    pub name: String,
+    pub device_type: vk::PhysicalDeviceType,
+    pub vendor_id: u32,
+    pub device_id: u32,
+    /// Only reported by drivers on Windows
+    pub luid: Option<[u8; 8]>,
+    /// Total size of the device's DEVICE_LOCAL memory heaps
+    pub device_local_memory: vk::DeviceSize,
+    /// Whether the device supports everything the context requires. Unsuitable devices are
+    /// never selected
+    pub suitable: bool
+}
+
+/// Which physical device VulkanRenderContext should create its logical device on
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+pub enum AdapterSelection {
+    /// The first suitable device, preferring discrete over integrated over virtual GPUs
+    #[default]
+    Auto,
+    /// See AdapterInfo::index
+    Index(usize),
+    /// The first device with this name
+    Name(String),
+    Luid([u8; 8])
+}
+
+impl AdapterSelection {
+    /// Finds the selected adapter among `adapters`. Returns None for Auto, or if the selected
+    /// adapter doesn't exist or isn't suitable
+    pub fn find<'a>(&self, adapters: &'a [AdapterInfo]) -> Option<&'a AdapterInfo> {
+        let selected = match self {
+            AdapterSelection::Auto => None,
+            AdapterSelection::Index(index) => adapters.iter().find(|adapter| adapter.index == *index),
+            AdapterSelection::Name(name) => adapters.iter().find(|adapter| &adapter.name == name),
+            AdapterSelection::Luid(luid) => adapters.iter().find(|adapter| adapter.luid == Some(*luid))
+        };
+        selected.filter(|adapter| adapter.suitable)
+    }
+}
+
+/// Ranks device types for AdapterSelection::Auto, lower is preferred
+pub(crate) fn get_device_ranking(device_type: vk::PhysicalDeviceType) -> u32 {
+    match device_type {
+        vk::PhysicalDeviceType::DISCRETE_GPU => 0,
+        vk::PhysicalDeviceType::INTEGRATED_GPU => 1,
+        vk::PhysicalDeviceType::VIRTUAL_GPU => 2,
+        _ => 3
+    }
+}
+
+pub(crate) fn query_adapter_info(
+    instance: &ash::Instance,
+    index: usize,
+    physical_device: vk::PhysicalDevice,
+    suitable: bool) -> AdapterInfo {
+
+    let mut id_properties = vk::PhysicalDeviceIDProperties::default();
+    let memory_properties = unsafe {
+        let mut properties2 = vk::PhysicalDeviceProperties2::builder()
+            .push_next(&mut id_properties);
+        instance.get_physical_device_properties2(physical_device, &mut properties2);
+        instance.get_physical_device_memory_properties(physical_device)
+    };
+    let properties = unsafe { instance.get_physical_device_properties(physical_device) };
+
+    let name = unsafe { CStr::from_ptr(properties.device_name.as_ptr()) }
+        .to_string_lossy()
+        .into_owned();
+
+    let device_local_memory = memory_properties.memory_heaps[..memory_properties.memory_heap_count as usize]
+        .iter()
+        .filter(|heap| heap.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL))
+        .map(|heap| heap.size)
+        .sum();
+
+    AdapterInfo {
+        index,
+        name,
+        device_type: properties.device_type,
+        vendor_id: properties.vendor_id,
+        device_id: properties.device_id,
+        luid: if id_properties.device_luid_valid > 0 { Some(id_properties.device_luid) } else { None },
+        device_local_memory,
+        suitable
+    }
+}
@@ -0,0 +1,342 @@
+use std::cell::RefCell;
+use std::fmt::{Debug, Formatter};
+use std::rc::Rc;
+use ash::extensions::ext::DescriptorBuffer;
+use ash::vk;
+use gpu_allocator::MemoryLocation;
+use api_types::buffer::BufferCreateInfo;
+use api_types::device::{DeviceResource, DeviceWrapper};
+
+/// Size of the first descriptor buffer created for each frame. Buffers created because a
+/// frame ran out of space are scaled up from this (see [`DescriptorBufferManager`])
+pub const DEFAULT_DESCRIPTOR_BUFFER_SIZE: vk::DeviceSize = 256 * 1024;
+
+// allocations which don't fit in a buffer 2^16 times the default size are assumed to
+// never fit
+const MAX_BUFFER_GENERATION: u32 = 16;
+
+// combined image samplers live in buffers with both usages
+const DESCRIPTOR_BUFFER_USAGE: vk::BufferUsageFlags = vk::BufferUsageFlags::from_raw(
+    vk::BufferUsageFlags::RESOURCE_DESCRIPTOR_BUFFER_EXT.as_raw() |
+    vk::BufferUsageFlags::SAMPLER_DESCRIPTOR_BUFFER_EXT.as_raw());
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct DescriptorBufferStats {
+    /// Descriptor buffers currently allocated across every frame
+    pub buffer_count: u32,
+    /// Buffers created because a frame's existing buffers were exhausted
+    pub growth_count: u32,
+    /// Bytes of descriptors written so far during the current frame
+    pub frame_bytes_used: vk::DeviceSize,
+    /// Most bytes of descriptors written during any single frame
+    pub peak_bytes_used: vk::DeviceSize
+}
+
+struct FrameBuffers {
+    buffers: Vec<DeviceResource>,
+    current_buffer: usize,
+    // next free byte of the current buffer
+    cursor: vk::DeviceSize,
+    bytes_used: vk::DeviceSize
+}
+
+/// The descriptor sets of a single pass, staged in one of the frame's descriptor buffers.
+/// Only valid until the manager begins the same frame index again
+#[derive(Clone, Debug)]
+pub struct DescriptorBufferSets {
+    buffer_address: vk::DeviceAddress,
+    mapped_ptr: *mut u8,
+    set_layouts: Vec<vk::DescriptorSetLayout>,
+    // offset of each set from the start of the buffer; None for null set layouts
+    set_offsets: Vec<Option<vk::DeviceSize>>
+}
+
+/// Stages descriptors for each frame in flight into persistently mapped descriptor buffers,
+/// replacing descriptor pools on devices with NegotiatedFeature::DescriptorBuffer. Each pass's
+/// sets are suballocated linearly; when a frame's buffer is exhausted another buffer, twice the
+/// size of the last, is added to its chain, the same way DescriptorPoolManager grows pools.
+///
+/// Nothing is freed individually. Instead a frame's buffers are reused from the start once its
+/// fence has signaled (see [`begin_frame`](Self::begin_frame)).
+pub struct DescriptorBufferManager {
+    loader: DescriptorBuffer,
+    properties: vk::PhysicalDeviceDescriptorBufferPropertiesEXT,
+    frames: Vec<FrameBuffers>,
+    frame_index: usize,
+    growth_count: u32,
+    peak_bytes_used: vk::DeviceSize,
+    device: Rc<RefCell<DeviceWrapper>>
+}
+
+impl Debug for DescriptorBufferManager {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DescriptorBufferManager")
+            .field("offset alignment", &self.properties.descriptor_buffer_offset_alignment)
+            .field("stats", &self.get_stats())
+            .finish()
+    }
+}
+
+fn align_up(value: vk::DeviceSize, alignment: vk::DeviceSize) -> vk::DeviceSize {
+    (value + alignment - 1) / alignment * alignment
+}
+
+/// Places sets of `set_sizes` one after another, starting from `cursor` rounded up to
+/// `alignment`. Returns the offset of each set, None for sets without a size, and the end of
+/// the last set
+fn suballocate(
+    cursor: vk::DeviceSize,
+    set_sizes: &[Option<vk::DeviceSize>],
+    alignment: vk::DeviceSize) -> (Vec<Option<vk::DeviceSize>>, vk::DeviceSize) {
+
+    let mut offset = align_up(cursor, alignment);
+    let set_offsets = set_sizes.iter().map(|set_size| {
+        set_size.map(|set_size| {
+            let set_offset = offset;
+            offset += set_size;
+            set_offset
+        })
+    }).collect();
+    (set_offsets, offset)
+}
+
+impl DescriptorBufferManager {
+    pub fn new(
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        device: Rc<RefCell<DeviceWrapper>>,
+        num_frames: u32) -> Self {
+
+        let loader = DescriptorBuffer::new(instance, device.borrow().get());
+        let properties = {
+            let mut descriptor_buffer_properties = vk::PhysicalDeviceDescriptorBufferPropertiesEXT::default();
+            let mut properties = vk::PhysicalDeviceProperties2::builder()
+                .push_next(&mut descriptor_buffer_properties)
+                .build();
+            unsafe {
+                instance.get_physical_device_properties2(physical_device, &mut properties);
+            }
+            descriptor_buffer_properties
+        };
+        log::trace!(target: "descriptor", "Using descriptor buffers with offset alignment {}", properties.descriptor_buffer_offset_alignment);
+
+        let mut manager = DescriptorBufferManager {
+            loader,
+            properties,
+            frames: Vec::new(),
+            frame_index: 0,
+            growth_count: 0,
+            peak_bytes_used: 0,
+            device
+        };
+
+        for frame_index in 0..num_frames {
+            let buffer = manager.create_buffer(frame_index as usize, 0);
+            manager.frames.push(FrameBuffers {
+                buffers: vec![buffer],
+                current_buffer: 0,
+                cursor: 0,
+                bytes_used: 0
+            });
+        }
+
+        manager
+    }
+
+    /// Stages into the buffers used by `frame_index` from their start until the next call.
+    /// The frame's fence must have signaled
+    pub fn begin_frame(&mut self, frame_index: u32) {
+        self.frame_index = frame_index as usize;
+        let frame = &mut self.frames[self.frame_index];
+        frame.current_buffer = 0;
+        frame.cursor = 0;
+        frame.bytes_used = 0;
+    }
+
+    /// Reserves space for a set of each layout, in a single buffer so they can be bound together
+    pub fn allocate(&mut self, layouts: &[vk::DescriptorSetLayout], name: &str) -> DescriptorBufferSets {
+        let alignment = self.properties.descriptor_buffer_offset_alignment;
+        let set_sizes: Vec<Option<vk::DeviceSize>> = layouts.iter().map(|layout| {
+            match *layout == vk::DescriptorSetLayout::null() {
+                true => None,
+                false => Some(align_up(unsafe { self.loader.get_descriptor_set_layout_size(*layout) }, alignment))
+            }
+        }).collect();
+        let total_size: vk::DeviceSize = set_sizes.iter().flatten().sum();
+
+        let (set_offsets, end) = loop {
+            let frame = &self.frames[self.frame_index];
+            let buffer = frame.buffers[frame.current_buffer].get_buffer();
+            let (set_offsets, end) = suballocate(frame.cursor, &set_sizes, alignment);
+            if end <= buffer.create_info.size {
+                break (set_offsets, end);
+            }
+            self.advance_buffer(total_size, name);
+        };
+
+        let frame = &mut self.frames[self.frame_index];
+        let resource = &frame.buffers[frame.current_buffer];
+        frame.bytes_used += end - frame.cursor;
+        frame.cursor = end;
+        self.peak_bytes_used = self.peak_bytes_used.max(frame.bytes_used);
+
+        DescriptorBufferSets {
+            buffer_address: resource.get_buffer().get_device_address()
+                .expect("Descriptor buffer has no device address"),
+            mapped_ptr: resource.allocation.as_ref()
+                .and_then(|allocation| allocation.mapped_ptr())
+                .expect("Descriptor buffer isn't mapped")
+                .as_ptr() as *mut u8,
+            set_layouts: layouts.to_vec(),
+            set_offsets
+        }
+    }
+
+    /// Writes a single descriptor to `binding` of `set`
+    pub fn write(&self, sets: &DescriptorBufferSets, set: u32, binding: u32, descriptor_info: &vk::DescriptorGetInfoEXT) {
+        let set_offset = sets.set_offsets[set as usize]
+            .unwrap_or_else(|| panic!("Descriptor set {} has no layout", set));
+        let size = self.get_descriptor_size(descriptor_info.ty);
+        unsafe {
+            let binding_offset = self.loader.get_descriptor_set_layout_binding_offset(sets.set_layouts[set as usize], binding);
+            let descriptor = std::slice::from_raw_parts_mut(
+                sets.mapped_ptr.add((set_offset + binding_offset) as usize),
+                size);
+            self.loader.get_descriptor(descriptor_info, descriptor);
+        }
+    }
+
+    /// Binds the buffer `sets` were staged in, and points each set at its descriptors
+    pub fn bind(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        bind_point: vk::PipelineBindPoint,
+        pipeline_layout: vk::PipelineLayout,
+        sets: &DescriptorBufferSets) {
+
+        if sets.set_offsets.iter().all(|set_offset| set_offset.is_none()) {
+            return;
+        }
+
+        let binding_info = vk::DescriptorBufferBindingInfoEXT::builder()
+            .address(sets.buffer_address)
+            .usage(DESCRIPTOR_BUFFER_USAGE)
+            .build();
+        unsafe {
+            self.loader.cmd_bind_descriptor_buffers(command_buffer, std::slice::from_ref(&binding_info));
+            for (set, set_offset) in sets.set_offsets.iter().enumerate() {
+                if let Some(set_offset) = set_offset {
+                    self.loader.cmd_set_descriptor_buffer_offsets(
+                        command_buffer,
+                        bind_point,
+                        pipeline_layout,
+                        set as u32,
+                        &[0],
+                        std::slice::from_ref(set_offset));
+                }
+            }
+        }
+    }
+
+    pub fn get_stats(&self) -> DescriptorBufferStats {
+        DescriptorBufferStats {
+            buffer_count: self.frames.iter().map(|frame| frame.buffers.len() as u32).sum(),
+            growth_count: self.growth_count,
+            frame_bytes_used: self.frames.get(self.frame_index).map_or(0, |frame| frame.bytes_used),
+            peak_bytes_used: self.peak_bytes_used
+        }
+    }
+
+    pub fn get_properties(&self) -> &vk::PhysicalDeviceDescriptorBufferPropertiesEXT { &self.properties }
+
+    fn get_descriptor_size(&self, descriptor_type: vk::DescriptorType) -> usize {
+        match descriptor_type {
+            vk::DescriptorType::SAMPLER => self.properties.sampler_descriptor_size,
+            vk::DescriptorType::COMBINED_IMAGE_SAMPLER => self.properties.combined_image_sampler_descriptor_size,
+            vk::DescriptorType::SAMPLED_IMAGE => self.properties.sampled_image_descriptor_size,
+            vk::DescriptorType::STORAGE_IMAGE => self.properties.storage_image_descriptor_size,
+            vk::DescriptorType::UNIFORM_BUFFER => self.properties.uniform_buffer_descriptor_size,
+            vk::DescriptorType::STORAGE_BUFFER => self.properties.storage_buffer_descriptor_size,
+            vk::DescriptorType::INPUT_ATTACHMENT => self.properties.input_attachment_descriptor_size,
+            _ => panic!("Unsupported descriptor buffer descriptor type: {:?}", descriptor_type)
+        }
+    }
+
+    /// Moves the current frame on to its next buffer, creating one if the chain is exhausted
+    fn advance_buffer(&mut self, size: vk::DeviceSize, name: &str) {
+        let frame = &self.frames[self.frame_index];
+        let next_buffer = frame.current_buffer + 1;
+        if next_buffer == frame.buffers.len() {
+            assert!(next_buffer as u32 <= MAX_BUFFER_GENERATION,
+                "Descriptor buffers for frame {} can't grow to fit {} bytes of descriptors for {}", self.frame_index, size, name);
+            let buffer = self.create_buffer(self.frame_index, next_buffer as u32);
+            self.growth_count += 1;
+            log::trace!(target: "descriptor", "Frame {} exhausted {} descriptor buffers, growing", self.frame_index, next_buffer);
+            self.frames[self.frame_index].buffers.push(buffer);
+        }
+        let frame = &mut self.frames[self.frame_index];
+        frame.current_buffer = next_buffer;
+        frame.cursor = 0;
+    }
+
+    /// Creates a buffer 2^`generation` times the default size
+    fn create_buffer(&self, frame_index: usize, generation: u32) -> DeviceResource {
+        let create_info = vk::BufferCreateInfo::builder()
+            .size(DEFAULT_DESCRIPTOR_BUFFER_SIZE << generation)
+            .usage(DESCRIPTOR_BUFFER_USAGE | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .build();
+        DeviceWrapper::create_buffer(
+            self.device.clone(),
+            &BufferCreateInfo::new(create_info, format!("descriptor_buffer_frame{}_gen{}", frame_index, generation)),
+            MemoryLocation::CpuToGpu)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn align_up_rounds_to_the_next_multiple() {
+        assert_eq!(align_up(0, 64), 0);
+        assert_eq!(align_up(1, 64), 64);
+        assert_eq!(align_up(64, 64), 64);
+        assert_eq!(align_up(65, 64), 128);
+        assert_eq!(align_up(7, 1), 7);
+    }
+
+    #[test]
+    fn sets_start_at_the_aligned_cursor() {
+        let (set_offsets, end) = suballocate(100, &[Some(64), Some(128)], 64);
+        assert_eq!(set_offsets, vec![Some(128), Some(192)]);
+        assert_eq!(end, 320);
+    }
+
+    #[test]
+    fn null_layouts_take_no_space() {
+        let (set_offsets, end) = suballocate(0, &[Some(64), None, Some(64)], 64);
+        assert_eq!(set_offsets, vec![Some(0), None, Some(64)]);
+        assert_eq!(end, 128);
+
+        let (set_offsets, end) = suballocate(32, &[None, None], 64);
+        assert_eq!(set_offsets, vec![None, None]);
+        assert_eq!(end, 64);
+    }
+
+    #[test]
+    fn consecutive_suballocations_are_aligned_and_disjoint() {
+        let alignment = 256;
+        let mut cursor = 0;
+        let mut ranges: Vec<(vk::DeviceSize, vk::DeviceSize)> = Vec::new();
+        for set_size in [align_up(48, alignment), align_up(300, alignment), align_up(1, alignment)] {
+            let (set_offsets, end) = suballocate(cursor, &[Some(set_size)], alignment);
+            let offset = set_offsets[0].unwrap();
+            assert_eq!(offset % alignment, 0);
+            assert!(ranges.iter().all(|(start, range_end)| offset >= *range_end || offset + set_size <= *start));
+            ranges.push((offset, offset + set_size));
+            cursor = end;
+        }
+        assert_eq!(cursor, 256 + 512 + 256);
+    }
+}
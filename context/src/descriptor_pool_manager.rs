@@ -0,0 +1,233 @@
+use std::cell::RefCell;
+use std::fmt::{Debug, Formatter};
+use std::rc::Rc;
+use ash::vk;
+use ash::vk::Handle;
+use api_types::device::DeviceWrapper;
+
+/// Sizes of the first descriptor pool created for each frame. Pools created because a
+/// frame ran out of space are scaled up from these (see [`DescriptorPoolManager`])
+#[derive(Clone, Debug)]
+pub struct DescriptorPoolConfig {
+    pub pool_sizes: Vec<vk::DescriptorPoolSize>,
+    pub max_sets: u32
+}
+
+impl Default for DescriptorPoolConfig {
+    fn default() -> Self {
+        DescriptorPoolConfig {
+            pool_sizes: vec![
+                vk::DescriptorPoolSize {
+                    ty: vk::DescriptorType::UNIFORM_BUFFER,
+                    descriptor_count: 16
+                },
+                vk::DescriptorPoolSize {
+                    ty: vk::DescriptorType::STORAGE_BUFFER,
+                    descriptor_count: 16
+                },
+                vk::DescriptorPoolSize {
+                    ty: vk::DescriptorType::INPUT_ATTACHMENT,
+                    descriptor_count: 16
+                },
+                vk::DescriptorPoolSize {
+                    ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                    descriptor_count: 16
+                },
+                vk::DescriptorPoolSize {
+                    ty: vk::DescriptorType::STORAGE_IMAGE,
+                    descriptor_count: 16
+                }
+            ],
+            max_sets: 8
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct DescriptorPoolStats {
+    /// Pools currently allocated across every frame
+    pub pool_count: u32,
+    /// Pools created because a frame's existing pools were exhausted
+    pub growth_count: u32,
+    /// Sets allocated so far during the current frame
+    pub frame_sets_allocated: u32,
+    /// Most sets allocated during any single frame
+    pub peak_sets_allocated: u32
+}
+
+// allocations which don't fit in a pool 2^16 times the configured size are assumed
+// to never fit
+const MAX_POOL_GENERATION: u32 = 16;
+
+struct FramePools {
+    pools: Vec<vk::DescriptorPool>,
+    current_pool: usize,
+    sets_allocated: u32
+}
+
+/// Allocates descriptor sets for each frame in flight from a chain of pools. When every
+/// pool in a frame's chain is exhausted another pool, twice the size of the last, is added
+/// to it; pools are never destroyed, so a frame's chain settles at whatever size its
+/// heaviest frame needed.
+///
+/// Sets aren't freed individually. Instead all of a frame's pools are reset once its fence
+/// has signaled (see [`begin_frame`](Self::begin_frame)).
+pub struct DescriptorPoolManager {
+    config: DescriptorPoolConfig,
+    frames: Vec<FramePools>,
+    frame_index: usize,
+    growth_count: u32,
+    peak_sets_allocated: u32,
+    device: Rc<RefCell<DeviceWrapper>>
+}
+
+impl Debug for DescriptorPoolManager {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DescriptorPoolManager")
+            .field("config", &self.config)
+            .field("stats", &self.get_stats())
+            .finish()
+    }
+}
+
+impl Drop for DescriptorPoolManager {
+    fn drop(&mut self) {
+        let device = self.device.borrow();
+        for frame in &self.frames {
+            for pool in &frame.pools {
+                unsafe {
+                    device.get().destroy_descriptor_pool(*pool, None);
+                }
+            }
+        }
+    }
+}
+
+impl DescriptorPoolManager {
+    pub fn new(device: Rc<RefCell<DeviceWrapper>>, config: DescriptorPoolConfig, num_frames: u32) -> Self {
+        let mut manager = DescriptorPoolManager {
+            config,
+            frames: Vec::new(),
+            frame_index: 0,
+            growth_count: 0,
+            peak_sets_allocated: 0,
+            device
+        };
+
+        for frame_index in 0..num_frames {
+            let pool = manager.create_pool(frame_index as usize, 0);
+            manager.frames.push(FramePools {
+                pools: vec![pool],
+                current_pool: 0,
+                sets_allocated: 0
+            });
+        }
+
+        manager
+    }
+
+    /// Resets every pool used by `frame_index` and allocates from them until the next call.
+    /// The frame's fence must have signaled
+    pub fn begin_frame(&mut self, frame_index: u32) {
+        self.frame_index = frame_index as usize;
+        let frame = &mut self.frames[self.frame_index];
+        let device = self.device.borrow();
+        for pool in &frame.pools {
+            unsafe {
+                device.get().reset_descriptor_pool(*pool, vk::DescriptorPoolResetFlags::empty())
+                    .expect("Failed to reset descriptor pool");
+            }
+        }
+        frame.current_pool = 0;
+        frame.sets_allocated = 0;
+    }
+
+    /// Sets are named `name` followed by their index in `layouts`
+    pub fn allocate(&mut self, layouts: &[vk::DescriptorSetLayout], name: &str) -> Vec<vk::DescriptorSet> {
+        if layouts.is_empty() {
+            return Vec::new();
+        }
+
+        loop {
+            let frame = &self.frames[self.frame_index];
+            let pool = frame.pools[frame.current_pool];
+            let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(pool)
+                .set_layouts(layouts)
+                .build();
+
+            let result = unsafe {
+                self.device.borrow().get().allocate_descriptor_sets(&alloc_info)
+            };
+
+            match result {
+                Ok(descriptor_sets) => {
+                    let frame = &mut self.frames[self.frame_index];
+                    frame.sets_allocated += descriptor_sets.len() as u32;
+                    self.peak_sets_allocated = self.peak_sets_allocated.max(frame.sets_allocated);
+                    let device = self.device.borrow();
+                    for (index, descriptor_set) in descriptor_sets.iter().enumerate() {
+                        device.set_debug_name(vk::ObjectType::DESCRIPTOR_SET, descriptor_set.as_raw(), &format!("{}_set{}", name, index));
+                    }
+                    return descriptor_sets;
+                },
+                Err(vk::Result::ERROR_OUT_OF_POOL_MEMORY) | Err(vk::Result::ERROR_FRAGMENTED_POOL) => {
+                    self.advance_pool(layouts.len());
+                },
+                Err(error) => {
+                    panic!("Failed to allocate descriptor sets: {:?}", error);
+                }
+            }
+        }
+    }
+
+    pub fn get_stats(&self) -> DescriptorPoolStats {
+        DescriptorPoolStats {
+            pool_count: self.frames.iter().map(|frame| frame.pools.len() as u32).sum(),
+            growth_count: self.growth_count,
+            frame_sets_allocated: self.frames.get(self.frame_index).map_or(0, |frame| frame.sets_allocated),
+            peak_sets_allocated: self.peak_sets_allocated
+        }
+    }
+
+    pub fn get_config(&self) -> &DescriptorPoolConfig { &self.config }
+
+    /// Moves the current frame on to its next pool, creating one if the chain is exhausted
+    fn advance_pool(&mut self, set_count: usize) {
+        let frame = &self.frames[self.frame_index];
+        let next_pool = frame.current_pool + 1;
+        if next_pool == frame.pools.len() {
+            assert!(next_pool as u32 <= MAX_POOL_GENERATION,
+                "Descriptor pools for frame {} can't grow to fit an allocation of {} sets", self.frame_index, set_count);
+            let pool = self.create_pool(self.frame_index, next_pool as u32);
+            self.growth_count += 1;
+            log::trace!(target: "descriptor", "Frame {} exhausted {} descriptor pools, growing", self.frame_index, next_pool);
+            self.frames[self.frame_index].pools.push(pool);
+        }
+        self.frames[self.frame_index].current_pool = next_pool;
+    }
+
+    /// Creates a pool 2^`generation` times the configured size
+    fn create_pool(&self, frame_index: usize, generation: u32) -> vk::DescriptorPool {
+        let scale = 1u32 << generation;
+        let pool_sizes: Vec<vk::DescriptorPoolSize> = self.config.pool_sizes.iter().map(|pool_size| {
+            vk::DescriptorPoolSize {
+                ty: pool_size.ty,
+                descriptor_count: pool_size.descriptor_count.saturating_mul(scale)
+            }
+        }).collect();
+        let create_info = vk::DescriptorPoolCreateInfo::builder()
+            .max_sets(self.config.max_sets.saturating_mul(scale))
+            .pool_sizes(&pool_sizes);
+
+        let pool = unsafe {
+            self.device.borrow().get().create_descriptor_pool(&create_info, None)
+                .expect("Failed to create descriptor pool")
+        };
+        self.device.borrow().set_debug_name(
+            vk::ObjectType::DESCRIPTOR_POOL,
+            pool.as_raw(),
+            &format!("descriptor_pool_frame{}_gen{}", frame_index, generation));
+        pool
+    }
+}
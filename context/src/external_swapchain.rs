@@ -0,0 +1,290 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use ash::vk;
+use api_types::device::{DeviceResource, DeviceWrapper};
+use api_types::resource_state::ResourceState;
+
+/// An image created by an ExternalPresenter
+#[derive(Copy, Clone, Debug)]
+pub struct ExternalImageDesc {
+    pub image: vk::Image,
+    pub format: vk::Format,
+    pub extent: vk::Extent2D,
+    /// More than one for images rendered with multiview, e.g. both eyes of an OpenXR swapchain
+    pub array_layers: u32,
+    pub aspect_flags: vk::ImageAspectFlags
+}
+
+/// Owns the images frames are rendered into and decides when frames happen, in place of a
+/// VkSwapchainKHR. For OpenXR, begin_frame and end_frame wrap xrWaitFrame/xrBeginFrame and
+/// xrEndFrame, and acquire_image and release_image wrap the xr*SwapchainImage calls.
+///
+/// No semaphores are exchanged: a frame's work is submitted to the context's graphics queue
+/// before release_image is called, and the presenter is expected to synchronize with that
+/// queue itself, as OpenXR runtimes do
+pub trait ExternalPresenter {
+    /// Called once, when the ExternalSwapchain is created
+    fn get_images(&self) -> Vec<ExternalImageDesc>;
+    /// The layout images are in once acquired, and must be released in
+    /// (COLOR_ATTACHMENT_OPTIMAL for OpenXR color swapchains). Images are treated as undefined
+    /// the first time they're acquired, so presenters needn't transition them up front
+    fn get_layout(&self) -> vk::ImageLayout;
+    /// Blocks until the next frame should start, then starts it. Returns false if the frame
+    /// shouldn't be rendered (e.g. the session isn't visible), though it must still be ended
+    fn begin_frame(&mut self) -> bool;
+    /// Returns the index of the image to render into, once it's ready to be written
+    fn acquire_image(&mut self) -> u32;
+    fn release_image(&mut self);
+    fn end_frame(&mut self, rendered: bool);
+}
+
+/// The image to render into this frame, with the states to import it in and leave it in
+/// (see Frame::import_resource)
+pub struct ExternalFrameImage {
+    pub image: Rc<RefCell<DeviceResource>>,
+    pub index: u32,
+    pub current_state: ResourceState,
+    pub final_state: ResourceState
+}
+
+/// Renders into the images of an ExternalPresenter, imported once as DeviceResources. The
+/// presenter drives the frame loop:
+///  * begin_frame, skipping the frame's rendering if it returns None
+///  * start the render context's frame and a Frame without a present node (Frame::begin),
+///    importing the image in its current state and exporting it in its final state
+///  * end the Frame and submit its work with no swapchain semaphores to wait on or signal
+///  * end_frame
+///
+/// The images themselves belong to the presenter, so dropping the ExternalSwapchain only
+/// destroys their views; the device must be idle first
+pub struct ExternalSwapchain<P: ExternalPresenter> {
+    // dropped before the presenter, which may own the images they view
+    images: Vec<Rc<RefCell<DeviceResource>>>,
+    frames: PresenterFrames<P>,
+    layout: vk::ImageLayout
+}
+
+/// The order of the presenter's calls, kept apart from the imported images
+struct PresenterFrames<P: ExternalPresenter> {
+    presenter: P,
+    // images hold nothing until they've been acquired once
+    acquired_before: Vec<bool>,
+    frame_started: bool,
+    acquired: bool
+}
+
+impl<P: ExternalPresenter> PresenterFrames<P> {
+    fn new(presenter: P, image_count: usize) -> Self {
+        PresenterFrames {
+            presenter,
+            acquired_before: vec![false; image_count],
+            frame_started: false,
+            acquired: false
+        }
+    }
+
+    /// The index of the image acquired for this frame and whether it was acquired by an earlier
+    /// one, or None if the frame isn't to be rendered
+    fn begin(&mut self) -> Option<(u32, bool)> {
+        assert!(!self.frame_started, "ExternalSwapchain frame has already been started");
+        self.frame_started = true;
+        if !self.presenter.begin_frame() {
+            return None;
+        }
+
+        let index = self.presenter.acquire_image();
+        self.acquired = true;
+        let acquired_before = self.acquired_before.get_mut(index as usize)
+            .expect("ExternalPresenter acquired an image it didn't provide");
+        let initialized = *acquired_before;
+        *acquired_before = true;
+        Some((index, initialized))
+    }
+
+    fn end(&mut self) {
+        assert!(self.frame_started, "ExternalSwapchain frame must be started before it's ended");
+        let rendered = self.acquired;
+        if self.acquired {
+            self.presenter.release_image();
+            self.acquired = false;
+        }
+        self.presenter.end_frame(rendered);
+        self.frame_started = false;
+    }
+}
+
+impl<P: ExternalPresenter> ExternalSwapchain<P> {
+    pub fn new(device: Rc<RefCell<DeviceWrapper>>, presenter: P, name: &str) -> Self {
+        let layout = presenter.get_layout();
+        let images = presenter.get_images().iter().enumerate().map(|(index, desc)| {
+            Rc::new(RefCell::new(DeviceWrapper::import_layered_image(
+                device.clone(),
+                desc.image,
+                desc.format,
+                desc.aspect_flags,
+                1,
+                desc.array_layers,
+                vk::Extent3D {
+                    width: desc.extent.width,
+                    height: desc.extent.height,
+                    depth: 1
+                },
+                layout,
+                &format!("{}_{}", name, index))))
+        }).collect();
+
+        let image_count = images.len();
+        ExternalSwapchain {
+            images,
+            frames: PresenterFrames::new(presenter, image_count),
+            layout
+        }
+    }
+
+    pub fn get_presenter(&self) -> &P { &self.frames.presenter }
+
+    pub fn get_presenter_mut(&mut self) -> &mut P { &mut self.frames.presenter }
+
+    pub fn get_images(&self) -> &[Rc<RefCell<DeviceResource>>] { &self.images }
+
+    pub fn get_layout(&self) -> vk::ImageLayout { self.layout }
+
+    /// Starts the presenter's next frame and acquires the image to render into, or returns None
+    /// if the frame shouldn't be rendered. end_frame must be called either way
+    pub fn begin_frame(&mut self) -> Option<ExternalFrameImage> {
+        let (index, acquired_before) = self.frames.begin()?;
+        let current_layout = if acquired_before {
+            self.layout
+        } else {
+            vk::ImageLayout::UNDEFINED
+        };
+
+        Some(ExternalFrameImage {
+            image: self.images[index as usize].clone(),
+            index,
+            current_state: ResourceState {
+                access: vk::AccessFlags::NONE,
+                stage: vk::PipelineStageFlags::TOP_OF_PIPE,
+                layout: Some(current_layout)
+            },
+            final_state: ResourceState {
+                access: vk::AccessFlags::NONE,
+                stage: vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                layout: Some(self.layout)
+            }
+        })
+    }
+
+    /// Releases the acquired image, if any, and ends the presenter's frame. The frame's work
+    /// must already have been submitted
+    pub fn end_frame(&mut self) {
+        self.frames.end();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct MockPresenter {
+        visible: bool,
+        next_image: u32,
+        calls: Vec<String>
+    }
+
+    impl MockPresenter {
+        fn new(visible: bool) -> Self {
+            MockPresenter {
+                visible,
+                next_image: 0,
+                calls: Vec::new()
+            }
+        }
+    }
+
+    impl ExternalPresenter for MockPresenter {
+        fn get_images(&self) -> Vec<ExternalImageDesc> { Vec::new() }
+
+        fn get_layout(&self) -> vk::ImageLayout { vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL }
+
+        fn begin_frame(&mut self) -> bool {
+            self.calls.push("begin_frame".to_string());
+            self.visible
+        }
+
+        fn acquire_image(&mut self) -> u32 {
+            self.calls.push("acquire_image".to_string());
+            let index = self.next_image;
+            self.next_image = (self.next_image + 1) % 2;
+            index
+        }
+
+        fn release_image(&mut self) {
+            self.calls.push("release_image".to_string());
+        }
+
+        fn end_frame(&mut self, rendered: bool) {
+            self.calls.push(format!("end_frame({})", rendered));
+        }
+    }
+
+    #[test]
+    fn rendered_frames_acquire_and_release_an_image() {
+        let mut frames = PresenterFrames::new(MockPresenter::new(true), 2);
+        assert_eq!(frames.begin(), Some((0, false)));
+        assert_eq!(frames.presenter.calls, ["begin_frame", "acquire_image"]);
+        frames.end();
+        assert_eq!(frames.presenter.calls, ["begin_frame", "acquire_image", "release_image", "end_frame(true)"]);
+    }
+
+    #[test]
+    fn skipped_frames_are_still_ended() {
+        let mut frames = PresenterFrames::new(MockPresenter::new(false), 2);
+        assert_eq!(frames.begin(), None);
+        frames.end();
+        assert_eq!(frames.presenter.calls, ["begin_frame", "end_frame(false)"]);
+
+        // the next frame is rendered once the presenter wants it again
+        frames.presenter.visible = true;
+        assert_eq!(frames.begin(), Some((0, false)));
+        frames.end();
+        assert_eq!(frames.presenter.calls.last().unwrap(), "end_frame(true)");
+    }
+
+    #[test]
+    fn images_are_undefined_until_acquired() {
+        let mut frames = PresenterFrames::new(MockPresenter::new(true), 2);
+        let mut acquisitions = Vec::new();
+        for _ in 0..4 {
+            acquisitions.push(frames.begin().unwrap());
+            frames.end();
+        }
+        assert_eq!(acquisitions, [(0, false), (1, false), (0, true), (1, true)]);
+    }
+
+    #[test]
+    #[should_panic(expected = "already been started")]
+    fn frames_cant_be_started_twice() {
+        let mut frames = PresenterFrames::new(MockPresenter::new(true), 2);
+        frames.begin();
+        frames.begin();
+    }
+
+    #[test]
+    #[should_panic(expected = "must be started before it's ended")]
+    fn frames_must_be_started_to_end() {
+        let mut frames = PresenterFrames::new(MockPresenter::new(true), 2);
+        frames.begin();
+        frames.end();
+        frames.end();
+    }
+
+    #[test]
+    #[should_panic(expected = "acquired an image it didn't provide")]
+    fn acquired_images_must_exist() {
+        let mut frames = PresenterFrames::new(MockPresenter::new(true), 1);
+        frames.begin();
+        frames.end();
+        frames.begin();
+    }
+}
@@ -0,0 +1,391 @@
+use std::collections::HashSet;
+use std::ffi::CStr;
+use ash::vk;
+use api_types::device_capabilities::{EnabledFeatures, NegotiatedFeature};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FeatureRequirement {
+    /// Physical devices which don't support the feature are unsuitable
+    Required,
+    /// Enabled if supported; check DeviceWrapper::is_feature_enabled before relying on it
+    Optional
+}
+
+/// The negotiated features VulkanRenderContext should try to enable
+#[derive(Clone, Debug)]
+pub struct DeviceFeatureRequests {
+    pub requests: Vec<(NegotiatedFeature, FeatureRequirement)>
+}
+
+impl Default for DeviceFeatureRequests {
+    fn default() -> Self {
+        DeviceFeatureRequests {
+            requests: vec![
+                // GPU profiling resets its query pools from the host
+                (NegotiatedFeature::HostQueryReset, FeatureRequirement::Required),
+                (NegotiatedFeature::Synchronization2, FeatureRequirement::Optional),
+                (NegotiatedFeature::DynamicRendering, FeatureRequirement::Optional),
+                (NegotiatedFeature::DescriptorIndexing, FeatureRequirement::Optional),
+                (NegotiatedFeature::SwapchainMaintenance1, FeatureRequirement::Optional),
+                (NegotiatedFeature::Multiview, FeatureRequirement::Optional)
+            ]
+        }
+    }
+}
+
+impl DeviceFeatureRequests {
+    pub fn require(mut self, feature: NegotiatedFeature) -> Self {
+        self.set(feature, FeatureRequirement::Required);
+        self
+    }
+
+    pub fn request(mut self, feature: NegotiatedFeature) -> Self {
+        self.set(feature, FeatureRequirement::Optional);
+        self
+    }
+
+    /// Stops requesting `feature`, e.g. when an instance extension it depends on is missing
+    pub fn remove(mut self, feature: NegotiatedFeature) -> Self {
+        self.requests.retain(|(requested, _)| *requested != feature);
+        self
+    }
+
+    fn set(&mut self, feature: NegotiatedFeature, requirement: FeatureRequirement) {
+        match self.requests.iter_mut().find(|(requested, _)| *requested == feature) {
+            Some(request) => request.1 = requirement,
+            None => self.requests.push((feature, requirement))
+        }
+    }
+}
+
+/// Queries support for, and then enables, a single NegotiatedFeature. Each checker owns the
+/// feature struct it chains onto vk::PhysicalDeviceFeatures2
+pub(crate) trait PhysicalDeviceFeatureChecker {
+    fn get_feature(&self) -> NegotiatedFeature;
+
+    /// Device extensions which must be supported, and are enabled along with the feature
+    fn get_extensions(&self) -> Vec<&'static CStr> { Vec::new() }
+
+    /// Other negotiated features which must be enabled for this one to be
+    fn get_dependencies(&self) -> Vec<NegotiatedFeature> { Vec::new() }
+
+    fn add_feature<'a>(&'a mut self, device_features: vk::PhysicalDeviceFeatures2Builder<'a>) -> vk::PhysicalDeviceFeatures2Builder<'a>;
+
+    /// Whether the feature is supported, once the features chained by add_feature have been
+    /// queried
+    fn check_feature(&self) -> bool;
+
+    /// Resets the feature struct to just the members which should be enabled, ready to be
+    /// chained onto the device's create info
+    fn prepare_enable(&mut self);
+}
+
+struct HostQueryResetFeature {
+    feature: vk::PhysicalDeviceHostQueryResetFeatures
+}
+
+impl PhysicalDeviceFeatureChecker for HostQueryResetFeature {
+    fn get_feature(&self) -> NegotiatedFeature { NegotiatedFeature::HostQueryReset }
+
+    fn add_feature<'a>(&'a mut self, device_features: vk::PhysicalDeviceFeatures2Builder<'a>) -> vk::PhysicalDeviceFeatures2Builder<'a> {
+        device_features.push_next(&mut self.feature)
+    }
+
+    fn check_feature(&self) -> bool {
+        self.feature.host_query_reset > 0
+    }
+
+    fn prepare_enable(&mut self) {
+        self.feature = vk::PhysicalDeviceHostQueryResetFeatures::builder()
+            .host_query_reset(true)
+            .build();
+    }
+}
+
+struct Synchronization2Feature {
+    feature: vk::PhysicalDeviceSynchronization2Features
+}
+
+impl PhysicalDeviceFeatureChecker for Synchronization2Feature {
+    fn get_feature(&self) -> NegotiatedFeature { NegotiatedFeature::Synchronization2 }
+
+    // core in Vulkan 1.3, but the context targets 1.2
+    fn get_extensions(&self) -> Vec<&'static CStr> {
+        vec![vk::KhrSynchronization2Fn::name()]
+    }
+
+    fn add_feature<'a>(&'a mut self, device_features: vk::PhysicalDeviceFeatures2Builder<'a>) -> vk::PhysicalDeviceFeatures2Builder<'a> {
+        device_features.push_next(&mut self.feature)
+    }
+
+    fn check_feature(&self) -> bool {
+        self.feature.synchronization2 > 0
+    }
+
+    fn prepare_enable(&mut self) {
+        self.feature = vk::PhysicalDeviceSynchronization2Features::builder()
+            .synchronization2(true)
+            .build();
+    }
+}
+
+struct DynamicRenderingFeature {
+    feature: vk::PhysicalDeviceDynamicRenderingFeatures
+}
+
+impl PhysicalDeviceFeatureChecker for DynamicRenderingFeature {
+    fn get_feature(&self) -> NegotiatedFeature { NegotiatedFeature::DynamicRendering }
+
+    // core in Vulkan 1.3; its dependencies are core in 1.2
+    fn get_extensions(&self) -> Vec<&'static CStr> {
+        vec![vk::KhrDynamicRenderingFn::name()]
+    }
+
+    fn add_feature<'a>(&'a mut self, device_features: vk::PhysicalDeviceFeatures2Builder<'a>) -> vk::PhysicalDeviceFeatures2Builder<'a> {
+        device_features.push_next(&mut self.feature)
+    }
+
+    fn check_feature(&self) -> bool {
+        self.feature.dynamic_rendering > 0
+    }
+
+    fn prepare_enable(&mut self) {
+        self.feature = vk::PhysicalDeviceDynamicRenderingFeatures::builder()
+            .dynamic_rendering(true)
+            .build();
+    }
+}
+
+struct DescriptorIndexingFeature {
+    feature: vk::PhysicalDeviceDescriptorIndexingFeatures
+}
+
+impl PhysicalDeviceFeatureChecker for DescriptorIndexingFeature {
+    fn get_feature(&self) -> NegotiatedFeature { NegotiatedFeature::DescriptorIndexing }
+
+    fn add_feature<'a>(&'a mut self, device_features: vk::PhysicalDeviceFeatures2Builder<'a>) -> vk::PhysicalDeviceFeatures2Builder<'a> {
+        device_features.push_next(&mut self.feature)
+    }
+
+    fn check_feature(&self) -> bool {
+        self.feature.runtime_descriptor_array > 0 &&
+            self.feature.descriptor_binding_partially_bound > 0 &&
+            self.feature.shader_sampled_image_array_non_uniform_indexing > 0
+    }
+
+    fn prepare_enable(&mut self) {
+        self.feature = vk::PhysicalDeviceDescriptorIndexingFeatures::builder()
+            .runtime_descriptor_array(true)
+            .descriptor_binding_partially_bound(true)
+            .shader_sampled_image_array_non_uniform_indexing(true)
+            .build();
+    }
+}
+
+struct SwapchainMaintenance1Feature {
+    feature: vk::PhysicalDeviceSwapchainMaintenance1FeaturesEXT
+}
+
+impl PhysicalDeviceFeatureChecker for SwapchainMaintenance1Feature {
+    fn get_feature(&self) -> NegotiatedFeature { NegotiatedFeature::SwapchainMaintenance1 }
+
+    // also depends on the instance extension VK_EXT_surface_maintenance1
+    fn get_extensions(&self) -> Vec<&'static CStr> {
+        vec![vk::ExtSwapchainMaintenance1Fn::name()]
+    }
+
+    fn add_feature<'a>(&'a mut self, device_features: vk::PhysicalDeviceFeatures2Builder<'a>) -> vk::PhysicalDeviceFeatures2Builder<'a> {
+        device_features.push_next(&mut self.feature)
+    }
+
+    fn check_feature(&self) -> bool {
+        self.feature.swapchain_maintenance1 > 0
+    }
+
+    fn prepare_enable(&mut self) {
+        self.feature = vk::PhysicalDeviceSwapchainMaintenance1FeaturesEXT::builder()
+            .swapchain_maintenance1(true)
+            .build();
+    }
+}
+
+struct BufferDeviceAddressFeature {
+    feature: vk::PhysicalDeviceBufferDeviceAddressFeatures
+}
+
+impl PhysicalDeviceFeatureChecker for BufferDeviceAddressFeature {
+    fn get_feature(&self) -> NegotiatedFeature { NegotiatedFeature::BufferDeviceAddress }
+
+    // VK_KHR_buffer_device_address is core in Vulkan 1.2, which the context targets
+
+    fn add_feature<'a>(&'a mut self, device_features: vk::PhysicalDeviceFeatures2Builder<'a>) -> vk::PhysicalDeviceFeatures2Builder<'a> {
+        device_features.push_next(&mut self.feature)
+    }
+
+    fn check_feature(&self) -> bool {
+        self.feature.buffer_device_address > 0
+    }
+
+    fn prepare_enable(&mut self) {
+        self.feature = vk::PhysicalDeviceBufferDeviceAddressFeatures::builder()
+            .buffer_device_address(true)
+            .build();
+    }
+}
+
+struct DescriptorBufferFeature {
+    feature: vk::PhysicalDeviceDescriptorBufferFeaturesEXT
+}
+
+impl PhysicalDeviceFeatureChecker for DescriptorBufferFeature {
+    fn get_feature(&self) -> NegotiatedFeature { NegotiatedFeature::DescriptorBuffer }
+
+    fn get_extensions(&self) -> Vec<&'static CStr> {
+        vec![vk::ExtDescriptorBufferFn::name()]
+    }
+
+    // descriptor buffers are bound by their device address
+    fn get_dependencies(&self) -> Vec<NegotiatedFeature> {
+        vec![NegotiatedFeature::BufferDeviceAddress]
+    }
+
+    fn add_feature<'a>(&'a mut self, device_features: vk::PhysicalDeviceFeatures2Builder<'a>) -> vk::PhysicalDeviceFeatures2Builder<'a> {
+        device_features.push_next(&mut self.feature)
+    }
+
+    fn check_feature(&self) -> bool {
+        self.feature.descriptor_buffer > 0
+    }
+
+    fn prepare_enable(&mut self) {
+        self.feature = vk::PhysicalDeviceDescriptorBufferFeaturesEXT::builder()
+            .descriptor_buffer(true)
+            .build();
+    }
+}
+
+struct MultiviewFeature {
+    feature: vk::PhysicalDeviceMultiviewFeatures
+}
+
+impl PhysicalDeviceFeatureChecker for MultiviewFeature {
+    fn get_feature(&self) -> NegotiatedFeature { NegotiatedFeature::Multiview }
+
+    // VK_KHR_multiview is core in Vulkan 1.1
+
+    fn add_feature<'a>(&'a mut self, device_features: vk::PhysicalDeviceFeatures2Builder<'a>) -> vk::PhysicalDeviceFeatures2Builder<'a> {
+        device_features.push_next(&mut self.feature)
+    }
+
+    fn check_feature(&self) -> bool {
+        self.feature.multiview > 0
+    }
+
+    fn prepare_enable(&mut self) {
+        self.feature = vk::PhysicalDeviceMultiviewFeatures::builder()
+            .multiview(true)
+            .build();
+    }
+}
+
+fn create_checker(feature: NegotiatedFeature) -> Box<dyn PhysicalDeviceFeatureChecker> {
+    match feature {
+        NegotiatedFeature::HostQueryReset => Box::new(HostQueryResetFeature { feature: Default::default() }),
+        NegotiatedFeature::Synchronization2 => Box::new(Synchronization2Feature { feature: Default::default() }),
+        NegotiatedFeature::DynamicRendering => Box::new(DynamicRenderingFeature { feature: Default::default() }),
+        NegotiatedFeature::DescriptorIndexing => Box::new(DescriptorIndexingFeature { feature: Default::default() }),
+        NegotiatedFeature::SwapchainMaintenance1 => Box::new(SwapchainMaintenance1Feature { feature: Default::default() }),
+        NegotiatedFeature::BufferDeviceAddress => Box::new(BufferDeviceAddressFeature { feature: Default::default() }),
+        NegotiatedFeature::DescriptorBuffer => Box::new(DescriptorBufferFeature { feature: Default::default() }),
+        NegotiatedFeature::Multiview => Box::new(MultiviewFeature { feature: Default::default() })
+    }
+}
+
+/// The outcome of negotiating a DeviceFeatureRequests against a physical device
+pub(crate) struct FeatureNegotiation {
+    /// Supported checkers, whose feature structs are ready to be chained for device creation
+    checkers: Vec<Box<dyn PhysicalDeviceFeatureChecker>>,
+    /// Required features the device doesn't support
+    pub missing_required: Vec<NegotiatedFeature>
+}
+
+impl FeatureNegotiation {
+    /// `extensions_supported` reports whether the device supports all of the given extensions
+    pub fn negotiate(
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        extensions_supported: impl Fn(&[&CStr]) -> bool,
+        requests: &DeviceFeatureRequests) -> Self {
+
+        let mut checkers: Vec<Box<dyn PhysicalDeviceFeatureChecker>> = requests.requests.iter()
+            .map(|(feature, _)| create_checker(*feature))
+            .collect();
+
+        {
+            let mut physical_device_features = vk::PhysicalDeviceFeatures2::builder();
+            for checker in &mut checkers {
+                physical_device_features = checker.add_feature(physical_device_features);
+            }
+            let mut resolved_physical_device_features = physical_device_features.build();
+            unsafe {
+                instance.get_physical_device_features2(
+                    physical_device,
+                    &mut resolved_physical_device_features);
+            }
+        }
+
+        let mut missing_required = Vec::new();
+        let mut supported_checkers = Vec::new();
+        let mut requirements = Vec::new();
+        for (mut checker, (feature, requirement)) in checkers.into_iter().zip(&requests.requests) {
+            if extensions_supported(&checker.get_extensions()) && checker.check_feature() {
+                checker.prepare_enable();
+                supported_checkers.push(checker);
+                requirements.push(*requirement);
+            } else if *requirement == FeatureRequirement::Required {
+                missing_required.push(*feature);
+            }
+        }
+
+        // dependencies are supported checkers rather than requests, so a feature whose
+        // dependency is missing is dropped along with it
+        let supported: HashSet<NegotiatedFeature> = supported_checkers.iter().map(|checker| checker.get_feature()).collect();
+        let (supported_checkers, unmet): (Vec<_>, Vec<_>) = supported_checkers.into_iter().zip(requirements)
+            .partition(|(checker, _)| checker.get_dependencies().iter().all(|dependency| supported.contains(dependency)));
+        for (checker, requirement) in unmet {
+            log::trace!(target: "context", "{:?} is supported, but not its dependencies {:?}", checker.get_feature(), checker.get_dependencies());
+            if requirement == FeatureRequirement::Required {
+                missing_required.push(checker.get_feature());
+            }
+        }
+        let supported_checkers: Vec<Box<dyn PhysicalDeviceFeatureChecker>> = supported_checkers.into_iter()
+            .map(|(checker, _)| checker)
+            .collect();
+
+        FeatureNegotiation {
+            checkers: supported_checkers,
+            missing_required
+        }
+    }
+
+    pub fn is_satisfied(&self) -> bool {
+        self.missing_required.is_empty()
+    }
+
+    /// Device extensions needed by the supported features
+    pub fn get_extensions(&self) -> Vec<&'static CStr> {
+        self.checkers.iter().flat_map(|checker| checker.get_extensions()).collect()
+    }
+
+    /// Chains every supported feature onto `device_features`
+    pub fn add_features<'a>(&'a mut self, mut device_features: vk::PhysicalDeviceFeatures2Builder<'a>) -> vk::PhysicalDeviceFeatures2Builder<'a> {
+        for checker in &mut self.checkers {
+            device_features = checker.add_feature(device_features);
+        }
+        device_features
+    }
+
+    pub fn get_enabled(&self) -> EnabledFeatures {
+        EnabledFeatures::new(self.checkers.iter().map(|checker| checker.get_feature()).collect::<HashSet<_>>())
+    }
+}
@@ -1,3 +1,11 @@
 pub mod vulkan_render_context;
 pub mod render_context;
+pub mod transient_image_pool;
+pub mod sync_object_pool;
+pub mod descriptor_buffer_manager;
+pub mod descriptor_pool_manager;
+pub mod render_settings;
+pub mod adapter;
+pub mod feature_negotiation;
+pub mod external_swapchain;
 
@@ -0,0 +1,161 @@
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use ash::vk;
+use serde::Deserialize;
+use crate::adapter::AdapterSelection;
+
+/// Settings which would otherwise be hardcoded by the context and its applications. Loaded
+/// from a RON file, where any missing field keeps its default, e.g.
+/// `(vsync: true, msaa_samples: 4)`
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct RenderSettings {
+    /// Scale applied to the swapchain extent for offscreen render targets
+    pub resolution_scale: f32,
+    /// Presents with FIFO when enabled, otherwise IMMEDIATE if the surface supports it
+    pub vsync: bool,
+    /// Sample count for multisampled render targets; rounded down to a power of two
+    pub msaa_samples: u32,
+    /// Enables the Khronos validation layer. Only read when the context is created
+    pub validation: bool,
+    /// Prefers an HDR10 or extended sRGB swapchain when the surface supports one
+    pub hdr: bool,
+    /// The physical device to use, e.g. `adapter: Index(1)`. Only read when the context is
+    /// created; see VulkanRenderContext::enumerate_adapters
+    pub adapter: AdapterSelection
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        RenderSettings {
+            resolution_scale: 1.0,
+            vsync: false,
+            msaa_samples: 1,
+            validation: true,
+            hdr: false,
+            adapter: AdapterSelection::Auto
+        }
+    }
+}
+
+/// What has to be rebuilt for a new set of settings to take effect
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct RenderSettingsChanges {
+    /// Present mode or surface format changed; handled by VulkanRenderContext::apply_settings
+    pub swapchain: bool,
+    /// Sample count or swapchain format changed, so pipelines and multisampled targets must
+    /// be recreated
+    pub pipelines: bool,
+    /// Resolution scale changed, so offscreen targets must be recreated
+    pub render_targets: bool,
+    /// Settings which can't change without recreating the context
+    pub requires_restart: bool
+}
+
+impl RenderSettingsChanges {
+    pub fn between(old: &RenderSettings, new: &RenderSettings) -> Self {
+        RenderSettingsChanges {
+            swapchain: old.vsync != new.vsync || old.hdr != new.hdr,
+            pipelines: old.get_sample_count() != new.get_sample_count() || old.hdr != new.hdr,
+            render_targets: old.resolution_scale != new.resolution_scale,
+            requires_restart: old.validation != new.validation || old.adapter != new.adapter
+        }
+    }
+
+    pub fn any(&self) -> bool {
+        self.swapchain || self.pipelines || self.render_targets || self.requires_restart
+    }
+}
+
+impl RenderSettings {
+    pub fn from_ron(source: &str) -> Result<Self, ron::error::SpannedError> {
+        ron::from_str(source)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let source = std::fs::read_to_string(path)
+            .map_err(|error| format!("Failed to read render settings {}: {}", path.display(), error))?;
+        Self::from_ron(&source)
+            .map_err(|error| format!("Failed to parse render settings {}: {}", path.display(), error))
+    }
+
+    pub fn get_sample_count(&self) -> vk::SampleCountFlags {
+        let samples = self.msaa_samples.clamp(1, 64);
+        // round down to a power of two
+        vk::SampleCountFlags::from_raw(1 << (31 - samples.leading_zeros()))
+    }
+
+    pub fn scale_extent(&self, extent: vk::Extent2D) -> vk::Extent2D {
+        let scale = if self.resolution_scale > 0.0 { self.resolution_scale } else { 1.0 };
+        vk::Extent2D {
+            width: ((extent.width as f32 * scale) as u32).max(1),
+            height: ((extent.height as f32 * scale) as u32).max(1)
+        }
+    }
+}
+
+/// Reloads a settings file whenever its modification time changes
+#[derive(Debug)]
+pub struct RenderSettingsWatcher {
+    path: PathBuf,
+    modified: Option<SystemTime>
+}
+
+impl RenderSettingsWatcher {
+    /// The file isn't read until the first call to [`poll`](Self::poll)
+    pub fn new(path: &Path) -> Self {
+        RenderSettingsWatcher {
+            path: path.to_path_buf(),
+            modified: None
+        }
+    }
+
+    /// Returns the file's settings if it has been modified since the last poll. A file which
+    /// fails to parse is logged and skipped until it's modified again
+    pub fn poll(&mut self) -> Option<RenderSettings> {
+        let modified = std::fs::metadata(&self.path).and_then(|metadata| metadata.modified()).ok()?;
+        if self.modified == Some(modified) {
+            return None;
+        }
+        self.modified = Some(modified);
+
+        match RenderSettings::load(&self.path) {
+            Ok(settings) => {
+                log::trace!(target: "settings", "Reloaded render settings from {}", self.path.display());
+                Some(settings)
+            },
+            Err(error) => {
+                log::warn!(target: "settings", "{}", error);
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sample_count_rounds_down_to_a_power_of_two() {
+        let samples = |msaa_samples| RenderSettings { msaa_samples, ..Default::default() }.get_sample_count();
+        assert_eq!(samples(0), vk::SampleCountFlags::TYPE_1);
+        assert_eq!(samples(1), vk::SampleCountFlags::TYPE_1);
+        assert_eq!(samples(3), vk::SampleCountFlags::TYPE_2);
+        assert_eq!(samples(4), vk::SampleCountFlags::TYPE_4);
+        assert_eq!(samples(7), vk::SampleCountFlags::TYPE_4);
+        assert_eq!(samples(100), vk::SampleCountFlags::TYPE_64);
+    }
+
+    #[test]
+    fn sample_count_changes_recreate_pipelines() {
+        let old = RenderSettings::default();
+        let changes = RenderSettingsChanges::between(&old, &RenderSettings { msaa_samples: 4, ..old.clone() });
+        assert!(changes.pipelines);
+        assert!(!changes.swapchain && !changes.requires_restart);
+
+        // rounds to the same sample count, so nothing is rebuilt
+        let changes = RenderSettingsChanges::between(&RenderSettings { msaa_samples: 4, ..old.clone() }, &RenderSettings { msaa_samples: 5, ..old });
+        assert!(!changes.any());
+    }
+}
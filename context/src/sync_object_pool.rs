@@ -0,0 +1,128 @@
+use std::cell::RefCell;
+use std::fmt::{Debug, Formatter};
+use std::rc::Rc;
+use ash::vk;
+use ash::vk::Handle;
+use api_types::device::DeviceWrapper;
+
+/// Fences and binary semaphores handed out by the render context, so their owners don't have to
+/// destroy them. Released objects are recycled by later requests, and everything the pool
+/// created is destroyed when it's dropped, whether or not it was released.
+///
+/// Objects must only be released once no pending submission, present or acquire uses them:
+/// a released semaphore must be unsignaled, while a released fence may be in either state
+pub struct SyncObjectPool {
+    fences: Vec<vk::Fence>,
+    semaphores: Vec<vk::Semaphore>,
+    free_signaled_fences: Vec<vk::Fence>,
+    free_unsignaled_fences: Vec<vk::Fence>,
+    free_semaphores: Vec<vk::Semaphore>,
+    device: Rc<RefCell<DeviceWrapper>>
+}
+
+impl Debug for SyncObjectPool {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SyncObjectPool")
+            .field("fences", &self.fences.len())
+            .field("semaphores", &self.semaphores.len())
+            .field("free fences", &(self.free_signaled_fences.len() + self.free_unsignaled_fences.len()))
+            .field("free semaphores", &self.free_semaphores.len())
+            .finish()
+    }
+}
+
+impl SyncObjectPool {
+    pub fn new(device: Rc<RefCell<DeviceWrapper>>) -> Self {
+        SyncObjectPool {
+            fences: Vec::new(),
+            semaphores: Vec::new(),
+            free_signaled_fences: Vec::new(),
+            free_unsignaled_fences: Vec::new(),
+            free_semaphores: Vec::new(),
+            device
+        }
+    }
+
+    /// A fence in the signaled state if `signaled`, e.g. for the first wait on a frame's
+    /// fence, or unsignaled otherwise. `name` replaces the debug name of a recycled fence
+    pub fn acquire_fence(&mut self, signaled: bool, name: &str) -> vk::Fence {
+        let recycled = match signaled {
+            true => self.free_signaled_fences.pop(),
+            false => self.free_unsignaled_fences.pop().or_else(|| {
+                // signaled fences can be reset, but can't be signaled again from the host
+                self.free_signaled_fences.pop().map(|fence| {
+                    unsafe {
+                        self.device.borrow().get().reset_fences(std::slice::from_ref(&fence))
+                            .expect("Failed to reset recycled fence");
+                    }
+                    fence
+                })
+            })
+        };
+
+        let fence = recycled.unwrap_or_else(|| {
+            let flags = match signaled {
+                true => vk::FenceCreateFlags::SIGNALED,
+                false => vk::FenceCreateFlags::empty()
+            };
+            let create_info = vk::FenceCreateInfo::builder()
+                .flags(flags)
+                .build();
+            let fence = unsafe {
+                self.device.borrow().get().create_fence(&create_info, None)
+                    .expect("Failed to create pooled fence")
+            };
+            self.fences.push(fence);
+            fence
+        });
+        self.device.borrow().set_debug_name(vk::ObjectType::FENCE, fence.as_raw(), name);
+        fence
+    }
+
+    /// Returns a fence for reuse. It's recycled in whichever state it's in when released
+    pub fn release_fence(&mut self, fence: vk::Fence) {
+        debug_assert!(self.fences.contains(&fence), "Releasing a fence the pool didn't create");
+        let signaled = unsafe {
+            self.device.borrow().get().get_fence_status(fence)
+                .expect("Failed to get status of released fence")
+        };
+        match signaled {
+            true => self.free_signaled_fences.push(fence),
+            false => self.free_unsignaled_fences.push(fence)
+        }
+    }
+
+    /// An unsignaled binary semaphore. `name` replaces the debug name of a recycled semaphore
+    pub fn acquire_semaphore(&mut self, name: &str) -> vk::Semaphore {
+        let semaphore = self.free_semaphores.pop().unwrap_or_else(|| {
+            let create_info = vk::SemaphoreCreateInfo::builder().build();
+            let semaphore = unsafe {
+                self.device.borrow().get().create_semaphore(&create_info, None)
+                    .expect("Failed to create pooled semaphore")
+            };
+            self.semaphores.push(semaphore);
+            semaphore
+        });
+        self.device.borrow().set_debug_name(vk::ObjectType::SEMAPHORE, semaphore.as_raw(), name);
+        semaphore
+    }
+
+    pub fn release_semaphore(&mut self, semaphore: vk::Semaphore) {
+        debug_assert!(self.semaphores.contains(&semaphore), "Releasing a semaphore the pool didn't create");
+        self.free_semaphores.push(semaphore);
+    }
+}
+
+impl Drop for SyncObjectPool {
+    fn drop(&mut self) {
+        let device = self.device.borrow();
+        unsafe {
+            for fence in &self.fences {
+                device.get().destroy_fence(*fence, None);
+            }
+            for semaphore in &self.semaphores {
+                device.get().destroy_semaphore(*semaphore, None);
+            }
+        }
+    }
+}
@@ -0,0 +1,161 @@
+use std::cell::RefCell;
+use std::fmt::{Debug, Formatter};
+use std::rc::Rc;
+use ash::vk;
+use ash::vk::Handle;
+use gpu_allocator::MemoryLocation;
+use api_types::device::{DeviceResource, DeviceWrapper};
+use api_types::image::{ImageCreateInfo, ImageType};
+
+/// Everything which must match for a pooled image to be reused
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TransientImageDesc {
+    pub extent: vk::Extent3D,
+    pub format: vk::Format,
+    pub usage: vk::ImageUsageFlags,
+    pub samples: vk::SampleCountFlags,
+    pub image_type: ImageType
+}
+
+struct PooledImage {
+    desc: TransientImageDesc,
+    resource: Rc<RefCell<DeviceResource>>,
+    size: vk::DeviceSize,
+    last_used_frame: u64
+}
+
+/// Render targets which are recreated every frame (depth buffers, offscreen targets) are
+/// handed out from here instead, reusing images from previous frames with a matching
+/// description. Images which haven't been requested recently are evicted, least recently
+/// used first, once the pool's total memory exceeds its budget.
+///
+/// A reused image keeps its tracked state from the frame which last used it, so the
+/// framegraph orders the new frame's writes after the old frame's through its usual barriers.
+///
+/// Images with SAMPLED usage are given a nearest, clamped sampler, so a pass writing one can
+/// be followed by passes reading it as a combined image sampler.
+pub struct TransientImagePool {
+    images: Vec<PooledImage>,
+    budget: vk::DeviceSize,
+    allocated: vk::DeviceSize,
+    frame_number: u64,
+    device: Rc<RefCell<DeviceWrapper>>
+}
+
+impl Debug for TransientImagePool {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TransientImagePool")
+            .field("image count", &self.images.len())
+            .field("allocated", &self.allocated)
+            .field("budget", &self.budget)
+            .finish()
+    }
+}
+
+impl TransientImagePool {
+    pub fn new(device: Rc<RefCell<DeviceWrapper>>, budget: vk::DeviceSize) -> Self {
+        TransientImagePool {
+            images: Vec::new(),
+            budget,
+            allocated: 0,
+            frame_number: 0,
+            device
+        }
+    }
+
+    /// Images handed out during the previous frame become available again
+    pub fn begin_frame(&mut self) {
+        self.frame_number += 1;
+        self.evict_to_budget();
+    }
+
+    /// Returns an image matching `desc` which hasn't already been requested this frame,
+    /// creating one if none is available
+    pub fn request_image(&mut self, desc: &TransientImageDesc, name: &str) -> Rc<RefCell<DeviceResource>> {
+        let frame_number = self.frame_number;
+        if let Some(pooled) = self.images.iter_mut().find(|pooled| {
+            pooled.desc == *desc && pooled.last_used_frame < frame_number
+        }) {
+            pooled.last_used_frame = frame_number;
+            return pooled.resource.clone();
+        }
+
+        let create_info = vk::ImageCreateInfo::builder()
+            .format(desc.format)
+            .image_type(vk::ImageType::TYPE_2D)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .samples(desc.samples)
+            .usage(desc.usage)
+            .extent(desc.extent)
+            .mip_levels(1)
+            .array_layers(1)
+            .build();
+        let mut image = DeviceWrapper::create_image(
+            self.device.clone(),
+            &ImageCreateInfo::new(create_info, name.to_string(), desc.image_type),
+            MemoryLocation::GpuOnly);
+        if desc.usage.contains(vk::ImageUsageFlags::SAMPLED) {
+            let sampler = unsafe {
+                let sampler_create = vk::SamplerCreateInfo::builder()
+                    .mag_filter(vk::Filter::NEAREST)
+                    .min_filter(vk::Filter::NEAREST)
+                    .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+                    .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                    .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                    .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                    .build();
+
+                self.device.borrow().get().create_sampler(&sampler_create, None)
+                    .expect("Failed to create pooled transient image sampler")
+            };
+            self.device.borrow().set_debug_name(vk::ObjectType::SAMPLER, sampler.as_raw(), &format!("{}_sampler", name));
+            image.get_image_mut().sampler = Some(sampler);
+        }
+        let size = image.allocation.as_ref().map_or(0, |allocation| allocation.size());
+        log::trace!(target: "resource", "Creating pooled transient image {} ({} bytes)", name, size);
+
+        let resource = Rc::new(RefCell::new(image));
+        self.allocated += size;
+        self.images.push(PooledImage {
+            desc: *desc,
+            resource: resource.clone(),
+            size,
+            last_used_frame: frame_number
+        });
+        self.evict_to_budget();
+
+        resource
+    }
+
+    pub fn set_budget(&mut self, budget: vk::DeviceSize) {
+        self.budget = budget;
+        self.evict_to_budget();
+    }
+
+    pub fn get_allocated_size(&self) -> vk::DeviceSize { self.allocated }
+
+    /// Dropped images go through DeviceResource's deferred destruction, so evicting an image
+    /// the GPU may still be using is safe
+    fn evict_to_budget(&mut self) {
+        while self.allocated > self.budget {
+            let frame_number = self.frame_number;
+            let least_recent = self.images.iter().enumerate()
+                .filter(|(_, pooled)| pooled.last_used_frame < frame_number)
+                .min_by_key(|(_, pooled)| pooled.last_used_frame)
+                .map(|(index, _)| index);
+
+            match least_recent {
+                Some(index) => {
+                    let evicted = self.images.swap_remove(index);
+                    log::trace!(target: "resource", "Evicting pooled transient image {}", evicted.resource.borrow().get_handle());
+                    self.allocated -= evicted.size;
+                },
+                None => {
+                    // everything in the pool is in use by the current frame
+                    break;
+                }
+            }
+        }
+    }
+}
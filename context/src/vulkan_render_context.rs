@@ -1,24 +1,38 @@
 use std::cell::RefCell;
+use std::collections::HashMap;
 use std::ffi::{c_void, CStr};
 use std::fmt::{Debug, Formatter};
 use std::os::raw::c_char;
 use std::rc::Rc;
 use ash::{vk};
-use ash::vk::{ExtendsPhysicalDeviceFeatures2, PFN_vkGetPhysicalDeviceFeatures2, PhysicalDeviceFeatures2, PhysicalDeviceFeatures2Builder, PresentModeKHR};
+use ash::vk::{ExtendsPhysicalDeviceFeatures2, Handle, PFN_vkGetPhysicalDeviceFeatures2, PresentModeKHR};
 
 use ash::vk::DebugUtilsMessageSeverityFlagsEXT as severity_flags;
 use ash::vk::DebugUtilsMessageTypeFlagsEXT as type_flags;
 use api_types::device::{DeviceFramebuffer, DeviceResource, DeviceWrapper, PhysicalDeviceWrapper, QueueFamilies, VulkanDebug};
 use api_types::image::ImageWrapper;
+use api_types::resource_state::ResourceState;
 use api_types::instance::InstanceWrapper;
 use api_types::surface;
 use api_types::surface::SurfaceWrapper;
 use api_types::swapchain::{NextImage, SwapchainStatus, SwapchainWrapper};
-use profiling::{enter_span, init_gpu_profiling, reset_gpu_profiling};
+use profiling::{enter_span, init_gpu_profiling, FramegraphProfiler, GpuProfiler, GpuQueue};
 
+use crate::adapter::{get_device_ranking, query_adapter_info, AdapterInfo, AdapterSelection};
+use api_types::device_capabilities::NegotiatedFeature;
+use crate::feature_negotiation::{DeviceFeatureRequests, FeatureNegotiation};
+use crate::descriptor_buffer_manager::{DescriptorBufferManager, DescriptorBufferSets, DescriptorBufferStats};
+use crate::descriptor_pool_manager::{DescriptorPoolConfig, DescriptorPoolManager, DescriptorPoolStats};
 use crate::render_context::RenderContext;
+use crate::render_settings::{RenderSettings, RenderSettingsChanges};
+use crate::sync_object_pool::SyncObjectPool;
+use crate::transient_image_pool::TransientImagePool;
 
-const MAX_FRAMES_IN_FLIGHT: u32 = 2;
+/// Frames in flight for contexts created with VulkanRenderContext::new
+pub const DEFAULT_FRAMES_IN_FLIGHT: u32 = 2;
+/// The most frames in flight VulkanRenderContext::init accepts
+pub const MAX_FRAMES_IN_FLIGHT: u32 = 3;
+const TRANSIENT_IMAGE_BUDGET: vk::DeviceSize = 256 * 1024 * 1024;
 
 unsafe extern "system" fn debug_utils_callback(
     severity: vk::DebugUtilsMessageSeverityFlagsEXT,
@@ -52,8 +66,6 @@ fn get_instance_extensions() -> Vec<&'static CStr> {
     vec![
         vk::KhrPortabilityEnumerationFn::name(),
         vk::KhrGetPhysicalDeviceProperties2Fn::name(),
-        vk::KhrGetSurfaceCapabilities2Fn::name(), // dependency of EXTSurfaceMaintenance1
-        vk::ExtSurfaceMaintenance1Fn::name(), // dependency of device extension EXTSwapchainMaintenance1
     ]
 }
 
@@ -61,8 +73,6 @@ fn get_instance_extensions() -> Vec<&'static CStr> {
 fn get_instance_extensions() -> Vec<&'static CStr> {
     vec![
         vk::KhrGetPhysicalDeviceProperties2Fn::name(),
-        vk::KhrGetSurfaceCapabilities2Fn::name(), // dependency of EXTSurfaceMaintenance1
-        vk::ExtSurfaceMaintenance1Fn::name() // dependency of device extension EXTSwapchainMaintenance1
         // use winit::platform::macos::WindowBuilderExtMacOS;
 
     ]
@@ -70,10 +80,52 @@ fn get_instance_extensions() -> Vec<&'static CStr> {
     // instance_extensions.push(vk::KhrGetPhysicalDeviceProperties2Fn::name());
 }
 
+// Only used when presenting, so headless contexts can run on devices without them. They're
+// only needed by swapchain_maintenance1, so they're skipped where unsupported (e.g. MoltenVK)
+fn get_presentation_instance_extensions(entry: &ash::Entry) -> Vec<&'static CStr> {
+    let extensions = vec![
+        vk::KhrGetSurfaceCapabilities2Fn::name(), // dependency of EXTSurfaceMaintenance1
+        vk::ExtSurfaceMaintenance1Fn::name() // dependency of device extension EXTSwapchainMaintenance1
+    ];
+    if extensions.iter().all(|extension| is_instance_extension_supported(entry, extension)) {
+        extensions
+    } else {
+        Vec::new()
+    }
+}
+
+/// Instance extension support, checked before an instance exists
+fn is_instance_extension_supported(entry: &ash::Entry, extension: &CStr) -> bool {
+    let extension_properties = entry.enumerate_instance_extension_properties(None)
+        .expect("Failed to enumerate instance extensions");
+    extension_properties.iter().any(|properties| {
+        unsafe { CStr::from_ptr(properties.extension_name.as_ptr()) == extension }
+    })
+}
+
+// EXTSwapchainMaintenance1 is negotiated as NegotiatedFeature::SwapchainMaintenance1
+fn get_presentation_device_extensions() -> Vec<&'static CStr> {
+    vec![
+        ash::extensions::khr::Swapchain::name()
+    ]
+}
+
+/// Drops the request for swapchain_maintenance1 when the instance extensions it depends on
+/// weren't enabled
+fn get_presentation_feature_requests(
+    feature_requests: DeviceFeatureRequests,
+    presentation_instance_extensions: &[&CStr]) -> DeviceFeatureRequests {
+
+    if presentation_instance_extensions.contains(&vk::ExtSurfaceMaintenance1Fn::name()) {
+        feature_requests
+    } else {
+        feature_requests.remove(NegotiatedFeature::SwapchainMaintenance1)
+    }
+}
+
 #[cfg(target_os = "macos")]
 fn get_logical_device_extensions() -> Vec<&'static CStr> {
     vec![
-        ash::extensions::khr::Swapchain::name(),
         // Needed for MoltenVK
         vk::KhrPortabilitySubsetFn::name()
     ]
@@ -81,17 +133,15 @@ fn get_logical_device_extensions() -> Vec<&'static CStr> {
 
 #[cfg(not(target_os = "macos"))]
 fn get_logical_device_extensions() -> Vec<&'static CStr> {
-    vec![
-        ash::extensions::khr::Swapchain::name()
-    ]
+    vec![]
 }
 
 fn get_physical_device_extensions() -> Vec<&'static CStr> {
-    vec![
-        ash::extensions::khr::Swapchain::name(),
-        vk::ExtSwapchainMaintenance1Fn::name()  // required for present signaling
-
-    ]
+    #[allow(unused_mut)]
+    let mut extensions: Vec<&'static CStr> = vec![];
+    #[cfg(feature = "external-memory")]
+    extensions.append(&mut api_types::external_memory::get_external_memory_extensions());
+    extensions
 }
 
 #[cfg(target_os = "macos")]
@@ -104,43 +154,6 @@ fn get_instance_flags() -> vk::InstanceCreateFlags {
     vk::InstanceCreateFlags::empty()
 }
 
-trait PhysicalDeviceFeatureChecker {
-    fn add_feature<'a>(&'a mut self, device_features: vk::PhysicalDeviceFeatures2Builder<'a>) -> vk::PhysicalDeviceFeatures2Builder<'a>;
-
-    fn check_feature(&self, device_features: &vk::PhysicalDeviceFeatures2) -> bool;
-}
-
-struct HostQueryResetPhysicalDeviceFeature {
-    feature: vk::PhysicalDeviceHostQueryResetFeatures
-}
-
-impl HostQueryResetPhysicalDeviceFeature {
-    pub fn new() -> Self {
-        let feature = vk::PhysicalDeviceHostQueryResetFeatures::builder()
-            .host_query_reset(true)
-            .build();
-        HostQueryResetPhysicalDeviceFeature {
-            feature: feature
-        }
-    }
-}
-
-impl PhysicalDeviceFeatureChecker for HostQueryResetPhysicalDeviceFeature {
-    fn add_feature<'a>(&'a mut self, device_features: PhysicalDeviceFeatures2Builder<'a>) -> vk::PhysicalDeviceFeatures2Builder<'a> {
-        device_features.push_next(&mut self.feature)
-    }
-
-    fn check_feature(&self, device_features: &PhysicalDeviceFeatures2) -> bool {
-        self.feature.host_query_reset > 0
-    }
-}
-
-fn get_required_physical_device_features() -> Vec<Box<dyn PhysicalDeviceFeatureChecker>> {
-    vec![
-        Box::new(HostQueryResetPhysicalDeviceFeature::new())
-    ]
-}
-
 fn create_vulkan_instance(
     entry: &ash::Entry,
     application_info: &vk::ApplicationInfo,
@@ -190,7 +203,14 @@ fn get_queue_family_indices(
         instance.get().get_physical_device_queue_family_properties(physical_device)
     };
 
-    let mut queue_family_indices = QueueFamilies {graphics: None, compute: None, present: None};
+    let mut queue_family_indices = QueueFamilies {graphics: None, compute: None, present: None, transfer: None};
+    queue_family_indices.transfer = queue_families.iter()
+        .position(|queue_family| {
+            queue_family.queue_count > 0 &&
+                queue_family.queue_flags.contains(vk::QueueFlags::TRANSFER) &&
+                !queue_family.queue_flags.intersects(vk::QueueFlags::GRAPHICS | vk::QueueFlags::COMPUTE)
+        })
+        .map(|index| index as u32);
 
     let mut current_index: u32 = 0;
     for queue_family in queue_families.iter() {
@@ -274,11 +294,24 @@ pub fn are_extensions_supported(
     all_extensions_found
 }
 
+fn negotiate_features(
+    instance: &InstanceWrapper,
+    physical_device: vk::PhysicalDevice,
+    feature_requests: &DeviceFeatureRequests) -> FeatureNegotiation {
+
+    FeatureNegotiation::negotiate(
+        instance.get(),
+        physical_device,
+        |extensions| are_extensions_supported(instance, physical_device, extensions),
+        feature_requests)
+}
+
 fn is_physical_device_suitable(
     physical_device: vk::PhysicalDevice,
     instance: &InstanceWrapper,
     surface: &Option<SurfaceWrapper>,
-    required_extensions: &[&CStr] ) -> bool {
+    required_extensions: &[&CStr],
+    feature_requests: &DeviceFeatureRequests) -> bool {
 
     let queue_families = get_queue_family_indices(instance, physical_device, surface);
     let extensions_supported = are_extensions_supported(
@@ -286,27 +319,7 @@ fn is_physical_device_suitable(
         physical_device,
         required_extensions);
 
-    let mut required_features = get_required_physical_device_features();
-
-    let mut physical_device_features = vk::PhysicalDeviceFeatures2::builder();
-    for mut required_feature in &mut required_features {
-        physical_device_features = required_feature.add_feature(physical_device_features);
-    }
-
-    let mut resolved_physical_device_features = physical_device_features.build();
-
-    unsafe {
-        instance.get().get_physical_device_features2(
-            physical_device,
-            &mut resolved_physical_device_features);
-    }
-
-    let mut required_features_supported = true;
-    for mut required_feature in required_features {
-        if !required_feature.check_feature(&resolved_physical_device_features) {
-            required_features_supported = false;
-        }
-    }
+    let required_features_supported = negotiate_features(instance, physical_device, feature_requests).is_satisfied();
 
     match surface {
         Some(_) => {
@@ -321,51 +334,57 @@ fn is_physical_device_suitable(
     }
 }
 
-fn pick_physical_device(
+/// Every physical device in enumeration order, along with its AdapterInfo
+fn get_adapters(
     instance: &InstanceWrapper,
     surface: &Option<SurfaceWrapper>,
-    required_extensions: &[&CStr]) -> Result<PhysicalDeviceWrapper, &'static str> {
+    required_extensions: &[&CStr],
+    feature_requests: &DeviceFeatureRequests) -> Vec<(vk::PhysicalDevice, AdapterInfo)> {
 
-    let mut devices = unsafe {
+    let devices = unsafe {
         instance.get()
             .enumerate_physical_devices()
             .expect("Error enumerating physical devides")
     };
 
-    // sort physical devices such that discrete GPUs are preferred
-    unsafe {
-        devices.sort_by(|a, b| {
-            let a_properties = instance.get().get_physical_device_properties(a.clone());
-            let b_properties = instance.get().get_physical_device_properties(b.clone());
-
-            let get_device_ranking = |device_type: vk::PhysicalDeviceType| -> u32 {
-                match device_type {
-                    vk::PhysicalDeviceType::DISCRETE_GPU => 0,
-                    vk::PhysicalDeviceType::INTEGRATED_GPU => 1,
-                    vk::PhysicalDeviceType::VIRTUAL_GPU => 2,
-                    _ => 3
-                }
-            };
-
-            let a_rank = get_device_ranking(a_properties.device_type);
-            let b_rank = get_device_ranking(b_properties.device_type);
-            a_rank.cmp(&b_rank)
-        });
-    }
-
-    let result = devices.iter().find(|device| {
-        let is_suitable = is_physical_device_suitable(
-            **device,
+    devices.iter().enumerate().map(|(index, device)| {
+        let suitable = is_physical_device_suitable(
+            *device,
             instance,
             surface,
-            required_extensions
+            required_extensions,
+            feature_requests
         );
 
-        is_suitable
-    });
+        (*device, query_adapter_info(instance.get(), index, *device, suitable))
+    }).collect()
+}
+
+fn pick_physical_device(
+    instance: &InstanceWrapper,
+    surface: &Option<SurfaceWrapper>,
+    required_extensions: &[&CStr],
+    feature_requests: &DeviceFeatureRequests,
+    selection: &AdapterSelection) -> Result<PhysicalDeviceWrapper, &'static str> {
+
+    let adapters = get_adapters(instance, surface, required_extensions, feature_requests);
+    let adapter_infos: Vec<AdapterInfo> = adapters.iter().map(|(_, info)| info.clone()).collect();
+
+    if let Some(selected) = selection.find(&adapter_infos) {
+        log::trace!(target: "context", "Using selected adapter {}", selected.name);
+        return Ok(PhysicalDeviceWrapper::new(adapters[selected.index].0));
+    }
+    if *selection != AdapterSelection::Auto {
+        log::warn!(target: "context", "Selected adapter {:?} isn't available, falling back to automatic selection", selection);
+    }
+
+    // prefer discrete GPUs; min_by_key keeps enumeration order between devices of the same type
+    let result = adapters.iter()
+        .filter(|(_, info)| info.suitable)
+        .min_by_key(|(_, info)| get_device_ranking(info.device_type));
 
     match result {
-        Some(physical_device) => Ok(PhysicalDeviceWrapper::new(*physical_device)),
+        Some((physical_device, _)) => Ok(PhysicalDeviceWrapper::new(*physical_device)),
         None => Err("No suitable device found.")
     }
 
@@ -378,7 +397,8 @@ fn create_logical_device(
     physical_device: &PhysicalDeviceWrapper,
     surface: &Option<SurfaceWrapper>,
     layers: &[&CStr],
-    extensions: &[&CStr]
+    extensions: &[&CStr],
+    feature_requests: &DeviceFeatureRequests
 ) -> DeviceWrapper {
     let queue_family_indices = get_queue_family_indices(
         instance,
@@ -395,6 +415,9 @@ fn create_logical_device(
     if queue_family_indices.present.is_some() {
         unique_family_indices.insert(queue_family_indices.present.unwrap());
     }
+    if let Some(transfer) = queue_family_indices.transfer {
+        unique_family_indices.insert(transfer);
+    }
 
     let priorities = [1.0_f32];
     let mut queue_create_infos = vec![];
@@ -406,15 +429,31 @@ fn create_logical_device(
         queue_create_infos.push(queue_create_info);
     }
 
-    let mut core_physical_device_features = vk::PhysicalDeviceFeatures::builder().build();
-    let mut physical_device_features = vk::PhysicalDeviceFeatures2::builder();
-    // TODO: make this an argument rather than a function call here
-    let mut required_features = get_required_physical_device_features();
-    for mut required_feature in &mut required_features {
-        physical_device_features = required_feature.add_feature(physical_device_features);
+    let mut negotiation = negotiate_features(instance, physical_device.get(), feature_requests);
+    assert!(negotiation.is_satisfied(), "Required device features are unsupported: {:?}", negotiation.missing_required);
+    let enabled_features = negotiation.get_enabled();
+    log::trace!(target: "context", "Enabled device features: {:?}", enabled_features);
+
+    let mut extensions = extensions.to_vec();
+    for extension in negotiation.get_extensions() {
+        if !extensions.contains(&extension) {
+            extensions.push(extension);
+        }
     }
 
-    let mut resolved_physical_device_features = physical_device_features.build();
+    // pipelines validate their rasterization and blend state against DeviceFeatures, so these
+    // are always enabled when supported
+    let core_features = {
+        let supported = unsafe { instance.get().get_physical_device_features(physical_device.get()) };
+        vk::PhysicalDeviceFeatures::builder()
+            .fill_mode_non_solid(supported.fill_mode_non_solid > 0)
+            .depth_clamp(supported.depth_clamp > 0)
+            .depth_bias_clamp(supported.depth_bias_clamp > 0)
+            .independent_blend(supported.independent_blend > 0)
+            .build()
+    };
+    let mut resolved_physical_device_features = negotiation.add_features(vk::PhysicalDeviceFeatures2::builder()
+        .features(core_features)).build();
 
     // convert layer names to const char*
     let p_layers: Vec<*const c_char> = layers.iter().map(|c_layer| {
@@ -444,40 +483,51 @@ fn create_logical_device(
         &physical_device,
         physical_device_properties,
         debug,
-        queue_family_indices)
+        queue_family_indices,
+        enabled_features)
 }
 
 fn create_command_pool(
     device: &DeviceWrapper,
-    queue_family_index: u32
+    queue_family_index: u32,
+    flags: vk::CommandPoolCreateFlags,
+    name: &str
 ) -> vk::CommandPool {
     let create_info = vk::CommandPoolCreateInfo {
         s_type: vk::StructureType::COMMAND_POOL_CREATE_INFO,
         p_next: std::ptr::null(),
-        flags: vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER,
+        flags,
         queue_family_index
     };
 
-    unsafe {
+    let command_pool = unsafe {
         device.get().create_command_pool(&create_info, None)
             .expect("Failed to create graphics command pool.")
-    }
+    };
+    device.set_debug_name(vk::ObjectType::COMMAND_POOL, command_pool.as_raw(), name);
+    command_pool
 }
 
+/// Command buffers are named `name` followed by their index
 fn create_command_buffers(
     device: &DeviceWrapper,
     command_pool: vk::CommandPool,
-    num_command_buffers: u32) -> Vec<vk::CommandBuffer> {
+    num_command_buffers: u32,
+    name: &str) -> Vec<vk::CommandBuffer> {
     let command_buffer_allocate_info = vk::CommandBufferAllocateInfo::builder()
         .command_buffer_count(num_command_buffers)
         .command_pool(command_pool)
         .level(vk::CommandBufferLevel::PRIMARY)
         .build();
 
-    unsafe {
+    let command_buffers = unsafe {
         device.get().allocate_command_buffers(&command_buffer_allocate_info)
             .expect("Failed to allocate Command Buffers")
+    };
+    for (index, command_buffer) in command_buffers.iter().enumerate() {
+        device.set_debug_name(vk::ObjectType::COMMAND_BUFFER, command_buffer.as_raw(), &format!("{}_{}", name, index));
     }
+    command_buffers
 }
 
 fn create_debug_util(
@@ -510,19 +560,40 @@ fn create_swapchain(
     physical_device: &PhysicalDeviceWrapper,
     surface: &SurfaceWrapper,
     window: &winit::window::Window,
-    old_swapchain: &Option<OldSwapchain>
+    old_swapchain: &Option<OldSwapchain>,
+    settings: &RenderSettings,
+    requested_present_mode: Option<vk::PresentModeKHR>,
+    frames_in_flight: u32,
+    sync_object_pool: &mut SyncObjectPool
 ) -> SwapchainWrapper {
     let swapchain_capabilities = surface.get_surface_capabilities(physical_device);
 
-    // TODO: may want to make format and color space customizable
     let swapchain_format = {
         let mut chosen_format: Option<vk::SurfaceFormatKHR> = None;
-        for format in &swapchain_capabilities.formats {
-            if format.format == vk::Format::R8G8B8A8_SRGB &&
-                format.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR {
-                // break format.clone();
-                chosen_format = Some(format.clone());
-                break;
+        // HDR color spaces are only reported if VK_EXT_swapchain_colorspace is enabled
+        if settings.hdr {
+            let hdr_formats = [
+                (vk::Format::A2B10G10R10_UNORM_PACK32, vk::ColorSpaceKHR::HDR10_ST2084_EXT),
+                (vk::Format::R16G16B16A16_SFLOAT, vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT)
+            ];
+            chosen_format = hdr_formats.iter().find_map(|(hdr_format, hdr_color_space)| {
+                swapchain_capabilities.formats.iter().find(|format| {
+                    format.format == *hdr_format && format.color_space == *hdr_color_space
+                }).cloned()
+            });
+            if chosen_format.is_none() {
+                log::warn!(target: "settings", "HDR requested but the surface has no HDR formats");
+            }
+        }
+
+        if chosen_format.is_none() {
+            for format in &swapchain_capabilities.formats {
+                if format.format == vk::Format::R8G8B8A8_SRGB &&
+                    format.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR {
+                    // break format.clone();
+                    chosen_format = Some(format.clone());
+                    break;
+                }
             }
         }
 
@@ -535,19 +606,19 @@ fn create_swapchain(
     };
 
     let swapchain_present_mode = {
-        let mut chosen_mode: Option<PresentModeKHR> = None;
-        for present_mode in swapchain_capabilities.present_modes {
-            if present_mode == vk::PresentModeKHR::IMMEDIATE {
-                chosen_mode = Some(present_mode);
-                break;
-            }
+        // an explicitly requested mode overrides the vsync setting, if the surface supports it
+        let mut chosen_mode: Option<PresentModeKHR> = requested_present_mode
+            .filter(|mode| swapchain_capabilities.present_modes.contains(mode));
+        if chosen_mode.is_none() && !settings.vsync {
+            chosen_mode = swapchain_capabilities.present_modes.iter()
+                .find(|present_mode| **present_mode == vk::PresentModeKHR::IMMEDIATE)
+                .cloned();
         }
 
-        if chosen_mode.is_none() {
-            chosen_mode = Some(vk::PresentModeKHR::FIFO);
-        }
-
-        chosen_mode.unwrap()
+        // FIFO is the only mode every surface supports
+        let chosen_mode = chosen_mode.unwrap_or(vk::PresentModeKHR::FIFO);
+        log::info!(target: "swapchain", "Creating swapchain with present mode {:?}", chosen_mode);
+        chosen_mode
     };
 
     let swapchain_extent = {
@@ -568,11 +639,16 @@ fn create_swapchain(
         }
     };
 
+    // at least one image per frame in flight, so no frame waits on the presentation engine
+    // for an image the previous frames aren't using
     let image_count = {
-        if swapchain_capabilities.capabilities.min_image_count > MAX_FRAMES_IN_FLIGHT {
-            swapchain_capabilities.capabilities.min_image_count
+        let caps = &swapchain_capabilities.capabilities;
+        let image_count = caps.min_image_count.max(frames_in_flight);
+        // a max_image_count of 0 means there is no limit
+        if caps.max_image_count > 0 {
+            image_count.min(caps.max_image_count)
         } else {
-            MAX_FRAMES_IN_FLIGHT
+            image_count
         }
     };
 
@@ -635,19 +711,12 @@ fn create_swapchain(
             .collect()
     };
 
+    // without swapchain_maintenance1 there's no way to signal a fence on present;
+    // OldSwapchain falls back to waiting for every frame in flight's fence instead
     let mut present_fences: Vec<vk::Fence> = Vec::new();
-    unsafe {
-        let fence_create = vk::FenceCreateInfo::builder()
-            .flags(vk::FenceCreateFlags::SIGNALED)
-            .build();
-        for _ in 0..swapchain_images.len() {
-            present_fences.push(
-                device.borrow().get().create_fence(
-                    &fence_create,
-                    None
-                )
-                .expect("Failed to create Present fence")
-            );
+    if device.borrow().is_feature_enabled(NegotiatedFeature::SwapchainMaintenance1) {
+        for i in 0..swapchain_images.len() {
+            present_fences.push(sync_object_pool.acquire_fence(true, &format!("present_fence_{}", i)));
         }
     }
 
@@ -657,42 +726,146 @@ fn create_swapchain(
         swapchain,
         swapchain_images,
         swapchain_format.format,
+        swapchain_format.color_space,
         swapchain_extent,
+        swapchain_present_mode,
         present_fences)
 }
 
+/// Returns the present fences of a swapchain the presentation engine has finished with to the
+/// pool they were acquired from, then destroys the swapchain
+fn retire_swapchain(sync_object_pool: &mut SyncObjectPool, swapchain: SwapchainWrapper) {
+    for fence in swapchain.get_present_fences() {
+        sync_object_pool.release_fence(*fence);
+    }
+}
+
 #[derive(Debug)]
 pub struct OldSwapchain {
     pub swapchain: SwapchainWrapper,
-    pub frame_index: u32
+    pub frame_index: u32,
+    // frames started since the swapchain was retired
+    frames_started: u32
+}
+
+impl OldSwapchain {
+    fn new(swapchain: SwapchainWrapper, frame_index: u32) -> Self {
+        OldSwapchain {
+            swapchain,
+            frame_index,
+            frames_started: 0
+        }
+    }
 
+    /// Without present fences, the swapchain is assumed to be finished with once every frame
+    /// index has been started again since it was retired, since each frame's fence has then
+    /// signaled for the submissions whose presents used it
+    fn can_destroy(&self, frames_in_flight: u32) -> bool {
+        if self.swapchain.has_present_fences() {
+            self.swapchain.can_destroy()
+        } else {
+            self.frames_started >= frames_in_flight
+        }
+    }
 }
 
 pub struct VulkanFrameObjects {
     pub graphics_command_buffer: vk::CommandBuffer,
+    /// The pool of the frame's additional command buffers (see acquire_named_command_buffer),
+    /// reset as a whole when this frame index is started again. Command buffers allocated from
+    /// it directly are reset along with it, but are only freed with the context
+    pub command_pool: vk::CommandPool,
     pub swapchain_image: Option<NextImage>,
     pub swapchain_semaphore: vk::Semaphore,
-    pub descriptor_pool: vk::DescriptorPool,
     pub frame_index: u32
 }
 
+/// One command buffer of a batch submitted with VulkanRenderContext::submit_graphics_batch
+#[derive(Copy, Clone, Debug)]
+pub struct GraphicsSubmit {
+    pub command_buffer: vk::CommandBuffer,
+    /// Stages of this command buffer which wait for the previous command buffer in the batch
+    pub wait_stage_mask: vk::PipelineStageFlags,
+    /// Signaled once this command buffer completes, for the next command buffer in the batch.
+    /// Unused for the last command buffer (see acquire_frame_semaphore)
+    pub signal_semaphore: vk::Semaphore
+}
 
-// swapchain_index must be independent from frame_index since it will "reset"
-// whenever we recreate the swapchain
-// Necessary for avoiding errors when specifying image indices in VkPresentInfoKHR
+/// Command buffers and semaphores handed out for one frame index, reused once that frame
+/// index is started again
+struct FrameCommandLists {
+    // reset as a whole, rather than resetting each command buffer as it's handed out
+    command_pool: vk::CommandPool,
+    command_buffers: Vec<vk::CommandBuffer>,
+    semaphores: Vec<vk::Semaphore>,
+    used_command_buffers: usize,
+    used_semaphores: usize
+}
+
+impl FrameCommandLists {
+    fn new(command_pool: vk::CommandPool) -> Self {
+        FrameCommandLists {
+            command_pool,
+            command_buffers: Vec::new(),
+            semaphores: Vec::new(),
+            used_command_buffers: 0,
+            used_semaphores: 0
+        }
+    }
+}
+
+pub struct WindowFrameObjects {
+    pub swapchain_image: NextImage,
+    pub swapchain_semaphore: vk::Semaphore
+}
+
+/// Swapchain for an additional OS window presenting from the same device.
+/// Field order matters: the swapchains must be dropped before their surface.
+struct WindowSwapchain {
+    swapchain: Option<SwapchainWrapper>,
+    old_swapchain: Option<OldSwapchain>,
+    semaphores: Vec<vk::Semaphore>,
+    image_index: u32,
+    surface: SurfaceWrapper
+}
+
+
+// swapchain_index must be independent from frame_index: the swapchain may have more images
+// than there are frames in flight, and hands them out in any order
 pub struct VulkanRenderContext {
+    frames_in_flight: u32,
     frame_index: u32,
+    // the swapchain image most recently acquired, which is the next to be presented
     swapchain_index: u32,
     graphics_queue: vk::Queue,
     present_queue: vk::Queue,
     compute_queue: vk::Queue,
+    // only when the device has a dedicated transfer family
+    transfer_queue: Option<vk::Queue>,
     graphics_command_pool: vk::CommandPool,
     graphics_command_buffers: Vec<vk::CommandBuffer>,
+    // additional command buffers and semaphores for frames split into several command lists
+    frame_command_lists: Vec<FrameCommandLists>,
     immediate_command_buffer: vk::CommandBuffer,
-    descriptor_pools: Vec<vk::DescriptorPool>,
+    // exactly one of these, depending on whether the device uses descriptor buffers
+    descriptor_pool_manager: Option<DescriptorPoolManager>,
+    descriptor_buffer_manager: Option<DescriptorBufferManager>,
     swapchain: Option<SwapchainWrapper>,
     old_swapchain: Option<OldSwapchain>,
     swapchain_semaphores: Vec<vk::Semaphore>,
+    window_swapchains: HashMap<winit::window::WindowId, WindowSwapchain>,
+    // resources released while recording a frame, held until that frame index is started again
+    deferred_releases: Vec<Vec<Rc<RefCell<DeviceResource>>>>,
+    // device frame value submitted for each frame index, used to retire its deferred destructions
+    submitted_frame_values: Vec<Option<u64>>,
+    transient_image_pool: TransientImagePool,
+    // semaphores and fences of the context and its swapchains, see get_sync_object_pool
+    sync_object_pool: SyncObjectPool,
+    profiler: FramegraphProfiler,
+    settings: RenderSettings,
+    // see set_present_mode
+    present_mode: Option<vk::PresentModeKHR>,
+    present_mode_changed: bool,
     device: Rc<RefCell<DeviceWrapper>>,
     physical_device: PhysicalDeviceWrapper,
     surface: Option<SurfaceWrapper>,
@@ -710,16 +883,16 @@ impl Debug for VulkanRenderContext {
 impl Drop for VulkanRenderContext {
     fn drop(&mut self) {
         unsafe {
+            // swapchain and command list semaphores are destroyed by the sync object pool
             let device = self.device.borrow();
-            for semaphore in &self.swapchain_semaphores {
-                device.get().destroy_semaphore(*semaphore, None);
-            }
             device.get().free_command_buffers(self.graphics_command_pool, &[self.immediate_command_buffer]);
             device.get().free_command_buffers(self.graphics_command_pool, &self.graphics_command_buffers);
-            device.get().destroy_command_pool(self.graphics_command_pool, None);
-            for pool in &self.descriptor_pools {
-                device.get().destroy_descriptor_pool(*pool, None);
+            // which frees the command buffers allocated from them
+            for frame_command_lists in &self.frame_command_lists {
+                device.get().destroy_command_pool(frame_command_lists.command_pool, None);
             }
+            device.get().destroy_command_pool(self.graphics_command_pool, None);
+            self.profiler.destroy();
         }
     }
 }
@@ -738,9 +911,75 @@ impl VulkanRenderContext {
         debug_enabled: bool,
         window: Option<&winit::window::Window>
     ) -> VulkanRenderContext {
-        let layers = [
-            unsafe { ::std::ffi::CStr::from_bytes_with_nul_unchecked(b"VK_LAYER_KHRONOS_validation\0") }
-        ];
+        Self::init(
+            application_info,
+            debug_enabled,
+            window,
+            DescriptorPoolConfig::default(),
+            RenderSettings::default(),
+            DeviceFeatureRequests::default(),
+            DEFAULT_FRAMES_IN_FLIGHT)
+    }
+
+    /// Lists the physical devices a context could be created on, e.g. to let the user choose
+    /// one for RenderSettings::adapter. Creates a temporary instance; when `window` is given,
+    /// adapters which can't present to it are reported as unsuitable, as are adapters missing
+    /// any feature required by `feature_requests`
+    pub fn enumerate_adapters(
+        application_info: &vk::ApplicationInfo,
+        window: Option<&winit::window::Window>,
+        feature_requests: &DeviceFeatureRequests
+    ) -> Vec<AdapterInfo> {
+        let entry = ash::Entry::linked();
+        let mut instance_extensions = get_instance_extensions();
+        let mut device_extensions = get_physical_device_extensions();
+        let mut presentation_instance_extensions = Vec::new();
+        if let Some(resolved_window) = window {
+            for extension in surface::get_required_surface_extensions(resolved_window) {
+                unsafe {
+                    instance_extensions.push(CStr::from_ptr(*extension));
+                }
+            }
+            presentation_instance_extensions = get_presentation_instance_extensions(&entry);
+            instance_extensions.extend_from_slice(&presentation_instance_extensions);
+            device_extensions.append(&mut get_presentation_device_extensions());
+        }
+        let feature_requests = get_presentation_feature_requests(feature_requests.clone(), &presentation_instance_extensions);
+
+        let instance_wrapper = InstanceWrapper::new(create_vulkan_instance(
+            &entry,
+            application_info,
+            &[],
+            &instance_extensions));
+        // declared after the instance so it's destroyed first
+        let surface_wrapper = window.map(|win| SurfaceWrapper::new(&entry, instance_wrapper.get(), win));
+
+        get_adapters(&instance_wrapper, &surface_wrapper, &device_extensions, &feature_requests)
+            .into_iter()
+            .map(|(_, info)| info)
+            .collect()
+    }
+
+    /// `descriptor_pool_config` sizes the descriptor pools created for each frame in flight,
+    /// unless NegotiatedFeature::DescriptorBuffer is enabled and descriptor buffers replace them.
+    /// `feature_requests` lists the negotiated features to enable; which ones were enabled can
+    /// be checked with DeviceWrapper::is_feature_enabled
+    pub fn init(
+        application_info: &vk::ApplicationInfo,
+        debug_enabled: bool,
+        window: Option<&winit::window::Window>,
+        descriptor_pool_config: DescriptorPoolConfig,
+        settings: RenderSettings,
+        feature_requests: DeviceFeatureRequests,
+        frames_in_flight: u32
+    ) -> VulkanRenderContext {
+        assert!((1..=MAX_FRAMES_IN_FLIGHT).contains(&frames_in_flight),
+            "Frames in flight must be between 1 and {}, got {}", MAX_FRAMES_IN_FLIGHT, frames_in_flight);
+
+        let mut layers: Vec<&CStr> = Vec::new();
+        if settings.validation {
+            layers.push(unsafe { ::std::ffi::CStr::from_bytes_with_nul_unchecked(b"VK_LAYER_KHRONOS_validation\0") });
+        }
 
         let mut instance_extensions = vec![
             ash::extensions::ext::DebugUtils::name()
@@ -757,10 +996,20 @@ impl VulkanRenderContext {
 
         instance_extensions.append(&mut get_instance_extensions());
 
+        let entry = ash::Entry::linked();
         let mut physical_device_extensions = get_physical_device_extensions();
         let mut logical_device_extensions = get_logical_device_extensions();
-
-        let entry = ash::Entry::linked();
+        let mut presentation_instance_extensions = Vec::new();
+        if window.is_some() {
+            presentation_instance_extensions = get_presentation_instance_extensions(&entry);
+            instance_extensions.extend_from_slice(&presentation_instance_extensions);
+            physical_device_extensions.append(&mut get_presentation_device_extensions());
+        }
+        let feature_requests = get_presentation_feature_requests(feature_requests, &presentation_instance_extensions);
+        // enabled whenever available so HDR can be switched on without recreating the instance
+        if window.is_some() && is_instance_extension_supported(&entry, vk::ExtSwapchainColorspaceFn::name()) {
+            instance_extensions.push(vk::ExtSwapchainColorspaceFn::name());
+        }
         let instance = create_vulkan_instance(
             &entry,
             application_info,
@@ -799,7 +1048,9 @@ impl VulkanRenderContext {
         let physical_device = pick_physical_device(
             &instance_wrapper,
             &surface_wrapper,
-            &physical_device_extensions).expect("Failed to select a suitable physical device.");
+            &physical_device_extensions,
+            &feature_requests,
+            &settings.adapter).expect("Failed to select a suitable physical device.");
 
         let device_properties = unsafe {
             instance_wrapper.get().get_physical_device_properties(
@@ -808,6 +1059,29 @@ impl VulkanRenderContext {
         };
 
 
+        // calibrated timestamps let GPU profiling align with the CPU timeline without a queue submission
+        let calibrated_timestamps = {
+            let supported = are_extensions_supported(
+                &instance_wrapper,
+                physical_device.get(),
+                &[vk::ExtCalibratedTimestampsFn::name()]);
+            if supported {
+                let calibrated_timestamps = ash::extensions::ext::CalibratedTimestamps::new(&entry, instance_wrapper.get());
+                let time_domains = unsafe {
+                    calibrated_timestamps.get_physical_device_calibrateable_time_domains(physical_device.get())
+                        .expect("Failed to get calibrateable time domains")
+                };
+                if time_domains.contains(&vk::TimeDomainEXT::DEVICE) {
+                    logical_device_extensions.push(vk::ExtCalibratedTimestampsFn::name());
+                    Some(calibrated_timestamps)
+                } else {
+                    None
+                }
+            } else {
+                None
+            }
+        };
+
         logical_device_extensions.append(&mut physical_device_extensions);
 
         let logical_device = Rc::new(RefCell::new(create_logical_device(
@@ -817,9 +1091,12 @@ impl VulkanRenderContext {
             &physical_device,
             &surface_wrapper,
             &layers,
-            &logical_device_extensions
+            &logical_device_extensions,
+            &feature_requests
         )));
 
+        let mut sync_object_pool = SyncObjectPool::new(logical_device.clone());
+
         let swapchain = {
             if window.is_some() && surface_wrapper.is_some() {
                 Some(create_swapchain(
@@ -828,7 +1105,11 @@ impl VulkanRenderContext {
                     &physical_device,
                     &surface_wrapper.as_ref().unwrap(),
                     window.unwrap(),
-                    &None))
+                    &None,
+                    &settings,
+                    None,
+                    frames_in_flight,
+                    &mut sync_object_pool))
             } else {
                 None
             }
@@ -836,16 +1117,11 @@ impl VulkanRenderContext {
 
         let swapchain_semaphores = {
             let mut semaphores: Vec<vk::Semaphore> = Vec::new();
-            if let Some(swapchain) = &swapchain {
-                semaphores.reserve(swapchain.get_images().len());
-                for i in 0..swapchain.get_images().len() {
-                    let create_info = vk::SemaphoreCreateInfo::builder()
-                        .build();
-
-                    semaphores.push(unsafe {
-                        logical_device.borrow().get().create_semaphore(&create_info, None)
-                            .expect("Failed to create semaphore for swapchain image")
-                    });
+            // acquire semaphores are indexed by frame index
+            if swapchain.is_some() {
+                semaphores.reserve(frames_in_flight as usize);
+                for i in 0..frames_in_flight {
+                    semaphores.push(sync_object_pool.acquire_semaphore(&format!("swapchain_semaphore_{}", i)));
                 }
             }
 
@@ -857,9 +1133,11 @@ impl VulkanRenderContext {
                 logical_device.borrow().get_queue_family_indices().graphics.unwrap(),
                 0)
         };
+        // headless contexts have no present family; fall back to the graphics queue
         let present_queue = unsafe {
+            let queue_families = *logical_device.borrow().get_queue_family_indices();
             logical_device.borrow().get().get_device_queue(
-                logical_device.borrow().get_queue_family_indices().present.unwrap(),
+                queue_families.present.unwrap_or(queue_families.graphics.unwrap()),
                 0)
         };
         let compute_queue = unsafe {
@@ -868,77 +1146,103 @@ impl VulkanRenderContext {
                 0)
         };
 
+        let transfer_queue = logical_device.borrow().get_queue_family_indices().transfer.map(|transfer| unsafe {
+            logical_device.borrow().get().get_device_queue(transfer, 0)
+        });
+        if transfer_queue.is_some() {
+            log::trace!(target: "context", "Using a dedicated transfer queue");
+        }
+
+        let graphics_family = logical_device.borrow().get_queue_family_indices().graphics.unwrap();
         let graphics_command_pool = create_command_pool(
             &logical_device.borrow(),
-            logical_device.borrow().get_queue_family_indices().graphics.unwrap());
-
-        let max_frames_in_flight = {
-            if let Some(swapchain) = &swapchain {
-               swapchain.get_images().len() as u32
-            } else {
-                MAX_FRAMES_IN_FLIGHT
-            }
+            graphics_family,
+            vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER,
+            &format!("command_pool_family{}", graphics_family));
+        let frame_command_lists = (0..frames_in_flight).map(|frame_index| {
+            FrameCommandLists::new(create_command_pool(
+                &logical_device.borrow(),
+                graphics_family,
+                vk::CommandPoolCreateFlags::TRANSIENT,
+                &format!("command_pool_frame{}", frame_index)))
+        }).collect();
+
+        let uses_descriptor_buffers = logical_device.borrow().uses_descriptor_buffers();
+        let (descriptor_pool_manager, descriptor_buffer_manager) = match uses_descriptor_buffers {
+            true => (None, Some(DescriptorBufferManager::new(
+                instance_wrapper.get(),
+                physical_device.get(),
+                logical_device.clone(),
+                frames_in_flight))),
+            false => (Some(DescriptorPoolManager::new(
+                logical_device.clone(),
+                descriptor_pool_config,
+                frames_in_flight)), None)
         };
 
-
-        let ubo_pool_size = vk::DescriptorPoolSize {
-            ty: vk::DescriptorType::UNIFORM_BUFFER,
-            descriptor_count: 16
-        };
-        let image_pool_size = vk::DescriptorPoolSize {
-            ty: vk::DescriptorType::INPUT_ATTACHMENT,
-            descriptor_count: 16
-        };
-        let combined_sampler_pool_size = vk::DescriptorPoolSize::builder()
-            .ty(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
-            .descriptor_count(16)
-            .build();
-        let descriptor_pool_sizes = [ubo_pool_size, image_pool_size, combined_sampler_pool_size];
-        let descriptor_pool_create = vk::DescriptorPoolCreateInfo::builder()
-            .flags(vk::DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET)
-            .max_sets(8)
-            .pool_sizes(&descriptor_pool_sizes);
-
-        let mut descriptor_pools: Vec<vk::DescriptorPool> = Vec::new();
-        for i in (0..max_frames_in_flight) {
-            let descriptor_pool = unsafe {
-                logical_device.borrow().get().create_descriptor_pool(
-                    &descriptor_pool_create,
-                    None)
-                    .expect("Failed to create descriptor pool")
-            };
-            descriptor_pools.push(descriptor_pool);
-        }
-
         let immediate_command_buffer = create_command_buffers(
             &logical_device.borrow(),
             graphics_command_pool,
-            1);
+            1,
+            "immediate_command_buffer");
 
         let graphics_command_buffers = create_command_buffers(
             &logical_device.borrow(),
             graphics_command_pool,
-            max_frames_in_flight);
+            frames_in_flight,
+            "graphics_command_buffer");
 
         let frame_index = 0;
 
-        {
+        let profiler = {
             let borrowed_device = logical_device.borrow();
-            let num_frames = match &swapchain {
-                None => { MAX_FRAMES_IN_FLIGHT }
-                Some(swapchain) => {
-                    swapchain.get_images().len() as u32
-                }
-            };
 
-            init_gpu_profiling!(
+            let mut gpu_profiler = init_gpu_profiling!(
                 borrowed_device.get(),
                 device_properties.limits.timestamp_period,
+                calibrated_timestamps.as_ref(),
                 &immediate_command_buffer[0],
                 &graphics_queue,
-                num_frames);
-        }
+                frames_in_flight);
+
+            // queues of other families get their own timelines; a compute queue sharing the
+            // graphics family is the graphics queue
+            let queue_families = *borrowed_device.get_queue_family_indices();
+            let timestamps = borrowed_device.features().timestamps;
+            let compute_family = queue_families.compute.unwrap();
+            let mut profiled_queues = Vec::new();
+            if compute_family != graphics_family && timestamps.compute_supported() {
+                profiled_queues.push((GpuQueue::Compute, compute_family, compute_queue));
+            }
+            if let (Some(transfer_family), Some(transfer_queue)) = (queue_families.transfer, transfer_queue) {
+                if timestamps.transfer_supported() {
+                    profiled_queues.push((GpuQueue::Transfer, transfer_family, transfer_queue));
+                }
+            }
+            for (gpu_queue, family, queue) in profiled_queues {
+                // only used to sample the queue's first timestamp
+                let command_pool = create_command_pool(
+                    &borrowed_device,
+                    family,
+                    vk::CommandPoolCreateFlags::TRANSIENT,
+                    &format!("profiler_command_pool_family{}", family));
+                let command_buffer = create_command_buffers(&borrowed_device, command_pool, 1, "profiler_command_buffer")[0];
+                gpu_profiler.add_queue(
+                    gpu_queue,
+                    borrowed_device.get(),
+                    device_properties.limits.timestamp_period,
+                    calibrated_timestamps.as_ref(),
+                    &command_buffer,
+                    &queue,
+                    frames_in_flight);
+                unsafe {
+                    borrowed_device.get().destroy_command_pool(command_pool, None);
+                }
+            }
+            FramegraphProfiler::new(gpu_profiler, borrowed_device.get().clone())
+        };
 
+        let transient_image_pool = TransientImagePool::new(logical_device.clone(), TRANSIENT_IMAGE_BUDGET);
 
         VulkanRenderContext {
             entry,
@@ -948,14 +1252,27 @@ impl VulkanRenderContext {
             graphics_queue,
             present_queue,
             compute_queue,
+            transfer_queue,
             graphics_command_pool,
             surface: surface_wrapper,
             swapchain,
             old_swapchain: None,
             swapchain_semaphores,
-            descriptor_pools,
+            window_swapchains: HashMap::new(),
+            deferred_releases: (0..frames_in_flight).map(|_| Vec::new()).collect(),
+            submitted_frame_values: vec![None; frames_in_flight as usize],
+            transient_image_pool,
+            sync_object_pool,
+            profiler,
+            settings,
+            present_mode: None,
+            present_mode_changed: false,
+            descriptor_pool_manager,
+            descriptor_buffer_manager,
             graphics_command_buffers,
+            frame_command_lists,
             immediate_command_buffer: immediate_command_buffer[0],
+            frames_in_flight,
             frame_index,
             swapchain_index: 0,
         }
@@ -980,12 +1297,58 @@ impl VulkanRenderContext {
         self.present_queue
     }
 
+    /// The family of the dedicated transfer queue, if the device has one
+    pub fn get_transfer_queue_index(&self) -> Option<u32> {
+        self.device.borrow().get_queue_family_indices().transfer
+    }
+
+    /// A queue which only does transfers, for uploads which overlap rendering. Resources it
+    /// writes need their ownership transferred to the graphics family before they're used
+    pub fn get_transfer_queue(&self) -> Option<vk::Queue> {
+        self.transfer_queue
+    }
+
     pub fn get_graphics_command_pool(&self) -> vk::CommandPool { self.graphics_command_pool }
 
     fn get_graphics_command_buffer(&self, index: usize) -> vk::CommandBuffer { self.graphics_command_buffers[index] }
 
     pub fn get_immediate_command_buffer(&self) -> vk::CommandBuffer { self.immediate_command_buffer }
 
+    /// Creates a semaphore whose payload can be shared with other APIs or processes.
+    /// The caller is responsible for destroying it
+    #[cfg(feature = "external-memory")]
+    pub fn create_exportable_semaphore(&self) -> vk::Semaphore {
+        let mut export_info = vk::ExportSemaphoreCreateInfo::builder()
+            .handle_types(api_types::external_memory::get_semaphore_handle_type());
+        let create_info = vk::SemaphoreCreateInfo::builder()
+            .push_next(&mut export_info);
+        let semaphore = unsafe {
+            self.device.borrow().get().create_semaphore(&create_info, None)
+                .expect("Failed to create exportable semaphore")
+        };
+        self.device.borrow().set_debug_name(vk::ObjectType::SEMAPHORE, semaphore.as_raw(), "exportable_semaphore");
+        semaphore
+    }
+
+    #[cfg(feature = "external-memory")]
+    pub fn get_semaphore_handle(&self, semaphore: vk::Semaphore) -> api_types::external_memory::ExternalHandle {
+        self.device.borrow().get_external_memory().get_semaphore_handle(semaphore)
+    }
+
+    #[cfg(feature = "external-memory")]
+    pub fn import_semaphore(&self, semaphore: vk::Semaphore, handle: api_types::external_memory::ExternalHandle) {
+        self.device.borrow().get_external_memory().import_semaphore_handle(semaphore, handle);
+    }
+
+    /// The last known state of a resource across frames and manual submissions
+    pub fn get_resource_state(&self, handle: u64) -> Option<ResourceState> {
+        self.device.borrow().get_resource_state(handle)
+    }
+
+    pub fn update_resource_state(&self, handle: u64, state: ResourceState) {
+        self.device.borrow_mut().update_resource_state(handle, state);
+    }
+
     pub fn get_swapchain(&self) -> &Option<SwapchainWrapper> { &self.swapchain }
 
     pub fn recreate_swapchain(
@@ -996,20 +1359,24 @@ impl VulkanRenderContext {
             Some(surface) => {
                 // Only rebuild the swapchain if we aren't already doing so
                 if let None = &self.old_swapchain {
-                    self.old_swapchain = Some(OldSwapchain {
-                        swapchain: self.swapchain.take().unwrap(),
-                        frame_index: self.frame_index
-                    });
+                    self.old_swapchain = Some(OldSwapchain::new(
+                        self.swapchain.take().unwrap(),
+                        self.frame_index));
                     let new_swapchain = create_swapchain(
                         &self.instance,
                         self.device.clone(),
                         &self.physical_device,
                         surface,
                         window,
-                        &self.old_swapchain);
+                        &self.old_swapchain,
+                        &self.settings,
+                        self.present_mode,
+                        self.frames_in_flight,
+                        &mut self.sync_object_pool);
 
                     self.swapchain = Some(new_swapchain);
                     self.swapchain_index = 0;
+                    self.present_mode_changed = false;
                 }
             }
             None => {
@@ -1018,6 +1385,228 @@ impl VulkanRenderContext {
         }
     }
 
+    pub fn get_settings(&self) -> &RenderSettings { &self.settings }
+
+    /// Whether the main window's surface can present with `present_mode`
+    pub fn is_present_mode_supported(&self, present_mode: vk::PresentModeKHR) -> bool {
+        self.surface.as_ref().map_or(false, |surface| {
+            surface.get_surface_capabilities(&self.physical_device).present_modes.contains(&present_mode)
+        })
+    }
+
+    /// Requests that the main swapchain be rebuilt with `present_mode`, overriding the vsync
+    /// setting. The next [`flip`](Self::flip) reports the swapchain as suboptimal so the caller
+    /// recreates it. Returns false, leaving the present mode unchanged, if the surface doesn't
+    /// support the mode
+    pub fn set_present_mode(&mut self, present_mode: vk::PresentModeKHR) -> bool {
+        if !self.is_present_mode_supported(present_mode) {
+            log::warn!(target: "swapchain", "Present mode {:?} isn't supported by the surface", present_mode);
+            return false;
+        }
+
+        let current_mode = self.swapchain.as_ref().map(|swapchain| swapchain.get_present_mode());
+        self.present_mode = Some(present_mode);
+        self.present_mode_changed = current_mode != Some(present_mode);
+        true
+    }
+
+    /// The main swapchain's present mode, if there is a swapchain
+    pub fn get_present_mode(&self) -> Option<vk::PresentModeKHR> {
+        self.swapchain.as_ref().map(|swapchain| swapchain.get_present_mode())
+    }
+
+    /// The first of `candidates` supporting `features` with optimal tiling. Lets callers fall
+    /// back to formats which are available on every platform, e.g. MoltenVK lacks
+    /// D24_UNORM_S8_UINT on Apple silicon. Passes without the render context can use the
+    /// device's FormatSelector directly
+    pub fn find_supported_format(
+        &self,
+        candidates: &[vk::Format],
+        features: vk::FormatFeatureFlags) -> Option<vk::Format> {
+
+        self.device.borrow().formats().select(candidates, features)
+    }
+
+    /// The configured MSAA sample count, rounded down to one the device supports
+    pub fn get_sample_count(&self) -> vk::SampleCountFlags {
+        self.device.borrow().limits().clamp_sample_count(self.settings.get_sample_count())
+    }
+
+    /// The swapchain extent scaled by the current resolution scale, for offscreen render targets
+    pub fn get_render_extent(&self) -> Option<vk::Extent2D> {
+        self.swapchain.as_ref().map(|swapchain| self.settings.scale_extent(swapchain.get_extent()))
+    }
+
+    /// Overrides the resolution scale setting, e.g. with a DynamicResolution's scale, until
+    /// settings are next applied
+    pub fn set_resolution_scale(&mut self, resolution_scale: f32) {
+        self.settings.resolution_scale = resolution_scale;
+    }
+
+    /// Applies new settings, recreating the main swapchain if its present mode or format
+    /// depends on a changed setting. Other windows pick the settings up the next time their
+    /// swapchains are recreated. The returned changes tell the caller what else must be
+    /// rebuilt, e.g. pipelines when the sample count changes
+    pub fn apply_settings(
+        &mut self,
+        settings: RenderSettings,
+        window: Option<&winit::window::Window>
+    ) -> RenderSettingsChanges {
+        let changes = RenderSettingsChanges::between(&self.settings, &settings);
+        if changes.requires_restart {
+            log::warn!(target: "settings", "Validation can only be toggled when the render context is created");
+        }
+        if self.settings.vsync != settings.vsync {
+            // the new vsync setting takes precedence over an earlier set_present_mode
+            self.present_mode = None;
+        }
+        self.settings = settings;
+
+        if changes.swapchain {
+            if let Some(window) = window {
+                self.recreate_swapchain(window);
+            }
+        }
+
+        changes
+    }
+
+    /// Creates a surface and swapchain for an additional window. The window is
+    /// identified by its [`WindowId`](winit::window::WindowId) in all further calls.
+    pub fn add_window(
+        &mut self,
+        window: &winit::window::Window
+    ) {
+        assert!(self.surface.is_some(), "Additional windows require a context created with a window");
+        assert!(!self.window_swapchains.contains_key(&window.id()),
+            "Window already has a swapchain");
+
+        let surface = SurfaceWrapper::new(
+            &self.entry,
+            self.instance.get(),
+            window);
+
+        let present_index = self.device.borrow().get_queue_family_indices().present
+            .expect("Adding a window requires a present queue");
+        let is_present_supported = unsafe {
+            surface.get_loader().get_physical_device_surface_support(
+                self.physical_device.get(),
+                present_index,
+                surface.get_surface())
+                .expect("Failed to query surface present support")
+        };
+        assert!(is_present_supported, "Present queue cannot present to the new window's surface");
+
+        let swapchain = create_swapchain(
+            &self.instance,
+            self.device.clone(),
+            &self.physical_device,
+            &surface,
+            window,
+            &None,
+            &self.settings,
+            self.present_mode,
+            self.frames_in_flight,
+            &mut self.sync_object_pool);
+
+        let semaphores = (0..self.graphics_command_buffers.len())
+            .map(|i| self.sync_object_pool.acquire_semaphore(&format!("window_swapchain_semaphore_{}", i)))
+            .collect();
+
+        self.window_swapchains.insert(window.id(), WindowSwapchain {
+            swapchain: Some(swapchain),
+            old_swapchain: None,
+            semaphores,
+            image_index: 0,
+            surface
+        });
+    }
+
+    /// Destroys the swapchain and surface of an additional window.
+    /// Waits for the device to go idle since the swapchain images may still be in use.
+    pub fn remove_window(
+        &mut self,
+        window_id: winit::window::WindowId
+    ) {
+        let mut window_swapchain = self.window_swapchains.remove(&window_id)
+            .expect("Attempting to remove a window without a swapchain");
+
+        self.wait_idle();
+        for semaphore in &window_swapchain.semaphores {
+            self.sync_object_pool.release_semaphore(*semaphore);
+        }
+        let swapchains = window_swapchain.swapchain.take().into_iter()
+            .chain(window_swapchain.old_swapchain.take().map(|old_swapchain| old_swapchain.swapchain));
+        for swapchain in swapchains {
+            retire_swapchain(&mut self.sync_object_pool, swapchain);
+        }
+    }
+
+    pub fn get_window_swapchain(
+        &self,
+        window_id: winit::window::WindowId
+    ) -> Option<&SwapchainWrapper> {
+        self.window_swapchains.get(&window_id)
+            .and_then(|window_swapchain| window_swapchain.swapchain.as_ref())
+    }
+
+    pub fn recreate_window_swapchain(
+        &mut self,
+        window: &winit::window::Window
+    ) {
+        let window_swapchain = self.window_swapchains.get_mut(&window.id())
+            .expect("Attempting to recreate swapchain for an unknown window");
+
+        // Only rebuild the swapchain if we aren't already doing so
+        if let None = &window_swapchain.old_swapchain {
+            window_swapchain.old_swapchain = Some(OldSwapchain::new(
+                window_swapchain.swapchain.take().unwrap(),
+                self.frame_index));
+            let new_swapchain = create_swapchain(
+                &self.instance,
+                self.device.clone(),
+                &self.physical_device,
+                &window_swapchain.surface,
+                window,
+                &window_swapchain.old_swapchain,
+                &self.settings,
+                self.present_mode,
+                self.frames_in_flight,
+                &mut self.sync_object_pool);
+
+            window_swapchain.swapchain = Some(new_swapchain);
+        }
+    }
+
+    /// Acquires the next image of an additional window's swapchain for the current frame.
+    /// The returned semaphore is signaled once the image is available.
+    #[tracing::instrument]
+    pub fn get_next_window_objects(
+        &mut self,
+        window_id: winit::window::WindowId
+    ) -> WindowFrameObjects {
+        let window_swapchain = self.window_swapchains.get_mut(&window_id)
+            .expect("Attempting to acquire an image for an unknown window");
+
+        let semaphore = window_swapchain.semaphores[self.frame_index as usize];
+        let image = window_swapchain.swapchain.as_mut()
+            .expect("Window has no swapchain")
+            .acquire_next_image(None, Some(semaphore), None);
+        window_swapchain.image_index = image.index;
+
+        if let Some(old_swapchain) = &window_swapchain.old_swapchain {
+            if old_swapchain.can_destroy(self.frames_in_flight) {
+                let old_swapchain = window_swapchain.old_swapchain.take().unwrap();
+                retire_swapchain(&mut self.sync_object_pool, old_swapchain.swapchain);
+            }
+        }
+
+        WindowFrameObjects {
+            swapchain_image: image,
+            swapchain_semaphore: semaphore
+        }
+    }
+
     fn get_next_swapchain_image(
         &mut self,
         timeout: Option<u64>,
@@ -1026,7 +1615,11 @@ impl VulkanRenderContext {
 
         match &mut self.swapchain {
             Some(swapchain) => {
-                Some(swapchain.acquire_next_image(timeout, semaphore, fence))
+                let next_image = swapchain.acquire_next_image(timeout, semaphore, fence);
+                if next_image.image.is_some() {
+                    self.swapchain_index = next_image.index;
+                }
+                Some(next_image)
             }
             None => {
                 None
@@ -1038,7 +1631,10 @@ impl VulkanRenderContext {
     pub fn get_next_frame_objects(&mut self) -> VulkanFrameObjects {
         let old_index = self.frame_index;
 
-        let semaphore = self.swapchain_semaphores[old_index as usize];
+        // headless contexts have no swapchain to acquire from
+        let semaphore = self.swapchain_semaphores.get(old_index as usize)
+            .cloned()
+            .unwrap_or(vk::Semaphore::null());
         let image = self.get_next_swapchain_image(
             None,
             Some(semaphore),
@@ -1048,52 +1644,79 @@ impl VulkanRenderContext {
         // we recreated the swapchain should indicate that the presentation engine
         // is no longer using the old swapchain
         if let Some(old_swapchain) = &self.old_swapchain {
-            if old_swapchain.swapchain.can_destroy() {
-                self.old_swapchain = None;
+            if old_swapchain.can_destroy(self.frames_in_flight) {
+                let old_swapchain = self.old_swapchain.take().unwrap();
+                retire_swapchain(&mut self.sync_object_pool, old_swapchain.swapchain);
             }
         }
 
         VulkanFrameObjects {
             graphics_command_buffer: self.graphics_command_buffers[old_index as usize],
+            command_pool: self.frame_command_lists[old_index as usize].command_pool,
             swapchain_image: image,
             swapchain_semaphore: semaphore,
-            descriptor_pool: self.descriptor_pools[old_index as usize], // TODO: this should be per-frame
             frame_index: old_index
         }
     }
 
+    /// Allocates from the pools of the frame most recently started with
+    /// [`start_frame`](Self::start_frame); the sets are valid until that frame index is started again.
+    /// Sets are named `name` followed by their index in `layouts`
     pub fn create_descriptor_sets(
-        &self,
+        &mut self,
         layouts: &[vk::DescriptorSetLayout],
-        descriptor_pool: vk::DescriptorPool) -> Vec<vk::DescriptorSet> {
+        name: &str) -> Vec<vk::DescriptorSet> {
         enter_span!(tracing::Level::TRACE, "Create Descriptorsets");
 
-        if layouts.len() > 0 {
-            let alloc_info = vk::DescriptorSetAllocateInfo {
-                s_type: vk::StructureType::DESCRIPTOR_SET_ALLOCATE_INFO,
-                p_next: std::ptr::null(),
-                descriptor_pool: descriptor_pool,
-                descriptor_set_count: layouts.len() as u32,
-                p_set_layouts: layouts.as_ptr()
-            };
-
-            let descriptor_sets = unsafe {
-                self.device.borrow().get().allocate_descriptor_sets(&alloc_info )
-                    .expect("Failed to allocate descriptor sets")
-            };
-
-            return descriptor_sets;
-        }
+        self.descriptor_pool_manager.as_mut()
+            .expect("Descriptor sets can't be allocated while using descriptor buffers")
+            .allocate(layouts, name)
+    }
 
-        Vec::new()
+    /// Zeroed while using descriptor buffers
+    pub fn get_descriptor_pool_stats(&self) -> DescriptorPoolStats {
+        self.descriptor_pool_manager.as_ref().map_or(DescriptorPoolStats::default(), |manager| manager.get_stats())
+    }
+
+    /// Whether passes write their descriptors through
+    /// [`get_descriptor_buffer_manager`](Self::get_descriptor_buffer_manager) instead of
+    /// [`create_descriptor_sets`](Self::create_descriptor_sets)
+    pub fn uses_descriptor_buffers(&self) -> bool {
+        self.descriptor_buffer_manager.is_some()
+    }
+
+    pub fn get_descriptor_buffer_manager(&self) -> Option<&DescriptorBufferManager> {
+        self.descriptor_buffer_manager.as_ref()
+    }
+
+    /// Stages sets for `layouts` in the descriptor buffers of the frame most recently started with
+    /// [`start_frame`](Self::start_frame); they're valid until that frame index is started again
+    pub fn allocate_descriptor_buffer_sets(
+        &mut self,
+        layouts: &[vk::DescriptorSetLayout],
+        name: &str) -> DescriptorBufferSets {
+        enter_span!(tracing::Level::TRACE, "Allocate descriptor buffer sets");
+
+        self.descriptor_buffer_manager.as_mut()
+            .expect("Descriptor buffers are only used with NegotiatedFeature::DescriptorBuffer")
+            .allocate(layouts, name)
+    }
+
+    /// Zeroed unless using descriptor buffers
+    pub fn get_descriptor_buffer_stats(&self) -> DescriptorBufferStats {
+        self.descriptor_buffer_manager.as_ref().map_or(DescriptorBufferStats::default(), |manager| manager.get_stats())
     }
 
+    /// `layers` is the number of array layers layered rendering can write to, and must be 1 for
+    /// multiview renderpasses, whose views select the layers instead
     pub fn create_framebuffer(
         &self,
         render_pass: vk::RenderPass,
         extent: &vk::Extent3D,
+        layers: u32,
         images: &[ImageWrapper],
-        depth: &Option<ImageWrapper>) -> DeviceFramebuffer {
+        depth: &Option<ImageWrapper>,
+        name: &str) -> DeviceFramebuffer {
         enter_span!(tracing::Level::TRACE, "Create framebuffer");
 
         let mut image_views: Vec<vk::ImageView> = Vec::new();
@@ -1112,11 +1735,12 @@ impl VulkanRenderContext {
             .attachments(&image_views)
             .width(extent.width)
             .height(extent.height)
-            .layers(extent.depth);
+            .layers(layers);
 
         unsafe {
             let framebuffer = self.device.borrow().get().create_framebuffer(&create_info, None)
                 .expect("Failed to create framebuffer");
+            self.device.borrow().set_debug_name(vk::ObjectType::FRAMEBUFFER, framebuffer.as_raw(), name);
             DeviceFramebuffer::new(framebuffer, self.device.clone())
         }
     }
@@ -1146,6 +1770,92 @@ impl VulkanRenderContext {
         }
     }
 
+    /// Submits `submits` to the graphics queue in order, each waiting on the one before it.
+    /// The first waits on `wait_semaphores` and the last signals `signal_semaphores` and `fence`
+    #[tracing::instrument]
+    pub fn submit_graphics_batch(
+        &self,
+        submits: &[GraphicsSubmit],
+        fence: vk::Fence,
+        wait_semaphores: &[vk::Semaphore],
+        signal_semaphores: &[vk::Semaphore]) {
+
+        let last = submits.len().saturating_sub(1);
+        let waits: Vec<(Vec<vk::Semaphore>, Vec<vk::PipelineStageFlags>)> = submits.iter().enumerate().map(|(index, submit)| {
+            if index == 0 {
+                (wait_semaphores.to_vec(), vec![vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT; wait_semaphores.len()])
+            } else {
+                (vec![submits[index - 1].signal_semaphore], vec![submit.wait_stage_mask])
+            }
+        }).collect();
+        let signals: Vec<Vec<vk::Semaphore>> = submits.iter().enumerate().map(|(index, submit)| {
+            if index == last {
+                signal_semaphores.to_vec()
+            } else {
+                vec![submit.signal_semaphore]
+            }
+        }).collect();
+
+        let submit_infos: Vec<vk::SubmitInfo> = submits.iter().enumerate().map(|(index, submit)| {
+            vk::SubmitInfo::builder()
+                .wait_semaphores(&waits[index].0)
+                .wait_dst_stage_mask(&waits[index].1)
+                .command_buffers(std::slice::from_ref(&submit.command_buffer))
+                .signal_semaphores(&signals[index])
+                .build()
+        }).collect();
+
+        unsafe {
+            self.device.borrow().get()
+                .queue_submit(
+                    self.get_graphics_queue(),
+                    &submit_infos,
+                    fence)
+                .expect("Failed to execute Graphics batch submit");
+        }
+    }
+
+    /// A reset command buffer for the frame currently being recorded, for frames whose work
+    /// is split into several command lists. Valid until this frame index is started again
+    pub fn acquire_command_buffer(&mut self) -> vk::CommandBuffer {
+        let name = format!("command_list_frame{}_{}",
+            self.frame_index,
+            self.frame_command_lists[self.frame_index as usize].used_command_buffers);
+        self.acquire_named_command_buffer(&name)
+    }
+
+    /// A reset command buffer from the pool of the frame currently being recorded, e.g. for
+    /// transfers submitted to the graphics queue. Every command buffer of a frame shares one
+    /// command pool, so they must all be recorded on the thread owning the context. It's
+    /// given `name` for debuggers until it's handed out again, and is valid until this frame
+    /// index is started again, when the pool is reset
+    pub fn acquire_named_command_buffer(&mut self, name: &str) -> vk::CommandBuffer {
+        let frame_command_lists = &mut self.frame_command_lists[self.frame_index as usize];
+        if frame_command_lists.used_command_buffers == frame_command_lists.command_buffers.len() {
+            let command_buffer = create_command_buffers(&self.device.borrow(), frame_command_lists.command_pool, 1, name)[0];
+            frame_command_lists.command_buffers.push(command_buffer);
+        }
+        let command_buffer = frame_command_lists.command_buffers[frame_command_lists.used_command_buffers];
+        frame_command_lists.used_command_buffers += 1;
+
+        self.device.borrow().set_debug_name(vk::ObjectType::COMMAND_BUFFER, command_buffer.as_raw(), name);
+        command_buffer
+    }
+
+    /// A binary semaphore for ordering the frame currently being recorded's command buffers
+    /// (see GraphicsSubmit). Valid until this frame index is started again
+    pub fn acquire_frame_semaphore(&mut self) -> vk::Semaphore {
+        let frame_command_lists = &mut self.frame_command_lists[self.frame_index as usize];
+        if frame_command_lists.used_semaphores == frame_command_lists.semaphores.len() {
+            let semaphore = self.sync_object_pool.acquire_semaphore(
+                &format!("command_list_frame{}_semaphore{}", self.frame_index, frame_command_lists.semaphores.len()));
+            frame_command_lists.semaphores.push(semaphore);
+        }
+        let semaphore = frame_command_lists.semaphores[frame_command_lists.used_semaphores];
+        frame_command_lists.used_semaphores += 1;
+        semaphore
+    }
+
     #[tracing::instrument]
     pub fn flip(
         &self,
@@ -1172,23 +1882,27 @@ impl VulkanRenderContext {
 
         // wait for and reset the presentation fence
         let present_fence = swapchain.get_present_fence(self.swapchain_index);
-        unsafe {
-            enter_span!(tracing::Level::TRACE, "Waiting for Present fence");
-            self.device.borrow().get().wait_for_fences(
-                std::slice::from_ref(&present_fence),
-                true,
-                u64::MAX )
-                .expect("Failed to wait for Present fence");
-
-            self.device.borrow().get().reset_fences(
-                std::slice::from_ref(&present_fence)
-            ).expect("Failed to reset Present fence");
+        let mut swapchain_fence: vk::SwapchainPresentFenceInfoEXT;
+        if let Some(present_fence) = &present_fence {
+            unsafe {
+                enter_span!(tracing::Level::TRACE, "Waiting for Present fence");
+                self.device.borrow().get().wait_for_fences(
+                    std::slice::from_ref(present_fence),
+                    true,
+                    u64::MAX )
+                    .expect("Failed to wait for Present fence");
+
+                self.device.borrow().get().reset_fences(
+                    std::slice::from_ref(present_fence)
+                ).expect("Failed to reset Present fence");
+            }
+            swapchain_fence = vk::SwapchainPresentFenceInfoEXT::builder()
+                .fences(std::slice::from_ref(present_fence))
+                .build();
+            present_info = present_info.push_next(&mut swapchain_fence);
         }
-        let mut swapchain_fence = vk::SwapchainPresentFenceInfoEXT::builder()
-            .fences(std::slice::from_ref(&present_fence))
-            .build();
 
-        let resolved_present_info = present_info.push_next(&mut swapchain_fence).build();
+        let resolved_present_info = present_info.build();
 
         let is_suboptimal = unsafe {
             swapchain.get_loader().queue_present(
@@ -1197,26 +1911,143 @@ impl VulkanRenderContext {
                 .expect("Failed to execute queue present")
         };
 
-        match is_suboptimal {
+        // a present mode change is applied by the caller's usual handling of suboptimal swapchains
+        match is_suboptimal || self.present_mode_changed {
             true => {SwapchainStatus::Suboptimal}
             false => {SwapchainStatus::Ok}
         }
     }
 
+    /// Presents the image last acquired through [`get_next_window_objects`](Self::get_next_window_objects).
+    #[tracing::instrument]
+    pub fn flip_window(
+        &self,
+        window_id: winit::window::WindowId,
+        wait_semaphores: &[vk::Semaphore]) -> SwapchainStatus {
+
+        let window_swapchain = self.window_swapchains.get(&window_id)
+            .expect("Attempted to flip an unknown window");
+        let swapchain = window_swapchain.swapchain.as_ref()
+            .expect("Attempted to flip a window without a swapchain");
+
+        let raw_swapchain = swapchain.get();
+        let image_index = window_swapchain.image_index;
+        let mut present_info = vk::PresentInfoKHR::builder()
+            .wait_semaphores(wait_semaphores)
+            .swapchains(std::slice::from_ref(&raw_swapchain))
+            .image_indices(std::slice::from_ref(&image_index));
+
+        let present_fence = swapchain.get_present_fence(image_index);
+        let mut swapchain_fence: vk::SwapchainPresentFenceInfoEXT;
+        if let Some(present_fence) = &present_fence {
+            unsafe {
+                enter_span!(tracing::Level::TRACE, "Waiting for window Present fence");
+                self.device.borrow().get().wait_for_fences(
+                    std::slice::from_ref(present_fence),
+                    true,
+                    u64::MAX )
+                    .expect("Failed to wait for window Present fence");
+
+                self.device.borrow().get().reset_fences(
+                    std::slice::from_ref(present_fence)
+                ).expect("Failed to reset window Present fence");
+            }
+            swapchain_fence = vk::SwapchainPresentFenceInfoEXT::builder()
+                .fences(std::slice::from_ref(present_fence))
+                .build();
+            present_info = present_info.push_next(&mut swapchain_fence);
+        }
+
+        let resolved_present_info = present_info.build();
+
+        let result = unsafe {
+            swapchain.get_loader().queue_present(
+                self.get_present_queue(),
+                &resolved_present_info)
+        };
+
+        match result {
+            Ok(true) => {SwapchainStatus::Suboptimal}
+            Ok(false) => {SwapchainStatus::Ok}
+            Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {SwapchainStatus::Outdated}
+            Err(err) => {panic!("Failed to execute window queue present: {}", err)}
+        }
+    }
+
+    /// Must be called once the fence guarding the previous submission of `frame_index` has signaled,
+    /// whether or not the context presents. Destroys the resources dropped while that frame was recorded
     pub fn start_frame(&mut self, frame_index: u32) {
-        let borrowed_device = self.device.borrow();
-        reset_gpu_profiling!(borrowed_device.get());
+        // the GPU is past the last use of anything released during this frame index
+        self.deferred_releases[frame_index as usize].clear();
+        if let Some(frame_value) = self.submitted_frame_values[frame_index as usize].take() {
+            self.device.borrow_mut().complete_frame(frame_value);
+        }
+        self.transient_image_pool.begin_frame();
+        if let Some(descriptor_pool_manager) = &mut self.descriptor_pool_manager {
+            descriptor_pool_manager.begin_frame(frame_index);
+        }
+        if let Some(descriptor_buffer_manager) = &mut self.descriptor_buffer_manager {
+            descriptor_buffer_manager.begin_frame(frame_index);
+        }
+        let frame_command_lists = &mut self.frame_command_lists[frame_index as usize];
+        unsafe {
+            self.device.borrow().get().reset_command_pool(frame_command_lists.command_pool, vk::CommandPoolResetFlags::empty())
+                .expect("Failed to reset frame command pool");
+        }
+        frame_command_lists.used_command_buffers = 0;
+        frame_command_lists.used_semaphores = 0;
+        let old_swapchains = self.old_swapchain.iter_mut()
+            .chain(self.window_swapchains.values_mut().filter_map(|window_swapchain| window_swapchain.old_swapchain.as_mut()));
+        for old_swapchain in old_swapchains {
+            old_swapchain.frames_started += 1;
+        }
+        self.profiler.reset();
     }
 
-    pub fn end_frame(&mut self) {
-        let max_frames_in_flight = {
-            if let Some(swapchain) = &self.swapchain {
-                swapchain.get_images().len() as u32
-            } else {
-                MAX_FRAMES_IN_FLIGHT
+    /// Defers dropping a resource which may still be referenced by the frame currently being
+    /// recorded until that frame's fence has signaled (see [`start_frame`](Self::start_frame))
+    pub fn release_after_frame(&mut self, resource: Rc<RefCell<DeviceResource>>) {
+        self.deferred_releases[self.frame_index as usize].push(resource);
+    }
+
+    /// Shared by every thread recording this frame's command buffers (see enter_gpu_span!)
+    pub fn get_gpu_profiler(&self) -> &GpuProfiler {
+        self.profiler.get_gpu_profiler()
+    }
+
+    /// Opens GPU spans with `get_profiler().scope(name, command_buffer)`
+    pub fn get_profiler(&self) -> &FramegraphProfiler {
+        &self.profiler
+    }
+
+    /// Pooled images for render targets which would otherwise be recreated every frame
+    pub fn get_transient_image_pool(&mut self) -> &mut TransientImagePool {
+        &mut self.transient_image_pool
+    }
+
+    /// Recycled fences and semaphores, destroyed along with the context
+    pub fn get_sync_object_pool(&mut self) -> &mut SyncObjectPool {
+        &mut self.sync_object_pool
+    }
+
+    /// Waits for the device to go idle and retires every submitted frame, destroying the
+    /// resources they released without waiting for their frame indices to be started again
+    pub fn wait_idle(&mut self) {
+        self.device.borrow_mut().wait_idle();
+        for (frame_index, submitted) in self.submitted_frame_values.iter_mut().enumerate() {
+            if submitted.take().is_some() {
+                self.deferred_releases[frame_index].clear();
             }
-        };
-        self.swapchain_index = (self.swapchain_index + 1) % max_frames_in_flight;
-        self.frame_index = (self.frame_index + 1) % max_frames_in_flight;
+        }
     }
+
+    /// Called once the frame's work has been submitted, with or without presenting it
+    pub fn end_frame(&mut self) {
+        self.submitted_frame_values[self.frame_index as usize] = Some(self.device.borrow_mut().advance_frame());
+        self.frame_index = (self.frame_index + 1) % self.frames_in_flight;
+    }
+
+    /// Number of frame indices, each with its own command buffers, semaphores, descriptor pools
+    /// and profiling queries. Callers size their own per-frame objects to match
+    pub fn get_frames_in_flight(&self) -> u32 { self.frames_in_flight }
 }
\ No newline at end of file
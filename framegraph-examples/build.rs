@@ -31,6 +31,42 @@ fn compile_shaders(paths: Paths, out_dir: &str) {
     }
 }
 
+// each line of a <shader>.variants file lists the defines of one variant of <shader>, as NAME
+// or NAME=value separated by whitespace. Variants are named like ShaderVariant::get_file_name
+fn compile_variants(paths: Paths, out_dir: &str) {
+    for entry in paths {
+        let variants_path = entry.expect("Failed to find shader variants");
+        let shader_path = variants_path.with_extension("");
+        let shader_name = shader_path.file_stem()
+            .expect("Unknown shader name")
+            .to_str().unwrap();
+        let shader_ext = shader_path.extension()
+            .expect("Couldn't determine shader extension")
+            .to_str().unwrap();
+        let variants = std::fs::read_to_string(&variants_path)
+            .expect("Failed to read shader variants");
+        for line in variants.lines() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let mut defines: Vec<&str> = line.split_whitespace().collect();
+            defines.sort_by_key(|define| define.split('=').next().unwrap());
+            defines.dedup();
+
+            let mut command = Command::new("glslangValidator");
+            command.args(&[shader_path.to_str().unwrap(), "--target-env", "vulkan1.1"]);
+            for define in &defines {
+                command.arg(&format!("-D{}", define));
+            }
+            command.arg("-o")
+                .arg(&format!("{}/shaders/{}-{}.{}.spv", out_dir, shader_name, shader_ext, defines.join(".")))
+                .status()
+                .expect("Error compiling shader variant");
+        }
+    }
+}
+
 fn main() {
     println!("cargo:rerun-if-changed=shaders");
     println!("cargo:rerun-if-changed='../passes/shaders'");
@@ -62,4 +98,7 @@ fn main() {
     compile_shaders(pass_vert_shaders, &out_dir);
     compile_shaders(pass_frag_shaders, &out_dir);
     compile_shaders(pass_compute_shaders, &out_dir);
+
+    compile_variants(glob("shaders/*.variants").expect("No shader variants found"), &out_dir);
+    compile_variants(glob("../passes/shaders/*.variants").expect("No pass shader variants"), &out_dir);
 }
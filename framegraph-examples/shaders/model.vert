@@ -1,12 +1,17 @@
 #version 450
+#extension GL_GOOGLE_include_directive : require
+
+#include "../../passes/shaders/frame_constants.glsl"
+
 layout(location = 0) in vec3 position;
 layout(location = 1) in vec3 normal;
 layout(location = 2) in vec2 uv;
 
+// the camera is in the frame's constants
 layout(set = 0, binding = 0) uniform Model {
     mat4 model;
-    mat4 view;
-    mat4 proj;
+    mat4 previousModelViewProj;
+    vec2 jitter;
 } model;
 
 out gl_PerVertex {
@@ -19,9 +24,16 @@ layout(location=0) out struct {
     vec2 uv;
 } Out;
 
+// this frame's and the previous frame's clip space position, for motion vectors
+layout(location=3) out vec4 currentPosition;
+layout(location=4) out vec4 previousPosition;
+
 void main() {
     Out.uv = uv;
-    Out.normal = normal;
+    Out.normal = mat3(transpose(inverse(frame.view * model.model))) * normal;
     Out.color = vec4(1.0, 0.0, 0.0, 1.0);
-    gl_Position = model.proj * model.view * model.model * vec4(position, 1.0);
+    currentPosition = frame.viewProjection * model.model * vec4(position, 1.0);
+    previousPosition = model.previousModelViewProj * vec4(position, 1.0);
+    // motion vectors are between unjittered positions, so only the rasterized one is jittered
+    gl_Position = currentPosition + vec4(model.jitter * currentPosition.w, 0.0, 0.0);
 }
@@ -0,0 +1,29 @@
+#version 450
+
+// model.frag, also writing each texel's motion since the previous frame and its view space
+// normal to the second and third targets
+
+layout(location = 0) out vec4 fragColor;
+layout(location = 1) out vec2 velocity;
+layout(location = 2) out vec4 viewNormal;
+
+layout(location = 0) in struct {
+    vec4 color;
+    vec3 normal;
+    vec2 uv;
+} In;
+layout(location = 3) in vec4 currentPosition;
+layout(location = 4) in vec4 previousPosition;
+
+layout(binding = 1) uniform sampler2D colorSampler;
+
+void main() {
+    fragColor = texture(colorSampler, In.uv);
+
+    vec2 current = currentPosition.xy / currentPosition.w;
+    vec2 previous = previousPosition.xy / previousPosition.w;
+    // in UV units, with Y flipped to match the model passes' viewport
+    velocity = (current - previous) * vec2(0.5, -0.5);
+
+    viewNormal = vec4(normalize(In.normal), 0.0);
+}
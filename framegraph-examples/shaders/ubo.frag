@@ -1,4 +1,9 @@
 #version 450
+#extension GL_GOOGLE_include_directive : require
+
+#ifdef PULSE
+#include "../../passes/shaders/frame_constants.glsl"
+#endif
 
 layout(location = 0) out vec4 outColor;
 
@@ -7,5 +12,11 @@ layout(set = 0, binding = 0) uniform UBO {
 } ubo;
 
 void main() {
+#ifdef PULSE
+    // pulses with the frame's time, which the pass never binds itself
+    float pulse = 0.75 + 0.25 * sin(frame.time * 2.0);
+    outColor = vec4(ubo.color * pulse, 1.0);
+#else
     outColor = vec4(ubo.color, 1.0);
+#endif
 }
\ No newline at end of file
@@ -0,0 +1,344 @@
+use alloc::rc::Rc;
+use std::cell::RefCell;
+use ash::vk;
+use ash::vk::Handle;
+use glam::IVec2;
+use gpu_allocator::MemoryLocation;
+use imgui::Ui;
+use api_types::buffer::BufferCreateInfo;
+use api_types::device::{DeviceResource, DeviceWrapper};
+use api_types::image::{ImageCreateInfo, ImageType};
+use api_types::upload_buffer::DynamicUploadBuffer;
+use context::render_context::RenderContext;
+use context::transient_image_pool::TransientImagePool;
+use context::vulkan_render_context::VulkanRenderContext;
+use framegraph::attachment::AttachmentReference;
+use framegraph::binding::{SampledImage, ShaderInterface, StorageBuffer, StorageImage, UniformBuffer};
+use framegraph::compute_pass_node::{ComputePassNode, ComputePassNodeBuilder};
+use framegraph::copy_pass_node::CopyPassNode;
+use framegraph::frame::Frame;
+use framegraph::pass_type::PassType;
+use framegraph::pipeline::ComputePipelineDescription;
+use framegraph::uniform_layout::UniformBlock;
+use passes::blit;
+use passes::reduction::LuminanceHistogram;
+use profiling::enter_span;
+use crate::example::{Example, ExampleSettings};
+use crate::input::InputState;
+
+// log2 luminance covered by the histogram, from well below the darkest ground to the sun at
+// the highest brightness
+const MIN_LOG_LUMINANCE: f32 = -10.0;
+const MAX_LOG_LUMINANCE: f32 = 12.0;
+
+// what the exposure starts from before the first frame has adapted it
+const INITIAL_AVERAGE_LUMINANCE: f32 = 0.18;
+const INITIAL_EXPOSURE: f32 = 1.0;
+
+#[repr(C)]
+#[derive(UniformBlock)]
+struct SceneParams {
+    time: f32,
+    brightness: f32
+}
+
+#[repr(C)]
+#[derive(UniformBlock)]
+struct ExposureParams {
+    delta_time: f32,
+    adaptation_rate: f32,
+    low_percentile: f32,
+    high_percentile: f32
+}
+
+/// Descriptors of hdr_scene.comp. Never constructed; only its slots are used
+#[derive(ShaderInterface)]
+#[allow(dead_code)]
+struct SceneInterface {
+    #[binding(set = 0, slot = 0, stage = COMPUTE_SHADER)]
+    params: UniformBuffer,
+    #[binding(set = 0, slot = 1, stage = COMPUTE_SHADER, access = SHADER_WRITE)]
+    scene: StorageImage
+}
+
+/// Descriptors of adapt_exposure.comp
+#[derive(ShaderInterface)]
+#[allow(dead_code)]
+struct AdaptInterface {
+    #[binding(set = 0, slot = 0, stage = COMPUTE_SHADER)]
+    params: UniformBuffer,
+    #[binding(set = 0, slot = 1, stage = COMPUTE_SHADER, access = SHADER_READ)]
+    histogram: StorageBuffer,
+    #[binding(set = 0, slot = 2, stage = COMPUTE_SHADER)]
+    exposure: StorageBuffer
+}
+
+/// Descriptors of exposure_tonemap.comp
+#[derive(ShaderInterface)]
+#[allow(dead_code)]
+struct TonemapInterface {
+    #[binding(set = 0, slot = 0, stage = COMPUTE_SHADER)]
+    scene: SampledImage,
+    #[binding(set = 0, slot = 1, stage = COMPUTE_SHADER, access = SHADER_READ)]
+    exposure: StorageBuffer,
+    #[binding(set = 0, slot = 2, stage = COMPUTE_SHADER, access = SHADER_WRITE)]
+    output: StorageImage
+}
+
+/// The HDR scene and its tonemapped output, recreated when the back buffer is resized
+struct SceneImages {
+    scene: Rc<RefCell<DeviceResource>>,
+    output: Rc<RefCell<DeviceResource>>
+}
+
+/// State carried from one frame to the next on the GPU: the histogram is refilled every frame,
+/// while the exposure buffer holds the adapted average luminance and exposure each frame
+/// continues from
+struct ExposureState {
+    histogram: LuminanceHistogram,
+    exposure: Rc<RefCell<DeviceResource>>
+}
+
+fn create_image(
+    device: Rc<RefCell<DeviceWrapper>>,
+    extent: vk::Extent3D,
+    format: vk::Format,
+    usage: vk::ImageUsageFlags,
+    sampled: bool,
+    name: &str) -> Rc<RefCell<DeviceResource>> {
+
+    let create_info = ImageCreateInfo::new(
+        vk::ImageCreateInfo::builder()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(format)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .extent(extent)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .usage(usage)
+            .mip_levels(1)
+            .array_layers(1)
+            .build(),
+        name.to_string(),
+        ImageType::Color);
+
+    let mut image = DeviceWrapper::create_image(device.clone(), &create_info, MemoryLocation::GpuOnly);
+    if sampled {
+        let sampler = unsafe {
+            let sampler_create = vk::SamplerCreateInfo::builder()
+                .mag_filter(vk::Filter::NEAREST)
+                .min_filter(vk::Filter::NEAREST)
+                .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+                .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .build();
+
+            device.borrow().get().create_sampler(&sampler_create, None)
+                .expect("Failed to create sampler for auto exposure image")
+        };
+        device.borrow().set_debug_name(vk::ObjectType::SAMPLER, sampler.as_raw(), &format!("{}_sampler", name));
+        image.get_image_mut().sampler = Some(sampler);
+    }
+
+    Rc::new(RefCell::new(image))
+}
+
+fn compute_pass(
+    name: &str,
+    shader: &str,
+    groups: (u32, u32),
+    builder: impl FnOnce(ComputePassNodeBuilder) -> ComputePassNodeBuilder) -> PassType {
+
+    let scope_name = name.to_string();
+    let pass_node = builder(ComputePassNode::builder(name.to_string()))
+        .pipeline_description(ComputePipelineDescription::new(shader))
+        .fill_commands(Box::new(
+            move |render_ctx: &VulkanRenderContext,
+                  command_buffer: &vk::CommandBuffer| {
+
+                enter_span!(tracing::Level::TRACE, "Auto Exposure");
+                let _gpu_scope = render_ctx.get_profiler().scope(&scope_name, command_buffer);
+
+                unsafe {
+                    render_ctx.get_device().borrow().get().cmd_dispatch(
+                        *command_buffer,
+                        groups.0,
+                        groups.1,
+                        1);
+                }
+            }
+        ))
+        .build()
+        .expect("Failed to create auto exposure passnode");
+
+    PassType::Compute(pass_node)
+}
+
+/// Renders a scene whose brightness varies far more than a display's range, and exposes it
+/// with an exposure adapted on the GPU from the scene's luminance histogram. The adapted
+/// exposure lives in a buffer which every frame reads and updates, so the framegraph orders it
+/// against the previous frame's accesses through the state it keeps for persistent resources
+pub struct AutoExposureExample {
+    images: RefCell<Option<SceneImages>>,
+    state: RefCell<Option<ExposureState>>,
+    time: f32,
+    delta_time: f32,
+    animate: bool,
+    brightness_ev: f32,
+    adaptation_rate: f32,
+    percentiles: [f32; 2]
+}
+
+impl Example for AutoExposureExample {
+    fn get_name(&self) -> &'static str {
+        "Auto Exposure"
+    }
+
+    fn ui(&mut self, ui: &Ui) {
+        ui.checkbox("Animate Brightness", &mut self.animate);
+        ui.slider("Brightness (EV)", -6.0, 6.0, &mut self.brightness_ev);
+        ui.slider("Adaptation Rate", 0.1, 10.0, &mut self.adaptation_rate);
+        ui.slider("Low Percentile", 0.0, 0.99, &mut self.percentiles[0]);
+        ui.slider("High Percentile", 0.01, 1.0, &mut self.percentiles[1]);
+        self.percentiles[1] = self.percentiles[1].max(self.percentiles[0] + 0.01);
+    }
+
+    fn update(&mut self, _input: &InputState, _settings: &ExampleSettings, delta_time: f32) {
+        self.delta_time = delta_time;
+        self.time += delta_time;
+        if self.animate {
+            // sweeps the scene 4 stops either side of its mean brightness
+            self.brightness_ev = (self.time * 0.4).sin() * 4.0;
+        }
+    }
+
+    fn execute(&self, frame: &mut Frame, device: Rc<RefCell<DeviceWrapper>>, upload_buffer: &mut DynamicUploadBuffer, _image_pool: &mut TransientImagePool, _settings: &ExampleSettings, _imgui_ui: &mut Ui, back_buffer: AttachmentReference) -> Vec<PassType> {
+        enter_span!(tracing::Level::TRACE, "Generating Auto Exposure Passes");
+
+        let extent = back_buffer.resource_image.borrow().get_image().extent;
+        let mut images_ref = self.images.borrow_mut();
+        let recreate = images_ref.as_ref().map_or(true, |images| {
+            images.scene.borrow().get_image().extent != extent
+        });
+        if recreate {
+            *images_ref = Some(SceneImages {
+                scene: create_image(
+                    device.clone(),
+                    extent,
+                    vk::Format::R16G16B16A16_SFLOAT,
+                    vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED,
+                    true,
+                    "auto_exposure_scene"),
+                output: create_image(
+                    device.clone(),
+                    extent,
+                    vk::Format::R8G8B8A8_UNORM,
+                    vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::TRANSFER_SRC,
+                    false,
+                    "auto_exposure_output")
+            });
+        }
+        let images = images_ref.as_ref().unwrap();
+
+        let mut state_ref = self.state.borrow_mut();
+        let state = state_ref.get_or_insert_with(|| {
+            let create_info = BufferCreateInfo::new(
+                vk::BufferCreateInfo::builder()
+                    .size(2 * std::mem::size_of::<f32>() as vk::DeviceSize)
+                    .usage(vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::TRANSFER_DST)
+                    .sharing_mode(vk::SharingMode::EXCLUSIVE)
+                    .build(),
+                "auto_exposure_exposure".to_string());
+            ExposureState {
+                histogram: LuminanceHistogram::new(device.clone(), MIN_LOG_LUMINANCE, MAX_LOG_LUMINANCE, "auto_exposure_histogram"),
+                exposure: Rc::new(RefCell::new(DeviceWrapper::create_buffer(device.clone(), &create_info, MemoryLocation::GpuOnly)))
+            }
+        });
+
+        let alignment = upload_buffer.get_uniform_alignment();
+        let scene_params_offset = upload_buffer.push(std::slice::from_ref(&SceneParams {
+            time: self.time,
+            brightness: self.brightness_ev.exp2()
+        }), alignment);
+        let exposure_params_offset = upload_buffer.push(std::slice::from_ref(&ExposureParams {
+            delta_time: self.delta_time,
+            adaptation_rate: self.adaptation_rate,
+            low_percentile: self.percentiles[0],
+            high_percentile: self.percentiles[1]
+        }), alignment);
+
+        let groups = (extent.width.div_ceil(8), extent.height.div_ceil(8));
+        let mut passes: Vec<PassType> = Vec::new();
+        passes.push(compute_pass("hdr_scene", "hdr_scene-comp.spv", groups, |builder| {
+            builder
+                .input(SceneInterface::PARAMS.bind_uniform::<SceneParams>(upload_buffer.get_buffer(), scene_params_offset))
+                .output(SceneInterface::SCENE.bind(images.scene.clone()))
+        }));
+
+        passes.extend(state.histogram.generate_passes(images.scene.clone()));
+
+        // the exposure buffer's contents are undefined until a frame has written it
+        if !frame.import_persistent(&state.exposure) {
+            let exposure_buffer = state.exposure.borrow().get_buffer().buffer;
+            let initial_data: Vec<u8> = [INITIAL_AVERAGE_LUMINANCE, INITIAL_EXPOSURE].iter()
+                .flat_map(|value| value.to_ne_bytes())
+                .collect();
+            let init_node = CopyPassNode::builder("exposure_init".to_string())
+                .copy_dst(state.exposure.clone())
+                .fill_commands(Box::new(
+                    move |render_ctx: &VulkanRenderContext,
+                          command_buffer: &vk::CommandBuffer| {
+
+                        unsafe {
+                            render_ctx.get_device().borrow().get().cmd_update_buffer(
+                                *command_buffer,
+                                exposure_buffer,
+                                0,
+                                &initial_data);
+                        }
+                    }
+                ))
+                .build()
+                .expect("Failed to create exposure init passnode");
+            passes.push(PassType::Copy(init_node));
+        }
+
+        passes.push(compute_pass("adapt_exposure", "adapt_exposure-comp.spv", (1, 1), |builder| {
+            builder
+                .input(AdaptInterface::PARAMS.bind_uniform::<ExposureParams>(upload_buffer.get_buffer(), exposure_params_offset))
+                .input(AdaptInterface::HISTOGRAM.bind(state.histogram.get_buffer().clone()))
+                .output(AdaptInterface::EXPOSURE.bind(state.exposure.clone()))
+        }));
+
+        passes.push(compute_pass("exposure_tonemap", "exposure_tonemap-comp.spv", groups, |builder| {
+            builder
+                .input(TonemapInterface::SCENE.bind(images.scene.clone()))
+                .input(TonemapInterface::EXPOSURE.bind(state.exposure.clone()))
+                .output(TonemapInterface::OUTPUT.bind(images.output.clone()))
+        }));
+
+        passes.push(blit::generate_pass(
+            images.output.clone(),
+            0,
+            back_buffer.resource_image.clone(),
+            0,
+            [IVec2::new(0, 0), IVec2::new(extent.width as i32, extent.height as i32)]));
+
+        passes
+    }
+}
+
+impl AutoExposureExample {
+    pub fn new() -> Self {
+        AutoExposureExample {
+            images: RefCell::new(None),
+            state: RefCell::new(None),
+            time: 0.0,
+            delta_time: 0.0,
+            animate: true,
+            brightness_ev: 0.0,
+            adaptation_rate: 1.5,
+            percentiles: [0.5, 0.95]
+        }
+    }
+}
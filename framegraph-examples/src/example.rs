@@ -1,12 +1,55 @@
 use alloc::rc::Rc;
 use std::cell::RefCell;
+use ash::vk;
 use imgui::Ui;
 use api_types::device::DeviceWrapper;
+use api_types::upload_buffer::DynamicUploadBuffer;
+use context::transient_image_pool::TransientImagePool;
 use framegraph::attachment::AttachmentReference;
+use framegraph::frame::Frame;
+use framegraph::frame_constants::FrameCamera;
 use framegraph::pass_type::PassType;
+use crate::input::InputState;
+
+/// Parameters shared between examples, shown by the host app in the example's panel and
+/// kept while switching between examples. Parameters an example doesn't use are None and
+/// aren't shown
+#[derive(Clone, Debug, Default)]
+pub struct ExampleSettings {
+    /// What the back buffer is cleared to before the example draws
+    pub clear_color: Option<[f32; 4]>,
+    /// Scales how fast interactive cameras move
+    pub camera_speed: Option<f32>,
+    /// For examples which multisample their own render targets. Starts at the render
+    /// settings' sample count, and follows it whenever it changes
+    pub msaa_samples: Option<vk::SampleCountFlags>,
+    pub wireframe: Option<bool>
+}
 
 pub trait Example {
     fn get_name(&self) -> &'static str;
 
-    fn execute(&self, device: Rc<RefCell<DeviceWrapper>>, imgui_ui: &mut Ui, back_buffer: AttachmentReference) -> Vec<PassType>;
-}
\ No newline at end of file
+    /// The settings the host app starts this example with
+    fn default_settings(&self) -> ExampleSettings {
+        ExampleSettings::default()
+    }
+
+    /// Adds the example's own parameters to its panel, below the shared settings
+    fn ui(&mut self, _ui: &Ui) {}
+
+    /// Called once the fence of the frame `frame_index` last used has signaled, before any of
+    /// this frame's passes are built, for examples reading back what earlier frames wrote
+    fn begin_frame(&mut self, _frame_index: u32, _fence: vk::Fence) {}
+
+    /// Called each frame before execute while the example is active, with the input since
+    /// the last frame
+    fn update(&mut self, _input: &InputState, _settings: &ExampleSettings, _delta_time: f32) {}
+
+    /// The camera the host app puts in the frame's constants. Examples without one get an
+    /// identity camera
+    fn get_camera(&self) -> Option<FrameCamera> { None }
+
+    /// The passes drawing the example into `back_buffer`. `frame` is the Frame they'll be added
+    /// to, for importing the resources they use
+    fn execute(&self, frame: &mut Frame, device: Rc<RefCell<DeviceWrapper>>, upload_buffer: &mut DynamicUploadBuffer, image_pool: &mut TransientImagePool, settings: &ExampleSettings, imgui_ui: &mut Ui, back_buffer: AttachmentReference) -> Vec<PassType>;
+}
@@ -0,0 +1,178 @@
+use alloc::rc::Rc;
+use std::cell::{Cell, RefCell};
+use ash::vk;
+use glam::IVec2;
+use gpu_allocator::MemoryLocation;
+use imgui::Ui;
+use api_types::device::{DeviceResource, DeviceWrapper};
+use api_types::image::{ImageCreateInfo, ImageType};
+use api_types::upload_buffer::DynamicUploadBuffer;
+use context::external_swapchain::{ExternalImageDesc, ExternalPresenter, ExternalSwapchain};
+use context::transient_image_pool::TransientImagePool;
+use framegraph::attachment::AttachmentReference;
+use framegraph::frame::Frame;
+use framegraph::pass_type::PassType;
+use passes::{blit, clear};
+use profiling::enter_span;
+use crate::example::{Example, ExampleSettings};
+use crate::input::InputState;
+
+const IMAGE_COUNT: u32 = 3;
+const IMAGE_FORMAT: vk::Format = vk::Format::R8G8B8A8_UNORM;
+const IMAGE_EXTENT: vk::Extent2D = vk::Extent2D { width: 256, height: 256 };
+
+/// Stands in for an OpenXR runtime or compositor, handing out a ring of images it owns. Nothing
+/// reads the images once they're released, so the example copies each to the back buffer itself
+struct OffscreenPresenter {
+    images: Vec<DeviceResource>,
+    next_image: u32,
+    paused: bool,
+    frames_rendered: u64,
+    frames_skipped: u64
+}
+
+impl OffscreenPresenter {
+    fn new(device: Rc<RefCell<DeviceWrapper>>) -> Self {
+        let images = (0..IMAGE_COUNT).map(|index| {
+            let create_info = ImageCreateInfo::new(
+                vk::ImageCreateInfo::builder()
+                    .image_type(vk::ImageType::TYPE_2D)
+                    .format(IMAGE_FORMAT)
+                    .initial_layout(vk::ImageLayout::UNDEFINED)
+                    .extent(vk::Extent3D {
+                        width: IMAGE_EXTENT.width,
+                        height: IMAGE_EXTENT.height,
+                        depth: 1
+                    })
+                    .samples(vk::SampleCountFlags::TYPE_1)
+                    .usage(vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC)
+                    .mip_levels(1)
+                    .array_layers(1)
+                    .build(),
+                format!("offscreen_presenter_{}", index),
+                ImageType::Color);
+            DeviceWrapper::create_image(device.clone(), &create_info, MemoryLocation::GpuOnly)
+        }).collect();
+
+        OffscreenPresenter {
+            images,
+            next_image: 0,
+            paused: false,
+            frames_rendered: 0,
+            frames_skipped: 0
+        }
+    }
+}
+
+impl ExternalPresenter for OffscreenPresenter {
+    fn get_images(&self) -> Vec<ExternalImageDesc> {
+        self.images.iter().map(|image| {
+            let image = image.get_image();
+            ExternalImageDesc {
+                image: image.image,
+                format: image.format,
+                extent: IMAGE_EXTENT,
+                array_layers: 1,
+                aspect_flags: vk::ImageAspectFlags::COLOR
+            }
+        }).collect()
+    }
+
+    fn get_layout(&self) -> vk::ImageLayout {
+        vk::ImageLayout::TRANSFER_SRC_OPTIMAL
+    }
+
+    fn begin_frame(&mut self) -> bool {
+        !self.paused
+    }
+
+    fn acquire_image(&mut self) -> u32 {
+        let index = self.next_image;
+        self.next_image = (index + 1) % IMAGE_COUNT;
+        index
+    }
+
+    fn release_image(&mut self) {}
+
+    fn end_frame(&mut self, rendered: bool) {
+        if rendered {
+            self.frames_rendered += 1;
+        } else {
+            self.frames_skipped += 1;
+        }
+    }
+}
+
+/// Renders into an ExternalSwapchain instead of the window's swapchain, in a Frame alongside
+/// the host app's own passes
+pub struct ExternalPresenterExample {
+    swapchain: RefCell<ExternalSwapchain<OffscreenPresenter>>,
+    // the presenter's frame is ended once the host app has submitted the passes rendering it
+    frame_started: Cell<bool>,
+    elapsed: f32
+}
+
+impl ExternalPresenterExample {
+    pub fn new(device: Rc<RefCell<DeviceWrapper>>) -> Self {
+        let presenter = OffscreenPresenter::new(device.clone());
+        ExternalPresenterExample {
+            swapchain: RefCell::new(ExternalSwapchain::new(device, presenter, "external_swapchain")),
+            frame_started: Cell::new(false),
+            elapsed: 0.0
+        }
+    }
+}
+
+impl Example for ExternalPresenterExample {
+    fn get_name(&self) -> &'static str {
+        "External Presenter"
+    }
+
+    fn ui(&mut self, ui: &Ui) {
+        let presenter = self.swapchain.get_mut().get_presenter_mut();
+        ui.checkbox("Pause Presenter", &mut presenter.paused);
+        ui.text(format!("Frames rendered: {}", presenter.frames_rendered));
+        ui.text(format!("Frames skipped: {}", presenter.frames_skipped));
+    }
+
+    fn begin_frame(&mut self, _frame_index: u32, _fence: vk::Fence) {
+        if self.frame_started.replace(false) {
+            self.swapchain.get_mut().end_frame();
+        }
+    }
+
+    fn update(&mut self, _input: &InputState, _settings: &ExampleSettings, delta_time: f32) {
+        self.elapsed += delta_time;
+    }
+
+    fn execute(&self, frame: &mut Frame, _device: Rc<RefCell<DeviceWrapper>>, _upload_buffer: &mut DynamicUploadBuffer, _image_pool: &mut TransientImagePool, _settings: &ExampleSettings, _imgui_ui: &mut Ui, back_buffer: AttachmentReference) -> Vec<PassType> {
+        enter_span!(tracing::Level::TRACE, "Generating External Presenter Passes");
+
+        let frame_image = self.swapchain.borrow_mut().begin_frame();
+        self.frame_started.set(true);
+        let frame_image = match frame_image {
+            Some(frame_image) => frame_image,
+            None => return Vec::new()
+        };
+        frame.import_resource(frame_image.image.clone(), frame_image.current_state, frame_image.final_state);
+
+        let color = [
+            0.5 + 0.5 * self.elapsed.sin(),
+            0.5 + 0.5 * (self.elapsed + 2.1).sin(),
+            0.5 + 0.5 * (self.elapsed + 4.2).sin(),
+            1.0];
+        let back_buffer_extent = back_buffer.resource_image.borrow().get_image().extent;
+        let blit_extent = IVec2::new(
+            IMAGE_EXTENT.width.min(back_buffer_extent.width) as i32,
+            IMAGE_EXTENT.height.min(back_buffer_extent.height) as i32);
+
+        vec![
+            clear::clear_with_color(frame_image.image.clone(), vk::ImageAspectFlags::COLOR, color),
+            blit::generate_pass(
+                frame_image.image,
+                0,
+                back_buffer.resource_image.clone(),
+                0,
+                [IVec2::new(0, 0), blit_extent])]
+    }
+}
@@ -0,0 +1,310 @@
+//! Input gathered from winit events (and gamepads with the `gamepad` feature) between frames,
+//! queried by the active example through [`InputState`] instead of decoding events itself.
+
+use std::collections::HashSet;
+use winit::event::{DeviceEvent, ElementState, Event, MouseButton, MouseScrollDelta, WindowEvent};
+use winit::keyboard::{KeyCode, PhysicalKey};
+use util::camera_controller::CameraInput;
+
+// roughly how many pixels a scroll wheel line moves on platforms which report pixels
+const PIXELS_PER_SCROLL_LINE: f32 = 20.0;
+// stick deflection below this is treated as resting
+#[cfg(feature = "gamepad")]
+const GAMEPAD_DEADZONE: f32 = 0.15;
+// how many pixels of mouse movement a fully deflected look stick equals each second
+const GAMEPAD_LOOK_PIXELS_PER_SECOND: f32 = 600.0;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum GamepadButton {
+    South,
+    East,
+    North,
+    West,
+    LeftBumper,
+    RightBumper,
+    Select,
+    Start,
+    LeftStick,
+    RightStick,
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight
+}
+
+/// The first connected gamepad. Sticks range from -1 to 1 with up positive, triggers from 0 to 1
+#[derive(Clone, Debug, Default)]
+pub struct GamepadState {
+    pub left_stick: glm::Vec2,
+    pub right_stick: glm::Vec2,
+    pub left_trigger: f32,
+    pub right_trigger: f32,
+    held_buttons: HashSet<GamepadButton>
+}
+
+impl GamepadState {
+    pub fn is_button_down(&self, button: GamepadButton) -> bool {
+        self.held_buttons.contains(&button)
+    }
+}
+
+/// Everything an example can query about this frame's input
+#[derive(Clone, Debug, Default)]
+pub struct InputState {
+    held_keys: HashSet<KeyCode>,
+    pressed_keys: HashSet<KeyCode>,
+    held_mouse_buttons: HashSet<MouseButton>,
+    pressed_mouse_buttons: HashSet<MouseButton>,
+    cursor_position: Option<glm::Vec2>,
+    window_size: glm::Vec2,
+    mouse_delta: glm::Vec2,
+    scroll_lines: f32,
+    gamepad: Option<GamepadState>
+}
+
+impl InputState {
+    pub fn is_key_down(&self, key: KeyCode) -> bool {
+        self.held_keys.contains(&key)
+    }
+
+    /// Whether the key went down since the last frame
+    pub fn was_key_pressed(&self, key: KeyCode) -> bool {
+        self.pressed_keys.contains(&key)
+    }
+
+    pub fn is_mouse_button_down(&self, button: MouseButton) -> bool {
+        self.held_mouse_buttons.contains(&button)
+    }
+
+    /// Whether the button went down since the last frame
+    pub fn was_mouse_button_pressed(&self, button: MouseButton) -> bool {
+        self.pressed_mouse_buttons.contains(&button)
+    }
+
+    /// In physical pixels from the window's top left, None while the cursor is outside it
+    pub fn get_cursor_position(&self) -> Option<glm::Vec2> {
+        self.cursor_position
+    }
+
+    /// The cursor position as a fraction of the window's size, for finding the texel under it
+    /// in images which don't match the window, e.g. the scene below full resolution
+    pub fn get_cursor_uv(&self) -> Option<glm::Vec2> {
+        if self.window_size.x <= 0.0 || self.window_size.y <= 0.0 {
+            return None;
+        }
+        self.cursor_position.map(|position| position.component_div(&self.window_size))
+    }
+
+    /// Raw mouse movement since the last frame, which keeps going at the edge of the window
+    pub fn get_mouse_delta(&self) -> glm::Vec2 {
+        self.mouse_delta
+    }
+
+    /// Scroll wheel lines since the last frame, positive away from the user
+    pub fn get_scroll(&self) -> f32 {
+        self.scroll_lines
+    }
+
+    /// Always None without the `gamepad` feature
+    pub fn get_gamepad(&self) -> Option<&GamepadState> {
+        self.gamepad.as_ref()
+    }
+
+    /// The usual camera mapping: right drag or the right stick to look, WASD/QE or the left
+    /// stick and triggers to move, scroll to zoom and shift or the south button to boost
+    pub fn get_camera_input(&self, delta_time: f32) -> CameraInput {
+        let axis = |positive: KeyCode, negative: KeyCode| {
+            self.is_key_down(positive) as i32 as f32 - self.is_key_down(negative) as i32 as f32
+        };
+        let mut camera_input = CameraInput {
+            movement: glm::Vec3::new(
+                axis(KeyCode::KeyD, KeyCode::KeyA),
+                axis(KeyCode::KeyE, KeyCode::KeyQ),
+                axis(KeyCode::KeyW, KeyCode::KeyS)),
+            look_delta: self.mouse_delta,
+            rotating: self.is_mouse_button_down(MouseButton::Right),
+            zoom_delta: self.scroll_lines,
+            boost: self.is_key_down(KeyCode::ShiftLeft) || self.is_key_down(KeyCode::ShiftRight)
+        };
+
+        if let Some(gamepad) = &self.gamepad {
+            camera_input.movement += glm::Vec3::new(
+                gamepad.left_stick.x,
+                gamepad.right_trigger - gamepad.left_trigger,
+                gamepad.left_stick.y);
+            camera_input.movement = glm::clamp(&camera_input.movement, -1.0, 1.0);
+            if gamepad.right_stick != glm::Vec2::zeros() {
+                // the stick overrides the mouse rather than adding to it, since the mouse only
+                // counts while dragging. Stick up looks up, where the mouse moves down to
+                let look_delta = glm::Vec2::new(gamepad.right_stick.x, -gamepad.right_stick.y)
+                    * GAMEPAD_LOOK_PIXELS_PER_SECOND * delta_time;
+                camera_input.look_delta = look_delta;
+                camera_input.rotating = true;
+            }
+            camera_input.boost |= gamepad.is_button_down(GamepadButton::South);
+        }
+
+        camera_input
+    }
+}
+
+/// Feeds events into the [`InputState`] handed to examples each frame
+pub struct Input {
+    state: InputState,
+    #[cfg(feature = "gamepad")]
+    gilrs: Option<gilrs::Gilrs>
+}
+
+impl std::fmt::Debug for Input {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Input")
+            .field("state", &self.state)
+            .finish()
+    }
+}
+
+impl Input {
+    pub fn new() -> Self {
+        Input {
+            state: InputState::default(),
+            #[cfg(feature = "gamepad")]
+            gilrs: match gilrs::Gilrs::new() {
+                Ok(gilrs) => Some(gilrs),
+                Err(error) => {
+                    log::warn!(target: "input", "Gamepads are unavailable: {}", error);
+                    None
+                }
+            }
+        }
+    }
+
+    /// Input imgui wants is left to it, apart from releases so nothing stays held
+    pub fn handle_event(&mut self, event: &Event<()>, io: &imgui::Io) {
+        let state = &mut self.state;
+        match event {
+            Event::WindowEvent { event: WindowEvent::KeyboardInput { event: key_event, .. }, .. } => {
+                if let PhysicalKey::Code(key_code) = key_event.physical_key {
+                    match key_event.state {
+                        ElementState::Pressed if !io.want_capture_keyboard => {
+                            // held keys repeat their presses
+                            if state.held_keys.insert(key_code) {
+                                state.pressed_keys.insert(key_code);
+                            }
+                        },
+                        ElementState::Released => { state.held_keys.remove(&key_code); },
+                        _ => {}
+                    }
+                }
+            },
+            Event::WindowEvent { event: WindowEvent::MouseInput { state: button_state, button, .. }, .. } => {
+                match button_state {
+                    ElementState::Pressed if !io.want_capture_mouse => {
+                        state.held_mouse_buttons.insert(*button);
+                        state.pressed_mouse_buttons.insert(*button);
+                    },
+                    ElementState::Released => { state.held_mouse_buttons.remove(button); },
+                    _ => {}
+                }
+            },
+            Event::WindowEvent { event: WindowEvent::MouseWheel { delta, .. }, .. } if !io.want_capture_mouse => {
+                state.scroll_lines += match delta {
+                    MouseScrollDelta::LineDelta(_, y) => *y,
+                    MouseScrollDelta::PixelDelta(position) => position.y as f32 / PIXELS_PER_SCROLL_LINE
+                };
+            },
+            Event::WindowEvent { event: WindowEvent::CursorMoved { position, .. }, .. } => {
+                state.cursor_position = Some(glm::Vec2::new(position.x as f32, position.y as f32));
+            },
+            Event::WindowEvent { event: WindowEvent::Resized(size), .. } => {
+                state.window_size = glm::Vec2::new(size.width as f32, size.height as f32);
+            },
+            Event::WindowEvent { event: WindowEvent::CursorLeft { .. }, .. } => {
+                state.cursor_position = None;
+            },
+            Event::WindowEvent { event: WindowEvent::Focused(false), .. } => {
+                state.held_keys.clear();
+                state.held_mouse_buttons.clear();
+            },
+            Event::DeviceEvent { event: DeviceEvent::MouseMotion { delta }, .. } => {
+                state.mouse_delta += glm::Vec2::new(delta.0 as f32, delta.1 as f32);
+            },
+            _ => {}
+        }
+    }
+
+    /// The window's size in physical pixels until the next resize, since the window may not
+    /// report its initial size
+    pub fn set_window_size(&mut self, width: u32, height: u32) {
+        self.state.window_size = glm::Vec2::new(width as f32, height as f32);
+    }
+
+    /// Polls gamepads and returns the input since the last frame
+    pub fn begin_frame(&mut self) -> &InputState {
+        #[cfg(feature = "gamepad")]
+        if let Some(gilrs) = &mut self.gilrs {
+            // gilrs only updates gamepad state while its events are drained
+            while gilrs.next_event().is_some() {}
+            self.state.gamepad = gilrs.gamepads().next().map(|(_, gamepad)| read_gamepad(&gamepad));
+        }
+
+        &self.state
+    }
+
+    /// Clears what only applies to the frame just finished
+    pub fn end_frame(&mut self) {
+        self.state.pressed_keys.clear();
+        self.state.pressed_mouse_buttons.clear();
+        self.state.mouse_delta = glm::Vec2::zeros();
+        self.state.scroll_lines = 0.0;
+    }
+}
+
+impl Default for Input {
+    fn default() -> Self {
+        Input::new()
+    }
+}
+
+#[cfg(feature = "gamepad")]
+fn read_gamepad(gamepad: &gilrs::Gamepad) -> GamepadState {
+    use gilrs::{Axis, Button};
+
+    let stick = |x: Axis, y: Axis| {
+        let stick = glm::Vec2::new(gamepad.value(x), gamepad.value(y));
+        match glm::length(&stick) < GAMEPAD_DEADZONE {
+            true => glm::Vec2::zeros(),
+            false => stick
+        }
+    };
+    let trigger = |button: Button| {
+        gamepad.button_data(button).map_or(0.0, |data| data.value())
+    };
+
+    let held_buttons = [
+        (Button::South, GamepadButton::South),
+        (Button::East, GamepadButton::East),
+        (Button::North, GamepadButton::North),
+        (Button::West, GamepadButton::West),
+        (Button::LeftTrigger, GamepadButton::LeftBumper),
+        (Button::RightTrigger, GamepadButton::RightBumper),
+        (Button::Select, GamepadButton::Select),
+        (Button::Start, GamepadButton::Start),
+        (Button::LeftThumb, GamepadButton::LeftStick),
+        (Button::RightThumb, GamepadButton::RightStick),
+        (Button::DPadUp, GamepadButton::DPadUp),
+        (Button::DPadDown, GamepadButton::DPadDown),
+        (Button::DPadLeft, GamepadButton::DPadLeft),
+        (Button::DPadRight, GamepadButton::DPadRight)
+    ].into_iter()
+        .filter(|(button, _)| gamepad.is_pressed(*button))
+        .map(|(_, gamepad_button)| gamepad_button)
+        .collect();
+
+    GamepadState {
+        left_stick: stick(Axis::LeftStickX, Axis::LeftStickY),
+        right_stick: stick(Axis::RightStickX, Axis::RightStickY),
+        left_trigger: trigger(Button::LeftTrigger2),
+        right_trigger: trigger(Button::RightTrigger2),
+        held_buttons
+    }
+}
@@ -1,14 +1,22 @@
+mod auto_exposure_example;
 mod ubo_example;
 mod example;
+mod external_presenter_example;
 mod model_example;
+mod ping_pong_example;
+mod post_process_example;
+mod input;
 
 extern crate alloc;
 extern crate nalgebra_glm as glm;
 extern crate core;
 
 use core::fmt::{Debug, Formatter};
+use std::cell::RefCell;
+use std::rc::Rc;
 use std::ffi::CString;
 use std::mem::swap;
+use std::path::Path;
 use std::time::Instant;
 use ash::vk;
 
@@ -24,11 +32,18 @@ use imgui;
 use imgui::BackendFlags;
 use tracy_client::span_location;
 use winit::error::EventLoopError;
+use api_types::device::DeviceResource;
 use api_types::swapchain::SwapchainStatus;
+use api_types::upload_buffer::DynamicUploadBuffer;
 use context::render_context::RenderContext;
+use context::descriptor_pool_manager::DescriptorPoolConfig;
+use context::feature_negotiation::DeviceFeatureRequests;
+use context::render_settings::{RenderSettings, RenderSettingsWatcher};
 use context::vulkan_render_context::{VulkanFrameObjects, VulkanRenderContext};
 use framegraph::attachment::AttachmentReference;
+use framegraph::capture::CaptureRequest;
 use framegraph::frame::Frame;
+use framegraph::frame_constants::FrameConstants;
 use framegraph::frame_graph::FrameGraph;
 use framegraph::pass_type::PassType;
 use framegraph::pipeline::VulkanPipelineManager;
@@ -37,24 +52,57 @@ use framegraph::renderpass_manager::VulkanRenderpassManager;
 use framegraph::vulkan_frame_graph::VulkanFrameGraph;
 use passes::imgui_draw::ImguiRender;
 use passes::clear;
-use crate::example::Example;
+use passes::final_output::{FinalOutput, OutputEncoding, OutputSettings, Tonemap};
+use profiling::GpuQueue;
+use util::asset_loader::AssetLoader;
+use util::dynamic_resolution::DynamicResolution;
+use crate::auto_exposure_example::AutoExposureExample;
+use crate::example::{Example, ExampleSettings};
+use crate::external_presenter_example::ExternalPresenterExample;
+use crate::input::Input;
 use crate::model_example::ModelExample;
+use crate::ping_pong_example::PingPongExample;
+use crate::post_process_example::PostProcessExample;
 use crate::ubo_example::UboExample;
 
-const MAX_FRAMES_IN_FLIGHT: u32 = 2;
+const FRAMES_IN_FLIGHT: u32 = 2;
+const UPLOAD_REGION_SIZE: vk::DeviceSize = 4 * 1024 * 1024;
+const ASSET_LOADER_THREADS: usize = 2;
+// edits to this file are applied while the examples run
+const RENDER_SETTINGS_PATH: &str = "assets/render_settings.ron";
+
+// sample counts offered for examples which multisample
+const MSAA_SAMPLE_COUNTS: [vk::SampleCountFlags; 4] = [
+    vk::SampleCountFlags::TYPE_1,
+    vk::SampleCountFlags::TYPE_2,
+    vk::SampleCountFlags::TYPE_4,
+    vk::SampleCountFlags::TYPE_8];
 
 struct Examples {
     examples: Vec<Box<dyn Example>>,
+    // kept for every example, so switching away and back doesn't lose them
+    settings: Vec<ExampleSettings>,
     active_example_index: Option<usize>
 }
 
 impl Examples {
     pub fn new(examples: Vec<Box<dyn Example>>) -> Self {
+        let settings = examples.iter()
+            .map(|example| example.default_settings())
+            .collect();
         Examples {
             examples,
+            settings,
             active_example_index: None
         }
     }
+
+    /// Moves every example which multisamples to `samples`
+    pub fn set_sample_count(&mut self, samples: vk::SampleCountFlags) {
+        for msaa_samples in self.settings.iter_mut().filter_map(|settings| settings.msaa_samples.as_mut()) {
+            *msaa_samples = samples;
+        }
+    }
 }
 
 struct WindowedVulkanApp {
@@ -63,17 +111,30 @@ struct WindowedVulkanApp {
     imgui: imgui::Context,
 
     frame_index: u32,
+    // counts every frame rendered, unlike frame_index which cycles through the frames in flight
+    frame_count: u32,
+    start_time: Instant,
     render_semaphores: Vec<vk::Semaphore>,
     frame_fences: Vec<vk::Fence>,
     frames: Vec<Option<Box<Frame>>>,
+    upload_buffer: DynamicUploadBuffer,
 
     // examples: Vec<Box<dyn Example>>,
     examples: Examples,
+    asset_loader: AssetLoader,
+    input: Input,
 
     imgui_renderer: ImguiRender,
+    final_output: FinalOutput,
+    output_settings: OutputSettings,
+    // examples render here when the swapchain needs its output converted or the scene is
+    // rendered below the swapchain's resolution, recreated on resize
+    scene_target: Option<Rc<RefCell<DeviceResource>>>,
+    dynamic_resolution: DynamicResolution,
     frame_graph: VulkanFrameGraph,
 
     render_context: VulkanRenderContext,
+    settings_watcher: RenderSettingsWatcher,
 
     tracy: tracy_client::Client
 }
@@ -108,17 +169,32 @@ impl WindowedVulkanApp {
         let mut platform = WinitPlatform::init(&mut imgui);
         platform.attach_window(imgui.io_mut(), &window, HiDpiMode::Default);
 
-        let render_context = {
+        let mut render_context = {
             let c_title = CString::new(title).unwrap();
             let application_info = vk::ApplicationInfo::builder()
                 .application_name(&c_title)
                 .api_version(vk::API_VERSION_1_2);
 
-            VulkanRenderContext::new(
+            let settings_path = Path::new(RENDER_SETTINGS_PATH);
+            let settings = if settings_path.exists() {
+                RenderSettings::load(settings_path).unwrap_or_else(|error| {
+                    log::warn!("{}", error);
+                    RenderSettings::default()
+                })
+            } else {
+                RenderSettings::default()
+            };
+
+            VulkanRenderContext::init(
                 &application_info,
                 true,
-                Some(&window))
+                Some(&window),
+                DescriptorPoolConfig::default(),
+                settings,
+                DeviceFeatureRequests::default(),
+                FRAMES_IN_FLIGHT)
         };
+        let settings_watcher = RenderSettingsWatcher::new(Path::new(RENDER_SETTINGS_PATH));
 
         let frame_graph = VulkanFrameGraph::new(
             VulkanRenderpassManager::new(),
@@ -136,88 +212,78 @@ impl WindowedVulkanApp {
                 font_texture)
         };
 
-        let max_frames_in_flight = {
-            match render_context.get_swapchain() {
-                Some(swapchain) => {
-                    swapchain.get_images().len() as u32
-                }
-                None => {
-                    MAX_FRAMES_IN_FLIGHT
-                }
-            }
-        };
+        let final_output = FinalOutput::new(render_context.get_device().clone());
+
+        let frames_in_flight = render_context.get_frames_in_flight();
 
+        // owned by the render context's pool, which destroys them along with the context.
+        // Frame fences start as signaled so we don't wait the first time we execute that frame
         let mut frame_fences: Vec<vk::Fence> = Vec::new();
         let mut render_semaphores: Vec<vk::Semaphore> = Vec::new();
         {
-            // frame fences start as signaled so we don't wait the first time
-            // we execute that frame
-            let fence_create = vk::FenceCreateInfo::builder()
-                .flags(vk::FenceCreateFlags::SIGNALED)
-                .build();
-
-            let semaphore_create = vk::SemaphoreCreateInfo::builder()
-                .build();
-
-            unsafe {
-                for _ in 0..max_frames_in_flight {
-                    frame_fences.push(
-                        render_context.get_device().borrow().get().create_fence(
-                            &fence_create,
-                            None)
-                            .expect("Failed to create Frame fence")
-                    );
-
-                    render_semaphores.push(
-                        render_context.get_device().borrow().get().create_semaphore(
-                            &semaphore_create, None)
-                            .expect("Failed to create Render semaphore")
-                    );
-                }
+            let sync_object_pool = render_context.get_sync_object_pool();
+            for i in 0..frames_in_flight {
+                frame_fences.push(sync_object_pool.acquire_fence(true, &format!("frame_fence_{}", i)));
+                render_semaphores.push(sync_object_pool.acquire_semaphore(&format!("render_semaphore_{}", i)));
             }
         }
 
+        let mut asset_loader = AssetLoader::new(&render_context, ASSET_LOADER_THREADS);
         let examples: Vec<Box<dyn Example>> = vec![
             Box::new(UboExample::new(render_context.get_device().clone())),
-            Box::new(ModelExample::new(render_context.get_device().clone(), &render_context))
+            Box::new(ModelExample::new(render_context.get_device().clone(), &mut asset_loader, frames_in_flight)),
+            Box::new(PingPongExample::new()),
+            Box::new(AutoExposureExample::new()),
+            Box::new(PostProcessExample::new(render_context.get_device().clone())),
+            Box::new(ExternalPresenterExample::new(render_context.get_device().clone()))
         ];
+        let mut examples = Examples::new(examples);
+        examples.set_sample_count(render_context.get_sample_count());
 
         let mut frames: Vec<Option<Box<Frame>>> = Vec::new();
-        frames.resize_with(max_frames_in_flight as usize, Default::default);
+        frames.resize_with(frames_in_flight as usize, Default::default);
+
+        let upload_buffer = DynamicUploadBuffer::new(
+            render_context.get_device(),
+            UPLOAD_REGION_SIZE,
+            frames_in_flight,
+            "frame_upload_buffer");
+
+        let mut input = Input::new();
+        {
+            let window_size = window.inner_size();
+            input.set_window_size(window_size.width, window_size.height);
+        }
 
         WindowedVulkanApp {
             window,
             platform,
-            examples: Examples::new(examples),
+            examples,
+            asset_loader,
+            input,
             imgui,
             frame_graph,
             imgui_renderer,
+            final_output,
+            output_settings: OutputSettings::default(),
+            scene_target: None,
+            dynamic_resolution: DynamicResolution::default(),
             render_semaphores,
             frames,
+            upload_buffer,
             frame_fences,
             frame_index: 0,
+            frame_count: 0,
+            start_time: Instant::now(),
             render_context,
+            settings_watcher,
             tracy
         }
     }
 
     pub fn shutdown(&mut self) {
         println!("Shutting down");
-        unsafe {
-            let device = self.render_context.get_device();
-            device.borrow().get()
-                .device_wait_idle()
-                .expect("Failed to wait for GPU to be idle");
-
-            for semaphore in &self.render_semaphores {
-                device.borrow().get().destroy_semaphore(*semaphore, None);
-            }
-
-            for fence in &self.frame_fences {
-                device.borrow().get().destroy_fence(*fence, None);
-            }
-
-        }
+        self.render_context.wait_idle();
     }
 
     #[tracing::instrument]
@@ -239,15 +305,29 @@ impl WindowedVulkanApp {
         log::trace!(target: "frame", "Wait complete; cleaning up frame.");
         // clean up the completed frame
         self.frames[self.frame_index as usize] = None;
+        self.upload_buffer.begin_frame(self.frame_index, frame_fence);
+        for example in &mut self.examples.examples {
+            example.begin_frame(self.frame_index, frame_fence);
+        }
 
         self.render_context.start_frame(self.frame_index);
 
+        // the profiler has just read back the timings of the last frame to use this index
+        let gpu_frame_time = self.render_context.get_gpu_profiler().get_last_timings(GpuQueue::Graphics)
+            .map(|timings| timings.total)
+            .filter(|total| !total.is_zero());
+        if let Some(gpu_frame_time) = gpu_frame_time {
+            if self.dynamic_resolution.update(gpu_frame_time) {
+                self.render_context.set_resolution_scale(self.dynamic_resolution.get_scale());
+            }
+        }
+
         // get swapchain image for this frame
         let VulkanFrameObjects {
             graphics_command_buffer: command_buffer,
+            command_pool: _,
             swapchain_image,
             swapchain_semaphore,
-            descriptor_pool,
             frame_index: render_ctx_frame_index,
         } = self.render_context.get_next_frame_objects();
 
@@ -274,6 +354,21 @@ impl WindowedVulkanApp {
                 .expect("Failed to begin recording command buffer");
         }
 
+        // assets whose uploads finished are ready for this frame's passes
+        self.asset_loader.poll();
+
+        // let the active example react to input before it builds its passes
+        let delta_time = self.imgui.io().delta_time;
+        {
+            let input = self.input.begin_frame();
+            if let Some(index) = self.examples.active_example_index {
+                if let Some(active_example) = self.examples.examples.get_mut(index) {
+                    active_example.update(input, &self.examples.settings[index], delta_time);
+                }
+            }
+            self.input.end_frame();
+        }
+
         // update imgui UI
         let ui = self.imgui.new_frame();
         {
@@ -287,14 +382,182 @@ impl WindowedVulkanApp {
                         }
                     }
                 }
+                if let Some(display_menu) = ui.begin_menu("Display") {
+                    let vsync = self.render_context.get_present_mode() == Some(vk::PresentModeKHR::FIFO);
+                    if ui.menu_item_config("VSync").selected(vsync).build() {
+                        // prefer MAILBOX without vsync, since it doesn't tear
+                        let present_mode = if vsync {
+                            [vk::PresentModeKHR::MAILBOX, vk::PresentModeKHR::IMMEDIATE].into_iter()
+                                .find(|present_mode| self.render_context.is_present_mode_supported(*present_mode))
+                        } else {
+                            Some(vk::PresentModeKHR::FIFO)
+                        };
+                        // the swapchain is rebuilt with the new mode after this frame is presented
+                        match present_mode {
+                            Some(present_mode) => { self.render_context.set_present_mode(present_mode); },
+                            None => log::warn!("The surface doesn't support presenting without vsync")
+                        }
+                    }
+                    ui.separator();
+                    for (name, tonemap) in [("No Tonemap", Tonemap::None), ("Reinhard", Tonemap::Reinhard), ("ACES", Tonemap::Aces)] {
+                        if ui.menu_item_config(name).selected(self.output_settings.tonemap == tonemap).build() {
+                            self.output_settings.tonemap = tonemap;
+                        }
+                    }
+                    ui.slider("Exposure", 0.1, 8.0, &mut self.output_settings.exposure);
+                    ui.slider("Paper White (nits)", 80.0, 400.0, &mut self.output_settings.paper_white);
+                    ui.separator();
+                    let dynamic_resolution_enabled = self.dynamic_resolution.is_enabled();
+                    if ui.menu_item_config("Dynamic Resolution").selected(dynamic_resolution_enabled).build() {
+                        // scales down from the configured resolution scale, and returns to it when disabled
+                        if !dynamic_resolution_enabled {
+                            self.dynamic_resolution.settings.max_scale = self.render_context.get_settings().resolution_scale;
+                        }
+                        self.dynamic_resolution.set_enabled(!dynamic_resolution_enabled);
+                        self.render_context.set_resolution_scale(self.dynamic_resolution.get_scale());
+                    }
+                }
+                if let Some(debug_menu) = ui.begin_menu("Debug") {
+                    // captures apply to the frame started below
+                    if ui.menu_item("Capture Frame") {
+                        self.frame_graph.get_capture_mut().request(CaptureRequest::Frame);
+                    }
+                    if let Some(pass_menu) = ui.begin_menu("Capture Pass") {
+                        let pass_names: Vec<String> = self.frame_graph.get_last_frame_stats().passes.iter()
+                            .map(|pass| pass.name.to_string())
+                            .collect();
+                        for pass_name in pass_names {
+                            if ui.menu_item(&pass_name) {
+                                self.frame_graph.get_capture_mut().request(CaptureRequest::Pass(pass_name));
+                            }
+                        }
+                    }
+                    if !self.frame_graph.get_capture().is_renderdoc_available() {
+                        ui.text_disabled("RenderDoc not attached, captures are only labeled");
+                    }
+                }
+            }
+
+            if self.dynamic_resolution.is_enabled() {
+                let render_extent = self.render_context.get_render_extent().expect("No swapchain exists");
+                let gpu_timings = self.render_context.get_gpu_profiler().get_last_timings(GpuQueue::Graphics);
+                let dynamic_resolution = &mut self.dynamic_resolution;
+                ui.window("Dynamic Resolution")
+                    .size([300.0, 300.0], imgui::Condition::FirstUseEver)
+                    .build(|| {
+                        let settings = &mut dynamic_resolution.settings;
+                        let mut target_ms = settings.target_frame_time.as_secs_f32() * 1000.0;
+                        if ui.slider("Target (ms)", 4.0, 50.0, &mut target_ms) {
+                            settings.target_frame_time = std::time::Duration::from_secs_f32(target_ms / 1000.0);
+                        }
+                        ui.slider("Min Scale", 0.25, 1.0, &mut settings.min_scale);
+                        ui.slider("Headroom", 0.0, 0.5, &mut settings.headroom);
+                        ui.slider("Hysteresis (frames)", 1, 120, &mut settings.hysteresis_frames);
+                        ui.separator();
+                        ui.text(format!("Scale: {:.0}%", dynamic_resolution.get_scale() * 100.0));
+                        ui.text(format!("Render Resolution: {}x{}", render_extent.width, render_extent.height));
+                        if let Some(frame_time) = dynamic_resolution.get_smoothed_frame_time() {
+                            ui.text(format!("GPU Frame Time: {:.2}ms", frame_time.as_secs_f32() * 1000.0));
+                        }
+                        if let Some(gpu_timings) = gpu_timings {
+                            ui.separator();
+                            for span in &gpu_timings.spans {
+                                ui.text(format!("{}: {:.3}ms", span.name, span.duration.as_secs_f32() * 1000.0));
+                            }
+                        }
+                    });
+            }
+
+            if let Some(index) = self.examples.active_example_index {
+                let (wireframe_supported, supported_sample_counts) = {
+                    let device = self.render_context.get_device();
+                    let device = device.borrow();
+                    let limits = device.get_device_limits();
+                    (device.features().fill_mode_non_solid,
+                     limits.framebuffer_color_sample_counts & limits.framebuffer_depth_sample_counts)
+                };
+                let example = &mut self.examples.examples[index];
+                let settings = &mut self.examples.settings[index];
+                ui.window(example.get_name())
+                    .size([300.0, 300.0], imgui::Condition::FirstUseEver)
+                    .build(|| {
+                        if let Some(clear_color) = &mut settings.clear_color {
+                            ui.color_edit4("Clear Color", clear_color);
+                        }
+                        if let Some(camera_speed) = &mut settings.camera_speed {
+                            ui.slider("Camera Speed", 0.1, 10.0, camera_speed);
+                        }
+                        if let Some(msaa_samples) = &mut settings.msaa_samples {
+                            for sample_count in MSAA_SAMPLE_COUNTS {
+                                if supported_sample_counts.contains(sample_count) {
+                                    ui.radio_button(format!("MSAA x{}", sample_count.as_raw()), msaa_samples, sample_count);
+                                    ui.same_line();
+                                }
+                            }
+                            ui.new_line();
+                        }
+                        if let Some(wireframe) = &mut settings.wireframe {
+                            if wireframe_supported {
+                                ui.checkbox("Wireframe", wireframe);
+                            } else {
+                                ui.text_disabled("Wireframe is unsupported on this device");
+                            }
+                        }
+                        ui.separator();
+                        example.ui(ui);
+                    });
             }
         }
 
         // prepare framegraph
         log::trace!(target: "frame", "Creating new frame: {}", self.frame_index);
-        self.frames[self.frame_index as usize] = Some(self.frame_graph.start(self.render_context.get_device(), descriptor_pool));
+        self.frames[self.frame_index as usize] = Some(self.frame_graph.start());
         let current_frame = self.frames[self.frame_index as usize].as_mut().unwrap();
 
+        // scenes are rendered to the swapchain directly unless its format or the output settings need them converted
+        let output_encoding = {
+            let swapchain = self.render_context.get_swapchain().as_ref().expect("No swapchain exists");
+            OutputEncoding::for_swapchain(swapchain.get_format(), swapchain.get_color_space())
+        };
+        // below full scale the scene is also rendered to the scene target, and upscaled when
+        // it's resolved to the swapchain. Examples size their targets from the image they're
+        // given, so they follow the scale
+        let swapchain_extent = next_image.borrow().get_image().extent;
+        let scene_extent = self.render_context.get_render_extent().expect("No swapchain exists");
+        let full_scale = scene_extent.width == swapchain_extent.width && scene_extent.height == swapchain_extent.height;
+        let scene_image = if self.output_settings.is_passthrough(output_encoding) && full_scale {
+            self.scene_target = None;
+            next_image.clone()
+        } else {
+            let stale = self.scene_target.as_ref()
+                .map_or(true, |scene_target| {
+                    let extent = scene_target.borrow().get_image().extent;
+                    extent.width != scene_extent.width || extent.height != scene_extent.height
+                });
+            if stale {
+                self.scene_target = Some(FinalOutput::create_scene_target(
+                    self.render_context.get_device(),
+                    scene_extent,
+                    "scene_target"));
+            }
+            self.scene_target.clone().unwrap()
+        };
+
+        // bound to every pass declaring them, so examples don't each upload their own camera
+        {
+            let camera = self.examples.active_example_index
+                .and_then(|index| self.examples.examples.get(index))
+                .and_then(|active_example| active_example.get_camera())
+                .unwrap_or_default();
+            let constants = FrameConstants::new(
+                &camera,
+                scene_extent,
+                self.start_time.elapsed().as_secs_f32(),
+                delta_time,
+                self.frame_count);
+            current_frame.set_constants(&constants, &mut self.upload_buffer);
+        }
+
         {
             let _span = tracy_client::span!("Build Framegraph");
             {
@@ -307,47 +570,76 @@ impl WindowedVulkanApp {
             }
 
             {
-                let clear_node = clear::clear(next_image.clone(), vk::ImageAspectFlags::COLOR);
+                let clear_color = self.examples.active_example_index
+                    .and_then(|index| self.examples.settings[index].clear_color)
+                    .unwrap_or([0.0, 0.0, 0.0, 0.0]);
+                let clear_node = clear::clear_with_color(scene_image.clone(), vk::ImageAspectFlags::COLOR, clear_color);
                 current_frame.add_node(clear_node);
             }
 
             {
-                let rt_ref = AttachmentReference::new(
-                    next_image.clone(),
+                let scene_ref = AttachmentReference::new(
+                    scene_image.clone(),
                     vk::SampleCountFlags::TYPE_1);
 
                 if let Some(index) = self.examples.active_example_index {
                     if let Some(active_example) = self.examples.examples.get(index) {
                         let nodes = active_example.execute(
+                            current_frame,
                             self.render_context.get_device(),
+                            &mut self.upload_buffer,
+                            self.render_context.get_transient_image_pool(),
+                            &self.examples.settings[index],
                             ui,
-                            rt_ref.clone());
+                            scene_ref.clone());
                         for node in nodes {
                             current_frame.add_node(node);
                         }
                     }
                 }
 
+                let rt_ref = AttachmentReference::new(
+                    next_image.clone(),
+                    vk::SampleCountFlags::TYPE_1);
+
+                if !Rc::ptr_eq(&scene_image, &next_image) {
+                    let output_node = self.final_output.generate_pass(
+                        scene_image.clone(),
+                        rt_ref.clone(),
+                        output_encoding,
+                        &self.output_settings,
+                        &mut self.upload_buffer);
+                    current_frame.add_node(output_node);
+                }
+
                 let imgui_draw_data = self.imgui.render();
 
-                let imgui_nodes = self.imgui_renderer.generate_passes(
+                let imgui_node = self.imgui_renderer.generate_pass(
                     imgui_draw_data,
                     rt_ref.clone(),
-                    self.render_context.get_device());
+                    &mut self.upload_buffer);
 
-                for imgui_node in imgui_nodes {
+                if let Some(imgui_node) = imgui_node {
                     current_frame.add_node(imgui_node);
                 }
             }
         }
 
-        self.frame_graph.end(
+        let submits = self.frame_graph.end(
             current_frame,
             &mut self.render_context,
             &command_buffer);
+        {
+            let frame_stats = self.frame_graph.get_last_frame_stats();
+            tracy_client::plot!("transient allocations", frame_stats.transient_allocated as f64);
+            tracy_client::plot!("transient watermark", frame_stats.transient_watermark as f64);
+            tracy_client::plot!("node storage reused", frame_stats.node_storage.reused as f64);
+            tracy_client::plot!("node storage fresh", frame_stats.node_storage.fresh as f64);
+            tracy_client::plot!("barriers", frame_stats.barrier_count() as f64);
+            tracy_client::plot!("redundant barriers", frame_stats.redundant_barrier_count() as f64);
+        }
 
-        // end command buffer
-        // TODO: support multiple command buffers
+        // end command buffer, any further command lists were ended by the framegraph
         unsafe {
             self.render_context.get_device().borrow().get().end_command_buffer(command_buffer)
                 .expect("Failed to finish recording command buffer");
@@ -363,8 +655,8 @@ impl WindowedVulkanApp {
                     .expect("Failed to reset Frame Fence");
             }
 
-            self.render_context.submit_graphics(
-                &[command_buffer],
+            self.render_context.submit_graphics_batch(
+                &submits,
                 frame_fence,
                 &[swapchain_semaphore],
                 &[self.render_semaphores[self.frame_index as usize]]);
@@ -380,6 +672,18 @@ impl WindowedVulkanApp {
 
             self.render_context.end_frame();
 
+            if let Some(settings) = self.settings_watcher.poll() {
+                let sample_count = self.render_context.get_sample_count();
+                let changes = self.render_context.apply_settings(settings, Some(&self.window));
+                if changes.pipelines {
+                    self.frame_graph.invalidate_pipelines();
+                }
+                // multisampled targets come from the transient image pool, which creates
+                // them again at the new sample count
+                if self.render_context.get_sample_count() != sample_count {
+                    self.examples.set_sample_count(self.render_context.get_sample_count());
+                }
+            }
 
             if swapchain_status == SwapchainStatus::Suboptimal {
                 self.render_context.recreate_swapchain(&self.window);
@@ -387,17 +691,8 @@ impl WindowedVulkanApp {
         }
         self.tracy.frame_mark();
 
-        let max_frames_in_flight = {
-            match self.render_context.get_swapchain() {
-                Some(swapchain) => {
-                    swapchain.get_images().len() as u32
-                }
-                None => {
-                    MAX_FRAMES_IN_FLIGHT
-                }
-            }
-        };
-        self.frame_index = (self.frame_index + 1) % max_frames_in_flight;
+        self.frame_index = (self.frame_index + 1) % self.render_context.get_frames_in_flight();
+        self.frame_count = self.frame_count.wrapping_add(1);
 
     }
 }
@@ -430,6 +725,7 @@ fn run(mut app: WindowedVulkanApp, event_loop: EventLoop<()>) -> Result<(), Even
                 app.shutdown();
             },
             event => {
+                app.input.handle_event(&event, app.imgui.io());
                 app.platform.handle_event(app.imgui.io_mut(), &app.window, &event);
             }
         }
@@ -1,38 +1,50 @@
 use alloc::rc::Rc;
 use std::cell::RefCell;
 use std::collections::HashMap;
-use std::ffi::c_void;
 use std::ops::Mul;
 
 use ash::vk;
 use ash::vk::{Handle};
-use imgui::{Condition, Ui};
+use imgui::Ui;
 use gltf::{Semantic};
 use gltf::accessor::{DataType, Dimensions};
-use gpu_allocator::MemoryLocation;
 use framegraph::attachment::AttachmentReference;
+use framegraph::frame::Frame;
+use framegraph::frame_constants::FrameCamera;
 use framegraph::pass_type::PassType;
 use once_cell::sync::Lazy;
 use context::vulkan_render_context::VulkanRenderContext;
 use framegraph::graphics_pass_node::GraphicsPassNode;
 use framegraph::shader::Shader;
-use util::camera::Camera;
+use util::asset_loader::{AssetHandle, AssetLoader, UploadBatch};
+use util::camera::{self, Camera};
+use util::camera_controller::{CameraController, FlyController, OrbitController};
 use util::math::DecomposedMatrix;
+use util::transform_history::TransformHistory;
 use glm;
 use glm::Vec4;
 use gltf::camera::Projection;
-use gltf::image::Source;
 use gltf::json::accessor::{Type};
-use api_types::buffer::BufferCreateInfo;
-use api_types::device::{DeviceResource, DeviceWrapper, ResourceType};
-use api_types::image::{ImageCreateInfo, ImageType};
+use api_types::device::{DeviceResource, DeviceWrapper};
+use api_types::image::ImageType;
+use api_types::upload_buffer::DynamicUploadBuffer;
+use context::transient_image_pool::{TransientImageDesc, TransientImagePool};
 use context::render_context::RenderContext;
-use framegraph::binding::{BindingInfo, BindingType, BufferBindingInfo, ImageBindingInfo, ResourceBinding};
-use framegraph::pipeline::{BlendType, DepthStencilType, PipelineDescription, RasterizationType};
+use framegraph::binding::{SampledImage, ShaderInterface, UniformBuffer};
+use framegraph::uniform_layout::UniformBlock;
+use framegraph::pipeline::{BlendType, DepthStencilType, PipelineDescription, RasterizationState};
 use framegraph::shader;
 use profiling::{enter_gpu_span, enter_span};
+use passes::blit;
 use passes::clear;
-use crate::example::Example;
+use passes::debug_lines::{DebugLineRender, DebugLines};
+use passes::editor::{Gizmo, GizmoHandle, GizmoMode, InfiniteGrid};
+use passes::picking::{IdPicker, ObjectIdRender, PickableMesh, NO_PICK_ID, PICK_ID_FORMAT};
+use passes::ssao::{AmbientOcclusion, NORMAL_FORMAT};
+use passes::taa::{TemporalAntiAliasing, VELOCITY_FORMATS};
+use crate::example::{Example, ExampleSettings};
+use crate::input::InputState;
+use winit::event::MouseButton;
 
 #[derive(Default)]
 #[repr(C)]
@@ -61,10 +73,26 @@ pub struct GltfModel {
     images: Vec<gltf::image::Data>
 }
 
-struct MVP {
+// the camera comes from the frame's constants, so only the mesh's own transforms are uploaded
+#[repr(C)]
+#[derive(UniformBlock)]
+struct ModelParams {
     model: glm::TMat4<f32>,
-    view: glm::TMat4<f32>,
-    proj: glm::TMat4<f32>
+    previous_model_view_proj: glm::TMat4<f32>,
+    // offset of this frame's clip space positions in NDC, kept out of proj so motion vectors
+    // don't include the change in jitter
+    jitter: [f32; 2]
+}
+
+/// Descriptors of model.vert and model.frag, apart from the per-frame set's FrameConstants.
+/// Never constructed; only its slots are used
+#[derive(ShaderInterface)]
+#[allow(dead_code)]
+struct ModelInterface {
+    #[binding(set = 0, slot = 0, stage = VERTEX_SHADER)]
+    model: UniformBuffer,
+    #[binding(set = 0, slot = 1, stage = FRAGMENT_SHADER)]
+    albedo: SampledImage
 }
 
 static ATTRIBUTE_LOOKUP: Lazy<HashMap<gltf::mesh::Semantic, u32>> = Lazy::new(|| HashMap::from([
@@ -80,9 +108,12 @@ const VERTEX_BINDING:  vk::VertexInputBindingDescription = vk::VertexInputBindin
 };
 
 pub struct RenderMesh {
-    // TODO: add primitive topology (also need to support this in pipeline.rs)
+    topology: vk::PrimitiveTopology,
+    /// Local space min and max of the mesh's positions
+    bounds: [[f32; 3]; 2],
     vertex_buffer: Rc<RefCell<DeviceResource>>,
     index_buffer: Option<Rc<RefCell<DeviceResource>>>,
+    index_type: vk::IndexType,
     num_indices: usize,
     vertex_binding: vk::VertexInputBindingDescription,
     vertex_attributes: [vk::VertexInputAttributeDescription; 3],
@@ -295,12 +326,49 @@ fn get_glm_format(data_type: DataType, dimensions: Dimensions, data_pointer: *co
     }
 }
 
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum CameraMode {
+    Orbit,
+    Fly
+}
+
 pub struct ModelExample {
     vertex_shader: Rc<RefCell<Shader>>,
     fragment_shader: Rc<RefCell<Shader>>,
+    // model.frag, also writing motion vectors and normals to a second and third render target
+    gbuffer_fragment_shader: Rc<RefCell<Shader>>,
     camera: Camera,
-    duck_model: GltfModel,
-    render_meshes: Vec<RenderMesh>
+    model: AssetHandle<LoadedModel>,
+    // whether the camera has been moved to the model since it finished loading
+    scene_framed: bool,
+    depth_format: vk::Format,
+    wireframe_supported: bool,
+    debug_lines: DebugLineRender,
+    show_bounds: bool,
+    // resolves the scene with TAA, which consumes the motion vectors
+    temporal_aa: bool,
+    taa: RefCell<Option<TemporalAntiAliasing>>,
+    ambient_occlusion: bool,
+    ssao: AmbientOcclusion,
+    // render with the camera's projection converted to reverse-Z
+    reverse_z: bool,
+    grid: InfiniteGrid,
+    show_grid: bool,
+    // drawn at the orbit target
+    gizmo: Gizmo,
+    show_gizmo: bool,
+    // meshes and gizmo handles are drawn into a pick target, and the id under the cursor read
+    // back a few frames later
+    object_ids: ObjectIdRender,
+    picker: RefCell<IdPicker>,
+    // where to pick this frame, as a fraction of the window
+    pick_position: Option<glm::Vec2>,
+    // the index of the mesh last clicked on
+    selected_mesh: Option<usize>,
+    transform_history: RefCell<TransformHistory>,
+    orbit: OrbitController,
+    fly: FlyController,
+    camera_mode: CameraMode
 }
 
 impl Example for ModelExample {
@@ -308,109 +376,268 @@ impl Example for ModelExample {
         "Model Render"
     }
 
-    fn execute(&self, device: Rc<RefCell<DeviceWrapper>>, imgui_ui: &mut Ui, back_buffer: AttachmentReference) -> Vec<PassType> {
-        enter_span!(tracing::Level::TRACE, "Generating Model Pass");
+    fn default_settings(&self) -> ExampleSettings {
+        ExampleSettings {
+            clear_color: Some([0.0, 0.0, 0.0, 1.0]),
+            camera_speed: Some(1.0),
+            wireframe: Some(false)
+        }
+    }
 
-        // build UI
-        imgui_ui.window("glTF Model")
-            .size([300.0, 300.0], Condition::Once)
-            .build(|| {
+    fn ui(&mut self, ui: &Ui) {
+        if let Some(error) = self.model.get_error() {
+            ui.text_colored([1.0, 0.3, 0.3, 1.0], format!("Failed to load {}: {}", self.model.get_name(), error));
+        } else if !self.model.is_ready() {
+            ui.text_disabled(format!("Loading {}...", self.model.get_name()));
+        }
 
-            });
+        ui.checkbox("Show Bounds", &mut self.show_bounds);
+        ui.checkbox("Temporal AA", &mut self.temporal_aa);
+        ui.checkbox("Ambient Occlusion", &mut self.ambient_occlusion);
+        if self.ambient_occlusion {
+            ui.slider("AO Radius", 0.05, 2.0, &mut self.ssao.settings.radius);
+            ui.slider("AO Intensity", 0.0, 2.0, &mut self.ssao.settings.intensity);
+        }
+        if ui.checkbox("Reverse Z", &mut self.reverse_z) {
+            self.debug_lines.depth_test = match self.reverse_z {
+                true => DepthStencilType::REVERSE_Z,
+                false => DepthStencilType::Enable
+            };
+            self.grid.depth_test = self.debug_lines.depth_test;
+            self.object_ids.depth_test = self.debug_lines.depth_test;
+        }
+        ui.checkbox("Show Grid", &mut self.show_grid);
+        ui.checkbox("Show Gizmo", &mut self.show_gizmo);
+        if self.show_gizmo {
+            ui.radio_button("Translate", &mut self.gizmo.mode, GizmoMode::Translate);
+            ui.same_line();
+            ui.radio_button("Rotate", &mut self.gizmo.mode, GizmoMode::Rotate);
+        }
+        match self.selected_mesh {
+            Some(mesh_index) => ui.text(format!("Selected mesh {}", mesh_index)),
+            None => ui.text_disabled("Click a mesh to select it")
+        }
+
+        let mut camera_mode = self.camera_mode;
+        ui.radio_button("Orbit", &mut camera_mode, CameraMode::Orbit);
+        ui.same_line();
+        ui.radio_button("Fly", &mut camera_mode, CameraMode::Fly);
+        if camera_mode != self.camera_mode {
+            // the newly chosen controller picks up from wherever the camera is
+            let eye = glm::Vec3::new(self.camera.view[(0, 3)], self.camera.view[(1, 3)], self.camera.view[(2, 3)]);
+            match camera_mode {
+                CameraMode::Orbit => {
+                    self.orbit = OrbitController::looking_at(&eye, &self.orbit.target);
+                },
+                CameraMode::Fly => {
+                    self.fly = FlyController::from_transform(&self.camera.view, self.fly.move_speed);
+                }
+            }
+            self.camera_mode = camera_mode;
+        }
+        ui.text_wrapped("Right drag to look, WASD/QE to move, scroll to zoom, shift to move faster");
+    }
+
+    fn update(&mut self, input: &InputState, settings: &ExampleSettings, delta_time: f32) {
+        if !self.scene_framed {
+            let model = self.model.clone();
+            if let Some(model) = model.get() {
+                self.frame_scene(&model);
+                self.scene_framed = true;
+            }
+        }
+
+        // the id read back is from a few frames ago, which is close enough to the cursor
+        let picked_id = self.picker.get_mut().get_picked_id();
+        self.gizmo.highlight = GizmoHandle::from_pick_id(picked_id);
+        if input.was_mouse_button_pressed(MouseButton::Left) && self.gizmo.highlight.is_none() {
+            self.selected_mesh = mesh_index_from_pick_id(picked_id);
+        }
+        self.pick_position = input.get_cursor_uv();
+
+        let mut camera_input = input.get_camera_input(delta_time);
+        camera_input.movement *= settings.camera_speed.unwrap_or(1.0);
+        match self.camera_mode {
+            CameraMode::Orbit => {
+                self.orbit.update(&camera_input, delta_time);
+                self.orbit.apply(&mut self.camera);
+            },
+            CameraMode::Fly => {
+                self.fly.update(&camera_input, delta_time);
+                self.fly.apply(&mut self.camera);
+            }
+        }
+    }
+
+    fn begin_frame(&mut self, frame_index: u32, fence: vk::Fence) {
+        self.picker.get_mut().begin_frame(frame_index, fence);
+    }
+
+    fn get_camera(&self) -> Option<FrameCamera> {
+        let projection = match self.reverse_z {
+            true => camera::to_reverse_z(&self.camera.projection),
+            false => self.camera.projection
+        };
+        let view = self.camera.get_view();
+        let view_projection = projection * view;
+        let position = glm::inverse(&view).column(3).xyz();
+        Some(FrameCamera {
+            view: view.into(),
+            projection: projection.into(),
+            view_projection: view_projection.into(),
+            inverse_view_projection: glm::inverse(&view_projection).into(),
+            position: position.into()
+        })
+    }
+
+    fn execute(&self, frame: &mut Frame, device: Rc<RefCell<DeviceWrapper>>, upload_buffer: &mut DynamicUploadBuffer, image_pool: &mut TransientImagePool, settings: &ExampleSettings, _imgui_ui: &mut Ui, back_buffer: AttachmentReference) -> Vec<PassType> {
+        enter_span!(tracing::Level::TRACE, "Generating Model Pass");
+
+        let model = match self.model.get() {
+            Some(model) => model,
+            None => return Vec::new()
+        };
+        let wireframe = self.wireframe_supported && settings.wireframe == Some(true);
 
         let mut passes: Vec<PassType> = Vec::new();
 
+        let (projection, clear_depth) = match self.reverse_z {
+            true => (camera::to_reverse_z(&self.camera.projection), 0.0),
+            false => (self.camera.projection, 1.0)
+        };
+
         let depth_attachment = {
             let depth_image = {
                 let rt_extent = back_buffer.resource_image.borrow().get_image().extent.clone();
-                let depth_create = vk::ImageCreateInfo::builder()
-                    .format(vk::Format::D32_SFLOAT)
-                    .image_type(vk::ImageType::TYPE_2D)
-                    .sharing_mode(vk::SharingMode::EXCLUSIVE)
-                    // .initial_layout(vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL)
-                    .initial_layout(vk::ImageLayout::UNDEFINED)
-                    .samples(vk::SampleCountFlags::TYPE_1)
+                let depth_desc = TransientImageDesc {
+                    extent: rt_extent,
+                    format: self.depth_format,
                     // transfer_dst required for this to be clearable via vkCmdClearDepthStencilImage
                     // https://vulkan.lunarg.com/doc/view/1.3.290.0/windows/1.3-extensions/vkspec.html#VUID-vkCmdClearDepthStencilImage-pRanges-02660
-                    .usage(vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_DST)
-                    .extent(rt_extent)
-                    .mip_levels(1)
-                    .array_layers(1)
-                    .build();
-
-                let image_create = ImageCreateInfo::new(
-                    depth_create,
-                    "model_example_depth".to_string(),
-                    ImageType::Depth
-                );
-
-                DeviceWrapper::create_image(
-                    device.clone(),
-                    &image_create,
-                    MemoryLocation::GpuOnly
-                )
+                    // sampled by the SSAO pass
+                    usage: match self.ambient_occlusion {
+                        true => vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED,
+                        false => vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_DST
+                    },
+                    samples: vk::SampleCountFlags::TYPE_1,
+                    image_type: ImageType::Depth
+                };
+
+                image_pool.request_image(&depth_desc, "model_example_depth")
             };
 
             AttachmentReference::new(
-                Rc::new(RefCell::new(depth_image)),
+                depth_image,
                 vk::SampleCountFlags::TYPE_1
-            )
+            ).transient()
         };
 
         // add depth clear pass
-        passes.push(clear::clear(
+        passes.push(clear::clear_depth(
             depth_attachment.resource_image.clone(),
-            vk::ImageAspectFlags::DEPTH));
-
-        for render_mesh in &self.render_meshes {
-            // create UBO for MVP
-            let mvp_buffer = {
-                let create_info = BufferCreateInfo::new(
-                    vk::BufferCreateInfo::builder()
-                        .size(std::mem::size_of::<MVP>() as vk::DeviceSize)
-                        .usage(vk::BufferUsageFlags::UNIFORM_BUFFER)
-                        .build(),
-                    "MVP_buffer".to_string()
-                );
-                let buffer = DeviceWrapper::create_buffer(
-                    device.clone(),
-                    &create_info,
-                    MemoryLocation::CpuToGpu
-                );
-
-                device.borrow().update_buffer(&buffer, |mapped_memory: *mut c_void, _size: u64| {
-                    let mvp = MVP {
-                        model: render_mesh.transform.clone(),
-                        view: self.camera.get_view(),
-                        proj: self.camera.projection.clone(),
-                    };
-
-                    unsafe {
-                        core::ptr::copy_nonoverlapping(
-                            &mvp,
-                            mapped_memory as *mut MVP,
-                            1
-                        );
-                    }
+            clear_depth));
+
+        let extent = back_buffer.resource_image.borrow().get_image().extent;
+        let target_usage = vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST;
+        let request_target = |image_pool: &mut TransientImagePool, format: vk::Format, name: &str| {
+            let desc = TransientImageDesc {
+                extent,
+                format,
+                usage: target_usage,
+                samples: vk::SampleCountFlags::TYPE_1,
+                image_type: ImageType::Color
+            };
+
+            image_pool.request_image(&desc, name)
+        };
+
+        // TAA resolves the scene into its history, so the scene is drawn into an image it can
+        // sample and the resolved image is copied to the back buffer afterwards
+        let mut taa_ref = self.taa.borrow_mut();
+        let taa = match self.temporal_aa {
+            true => {
+                let recreate = taa_ref.as_ref().map_or(true, |taa| {
+                    let taa_extent = taa.get_extent();
+                    taa_extent.width != extent.width || taa_extent.height != extent.height
                 });
+                if recreate {
+                    *taa_ref = Some(TemporalAntiAliasing::new(
+                        device.clone(),
+                        vk::Extent2D { width: extent.width, height: extent.height },
+                        "model_example_taa"));
+                }
+                taa_ref.as_mut()
+            },
+            false => None
+        };
+        let scene_target = match &taa {
+            Some(taa) => {
+                let color_image = request_target(image_pool, taa.get_history_format(), "model_example_color");
+                passes.push(clear::clear_with_color(
+                    color_image.clone(),
+                    vk::ImageAspectFlags::COLOR,
+                    settings.clear_color.unwrap_or([0.0, 0.0, 0.0, 1.0])));
+                AttachmentReference::new(color_image, vk::SampleCountFlags::TYPE_1).transient()
+            },
+            None => back_buffer.clone()
+        };
+        let jitter = match &taa {
+            Some(taa) => {
+                let (x, y) = taa.get_jitter();
+                [2.0 * x / extent.width as f32, 2.0 * y / extent.height as f32]
+            },
+            None => [0.0, 0.0]
+        };
 
-                Rc::new(RefCell::new(buffer))
+        // surfaces' motion since the previous frame and their normals, for TAA or SSAO to
+        // consume. Both are written by the same shader, so neither is optional once the other
+        // is needed
+        let gbuffer_attachments = if taa.is_some() || self.ambient_occlusion {
+            // anything not drawn this frame hasn't moved
+            let velocity_attachment = match &taa {
+                Some(taa) => {
+                    passes.push(taa.clear_velocity_pass());
+                    taa.velocity_attachment()
+                },
+                None => {
+                    let velocity_format = device.borrow().formats().require(
+                        VELOCITY_FORMATS,
+                        target_usage,
+                        vk::FormatFeatureFlags::empty(),
+                        "model_example_velocity");
+                    let velocity_image = request_target(image_pool, velocity_format, "model_example_velocity");
+                    passes.push(clear::clear(velocity_image.clone(), vk::ImageAspectFlags::COLOR));
+                    AttachmentReference::new(velocity_image, vk::SampleCountFlags::TYPE_1).transient()
+                }
             };
+            let normal_image = request_target(image_pool, NORMAL_FORMAT, "model_example_normals");
+            passes.push(clear::clear(normal_image.clone(), vk::ImageAspectFlags::COLOR));
+            Some([
+                velocity_attachment,
+                AttachmentReference::new(normal_image, vk::SampleCountFlags::TYPE_1).transient()])
+        } else {
+            None
+        };
 
-            let mvp_binding = ResourceBinding {
-                resource: mvp_buffer.clone(),
-                binding_info: BindingInfo {
-                    binding_type: BindingType::Buffer(BufferBindingInfo{
-                        offset: 0,
-                        range: std::mem::size_of::<MVP>() as vk::DeviceSize,
-                    }),
-                    set: 0,
-                    slot: 0,
-                    stage: vk::PipelineStageFlags::VERTEX_SHADER,
-                    access: vk::AccessFlags::SHADER_READ,
-                }
+        let mut transform_history = self.transform_history.borrow_mut();
+        for (mesh_index, render_mesh) in model.meshes.iter().enumerate() {
+            // stream the mesh's transforms into this frame's upload region
+            let params_offset = {
+                let model_view_proj = projection * self.camera.get_view() * render_mesh.transform;
+                let params = ModelParams {
+                    model: render_mesh.transform.clone(),
+                    // kept up to date while motion vectors are off, so turning them on doesn't
+                    // start from a stale transform
+                    previous_model_view_proj: transform_history.record(mesh_index as u64, &model_view_proj),
+                    jitter
+                };
+
+                let alignment = upload_buffer.get_uniform_alignment();
+                upload_buffer.push(std::slice::from_ref(&params), alignment)
             };
 
+            let params_binding = ModelInterface::MODEL.bind_uniform::<ModelParams>(upload_buffer.get_buffer(), params_offset);
+
             let dynamic_states = vec!(vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR);
             let vertex_input = vk::PipelineVertexInputStateCreateInfo::builder()
                 .vertex_binding_descriptions(std::slice::from_ref(&render_mesh.vertex_binding))
@@ -420,12 +647,22 @@ impl Example for ModelExample {
             let pipeline_description = PipelineDescription::new(
                 vertex_input,
                 dynamic_states,
-                RasterizationType::Standard,
-                DepthStencilType::Enable,
+                match wireframe {
+                    true => RasterizationState::wireframe(),
+                    false => RasterizationState::default()
+                },
+                match self.reverse_z {
+                    true => DepthStencilType::REVERSE_Z,
+                    false => DepthStencilType::Enable
+                },
                 BlendType::None,
                 "gltf-model-draw",
                 self.vertex_shader.clone(),
-                self.fragment_shader.clone());
+                match gbuffer_attachments.is_some() {
+                    true => self.gbuffer_fragment_shader.clone(),
+                    false => self.fragment_shader.clone()
+                })
+                .topology(render_mesh.topology, false);
 
             let (viewport, scissor) = {
                 let extent = back_buffer.resource_image.borrow().get_image().extent;
@@ -448,30 +685,22 @@ impl Example for ModelExample {
                 (v, s)
             };
 
-            let albedo_binding = ResourceBinding {
-                resource: render_mesh.albedo_tex.as_ref().unwrap().clone(),
-                binding_info: BindingInfo {
-                    binding_type: BindingType::Image(ImageBindingInfo {
-                        layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL }),
-                    set: 0,
-                    slot: 1,
-                    stage: vk::PipelineStageFlags::FRAGMENT_SHADER,
-                    access: vk::AccessFlags::SHADER_READ,
-                },
-            };
+            let albedo_binding = ModelInterface::ALBEDO.bind(render_mesh.albedo_tex.as_ref().unwrap().clone());
 
             if let Some(ibo_ref) = &render_mesh.index_buffer {
-                let ibo = ibo_ref.clone();
-                let vbo = render_mesh.vertex_buffer.clone();
                 let idx_length = render_mesh.num_indices;
-                let passnode = GraphicsPassNode::builder("model_render".to_string())
+                let mut passnode = GraphicsPassNode::builder("model_render".to_string())
                     .pipeline_description(pipeline_description)
-                    .render_target(back_buffer.clone())
+                    .render_target(scene_target.clone());
+                for gbuffer_attachment in gbuffer_attachments.iter().flatten() {
+                    passnode = passnode.render_target(gbuffer_attachment.clone());
+                }
+                let passnode = passnode
                     .depth_target(depth_attachment.clone())
-                    .read(mvp_binding.clone())
+                    .read(params_binding.clone())
                     .read(albedo_binding)
-                    .tag(render_mesh.vertex_buffer.clone())
-                    .tag(ibo.clone())
+                    .vertex_buffer(render_mesh.vertex_buffer.clone(), 0)
+                    .index_buffer(ibo_ref.clone(), 0, render_mesh.index_type)
                     .viewport(viewport)
                     .scissor(scissor)
                     .fill_commands(Box::new(
@@ -481,38 +710,10 @@ impl Example for ModelExample {
                             enter_span!(tracing::Level::TRACE, "Draw RenderMesh");
                             let device = render_ctx.get_device();
                             let borrowed_device = device.borrow();
-                            enter_gpu_span!("RenderMesh GPU", "examples", borrowed_device.get(), command_buffer, vk::PipelineStageFlags::ALL_GRAPHICS);
+                            enter_gpu_span!(render_ctx.get_gpu_profiler(), "RenderMesh GPU", "examples", borrowed_device.get(), command_buffer, vk::PipelineStageFlags::ALL_GRAPHICS);
 
                             unsafe {
                                 enter_span!(tracing::Level::TRACE, "Model Draw");
-                                // set vertex buffer
-                                {
-                                    if let ResourceType::Buffer(vb) = vbo.borrow().resource_type.as_ref().unwrap() {
-                                        render_ctx.get_device().borrow().get().cmd_bind_vertex_buffers(
-                                            *command_buffer,
-                                            0,
-                                            &[vb.buffer],
-                                            &[0 as vk::DeviceSize]
-                                        );
-                                    } else {
-                                        panic!("Invalid vertex buffer for gltf draw");
-                                    }
-                                }
-
-                                // set index buffer
-                                {
-                                    if let ResourceType::Buffer(ib) = ibo.borrow().resource_type.as_ref().unwrap() {
-                                        render_ctx.get_device().borrow().get().cmd_bind_index_buffer(
-                                            *command_buffer,
-                                            ib.buffer,
-                                            0 as vk::DeviceSize,
-                                            vk::IndexType::UINT16
-                                        );
-                                    } else {
-                                        panic!("Invalid index buffer for gltf draw");
-                                    }
-                                }
-
                                 render_ctx.get_device().borrow().get().cmd_draw_indexed(
                                     *command_buffer,
                                     idx_length as u32,
@@ -530,11 +731,174 @@ impl Example for ModelExample {
                 passes.push(PassType::Graphics(passnode));
             }
         }
+        transform_history.end_frame();
+
+        if self.ambient_occlusion {
+            let [_, normal_attachment] = gbuffer_attachments.as_ref().expect("SSAO requires the normal target");
+            let (ao_passes, ao) = self.ssao.generate_passes(
+                device.clone(),
+                depth_attachment.resource_image.clone(),
+                normal_attachment.resource_image.clone(),
+                &glm_to_glam(&projection),
+                true,
+                clear_depth,
+                image_pool,
+                upload_buffer);
+            passes.extend(ao_passes);
+            passes.push(self.ssao.generate_apply_pass(ao, scene_target.clone()));
+        }
+
+        if let Some(taa) = taa {
+            let (taa_pass, resolved) = taa.generate_pass(frame, scene_target.resource_image.clone(), upload_buffer);
+            passes.push(taa_pass);
+            passes.push(blit::generate_pass(
+                resolved,
+                0,
+                back_buffer.resource_image.clone(),
+                0,
+                [glam::IVec2::new(0, 0), glam::IVec2::new(extent.width as i32, extent.height as i32)]));
+        }
+
+        // matches the Y flip of the model passes' viewport
+        let overlay_viewport = {
+            let extent = back_buffer.resource_image.borrow().get_image().extent;
+            vk::Viewport::builder()
+                .x(0.0)
+                .y(extent.height as f32)
+                .width(extent.width as f32)
+                .height(-(extent.height as f32))
+                .min_depth(0.0)
+                .max_depth(1.0)
+                .build()
+        };
+        let view_projection = glm_to_glam(&projection) * glm_to_glam(&self.camera.get_view());
+        let camera_position = glam::Vec3::new(self.camera.view[(0, 3)], self.camera.view[(1, 3)], self.camera.view[(2, 3)]);
+
+        // the texel under the cursor, which the meshes and gizmo are drawn into a pick target for
+        let pick_texel = self.pick_position
+            .filter(|uv| (0.0..1.0).contains(&uv.x) && (0.0..1.0).contains(&uv.y))
+            .map(|uv| ((uv.x * extent.width as f32) as u32, (uv.y * extent.height as f32) as u32));
+        let pick_target = pick_texel.map(|_| {
+            let desc = TransientImageDesc {
+                extent,
+                format: PICK_ID_FORMAT,
+                // cleared with a transfer and copied from by the picker
+                usage: vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC | vk::ImageUsageFlags::TRANSFER_DST,
+                samples: vk::SampleCountFlags::TYPE_1,
+                image_type: ImageType::Color
+            };
+            let pick_image = image_pool.request_image(&desc, "model_example_pick_ids");
+            passes.push(clear::clear_ids(pick_image.clone(), NO_PICK_ID));
+            AttachmentReference::new(pick_image, vk::SampleCountFlags::TYPE_1).transient()
+        });
+        if let Some(pick_target) = &pick_target {
+            // the scene's depth was drawn jittered, so the ids are too to pass the depth test
+            let jittered_view_projection = glam::Mat4::from_translation(glam::Vec3::new(jitter[0], jitter[1], 0.0)) * view_projection;
+            let meshes: Vec<PickableMesh> = model.meshes.iter().enumerate()
+                .filter_map(|(mesh_index, render_mesh)| {
+                    // only indexed meshes are drawn
+                    let index_buffer = render_mesh.index_buffer.as_ref()?;
+                    Some(PickableMesh {
+                        vertex_buffer: render_mesh.vertex_buffer.clone(),
+                        vertex_binding: &render_mesh.vertex_binding,
+                        vertex_attributes: &render_mesh.vertex_attributes,
+                        index_buffer: Some((index_buffer.clone(), render_mesh.index_type)),
+                        topology: render_mesh.topology,
+                        count: render_mesh.num_indices as u32,
+                        model_view_proj: jittered_view_projection * glm_to_glam(&render_mesh.transform),
+                        id: pick_id_from_mesh_index(mesh_index)
+                    })
+                })
+                .collect();
+            passes.extend(self.object_ids.generate_passes(
+                &meshes,
+                overlay_viewport,
+                pick_target.clone(),
+                depth_attachment.clone(),
+                upload_buffer));
+        }
+
+        if self.show_grid {
+            passes.push(self.grid.generate_pass(
+                &view_projection,
+                camera_position,
+                overlay_viewport,
+                back_buffer.clone(),
+                Some(depth_attachment.clone()),
+                upload_buffer));
+        }
+
+        // the selected mesh's bounds are shown even while the others' aren't
+        let selected_mesh = self.selected_mesh.and_then(|mesh_index| model.meshes.get(mesh_index).map(|_| mesh_index));
+        if self.show_bounds || selected_mesh.is_some() {
+            let mut lines = DebugLines::new();
+            for (mesh_index, render_mesh) in model.meshes.iter().enumerate() {
+                let color = match selected_mesh == Some(mesh_index) {
+                    true => glam::Vec4::new(1.0, 1.0, 0.0, 1.0),
+                    false if self.show_bounds => glam::Vec4::new(0.0, 1.0, 0.0, 1.0),
+                    false => continue
+                };
+                lines.add_aabb(
+                    glam::Vec3::from(render_mesh.bounds[0]),
+                    glam::Vec3::from(render_mesh.bounds[1]),
+                    &glm_to_glam(&render_mesh.transform),
+                    color);
+            }
+
+            if let Some(bounds_pass) = self.debug_lines.generate_pass(
+                &lines,
+                &view_projection,
+                overlay_viewport,
+                back_buffer.clone(),
+                Some(depth_attachment.clone()),
+                upload_buffer) {
+                passes.push(bounds_pass);
+            }
+        }
+
+        if self.show_gizmo {
+            let target = &self.orbit.target;
+            passes.push(self.gizmo.generate_pass(
+                glam::Vec3::new(target.x, target.y, target.z),
+                glam::Quat::IDENTITY,
+                camera_position,
+                &view_projection,
+                overlay_viewport,
+                back_buffer.clone(),
+                pick_target.clone(),
+                upload_buffer));
+        }
+
+        if let (Some(pick_target), Some((x, y))) = (pick_target, pick_texel) {
+            // nothing reads the picked id back in the graph, so the copy is a root of its own.
+            // It's added before the passes writing the pick target, which it's still ordered after
+            if let Some(pick_pass) = self.picker.borrow_mut().generate_pass(pick_target.resource_image.clone(), x, y) {
+                frame.add_root(pick_pass);
+            }
+        }
 
         passes
     }
 }
 
+/// Meshes are drawn into the pick target with their index offset past NO_PICK_ID
+fn pick_id_from_mesh_index(mesh_index: usize) -> u32 {
+    mesh_index as u32 + 1
+}
+
+/// The mesh a picked id belongs to, None for NO_PICK_ID and the gizmo's handles
+fn mesh_index_from_pick_id(id: u32) -> Option<usize> {
+    match id == NO_PICK_ID || GizmoHandle::from_pick_id(id).is_some() {
+        true => None,
+        false => Some(id as usize - 1)
+    }
+}
+
+fn glm_to_glam(m: &glm::Mat4) -> glam::Mat4 {
+    // both are column-major
+    glam::Mat4::from_cols_slice(m.as_slice())
+}
+
 // The gltf lib returns transform matrices in column-major order as
 // a &[[f32; 4];4]. Since I haven't found a glm::Mat4::from implementation
 // which accepts that as an input, we use this utility function
@@ -559,434 +923,437 @@ fn gltf_to_decomposed_matrix(t: gltf::scene::Transform) -> DecomposedMatrix {
         glm::Vec3::new(scale[0], scale[1], scale[2]))
 }
 
-impl ModelExample {
-    pub fn new(
-        device: Rc<RefCell<DeviceWrapper>>,
-        render_context: &VulkanRenderContext) -> Self {
-
-        let duck_import = gltf::import("assets/models/gltf/duck/Duck.gltf");
-        // let duck_import = gltf::import("assets/models/gltf/Box/glTF/Box.gltf");
-        let duck_gltf = match duck_import {
-            Ok(gltf) => {
-                GltfModel {
-                    document: gltf.0,
-                    buffers: gltf.1,
-                    images: gltf.2
-                }
-            },
-            Err(e) => {
-                panic!("Failed to open Duck gltf model: {}", e)
-            }
-        };
+const MODEL_PATH: &str = "assets/models/gltf/duck/Duck.gltf";
 
-        // create images and upload gltf images data
-
-        // prepare meshes
-        //  * vertex layout
-        //  * buffer bindings
-        //  * image bindings
-        // each node could be a separate object in the scene
-        let mut scene_cameras : Vec<Camera> = Vec::new();
-        let mut meshes: Vec<RenderMesh> = Vec::new();
-        for _scene in duck_gltf.document.scenes() {
-            for node in duck_gltf.document.nodes() {
-                let node_transform = gltf_to_glm(&node.transform().matrix());
-                for child in node.children() {
-                    let child_transform = gltf_to_glm(&child.transform().matrix());
-                    if let Some(camera) = child.camera() {
-                        match camera.projection() {
-                            Projection::Orthographic(_ortho) => {
-                                panic!("Currently don't support orthographic projections")
-                            }
-                            Projection::Perspective(persp) => {
-                                // per the glTF 2.0 spec, we should exclude the scale of any node
-                                // transforms in the camera's node hierarchy
-                                // https://registry.khronos.org/glTF/specs/2.0/glTF-2.0.html#view-matrix
-                                let scene_resolved = {
-                                    // Despite what the spec says, all glTF viewers I've found
-                                    // online have included the scale components from their hierarchy
-                                    node_transform
-                                };
-                                let view_resolved = scene_resolved.mul(&child_transform);
+/// An image decoded by the glTF importer, converted to a format we can sample
+struct DecodedImage {
+    extent: vk::Extent3D,
+    format: vk::Format,
+    pixels: Vec<u8>
+}
 
-                                let far = match persp.zfar() {
-                                    None => { 10000.0 }
-                                    Some(zfar) => { zfar }
-                                };
-                                scene_cameras.push(Camera::new_from_view(
-                                    persp.aspect_ratio().unwrap(),
-                                    persp.yfov(),
-                                    persp.znear(),
-                                    far,
-                                    view_resolved))
-                            }
+/// A primitive read out of the glTF document on a loader thread, ready to be uploaded
+struct DecodedPrimitive {
+    name: String,
+    topology: vk::PrimitiveTopology,
+    bounds: [[f32; 3]; 2],
+    transform: glm::TMat4<f32>,
+    vertices: Vec<Vert>,
+    indices: Option<(Vec<u8>, vk::IndexType)>,
+    num_indices: usize,
+    albedo: Option<DecodedImage>
+}
+
+struct DecodedModel {
+    cameras: Vec<Camera>,
+    primitives: Vec<DecodedPrimitive>
+}
+
+/// The model once its buffers and textures are on the GPU
+pub struct LoadedModel {
+    cameras: Vec<Camera>,
+    meshes: Vec<RenderMesh>
+}
+
+fn decode_image(image: &gltf::image::Data) -> Result<DecodedImage, String> {
+    let pixels = match image.format {
+        // 24-bit RGB image formats are not supported on Metal, so we are
+        // just going to cheat and convert to RGBA
+        gltf::image::Format::R8G8B8 => image.pixels.chunks_exact(3)
+            .flat_map(|rgb| [rgb[0], rgb[1], rgb[2], 0xff])
+            .collect(),
+        gltf::image::Format::R8G8B8A8 => image.pixels.clone(),
+        format => return Err(format!("Unsupported albedo texture format: {:?}", format))
+    };
+
+    Ok(DecodedImage {
+        extent: vk::Extent3D {
+            width: image.width,
+            height: image.height,
+            depth: 1
+        },
+        format: vk::Format::R8G8B8A8_UNORM,
+        pixels
+    })
+}
+
+/// Reads `accessor`'s indices, widening 8-bit indices since they need an extension
+fn decode_indices(accessor: &gltf::Accessor, buffers: &[gltf::buffer::Data]) -> Result<(Vec<u8>, vk::IndexType), String> {
+    let view = accessor.view().ok_or("Sparse index buffers aren't supported")?;
+    let buffer_data = buffers.get(view.buffer().index())
+        .ok_or("Failed to get buffer data for index buffer")?;
+    let source_offset = view.offset() + accessor.offset();
+    let source = buffer_data.0.get(source_offset..source_offset + accessor.count() * accessor.size())
+        .ok_or("Index buffer view is out of range")?;
+
+    match accessor.data_type() {
+        DataType::U8 => Ok((source.iter().flat_map(|index| (*index as u16).to_le_bytes()).collect(), vk::IndexType::UINT16)),
+        DataType::U16 => Ok((source.to_vec(), vk::IndexType::UINT16)),
+        DataType::U32 => Ok((source.to_vec(), vk::IndexType::UINT32)),
+        data_type => Err(format!("Invalid index type: {:?}", data_type))
+    }
+}
+
+/// Imports and decodes the model at `path`. Runs on an asset loader thread
+fn decode_model(path: &str) -> Result<DecodedModel, String> {
+    let duck_gltf = match gltf::import(path) {
+        Ok(gltf) => {
+            GltfModel {
+                document: gltf.0,
+                buffers: gltf.1,
+                images: gltf.2
+            }
+        },
+        Err(e) => {
+            return Err(format!("Failed to open glTF model {}: {}", path, e))
+        }
+    };
+
+    // prepare meshes
+    //  * vertex layout
+    //  * buffer bindings
+    //  * image bindings
+    // each node could be a separate object in the scene
+    let mut scene_cameras : Vec<Camera> = Vec::new();
+    let mut primitives: Vec<DecodedPrimitive> = Vec::new();
+    for _scene in duck_gltf.document.scenes() {
+        for node in duck_gltf.document.nodes() {
+            let node_transform = gltf_to_glm(&node.transform().matrix());
+            for child in node.children() {
+                let child_transform = gltf_to_glm(&child.transform().matrix());
+                if let Some(camera) = child.camera() {
+                    match camera.projection() {
+                        Projection::Orthographic(_ortho) => {
+                            return Err("Currently don't support orthographic projections".to_string())
                         }
-                    }
-                    if let Some(mesh) = child.mesh() {
-                        for (i, primitive) in mesh.primitives().enumerate() {
-                            let mut ibo: Option<Rc<RefCell<DeviceResource>>> = None;
-
-                            let primitive_name = {
-                                if let Some(mesh_name) = mesh.name() {
-                                    format!("{}_{}", mesh_name, i)
-                                } else {
-                                    format!("UnknownMesh_{}", i)
-                                }
+                        Projection::Perspective(persp) => {
+                            // per the glTF 2.0 spec, we should exclude the scale of any node
+                            // transforms in the camera's node hierarchy
+                            // https://registry.khronos.org/glTF/specs/2.0/glTF-2.0.html#view-matrix
+                            let scene_resolved = {
+                                // Despite what the spec says, all glTF viewers I've found
+                                // online have included the scale components from their hierarchy
+                                node_transform
                             };
+                            let view_resolved = scene_resolved.mul(&child_transform);
 
-                            let mode = primitive.mode();
-                            let mut num_indices = 0;
-                            if let Some(indices_accessor) = primitive.indices() {
-                                // * create GPU index buffer
-                                num_indices = indices_accessor.count();
-                                let index_size = indices_accessor.size();
-                                let ibo_size = indices_accessor.count() * index_size;
-                                indices_accessor.data_type();
-                                ibo = Some({
-                                    let ibo_create = BufferCreateInfo::new(
-                                        vk::BufferCreateInfo::builder()
-                                            .size(ibo_size as vk::DeviceSize)
-                                            .usage(vk::BufferUsageFlags::INDEX_BUFFER)
-                                            .sharing_mode(vk::SharingMode::EXCLUSIVE)
-                                            .build(),
-                                        primitive_name.clone()
-                                    );
-                                    Rc::new(RefCell::new(DeviceWrapper::create_buffer(
-                                        device.clone(),
-                                        &ibo_create,
-                                        MemoryLocation::CpuToGpu
-                                    )))
-                                });
-
-                                // * memory map the buffer
-                                // * use the indices accessor to copy indices data into the GPU buffer
-                                device.borrow().update_buffer(&ibo.as_ref().unwrap().borrow(), |mapped_memory: *mut c_void, _size: u64| {
-                                    unsafe {
-                                        let view = indices_accessor.view().expect("Failed to get view for index buffer");
-                                        let buffer_data = duck_gltf.buffers.get(view.buffer().index())
-                                            .expect("Failed to get buffer data for index buffer");
-                                        let source_offset = view.offset() + indices_accessor.offset();
-                                        core::ptr::copy_nonoverlapping(
-                                            buffer_data.0.as_ptr().byte_add(source_offset),
-                                            mapped_memory as *mut u8,
-                                            ibo_size);
-                                    }
-                                });
+                            let far = match persp.zfar() {
+                                None => { 10000.0 }
+                                Some(zfar) => { zfar }
+                            };
+                            scene_cameras.push(Camera::new_from_view(
+                                persp.aspect_ratio().unwrap(),
+                                persp.yfov(),
+                                persp.znear(),
+                                far,
+                                view_resolved))
+                        }
+                    }
+                }
+                if let Some(mesh) = child.mesh() {
+                    for (i, primitive) in mesh.primitives().enumerate() {
+                        let primitive_name = {
+                            if let Some(mesh_name) = mesh.name() {
+                                format!("{}_{}", mesh_name, i)
+                            } else {
+                                format!("UnknownMesh_{}", i)
                             }
+                        };
+
+                        let mode = primitive.mode();
+                        let mut num_indices = 0;
+                        let mut indices = None;
+                        if let Some(indices_accessor) = primitive.indices() {
+                            num_indices = indices_accessor.count();
+                            indices = Some(decode_indices(&indices_accessor, &duck_gltf.buffers)?);
+                        }
 
-                            // we want interior mutability of this map; i.e. we can't add or remove
-                            // entries, but we can modify each existing entry
-                            let vertex_attribute_map: HashMap<gltf::mesh::Semantic, RefCell<Option<gltf::Accessor>>> = HashMap::from([
-                                (gltf::mesh::Semantic::Positions, RefCell::new(None)),
-                                (gltf::mesh::Semantic::Normals, RefCell::new(None)),
-                                (gltf::mesh::Semantic::TexCoords(0), RefCell::new(None))
-                            ]);
-
-                            let normals_offset = 3 * 4;
-                            let uvs_offset = 3 * 4 + normals_offset;
-                            let vertex_attributes: [vk::VertexInputAttributeDescription; 3] = [
-                                // TODO: map the glTF componentTypes to the correct format (or alter the data)
-                                // positions
-                                vk::VertexInputAttributeDescription::builder()
-                                    .binding(0)
-                                    .location(0)
-                                    .format(vk::Format::R32G32B32_SFLOAT)
-                                    .offset(0)
-                                    .build(),
-
-                                // normals
-                                vk::VertexInputAttributeDescription::builder()
-                                    .binding(0)
-                                    .location(1)
-                                    .format(vk::Format::R32G32B32_SFLOAT)
-                                    .offset(normals_offset)
-                                    .build(),
-
-                                // UVs
-                                vk::VertexInputAttributeDescription::builder()
-                                    .binding(0)
-                                    .location(2)
-                                    .format(vk::Format::R32G32_SFLOAT)
-                                    .offset(uvs_offset)
-                                    .build(),
-                            ];
-
-                            // need to do an initial pass over attributes to calculate total VBO size and vertex size
-                            let vertex_size = std::mem::size_of::<Vert>();
-                            let mut vertex_data_size = 0usize;
-                            let mut vertex_count = 0usize;
-                            let mut found_positions = false;
-                            for (semantic, attribute_accessor) in primitive.attributes() {
-                                if semantic == gltf::mesh::Semantic::Positions {
-                                    found_positions = true;
-
-                                    vertex_count = attribute_accessor.count();
-                                    vertex_data_size = vertex_size * vertex_count;
-                                }
-                                // only keep attributes which are used in the renderer
-                                if let Some(found_attribute) = vertex_attribute_map.get(&semantic) {
-                                    let mut attribute = found_attribute.borrow_mut();
-                                    *attribute = Some(attribute_accessor);
-                                }
+                        // we want interior mutability of this map; i.e. we can't add or remove
+                        // entries, but we can modify each existing entry
+                        let vertex_attribute_map: HashMap<gltf::mesh::Semantic, RefCell<Option<gltf::Accessor>>> = HashMap::from([
+                            (gltf::mesh::Semantic::Positions, RefCell::new(None)),
+                            (gltf::mesh::Semantic::Normals, RefCell::new(None)),
+                            (gltf::mesh::Semantic::TexCoords(0), RefCell::new(None))
+                        ]);
+
+                        // need to do an initial pass over attributes to calculate the vertex count
+                        let mut vertex_count = 0usize;
+                        let mut found_positions = false;
+                        for (semantic, attribute_accessor) in primitive.attributes() {
+                            if semantic == gltf::mesh::Semantic::Positions {
+                                found_positions = true;
+
+                                vertex_count = attribute_accessor.count();
                             }
-                            assert!(found_positions, "No positions attribute was found while processing glTF model");
-
-                            // create vertex buffer
-                            let vbo = {
-                                let vbo_create = BufferCreateInfo::new(
-                                    vk::BufferCreateInfo::builder()
-                                        .size(vertex_data_size as vk::DeviceSize)
-                                        .usage(vk::BufferUsageFlags::VERTEX_BUFFER)
-                                        .sharing_mode(vk::SharingMode::EXCLUSIVE)
-                                        .build(),
-                                    primitive_name.clone()
-                                );
-                                DeviceWrapper::create_buffer(
-                                    device.clone(),
-                                    &vbo_create,
-                                    MemoryLocation::CpuToGpu
-                                )
-                            };
+                            // only keep attributes which are used in the renderer
+                            if let Some(found_attribute) = vertex_attribute_map.get(&semantic) {
+                                let mut attribute = found_attribute.borrow_mut();
+                                *attribute = Some(attribute_accessor);
+                            }
+                        }
+                        if !found_positions {
+                            return Err("No positions attribute was found while processing glTF model".to_string());
+                        }
 
-                            let mut vertices : Vec<Vert> = Vec::new();
-                            vertices.resize_with(vertex_count, Default::default);
-                            // iterate over attributes again and copy them from mesh buffers into the VBO
-                            for (semantic, attribute) in &vertex_attribute_map {
-                                if let Some(attribute_accessor) = attribute.borrow().as_ref() {
-                                    assert_eq!(
-                                        attribute_accessor.count(),
-                                        vertex_count,
+                        let mut vertices : Vec<Vert> = Vec::new();
+                        vertices.resize_with(vertex_count, Default::default);
+                        // iterate over attributes again and copy them from mesh buffers into the vertices
+                        for (semantic, attribute) in &vertex_attribute_map {
+                            if let Some(attribute_accessor) = attribute.borrow().as_ref() {
+                                if attribute_accessor.count() != vertex_count {
+                                    return Err(format!(
                                         "Attribute count ({}) does not match vertex count ({})",
                                         attribute_accessor.count(),
-                                        vertex_count);
-
-                                    let view = attribute_accessor.view().expect("Failed to get view for vertex attribute");
-                                    let buffer_data = duck_gltf.buffers.get(view.buffer().index())
-                                        .expect("Failed to get buffer for vertex attribute");
-                                    let stride = match view.stride() {
-                                        None => {1} // I think this is a safe assumption?
-                                        Some(s) => {s}
+                                        vertex_count));
+                                }
+
+                                let view = attribute_accessor.view().ok_or("Failed to get view for vertex attribute")?;
+                                let buffer_data = duck_gltf.buffers.get(view.buffer().index())
+                                    .ok_or("Failed to get buffer for vertex attribute")?;
+                                let stride = match view.stride() {
+                                    None => {1} // I think this is a safe assumption?
+                                    Some(s) => {s}
+                                };
+
+                                // source_offset is the offset into the source buffer defined by the buffer view (base) and the accessor
+                                let mut source_offset = view.offset() + attribute_accessor.offset();
+
+                                for i in (0..vertex_count) {
+                                    let vertex = vertices.get_mut(i).unwrap();
+
+                                    let glm_value = unsafe {
+                                        get_glm_format(
+                                            attribute_accessor.data_type(),
+                                            attribute_accessor.dimensions(),
+                                            buffer_data.0.as_ptr().byte_add(source_offset))
                                     };
 
-                                    // source_offset is the offset into the source buffer defined by the buffer view (base) and the accessor
-                                    let mut source_offset = view.offset() + attribute_accessor.offset();
-
-                                    for i in (0..vertex_count) {
-                                        let vertex = vertices.get_mut(i).unwrap();
-
-                                        let glm_value = unsafe {
-                                            get_glm_format(
-                                                attribute_accessor.data_type(),
-                                                attribute_accessor.dimensions(),
-                                                buffer_data.0.as_ptr().byte_add(source_offset))
-                                        };
-
-                                        match semantic {
-                                            Semantic::Positions => {
-                                                let GlmType::Vec3(pos) = glm_value else {
-                                                    panic!("Position must be a vec3")
-                                                };
-                                                vertex.pos = [pos.x, pos.y, pos.z];
-                                            }
-                                            Semantic::Normals => {
-                                                let GlmType::Vec3(normal) = glm_value else {
-                                                    panic!("Normals must be a vec3")
-                                                };
-                                                vertex.normal = [normal.x, normal.y, normal.z];
-                                            }
-                                            Semantic::TexCoords(0) => {
-                                                let GlmType::Vec2(uv) = glm_value else {
-                                                    panic!("UVs must be a vec2")
-                                                };
-                                                vertex.uv = [uv.x, uv.y];
-                                            }
-                                            _ => {
-                                                panic!("Unsupported input semantic");
-                                            }
+                                    match semantic {
+                                        Semantic::Positions => {
+                                            let GlmType::Vec3(pos) = glm_value else {
+                                                return Err("Position must be a vec3".to_string())
+                                            };
+                                            vertex.pos = [pos.x, pos.y, pos.z];
                                         }
-
-                                        source_offset += stride;
-                                    }
-                                } else {
-                                    // use default values
-                                    for i in (0..vertex_count) {
-                                        let vertex = vertices.get_mut(i).unwrap();
-
-                                        match semantic {
-                                            gltf::Semantic::Normals => {
-                                                // TODO: we should actually calculate this based on neighboring vertex positions
-                                                vertex.normal = [0.0, 0.0, 1.0];
-                                            },
-                                            gltf::Semantic::TexCoords(0) => {
-                                                vertex.uv = [0.0, 0.0];
-                                            },
-                                            _ => {}
+                                        Semantic::Normals => {
+                                            let GlmType::Vec3(normal) = glm_value else {
+                                                return Err("Normals must be a vec3".to_string())
+                                            };
+                                            vertex.normal = [normal.x, normal.y, normal.z];
+                                        }
+                                        Semantic::TexCoords(0) => {
+                                            let GlmType::Vec2(uv) = glm_value else {
+                                                return Err("UVs must be a vec2".to_string())
+                                            };
+                                            vertex.uv = [uv.x, uv.y];
+                                        }
+                                        _ => {
+                                            return Err("Unsupported input semantic".to_string());
                                         }
                                     }
-                                }
-                            }
 
-                            device.borrow().update_buffer(&vbo, |mapped_memory: *mut c_void, _size: u64| {
-                                unsafe {
-                                    // core::ptr::copy_nonoverlapping(
-                                    //     vertices.as_ptr(),
-                                    //     mapped_memory as *mut Vert,
-                                    //     vertices.len());
-                                    for i in (0..vertex_count) {
-                                        let vertex = &vertices[i];
-
-                                        let offset = i * std::mem::size_of::<Vert>();
-
-                                        core::ptr::copy_nonoverlapping(
-                                            &vertex.pos,
-                                            (mapped_memory as *mut [f32;3]).byte_add(offset),
-                                            1);
-
-                                        core::ptr::copy_nonoverlapping(
-                                            &vertex.normal,
-                                            (mapped_memory as *mut [f32;3]).byte_add(offset + (3*4)),
-                                            1);
-
-                                        core::ptr::copy_nonoverlapping(
-                                            &vertex.uv,
-                                            (mapped_memory as *mut [f32;2]).byte_add(offset + (6*4)),
-                                            1);
-                                    }
+                                    source_offset += stride;
                                 }
-                            });
-
-                            // process  material
-                            let mut albedo_dev_tex: Option<Rc<RefCell<DeviceResource>>> = None;
-                            {
-                                let material = primitive.material();
-                                if let Some(material_id) = material.index() {
-                                    if let Some(albedo_tex) = material.pbr_metallic_roughness().base_color_texture() {
-                                        // create device image from image bytes
-                                        let image_source = albedo_tex.texture().source().source();
-                                        match image_source {
-                                            Source::View{view, mime_type } => {
-                                                let buffer_data = duck_gltf.buffers.get(view.buffer().index())
-                                                    .expect("Failed to get buffer data for image");
-                                                let source_offset = view.offset();
-                                                // util::image::create_from_bytes(
-                                                //     device.clone(),
-                                                //     render_context)
-
-                                                // let view = indices_accessor.view().expect("Failed to get view for index buffer");
-                                                // let buffer_data = duck_gltf.buffers.get(view.buffer().index())
-                                                //     .expect("Failed to get buffer data for index buffer");
-                                                // let source_offset = view.offset() + indices_accessor.offset();
-                                                // core::ptr::copy_nonoverlapping(
-                                                //     buffer_data.0.as_ptr().byte_add(source_offset),
-                                                //     mapped_memory as *mut u8,
-                                                //     ibo_size);
-                                            }
-                                            Source::Uri{ uri, mime_type } => {
-                                                let mut tex = util::image::create_from_uri(
-                                                    device.clone(),
-                                                    render_context,
-                                                    &format!("{}{}", "assets/models/gltf/duck/", uri),
-                                                    true
-                                                );
-                                                // albedo_dev_tex = Some(Rc::new(RefCell::new(tex)));
-                                                unsafe {
-                                                    let create = vk::SamplerCreateInfo::builder()
-                                                        .mag_filter(vk::Filter::LINEAR)
-                                                        .min_filter(vk::Filter::LINEAR)
-                                                        .mipmap_mode(vk::SamplerMipmapMode::LINEAR)
-                                                        .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_BORDER)
-                                                        .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_BORDER)
-                                                        .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_BORDER)
-                                                        .border_color(vk::BorderColor::INT_OPAQUE_BLACK)
-                                                        .build();
-
-                                                    let sampler = device.borrow().get().create_sampler(&create, None)
-                                                        .expect("Failed to create sampler for albedo texture");
-                                                    device.borrow().set_debug_name(vk::ObjectType::SAMPLER, sampler.as_raw(), "albedo_sampler");
-
-                                                    tex.get_image_mut().sampler = Some(sampler);
-                                                };
-                                                albedo_dev_tex = Some(Rc::new(RefCell::new(tex)));
-                                            }
-                                        }
-
-                                        // create sampler
-
-                                        // apply sampler to device image
+                            } else {
+                                // use default values
+                                for i in (0..vertex_count) {
+                                    let vertex = vertices.get_mut(i).unwrap();
+
+                                    match semantic {
+                                        gltf::Semantic::Normals => {
+                                            // TODO: we should actually calculate this based on neighboring vertex positions
+                                            vertex.normal = [0.0, 0.0, 1.0];
+                                        },
+                                        gltf::Semantic::TexCoords(0) => {
+                                            vertex.uv = [0.0, 0.0];
+                                        },
+                                        _ => {}
                                     }
                                 }
                             }
-
-                            let render_mesh = RenderMesh {
-                                vertex_buffer: Rc::new(RefCell::new(vbo)),
-                                index_buffer: ibo,
-                                num_indices,
-                                vertex_binding: VERTEX_BINDING,
-                                vertex_attributes,
-                                transform: node_transform.mul(child_transform),
-                                albedo_tex: albedo_dev_tex,
-                            };
-                            meshes.push(render_mesh);
                         }
+
+                        // process material; the importer has already decoded the images,
+                        // whether they're embedded or referenced by URI
+                        let albedo = match primitive.material().pbr_metallic_roughness().base_color_texture() {
+                            Some(albedo_tex) => {
+                                let image_index = albedo_tex.texture().source().index();
+                                let image = duck_gltf.images.get(image_index)
+                                    .ok_or("Failed to get image data for albedo texture")?;
+                                Some(decode_image(image)?)
+                            },
+                            None => None
+                        };
+
+                        let topology = match mode {
+                            gltf::mesh::Mode::Points => vk::PrimitiveTopology::POINT_LIST,
+                            gltf::mesh::Mode::Lines => vk::PrimitiveTopology::LINE_LIST,
+                            gltf::mesh::Mode::LineLoop => {
+                                // Vulkan has no line loops, so the closing segment is missing
+                                log::warn!(target: "model_example", "{} is a line loop, drawing it as a line strip", primitive_name);
+                                vk::PrimitiveTopology::LINE_STRIP
+                            },
+                            gltf::mesh::Mode::LineStrip => vk::PrimitiveTopology::LINE_STRIP,
+                            gltf::mesh::Mode::Triangles => vk::PrimitiveTopology::TRIANGLE_LIST,
+                            gltf::mesh::Mode::TriangleStrip => vk::PrimitiveTopology::TRIANGLE_STRIP,
+                            gltf::mesh::Mode::TriangleFan => vk::PrimitiveTopology::TRIANGLE_FAN
+                        };
+                        let bounding_box = primitive.bounding_box();
+
+                        primitives.push(DecodedPrimitive {
+                            name: primitive_name,
+                            topology,
+                            bounds: [bounding_box.min, bounding_box.max],
+                            transform: node_transform.mul(child_transform),
+                            vertices,
+                            indices,
+                            num_indices,
+                            albedo
+                        });
                     }
                 }
             }
         }
+    }
 
-        // let camera = Camera::new(
-        //     1.5,
-        //     0.66,
-        //     1.0,
-        //     10000.0,
-        //     &glm::Vec3::new(0.0, 0.0, 100.0),
-        //     &glm::Vec3::new(0.0, 0.0, -1.0),
-        //     &glm::Vec3::new(0.0, 1.0, 0.0)
-        // );
-
-        let camera = {
-            if scene_cameras.len() > 0 {
-                scene_cameras[0].clone()
-            } else {
-                Camera::new_from_view(
-                    1.5,
-                    0.66,
-                    1.0,
-                    10000.0,
-                    glm::look_at(
-                        &glm::Vec3::new(0.0, 0.0, 2.0),
-                        &glm::Vec3::new(0.0, 0.0, -1.0),
-                        &glm::Vec3::new(0.0, 1.0, 0.0)
-                    ).try_inverse().unwrap()
-                    // glm::Mat4::from_columns(&[
-                    //     Vec4::new( -0.7289686799049377, 0.0, -0.6845470666885376, 0.0),
-                    //     Vec4::new(-0.4252049028873444, 0.7836934328079224, 0.4527972936630249, 0.0),
-                    //     Vec4::new(0.5364750623703003, 0.6211478114128113, -0.571287989616394, 0.0),
-                    //     Vec4::new( 400.1130065917969, 463.2640075683594, -431.0780334472656, 1.00)
-                    // ])
-                )
-            }
+    Ok(DecodedModel {
+        cameras: scene_cameras,
+        primitives
+    })
+}
+
+/// Creates the model's GPU resources, recording their uploads into `batch`
+fn upload_model(device: Rc<RefCell<DeviceWrapper>>, model: DecodedModel, batch: &mut UploadBatch) -> LoadedModel {
+    let normals_offset = 3 * 4;
+    let uvs_offset = 3 * 4 + normals_offset;
+    let vertex_attributes: [vk::VertexInputAttributeDescription; 3] = [
+        // TODO: map the glTF componentTypes to the correct format (or alter the data)
+        // positions
+        vk::VertexInputAttributeDescription::builder()
+            .binding(0)
+            .location(0)
+            .format(vk::Format::R32G32B32_SFLOAT)
+            .offset(0)
+            .build(),
+
+        // normals
+        vk::VertexInputAttributeDescription::builder()
+            .binding(0)
+            .location(1)
+            .format(vk::Format::R32G32B32_SFLOAT)
+            .offset(normals_offset)
+            .build(),
+
+        // UVs
+        vk::VertexInputAttributeDescription::builder()
+            .binding(0)
+            .location(2)
+            .format(vk::Format::R32G32_SFLOAT)
+            .offset(uvs_offset)
+            .build(),
+    ];
+
+    let meshes = model.primitives.into_iter().map(|primitive| {
+        let index_buffer = primitive.indices.as_ref().map(|(indices, _)| {
+            Rc::new(RefCell::new(batch.upload_buffer(indices, vk::BufferUsageFlags::INDEX_BUFFER, &primitive.name)))
+        });
+
+        // Vert is tightly packed, so the vertices can be copied as they are
+        let vertex_bytes = unsafe {
+            std::slice::from_raw_parts(
+                primitive.vertices.as_ptr() as *const u8,
+                primitive.vertices.len() * std::mem::size_of::<Vert>())
+        };
+        let vertex_buffer = batch.upload_buffer(vertex_bytes, vk::BufferUsageFlags::VERTEX_BUFFER, &primitive.name);
+
+        let albedo_tex = primitive.albedo.as_ref().map(|albedo| {
+            let texture_create = vk::ImageCreateInfo::builder()
+                .format(albedo.format)
+                .image_type(vk::ImageType::TYPE_2D)
+                .sharing_mode(vk::SharingMode::EXCLUSIVE)
+                .initial_layout(vk::ImageLayout::UNDEFINED)
+                .samples(vk::SampleCountFlags::TYPE_1)
+                .usage(vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED)
+                .extent(albedo.extent)
+                .mip_levels(1)
+                .array_layers(1)
+                .build();
+            let mut tex = batch.upload_image(texture_create, &albedo.pixels, &format!("{}_albedo", primitive.name));
+
+            unsafe {
+                let create = vk::SamplerCreateInfo::builder()
+                    .mag_filter(vk::Filter::LINEAR)
+                    .min_filter(vk::Filter::LINEAR)
+                    .mipmap_mode(vk::SamplerMipmapMode::LINEAR)
+                    .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_BORDER)
+                    .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_BORDER)
+                    .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_BORDER)
+                    .border_color(vk::BorderColor::INT_OPAQUE_BLACK)
+                    .build();
+
+                let sampler = device.borrow().get().create_sampler(&create, None)
+                    .expect("Failed to create sampler for albedo texture");
+                device.borrow().set_debug_name(vk::ObjectType::SAMPLER, sampler.as_raw(), "albedo_sampler");
+
+                tex.get_image_mut().sampler = Some(sampler);
+            };
+            Rc::new(RefCell::new(tex))
+        });
+
+        RenderMesh {
+            topology: primitive.topology,
+            bounds: primitive.bounds,
+            vertex_buffer: Rc::new(RefCell::new(vertex_buffer)),
+            index_buffer,
+            index_type: primitive.indices.as_ref().map_or(vk::IndexType::UINT16, |(_, index_type)| *index_type),
+            num_indices: primitive.num_indices,
+            vertex_binding: VERTEX_BINDING,
+            vertex_attributes,
+            transform: primitive.transform,
+            albedo_tex
+        }
+    }).collect();
+
+    LoadedModel {
+        cameras: model.cameras,
+        meshes
+    }
+}
 
+impl ModelExample {
+    pub fn new(
+        device: Rc<RefCell<DeviceWrapper>>,
+        asset_loader: &mut AssetLoader,
+        frames_in_flight: u32) -> Self {
+
+        // cleared with a transfer and sampled by the SSAO pass, so it mustn't have a stencil
+        let depth_format = device.borrow().formats().choose_depth_format(
+            false,
+            vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED);
+
+        // the duck pops in once it's loaded, and the camera is framed on it then
+        let model = {
+            let upload_device = device.clone();
+            asset_loader.load(
+                MODEL_PATH,
+                || decode_model(MODEL_PATH),
+                move |decoded, batch| upload_model(upload_device, decoded, batch))
         };
 
-        // let camera = Camera::new_from_view(
-        //     1.5,
-        //     0.66,
-        //     1.0,
-        //     10000.0,
-        //     glm::Mat4::from_columns(&[
-        //         Vec4::new( -0.7289686799049377, -0.425, 0.5365, 400.113),
-        //         Vec4::new(0.0, 0.7836934328079224, 0.62115, 463.264),
-        //         Vec4::new(-0.6845, 0.4528, -0.571287989616394, -431.078),
-        //         Vec4::new( 0.0, 0.0, 0.0, 1.00)
-        //     ])
-        // );
-
-        // glm::Mat4::from_columns(&[
-        //     Vec4::from(m[0]),
-        //     Vec4::from(m[1]),
-        //     Vec4::from(m[2]),
-        //     Vec4::from(m[3])
-        // ])
+        let camera = Camera::new_from_view(
+            1.5,
+            0.66,
+            1.0,
+            10000.0,
+            glm::look_at(
+                &glm::Vec3::new(0.0, 0.0, 2.0),
+                &glm::Vec3::new(0.0, 0.0, -1.0),
+                &glm::Vec3::new(0.0, 1.0, 0.0)
+            ).try_inverse().unwrap()
+        );
+        let eye = glm::Vec3::new(camera.view[(0, 3)], camera.view[(1, 3)], camera.view[(2, 3)]);
+        let orbit = OrbitController::looking_at(&eye, &glm::Vec3::zeros());
+        let fly = FlyController::from_transform(&camera.view, orbit.distance * 0.5);
 
         let vert_shader = Rc::new(RefCell::new(
             shader::create_shader_module_from_bytes(
@@ -998,13 +1365,58 @@ impl ModelExample {
                 device.clone(),
                 "model-frag",
                 include_bytes!(concat!(env!("OUT_DIR"), "/shaders/model-frag.spv")))));
+        let gbuffer_frag_shader = Rc::new(RefCell::new(
+            shader::create_shader_module_from_bytes(
+                device.clone(),
+                "model_gbuffer-frag",
+                include_bytes!(concat!(env!("OUT_DIR"), "/shaders/model_gbuffer-frag.spv")))));
 
         ModelExample{
             vertex_shader: vert_shader,
             fragment_shader: frag_shader,
+            gbuffer_fragment_shader: gbuffer_frag_shader,
             camera,
-            duck_model: duck_gltf,
-            render_meshes: meshes
+            model,
+            scene_framed: false,
+            depth_format,
+            wireframe_supported: device.borrow().features().fill_mode_non_solid,
+            debug_lines: DebugLineRender::new(device.clone()),
+            show_bounds: false,
+            temporal_aa: false,
+            taa: RefCell::new(None),
+            ambient_occlusion: false,
+            ssao: AmbientOcclusion::new(device.clone()),
+            reverse_z: false,
+            grid: InfiniteGrid::new(device.clone()),
+            show_grid: false,
+            gizmo: Gizmo::new(device.clone()),
+            show_gizmo: false,
+            object_ids: ObjectIdRender::new(device.clone()),
+            picker: RefCell::new(IdPicker::new(device.clone(), frames_in_flight)),
+            pick_position: None,
+            selected_mesh: None,
+            transform_history: RefCell::new(TransformHistory::new()),
+            orbit,
+            fly,
+            camera_mode: CameraMode::Orbit
         }
     }
-}
\ No newline at end of file
+
+    /// Starts from the model's own camera if it has one, orbiting the middle of its meshes
+    fn frame_scene(&mut self, model: &LoadedModel) {
+        if let Some(scene_camera) = model.cameras.first() {
+            self.camera = scene_camera.clone();
+        }
+
+        let scene_center = {
+            let mesh_centers: Vec<glm::Vec3> = model.meshes.iter().map(|mesh| {
+                let local_center = (glm::Vec3::from(mesh.bounds[0]) + glm::Vec3::from(mesh.bounds[1])) * 0.5;
+                (mesh.transform * glm::vec4(local_center.x, local_center.y, local_center.z, 1.0)).xyz()
+            }).collect();
+            mesh_centers.iter().fold(glm::Vec3::zeros(), |sum, center| sum + center) / mesh_centers.len().max(1) as f32
+        };
+        let eye = glm::Vec3::new(self.camera.view[(0, 3)], self.camera.view[(1, 3)], self.camera.view[(2, 3)]);
+        self.orbit = OrbitController::looking_at(&eye, &scene_center);
+        self.fly = FlyController::from_transform(&self.camera.view, self.orbit.distance * 0.5);
+    }
+}
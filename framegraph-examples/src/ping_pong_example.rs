@@ -0,0 +1,97 @@
+use alloc::rc::Rc;
+use std::cell::RefCell;
+use ash::vk;
+use glam::IVec2;
+use imgui::Ui;
+use api_types::device::DeviceWrapper;
+use api_types::upload_buffer::DynamicUploadBuffer;
+use context::render_context::RenderContext;
+use context::transient_image_pool::TransientImagePool;
+use context::vulkan_render_context::VulkanRenderContext;
+use framegraph::attachment::AttachmentReference;
+use framegraph::compute_pass_node::ComputePassNode;
+use framegraph::frame::Frame;
+use framegraph::pass_type::PassType;
+use framegraph::ping_pong::PingPongImages;
+use framegraph::pipeline::ComputePipelineDescription;
+use passes::blit;
+use profiling::enter_span;
+use crate::example::{Example, ExampleSettings};
+
+// compute iterations run each frame, each reading the previous iteration's results
+const ITERATIONS_PER_FRAME: u32 = 4;
+
+pub struct PingPongExample {
+    images: RefCell<Option<PingPongImages>>
+}
+
+impl Example for PingPongExample {
+    fn get_name(&self) -> &'static str {
+        "Compute Ping-Pong"
+    }
+
+    fn execute(&self, _frame: &mut Frame, device: Rc<RefCell<DeviceWrapper>>, _upload_buffer: &mut DynamicUploadBuffer, _image_pool: &mut TransientImagePool, _settings: &ExampleSettings, _imgui_ui: &mut Ui, back_buffer: AttachmentReference) -> Vec<PassType> {
+        enter_span!(tracing::Level::TRACE, "Generating Ping-Pong Passes");
+
+        let back_buffer_extent = back_buffer.resource_image.borrow().get_image().extent;
+        let extent = vk::Extent2D {
+            width: back_buffer_extent.width,
+            height: back_buffer_extent.height
+        };
+
+        let mut images_ref = self.images.borrow_mut();
+        let recreate = images_ref.as_ref().map_or(true, |images| {
+            images.get_write().borrow().get_image().extent != back_buffer_extent
+        });
+        if recreate {
+            *images_ref = Some(PingPongImages::new(device, extent, vk::Format::R8G8B8A8_UNORM, "ping_pong"));
+        }
+        let images = images_ref.as_mut().unwrap();
+
+        let mut passes: Vec<PassType> = Vec::new();
+        for iteration in 0..ITERATIONS_PER_FRAME {
+            let pass_node = ComputePassNode::builder(format!("ping_pong_{}", iteration))
+                .pipeline_description(ComputePipelineDescription::new("ping_pong-comp.spv"))
+                .input(images.read_binding(0, 0, vk::PipelineStageFlags::COMPUTE_SHADER))
+                .output(images.write_binding(0, 1, vk::PipelineStageFlags::COMPUTE_SHADER))
+                .fill_commands(Box::new(
+                    move |render_ctx: &VulkanRenderContext,
+                          command_buffer: &vk::CommandBuffer| {
+
+                        enter_span!(tracing::Level::TRACE, "Ping-Pong");
+                        let _gpu_scope = render_ctx.get_profiler().scope("Ping-Pong GPU", command_buffer);
+
+                        unsafe {
+                            render_ctx.get_device().borrow().get().cmd_dispatch(
+                                *command_buffer,
+                                (extent.width + 7) / 8,
+                                (extent.height + 7) / 8,
+                                1);
+                        }
+                    }
+                ))
+                .build()
+                .expect("Failed to create ping-pong passnode");
+
+            passes.push(PassType::Compute(pass_node));
+            images.flip();
+        }
+
+        passes.push(blit::generate_pass(
+            images.get_latest().clone(),
+            0,
+            back_buffer.resource_image.clone(),
+            0,
+            [IVec2::new(0, 0), IVec2::new(extent.width as i32, extent.height as i32)]));
+
+        passes
+    }
+}
+
+impl PingPongExample {
+    pub fn new() -> Self {
+        PingPongExample {
+            images: RefCell::new(None)
+        }
+    }
+}
@@ -0,0 +1,241 @@
+use alloc::rc::Rc;
+use std::cell::RefCell;
+use ash::vk;
+use glam::IVec2;
+use gpu_allocator::MemoryLocation;
+use imgui::Ui;
+use api_types::device::{DeviceResource, DeviceWrapper};
+use api_types::image::{ImageCreateInfo, ImageType};
+use api_types::upload_buffer::DynamicUploadBuffer;
+use context::render_context::RenderContext;
+use context::transient_image_pool::TransientImagePool;
+use context::vulkan_render_context::VulkanRenderContext;
+use framegraph::attachment::AttachmentReference;
+use framegraph::binding::{ShaderInterface, StorageImage, UniformBuffer};
+use framegraph::compute_pass_node::{ComputePassNode, ComputePassNodeBuilder};
+use framegraph::frame::Frame;
+use framegraph::pass_type::PassType;
+use framegraph::pipeline::ComputePipelineDescription;
+use framegraph::uniform_layout::UniformBlock;
+use passes::{blit, clear};
+use profiling::enter_span;
+use crate::example::{Example, ExampleSettings};
+use crate::ubo_example::UboExample;
+
+const SCENE_FORMAT: vk::Format = vk::Format::R8G8B8A8_UNORM;
+
+#[repr(C)]
+#[derive(UniformBlock)]
+struct BlurParams {
+    direction: [i32; 2],
+    radius: i32,
+    sigma: f32
+}
+
+#[repr(C)]
+#[derive(UniformBlock)]
+struct SharpenParams {
+    amount: f32
+}
+
+/// Descriptors of separable_blur.comp. Never constructed; only its slots are used
+#[derive(ShaderInterface)]
+#[allow(dead_code)]
+struct BlurInterface {
+    #[binding(set = 0, slot = 0, stage = COMPUTE_SHADER)]
+    params: UniformBuffer,
+    #[binding(set = 0, slot = 1, stage = COMPUTE_SHADER, access = SHADER_READ)]
+    source: StorageImage,
+    #[binding(set = 0, slot = 2, stage = COMPUTE_SHADER, access = SHADER_WRITE)]
+    output: StorageImage
+}
+
+/// Descriptors of sharpen.comp
+#[derive(ShaderInterface)]
+#[allow(dead_code)]
+struct SharpenInterface {
+    #[binding(set = 0, slot = 0, stage = COMPUTE_SHADER)]
+    params: UniformBuffer,
+    #[binding(set = 0, slot = 1, stage = COMPUTE_SHADER, access = SHADER_READ)]
+    scene: StorageImage,
+    #[binding(set = 0, slot = 2, stage = COMPUTE_SHADER, access = SHADER_READ)]
+    blurred: StorageImage,
+    #[binding(set = 0, slot = 3, stage = COMPUTE_SHADER, access = SHADER_WRITE)]
+    output: StorageImage
+}
+
+/// The images of the chain, recreated when the back buffer is resized
+struct ChainImages {
+    scene: Rc<RefCell<DeviceResource>>,
+    horizontal: Rc<RefCell<DeviceResource>>,
+    blurred: Rc<RefCell<DeviceResource>>,
+    output: Rc<RefCell<DeviceResource>>
+}
+
+fn create_image(
+    device: Rc<RefCell<DeviceWrapper>>,
+    extent: vk::Extent3D,
+    usage: vk::ImageUsageFlags,
+    name: &str) -> Rc<RefCell<DeviceResource>> {
+
+    let create_info = ImageCreateInfo::new(
+        vk::ImageCreateInfo::builder()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(SCENE_FORMAT)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .extent(extent)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .usage(usage | vk::ImageUsageFlags::STORAGE)
+            .mip_levels(1)
+            .array_layers(1)
+            .build(),
+        name.to_string(),
+        ImageType::Color);
+
+    Rc::new(RefCell::new(DeviceWrapper::create_image(device, &create_info, MemoryLocation::GpuOnly)))
+}
+
+fn compute_pass(
+    name: &str,
+    shader: &str,
+    groups: (u32, u32),
+    builder: impl FnOnce(ComputePassNodeBuilder) -> ComputePassNodeBuilder) -> PassType {
+
+    let scope_name = name.to_string();
+    let pass_node = builder(ComputePassNode::builder(name.to_string()))
+        .pipeline_description(ComputePipelineDescription::new(shader))
+        .fill_commands(Box::new(
+            move |render_ctx: &VulkanRenderContext,
+                  command_buffer: &vk::CommandBuffer| {
+
+                enter_span!(tracing::Level::TRACE, "Post Process");
+                let _gpu_scope = render_ctx.get_profiler().scope(&scope_name, command_buffer);
+
+                unsafe {
+                    render_ctx.get_device().borrow().get().cmd_dispatch(
+                        *command_buffer,
+                        groups.0,
+                        groups.1,
+                        1);
+                }
+            }
+        ))
+        .build()
+        .expect("Failed to create post process passnode");
+
+    PassType::Compute(pass_node)
+}
+
+/// Renders the UBO example to an offscreen target, blurs it with a separable Gaussian blur in
+/// two compute passes, then sharpens the target against its blurred copy and copies the result
+/// to the back buffer. Every image after the first render target is only accessed as a storage
+/// image, so the chain moves the images into and between GENERAL accesses
+pub struct PostProcessExample {
+    ubo: UboExample,
+    images: RefCell<Option<ChainImages>>,
+    blur_radius: i32,
+    blur_sigma: f32,
+    sharpen_amount: f32
+}
+
+impl Example for PostProcessExample {
+    fn get_name(&self) -> &'static str {
+        "Post Process Chain"
+    }
+
+    fn default_settings(&self) -> ExampleSettings {
+        self.ubo.default_settings()
+    }
+
+    fn ui(&mut self, ui: &Ui) {
+        self.ubo.ui(ui);
+        ui.slider("Blur Radius", 0, 16, &mut self.blur_radius);
+        ui.slider("Blur Sigma", 0.5, 8.0, &mut self.blur_sigma);
+        ui.slider("Sharpen Amount", -1.0, 4.0, &mut self.sharpen_amount);
+    }
+
+    fn execute(&self, frame: &mut Frame, device: Rc<RefCell<DeviceWrapper>>, upload_buffer: &mut DynamicUploadBuffer, image_pool: &mut TransientImagePool, settings: &ExampleSettings, imgui_ui: &mut Ui, back_buffer: AttachmentReference) -> Vec<PassType> {
+        enter_span!(tracing::Level::TRACE, "Generating Post Process Passes");
+
+        let extent = back_buffer.resource_image.borrow().get_image().extent;
+        let mut images_ref = self.images.borrow_mut();
+        let recreate = images_ref.as_ref().map_or(true, |images| {
+            images.scene.borrow().get_image().extent != extent
+        });
+        if recreate {
+            *images_ref = Some(ChainImages {
+                scene: create_image(device.clone(), extent, vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_DST, "post_process_scene"),
+                horizontal: create_image(device.clone(), extent, vk::ImageUsageFlags::empty(), "post_process_horizontal"),
+                blurred: create_image(device.clone(), extent, vk::ImageUsageFlags::empty(), "post_process_blurred"),
+                output: create_image(device.clone(), extent, vk::ImageUsageFlags::TRANSFER_SRC, "post_process_output")
+            });
+        }
+        let images = images_ref.as_ref().unwrap();
+
+        let mut passes: Vec<PassType> = Vec::new();
+        let clear_color = settings.clear_color.unwrap_or([0.0, 0.0, 0.0, 1.0]);
+        passes.push(clear::clear_with_color(images.scene.clone(), vk::ImageAspectFlags::COLOR, clear_color));
+        passes.extend(self.ubo.execute(
+            frame,
+            device.clone(),
+            upload_buffer,
+            image_pool,
+            settings,
+            imgui_ui,
+            AttachmentReference::new(images.scene.clone(), vk::SampleCountFlags::TYPE_1)));
+
+        let alignment = upload_buffer.get_uniform_alignment();
+        let blur_offsets: Vec<vk::DeviceSize> = [[1, 0], [0, 1]].iter().map(|direction| {
+            upload_buffer.push(std::slice::from_ref(&BlurParams {
+                direction: *direction,
+                radius: self.blur_radius,
+                sigma: self.blur_sigma
+            }), alignment)
+        }).collect();
+        let sharpen_offset = upload_buffer.push(std::slice::from_ref(&SharpenParams {
+            amount: self.sharpen_amount
+        }), alignment);
+
+        let groups = (extent.width.div_ceil(8), extent.height.div_ceil(8));
+        passes.push(compute_pass("blur_horizontal", "separable_blur-comp.spv", groups, |builder| {
+            builder
+                .input(BlurInterface::PARAMS.bind_uniform::<BlurParams>(upload_buffer.get_buffer(), blur_offsets[0]))
+                .input(BlurInterface::SOURCE.bind(images.scene.clone()))
+                .output(BlurInterface::OUTPUT.bind(images.horizontal.clone()))
+        }));
+        passes.push(compute_pass("blur_vertical", "separable_blur-comp.spv", groups, |builder| {
+            builder
+                .input(BlurInterface::PARAMS.bind_uniform::<BlurParams>(upload_buffer.get_buffer(), blur_offsets[1]))
+                .input(BlurInterface::SOURCE.bind(images.horizontal.clone()))
+                .output(BlurInterface::OUTPUT.bind(images.blurred.clone()))
+        }));
+        passes.push(compute_pass("sharpen", "sharpen-comp.spv", groups, |builder| {
+            builder
+                .input(SharpenInterface::PARAMS.bind_uniform::<SharpenParams>(upload_buffer.get_buffer(), sharpen_offset))
+                .input(SharpenInterface::SCENE.bind(images.scene.clone()))
+                .input(SharpenInterface::BLURRED.bind(images.blurred.clone()))
+                .output(SharpenInterface::OUTPUT.bind(images.output.clone()))
+        }));
+
+        passes.push(blit::generate_pass(
+            images.output.clone(),
+            0,
+            back_buffer.resource_image.clone(),
+            0,
+            [IVec2::new(0, 0), IVec2::new(extent.width as i32, extent.height as i32)]));
+
+        passes
+    }
+}
+
+impl PostProcessExample {
+    pub fn new(device: Rc<RefCell<DeviceWrapper>>) -> Self {
+        PostProcessExample {
+            ubo: UboExample::new(device),
+            images: RefCell::new(None),
+            blur_radius: 6,
+            blur_sigma: 3.0,
+            sharpen_amount: 1.0
+        }
+    }
+}
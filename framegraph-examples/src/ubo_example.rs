@@ -6,24 +6,35 @@ use gpu_allocator::MemoryLocation;
 use imgui::Ui;
 use api_types::buffer::BufferCreateInfo;
 use api_types::device::{DeviceResource, DeviceWrapper};
+use api_types::image::ImageType;
+use api_types::upload_buffer::DynamicUploadBuffer;
 use context::render_context::RenderContext;
+use context::transient_image_pool::{TransientImageDesc, TransientImagePool};
 use context::vulkan_render_context::VulkanRenderContext;
-use framegraph::attachment::AttachmentReference;
+use framegraph::attachment::{AttachmentLoad, AttachmentReference};
 use framegraph::binding::{BindingInfo, BindingType, BufferBindingInfo, ResourceBinding};
+use framegraph::frame::Frame;
+use framegraph::uniform_layout::UniformBlock;
 use framegraph::graphics_pass_node::GraphicsPassNode;
 use framegraph::pass_type::PassType;
-use framegraph::pipeline::{BlendType, DepthStencilType, PipelineDescription, RasterizationType};
+use framegraph::pipeline::{BlendType, DepthStencilType, PipelineDescription, RasterizationState};
 use framegraph::shader;
+use framegraph::shader_variant::{ShaderVariant, ShaderVariantCache};
+use passes::resolve;
 use profiling::{enter_gpu_span, enter_span};
-use crate::example::Example;
+use crate::example::{Example, ExampleSettings};
 
+#[repr(C)]
+#[derive(UniformBlock)]
 pub struct UBO {
     pub color: [f32; 3]
 }
 pub struct UboExample {
     uniform_buffer: Rc<RefCell<DeviceResource>>,
     vert_shader: Rc<RefCell<shader::Shader>>,
-    frag_shader: Rc<RefCell<shader::Shader>>
+    // with and without PULSE
+    frag_variants: ShaderVariantCache,
+    pulse: bool
 }
 
 impl Example for UboExample {
@@ -31,7 +42,45 @@ impl Example for UboExample {
         "UBO"
     }
 
-    fn execute(&self, device: Rc<RefCell<DeviceWrapper>>, imgui_ui: &mut Ui, back_buffer: AttachmentReference) -> Vec<PassType> {
+    fn default_settings(&self) -> ExampleSettings {
+        ExampleSettings {
+            clear_color: Some([0.0, 0.0, 0.0, 1.0]),
+            msaa_samples: Some(vk::SampleCountFlags::TYPE_1),
+            ..Default::default()
+        }
+    }
+
+    fn ui(&mut self, ui: &Ui) {
+        ui.checkbox("Pulse", &mut self.pulse);
+    }
+
+    fn execute(&self, _frame: &mut Frame, device: Rc<RefCell<DeviceWrapper>>, _upload_buffer: &mut DynamicUploadBuffer, image_pool: &mut TransientImagePool, settings: &ExampleSettings, imgui_ui: &mut Ui, back_buffer: AttachmentReference) -> Vec<PassType> {
+        // when multisampling, the triangle is drawn into a pooled target of the same format and
+        // resolved into the back buffer afterwards. The pool hands out a new target whenever
+        // the sample count changes
+        let samples = settings.msaa_samples.unwrap_or(vk::SampleCountFlags::TYPE_1);
+        let (render_target, resolve_pass) = if samples == vk::SampleCountFlags::TYPE_1 {
+            (back_buffer, None)
+        } else {
+            let (extent, format) = {
+                let back_buffer_image = back_buffer.resource_image.borrow();
+                (back_buffer_image.get_image().extent, back_buffer_image.get_image().format)
+            };
+            let msaa_desc = TransientImageDesc {
+                extent,
+                format,
+                usage: vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC,
+                samples,
+                image_type: ImageType::Color
+            };
+            let msaa_image = image_pool.request_image(&msaa_desc, "ubo_example_msaa");
+            let clear_color = settings.clear_color.unwrap_or([0.0, 0.0, 0.0, 1.0]);
+            let msaa_target = AttachmentReference::new(msaa_image.clone(), samples)
+                .load(AttachmentLoad::Clear(vk::ClearValue { color: vk::ClearColorValue { float32: clear_color } }))
+                .transient();
+            (msaa_target, Some(resolve::generate_pass(msaa_image, back_buffer.resource_image.clone())))
+        };
+
         let vertex_state_create = vk::PipelineVertexInputStateCreateInfo::builder()
             .vertex_attribute_descriptions(&[])
             .vertex_binding_descriptions(&[]);
@@ -41,30 +90,30 @@ impl Example for UboExample {
         let pipeline_description = PipelineDescription::new(
             Default::default(),
             dynamic_states,
-            RasterizationType::Standard,
+            RasterizationState::default(),
             DepthStencilType::Disable,
             BlendType::None,
             "ubo",
             self.vert_shader.clone(),
-            self.frag_shader.clone());
+            self.frag_variants.get(&self.get_frag_variant()).expect("UBO fragment shader variant wasn't created"));
         
-        let ubo_binding = ResourceBinding {
-            resource: self.uniform_buffer.clone(),
-            binding_info: BindingInfo {
+        let ubo_binding = ResourceBinding::new(
+            self.uniform_buffer.clone(),
+            BindingInfo {
                 binding_type: BindingType::Buffer(BufferBindingInfo{
                     offset: 0,
-                    range: std::mem::size_of::<UBO>() as vk::DeviceSize }),
+                    range: std::mem::size_of::<UBO>() as vk::DeviceSize,
+                    layout: Some(UBO::layout()) }),
                 set: 0,
                 slot: 0,
                 stage: vk::PipelineStageFlags::ALL_GRAPHICS,
                 access: vk::AccessFlags::SHADER_READ
-            },
-        };
+            });
 
-        let passnode = GraphicsPassNode::builder("ubo_Pass".to_string())
+        let mut builder = GraphicsPassNode::builder("ubo_Pass".to_string())
             .pipeline_description(pipeline_description)
             .read(ubo_binding)
-            .render_target(back_buffer)
+            .render_target(render_target)
             .fill_commands(Box::new(
                 move |render_ctx: &VulkanRenderContext,
                      command_buffer: &vk::CommandBuffer | {
@@ -72,7 +121,7 @@ impl Example for UboExample {
                     enter_span!(tracing::Level::TRACE, "Draw Triangle");
                     let device = render_ctx.get_device();
                     let borrowed_device = device.borrow();
-                    enter_gpu_span!("Draw Triangle GPU", "examples", borrowed_device.get(), command_buffer, vk::PipelineStageFlags::ALL_GRAPHICS);
+                    enter_gpu_span!(render_ctx.get_gpu_profiler(), "Draw Triangle GPU", "examples", borrowed_device.get(), command_buffer, vk::PipelineStageFlags::ALL_GRAPHICS);
 
                     let viewport = vk::Viewport::builder()
                         .x(0.0)
@@ -107,11 +156,18 @@ impl Example for UboExample {
                             0);
                     }
                 }
-            ))
-            .build()
+            ));
+        // the triangle never changes, so its draw is recorded once and replayed on later
+        // frames. The pulsing variant reads the per-frame constants, which retained nodes can't
+        if !self.pulse {
+            builder = builder.retained(0);
+        }
+        let passnode = builder.build()
             .expect("Failed to create UBO passnode");
 
-        vec![PassType::Graphics(passnode)]
+        let mut passes = vec![PassType::Graphics(passnode)];
+        passes.extend(resolve_pass);
+        passes
     }
 }
 
@@ -146,13 +202,21 @@ impl UboExample {
 
         let vert_shader = Rc::new(RefCell::new(
             shader::create_shader_module_from_bytes(device.clone(), "ubo-vert", include_bytes!(concat!(env!("OUT_DIR"), "/shaders/ubo-vert.spv")))));
-        let frag_shader = Rc::new(RefCell::new(
-            shader::create_shader_module_from_bytes(device.clone(), "ubo-frag", include_bytes!(concat!(env!("OUT_DIR"), "/shaders/ubo-frag.spv")))));
+        let mut frag_variants = ShaderVariantCache::new();
+        frag_variants.get_or_create(device.clone(), &ShaderVariant::new("ubo-frag"),
+            |_| include_bytes!(concat!(env!("OUT_DIR"), "/shaders/ubo-frag.spv")));
+        frag_variants.get_or_create(device.clone(), &ShaderVariant::new("ubo-frag").define("PULSE", true),
+            |_| include_bytes!(concat!(env!("OUT_DIR"), "/shaders/ubo-frag.PULSE.spv")));
 
         UboExample {
             uniform_buffer: Rc::new(RefCell::new(ubo)),
             vert_shader,
-            frag_shader
+            frag_variants,
+            pulse: true
         }
     }
+
+    fn get_frag_variant(&self) -> ShaderVariant {
+        ShaderVariant::new("ubo-frag").define("PULSE", self.pulse)
+    }
 }
\ No newline at end of file
@@ -2,13 +2,37 @@ use std::cell::RefCell;
 use std::rc::Rc;
 use ash::vk;
 use api_types::device::{DeviceResource, ResourceType};
+use crate::binding::ResourceLifetime;
+
+/// How an attachment's existing contents are treated when its renderpass begins
+#[derive(Copy, Clone)]
+pub enum AttachmentLoad {
+    /// Deduced when the frame is compiled: contents are loaded if an earlier node in the
+    /// frame wrote them, or if they were kept from before the frame and the image isn't
+    /// one of the frame's transient resources. Otherwise they're discarded
+    Auto,
+    Load,
+    DontCare,
+    /// Clears color, or the depth of depth formats. The stencil of combined depth-stencil
+    /// formats is loaded
+    Clear(vk::ClearValue),
+    /// Clears both the depth and stencil of a combined depth-stencil format
+    ClearDepthStencil(vk::ClearValue)
+}
 
 #[derive(Clone)]
 pub struct AttachmentReference {
     pub resource_image: Rc<RefCell<DeviceResource>>,
     pub format: vk::Format,
     pub samples: vk::SampleCountFlags,
-    pub layout: vk::ImageLayout
+    pub layout: vk::ImageLayout,
+    pub load: AttachmentLoad,
+    pub lifetime: ResourceLifetime,
+    /// The load op `load` resolved to for the current frame
+    pub load_op: vk::AttachmentLoadOp,
+    /// DONT_CARE for transient attachments whose contents nothing reads after the node that
+    /// renders to them in the current frame, otherwise STORE
+    pub store_op: vk::AttachmentStoreOp
 }
 
 impl AttachmentReference {
@@ -27,7 +51,35 @@ impl AttachmentReference {
             resource_image: resource_image.clone(),
             format: resource_image.borrow().get_image().format,
             samples,
-            layout: vk::ImageLayout::UNDEFINED
+            layout: vk::ImageLayout::UNDEFINED,
+            load: AttachmentLoad::Auto,
+            lifetime: ResourceLifetime::Persistent,
+            load_op: vk::AttachmentLoadOp::DONT_CARE,
+            store_op: vk::AttachmentStoreOp::STORE
         }
     }
+
+    pub fn load(mut self, load: AttachmentLoad) -> Self {
+        self.load = load;
+        self
+    }
+
+    pub fn transient(mut self) -> Self {
+        self.lifetime = ResourceLifetime::Transient;
+        self
+    }
+
+    /// The value the attachment is cleared to if its load op is CLEAR
+    pub fn get_clear_value(&self) -> vk::ClearValue {
+        match self.load {
+            AttachmentLoad::Clear(clear_value) |
+            AttachmentLoad::ClearDepthStencil(clear_value) => clear_value,
+            _ => vk::ClearValue::default()
+        }
+    }
+
+    /// Whether the attachment's stencil is cleared along with its depth
+    pub fn clears_stencil(&self) -> bool {
+        matches!(self.load, AttachmentLoad::ClearDepthStencil(_))
+    }
 }
\ No newline at end of file
@@ -3,6 +3,125 @@ use std::rc::Rc;
 use ash::vk;
 use api_types::device::DeviceResource;
 
+/// The mip levels and array layers of an image a binding or barrier covers
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SubresourceRange {
+    pub base_mip_level: u32,
+    pub level_count: u32,
+    pub base_array_layer: u32,
+    pub layer_count: u32
+}
+
+impl SubresourceRange {
+    /// Every mip and layer of the image, however many it has
+    pub const WHOLE: SubresourceRange = SubresourceRange {
+        base_mip_level: 0,
+        level_count: vk::REMAINING_MIP_LEVELS,
+        base_array_layer: 0,
+        layer_count: vk::REMAINING_ARRAY_LAYERS
+    };
+
+    /// A single mip level of every layer
+    pub fn mip(level: u32) -> Self {
+        SubresourceRange {
+            base_mip_level: level,
+            level_count: 1,
+            ..Self::WHOLE
+        }
+    }
+
+    /// A single layer (e.g. a cube face) of every mip level
+    pub fn layer(layer: u32) -> Self {
+        SubresourceRange {
+            base_array_layer: layer,
+            layer_count: 1,
+            ..Self::WHOLE
+        }
+    }
+
+    pub fn is_whole(&self) -> bool {
+        *self == Self::WHOLE
+    }
+
+    // the exclusive ends of the range, where REMAINING counts run to u32::MAX
+    fn mip_end(&self) -> u32 { self.base_mip_level.saturating_add(self.level_count) }
+    fn layer_end(&self) -> u32 { self.base_array_layer.saturating_add(self.layer_count) }
+
+    fn from_bounds(mips: (u32, u32), layers: (u32, u32)) -> Self {
+        let count = |(start, end): (u32, u32), remaining: u32| {
+            if end == u32::MAX { remaining } else { end - start }
+        };
+        SubresourceRange {
+            base_mip_level: mips.0,
+            level_count: count(mips, vk::REMAINING_MIP_LEVELS),
+            base_array_layer: layers.0,
+            layer_count: count(layers, vk::REMAINING_ARRAY_LAYERS)
+        }
+    }
+
+    pub(crate) fn intersect(&self, other: &SubresourceRange) -> Option<SubresourceRange> {
+        let mips = (self.base_mip_level.max(other.base_mip_level), self.mip_end().min(other.mip_end()));
+        let layers = (self.base_array_layer.max(other.base_array_layer), self.layer_end().min(other.layer_end()));
+        (mips.0 < mips.1 && layers.0 < layers.1).then(|| Self::from_bounds(mips, layers))
+    }
+
+    /// The parts of this range outside `other`, as at most four disjoint ranges
+    pub(crate) fn subtract(&self, other: &SubresourceRange) -> Vec<SubresourceRange> {
+        let overlap = match self.intersect(other) {
+            Some(overlap) => overlap,
+            None => return vec![*self]
+        };
+
+        let mips = (self.base_mip_level, self.mip_end());
+        let layers = (self.base_array_layer, self.layer_end());
+        let overlap_mips = (overlap.base_mip_level, overlap.mip_end());
+        let overlap_layers = (overlap.base_array_layer, overlap.layer_end());
+        // every layer of the mips below and above the overlap, then the layers either side of
+        // it within the overlapped mips
+        [
+            ((mips.0, overlap_mips.0), layers),
+            ((overlap_mips.1, mips.1), layers),
+            (overlap_mips, (layers.0, overlap_layers.0)),
+            (overlap_mips, (overlap_layers.1, layers.1))
+        ].into_iter()
+            .filter(|(mips, layers)| mips.0 < mips.1 && layers.0 < layers.1)
+            .map(|(mips, layers)| Self::from_bounds(mips, layers))
+            .collect()
+    }
+
+    /// The part of this range within an image of `mip_levels` mips and `array_layers` layers,
+    /// or None if it lies entirely beyond them. REMAINING counts are kept
+    pub(crate) fn clamp(&self, mip_levels: u32, array_layers: u32) -> Option<SubresourceRange> {
+        if self.base_mip_level >= mip_levels || self.base_array_layer >= array_layers {
+            return None;
+        }
+        let clamp_count = |count: u32, remaining: u32, available: u32| {
+            if count == remaining { remaining } else { count.min(available) }
+        };
+        Some(SubresourceRange {
+            level_count: clamp_count(self.level_count, vk::REMAINING_MIP_LEVELS, mip_levels - self.base_mip_level),
+            layer_count: clamp_count(self.layer_count, vk::REMAINING_ARRAY_LAYERS, array_layers - self.base_array_layer),
+            ..*self
+        })
+    }
+
+    pub fn to_vk(&self, aspect_mask: vk::ImageAspectFlags) -> vk::ImageSubresourceRange {
+        vk::ImageSubresourceRange::builder()
+            .aspect_mask(aspect_mask)
+            .base_mip_level(self.base_mip_level)
+            .level_count(self.level_count)
+            .base_array_layer(self.base_array_layer)
+            .layer_count(self.layer_count)
+            .build()
+    }
+}
+
+impl Default for SubresourceRange {
+    fn default() -> Self {
+        Self::WHOLE
+    }
+}
+
 pub struct ImageBarrier {
     pub resource: Rc<RefCell<DeviceResource>>,
     pub source_stage: vk::PipelineStageFlags,
@@ -10,7 +129,8 @@ pub struct ImageBarrier {
     pub source_access: vk::AccessFlags,
     pub dest_access: vk::AccessFlags,
     pub old_layout: vk::ImageLayout,
-    pub new_layout: vk::ImageLayout
+    pub new_layout: vk::ImageLayout,
+    pub subresource: SubresourceRange
 }
 
 pub struct BufferBarrier {
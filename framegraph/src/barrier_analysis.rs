@@ -0,0 +1,282 @@
+//! Flags barriers which synchronize nothing, enabled with the "barrier-analysis" feature. Each
+//! node's barriers are checked as they're recorded and the redundant ones are added to the
+//! node's PassTiming and logged at debug level to the "barrier_analysis" target. The barriers of
+//! a renderpass group are recorded by its first node, so they're reported for that node.
+//!
+//! A barrier is flagged when either:
+//!  * neither side of it writes and it doesn't change the layout (a read after a read),
+//!  * an earlier barrier in the same batch covers an overlapping part of the same resource.
+//!    Barriers in a batch are recorded at the same point, so the later one can't order anything
+//!    the earlier one doesn't, and two layout transitions of the same subresource in one
+//!    vkCmdPipelineBarrier have no defined order, or
+//!  * it's identical to the last barrier an earlier node of the same command list recorded on an
+//!    overlapping part of the resource, e.g. between consecutive copy nodes, which are always
+//!    given barriers. Between copies writing the resource it's only needed if they write the
+//!    same bytes or texels, which the analysis can't see
+
+use std::collections::HashMap;
+use ash::vk;
+use crate::barrier::SubresourceRange;
+use crate::graph_core::is_write;
+use crate::vulkan_frame_graph::NodeBarriers;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RedundantBarrierKind {
+    /// Orders a read after a read without changing the layout
+    NoOp,
+    /// Overlaps an earlier barrier on the same resource recorded at the same point
+    Repeated,
+    /// Identical to the last barrier on the same part of the resource, recorded by an earlier
+    /// node of the command list
+    RepeatsEarlierNode
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct RedundantBarrier {
+    pub resource: u64,
+    pub kind: RedundantBarrierKind
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum BarrierRange {
+    Image(SubresourceRange),
+    // offset and exclusive end
+    Buffer(u64, u64)
+}
+
+impl BarrierRange {
+    fn overlaps(&self, other: &BarrierRange) -> bool {
+        match (self, other) {
+            (BarrierRange::Image(range), BarrierRange::Image(other)) => range.intersect(other).is_some(),
+            (BarrierRange::Buffer(start, end), BarrierRange::Buffer(other_start, other_end)) => {
+                start < other_end && other_start < end
+            },
+            _ => false
+        }
+    }
+}
+
+/// The parts of an ImageBarrier or BufferBarrier the analysis looks at
+#[derive(Copy, Clone, Debug)]
+struct BarrierSummary {
+    handle: u64,
+    source: (vk::AccessFlags, vk::PipelineStageFlags),
+    dest: (vk::AccessFlags, vk::PipelineStageFlags),
+    // None for buffers
+    layouts: Option<(vk::ImageLayout, vk::ImageLayout)>,
+    range: BarrierRange
+}
+
+impl BarrierSummary {
+    fn is_no_op(&self) -> bool {
+        let layout_changed = self.layouts.map_or(false, |(old, new)| old != new);
+        !layout_changed && !is_write(self.source.0, self.source.1) && !is_write(self.dest.0, self.dest.1)
+    }
+
+    fn overlaps(&self, other: &BarrierSummary) -> bool {
+        self.handle == other.handle && self.range.overlaps(&other.range)
+    }
+
+    fn repeats(&self, earlier: &BarrierSummary) -> bool {
+        self.overlaps(earlier)
+            && self.source == earlier.source
+            && self.dest == earlier.dest
+            && self.layouts == earlier.layouts
+    }
+}
+
+/// The last barrier on each part of each resource recorded by the nodes of a command list so
+/// far. Command lists are recorded into separate command buffers, so each starts a new history
+#[derive(Default)]
+pub(crate) struct BarrierHistory {
+    last_barriers: HashMap<u64, Vec<BarrierSummary>>
+}
+
+impl BarrierHistory {
+    fn repeated_by(&self, barrier: &BarrierSummary) -> bool {
+        self.last_barriers.get(&barrier.handle)
+            .map_or(false, |earlier| earlier.iter().any(|earlier| barrier.repeats(earlier)))
+    }
+
+    fn record(&mut self, barriers: &[BarrierSummary]) {
+        for barrier in barriers {
+            // only the most recent barrier on a part is compared against, so the earlier
+            // ones it overlaps are forgotten
+            let last_barriers = self.last_barriers.entry(barrier.handle).or_default();
+            last_barriers.retain(|earlier| !earlier.overlaps(barrier));
+            last_barriers.push(*barrier);
+        }
+    }
+}
+
+fn find_redundant(barriers: &[BarrierSummary], history: &BarrierHistory) -> Vec<RedundantBarrier> {
+    barriers.iter().enumerate().filter_map(|(index, barrier)| {
+        let repeated = barriers[..index].iter().any(|earlier| earlier.overlaps(barrier));
+        let kind = if repeated {
+            RedundantBarrierKind::Repeated
+        } else if barrier.is_no_op() {
+            RedundantBarrierKind::NoOp
+        } else if history.repeated_by(barrier) {
+            RedundantBarrierKind::RepeatsEarlierNode
+        } else {
+            return None
+        };
+        Some(RedundantBarrier { resource: barrier.handle, kind })
+    }).collect()
+}
+
+/// The redundant barriers of a single node, adding its barriers to the history of its command
+/// list
+pub(crate) fn analyze(barriers: &NodeBarriers, pass: &str, history: &mut BarrierHistory) -> Vec<RedundantBarrier> {
+    let images = barriers.image_barriers.iter().map(|barrier| BarrierSummary {
+        handle: barrier.resource.borrow().get_handle(),
+        source: (barrier.source_access, barrier.source_stage),
+        dest: (barrier.dest_access, barrier.dest_stage),
+        layouts: Some((barrier.old_layout, barrier.new_layout)),
+        range: BarrierRange::Image(barrier.subresource)
+    });
+    let buffers = barriers.buffer_barriers.iter().map(|barrier| BarrierSummary {
+        handle: barrier.resource.borrow().get_handle(),
+        source: (barrier.source_access, barrier.source_stage),
+        dest: (barrier.dest_access, barrier.dest_stage),
+        layouts: None,
+        range: BarrierRange::Buffer(barrier.offset as u64, (barrier.offset as u64).saturating_add(barrier.size as u64))
+    });
+    let summaries: Vec<BarrierSummary> = images.chain(buffers).collect();
+
+    let redundant = find_redundant(&summaries, history);
+    history.record(&summaries);
+    for barrier in &redundant {
+        log::debug!(target: "barrier_analysis", "Node {}: {:?} barrier on resource {}", pass, barrier.kind, barrier.resource);
+    }
+    redundant
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image_barrier(
+        handle: u64,
+        source: vk::AccessFlags,
+        dest: vk::AccessFlags,
+        layouts: (vk::ImageLayout, vk::ImageLayout),
+        subresource: SubresourceRange) -> BarrierSummary {
+
+        BarrierSummary {
+            handle,
+            source: (source, vk::PipelineStageFlags::FRAGMENT_SHADER),
+            dest: (dest, vk::PipelineStageFlags::FRAGMENT_SHADER),
+            layouts: Some(layouts),
+            range: BarrierRange::Image(subresource)
+        }
+    }
+
+    fn buffer_barrier(handle: u64, source: vk::AccessFlags, dest: vk::AccessFlags, offset: u64, size: u64) -> BarrierSummary {
+        BarrierSummary {
+            handle,
+            source: (source, vk::PipelineStageFlags::TRANSFER),
+            dest: (dest, vk::PipelineStageFlags::TRANSFER),
+            layouts: None,
+            range: BarrierRange::Buffer(offset, offset + size)
+        }
+    }
+
+    #[test]
+    fn read_after_read_in_the_same_layout_is_a_no_op() {
+        let read_only = (vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+        let barriers = [
+            image_barrier(1, vk::AccessFlags::SHADER_READ, vk::AccessFlags::SHADER_READ, read_only, SubresourceRange::WHOLE),
+            // a layout transition and a read after a write are both needed
+            image_barrier(2, vk::AccessFlags::SHADER_READ, vk::AccessFlags::SHADER_READ,
+                (vk::ImageLayout::GENERAL, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL), SubresourceRange::WHOLE),
+            image_barrier(3, vk::AccessFlags::SHADER_WRITE, vk::AccessFlags::SHADER_READ, read_only, SubresourceRange::WHOLE),
+            buffer_barrier(4, vk::AccessFlags::TRANSFER_READ, vk::AccessFlags::TRANSFER_READ, 0, 64)
+        ];
+
+        assert_eq!(find_redundant(&barriers, &BarrierHistory::default()), vec![
+            RedundantBarrier { resource: 1, kind: RedundantBarrierKind::NoOp },
+            RedundantBarrier { resource: 4, kind: RedundantBarrierKind::NoOp }
+        ]);
+    }
+
+    #[test]
+    fn overlapping_barriers_in_a_batch_are_repeated() {
+        let transition = (vk::ImageLayout::UNDEFINED, vk::ImageLayout::TRANSFER_DST_OPTIMAL);
+        let barriers = [
+            image_barrier(1, vk::AccessFlags::NONE, vk::AccessFlags::TRANSFER_WRITE, transition, SubresourceRange::WHOLE),
+            image_barrier(1, vk::AccessFlags::TRANSFER_WRITE, vk::AccessFlags::TRANSFER_WRITE, transition, SubresourceRange::mip(2)),
+            // separate mips and separate parts of a buffer don't overlap
+            image_barrier(2, vk::AccessFlags::NONE, vk::AccessFlags::TRANSFER_WRITE, transition, SubresourceRange::mip(0)),
+            image_barrier(2, vk::AccessFlags::NONE, vk::AccessFlags::TRANSFER_WRITE, transition, SubresourceRange::mip(1)),
+            buffer_barrier(3, vk::AccessFlags::NONE, vk::AccessFlags::TRANSFER_WRITE, 0, 64),
+            buffer_barrier(3, vk::AccessFlags::NONE, vk::AccessFlags::TRANSFER_WRITE, 64, 64),
+            buffer_barrier(3, vk::AccessFlags::TRANSFER_WRITE, vk::AccessFlags::TRANSFER_WRITE, 32, 64)
+        ];
+
+        assert_eq!(find_redundant(&barriers, &BarrierHistory::default()), vec![
+            RedundantBarrier { resource: 1, kind: RedundantBarrierKind::Repeated },
+            RedundantBarrier { resource: 3, kind: RedundantBarrierKind::Repeated }
+        ]);
+    }
+
+    fn copy_write(handle: u64, source: vk::AccessFlags) -> BarrierSummary {
+        buffer_barrier(handle, source, vk::AccessFlags::TRANSFER_WRITE, 0, 256)
+    }
+
+    #[test]
+    fn barriers_repeating_an_earlier_node_are_flagged() {
+        let mut history = BarrierHistory::default();
+        // the first copy's barrier is needed, the second copy's repeats it
+        let first = [copy_write(1, vk::AccessFlags::TRANSFER_WRITE)];
+        assert!(find_redundant(&first, &history).is_empty());
+        history.record(&first);
+
+        let second = [copy_write(1, vk::AccessFlags::TRANSFER_WRITE), copy_write(2, vk::AccessFlags::TRANSFER_WRITE)];
+        assert_eq!(find_redundant(&second, &history), vec![
+            RedundantBarrier { resource: 1, kind: RedundantBarrierKind::RepeatsEarlierNode }
+        ]);
+    }
+
+    #[test]
+    fn only_the_last_barrier_on_a_part_is_repeated() {
+        let to_dst = (vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL, vk::ImageLayout::TRANSFER_DST_OPTIMAL);
+        let to_read = (vk::ImageLayout::TRANSFER_DST_OPTIMAL, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+        let copy_dst = image_barrier(1, vk::AccessFlags::SHADER_READ, vk::AccessFlags::TRANSFER_WRITE, to_dst, SubresourceRange::WHOLE);
+        let sampled = image_barrier(1, vk::AccessFlags::TRANSFER_WRITE, vk::AccessFlags::SHADER_READ, to_read, SubresourceRange::WHOLE);
+
+        let mut history = BarrierHistory::default();
+        history.record(&[copy_dst]);
+        history.record(&[sampled]);
+        // the image has been transitioned back since, so transitioning it again is needed
+        assert!(find_redundant(&[copy_dst], &history).is_empty());
+    }
+
+    #[test]
+    fn barriers_on_other_parts_or_with_other_accesses_are_not_repeats() {
+        let transition = (vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL);
+        let mut history = BarrierHistory::default();
+        history.record(&[
+            image_barrier(1, vk::AccessFlags::SHADER_WRITE, vk::AccessFlags::SHADER_WRITE, transition, SubresourceRange::mip(0)),
+            buffer_barrier(2, vk::AccessFlags::TRANSFER_WRITE, vk::AccessFlags::TRANSFER_WRITE, 0, 64)
+        ]);
+
+        let barriers = [
+            image_barrier(1, vk::AccessFlags::SHADER_WRITE, vk::AccessFlags::SHADER_WRITE, transition, SubresourceRange::mip(1)),
+            buffer_barrier(2, vk::AccessFlags::TRANSFER_WRITE, vk::AccessFlags::TRANSFER_WRITE, 64, 64),
+            buffer_barrier(3, vk::AccessFlags::TRANSFER_WRITE, vk::AccessFlags::TRANSFER_READ, 0, 64)
+        ];
+        assert!(find_redundant(&barriers, &history).is_empty());
+    }
+
+    #[test]
+    fn new_histories_have_no_earlier_barriers() {
+        let barrier = copy_write(1, vk::AccessFlags::TRANSFER_WRITE);
+        let mut history = BarrierHistory::default();
+        history.record(&[barrier]);
+        assert_eq!(find_redundant(&[barrier], &history).len(), 1);
+        // a new command list
+        let history = BarrierHistory::default();
+        assert!(find_redundant(&[barrier], &history).is_empty());
+    }
+}
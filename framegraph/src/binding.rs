@@ -2,16 +2,49 @@ use std::cell::RefCell;
 use std::fmt::{Debug, Formatter};
 use std::rc::Rc;
 use ash::vk;
-use api_types::device::DeviceResource;
+use serde::Deserialize;
+use api_types::device::{DeviceResource, ResourceError, ResourceType};
+use crate::barrier::SubresourceRange;
+use crate::graph_core::is_write;
+use crate::uniform_layout::{UniformBlock, UniformLayout};
+
+pub use framegraph_derive::ShaderInterface;
+
+/// Who owns a resource referenced by a binding or attachment
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+pub enum ResourceLifetime {
+    /// Owned by the application, so its contents may be carried from one frame to the next
+    #[default]
+    Persistent,
+    /// Owned by the Frame and recycled once the Frame is dropped (see Frame::request_transient_image).
+    /// Its contents never outlive the Frame, so it must be written before it's read and can't be
+    /// referenced as a persistent resource by later frames. Images are only reused across
+    /// frames by the pool they came from; transients within a Frame never alias each other
+    Transient
+}
 
 #[derive(Clone)]
 pub struct ImageBindingInfo {
-    pub layout: vk::ImageLayout
+    pub layout: vk::ImageLayout,
+    /// The mips and layers barriers are issued for. Descriptors still use the image's view,
+    /// so a partial range is for shaders or commands which only touch those subresources
+    pub subresource: SubresourceRange
+}
+
+impl ImageBindingInfo {
+    /// A binding of every mip and layer of the image in `layout`
+    pub const fn new(layout: vk::ImageLayout) -> Self {
+        ImageBindingInfo {
+            layout,
+            subresource: SubresourceRange::WHOLE
+        }
+    }
 }
 
 impl Debug for ImageBindingInfo {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("ImageBindingInfo")
+            .field("subresource", &self.subresource)
             .finish()
     }
 }
@@ -19,8 +52,23 @@ impl Debug for ImageBindingInfo {
 #[derive(Clone)]
 pub struct BufferBindingInfo {
     pub offset: vk::DeviceSize,
-    pub range: vk::DeviceSize
+    pub range: vk::DeviceSize,
+    /// The struct uploaded into a uniform buffer, which debug builds check against the layout
+    /// of the shader's uniform block
+    pub layout: Option<&'static UniformLayout>
 }
+
+impl BufferBindingInfo {
+    /// Binds `range` bytes from `offset`, without a uniform layout to check
+    pub const fn new(offset: vk::DeviceSize, range: vk::DeviceSize) -> Self {
+        BufferBindingInfo {
+            offset,
+            range,
+            layout: None
+        }
+    }
+}
+
 impl Debug for BufferBindingInfo {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("BufferBindingInfo")
@@ -36,6 +84,41 @@ pub enum BindingType {
     Image(ImageBindingInfo)
 }
 
+/// How often the resources of a descriptor set change, which decides the set shaders declare
+/// them in. Each pass's own bindings stay in set 0, so shaders which don't split their bindings
+/// by frequency need no changes
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BindingFrequency {
+    /// A node's inputs and outputs, written for every pass
+    PerPass,
+    /// Resources shared by every pass of a frame, such as the camera. They're written once per
+    /// frame and bound for each pass whose pipeline declares the set (see Frame::bind_per_frame)
+    PerFrame,
+    /// Reserved for sets a pass binds itself between its draws. Nodes can't bind resources in
+    /// it, and it's never allocated or written for them
+    PerDraw
+}
+
+impl BindingFrequency {
+    /// The set shaders declare bindings of this frequency in
+    pub const fn get_set(self) -> u64 {
+        match self {
+            BindingFrequency::PerPass => 0,
+            BindingFrequency::PerFrame => 1,
+            BindingFrequency::PerDraw => 2
+        }
+    }
+
+    /// Sets past the per-draw set are written for every pass, like set 0
+    pub fn from_set(set: u64) -> Self {
+        match set {
+            1 => BindingFrequency::PerFrame,
+            2 => BindingFrequency::PerDraw,
+            _ => BindingFrequency::PerPass
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct BindingInfo {
     pub binding_type: BindingType,
@@ -58,5 +141,243 @@ impl Debug for BindingInfo {
 #[derive(Clone, Debug)]
 pub struct ResourceBinding {
     pub resource: Rc<RefCell<DeviceResource>>,
-    pub binding_info: BindingInfo
+    pub binding_info: BindingInfo,
+    pub lifetime: ResourceLifetime
+}
+
+impl ResourceBinding {
+    /// A binding of an application-owned resource. Chain `transient()` for a resource requested
+    /// from the Frame
+    pub fn new(resource: Rc<RefCell<DeviceResource>>, binding_info: BindingInfo) -> Self {
+        ResourceBinding {
+            resource,
+            binding_info,
+            lifetime: ResourceLifetime::Persistent
+        }
+    }
+
+    pub fn transient(mut self) -> Self {
+        self.lifetime = ResourceLifetime::Transient;
+        self
+    }
+
+    /// Whether the bound resource is resolved as the kind of resource the binding expects
+    pub fn validate(&self) -> Result<(), ResourceError> {
+        let resource = self.resource.borrow();
+        match &self.binding_info.binding_type {
+            BindingType::Image(_) => resource.try_get_image().map(|_| ()),
+            BindingType::Buffer(_) => resource.try_get_buffer().map(|_| ())
+        }
+    }
+}
+
+/// A resource a node accesses without a descriptor binding, such as vertex, index and indirect
+/// buffers. The node is ordered after earlier writers of the resource (and, if `access` includes
+/// a write, before later readers) and the linker inserts the barriers between them. Images
+/// are transitioned to the GENERAL layout
+#[derive(Clone, Debug)]
+pub struct ResourceDependency {
+    pub resource: Rc<RefCell<DeviceResource>>,
+    pub access: vk::AccessFlags,
+    pub stage: vk::PipelineStageFlags
+}
+
+impl ResourceDependency {
+    pub fn new(
+        resource: Rc<RefCell<DeviceResource>>,
+        access: vk::AccessFlags,
+        stage: vk::PipelineStageFlags) -> Self {
+        ResourceDependency {
+            resource,
+            access,
+            stage
+        }
+    }
+
+    pub fn is_write(&self) -> bool {
+        is_write(self.access, self.stage)
+    }
+
+    /// The binding the linker derives this dependency's barriers from. It never has a
+    /// descriptor written for it
+    pub(crate) fn to_binding(&self) -> ResourceBinding {
+        let binding_type = match self.resource.borrow().resource_type.as_ref() {
+            Some(ResourceType::Image(_)) => BindingType::Image(ImageBindingInfo::new(vk::ImageLayout::GENERAL)),
+            Some(ResourceType::Buffer(_)) => BindingType::Buffer(BufferBindingInfo::new(0, vk::WHOLE_SIZE)),
+            None => panic!("Invalid resource dependency")
+        };
+
+        ResourceBinding::new(
+            self.resource.clone(),
+            BindingInfo {
+                binding_type,
+                set: 0,
+                slot: 0,
+                stage: self.stage,
+                access: self.access
+            })
+    }
+}
+
+
+/// The kind of resource bound through a BindingSlot. Implemented by the field types of structs
+/// deriving ShaderInterface
+pub trait SlotKind {
+    /// Used as is by BindingSlot::bind; buffers are bound whole
+    const BINDING_TYPE: BindingType;
+    const ACCESS: vk::AccessFlags;
+}
+
+/// A uniform buffer
+pub struct UniformBuffer;
+
+/// A storage buffer, read and written
+pub struct StorageBuffer;
+
+/// A combined image sampler, read in SHADER_READ_ONLY_OPTIMAL
+pub struct SampledImage;
+
+/// A storage image, read and written in GENERAL
+pub struct StorageImage;
+
+impl SlotKind for UniformBuffer {
+    const BINDING_TYPE: BindingType = BindingType::Buffer(BufferBindingInfo::new(0, vk::WHOLE_SIZE));
+    const ACCESS: vk::AccessFlags = vk::AccessFlags::UNIFORM_READ;
+}
+
+impl SlotKind for StorageBuffer {
+    const BINDING_TYPE: BindingType = BindingType::Buffer(BufferBindingInfo::new(0, vk::WHOLE_SIZE));
+    const ACCESS: vk::AccessFlags = vk::AccessFlags::from_raw(
+        vk::AccessFlags::SHADER_READ.as_raw() | vk::AccessFlags::SHADER_WRITE.as_raw());
+}
+
+impl SlotKind for SampledImage {
+    const BINDING_TYPE: BindingType = BindingType::Image(ImageBindingInfo::new(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL));
+    const ACCESS: vk::AccessFlags = vk::AccessFlags::SHADER_READ;
+}
+
+impl SlotKind for StorageImage {
+    const BINDING_TYPE: BindingType = BindingType::Image(ImageBindingInfo::new(vk::ImageLayout::GENERAL));
+    const ACCESS: vk::AccessFlags = vk::AccessFlags::from_raw(
+        vk::AccessFlags::SHADER_READ.as_raw() | vk::AccessFlags::SHADER_WRITE.as_raw());
+}
+
+/// A shader binding whose set, slot, stages and kind are fixed at compile time, so they can't
+/// drift from the shader's declarations. Usually generated with `#[derive(ShaderInterface)]`
+/// from a struct describing the shader's interface:
+///
+/// ```ignore
+/// #[derive(ShaderInterface)]
+/// struct ModelInterface {
+///     #[binding(set = 0, slot = 0, stage = VERTEX_SHADER)]
+///     mvp: UniformBuffer,
+///     #[binding(set = 0, slot = 1, stage = FRAGMENT_SHADER)]
+///     albedo: SampledImage
+/// }
+///
+/// let node = GraphicsPassNode::builder("model".to_string())
+///     .read(ModelInterface::MVP.bind_uniform::<MVP>(upload_buffer.get_buffer(), mvp_offset))
+///     .read(ModelInterface::ALBEDO.bind(albedo))
+/// ```
+#[derive(Clone, Debug)]
+pub struct BindingSlot {
+    binding_info: BindingInfo
+}
+
+impl BindingSlot {
+    pub const fn new(binding_info: BindingInfo) -> Self {
+        BindingSlot {
+            binding_info
+        }
+    }
+
+    pub fn get_binding_info(&self) -> &BindingInfo {
+        &self.binding_info
+    }
+
+    /// A persistent binding of `resource` to this slot
+    pub fn bind(&self, resource: Rc<RefCell<DeviceResource>>) -> ResourceBinding {
+        ResourceBinding::new(
+            resource,
+            self.binding_info.clone())
+    }
+
+    /// Binds `range` bytes of `resource` starting at `offset`. Panics if the slot isn't a buffer
+    pub fn bind_range(
+        &self,
+        resource: Rc<RefCell<DeviceResource>>,
+        offset: vk::DeviceSize,
+        range: vk::DeviceSize) -> ResourceBinding {
+
+        let mut binding = self.bind(resource);
+        match &mut binding.binding_info.binding_type {
+            BindingType::Buffer(buffer) => {
+                buffer.offset = offset;
+                buffer.range = range;
+            },
+            BindingType::Image(_) => panic!("Only buffer slots can be bound to a range")
+        }
+        binding
+    }
+
+    /// Binds a `T` uploaded into `resource` at `offset`, so debug builds can check `T`'s layout
+    /// against the shader's uniform block. Panics if the slot isn't a buffer
+    pub fn bind_uniform<T: UniformBlock>(
+        &self,
+        resource: Rc<RefCell<DeviceResource>>,
+        offset: vk::DeviceSize) -> ResourceBinding {
+
+        let mut binding = self.bind_range(resource, offset, std::mem::size_of::<T>() as vk::DeviceSize);
+        if let BindingType::Buffer(buffer) = &mut binding.binding_info.binding_type {
+            buffer.layout = Some(T::layout());
+        }
+        binding
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(ShaderInterface)]
+    #[allow(dead_code)]
+    struct TestInterface {
+        #[binding(set = 0, slot = 0, stage = VERTEX_SHADER)]
+        mvp: UniformBuffer,
+        #[binding(set = 0, slot = 1, stage = FRAGMENT_SHADER)]
+        albedo: SampledImage,
+        #[binding(set = 1, slot = 0, stage = FRAGMENT_SHADER | COMPUTE_SHADER, access = SHADER_READ)]
+        history: StorageImage
+    }
+
+    #[test]
+    fn slots_follow_their_attributes() {
+        let mvp = TestInterface::MVP.get_binding_info();
+        assert_eq!((mvp.set, mvp.slot), (0, 0));
+        assert_eq!(mvp.stage, vk::PipelineStageFlags::VERTEX_SHADER);
+        assert_eq!(mvp.access, vk::AccessFlags::UNIFORM_READ);
+        assert!(matches!(mvp.binding_type, BindingType::Buffer(BufferBindingInfo { range: vk::WHOLE_SIZE, .. })));
+
+        let albedo = TestInterface::ALBEDO.get_binding_info();
+        assert_eq!((albedo.set, albedo.slot), (0, 1));
+        assert!(matches!(albedo.binding_type,
+            BindingType::Image(ImageBindingInfo { layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL, .. })));
+    }
+
+    #[test]
+    fn stage_flags_combine_and_access_can_be_overridden() {
+        let history = TestInterface::HISTORY.get_binding_info();
+        assert_eq!((history.set, history.slot), (1, 0));
+        assert_eq!(history.stage, vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::COMPUTE_SHADER);
+        assert_eq!(history.access, vk::AccessFlags::SHADER_READ);
+        assert!(matches!(history.binding_type, BindingType::Image(ImageBindingInfo { layout: vk::ImageLayout::GENERAL, .. })));
+    }
+
+    #[test]
+    fn slots_are_listed_in_declaration_order() {
+        let slots: Vec<(u64, u32)> = TestInterface::SLOTS.iter()
+            .map(|slot| (slot.get_binding_info().set, slot.get_binding_info().slot))
+            .collect();
+        assert_eq!(slots, vec![(0, 0), (0, 1), (1, 0)]);
+    }
 }
@@ -0,0 +1,110 @@
+//! Programmatic GPU captures. A [`CaptureRequest`] made through the frame graph's
+//! [`GpuCapture`] applies to the next frame started: with the `renderdoc` feature enabled and
+//! the application running under RenderDoc, that frame is captured in-application. In any
+//! case the requested frame or pass is wrapped in a `Capture: ...` debug label region, so it
+//! can be found quickly in RenderDoc's event browser or an Nsight Graphics frame capture.
+
+use crate::graph_debug::graph_debug;
+
+/// What the next frame's capture should cover
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CaptureRequest {
+    Frame,
+    /// The whole frame is still captured, but only the named pass is labeled
+    Pass(String)
+}
+
+pub struct GpuCapture {
+    #[cfg(feature = "renderdoc")]
+    renderdoc: Option<renderdoc::RenderDoc<renderdoc::V141>>,
+    pending: Option<CaptureRequest>,
+    active: Option<CaptureRequest>
+}
+
+impl std::fmt::Debug for GpuCapture {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GpuCapture")
+            .field("renderdoc available", &self.is_renderdoc_available())
+            .field("pending", &self.pending)
+            .field("active", &self.active)
+            .finish()
+    }
+}
+
+impl GpuCapture {
+    pub fn new() -> Self {
+        GpuCapture {
+            #[cfg(feature = "renderdoc")]
+            renderdoc: match renderdoc::RenderDoc::new() {
+                Ok(renderdoc) => Some(renderdoc),
+                Err(error) => {
+                    log::info!(target: "capture", "RenderDoc is unavailable, captures will only be labeled: {}", error);
+                    None
+                }
+            },
+            pending: None,
+            active: None
+        }
+    }
+
+    /// Whether captures are taken in-application, rather than only labeled
+    pub fn is_renderdoc_available(&self) -> bool {
+        #[cfg(feature = "renderdoc")]
+        {
+            self.renderdoc.is_some()
+        }
+        #[cfg(not(feature = "renderdoc"))]
+        {
+            false
+        }
+    }
+
+    /// Captures the next frame started. Replaces any request which hasn't started yet
+    pub fn request(&mut self, request: CaptureRequest) {
+        self.pending = Some(request);
+    }
+
+    pub fn get_active(&self) -> Option<&CaptureRequest> {
+        self.active.as_ref()
+    }
+
+    pub(crate) fn begin_frame(&mut self) {
+        self.active = self.pending.take();
+        if let Some(request) = &self.active {
+            log::info!(target: "capture", "Capturing {:?}", request);
+            graph_debug!(?request, "capture started");
+            // RenderDoc captures from the last present up to the next, which is the frame
+            // about to be recorded
+            #[cfg(feature = "renderdoc")]
+            if let Some(renderdoc) = &mut self.renderdoc {
+                renderdoc.trigger_capture();
+            }
+        }
+    }
+
+    pub(crate) fn end_frame(&mut self) {
+        self.active = None;
+    }
+
+    /// The label for the region wrapping the whole frame, if it's being captured
+    pub(crate) fn get_frame_label(&self) -> Option<&'static str> {
+        match &self.active {
+            Some(CaptureRequest::Frame) => Some("Capture: Frame"),
+            _ => None
+        }
+    }
+
+    /// The label for the region wrapping `pass_name`, if it's being captured
+    pub(crate) fn get_pass_label(&self, pass_name: &str) -> Option<String> {
+        match &self.active {
+            Some(CaptureRequest::Pass(name)) if name == pass_name => Some(format!("Capture: {}", pass_name)),
+            _ => None
+        }
+    }
+}
+
+impl Default for GpuCapture {
+    fn default() -> Self {
+        GpuCapture::new()
+    }
+}
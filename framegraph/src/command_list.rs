@@ -1,5 +1,6 @@
 use ash::vk;
 use petgraph::graph::NodeIndex;
+use context::vulkan_render_context::VulkanRenderContext;
 
 pub struct QueueWait {
     pub wait_stage_mask: vk::PipelineStageFlags
@@ -17,4 +18,17 @@ impl CommandList {
             wait: None,
         }
     }
+}
+
+/// Supplies VulkanFrameGraph::record with a command buffer, in the recording state, for the
+/// command list at the given index
+pub type CommandBufferProvider<'a> = dyn FnMut(&mut VulkanRenderContext, usize) -> vk::CommandBuffer + 'a;
+
+/// A command list recorded by VulkanFrameGraph::record
+#[derive(Copy, Clone, Debug)]
+pub struct RecordedCommandList {
+    pub command_buffer: vk::CommandBuffer,
+    /// Stages of this command buffer which must wait for the previous command list to
+    /// complete, e.g. with a semaphore between their submits. Unused for the first list
+    pub wait_stage_mask: vk::PipelineStageFlags
 }
\ No newline at end of file
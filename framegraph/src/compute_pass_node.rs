@@ -1,18 +1,47 @@
 use std::cell::RefCell;
 use std::fmt::{Debug, Formatter};
 use std::rc::Rc;
+use ash::vk;
 use ash::vk::CommandBuffer;
+use api_types::device::DeviceResource;
+use api_types::name::Name;
 use context::vulkan_render_context::VulkanRenderContext;
-use crate::binding::ResourceBinding;
-use crate::pass_node::{FillCallback, PassNode};
+use crate::binding::{ResourceBinding, ResourceDependency, ResourceLifetime};
+use crate::pass_node::{FillCallback, PassHandle, PassNode};
 use crate::pipeline::ComputePipelineDescription;
+use crate::node_arena;
+
+/// A dispatch the executor records for a compute node after its fill callback
+#[derive(Clone, Debug)]
+pub enum ComputeDispatch {
+    Groups { x: u32, y: u32, z: u32 },
+    /// Enough groups to cover `extent` with the local size reflected from the pipeline's shader
+    Extent(vk::Extent3D),
+    /// Group counts read from a vk::DispatchIndirectCommand at `offset` in `buffer`
+    Indirect { buffer: Rc<RefCell<DeviceResource>>, offset: vk::DeviceSize }
+}
+
+impl ComputeDispatch {
+    /// Enough groups of `workgroup_size` invocations to cover every texel of `extent`
+    pub fn covering(extent: vk::Extent3D, workgroup_size: [u32; 3]) -> Self {
+        ComputeDispatch::Groups {
+            x: extent.width.div_ceil(workgroup_size[0]),
+            y: extent.height.div_ceil(workgroup_size[1]),
+            z: extent.depth.div_ceil(workgroup_size[2])
+        }
+    }
+}
 
 pub struct ComputePassNode {
     pub inputs: Vec<ResourceBinding>,
     pub outputs: Vec<ResourceBinding>,
+    pub dependencies: Vec<ResourceDependency>,
     pub fill_callback: Box<FillCallback>,
+    pub dispatch: Option<ComputeDispatch>,
     pub pipeline_description: ComputePipelineDescription,
-    name: String
+    priority: i32,
+    execute_after: Vec<PassHandle>,
+    name: Name
 }
 
 impl Debug for ComputePassNode {
@@ -21,15 +50,20 @@ impl Debug for ComputePassNode {
             .field("name", &self.name)
             .field("inputs", &self.inputs)
             .field("outputs", &self.outputs)
+            .field("dependencies", &self.dependencies)
+            .field("dispatch", &self.dispatch)
             .field("pipeline description", &self.pipeline_description)
             .finish()
     }
 }
 
 impl ComputePassNode {
-    pub fn builder(name: String) -> ComputePassNodeBuilder {
+    pub fn builder(name: impl Into<Name>) -> ComputePassNodeBuilder {
         ComputePassNodeBuilder {
-            name,
+            name: name.into(),
+            inputs: node_arena::take_bindings(),
+            outputs: node_arena::take_bindings(),
+            dependencies: node_arena::take_dependencies(),
             ..Default::default()
         }
     }
@@ -46,12 +80,19 @@ impl PassNode for ComputePassNode {
        &self.name
     }
 
+    fn get_interned_name(&self) -> Name {
+        self.name
+    }
+
     fn get_reads(&self) -> Vec<u64> {
         let mut reads: Vec<u64> = Vec::new();
         reads.reserve(self.inputs.len());
         for input in &self.inputs {
             reads.push(input.resource.borrow().get_handle());
         }
+        for dependency in &self.dependencies {
+            reads.push(dependency.resource.borrow().get_handle());
+        }
         reads
     }
 
@@ -61,17 +102,44 @@ impl PassNode for ComputePassNode {
         for output in &self.outputs {
             writes.push(output.resource.borrow().get_handle());
         }
+        for dependency in self.dependencies.iter().filter(|dependency| dependency.is_write()) {
+            writes.push(dependency.resource.borrow().get_handle());
+        }
         writes
     }
+
+    fn get_dependencies(&self) -> &[ResourceDependency] {
+        &self.dependencies
+    }
+
+    fn get_transients(&self) -> Vec<u64> {
+        self.inputs.iter()
+            .chain(&self.outputs)
+            .filter(|binding| binding.lifetime == ResourceLifetime::Transient)
+            .map(|binding| binding.resource.borrow().get_handle())
+            .collect()
+    }
+
+    fn get_priority(&self) -> i32 {
+        self.priority
+    }
+
+    fn get_execute_after(&self) -> &[PassHandle] {
+        &self.execute_after
+    }
 }
 
 #[derive(Default)]
 pub struct ComputePassNodeBuilder {
-    name: String,
+    name: Name,
     inputs: Vec<ResourceBinding>,
     outputs: Vec<ResourceBinding>,
+    dependencies: Vec<ResourceDependency>,
     pipeline_description: Option<ComputePipelineDescription>,
     fill_callback: Option<Box<FillCallback>>,
+    dispatch: Option<ComputeDispatch>,
+    priority: i32,
+    execute_after: Vec<PassHandle>
 }
 
 impl ComputePassNodeBuilder {
@@ -90,21 +158,74 @@ impl ComputePassNodeBuilder {
         self
     }
 
+    /// Uses `resource` without binding it to a descriptor, e.g. an indirect dispatch buffer
+    /// (see ResourceDependency)
+    pub fn depends_on(
+        mut self,
+        resource: Rc<RefCell<DeviceResource>>,
+        access: vk::AccessFlags,
+        stage: vk::PipelineStageFlags) -> Self {
+        self.dependencies.push(ResourceDependency::new(resource, access, stage));
+        self
+    }
+
+    /// Optional with a dispatch, in which case it's recorded before the dispatch
     pub fn fill_commands(mut self, fill_callback: Box<FillCallback>) -> Self {
         self.fill_callback = Some(fill_callback);
         self
     }
 
-    pub fn build(mut self) -> Result<ComputePassNode, &'static str> {
-        let inputs_len = self.inputs.len();
-        let outputs_len = self.outputs.len();
+    pub fn dispatch(mut self, x: u32, y: u32, z: u32) -> Self {
+        self.dispatch = Some(ComputeDispatch::Groups { x, y, z });
+        self
+    }
+
+    /// See ComputeDispatch::Extent
+    pub fn dispatch_for_extent(mut self, extent: vk::Extent3D) -> Self {
+        self.dispatch = Some(ComputeDispatch::Extent(extent));
+        self
+    }
+
+    /// See ComputeDispatch::covering
+    pub fn dispatch_covering(mut self, extent: vk::Extent3D, workgroup_size: [u32; 3]) -> Self {
+        self.dispatch = Some(ComputeDispatch::covering(extent, workgroup_size));
+        self
+    }
 
-        if let Some(_) = &self.fill_callback {
+    /// Reads the group counts from `buffer`, which is added as a dependency so writes to it
+    /// earlier in the frame are waited on
+    pub fn dispatch_indirect(mut self, buffer: Rc<RefCell<DeviceResource>>, offset: vk::DeviceSize) -> Self {
+        self.dependencies.push(ResourceDependency::new(
+            buffer.clone(),
+            vk::AccessFlags::INDIRECT_COMMAND_READ,
+            vk::PipelineStageFlags::DRAW_INDIRECT));
+        self.dispatch = Some(ComputeDispatch::Indirect { buffer, offset });
+        self
+    }
+
+    /// e.g. to start long dispatches ahead of graphics work they share nothing with
+    pub fn priority(mut self, priority: i32) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// See PassNodeBuilder::after
+    pub fn after(mut self, node: PassHandle) -> Self {
+        self.execute_after.push(node);
+        self
+    }
+
+    pub fn build(mut self) -> Result<ComputePassNode, &'static str> {
+        if self.fill_callback.is_some() || self.dispatch.is_some() {
             Ok(ComputePassNode {
-                inputs: self.inputs.into_iter().take(inputs_len).collect(),
-                outputs: self.outputs.into_iter().take(outputs_len).collect(),
-                fill_callback: self.fill_callback.take().unwrap(),
+                inputs: self.inputs,
+                outputs: self.outputs,
+                dependencies: self.dependencies,
+                fill_callback: self.fill_callback.take().unwrap_or_else(|| Box::new(|_: &VulkanRenderContext, _: &CommandBuffer| {})),
+                dispatch: self.dispatch,
                 name: self.name,
+                priority: self.priority,
+                execute_after: self.execute_after,
                 pipeline_description: self.pipeline_description
                     .expect("ComputePassNode requires a pipeline description")
             })
@@ -1,16 +1,23 @@
 use std::cell::RefCell;
 use std::fmt::{Debug, Formatter};
 use std::rc::Rc;
+use ash::vk;
 use ash::vk::CommandBuffer;
 use api_types::device::DeviceResource;
+use api_types::name::Name;
 use context::vulkan_render_context::VulkanRenderContext;
-use crate::pass_node::{FillCallback, PassNode};
+use crate::binding::ResourceDependency;
+use crate::pass_node::{FillCallback, PassHandle, PassNode};
+use crate::node_arena;
 
 pub struct CopyPassNode {
     pub copy_sources: Vec<Rc<RefCell<DeviceResource>>>,
     pub copy_dests: Vec<Rc<RefCell<DeviceResource>>>,
+    pub dependencies: Vec<ResourceDependency>,
     pub fill_callback: Box<FillCallback>,
-    name: String
+    priority: i32,
+    execute_after: Vec<PassHandle>,
+    name: Name
 }
 
 impl Debug for CopyPassNode {
@@ -19,14 +26,18 @@ impl Debug for CopyPassNode {
             .field("name", &self.name)
             .field("copy sources", &self.copy_sources)
             .field("copy dests", &self.copy_dests)
+            .field("dependencies", &self.dependencies)
             .finish()
     }
 }
 
 impl CopyPassNode {
-    pub fn builder(name: String) -> CopyPassNodeBuilder {
+    pub fn builder(name: impl Into<Name>) -> CopyPassNodeBuilder {
         CopyPassNodeBuilder {
-            name,
+            name: name.into(),
+            copy_sources: node_arena::take_resources(),
+            copy_dests: node_arena::take_resources(),
+            dependencies: node_arena::take_dependencies(),
             ..Default::default()
         }
     }
@@ -41,12 +52,19 @@ impl PassNode for CopyPassNode {
         &self.name
     }
 
+    fn get_interned_name(&self) -> Name {
+        self.name
+    }
+
     fn get_reads(&self) -> Vec<u64> {
         let mut reads: Vec<u64> = Vec::new();
         reads.reserve(self.copy_sources.len());
         for source in &self.copy_sources {
             reads.push(source.borrow().get_handle());
         }
+        for dependency in &self.dependencies {
+            reads.push(dependency.resource.borrow().get_handle());
+        }
 
         reads
     }
@@ -57,17 +75,35 @@ impl PassNode for CopyPassNode {
         for dest in &self.copy_dests {
             writes.push(dest.borrow().get_handle());
         }
+        for dependency in self.dependencies.iter().filter(|dependency| dependency.is_write()) {
+            writes.push(dependency.resource.borrow().get_handle());
+        }
 
         writes
     }
+
+    fn get_dependencies(&self) -> &[ResourceDependency] {
+        &self.dependencies
+    }
+
+    fn get_priority(&self) -> i32 {
+        self.priority
+    }
+
+    fn get_execute_after(&self) -> &[PassHandle] {
+        &self.execute_after
+    }
 }
 
 #[derive(Default)]
 pub struct CopyPassNodeBuilder {
     copy_sources: Vec<Rc<RefCell<DeviceResource>>>,
     copy_dests: Vec<Rc<RefCell<DeviceResource>>>,
+    dependencies: Vec<ResourceDependency>,
     fill_callback: Option<Box<FillCallback>>,
-    name: String
+    priority: i32,
+    execute_after: Vec<PassHandle>,
+    name: Name
 }
 
 impl CopyPassNodeBuilder {
@@ -81,21 +117,43 @@ impl CopyPassNodeBuilder {
         self
     }
 
+    /// Uses `resource` without copying to or from it (see ResourceDependency)
+    pub fn depends_on(
+        mut self,
+        resource: Rc<RefCell<DeviceResource>>,
+        access: vk::AccessFlags,
+        stage: vk::PipelineStageFlags) -> Self {
+        self.dependencies.push(ResourceDependency::new(resource, access, stage));
+        self
+    }
+
     pub fn fill_commands(mut self, fill_callback: Box<FillCallback>) -> Self
     {
         self.fill_callback = Some(fill_callback);
         self
     }
 
+    /// e.g. to start uploads before the passes which don't depend on them
+    pub fn priority(mut self, priority: i32) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// See PassNodeBuilder::after
+    pub fn after(mut self, node: PassHandle) -> Self {
+        self.execute_after.push(node);
+        self
+    }
+
     pub fn build(mut self) -> Result<CopyPassNode, &'static str> {
         if let Some(_) = &self.fill_callback {
-            let copy_sources_len = self.copy_sources.len();
-            let copy_dests_len = self.copy_dests.len();
-
             Ok(CopyPassNode {
-                copy_sources: self.copy_sources.into_iter().take(copy_sources_len).collect(),
-                copy_dests: self.copy_dests.into_iter().take(copy_dests_len).collect(),
+                copy_sources: self.copy_sources,
+                copy_dests: self.copy_dests,
+                dependencies: self.dependencies,
                 fill_callback: self.fill_callback.take().unwrap(),
+                priority: self.priority,
+                execute_after: self.execute_after,
                 name: self.name
             })
         } else {
@@ -1,10 +1,20 @@
 use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
 use std::fmt::{Debug, Formatter};
 use std::rc::Rc;
 use ash::vk;
 use petgraph::stable_graph::{StableDiGraph, NodeIndex};
-use api_types::device::DeviceWrapper;
+use api_types::device::{DeviceResource, ResourceType};
+use api_types::resource_state::ResourceState;
+use api_types::upload_buffer::DynamicUploadBuffer;
+use context::transient_image_pool::{TransientImageDesc, TransientImagePool};
+use crate::binding::{BindingFrequency, BindingInfo, BindingType, BufferBindingInfo, ResourceBinding, ResourceLifetime};
+use crate::frame_constants::{FrameConstants, FRAME_CONSTANTS_SLOT};
+use crate::uniform_layout::UniformBlock;
 use crate::graphics_pass_node::GraphicsPassNode;
+use crate::node_arena;
+use crate::pass_description::{PassDescription, PassResourceTable};
+use crate::pass_node::PassHandle;
 use crate::pass_type::PassType;
 
 #[derive(Eq, PartialEq, Debug)]
@@ -14,14 +24,41 @@ enum FrameState {
     Ended
 }
 
+/// An externally-owned or persistent resource used by a Frame, along with the state
+/// it must be left in once the Frame's work has been recorded
+pub struct ImportedResource {
+    pub resource: Rc<RefCell<DeviceResource>>,
+    /// The state an externally-owned resource is in before the Frame's work, applied when the
+    /// Frame is recorded rather than built so it doesn't disturb Frames recorded before it
+    pub initial_state: Option<ResourceState>,
+    pub final_state: ResourceState
+}
+
+/// The descriptor sets allocated while recording a Frame, and the bindings of its per-frame set
+#[derive(Default)]
+pub struct FrameDescriptors {
+    pub descriptor_sets: Vec<vk::DescriptorSet>,
+    pub(crate) per_frame_bindings: Vec<ResourceBinding>,
+    // the per-frame set written for each set layout pipelines declared it with; shaders which
+    // declare the same per-frame bindings share a layout, and so a set
+    pub(crate) per_frame_sets: HashMap<vk::DescriptorSetLayout, vk::DescriptorSet>
+}
+
+/// A frame's node graph. Building a Frame doesn't touch the render context or the state of
+/// the resources it uses, so the next Frame can be built while this one is still to be
+/// recorded; Frames are recorded in the order they were started
 pub struct Frame {
     pub nodes: StableDiGraph<PassType, u32>,
-    root_index: Option<NodeIndex>,
+    root_indices: Vec<NodeIndex>,
+    disabled_nodes: HashSet<NodeIndex>,
     state: FrameState,
     pub sorted_nodes: Vec<NodeIndex>,
-    device: Rc<RefCell<DeviceWrapper>>,
-    pub(crate) descriptor_pool: vk::DescriptorPool,
-    pub descriptor_sets: Vec<vk::DescriptorSet>
+    // the order this Frame was started in by its FrameGraph
+    pub(crate) serial: u64,
+    pub descriptors: FrameDescriptors,
+    pub(crate) imports: Vec<ImportedResource>,
+    persistent_resources: Vec<Rc<RefCell<DeviceResource>>>,
+    transient_resources: Vec<Rc<RefCell<DeviceResource>>>
 }
 
 impl Debug for Frame {
@@ -32,49 +69,338 @@ impl Debug for Frame {
     }
 }
 
+// descriptor sets aren't freed here; the render context resets their pools once the
+// frame's work has completed
 impl Drop for Frame {
     fn drop(&mut self) {
         log::trace!(target: "frame", "Dropping frame");
-        unsafe {
-            self.device.borrow().get().free_descriptor_sets(
-                self.descriptor_pool,
-                &self.descriptor_sets)
-                .expect("Failed to free Descriptor Sets for Frame");
-        }
+        node_arena::recycle_graph(std::mem::take(&mut self.nodes));
     }
 }
 
 impl Frame {
-    pub fn new(device: Rc<RefCell<DeviceWrapper>>, descriptor_pool: vk::DescriptorPool) -> Self {
+    // Frames are started by a FrameGraph (see FrameGraph::start), which gives them the serial
+    // they're recorded in the order of
+    pub(crate) fn new() -> Self {
         Frame {
-            nodes: StableDiGraph::new(),
-            root_index: None,
+            nodes: node_arena::take_graph(),
+            root_indices: Vec::new(),
+            disabled_nodes: HashSet::new(),
             state: FrameState::New,
             sorted_nodes: Vec::new(),
-            device,
-            descriptor_pool,
-            descriptor_sets: Vec::new()
+            serial: 0,
+            descriptors: FrameDescriptors::default(),
+            imports: Vec::new(),
+            persistent_resources: Vec::new(),
+            transient_resources: Vec::new()
         }
     }
 
-    pub fn add_node(&mut self, node: PassType) -> NodeIndex {
+    pub fn add_node(&mut self, node: PassType) -> PassHandle {
         assert!(self.state == FrameState::Started, "Frame must be started before adding nodes");
-        self.nodes.add_node(node)
+        PassHandle::new(self.nodes.add_node(node))
+    }
+
+    /// Adds nodes in the order given, so the resulting graph (and its sort) doesn't depend
+    /// on which worker finished building a node first
+    pub fn add_nodes(&mut self, nodes: Vec<PassType>) -> Vec<PassHandle> {
+        nodes.into_iter().map(|node| self.add_node(node)).collect()
+    }
+
+    /// Resolves descriptions built on worker threads (see PassDescription::build_parallel)
+    /// and adds them in the order given. Resolving touches the Rc resources in `table`, so it
+    /// happens on this thread. If any description fails to resolve, none of them are added
+    pub fn add_descriptions(
+        &mut self,
+        descriptions: Vec<PassDescription>,
+        table: &PassResourceTable) -> Result<Vec<PassHandle>, &'static str> {
+        let nodes = descriptions.into_iter()
+            .map(|description| description.resolve(table))
+            .collect::<Result<Vec<PassType>, &'static str>>()?;
+        Ok(self.add_nodes(nodes))
+    }
+
+    pub fn get_node(&self, handle: PassHandle) -> Option<&PassType> {
+        self.nodes.node_weight(handle.get_index())
+    }
+
+    /// Changes a node after it's been added, until the Frame is recorded
+    pub fn get_node_mut(&mut self, handle: PassHandle) -> Option<&mut PassType> {
+        assert!(self.state == FrameState::Started, "Nodes can only be changed before the Frame is recorded");
+        self.nodes.node_weight_mut(handle.get_index())
+    }
+
+    /// Disabled nodes are removed when the Frame is recorded, as if they'd never been added.
+    /// Roots can't be disabled
+    pub fn set_enabled(&mut self, handle: PassHandle, enabled: bool) {
+        assert!(self.state == FrameState::Started, "Nodes can only be disabled before the Frame is recorded");
+        assert!(!self.root_indices.contains(&handle.get_index()), "Root nodes can't be disabled");
+        if enabled {
+            self.disabled_nodes.remove(&handle.get_index());
+        } else {
+            self.disabled_nodes.insert(handle.get_index());
+        }
+    }
+
+    pub fn is_enabled(&self, handle: PassHandle) -> bool {
+        !self.disabled_nodes.contains(&handle.get_index())
+    }
+
+    /// Declares the current state of an externally-owned resource (see DeviceWrapper::import_image
+    /// and DeviceWrapper::import_buffer) so the first node using it transitions from the right
+    /// layout, along with the state it will be transitioned to at the end of the frame
+    pub fn import_resource(
+        &mut self,
+        resource: Rc<RefCell<DeviceResource>>,
+        current_state: ResourceState,
+        final_state: ResourceState) {
+        assert!(self.state == FrameState::Started, "Frame must be started before importing resources");
+        if let Some(ResourceType::Image(_)) = resource.borrow().resource_type.as_ref() {
+            assert!(current_state.layout.is_some(), "Imported images require a current layout");
+            assert!(final_state.layout.is_some(), "Imported images require a final layout");
+        }
+
+        self.imports.push(ImportedResource {
+            resource,
+            initial_state: Some(current_state),
+            final_state
+        });
+    }
+
+    /// Continues using a resource whose contents carry over between frames (e.g. TAA history or
+    /// an adapted exposure) from the state the last frame using it left it in. Returns whether
+    /// there is such a state; if not, the resource's contents are undefined and its first use in
+    /// this frame must initialize it. Persistent resources can't also be transient to the frame.
+    ///
+    /// While an earlier Frame is still to be recorded, only the state left by Frames already
+    /// recorded is known, so a resource that earlier Frame initializes is reported as having none
+    pub fn import_persistent(&mut self, resource: &Rc<RefCell<DeviceResource>>) -> bool {
+        assert!(self.state == FrameState::Started, "Frame must be started before importing resources");
+
+        let has_state = {
+            let resource_ref = resource.borrow();
+            resource_ref.get_device().borrow().get_resource_state(resource_ref.get_handle()).is_some()
+        };
+        self.persistent_resources.push(resource.clone());
+
+        has_state
+    }
+
+    /// Declares the state a resource will be transitioned to at the end of the frame, e.g. so
+    /// a persistent history image is already in the layout the next frame first reads it in
+    pub fn export_resource(
+        &mut self,
+        resource: Rc<RefCell<DeviceResource>>,
+        final_state: ResourceState) {
+        assert!(self.state == FrameState::Started, "Frame must be started before exporting resources");
+        if let Some(ResourceType::Image(_)) = resource.borrow().resource_type.as_ref() {
+            assert!(final_state.layout.is_some(), "Exported images require a final layout");
+        }
+
+        self.imports.push(ImportedResource {
+            resource,
+            initial_state: None,
+            final_state
+        });
+    }
+
+    /// Keeps a resource alive for as long as this Frame. Frames must only be dropped once
+    /// the fence for their submission has signaled, so transient resources created while
+    /// building the frame are never destroyed while the GPU may still be using them.
+    /// Transient images' contents aren't expected to outlive the frame, so their first use
+    /// as an attachment discards them (see AttachmentLoad::Auto).
+    ///
+    /// Resources bound with ResourceLifetime::Transient are treated the same way without
+    /// being added here, since the Frame's nodes already keep them alive
+    pub fn add_transient_resource(&mut self, resource: Rc<RefCell<DeviceResource>>) {
+        self.transient_resources.push(resource);
+    }
+
+    /// Requests an image from `pool` which is transient to this Frame. The pool may hand it
+    /// out again once the next frame begins
+    pub fn request_transient_image(
+        &mut self,
+        pool: &mut TransientImagePool,
+        desc: &TransientImageDesc,
+        name: &str) -> Rc<RefCell<DeviceResource>> {
+        let image = pool.request_image(desc, name);
+        self.add_transient_resource(image.clone());
+        image
+    }
+
+    pub(crate) fn get_persistent_resources(&self) -> &[Rc<RefCell<DeviceResource>>] {
+        &self.persistent_resources
+    }
+
+    pub(crate) fn get_persistent_handles(&self) -> HashSet<u64> {
+        self.persistent_resources.iter()
+            .map(|resource| resource.borrow().get_handle())
+            .collect()
+    }
+
+    pub(crate) fn get_transient_handles(&self) -> HashSet<u64> {
+        let mut handles: HashSet<u64> = self.transient_resources.iter()
+            .map(|resource| resource.borrow().get_handle())
+            .collect();
+        for node in self.nodes.node_weights() {
+            handles.extend(node.get_transients());
+        }
+        handles
+    }
+
+    /// Binds a resource in the per-frame descriptor set (see BindingFrequency::PerFrame), which is
+    /// written once and bound for every pass whose pipeline declares the set. The binding's set
+    /// must be the per-frame set.
+    ///
+    /// Per-frame bindings don't order the passes reading them after anything, so only buffers
+    /// whose contents are ready before the frame starts can be bound, e.g. uniforms uploaded to
+    /// a DynamicUploadBuffer
+    pub fn bind_per_frame(&mut self, binding: ResourceBinding) {
+        assert!(self.state == FrameState::Started, "Frame must be started before binding per-frame resources");
+        let per_frame_set = BindingFrequency::PerFrame.get_set();
+        assert_eq!(binding.binding_info.set, per_frame_set,
+            "Per-frame resources must be bound in set {}", per_frame_set);
+        assert!(matches!(binding.binding_info.binding_type, BindingType::Buffer(_)),
+            "Only buffers can be bound per-frame");
+        let slot = binding.binding_info.slot;
+        assert!(self.descriptors.per_frame_bindings.iter().all(|bound| bound.binding_info.slot != slot),
+            "Per-frame slot {} is already bound", slot);
+
+        self.descriptors.per_frame_bindings.push(binding);
+    }
+
+    /// Uploads the frame's constants and binds them per-frame, for shaders including
+    /// frame_constants.glsl. Can only be called once per frame
+    pub fn set_constants(&mut self, constants: &FrameConstants, upload_buffer: &mut DynamicUploadBuffer) {
+        let alignment = upload_buffer.get_uniform_alignment();
+        let offset = upload_buffer.push(std::slice::from_ref(constants), alignment);
+
+        self.bind_per_frame(ResourceBinding::new(
+            upload_buffer.get_buffer(),
+            BindingInfo {
+                binding_type: BindingType::Buffer(BufferBindingInfo {
+                    offset,
+                    range: std::mem::size_of::<FrameConstants>() as vk::DeviceSize,
+                    layout: Some(FrameConstants::layout())
+                }),
+                set: BindingFrequency::PerFrame.get_set(),
+                slot: FRAME_CONSTANTS_SLOT,
+                stage: vk::PipelineStageFlags::ALL_COMMANDS,
+                access: vk::AccessFlags::UNIFORM_READ
+            }));
     }
 
     pub fn start(&mut self, root_node: PassType) {
+        self.begin();
+        self.add_root(root_node);
+    }
+
+    /// Starts a Frame without a present node, for frames which only render into images handed
+    /// back to something else (e.g. an OpenXR swapchain, see context::external_swapchain) or
+    /// read back. Its terminal nodes are declared with add_root, and images it leaves in a
+    /// particular layout with export_resource
+    pub fn begin(&mut self) {
         assert!(self.state == FrameState::New, "Frame has already been started");
         self.state = FrameState::Started;
-        self.root_index = Some(self.add_node(root_node));
+    }
+
+    /// Adds an additional terminal node (e.g. an offscreen readback alongside the present node).
+    /// Every node contributing to any root is retained when the Frame is compiled
+    pub fn add_root(&mut self, root_node: PassType) -> PassHandle {
+        let root_handle = self.add_node(root_node);
+        self.root_indices.push(root_handle.get_index());
+        root_handle
     }
 
     pub (crate) fn end(&mut self) {
         assert!(self.state == FrameState::Started, "Frame must be in Started state to be ended");
         self.state = FrameState::Ended;
+        for node_index in self.disabled_nodes.drain() {
+            self.nodes.remove_node(node_index);
+        }
     }
 
-    pub (crate) fn get_root_index(&self) -> NodeIndex {
-        assert!(self.state != FrameState::New, "Cannot get root index before the Frame has been started");
-        self.root_index.expect("Something bad happened; a Frame was started without a root node")
+    pub (crate) fn get_root_indices(&self) -> &[NodeIndex] {
+        assert!(self.state != FrameState::New, "Cannot get root indices before the Frame has been started");
+        assert!(!self.root_indices.is_empty(), "Something bad happened; a Frame was started without a root node");
+        &self.root_indices
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::copy_pass_node::CopyPassNode;
+    use crate::graph_core::{self, GraphNode};
+
+    fn copy_node(name: &str, after: &[PassHandle]) -> PassType {
+        let builder = after.iter().fold(CopyPassNode::builder(name.to_string()), |builder, handle| {
+            builder.after(*handle)
+        });
+        PassType::Copy(builder
+            .fill_commands(Box::new(|_render_ctx, _command_buffer| {}))
+            .build()
+            .expect("Failed to build copy pass node"))
+    }
+
+    fn sorted_names(frame: &mut Frame) -> Vec<String> {
+        let root_indices = frame.get_root_indices().to_vec();
+        graph_core::compile(&mut frame.nodes, &root_indices).iter()
+            .map(|node_index| frame.nodes[*node_index].get_name().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn disabled_nodes_are_removed_when_ended() {
+        let mut frame = Frame::new();
+        frame.begin();
+        let enabled = frame.add_node(copy_node("enabled", &[]));
+        let disabled = frame.add_node(copy_node("disabled", &[]));
+        frame.add_root(copy_node("root", &[enabled, disabled]));
+        frame.set_enabled(disabled, false);
+        assert!(!frame.is_enabled(disabled));
+        assert!(frame.get_node(disabled).is_some());
+
+        frame.end();
+        assert!(frame.get_node(disabled).is_none());
+        assert!(frame.get_node(enabled).is_some());
+        assert_eq!(frame.nodes.node_count(), 2);
+    }
+
+    #[test]
+    fn reenabled_nodes_are_kept() {
+        let mut frame = Frame::new();
+        frame.begin();
+        let node = frame.add_node(copy_node("node", &[]));
+        frame.add_root(copy_node("root", &[node]));
+        frame.set_enabled(node, false);
+        frame.set_enabled(node, true);
+
+        frame.end();
+        assert!(frame.get_node(node).is_some());
+        assert_eq!(sorted_names(&mut frame), ["node", "root"]);
+    }
+
+    #[test]
+    fn edges_after_disabled_nodes_are_ignored() {
+        let mut frame = Frame::new();
+        frame.begin();
+        let enabled = frame.add_node(copy_node("enabled", &[]));
+        let disabled = frame.add_node(copy_node("disabled", &[enabled]));
+        frame.add_root(copy_node("root", &[disabled]));
+        frame.set_enabled(disabled, false);
+
+        frame.end();
+        // root was only ordered after the disabled node, so nothing else is retained
+        assert_eq!(sorted_names(&mut frame), ["root"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Root nodes can't be disabled")]
+    fn roots_cant_be_disabled() {
+        let mut frame = Frame::new();
+        frame.begin();
+        let root = frame.add_root(copy_node("root", &[]));
+        frame.set_enabled(root, false);
+    }
+}
@@ -0,0 +1,81 @@
+use ash::vk;
+use crate::uniform_layout::UniformBlock;
+
+/// The slot of the per-frame set FrameConstants are bound to
+pub const FRAME_CONSTANTS_SLOT: u32 = 0;
+
+const IDENTITY: [[f32; 4]; 4] = [
+    [1.0, 0.0, 0.0, 0.0],
+    [0.0, 1.0, 0.0, 0.0],
+    [0.0, 0.0, 1.0, 0.0],
+    [0.0, 0.0, 0.0, 1.0]];
+
+/// The camera a frame is rendered from. Matrices are column major
+#[derive(Copy, Clone, Debug)]
+pub struct FrameCamera {
+    pub view: [[f32; 4]; 4],
+    pub projection: [[f32; 4]; 4],
+    pub view_projection: [[f32; 4]; 4],
+    pub inverse_view_projection: [[f32; 4]; 4],
+    /// In world space
+    pub position: [f32; 3]
+}
+
+impl Default for FrameCamera {
+    fn default() -> Self {
+        FrameCamera {
+            view: IDENTITY,
+            projection: IDENTITY,
+            view_projection: IDENTITY,
+            inverse_view_projection: IDENTITY,
+            position: [0.0; 3]
+        }
+    }
+}
+
+/// Constants shared by every pass of a frame. Set once per frame with Frame::set_constants,
+/// after which they're bound for every pipeline declaring them by including
+/// passes/shaders/frame_constants.glsl, whose block this matches
+#[repr(C)]
+#[derive(Copy, Clone, Debug, UniformBlock)]
+pub struct FrameConstants {
+    view: [[f32; 4]; 4],
+    projection: [[f32; 4]; 4],
+    view_projection: [[f32; 4]; 4],
+    inverse_view_projection: [[f32; 4]; 4],
+    camera_position: [f32; 4],
+    viewport_size: [f32; 2],
+    time: f32,
+    delta_time: f32,
+    frame_index: u32,
+    padding0: u32,
+    padding1: u32,
+    padding2: u32
+}
+
+impl FrameConstants {
+    /// `time` and `delta_time` are in seconds, and `viewport` is the size of the image the
+    /// frame's scene is rendered to
+    pub fn new(
+        camera: &FrameCamera,
+        viewport: vk::Extent2D,
+        time: f32,
+        delta_time: f32,
+        frame_index: u32) -> Self {
+
+        FrameConstants {
+            view: camera.view,
+            projection: camera.projection,
+            view_projection: camera.view_projection,
+            inverse_view_projection: camera.inverse_view_projection,
+            camera_position: [camera.position[0], camera.position[1], camera.position[2], 1.0],
+            viewport_size: [viewport.width as f32, viewport.height as f32],
+            time,
+            delta_time,
+            frame_index,
+            padding0: 0,
+            padding1: 0,
+            padding2: 0
+        }
+    }
+}
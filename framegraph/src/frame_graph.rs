@@ -1,7 +1,3 @@
-use std::cell::RefCell;
-use std::rc::Rc;
-use ash::vk;
-use api_types::device::DeviceWrapper;
 use crate::frame::Frame;
 
 pub trait FrameGraph
@@ -12,15 +8,17 @@ pub trait FrameGraph
     type CB;
     type RC;
     type Index;
+    type Submit;
 
-    fn start(
-        &mut self,
-        device: Rc<RefCell<DeviceWrapper>>,
-        descriptor_pool: vk::DescriptorPool) -> Box<Frame>;
+    /// Starts building a Frame. The render context is only needed once the Frame is ended, so
+    /// the next Frame can be started and built before this one is ended
+    fn start(&mut self) -> Box<Frame>;
 
+    /// Records the frame, starting in `command_buffer`. Returns the command buffers to submit
+    /// in order, the first of which is `command_buffer`; the caller must end it before submitting
     fn end(
         &mut self,
         frame: &mut Frame,
         render_context: &mut Self::RC,
-        command_buffer: &Self::CB);
+        command_buffer: &Self::CB) -> Vec<Self::Submit>;
 }
@@ -0,0 +1,79 @@
+use std::time::Duration;
+use api_types::name::Name;
+use crate::barrier_analysis::RedundantBarrier;
+use crate::node_arena::NodeArenaStats;
+use crate::pass_node::PassHandle;
+
+/// CPU time spent recording a single node
+#[derive(Clone, Debug, Default)]
+pub struct PassTiming {
+    pub name: Name,
+    pub handle: PassHandle,
+    /// Building and recording the node's barriers
+    pub barriers: Duration,
+    /// The number of barriers recorded before the node, including the barriers of the rest of
+    /// its renderpass group if it's the group's first node
+    pub image_barriers: u32,
+    pub buffer_barriers: u32,
+    /// Which of the node's barriers synchronize nothing. Only filled in with the
+    /// "barrier-analysis" feature
+    pub redundant_barriers: Vec<RedundantBarrier>,
+    /// Renderpass, pipeline and framebuffer lookup plus descriptor updates
+    pub setup: Duration,
+    /// The node's fill callback
+    pub fill: Duration,
+    /// The node's retained recording was executed instead of running its fill callback, see
+    /// PassNodeBuilder::retained
+    pub replayed: bool,
+    /// See Pipeline::get_layout_hash; None for nodes without a pipeline
+    pub pipeline_layout_hash: Option<u64>,
+    /// Bytes of GPU memory allocated for the pass this frame: transient resources it was the
+    /// first to use, and anything its fill callback allocated
+    pub transient_allocated: u64
+}
+
+impl PassTiming {
+    pub fn total(&self) -> Duration {
+        self.barriers + self.setup + self.fill
+    }
+}
+
+/// CPU timings for the most recently ended Frame
+#[derive(Clone, Debug, Default)]
+pub struct FrameStats {
+    /// Time spent compiling and linking, or applying a cached graph
+    pub compile_link: Duration,
+    pub graph_cache_hit: bool,
+    /// Pipeline layouts which had to be created for this frame's nodes, rather than
+    /// shared with an earlier pipeline
+    pub pipeline_layouts_created: u32,
+    /// Timings in execution order
+    pub passes: Vec<PassTiming>,
+    /// Bytes allocated for the frame's transient resources and under any tag this frame,
+    /// including those of passes which were culled
+    pub transient_allocated: u64,
+    /// The highest transient_allocated of any frame so far
+    pub transient_watermark: u64,
+    /// Node storage handed out since the previous Frame was recorded, see node_arena
+    pub node_storage: NodeArenaStats
+}
+
+impl FrameStats {
+    pub fn total(&self) -> Duration {
+        self.compile_link + self.passes.iter().map(|pass| pass.total()).sum::<Duration>()
+    }
+
+    /// Barriers recorded before every pass, not counting the final barriers after the last one
+    pub fn barrier_count(&self) -> u32 {
+        self.passes.iter().map(|pass| pass.image_barriers + pass.buffer_barriers).sum()
+    }
+
+    pub fn redundant_barrier_count(&self) -> usize {
+        self.passes.iter().map(|pass| pass.redundant_barriers.len()).sum()
+    }
+
+    /// None if the node was culled, disabled, or merged into the node after it
+    pub fn get_pass(&self, handle: PassHandle) -> Option<&PassTiming> {
+        self.passes.iter().find(|pass| pass.handle == handle)
+    }
+}
@@ -0,0 +1,598 @@
+use std::cell::RefCell;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Debug, Formatter};
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
+use ash::vk;
+use petgraph::stable_graph::{NodeIndex, StableDiGraph};
+use api_types::device::{DeviceResource, ResourceType};
+use api_types::resource_state::ResourceState;
+use context::vulkan_render_context::VulkanRenderContext;
+use crate::attachment::{AttachmentLoad, AttachmentReference};
+use crate::barrier::{BufferBarrier, ImageBarrier, SubresourceRange};
+use crate::binding::{BindingInfo, BindingType, ResourceBinding, ResourceDependency};
+use crate::command_list::{CommandList, QueueWait};
+use crate::pass_node::PassNode;
+use crate::pass_type::PassType;
+use crate::vulkan_frame_graph::NodeBarriers;
+
+/// Number of distinct graphs kept; a few are needed since e.g. each swapchain image
+/// produces a different fingerprint
+const GRAPH_CACHE_CAPACITY: usize = 8;
+
+fn for_each_resource(node: &PassType, mut f: impl FnMut(&Rc<RefCell<DeviceResource>>)) {
+    for dependency in node.get_dependencies() {
+        f(&dependency.resource);
+    }
+    match node {
+        PassType::Graphics(gn) => {
+            for binding in gn.inputs.iter().chain(&gn.outputs).chain(&gn.input_attachments) {
+                f(&binding.resource);
+            }
+            for rt in &gn.render_targets {
+                f(&rt.resource_image);
+            }
+            if let Some(dt) = &gn.depth_target {
+                f(&dt.resource_image);
+            }
+        },
+        PassType::Copy(cn) => {
+            for resource in cn.copy_sources.iter().chain(&cn.copy_dests) {
+                f(resource);
+            }
+        },
+        PassType::Compute(cn) => {
+            for binding in cn.inputs.iter().chain(&cn.outputs) {
+                f(&binding.resource);
+            }
+        },
+        PassType::Present(pn) => {
+            f(&pn.swapchain_image);
+        }
+    }
+}
+
+/// How fingerprints and cached graphs refer to a resource. Transient resources get new
+/// handles every frame, so they're numbered in the order the graph first uses them instead
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+enum ResourceKey {
+    Persistent(u64),
+    Transient(usize)
+}
+
+/// The key of every resource a frame's graph uses, see ResourceKey
+pub(crate) struct ResourceKeys {
+    transient_slots: HashMap<u64, usize>
+}
+
+impl ResourceKeys {
+    /// Must be created before the graph is compiled, so culled nodes are numbered too
+    pub(crate) fn new(nodes: &StableDiGraph<PassType, u32>, transient_handles: &HashSet<u64>) -> Self {
+        let mut transient_slots: HashMap<u64, usize> = HashMap::new();
+        for node_index in nodes.node_indices() {
+            let node = &nodes[node_index];
+            let mut assign = |handle: u64| {
+                if transient_handles.contains(&handle) {
+                    let next_slot = transient_slots.len();
+                    transient_slots.entry(handle).or_insert(next_slot);
+                }
+            };
+            for handle in node.get_reads().into_iter().chain(node.get_writes()) {
+                assign(handle);
+            }
+            for_each_resource(node, |resource| assign(resource.borrow().get_handle()));
+        }
+
+        ResourceKeys {
+            transient_slots
+        }
+    }
+
+    fn key(&self, handle: u64) -> ResourceKey {
+        match self.transient_slots.get(&handle) {
+            Some(slot) => ResourceKey::Transient(*slot),
+            None => ResourceKey::Persistent(handle)
+        }
+    }
+
+    fn is_transient(&self, handle: u64) -> bool {
+        self.transient_slots.contains_key(&handle)
+    }
+}
+
+fn hash_state(state: &Option<ResourceState>, hasher: &mut DefaultHasher) {
+    if let Some(state) = state {
+        state.access.as_raw().hash(hasher);
+        state.stage.as_raw().hash(hasher);
+        state.layout.map(|layout| layout.as_raw()).hash(hasher);
+    } else {
+        0u8.hash(hasher);
+    }
+}
+
+fn hash_binding_info(info: &BindingInfo, hasher: &mut DefaultHasher) {
+    info.set.hash(hasher);
+    info.slot.hash(hasher);
+    info.stage.as_raw().hash(hasher);
+    info.access.as_raw().hash(hasher);
+    match &info.binding_type {
+        BindingType::Buffer(buffer) => {
+            buffer.offset.hash(hasher);
+            buffer.range.hash(hasher);
+        },
+        BindingType::Image(image) => {
+            image.layout.as_raw().hash(hasher);
+            image.subresource.hash(hasher);
+        }
+    }
+}
+
+pub(crate) fn hash_bindings(bindings: &[ResourceBinding], hasher: &mut DefaultHasher) {
+    bindings.len().hash(hasher);
+    for binding in bindings {
+        binding.resource.borrow().get_handle().hash(hasher);
+        hash_binding_info(&binding.binding_info, hasher);
+    }
+}
+
+fn hash_keyed_bindings(bindings: &[ResourceBinding], keys: &ResourceKeys, hasher: &mut DefaultHasher) {
+    bindings.len().hash(hasher);
+    for binding in bindings {
+        keys.key(binding.resource.borrow().get_handle()).hash(hasher);
+        hash_binding_info(&binding.binding_info, hasher);
+    }
+}
+
+fn hash_dependencies(dependencies: &[ResourceDependency], keys: &ResourceKeys, hasher: &mut DefaultHasher) {
+    dependencies.len().hash(hasher);
+    for dependency in dependencies {
+        keys.key(dependency.resource.borrow().get_handle()).hash(hasher);
+        dependency.access.as_raw().hash(hasher);
+        dependency.stage.as_raw().hash(hasher);
+    }
+}
+
+fn hash_clear_value(clear_value: &vk::ClearValue, hasher: &mut DefaultHasher) {
+    // every variant of the union fits in the four words of a color value
+    let words = unsafe { clear_value.color.uint32 };
+    words.hash(hasher);
+}
+
+fn hash_attachment_load(attachment: &AttachmentReference, hasher: &mut DefaultHasher) {
+    // clear values are read when the renderpass begins, so only the kind of load matters. Cached
+    // graphs keep the current frame's clear values (see replayed_load)
+    let load: u8 = match attachment.load {
+        AttachmentLoad::Auto => 0,
+        AttachmentLoad::Load => 1,
+        AttachmentLoad::DontCare => 2,
+        AttachmentLoad::Clear(_) => 3,
+        AttachmentLoad::ClearDepthStencil(_) => 4
+    };
+    load.hash(hasher);
+}
+
+/// Hash of everything compile and link depend on: the nodes in insertion order, what they read
+/// and write, the shape of their bindings and attachments, the roots, the frame's transient
+/// resources, and the state each resource starts the frame in. Transients are hashed by their
+/// slot (see ResourceKeys) and image description, so a graph rebuilding its transient targets
+/// every frame still hits the cache
+pub(crate) fn graph_fingerprint(
+    nodes: &StableDiGraph<PassType, u32>,
+    root_indices: &[NodeIndex],
+    keys: &ResourceKeys,
+    render_context: &VulkanRenderContext) -> u64 {
+
+    let mut hasher = DefaultHasher::new();
+    root_indices.len().hash(&mut hasher);
+    for root_index in root_indices {
+        root_index.index().hash(&mut hasher);
+    }
+    keys.transient_slots.len().hash(&mut hasher);
+
+    let mut hashed_resources: HashSet<u64> = HashSet::new();
+    for node_index in nodes.node_indices() {
+        let node = &nodes[node_index];
+        node_index.index().hash(&mut hasher);
+        node.get_name().hash(&mut hasher);
+        node.get_priority().hash(&mut hasher);
+        for after in node.get_execute_after() {
+            after.get_index().index().hash(&mut hasher);
+        }
+        let reads = node.get_reads();
+        reads.len().hash(&mut hasher);
+        for read in reads {
+            keys.key(read).hash(&mut hasher);
+        }
+        let writes = node.get_writes();
+        writes.len().hash(&mut hasher);
+        for write in writes {
+            keys.key(write).hash(&mut hasher);
+        }
+        hash_dependencies(node.get_dependencies(), keys, &mut hasher);
+        match node {
+            PassType::Graphics(gn) => {
+                0u8.hash(&mut hasher);
+                hash_keyed_bindings(&gn.inputs, keys, &mut hasher);
+                hash_keyed_bindings(&gn.outputs, keys, &mut hasher);
+                hash_keyed_bindings(&gn.input_attachments, keys, &mut hasher);
+                gn.get_renderpass_group().hash(&mut hasher);
+                gn.render_targets.len().hash(&mut hasher);
+                gn.depth_target.is_some().hash(&mut hasher);
+                for attachment in gn.render_targets.iter().chain(&gn.depth_target) {
+                    hash_attachment_load(attachment, &mut hasher);
+                }
+                // clear nodes may be merged into the next node, whose attachment then keeps
+                // the clear value
+                gn.clear_value.is_some().hash(&mut hasher);
+                if let Some(clear_value) = &gn.clear_value {
+                    hash_clear_value(clear_value, &mut hasher);
+                    gn.clear_aspects.as_raw().hash(&mut hasher);
+                }
+            },
+            PassType::Copy(_) => {
+                1u8.hash(&mut hasher);
+            },
+            PassType::Compute(cn) => {
+                2u8.hash(&mut hasher);
+                hash_keyed_bindings(&cn.inputs, keys, &mut hasher);
+                hash_keyed_bindings(&cn.outputs, keys, &mut hasher);
+            },
+            PassType::Present(pn) => {
+                3u8.hash(&mut hasher);
+                pn.final_layout.hash(&mut hasher);
+            }
+        }
+
+        for_each_resource(node, |resource| {
+            let resource = resource.borrow();
+            let handle = resource.get_handle();
+            if hashed_resources.insert(handle) {
+                keys.key(handle).hash(&mut hasher);
+                hash_state(&render_context.get_resource_state(handle), &mut hasher);
+                if let Some(ResourceType::Image(image)) = &resource.resource_type {
+                    image.layout.as_raw().hash(&mut hasher);
+                    // a transient's slot may be filled by a differently sized or formatted image
+                    if keys.is_transient(handle) {
+                        image.format.as_raw().hash(&mut hasher);
+                        image.extent.hash(&mut hasher);
+                        image.array_layers.hash(&mut hasher);
+                    }
+                }
+            }
+        });
+    }
+
+    hasher.finish()
+}
+
+struct CachedImageBarrier {
+    key: ResourceKey,
+    source_stage: vk::PipelineStageFlags,
+    dest_stage: vk::PipelineStageFlags,
+    source_access: vk::AccessFlags,
+    dest_access: vk::AccessFlags,
+    old_layout: vk::ImageLayout,
+    new_layout: vk::ImageLayout,
+    subresource: SubresourceRange
+}
+
+struct CachedBufferBarrier {
+    key: ResourceKey,
+    source_stage: vk::PipelineStageFlags,
+    dest_stage: vk::PipelineStageFlags,
+    source_access: vk::AccessFlags,
+    dest_access: vk::AccessFlags,
+    size: usize,
+    offset: usize
+}
+
+/// Barriers refer to resources by key so a cached graph doesn't keep its resources alive, and
+/// can be applied to the next frame's transients
+struct CachedNodeBarriers {
+    image_barriers: Vec<CachedImageBarrier>,
+    buffer_barriers: Vec<CachedBufferBarrier>
+}
+
+impl CachedNodeBarriers {
+    fn capture(barriers: &NodeBarriers, keys: &ResourceKeys) -> Self {
+        CachedNodeBarriers {
+            image_barriers: barriers.image_barriers.iter().map(|ib| CachedImageBarrier {
+                key: keys.key(ib.resource.borrow().get_handle()),
+                source_stage: ib.source_stage,
+                dest_stage: ib.dest_stage,
+                source_access: ib.source_access,
+                dest_access: ib.dest_access,
+                old_layout: ib.old_layout,
+                new_layout: ib.new_layout,
+                subresource: ib.subresource
+            }).collect(),
+            buffer_barriers: barriers.buffer_barriers.iter().map(|bb| CachedBufferBarrier {
+                key: keys.key(bb.resource.borrow().get_handle()),
+                source_stage: bb.source_stage,
+                dest_stage: bb.dest_stage,
+                source_access: bb.source_access,
+                dest_access: bb.dest_access,
+                size: bb.size,
+                offset: bb.offset
+            }).collect()
+        }
+    }
+
+    fn resolve(&self, resolve: &impl Fn(ResourceKey) -> Rc<RefCell<DeviceResource>>) -> NodeBarriers {
+        NodeBarriers {
+            image_barriers: self.image_barriers.iter().map(|ib| ImageBarrier {
+                resource: resolve(ib.key),
+                source_stage: ib.source_stage,
+                dest_stage: ib.dest_stage,
+                source_access: ib.source_access,
+                dest_access: ib.dest_access,
+                old_layout: ib.old_layout,
+                new_layout: ib.new_layout,
+                subresource: ib.subresource
+            }).collect(),
+            buffer_barriers: self.buffer_barriers.iter().map(|bb| BufferBarrier {
+                resource: resolve(bb.key),
+                source_stage: bb.source_stage,
+                dest_stage: bb.dest_stage,
+                source_access: bb.source_access,
+                dest_access: bb.dest_access,
+                size: bb.size,
+                offset: bb.offset
+            }).collect()
+        }
+    }
+}
+
+/// Merging clear nodes can change an attachment's load as well as its resolved load op. Only
+/// merged loads are replayed, see replayed_load
+#[derive(Copy, Clone)]
+struct CachedAttachment {
+    layout: vk::ImageLayout,
+    load: AttachmentLoad,
+    load_op: vk::AttachmentLoadOp,
+    store_op: vk::AttachmentStoreOp
+}
+
+impl CachedAttachment {
+    fn capture(attachment: &AttachmentReference) -> Self {
+        CachedAttachment {
+            layout: attachment.layout,
+            load: attachment.load,
+            load_op: attachment.load_op,
+            store_op: attachment.store_op
+        }
+    }
+
+    fn apply(&self, attachment: &mut AttachmentReference) {
+        attachment.layout = self.layout;
+        attachment.load = replayed_load(attachment.load, self.load);
+        attachment.load_op = self.load_op;
+        attachment.store_op = self.store_op;
+    }
+}
+
+/// The load an attachment of a graph with a cached fingerprint ends up with. Clear nodes are
+/// only merged into Auto loads, and the clear values of clear nodes are fingerprinted, so an
+/// Auto load takes the cached one. Any other load is of the same kind as the cached one, but
+/// its clear value isn't fingerprinted, so the current frame's is kept
+fn replayed_load(current: AttachmentLoad, cached: AttachmentLoad) -> AttachmentLoad {
+    match current {
+        AttachmentLoad::Auto => cached,
+        _ => current
+    }
+}
+
+/// The result of compiling and linking a graph, along with every side effect linking has
+/// on the nodes and resources so it can be replayed on an identical graph
+pub(crate) struct CachedGraph {
+    retained_nodes: HashSet<NodeIndex>,
+    sorted_nodes: Vec<NodeIndex>,
+    command_lists: Vec<(Vec<NodeIndex>, Option<vk::PipelineStageFlags>)>,
+    node_barriers: Vec<(NodeIndex, CachedNodeBarriers)>,
+    final_barriers: CachedNodeBarriers,
+    attachment_layouts: Vec<(NodeIndex, Vec<CachedAttachment>, Option<CachedAttachment>)>,
+    image_layouts: Vec<(ResourceKey, vk::ImageLayout)>,
+    final_states: Vec<(ResourceKey, ResourceState)>
+}
+
+impl CachedGraph {
+    /// Records a graph which has just been compiled and linked
+    pub(crate) fn capture(
+        nodes: &StableDiGraph<PassType, u32>,
+        sorted_nodes: &[NodeIndex],
+        command_lists: &[CommandList],
+        node_barriers: &HashMap<NodeIndex, NodeBarriers>,
+        final_barriers: &NodeBarriers,
+        keys: &ResourceKeys,
+        render_context: &VulkanRenderContext) -> Self {
+
+        let mut cached_barriers = Vec::new();
+        let mut attachment_layouts = Vec::new();
+        let mut image_layouts = Vec::new();
+        let mut final_states = Vec::new();
+        let mut visited: HashSet<u64> = HashSet::new();
+        for node_index in sorted_nodes {
+            if let Some(barriers) = node_barriers.get(node_index) {
+                cached_barriers.push((*node_index, CachedNodeBarriers::capture(barriers, keys)));
+            }
+
+            let node = &nodes[*node_index];
+            if let PassType::Graphics(gn) = node {
+                attachment_layouts.push((
+                    *node_index,
+                    gn.render_targets.iter().map(CachedAttachment::capture).collect(),
+                    gn.depth_target.as_ref().map(CachedAttachment::capture)));
+            }
+
+            for_each_resource(node, |resource| {
+                let resource = resource.borrow();
+                let handle = resource.get_handle();
+                if visited.insert(handle) {
+                    if let Some(ResourceType::Image(image)) = &resource.resource_type {
+                        image_layouts.push((keys.key(handle), image.layout));
+                    }
+                    if let Some(state) = render_context.get_resource_state(handle) {
+                        final_states.push((keys.key(handle), state));
+                    }
+                }
+            });
+        }
+
+        CachedGraph {
+            retained_nodes: nodes.node_indices().collect(),
+            sorted_nodes: sorted_nodes.to_vec(),
+            command_lists: command_lists.iter().map(|list| {
+                (list.nodes.clone(), list.wait.as_ref().map(|wait| wait.wait_stage_mask))
+            }).collect(),
+            node_barriers: cached_barriers,
+            final_barriers: CachedNodeBarriers::capture(final_barriers, keys),
+            attachment_layouts,
+            image_layouts,
+            final_states
+        }
+    }
+
+    /// Applies the cached compile and link results to a graph with the same fingerprint
+    pub(crate) fn apply(
+        &self,
+        nodes: &mut StableDiGraph<PassType, u32>,
+        node_barriers: &mut HashMap<NodeIndex, NodeBarriers>,
+        final_barriers: &mut NodeBarriers,
+        keys: &ResourceKeys,
+        render_context: &VulkanRenderContext) -> Vec<CommandList> {
+
+        nodes.retain_nodes(|_graph, node_index| {
+            self.retained_nodes.contains(&node_index)
+        });
+
+        let mut resources: HashMap<ResourceKey, Rc<RefCell<DeviceResource>>> = HashMap::new();
+        for node_index in &self.sorted_nodes {
+            for_each_resource(&nodes[*node_index], |resource| {
+                resources.entry(keys.key(resource.borrow().get_handle())).or_insert_with(|| resource.clone());
+            });
+        }
+        let resolve = |key: ResourceKey| {
+            resources.get(&key).expect("Cached barrier refers to a resource not in the graph").clone()
+        };
+
+        for (node_index, barriers) in &self.node_barriers {
+            node_barriers.insert(*node_index, barriers.resolve(&resolve));
+        }
+        *final_barriers = self.final_barriers.resolve(&resolve);
+
+        for (node_index, rt_attachments, dt_attachment) in &self.attachment_layouts {
+            if let Some(PassType::Graphics(gn)) = nodes.node_weight_mut(*node_index) {
+                for (rt, cached) in gn.render_targets.iter_mut().zip(rt_attachments) {
+                    cached.apply(rt);
+                }
+                if let (Some(dt), Some(cached)) = (gn.depth_target.as_mut(), dt_attachment) {
+                    cached.apply(dt);
+                }
+            }
+        }
+
+        for (key, layout) in &self.image_layouts {
+            resolve(*key).borrow_mut().get_image_mut().layout = *layout;
+        }
+        for (key, state) in &self.final_states {
+            render_context.update_resource_state(resolve(*key).borrow().get_handle(), *state);
+        }
+
+        self.command_lists.iter().map(|(list_nodes, wait)| {
+            let mut command_list = CommandList::new();
+            command_list.nodes = list_nodes.clone();
+            command_list.wait = wait.map(|wait_stage_mask| QueueWait { wait_stage_mask });
+            command_list
+        }).collect()
+    }
+}
+
+/// Compile and link results keyed by graph fingerprint
+#[derive(Default)]
+pub(crate) struct GraphCache {
+    entries: HashMap<u64, (u64, CachedGraph)>,
+    use_counter: u64
+}
+
+impl GraphCache {
+    pub(crate) fn get(&mut self, fingerprint: u64) -> Option<&CachedGraph> {
+        self.use_counter += 1;
+        let use_counter = self.use_counter;
+        self.entries.get_mut(&fingerprint).map(|(last_used, cached)| {
+            *last_used = use_counter;
+            &*cached
+        })
+    }
+
+    pub(crate) fn insert(&mut self, fingerprint: u64, cached: CachedGraph) {
+        if self.entries.len() >= GRAPH_CACHE_CAPACITY {
+            let least_recent = self.entries.iter()
+                .min_by_key(|(_, (last_used, _))| *last_used)
+                .map(|(fingerprint, _)| *fingerprint);
+            if let Some(least_recent) = least_recent {
+                self.entries.remove(&least_recent);
+            }
+        }
+        self.entries.insert(fingerprint, (self.use_counter, cached));
+    }
+
+}
+
+impl Debug for GraphCache {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GraphCache")
+            .field("cached graphs", &self.entries.len())
+            .finish()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn color(clear_value: vk::ClearValue) -> [f32; 4] {
+        unsafe { clear_value.color.float32 }
+    }
+
+    fn clear_color(color: [f32; 4]) -> vk::ClearValue {
+        vk::ClearValue { color: vk::ClearColorValue { float32: color } }
+    }
+
+    #[test]
+    fn cached_graphs_keep_the_current_clear_value() {
+        // the first frame's graph was cached clearing to red, the second clears to blue
+        let cached = AttachmentLoad::Clear(clear_color([1.0, 0.0, 0.0, 1.0]));
+        let current = AttachmentLoad::Clear(clear_color([0.0, 0.0, 1.0, 1.0]));
+        match replayed_load(current, cached) {
+            AttachmentLoad::Clear(clear_value) => assert_eq!(color(clear_value), [0.0, 0.0, 1.0, 1.0]),
+            _ => panic!("Expected a clear")
+        }
+
+        let cached = AttachmentLoad::ClearDepthStencil(vk::ClearValue {
+            depth_stencil: vk::ClearDepthStencilValue { depth: 1.0, stencil: 0 }
+        });
+        let current = AttachmentLoad::ClearDepthStencil(vk::ClearValue {
+            depth_stencil: vk::ClearDepthStencilValue { depth: 0.0, stencil: 3 }
+        });
+        match replayed_load(current, cached) {
+            AttachmentLoad::ClearDepthStencil(clear_value) => {
+                let depth_stencil = unsafe { clear_value.depth_stencil };
+                assert_eq!((depth_stencil.depth, depth_stencil.stencil), (0.0, 3));
+            },
+            _ => panic!("Expected a depth-stencil clear")
+        }
+    }
+
+    #[test]
+    fn cached_graphs_replay_merged_clears() {
+        let merged = AttachmentLoad::Clear(clear_color([0.2, 0.4, 0.6, 1.0]));
+        match replayed_load(AttachmentLoad::Auto, merged) {
+            AttachmentLoad::Clear(clear_value) => assert_eq!(color(clear_value), [0.2, 0.4, 0.6, 1.0]),
+            _ => panic!("Expected the merged clear")
+        }
+        assert!(matches!(replayed_load(AttachmentLoad::Auto, AttachmentLoad::Auto), AttachmentLoad::Auto));
+        assert!(matches!(replayed_load(AttachmentLoad::Load, AttachmentLoad::Load), AttachmentLoad::Load));
+    }
+}
\ No newline at end of file
@@ -0,0 +1,1768 @@
+//! Sorting, culling and barrier generation over pass metadata alone. VulkanFrameGraph::compile
+//! and link adapt PassType to these, so the graph logic can run without a device.
+
+use std::cell::RefCell;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use ash::vk;
+use multimap::MultiMap;
+use petgraph::algo::has_path_connecting;
+use petgraph::stable_graph::{NodeIndex, StableDiGraph};
+use petgraph::visit::Dfs;
+use api_types::resource_state::{ResourceState, ResourceStateRegistry};
+use context::vulkan_render_context::VulkanRenderContext;
+use crate::barrier::SubresourceRange;
+use crate::command_list::{CommandList, QueueWait};
+use crate::graph_debug::graph_debug;
+
+pub(crate) fn is_write(access: vk::AccessFlags, stage: vk::PipelineStageFlags) -> bool {
+    let write_access=
+        vk::AccessFlags::COLOR_ATTACHMENT_WRITE |
+            vk::AccessFlags::SHADER_WRITE |
+            vk::AccessFlags::TRANSFER_WRITE |
+            vk::AccessFlags::HOST_WRITE |
+            vk::AccessFlags::MEMORY_WRITE;
+
+    let pipeline_write = vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT;
+
+    (write_access & access != vk::AccessFlags::NONE) || (pipeline_write & stage != vk::PipelineStageFlags::NONE)
+}
+
+/// How a node uses a resource, which decides when a barrier is needed
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) enum AccessKind {
+    /// A buffer bound as a descriptor or declared as a dependency
+    Buffer { offset: u64, size: u64 },
+    /// An image bound as a descriptor or declared as a dependency. `mip_levels` and
+    /// `array_layers` are the image's own, which the ranges tracked for it are clamped to
+    Image { layout: vk::ImageLayout, subresource: SubresourceRange, mip_levels: u32, array_layers: u32 },
+    /// Transitioned by the renderpass instead of a barrier when an earlier subpass of the
+    /// same group wrote it
+    InputAttachment { layout: vk::ImageLayout },
+    /// A render target or depth target, which always writes
+    Attachment { layout: vk::ImageLayout },
+    /// A buffer copy source or destination; `initial_stage` is waited on if the buffer
+    /// has no earlier usage
+    CopyBuffer { size: u64, initial_stage: vk::PipelineStageFlags },
+    CopyImage { layout: vk::ImageLayout, initial_stage: vk::PipelineStageFlags },
+    /// A swapchain image, which starts from `current_layout` if it has no earlier usage and is
+    /// left in `final_layout` (PRESENT_SRC_KHR unless it's handed back to an external presenter)
+    Present { current_layout: vk::ImageLayout, final_layout: vk::ImageLayout }
+}
+
+impl AccessKind {
+    fn get_layout(&self) -> Option<vk::ImageLayout> {
+        match self {
+            AccessKind::Buffer { .. } | AccessKind::CopyBuffer { .. } => None,
+            AccessKind::Image { layout, .. } |
+            AccessKind::InputAttachment { layout } |
+            AccessKind::Attachment { layout } |
+            AccessKind::CopyImage { layout, .. } => Some(*layout),
+            AccessKind::Present { final_layout, .. } => Some(*final_layout)
+        }
+    }
+
+    /// Attachments, copies and presents always cover the whole image
+    fn get_subresource(&self) -> SubresourceRange {
+        match self {
+            AccessKind::Image { subresource, .. } => *subresource,
+            _ => SubresourceRange::WHOLE
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) struct ResourceAccess {
+    pub handle: u64,
+    pub access: vk::AccessFlags,
+    pub stage: vk::PipelineStageFlags,
+    pub kind: AccessKind
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) enum BarrierRange {
+    Buffer { offset: u64, size: u64 },
+    Image { old_layout: vk::ImageLayout, new_layout: vk::ImageLayout, subresource: SubresourceRange }
+}
+
+/// A barrier link decided on, before it's turned into an ImageBarrier or BufferBarrier
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) struct Transition {
+    pub handle: u64,
+    pub source_stage: vk::PipelineStageFlags,
+    pub dest_stage: vk::PipelineStageFlags,
+    pub source_access: vk::AccessFlags,
+    pub dest_access: vk::AccessFlags,
+    pub range: BarrierRange
+}
+
+/// What compile and link need to know about a node
+pub(crate) trait GraphNode {
+    fn get_name(&self) -> &str;
+    fn get_reads(&self) -> Vec<u64>;
+    fn get_writes(&self) -> Vec<u64>;
+    /// Resources the node writes entirely without reading their previous contents (e.g.
+    /// attachments which aren't loaded), even though they're also in get_reads
+    fn get_overwrites(&self) -> Vec<u64>;
+    /// Attachments the node loads the previous contents of
+    fn get_loads(&self) -> Vec<u64>;
+    fn get_priority(&self) -> i32;
+    /// Nodes this node must execute after without sharing a resource with them
+    fn get_execute_after(&self) -> Vec<NodeIndex>;
+    fn get_renderpass_group(&self) -> Option<&str>;
+    /// Present nodes execute last, each starting a new command list which is ordered after the
+    /// previous one by a semaphore
+    fn is_present(&self) -> bool;
+    /// Every resource access in the order they're linked: descriptors, dependencies, input
+    /// attachments and then attachments
+    fn get_accesses(&self) -> Vec<ResourceAccess>;
+}
+
+/// Where link reads the usage of resources from before the frame and stores it afterwards
+pub(crate) trait ResourceStateStore {
+    fn get_resource_state(&self, handle: u64) -> Option<ResourceState>;
+    fn update_resource_state(&self, handle: u64, state: ResourceState);
+}
+
+impl ResourceStateStore for VulkanRenderContext {
+    fn get_resource_state(&self, handle: u64) -> Option<ResourceState> {
+        VulkanRenderContext::get_resource_state(self, handle)
+    }
+
+    fn update_resource_state(&self, handle: u64, state: ResourceState) {
+        VulkanRenderContext::update_resource_state(self, handle, state);
+    }
+}
+
+impl ResourceStateStore for RefCell<ResourceStateRegistry> {
+    fn get_resource_state(&self, handle: u64) -> Option<ResourceState> {
+        self.borrow().get(handle)
+    }
+
+    fn update_resource_state(&self, handle: u64, state: ResourceState) {
+        self.borrow_mut().update(handle, state);
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct NodeLink {
+    /// The node these barriers are recorded by, which is the first node of the group for
+    /// members of a renderpass group
+    pub barrier_node: NodeIndex,
+    /// Barriers paired with the index of the access in get_accesses they were made for
+    pub transitions: Vec<(usize, Transition)>,
+    /// Input attachments the renderpass transitions to their new layout without a barrier
+    pub implicit_transitions: Vec<usize>
+}
+
+pub(crate) struct LinkedGraph {
+    pub nodes: Vec<(NodeIndex, NodeLink)>,
+    pub command_lists: Vec<CommandList>,
+    /// Transitions recorded after every node, for images whose parts were left in different
+    /// layouts, paired with the node and index of the access which last used the image
+    pub final_transitions: Vec<(NodeIndex, usize, Transition)>
+}
+
+/// The usage assumed for an image the first time it appears. An image with no persistent state
+/// hasn't been used by the framegraph or declared with Frame::import_resource, so its first use
+/// is treated as an initialization from UNDEFINED whatever its layout field says
+fn initial_image_usage(handle: u64) -> ResourceState {
+    log::trace!(target: "framegraph", "Initializing image {} from UNDEFINED", handle);
+    ResourceState {
+        access: vk::AccessFlags::NONE,
+        stage: vk::PipelineStageFlags::TOP_OF_PIPE,
+        layout: Some(vk::ImageLayout::UNDEFINED)
+    }
+}
+
+fn make_transition(
+    handle: u64,
+    subresource: SubresourceRange,
+    last_usage: &ResourceState,
+    new_usage: &ResourceState,
+    buffer_range: Option<(u64, u64)>) -> Transition {
+    Transition {
+        handle,
+        source_stage: last_usage.stage,
+        dest_stage: new_usage.stage,
+        source_access: last_usage.access,
+        dest_access: new_usage.access,
+        range: match buffer_range {
+            Some((offset, size)) => BarrierRange::Buffer { offset, size },
+            None => BarrierRange::Image {
+                old_layout: last_usage.layout.expect("Using a non-image for an image transition"),
+                new_layout: new_usage.layout.unwrap(),
+                subresource
+            }
+        }
+    }
+}
+
+/// The most recent usage of every resource linked so far. An image is tracked as one range until
+/// parts of it are used separately (e.g. the mips of a downsample chain or the faces of a cube),
+/// after which each part used differently has its own disjoint range
+#[derive(Default)]
+struct UsageCache {
+    usages: HashMap<u64, Vec<(SubresourceRange, ResourceState)>>,
+    // the node and access which last used each resource
+    last_accesses: HashMap<u64, (NodeIndex, usize)>,
+    // the mip levels and array layers of images accessed by subresource range. The parts left
+    // over from splitting their ranges are clamped to these, so none lie beyond the image
+    image_bounds: HashMap<u64, (u32, u32)>
+}
+
+fn clamp_to_image(range: SubresourceRange, image_bounds: Option<(u32, u32)>) -> Option<SubresourceRange> {
+    match image_bounds {
+        Some((mip_levels, array_layers)) => range.clamp(mip_levels, array_layers),
+        None => Some(range)
+    }
+}
+
+impl UsageCache {
+    fn seed(&mut self, handle: u64, state: ResourceState) {
+        self.usages.insert(handle, vec![(SubresourceRange::WHOLE, state)]);
+    }
+
+    /// The parts of `subresource` paired with their last usage, or None for parts which
+    /// haven't been used
+    fn last_usages(&self, handle: u64, subresource: SubresourceRange) -> Vec<(SubresourceRange, Option<ResourceState>)> {
+        let image_bounds = self.image_bounds.get(&handle).copied();
+        let subresource = match clamp_to_image(subresource, image_bounds) {
+            Some(subresource) => subresource,
+            None => return Vec::new()
+        };
+        let mut last_usages = Vec::new();
+        let mut unused = vec![subresource];
+        if let Some(usages) = self.usages.get(&handle) {
+            for (range, state) in usages {
+                if let Some(overlap) = range.intersect(&subresource) {
+                    last_usages.push((overlap, Some(*state)));
+                    unused = unused.iter()
+                        .flat_map(|part| part.subtract(&overlap))
+                        .filter_map(|part| clamp_to_image(part, image_bounds))
+                        .collect();
+                }
+            }
+        }
+        last_usages.extend(unused.into_iter().map(|part| (part, None)));
+        last_usages
+    }
+
+    fn update(&mut self, handle: u64, subresource: SubresourceRange, state: ResourceState, last_access: (NodeIndex, usize)) {
+        let image_bounds = self.image_bounds.get(&handle).copied();
+        let subresource = match clamp_to_image(subresource, image_bounds) {
+            Some(subresource) => subresource,
+            None => return
+        };
+        let usages = self.usages.entry(handle).or_insert_with(|| match subresource.is_whole() {
+            true => Vec::new(),
+            // the rest of an image used for the first time is still uninitialized
+            false => vec![(SubresourceRange::WHOLE, initial_image_usage(handle))]
+        });
+        let mut updated: Vec<(SubresourceRange, ResourceState)> = usages.drain(..)
+            .flat_map(|(range, old_state)| range.subtract(&subresource).into_iter().map(move |part| (part, old_state)))
+            .filter_map(|(part, old_state)| clamp_to_image(part, image_bounds).map(|part| (part, old_state)))
+            .collect();
+        // the most recent usage is always last
+        updated.push((subresource, state));
+        *usages = updated;
+        self.last_accesses.insert(handle, last_access);
+    }
+}
+
+/// Kahn's algorithm over the dependency graph (edges point from a reader to the node which wrote
+/// what it reads). Of the nodes whose dependencies have all been scheduled, the one with the highest
+/// priority executes next, with ties going to the node added to the Frame first. Node indices in a
+/// StableDiGraph are never reused, so index order is insertion order and the result is reproducible.
+pub(crate) fn stable_toposort<N: GraphNode>(nodes: &StableDiGraph<N, u32>) -> Vec<NodeIndex> {
+    let mut remaining_dependencies: HashMap<NodeIndex, usize> = HashMap::new();
+    let mut ready: BinaryHeap<(i32, Reverse<usize>)> = BinaryHeap::new();
+    for node_index in nodes.node_indices() {
+        let dependency_count = nodes.neighbors_directed(node_index, petgraph::Direction::Outgoing).count();
+        if dependency_count == 0 {
+            ready.push((nodes[node_index].get_priority(), Reverse(node_index.index())));
+        }
+        remaining_dependencies.insert(node_index, dependency_count);
+    }
+
+    let mut sorted_nodes: Vec<NodeIndex> = Vec::with_capacity(nodes.node_count());
+    while let Some((_, Reverse(index))) = ready.pop() {
+        let node_index = NodeIndex::new(index);
+        sorted_nodes.push(node_index);
+        for dependent in nodes.neighbors_directed(node_index, petgraph::Direction::Incoming) {
+            let count = remaining_dependencies.get_mut(&dependent)
+                .expect("Dependent node missing from sort");
+            *count -= 1;
+            if *count == 0 {
+                ready.push((nodes[dependent].get_priority(), Reverse(dependent.index())));
+            }
+        }
+    }
+
+    if sorted_nodes.len() != nodes.node_count() {
+        // petgraph's sort visits nodes in index order, so the node it finds on a cycle is the
+        // same on every run
+        let cycle_node = match petgraph::algo::toposort(nodes, None) {
+            Err(cycle) => nodes[cycle.node_id()].get_name(),
+            Ok(_) => unreachable!("Nodes were left unsorted without a cycle")
+        };
+        panic!("A cycle was detected in the framegraph involving node {:?}", cycle_node);
+    }
+
+    sorted_nodes
+}
+
+/// Members of a renderpass group must execute back-to-back, so any nodes which were sorted
+/// in between them are moved ahead of the group if they don't depend on it, or after it otherwise
+pub(crate) fn gather_renderpass_groups<N: GraphNode>(nodes: &StableDiGraph<N, u32>, sorted_nodes: Vec<NodeIndex>) -> Vec<NodeIndex> {
+    let mut sorted_nodes = sorted_nodes;
+    let mut position = 0;
+    while position < sorted_nodes.len() {
+        let group = match nodes[sorted_nodes[position]].get_renderpass_group() {
+            Some(group) => group,
+            None => {
+                position += 1;
+                continue;
+            }
+        };
+        let last = sorted_nodes.iter().rposition(|index| {
+            nodes[*index].get_renderpass_group() == Some(group)
+        }).unwrap();
+
+        let mut before: Vec<NodeIndex> = Vec::new();
+        let mut members: Vec<NodeIndex> = Vec::new();
+        let mut after: Vec<NodeIndex> = Vec::new();
+        for index in &sorted_nodes[position..=last] {
+            // outgoing edges point from a node to the nodes it depends on
+            let depends_on = |set: &[NodeIndex]| {
+                nodes.neighbors(*index).any(|dependency| set.contains(&dependency))
+            };
+            if nodes[*index].get_renderpass_group() == Some(group) {
+                if depends_on(&after[..]) {
+                    panic!("Node {} in renderpass group {} depends on a node outside the group which itself depends on the group",
+                        nodes[*index].get_name(),
+                        group);
+                }
+                members.push(*index);
+            } else if depends_on(&members[..]) || depends_on(&after[..]) {
+                after.push(*index);
+            } else {
+                before.push(*index);
+            }
+        }
+
+        let next_position = position + before.len() + members.len();
+        sorted_nodes.splice(position..=last, before.into_iter().chain(members).chain(after));
+        position = next_position;
+    }
+
+    sorted_nodes
+}
+
+/// Adds an edge for every dependency between nodes, removes the nodes none of the roots
+/// depend on and returns the execution order of the rest
+pub(crate) fn compile<N: GraphNode>(nodes: &mut StableDiGraph<N, u32>, root_indices: &[NodeIndex]) -> Vec<NodeIndex> {
+    // create input/output maps to detect graph edges
+    let mut input_map = MultiMap::new();
+    let mut output_map = MultiMap::new();
+    for node_index in nodes.node_indices() {
+        let node = &nodes[node_index];
+        for read in node.get_reads() {
+            input_map.insert(read, node_index);
+        }
+        for write in node.get_writes() {
+            output_map.insert(write, node_index);
+        }
+    }
+
+    // A read depends on every write of the same resource added to the Frame before it.
+    // Node indices are insertion order, and these edges always point at an earlier node
+    // so they can't form a cycle. Reads with no earlier write are resolved afterwards
+    let mut deferred_reads: Vec<(u64, NodeIndex)> = Vec::new();
+    for (input, readers) in input_map.iter_all() {
+        let writers = output_map.get_vec(input).map(|writers| writers.as_slice()).unwrap_or(&[]);
+        for reader in readers {
+            let earlier_writers: Vec<NodeIndex> = writers.iter()
+                .filter(|writer| writer.index() < reader.index())
+                .cloned()
+                .collect();
+            if earlier_writers.is_empty() {
+                deferred_reads.push((*input, *reader));
+            }
+            for writer in earlier_writers {
+                // use update_edge instead of add_edge to avoid duplicates
+                nodes.update_edge(*reader, writer, 0);
+                graph_debug!(resource = *input, reader = nodes[*reader].get_name(), writer = nodes[writer].get_name(),
+                    "read-after-write edge");
+            }
+        }
+    }
+
+    // A read with no earlier write sees the resource after all of its later writes (e.g. a
+    // present node added before the passes rendering to the swapchain), unless one of those
+    // writes already depends on the reader. Then it reads the resource's contents from
+    // before the frame instead, like the first pass of a ping-pong chain
+    let mut initial_reads: HashSet<(u64, NodeIndex)> = HashSet::new();
+    for (input, reader) in deferred_reads {
+        let later_writers: Vec<NodeIndex> = output_map.get_vec(&input).into_iter().flatten()
+            .filter(|writer| **writer != reader)
+            .cloned()
+            .collect();
+        let reads_initial = later_writers.iter().any(|writer| {
+            has_path_connecting(&*nodes, *writer, reader, None)
+        });
+        if reads_initial {
+            graph_debug!(resource = input, reader = nodes[reader].get_name(),
+                "no edge to later writes, which depend on the reader; it reads the contents from before the frame");
+            initial_reads.insert((input, reader));
+        } else {
+            for writer in later_writers {
+                nodes.update_edge(reader, writer, 0);
+                graph_debug!(resource = input, reader = nodes[reader].get_name(), writer = nodes[writer].get_name(),
+                    "edge to a later write, since nothing writes the resource before the reader");
+            }
+        }
+    }
+
+    // Explicit ordering between nodes which share no resources. Like a read, it keeps the
+    // earlier node from being culled while the later one is retained. Nodes ordered after a
+    // disabled node (see Frame::set_enabled) are ordered as if it had never been added
+    let explicit_edges: Vec<(NodeIndex, NodeIndex)> = nodes.node_indices()
+        .flat_map(|node_index| {
+            nodes[node_index].get_execute_after().into_iter().map(move |after| (node_index, after))
+        })
+        .collect();
+    for (node_index, after) in explicit_edges {
+        assert!(after != node_index, "Node {} can't execute after itself", nodes[node_index].get_name());
+        if !nodes.contains_node(after) {
+            graph_debug!(node = nodes[node_index].get_name(), "execute-after edge to a disabled node ignored");
+            continue;
+        }
+        nodes.update_edge(node_index, after, 0);
+        graph_debug!(node = nodes[node_index].get_name(), after = nodes[after].get_name(), "explicit execute-after edge");
+    }
+
+    // Use DFS to find all accessible nodes from each root node
+    {
+        let mut retained_nodes: Vec<bool> = Vec::new();
+        retained_nodes.resize(nodes.node_bound(), false);
+
+        let mut dfs = Dfs::empty(&*nodes);
+        for root_index in root_indices {
+            // the discovered set persists across roots so shared producers are only visited once
+            dfs.move_to(*root_index);
+            while let Some(node_id) = dfs.next(&*nodes) {
+                retained_nodes[node_id.index()] = true;
+            }
+        }
+
+        #[cfg(feature = "graph-debug")]
+        for node_index in nodes.node_indices().filter(|node_index| !retained_nodes[node_index.index()]) {
+            let writes = nodes[node_index].get_writes();
+            let reason = match writes.is_empty() {
+                true => "it writes nothing",
+                false => "no root depends on its writes"
+            };
+            graph_debug!(node = nodes[node_index].get_name(), ?writes, reason, "culled node");
+        }
+
+        nodes.retain_nodes(|_graph, node_index| {
+            retained_nodes[node_index.index()]
+        });
+    }
+
+    // Writes must also wait for earlier reads of the previous contents (write-after-read),
+    // and for earlier writes (write-after-write). These edges are only added once unused
+    // nodes are culled, since a write doesn't need the accesses before it to happen at all
+    for (output, writers) in output_map.iter_all() {
+        let readers = input_map.get_vec(output).map(|readers| readers.as_slice()).unwrap_or(&[]);
+        for writer in writers.iter().filter(|writer| nodes.contains_node(**writer)) {
+            for reader in readers {
+                let reads_earlier_contents = reader.index() < writer.index() &&
+                    (initial_reads.contains(&(*output, *reader)) ||
+                        writers.iter().any(|earlier| earlier.index() < reader.index()));
+                if reads_earlier_contents && nodes.contains_node(*reader) {
+                    nodes.update_edge(*writer, *reader, 0);
+                    graph_debug!(resource = *output, writer = nodes[*writer].get_name(), reader = nodes[*reader].get_name(),
+                        "write-after-read edge");
+                }
+            }
+            for earlier in writers.iter().filter(|earlier| earlier.index() < writer.index()) {
+                if nodes.contains_node(*earlier) {
+                    nodes.update_edge(*writer, *earlier, 0);
+                    graph_debug!(resource = *output, writer = nodes[*writer].get_name(), earlier_writer = nodes[*earlier].get_name(),
+                        "write-after-write edge");
+                }
+            }
+        }
+    }
+
+    // unresolved and unused passes have been removed from the graph,
+    // so now we can use a topological sort to generate an execution order
+    let sorted_nodes = stable_toposort(&*nodes);
+    let mut sorted_nodes = gather_renderpass_groups(&*nodes, sorted_nodes);
+    // other roots may read the swapchain image without an edge to the present
+    // node, so present nodes always execute last
+    sorted_nodes.sort_by_key(|index| nodes[*index].is_present());
+    for i in &sorted_nodes {
+        log::trace!(target: "framegraph", "Sorted node: {:?}", nodes[*i].get_name())
+    }
+
+    sorted_nodes
+}
+
+/// The transient resources each sorted node writes which nothing after it in the frame reads,
+/// found by working back from the last node. A node which overwrites a resource doesn't read
+/// it, and nothing before the overwrite can be read after it. Persistent resources are always
+/// live, since later frames or other work may read them
+pub(crate) fn find_dead_writes<N: GraphNode>(
+    nodes: &StableDiGraph<N, u32>,
+    sorted_nodes: &[NodeIndex],
+    transient_handles: &HashSet<u64>) -> Vec<(NodeIndex, Vec<u64>)> {
+
+    let mut live: HashSet<u64> = HashSet::new();
+    let mut dead_writes: Vec<(NodeIndex, Vec<u64>)> = Vec::new();
+    for node_index in sorted_nodes.iter().rev() {
+        let node = &nodes[*node_index];
+        let mut dead: Vec<u64> = node.get_writes().into_iter()
+            .filter(|handle| transient_handles.contains(handle) && !live.contains(handle))
+            .collect();
+        dead.sort_unstable();
+        dead.dedup();
+
+        let overwrites = node.get_overwrites();
+        for handle in &overwrites {
+            live.remove(handle);
+        }
+        live.extend(node.get_reads().into_iter().filter(|handle| !overwrites.contains(handle)));
+
+        if !dead.is_empty() {
+            graph_debug!(node = node.get_name(), resources = ?dead, "dead writes");
+            dead_writes.push((*node_index, dead));
+        }
+    }
+
+    dead_writes.reverse();
+    dead_writes
+}
+
+/// Panics if a transient resource is read before any node in the frame has written it, since
+/// its contents would have to come from an earlier frame. Must run after load ops are deduced
+pub(crate) fn validate_transient_reads<N: GraphNode>(
+    nodes: &StableDiGraph<N, u32>,
+    sorted_nodes: &[NodeIndex],
+    transient_handles: &HashSet<u64>) {
+
+    let mut written: HashSet<u64> = HashSet::new();
+    for node_index in sorted_nodes {
+        let node = &nodes[*node_index];
+        let writes = node.get_writes();
+        // attachments are both read and written, and only read their contents when loaded
+        for read in node.get_reads() {
+            if transient_handles.contains(&read) && !written.contains(&read) && !writes.contains(&read) {
+                panic!("Node {} reads transient resource {} before it has been written in this frame", node.get_name(), read);
+            }
+        }
+        for handle in node.get_loads() {
+            if transient_handles.contains(&handle) && !written.contains(&handle) {
+                panic!("Node {} loads transient attachment {} before it has been written in this frame", node.get_name(), handle);
+            }
+        }
+        written.extend(writes);
+    }
+}
+
+/// Panics if a resource which was transient in the previous frame is referenced by this frame
+/// without being declared transient again. The previous frame's contents are gone and the
+/// resource may already have been handed to someone else
+pub(crate) fn validate_transient_lifetimes<N: GraphNode>(
+    nodes: &StableDiGraph<N, u32>,
+    transient_handles: &HashSet<u64>,
+    previous_transients: &HashSet<u64>) {
+
+    for node in nodes.node_weights() {
+        for handle in node.get_reads().into_iter().chain(node.get_writes()) {
+            if previous_transients.contains(&handle) && !transient_handles.contains(&handle) {
+                panic!("Node {} references resource {} as persistent, but it was transient in the previous frame",
+                    node.get_name(), handle);
+            }
+        }
+    }
+}
+
+/// Panics if a resource imported as persistent (see Frame::import_persistent) is also transient
+/// to the frame, since its contents wouldn't be there for the next frame to continue from
+pub(crate) fn validate_persistent_resources(
+    persistent_handles: &HashSet<u64>,
+    transient_handles: &HashSet<u64>) {
+
+    if let Some(handle) = persistent_handles.intersection(transient_handles).next() {
+        panic!("Resource {} is imported as persistent, but is transient to the frame", handle);
+    }
+}
+
+/// Decides the barriers before each sorted node from the usage of every resource before it,
+/// splits the nodes into command lists and stores the final usage of each resource
+pub(crate) fn link<N: GraphNode>(
+    nodes: &StableDiGraph<N, u32>,
+    sorted_nodes: &[NodeIndex],
+    resource_states: &dyn ResourceStateStore) -> LinkedGraph {
+
+    let mut linked_nodes: Vec<(NodeIndex, NodeLink)> = Vec::with_capacity(sorted_nodes.len());
+    let mut command_lists: Vec<CommandList> = Vec::new();
+    let mut current_list = CommandList::new();
+
+    // All image bindings and attachments require the most recent usage for that resource
+    // in case layout transitions are necessary. Since the graph has already been sorted,
+    // we can just iterate over the sorted nodes to do this
+    let mut usage_cache = UsageCache::default();
+
+    // Seed the usage cache with the persistent state of every resource in this frame,
+    // since they may have been used by previous frames or by work outside the framegraph
+    for node_index in sorted_nodes {
+        let node = &nodes[*node_index];
+        for handle in node.get_reads().into_iter().chain(node.get_writes()) {
+            if let Some(state) = resource_states.get_resource_state(handle) {
+                usage_cache.seed(handle, state);
+            }
+        }
+    }
+
+    // Subpasses of a renderpass group can't record barriers, so barriers for every
+    // member are issued by the first node of the group. Attachments shared within the
+    // group are synchronized by the renderpass' subpass dependencies instead
+    let mut active_group: Option<(String, NodeIndex)> = None;
+    let mut group_attachments: HashSet<u64> = HashSet::new();
+    for node_index in sorted_nodes {
+        let node = &nodes[*node_index];
+        let mut node_link = NodeLink {
+            barrier_node: *node_index,
+            ..Default::default()
+        };
+
+        match node.get_renderpass_group() {
+            Some(group_name) => match &active_group {
+                Some((active_name, leader)) if active_name == group_name => {
+                    node_link.barrier_node = *leader;
+                },
+                _ => {
+                    active_group = Some((group_name.to_string(), *node_index));
+                    group_attachments.clear();
+                }
+            },
+            None => {
+                active_group = None;
+                group_attachments.clear();
+            }
+        }
+        let grouped = active_group.is_some();
+
+        for (access_index, access) in node.get_accesses().iter().enumerate() {
+            let handle = access.handle;
+            if let AccessKind::Image { mip_levels, array_layers, .. } = access.kind {
+                usage_cache.image_bounds.insert(handle, (mip_levels, array_layers));
+            }
+            let subresource = access.kind.get_subresource();
+            let new_usage = ResourceState {
+                access: access.access,
+                stage: access.stage,
+                layout: access.kind.get_layout()
+            };
+            // every part of the access with a different last usage gets its own barrier
+            let last_usages = usage_cache.last_usages(handle, subresource);
+
+            let transitions: Vec<Transition> = match access.kind {
+                AccessKind::Buffer { offset, size } => {
+                    assert!(!group_attachments.contains(&handle),
+                        "Node {} binds an attachment of its renderpass group as a descriptor, it must be used as an input attachment instead",
+                        node.get_name());
+                    // barrier required if:
+                    //  * last usage was a write (RAW / WAW)
+                    //  * this usage is a write following a read (WAR)
+                    last_usages.into_iter()
+                        .filter_map(|(_, last_usage)| last_usage)
+                        .filter(|last_usage| is_write(last_usage.access, last_usage.stage) || is_write(new_usage.access, new_usage.stage))
+                        .map(|last_usage| make_transition(handle, subresource, &last_usage, &new_usage, Some((offset, size))))
+                        .collect()
+                },
+                AccessKind::Image { .. } | AccessKind::InputAttachment { .. } => {
+                    let is_input_attachment = matches!(access.kind, AccessKind::InputAttachment { .. });
+                    if is_input_attachment && group_attachments.contains(&handle) {
+                        // written by an earlier subpass, the renderpass handles the transition
+                        node_link.implicit_transitions.push(access_index);
+                        Vec::new()
+                    } else {
+                        if is_input_attachment {
+                            group_attachments.insert(handle);
+                        } else {
+                            assert!(!group_attachments.contains(&handle),
+                                "Node {} binds an attachment of its renderpass group as a descriptor, it must be used as an input attachment instead",
+                                node.get_name());
+                        }
+                        // barrier required if:
+                        //  * last usage was a write (RAW / WAW)
+                        //  * this usage is a write following a read (WAR)
+                        //  * image layout has changed
+                        last_usages.into_iter().filter_map(|(part, last_usage)| {
+                            let last_usage = last_usage.unwrap_or_else(|| initial_image_usage(handle));
+                            let layout_changed = last_usage.layout != new_usage.layout;
+                            (layout_changed || is_write(last_usage.access, last_usage.stage) || is_write(new_usage.access, new_usage.stage))
+                                .then(|| make_transition(handle, part, &last_usage, &new_usage, None))
+                        }).collect()
+                    }
+                },
+                AccessKind::Attachment { .. } => {
+                    // attachments always write, so we need a barrier unless another pass in
+                    // the same group already transitioned it
+                    let transitions: Vec<Transition> = match group_attachments.contains(&handle) {
+                        true => Vec::new(),
+                        false => last_usages.into_iter().map(|(part, last_usage)| {
+                            let last_usage = last_usage.unwrap_or_else(|| initial_image_usage(handle));
+                            make_transition(handle, part, &last_usage, &new_usage, None)
+                        }).collect()
+                    };
+                    if grouped {
+                        group_attachments.insert(handle);
+                    }
+                    transitions
+                },
+                AccessKind::CopyBuffer { size, initial_stage } => {
+                    // for copy sources and destinations, a barrier is always required
+                    last_usages.into_iter().map(|(_, last_usage)| {
+                        let last_usage = last_usage.unwrap_or(ResourceState {
+                            access: vk::AccessFlags::NONE,
+                            stage: initial_stage,
+                            layout: None
+                        });
+                        make_transition(handle, subresource, &last_usage, &new_usage, Some((0, size)))
+                    }).collect()
+                },
+                AccessKind::CopyImage { initial_stage, .. } => {
+                    last_usages.into_iter().map(|(part, last_usage)| {
+                        let last_usage = last_usage.unwrap_or(ResourceState {
+                            access: vk::AccessFlags::NONE,
+                            stage: initial_stage,
+                            layout: Some(vk::ImageLayout::UNDEFINED)
+                        });
+                        make_transition(handle, part, &last_usage, &new_usage, None)
+                    }).collect()
+                },
+                AccessKind::Present { current_layout, .. } => {
+                    last_usages.into_iter().map(|(part, last_usage)| {
+                        let last_usage = last_usage.unwrap_or(ResourceState {
+                            access: vk::AccessFlags::NONE,
+                            stage: vk::PipelineStageFlags::TOP_OF_PIPE,
+                            layout: Some(current_layout)
+                        });
+                        make_transition(handle, part, &last_usage, &new_usage, None)
+                    }).collect()
+                }
+            };
+
+            node_link.transitions.extend(transitions.into_iter().map(|transition| (access_index, transition)));
+            usage_cache.update(handle, subresource, new_usage, (*node_index, access_index));
+        }
+
+        if node.is_present() {
+            // a present (and anything after it, e.g. for another window) goes in a new
+            // command list, ordered after this one by a semaphore
+            command_lists.push(current_list);
+            current_list = CommandList::new();
+            current_list.wait = Some(QueueWait{
+                wait_stage_mask: vk::PipelineStageFlags::ALL_COMMANDS,
+            });
+        }
+        current_list.nodes.push(*node_index);
+        linked_nodes.push((*node_index, node_link));
+    }
+
+    // Persist the final usage of each resource for the next frame. Only one usage is kept per
+    // resource, so parts of an image left in another layout than its most recent usage are
+    // transitioned to that layout once the frame's nodes have executed
+    let mut final_transitions: Vec<(NodeIndex, usize, Transition)> = Vec::new();
+    for (handle, usages) in usage_cache.usages {
+        let (_, latest) = *usages.last().expect("Resource in the usage cache without a usage");
+        let mut final_usage = latest;
+        for (part, usage) in &usages[..usages.len() - 1] {
+            if usage.layout == latest.layout {
+                final_usage.access |= usage.access;
+                final_usage.stage |= usage.stage;
+            } else {
+                let (node_index, access_index) = usage_cache.last_accesses[&handle];
+                final_transitions.push((node_index, access_index, make_transition(handle, *part, usage, &latest, None)));
+            }
+        }
+        resource_states.update_resource_state(handle, final_usage);
+    }
+
+    if !current_list.nodes.is_empty() {
+        command_lists.push(current_list);
+    }
+
+    LinkedGraph {
+        nodes: linked_nodes,
+        command_lists,
+        final_transitions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct TestNode {
+        name: &'static str,
+        reads: Vec<u64>,
+        writes: Vec<u64>,
+        overwrites: Vec<u64>,
+        loads: Vec<u64>,
+        priority: i32,
+        execute_after: Vec<NodeIndex>,
+        renderpass_group: Option<&'static str>,
+        present: bool,
+        accesses: Vec<ResourceAccess>
+    }
+
+    impl TestNode {
+        fn new(name: &'static str, reads: &[u64], writes: &[u64]) -> Self {
+            TestNode {
+                name,
+                reads: reads.to_vec(),
+                writes: writes.to_vec(),
+                ..Default::default()
+            }
+        }
+
+        fn priority(mut self, priority: i32) -> Self {
+            self.priority = priority;
+            self
+        }
+
+        fn after(mut self, index: usize) -> Self {
+            self.execute_after.push(NodeIndex::new(index));
+            self
+        }
+
+        fn renderpass_group(mut self, group: &'static str) -> Self {
+            self.renderpass_group = Some(group);
+            self
+        }
+
+        fn present(mut self) -> Self {
+            self.present = true;
+            self
+        }
+
+        fn overwrites(mut self, overwrites: &[u64]) -> Self {
+            self.overwrites = overwrites.to_vec();
+            self
+        }
+
+        fn loads(mut self, loads: &[u64]) -> Self {
+            self.loads = loads.to_vec();
+            self
+        }
+
+        fn accesses(mut self, accesses: Vec<ResourceAccess>) -> Self {
+            self.accesses = accesses;
+            self
+        }
+    }
+
+    impl GraphNode for TestNode {
+        fn get_name(&self) -> &str {
+            self.name
+        }
+
+        fn get_reads(&self) -> Vec<u64> {
+            self.reads.clone()
+        }
+
+        fn get_writes(&self) -> Vec<u64> {
+            self.writes.clone()
+        }
+
+        fn get_overwrites(&self) -> Vec<u64> {
+            self.overwrites.clone()
+        }
+
+        fn get_loads(&self) -> Vec<u64> {
+            self.loads.clone()
+        }
+
+        fn get_priority(&self) -> i32 {
+            self.priority
+        }
+
+        fn get_execute_after(&self) -> Vec<NodeIndex> {
+            self.execute_after.clone()
+        }
+
+        fn get_renderpass_group(&self) -> Option<&str> {
+            self.renderpass_group
+        }
+
+        fn is_present(&self) -> bool {
+            self.present
+        }
+
+        fn get_accesses(&self) -> Vec<ResourceAccess> {
+            self.accesses.clone()
+        }
+    }
+
+    fn compile_names(nodes: Vec<TestNode>, roots: &[&str]) -> Vec<&'static str> {
+        let mut graph: StableDiGraph<TestNode, u32> = StableDiGraph::new();
+        let mut root_indices = Vec::new();
+        for node in nodes {
+            let is_root = roots.contains(&node.name);
+            let index = graph.add_node(node);
+            if is_root {
+                root_indices.push(index);
+            }
+        }
+        compile(&mut graph, &root_indices).iter()
+            .map(|index| graph[*index].name)
+            .collect()
+    }
+
+    #[test]
+    fn writers_execute_before_readers() {
+        let sorted = compile_names(vec![
+            TestNode::new("lighting", &[1, 2], &[3]),
+            TestNode::new("gbuffer", &[], &[1]),
+            TestNode::new("shadows", &[], &[2])
+        ], &["lighting"]);
+        assert_eq!(sorted, vec!["gbuffer", "shadows", "lighting"]);
+    }
+
+    #[test]
+    fn unused_nodes_are_culled() {
+        let sorted = compile_names(vec![
+            TestNode::new("gbuffer", &[], &[1]),
+            TestNode::new("debug view", &[1], &[2]),
+            TestNode::new("unread", &[], &[3]),
+            TestNode::new("lighting", &[1], &[4])
+        ], &["lighting"]);
+        assert_eq!(sorted, vec!["gbuffer", "lighting"]);
+    }
+
+    #[test]
+    fn priority_orders_independent_nodes() {
+        let sorted = compile_names(vec![
+            TestNode::new("first", &[], &[1]),
+            TestNode::new("urgent", &[], &[2]).priority(10),
+            TestNode::new("second", &[], &[3]),
+            TestNode::new("combine", &[1, 2, 3], &[4])
+        ], &["combine"]);
+        assert_eq!(sorted, vec!["urgent", "first", "second", "combine"]);
+    }
+
+    fn toposort_names(nodes: Vec<TestNode>, dependencies: &[(usize, usize)]) -> Vec<&'static str> {
+        let mut graph: StableDiGraph<TestNode, u32> = StableDiGraph::new();
+        for node in nodes {
+            graph.add_node(node);
+        }
+        // edges point from a node to the node it depends on
+        for (node, dependency) in dependencies {
+            graph.add_edge(NodeIndex::new(*node), NodeIndex::new(*dependency), 0);
+        }
+        stable_toposort(&graph).iter()
+            .map(|index| graph[*index].name)
+            .collect()
+    }
+
+    #[test]
+    fn toposort_ties_keep_insertion_order() {
+        let sorted = toposort_names(vec![
+            TestNode::new("c", &[], &[]),
+            TestNode::new("a", &[], &[]),
+            TestNode::new("b", &[], &[])
+        ], &[]);
+        assert_eq!(sorted, vec!["c", "a", "b"]);
+    }
+
+    #[test]
+    fn toposort_dependencies_outrank_priority() {
+        // "late" has the highest priority but can't run before the node it depends on,
+        // which still runs ahead of the lower priority "early"
+        let sorted = toposort_names(vec![
+            TestNode::new("early", &[], &[]).priority(1),
+            TestNode::new("late", &[], &[]).priority(10),
+            TestNode::new("dependency", &[], &[]).priority(-5)
+        ], &[(1, 2)]);
+        assert_eq!(sorted, vec!["early", "dependency", "late"]);
+    }
+
+    #[test]
+    fn toposort_prefers_newly_ready_high_priority_nodes() {
+        // once "base" is scheduled, "urgent" becomes ready and outranks "idle"
+        let sorted = toposort_names(vec![
+            TestNode::new("base", &[], &[]).priority(5),
+            TestNode::new("idle", &[], &[]),
+            TestNode::new("urgent", &[], &[]).priority(3)
+        ], &[(2, 0)]);
+        assert_eq!(sorted, vec!["base", "urgent", "idle"]);
+    }
+
+    #[test]
+    fn toposort_is_reproducible() {
+        let nodes = || vec![
+            TestNode::new("a", &[], &[]),
+            TestNode::new("b", &[], &[]).priority(2),
+            TestNode::new("c", &[], &[]),
+            TestNode::new("d", &[], &[]).priority(2),
+            TestNode::new("e", &[], &[])
+        ];
+        let dependencies = [(4, 0), (3, 2), (1, 0)];
+        let first = toposort_names(nodes(), &dependencies);
+        for _ in 0..8 {
+            assert_eq!(toposort_names(nodes(), &dependencies), first);
+        }
+        assert_eq!(first, vec!["a", "b", "c", "d", "e"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "A cycle was detected")]
+    fn toposort_panics_on_cycles() {
+        toposort_names(vec![
+            TestNode::new("a", &[], &[]),
+            TestNode::new("b", &[], &[])
+        ], &[(0, 1), (1, 0)]);
+    }
+
+    #[test]
+    fn cycle_panics_name_the_same_node_on_the_cycle() {
+        let cycle_message = || {
+            let panic = std::panic::catch_unwind(|| {
+                // "waits" depends on the cycle without being part of it
+                toposort_names(vec![
+                    TestNode::new("independent", &[], &[]),
+                    TestNode::new("waits", &[], &[]),
+                    TestNode::new("first", &[], &[]),
+                    TestNode::new("second", &[], &[])
+                ], &[(1, 2), (2, 3), (3, 2)]);
+            }).expect_err("Sorting a cycle should panic");
+            panic.downcast_ref::<String>().cloned().expect("Cycle panics should be formatted")
+        };
+
+        let message = cycle_message();
+        assert!(message.ends_with("node \"first\"") || message.ends_with("node \"second\""),
+            "Cycle panic named a node off the cycle: {}", message);
+        for _ in 0..8 {
+            assert_eq!(cycle_message(), message);
+        }
+    }
+
+    #[test]
+    fn explicit_ordering_without_shared_resources() {
+        // the timestamp reset shares nothing with the draw, but must execute before it, and
+        // is retained because the draw is
+        let sorted = compile_names(vec![
+            TestNode::new("draw", &[], &[1]).after(2),
+            TestNode::new("unrelated", &[], &[2]).priority(10),
+            TestNode::new("reset timestamps", &[], &[]),
+            TestNode::new("present", &[1, 2], &[])
+        ], &["present"]);
+        assert_eq!(sorted, vec!["unrelated", "reset timestamps", "draw", "present"]);
+    }
+
+    #[test]
+    fn writes_wait_for_earlier_reads() {
+        // without the write-after-read edge "overwrite" would sort before "blur" on priority
+        let sorted = compile_names(vec![
+            TestNode::new("render", &[], &[1]),
+            TestNode::new("blur", &[1], &[2]),
+            TestNode::new("overwrite", &[], &[1]).priority(10),
+            TestNode::new("composite", &[1, 2], &[3])
+        ], &["composite"]);
+        assert_eq!(sorted, vec!["render", "blur", "overwrite", "composite"]);
+    }
+
+    #[test]
+    fn reads_added_before_their_writer() {
+        let sorted = compile_names(vec![
+            TestNode::new("present", &[1], &[]).present(),
+            TestNode::new("draw", &[], &[1])
+        ], &["present"]);
+        assert_eq!(sorted, vec!["draw", "present"]);
+    }
+
+    #[test]
+    fn present_nodes_execute_last() {
+        let sorted = compile_names(vec![
+            TestNode::new("present", &[1], &[]).present(),
+            TestNode::new("draw", &[], &[1]),
+            TestNode::new("screenshot", &[1], &[2])
+        ], &["present", "screenshot"]);
+        assert_eq!(sorted, vec!["draw", "screenshot", "present"]);
+    }
+
+    #[test]
+    fn independent_nodes_move_ahead_of_renderpass_groups() {
+        let sorted = compile_names(vec![
+            TestNode::new("gbuffer", &[], &[1]).renderpass_group("deferred"),
+            TestNode::new("shadows", &[], &[2]),
+            TestNode::new("lighting", &[1], &[3]).renderpass_group("deferred"),
+            TestNode::new("composite", &[2, 3], &[4])
+        ], &["composite"]);
+        assert_eq!(sorted, vec!["shadows", "gbuffer", "lighting", "composite"]);
+    }
+
+    #[test]
+    fn dependent_nodes_move_after_renderpass_groups() {
+        let sorted = compile_names(vec![
+            TestNode::new("gbuffer", &[], &[1]).renderpass_group("deferred"),
+            TestNode::new("downsample", &[1], &[2]).priority(10),
+            TestNode::new("lighting", &[], &[3]).renderpass_group("deferred"),
+            TestNode::new("composite", &[2, 3], &[4])
+        ], &["composite"]);
+        assert_eq!(sorted, vec!["gbuffer", "lighting", "downsample", "composite"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "depends on a node outside the group")]
+    fn renderpass_groups_cant_depend_on_their_dependents() {
+        compile_names(vec![
+            TestNode::new("gbuffer", &[], &[1]).renderpass_group("deferred"),
+            TestNode::new("downsample", &[1], &[2]),
+            TestNode::new("lighting", &[2], &[3]).renderpass_group("deferred")
+        ], &["lighting"]);
+    }
+
+    fn buffer_access(handle: u64, access: vk::AccessFlags, stage: vk::PipelineStageFlags) -> ResourceAccess {
+        ResourceAccess { handle, access, stage, kind: AccessKind::Buffer { offset: 0, size: 256 } }
+    }
+
+    fn image_access(handle: u64, access: vk::AccessFlags, stage: vk::PipelineStageFlags, layout: vk::ImageLayout) -> ResourceAccess {
+        image_range_access(handle, SubresourceRange::WHOLE, access, stage, layout)
+    }
+
+    // a cube map with a full mip chain
+    const TEST_MIP_LEVELS: u32 = 8;
+    const TEST_ARRAY_LAYERS: u32 = 6;
+
+    fn image_range_access(
+        handle: u64,
+        subresource: SubresourceRange,
+        access: vk::AccessFlags,
+        stage: vk::PipelineStageFlags,
+        layout: vk::ImageLayout) -> ResourceAccess {
+        mip_chain_access(handle, subresource, TEST_MIP_LEVELS, access, stage, layout)
+    }
+
+    fn mip_chain_access(
+        handle: u64,
+        subresource: SubresourceRange,
+        mip_levels: u32,
+        access: vk::AccessFlags,
+        stage: vk::PipelineStageFlags,
+        layout: vk::ImageLayout) -> ResourceAccess {
+        ResourceAccess {
+            handle,
+            access,
+            stage,
+            kind: AccessKind::Image { layout, subresource, mip_levels, array_layers: TEST_ARRAY_LAYERS }
+        }
+    }
+
+    fn color_attachment(handle: u64) -> ResourceAccess {
+        ResourceAccess {
+            handle,
+            access: vk::AccessFlags::COLOR_ATTACHMENT_WRITE | vk::AccessFlags::COLOR_ATTACHMENT_READ,
+            stage: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+            kind: AccessKind::Attachment { layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL }
+        }
+    }
+
+    fn image_transition(
+        handle: u64,
+        source: (vk::PipelineStageFlags, vk::AccessFlags, vk::ImageLayout),
+        dest: (vk::PipelineStageFlags, vk::AccessFlags, vk::ImageLayout)) -> Transition {
+        ranged_transition(handle, SubresourceRange::WHOLE, source, dest)
+    }
+
+    fn ranged_transition(
+        handle: u64,
+        subresource: SubresourceRange,
+        source: (vk::PipelineStageFlags, vk::AccessFlags, vk::ImageLayout),
+        dest: (vk::PipelineStageFlags, vk::AccessFlags, vk::ImageLayout)) -> Transition {
+        Transition {
+            handle,
+            source_stage: source.0,
+            dest_stage: dest.0,
+            source_access: source.1,
+            dest_access: dest.1,
+            range: BarrierRange::Image { old_layout: source.2, new_layout: dest.2, subresource }
+        }
+    }
+
+    /// Links the nodes in the order given, with the resource states in `registry` from before the frame
+    fn link_nodes(nodes: Vec<TestNode>, registry: &RefCell<ResourceStateRegistry>) -> LinkedGraph {
+        let mut graph: StableDiGraph<TestNode, u32> = StableDiGraph::new();
+        let sorted: Vec<NodeIndex> = nodes.into_iter().map(|node| graph.add_node(node)).collect();
+        link(&graph, &sorted, registry)
+    }
+
+    fn node_transitions(linked: &LinkedGraph, index: usize) -> Vec<Transition> {
+        linked.nodes[index].1.transitions.iter().map(|(_, transition)| *transition).collect()
+    }
+
+    #[test]
+    fn image_write_then_read() {
+        let registry = RefCell::new(ResourceStateRegistry::new());
+        let linked = link_nodes(vec![
+            TestNode::new("simulate", &[], &[1]).accesses(vec![
+                image_access(1, vk::AccessFlags::SHADER_WRITE, vk::PipelineStageFlags::COMPUTE_SHADER, vk::ImageLayout::GENERAL)
+            ]),
+            TestNode::new("shade", &[1], &[]).accesses(vec![
+                image_access(1, vk::AccessFlags::SHADER_READ, vk::PipelineStageFlags::FRAGMENT_SHADER, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            ])
+        ], &registry);
+
+        // the first use initializes the image from UNDEFINED
+        assert_eq!(node_transitions(&linked, 0), vec![image_transition(1,
+            (vk::PipelineStageFlags::TOP_OF_PIPE, vk::AccessFlags::NONE, vk::ImageLayout::UNDEFINED),
+            (vk::PipelineStageFlags::COMPUTE_SHADER, vk::AccessFlags::SHADER_WRITE, vk::ImageLayout::GENERAL))]);
+        assert_eq!(node_transitions(&linked, 1), vec![image_transition(1,
+            (vk::PipelineStageFlags::COMPUTE_SHADER, vk::AccessFlags::SHADER_WRITE, vk::ImageLayout::GENERAL),
+            (vk::PipelineStageFlags::FRAGMENT_SHADER, vk::AccessFlags::SHADER_READ, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL))]);
+    }
+
+    #[test]
+    fn image_barrier_keeps_binding_subresource() {
+        let registry = RefCell::new(ResourceStateRegistry::new());
+        let linked = link_nodes(vec![
+            TestNode::new("generate", &[], &[1]).accesses(vec![
+                image_access(1, vk::AccessFlags::SHADER_WRITE, vk::PipelineStageFlags::COMPUTE_SHADER, vk::ImageLayout::GENERAL)
+            ]),
+            TestNode::new("sample mip", &[1], &[]).accesses(vec![ResourceAccess {
+                handle: 1,
+                access: vk::AccessFlags::SHADER_READ,
+                stage: vk::PipelineStageFlags::FRAGMENT_SHADER,
+                kind: AccessKind::Image {
+                    layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    subresource: SubresourceRange::mip(2),
+                    mip_levels: TEST_MIP_LEVELS,
+                    array_layers: TEST_ARRAY_LAYERS
+                }
+            }])
+        ], &registry);
+
+        // unranged bindings fall back to the whole image
+        assert_eq!(node_transitions(&linked, 0)[0].range, BarrierRange::Image {
+            old_layout: vk::ImageLayout::UNDEFINED,
+            new_layout: vk::ImageLayout::GENERAL,
+            subresource: SubresourceRange::WHOLE
+        });
+        assert_eq!(node_transitions(&linked, 1)[0].range, BarrierRange::Image {
+            old_layout: vk::ImageLayout::GENERAL,
+            new_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            subresource: SubresourceRange::mip(2)
+        });
+    }
+
+    #[test]
+    fn downsample_chain_transitions_each_mip() {
+        let registry = RefCell::new(ResourceStateRegistry::new());
+        let write = (vk::PipelineStageFlags::COMPUTE_SHADER, vk::AccessFlags::SHADER_WRITE, vk::ImageLayout::GENERAL);
+        let read = (vk::PipelineStageFlags::COMPUTE_SHADER, vk::AccessFlags::SHADER_READ, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+        let undefined = (vk::PipelineStageFlags::TOP_OF_PIPE, vk::AccessFlags::NONE, vk::ImageLayout::UNDEFINED);
+        let linked = link_nodes(vec![
+            TestNode::new("mip 0", &[], &[1]).accesses(vec![
+                mip_chain_access(1, SubresourceRange::mip(0), 2, write.1, write.0, write.2)
+            ]),
+            TestNode::new("mip 1", &[1], &[1]).accesses(vec![
+                mip_chain_access(1, SubresourceRange::mip(0), 2, read.1, read.0, read.2),
+                mip_chain_access(1, SubresourceRange::mip(1), 2, write.1, write.0, write.2)
+            ])
+        ], &registry);
+
+        assert_eq!(node_transitions(&linked, 0), vec![ranged_transition(1, SubresourceRange::mip(0), undefined, write)]);
+        // mip 1 is initialized without waiting on the write to mip 0
+        assert_eq!(node_transitions(&linked, 1), vec![
+            ranged_transition(1, SubresourceRange::mip(0), write, read),
+            ranged_transition(1, SubresourceRange::mip(1), undefined, write)
+        ]);
+
+        // both of the image's mips have been used, so only mip 0 is brought to the layout of
+        // the last write
+        let final_transitions: Vec<Transition> = linked.final_transitions.iter()
+            .map(|(_, _, transition)| *transition)
+            .collect();
+        assert_eq!(final_transitions, vec![ranged_transition(1, SubresourceRange::mip(0), read, write)]);
+        assert_eq!(linked.final_transitions[0].0, NodeIndex::new(1));
+        assert_eq!(registry.borrow().get(1).and_then(|state| state.layout), Some(vk::ImageLayout::GENERAL));
+    }
+
+    #[test]
+    fn unused_mips_are_transitioned_at_the_end_of_the_frame() {
+        let registry = RefCell::new(ResourceStateRegistry::new());
+        let write = (vk::PipelineStageFlags::COMPUTE_SHADER, vk::AccessFlags::SHADER_WRITE, vk::ImageLayout::GENERAL);
+        let undefined = (vk::PipelineStageFlags::TOP_OF_PIPE, vk::AccessFlags::NONE, vk::ImageLayout::UNDEFINED);
+        let linked = link_nodes(vec![
+            TestNode::new("mip 0", &[], &[1]).accesses(vec![
+                mip_chain_access(1, SubresourceRange::mip(0), 4, write.1, write.0, write.2)
+            ]),
+            TestNode::new("mip 1", &[1], &[1]).accesses(vec![
+                mip_chain_access(1, SubresourceRange::mip(1), 4, write.1, write.0, write.2)
+            ])
+        ], &registry);
+
+        // mips 2 and 3 are still uninitialized
+        let remaining_mips = SubresourceRange { base_mip_level: 2, ..SubresourceRange::WHOLE };
+        let final_transitions: Vec<Transition> = linked.final_transitions.iter()
+            .map(|(_, _, transition)| *transition)
+            .collect();
+        assert_eq!(final_transitions, vec![ranged_transition(1, remaining_mips, undefined, write)]);
+    }
+
+    #[test]
+    fn subresource_ranges_clamp_to_the_image() {
+        assert_eq!(SubresourceRange::WHOLE.clamp(4, 6), Some(SubresourceRange::WHOLE));
+        assert_eq!(SubresourceRange::mip(3).clamp(4, 6), Some(SubresourceRange::mip(3)));
+        assert_eq!(SubresourceRange::mip(4).clamp(4, 6), None);
+        assert_eq!(SubresourceRange::layer(6).clamp(4, 6), None);
+        let overhanging = SubresourceRange { base_mip_level: 1, level_count: 8, base_array_layer: 4, layer_count: 4 };
+        assert_eq!(overhanging.clamp(4, 6), Some(SubresourceRange {
+            base_mip_level: 1,
+            level_count: 3,
+            base_array_layer: 4,
+            layer_count: 2
+        }));
+    }
+
+    #[test]
+    fn cube_faces_leave_no_ranges_beyond_the_last_face() {
+        let registry = RefCell::new(ResourceStateRegistry::new());
+        let write = (vk::PipelineStageFlags::COMPUTE_SHADER, vk::AccessFlags::SHADER_WRITE, vk::ImageLayout::GENERAL);
+        let linked = link_nodes((0..TEST_ARRAY_LAYERS).map(|face| {
+            TestNode::new("face", &[], &[1]).accesses(vec![
+                image_range_access(1, SubresourceRange::layer(face), write.1, write.0, write.2)
+            ])
+        }).collect(), &registry);
+
+        // every face was written in the same layout, so nothing is left to transition
+        assert!(linked.final_transitions.is_empty());
+        assert!(linked.nodes.iter().all(|(_, node_link)| node_link.transitions.len() == 1));
+    }
+
+    #[test]
+    fn cube_faces_transition_separately() {
+        let registry = RefCell::new(ResourceStateRegistry::new());
+        registry.borrow_mut().update(1, ResourceState {
+            access: vk::AccessFlags::SHADER_READ,
+            stage: vk::PipelineStageFlags::FRAGMENT_SHADER,
+            layout: Some(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+        });
+        let write = (vk::PipelineStageFlags::COMPUTE_SHADER, vk::AccessFlags::SHADER_WRITE, vk::ImageLayout::GENERAL);
+        let read = (vk::PipelineStageFlags::FRAGMENT_SHADER, vk::AccessFlags::SHADER_READ, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+        let linked = link_nodes(vec![
+            TestNode::new("face 0", &[], &[1]).accesses(vec![
+                image_range_access(1, SubresourceRange::layer(0), write.1, write.0, write.2)
+            ]),
+            TestNode::new("face 1", &[], &[1]).accesses(vec![
+                image_range_access(1, SubresourceRange::layer(1), write.1, write.0, write.2)
+            ]),
+            TestNode::new("skybox", &[1], &[2]).accesses(vec![
+                image_access(1, read.1, read.0, read.2)
+            ])
+        ], &registry);
+
+        assert_eq!(node_transitions(&linked, 0), vec![ranged_transition(1, SubresourceRange::layer(0), read, write)]);
+        assert_eq!(node_transitions(&linked, 1), vec![ranged_transition(1, SubresourceRange::layer(1), read, write)]);
+        // the faces which weren't rendered are still readable and need no barrier
+        assert_eq!(node_transitions(&linked, 2), vec![
+            ranged_transition(1, SubresourceRange::layer(0), write, read),
+            ranged_transition(1, SubresourceRange::layer(1), write, read)
+        ]);
+        assert!(linked.final_transitions.is_empty());
+    }
+
+    #[test]
+    fn buffer_write_then_read() {
+        let registry = RefCell::new(ResourceStateRegistry::new());
+        let linked = link_nodes(vec![
+            TestNode::new("cull", &[], &[1]).accesses(vec![
+                buffer_access(1, vk::AccessFlags::SHADER_WRITE, vk::PipelineStageFlags::COMPUTE_SHADER)
+            ]),
+            TestNode::new("draw", &[1], &[]).accesses(vec![
+                buffer_access(1, vk::AccessFlags::SHADER_READ, vk::PipelineStageFlags::VERTEX_SHADER)
+            ])
+        ], &registry);
+
+        // a buffer with no earlier usage needs no barrier
+        assert!(node_transitions(&linked, 0).is_empty());
+        assert_eq!(node_transitions(&linked, 1), vec![Transition {
+            handle: 1,
+            source_stage: vk::PipelineStageFlags::COMPUTE_SHADER,
+            dest_stage: vk::PipelineStageFlags::VERTEX_SHADER,
+            source_access: vk::AccessFlags::SHADER_WRITE,
+            dest_access: vk::AccessFlags::SHADER_READ,
+            range: BarrierRange::Buffer { offset: 0, size: 256 }
+        }]);
+    }
+
+    #[test]
+    fn buffer_read_after_read() {
+        let registry = RefCell::new(ResourceStateRegistry::new());
+        registry.borrow_mut().update(1, ResourceState {
+            access: vk::AccessFlags::SHADER_READ,
+            stage: vk::PipelineStageFlags::COMPUTE_SHADER,
+            layout: None
+        });
+        let linked = link_nodes(vec![
+            TestNode::new("draw", &[1], &[2]).accesses(vec![
+                buffer_access(1, vk::AccessFlags::SHADER_READ, vk::PipelineStageFlags::VERTEX_SHADER)
+            ]),
+            TestNode::new("shade", &[1], &[3]).accesses(vec![
+                buffer_access(1, vk::AccessFlags::UNIFORM_READ, vk::PipelineStageFlags::FRAGMENT_SHADER)
+            ])
+        ], &registry);
+
+        assert!(node_transitions(&linked, 0).is_empty());
+        assert!(node_transitions(&linked, 1).is_empty());
+    }
+
+    #[test]
+    fn buffer_write_after_read() {
+        // the second iteration of a reduction overwrites the buffer the first one read
+        let registry = RefCell::new(ResourceStateRegistry::new());
+        let linked = link_nodes(vec![
+            TestNode::new("reduce_0", &[1], &[2]).accesses(vec![
+                buffer_access(1, vk::AccessFlags::SHADER_READ, vk::PipelineStageFlags::COMPUTE_SHADER),
+                buffer_access(2, vk::AccessFlags::SHADER_WRITE, vk::PipelineStageFlags::COMPUTE_SHADER)
+            ]),
+            TestNode::new("reduce_1", &[2], &[1]).accesses(vec![
+                buffer_access(2, vk::AccessFlags::SHADER_READ, vk::PipelineStageFlags::COMPUTE_SHADER),
+                buffer_access(1, vk::AccessFlags::SHADER_WRITE, vk::PipelineStageFlags::COMPUTE_SHADER)
+            ])
+        ], &registry);
+
+        assert!(node_transitions(&linked, 0).is_empty());
+        let compute_barrier = |handle: u64, source_access: vk::AccessFlags, dest_access: vk::AccessFlags| Transition {
+            handle,
+            source_stage: vk::PipelineStageFlags::COMPUTE_SHADER,
+            dest_stage: vk::PipelineStageFlags::COMPUTE_SHADER,
+            source_access,
+            dest_access,
+            range: BarrierRange::Buffer { offset: 0, size: 256 }
+        };
+        assert_eq!(node_transitions(&linked, 1), vec![
+            compute_barrier(2, vk::AccessFlags::SHADER_WRITE, vk::AccessFlags::SHADER_READ),
+            compute_barrier(1, vk::AccessFlags::SHADER_READ, vk::AccessFlags::SHADER_WRITE)
+        ]);
+    }
+
+    #[test]
+    fn image_read_after_read() {
+        let registry = RefCell::new(ResourceStateRegistry::new());
+        registry.borrow_mut().update(1, ResourceState {
+            access: vk::AccessFlags::SHADER_READ,
+            stage: vk::PipelineStageFlags::FRAGMENT_SHADER,
+            layout: Some(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+        });
+        let linked = link_nodes(vec![
+            TestNode::new("blur", &[1], &[2]).accesses(vec![
+                image_access(1, vk::AccessFlags::SHADER_READ, vk::PipelineStageFlags::COMPUTE_SHADER, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            ]),
+            TestNode::new("tonemap", &[1], &[3]).accesses(vec![
+                image_access(1, vk::AccessFlags::SHADER_READ, vk::PipelineStageFlags::FRAGMENT_SHADER, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            ])
+        ], &registry);
+
+        assert!(node_transitions(&linked, 0).is_empty());
+        assert!(node_transitions(&linked, 1).is_empty());
+    }
+
+    #[test]
+    fn layout_change_between_reads() {
+        let registry = RefCell::new(ResourceStateRegistry::new());
+        registry.borrow_mut().update(1, ResourceState {
+            access: vk::AccessFlags::SHADER_READ,
+            stage: vk::PipelineStageFlags::FRAGMENT_SHADER,
+            layout: Some(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+        });
+        let linked = link_nodes(vec![
+            TestNode::new("histogram", &[1], &[2]).accesses(vec![
+                image_access(1, vk::AccessFlags::SHADER_READ, vk::PipelineStageFlags::COMPUTE_SHADER, vk::ImageLayout::GENERAL)
+            ])
+        ], &registry);
+
+        assert_eq!(node_transitions(&linked, 0), vec![image_transition(1,
+            (vk::PipelineStageFlags::FRAGMENT_SHADER, vk::AccessFlags::SHADER_READ, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL),
+            (vk::PipelineStageFlags::COMPUTE_SHADER, vk::AccessFlags::SHADER_READ, vk::ImageLayout::GENERAL))]);
+    }
+
+    #[test]
+    fn copy_then_draw_then_present() {
+        let registry = RefCell::new(ResourceStateRegistry::new());
+        let linked = link_nodes(vec![
+            TestNode::new("upload", &[], &[1]).accesses(vec![ResourceAccess {
+                handle: 1,
+                access: vk::AccessFlags::TRANSFER_WRITE,
+                stage: vk::PipelineStageFlags::TRANSFER,
+                kind: AccessKind::CopyImage {
+                    layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    initial_stage: vk::PipelineStageFlags::TOP_OF_PIPE
+                }
+            }]),
+            TestNode::new("draw", &[], &[1]).accesses(vec![color_attachment(1)]),
+            TestNode::new("present", &[1], &[]).present().accesses(vec![ResourceAccess {
+                handle: 1,
+                access: vk::AccessFlags::NONE,
+                stage: vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                kind: AccessKind::Present {
+                    current_layout: vk::ImageLayout::UNDEFINED,
+                    final_layout: vk::ImageLayout::PRESENT_SRC_KHR
+                }
+            }])
+        ], &registry);
+
+        let color_attachment_stage = vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS;
+        let color_attachment_access = vk::AccessFlags::COLOR_ATTACHMENT_WRITE | vk::AccessFlags::COLOR_ATTACHMENT_READ;
+        assert_eq!(node_transitions(&linked, 0), vec![image_transition(1,
+            (vk::PipelineStageFlags::TOP_OF_PIPE, vk::AccessFlags::NONE, vk::ImageLayout::UNDEFINED),
+            (vk::PipelineStageFlags::TRANSFER, vk::AccessFlags::TRANSFER_WRITE, vk::ImageLayout::TRANSFER_DST_OPTIMAL))]);
+        assert_eq!(node_transitions(&linked, 1), vec![image_transition(1,
+            (vk::PipelineStageFlags::TRANSFER, vk::AccessFlags::TRANSFER_WRITE, vk::ImageLayout::TRANSFER_DST_OPTIMAL),
+            (color_attachment_stage, color_attachment_access, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL))]);
+        assert_eq!(node_transitions(&linked, 2), vec![image_transition(1,
+            (color_attachment_stage, color_attachment_access, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL),
+            (vk::PipelineStageFlags::BOTTOM_OF_PIPE, vk::AccessFlags::NONE, vk::ImageLayout::PRESENT_SRC_KHR))]);
+
+        // the present starts a new command list which waits on the first
+        assert_eq!(linked.command_lists.len(), 2);
+        assert_eq!(linked.command_lists[0].nodes, vec![NodeIndex::new(0), NodeIndex::new(1)]);
+        assert!(linked.command_lists[0].wait.is_none());
+        assert_eq!(linked.command_lists[1].nodes, vec![NodeIndex::new(2)]);
+        assert_eq!(linked.command_lists[1].wait.as_ref().map(|wait| wait.wait_stage_mask), Some(vk::PipelineStageFlags::ALL_COMMANDS));
+
+        let final_state = registry.borrow().get(1).expect("Final usage wasn't persisted");
+        assert_eq!(final_state.layout, Some(vk::ImageLayout::PRESENT_SRC_KHR));
+        assert_eq!(final_state.stage, vk::PipelineStageFlags::BOTTOM_OF_PIPE);
+    }
+
+    #[test]
+    fn present_to_external_layout() {
+        let registry = RefCell::new(ResourceStateRegistry::new());
+        let linked = link_nodes(vec![
+            TestNode::new("draw", &[], &[1]).accesses(vec![color_attachment(1)]),
+            TestNode::new("present", &[1], &[]).present().accesses(vec![ResourceAccess {
+                handle: 1,
+                access: vk::AccessFlags::NONE,
+                stage: vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                kind: AccessKind::Present {
+                    current_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                    final_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL
+                }
+            }])
+        ], &registry);
+
+        let color_attachment_stage = vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS;
+        let color_attachment_access = vk::AccessFlags::COLOR_ATTACHMENT_WRITE | vk::AccessFlags::COLOR_ATTACHMENT_READ;
+        assert_eq!(node_transitions(&linked, 1), vec![image_transition(1,
+            (color_attachment_stage, color_attachment_access, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL),
+            (vk::PipelineStageFlags::BOTTOM_OF_PIPE, vk::AccessFlags::NONE, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL))]);
+
+        let final_state = registry.borrow().get(1).expect("Final usage wasn't persisted");
+        assert_eq!(final_state.layout, Some(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL));
+    }
+
+    #[test]
+    fn copy_buffer_without_earlier_usage() {
+        let registry = RefCell::new(ResourceStateRegistry::new());
+        let linked = link_nodes(vec![
+            TestNode::new("readback", &[1], &[]).accesses(vec![ResourceAccess {
+                handle: 1,
+                access: vk::AccessFlags::TRANSFER_READ,
+                stage: vk::PipelineStageFlags::TRANSFER,
+                kind: AccessKind::CopyBuffer { size: 64, initial_stage: vk::PipelineStageFlags::ALL_COMMANDS }
+            }])
+        ], &registry);
+
+        assert_eq!(node_transitions(&linked, 0), vec![Transition {
+            handle: 1,
+            source_stage: vk::PipelineStageFlags::ALL_COMMANDS,
+            dest_stage: vk::PipelineStageFlags::TRANSFER,
+            source_access: vk::AccessFlags::NONE,
+            dest_access: vk::AccessFlags::TRANSFER_READ,
+            range: BarrierRange::Buffer { offset: 0, size: 64 }
+        }]);
+    }
+
+    #[test]
+    fn renderpass_groups_share_attachments() {
+        let registry = RefCell::new(ResourceStateRegistry::new());
+        let linked = link_nodes(vec![
+            TestNode::new("gbuffer", &[], &[1]).renderpass_group("deferred").accesses(vec![color_attachment(1)]),
+            TestNode::new("lighting", &[1], &[2]).renderpass_group("deferred").accesses(vec![
+                ResourceAccess {
+                    handle: 1,
+                    access: vk::AccessFlags::INPUT_ATTACHMENT_READ,
+                    stage: vk::PipelineStageFlags::FRAGMENT_SHADER,
+                    kind: AccessKind::InputAttachment { layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL }
+                },
+                color_attachment(2)
+            ]),
+            TestNode::new("tonemap", &[2], &[3]).accesses(vec![
+                image_access(2, vk::AccessFlags::SHADER_READ, vk::PipelineStageFlags::FRAGMENT_SHADER, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            ])
+        ], &registry);
+
+        // barriers of every subpass are recorded by the first, and the renderpass itself
+        // transitions the input attachment
+        let gbuffer = NodeIndex::new(0);
+        assert_eq!(linked.nodes[0].1.barrier_node, gbuffer);
+        assert_eq!(linked.nodes[1].1.barrier_node, gbuffer);
+        assert_eq!(linked.nodes[1].1.implicit_transitions, vec![0]);
+        assert_eq!(linked.nodes[1].1.transitions.iter().map(|(index, _)| *index).collect::<Vec<_>>(), vec![1]);
+        assert_eq!(linked.nodes[2].1.barrier_node, NodeIndex::new(2));
+        assert_eq!(node_transitions(&linked, 2), vec![image_transition(2,
+            (vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+                vk::AccessFlags::COLOR_ATTACHMENT_WRITE | vk::AccessFlags::COLOR_ATTACHMENT_READ,
+                vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL),
+            (vk::PipelineStageFlags::FRAGMENT_SHADER, vk::AccessFlags::SHADER_READ, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL))]);
+    }
+
+    #[test]
+    #[should_panic(expected = "must be used as an input attachment")]
+    fn group_attachments_cant_be_descriptors() {
+        let registry = RefCell::new(ResourceStateRegistry::new());
+        link_nodes(vec![
+            TestNode::new("gbuffer", &[], &[1]).renderpass_group("deferred").accesses(vec![color_attachment(1)]),
+            TestNode::new("lighting", &[1], &[2]).renderpass_group("deferred").accesses(vec![
+                image_access(1, vk::AccessFlags::SHADER_READ, vk::PipelineStageFlags::FRAGMENT_SHADER, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            ])
+        ], &registry);
+    }
+
+    fn dead_write_names(nodes: Vec<TestNode>, transients: &[u64]) -> Vec<(&'static str, Vec<u64>)> {
+        let mut graph: StableDiGraph<TestNode, u32> = StableDiGraph::new();
+        let sorted: Vec<NodeIndex> = nodes.into_iter().map(|node| graph.add_node(node)).collect();
+        let transient_handles: HashSet<u64> = transients.iter().cloned().collect();
+        find_dead_writes(&graph, &sorted, &transient_handles).into_iter()
+            .map(|(index, dead)| (graph[index].name, dead))
+            .collect()
+    }
+
+    #[test]
+    fn unread_transient_writes_are_dead() {
+        // the gbuffer's velocity target (3) is never read, and the final image (5) is persistent
+        let dead = dead_write_names(vec![
+            TestNode::new("gbuffer", &[1, 2, 3], &[1, 2, 3]).overwrites(&[1, 2, 3]),
+            TestNode::new("lighting", &[1, 2], &[4]),
+            TestNode::new("tonemap", &[4], &[5])
+        ], &[1, 2, 3, 4]);
+        assert_eq!(dead, vec![("gbuffer", vec![3])]);
+    }
+
+    #[test]
+    fn overwritten_contents_are_dead() {
+        // the second pass clears 1 rather than loading it, so the first pass's contents are
+        // never read, while 2 is loaded and keeps the first pass's write alive
+        let dead = dead_write_names(vec![
+            TestNode::new("first", &[1, 2], &[1, 2]).overwrites(&[1, 2]),
+            TestNode::new("second", &[1, 2], &[1, 2]).overwrites(&[1]),
+            TestNode::new("resolve", &[1, 2], &[3])
+        ], &[1, 2]);
+        assert_eq!(dead, vec![("first", vec![1])]);
+    }
+
+    fn validate_reads(nodes: Vec<TestNode>, transients: &[u64]) {
+        let mut graph: StableDiGraph<TestNode, u32> = StableDiGraph::new();
+        let sorted: Vec<NodeIndex> = nodes.into_iter().map(|node| graph.add_node(node)).collect();
+        let transient_handles: HashSet<u64> = transients.iter().cloned().collect();
+        validate_transient_reads(&graph, &sorted, &transient_handles);
+    }
+
+    fn validate_lifetimes(nodes: Vec<TestNode>, transients: &[u64], previous_transients: &[u64]) {
+        let mut graph: StableDiGraph<TestNode, u32> = StableDiGraph::new();
+        for node in nodes {
+            graph.add_node(node);
+        }
+        let transient_handles: HashSet<u64> = transients.iter().cloned().collect();
+        let previous_handles: HashSet<u64> = previous_transients.iter().cloned().collect();
+        validate_transient_lifetimes(&graph, &transient_handles, &previous_handles);
+    }
+
+    #[test]
+    fn transient_reads_after_writes_are_valid() {
+        // 2 is persistent, so its contents may come from an earlier frame
+        validate_reads(vec![
+            TestNode::new("gbuffer", &[1], &[1]).overwrites(&[1]),
+            TestNode::new("lighting", &[1, 2], &[3]),
+            TestNode::new("tonemap", &[3], &[4])
+        ], &[1, 3]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Node lighting reads transient resource 1 before it has been written")]
+    fn transient_read_before_write_panics() {
+        validate_reads(vec![
+            TestNode::new("lighting", &[1], &[3]),
+            TestNode::new("gbuffer", &[1], &[1]).overwrites(&[1])
+        ], &[1, 3]);
+    }
+
+    #[test]
+    fn cleared_transient_attachments_are_valid() {
+        // an attachment is read and written by the node, but isn't loaded
+        validate_reads(vec![
+            TestNode::new("gbuffer", &[1], &[1]).overwrites(&[1])
+        ], &[1]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Node gbuffer loads transient attachment 1 before it has been written")]
+    fn loaded_transient_attachment_before_write_panics() {
+        validate_reads(vec![
+            TestNode::new("gbuffer", &[1], &[1]).loads(&[1])
+        ], &[1]);
+    }
+
+    #[test]
+    fn loaded_transient_attachment_after_write_is_valid() {
+        validate_reads(vec![
+            TestNode::new("opaque", &[1], &[1]).overwrites(&[1]),
+            TestNode::new("transparent", &[1], &[1]).loads(&[1])
+        ], &[1]);
+    }
+
+    #[test]
+    fn transients_declared_again_are_valid() {
+        // 1 was transient last frame and still is, and 2 was never transient
+        validate_lifetimes(vec![
+            TestNode::new("blur", &[1, 2], &[3])
+        ], &[1, 3], &[1]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Node blur references resource 1 as persistent, but it was transient in the previous frame")]
+    fn previous_transient_read_as_persistent_panics() {
+        validate_lifetimes(vec![
+            TestNode::new("blur", &[1], &[3])
+        ], &[3], &[1]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Node blur references resource 3 as persistent, but it was transient in the previous frame")]
+    fn previous_transient_written_as_persistent_panics() {
+        validate_lifetimes(vec![
+            TestNode::new("blur", &[1], &[3])
+        ], &[1], &[3]);
+    }
+
+    #[test]
+    fn persistent_resources_outside_the_transients_are_valid() {
+        validate_persistent_resources(&HashSet::from([1, 2]), &HashSet::from([3]));
+        validate_persistent_resources(&HashSet::new(), &HashSet::from([3]));
+    }
+
+    #[test]
+    #[should_panic(expected = "Resource 2 is imported as persistent, but is transient to the frame")]
+    fn transient_persistent_resource_panics() {
+        validate_persistent_resources(&HashSet::from([1, 2]), &HashSet::from([2, 3]));
+    }
+}
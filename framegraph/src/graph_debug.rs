@@ -0,0 +1,41 @@
+//! Logging of the decisions the frame graph makes, enabled with the "graph-debug" feature:
+//! the edges compile adds, the nodes it culls, the barriers recorded and the renderpass and
+//! pipeline cache lookups. Every event is logged at debug level to the "graph_debug" target
+//! along with the sequence number of the Frame being recorded, which matches the one logged
+//! when a capture of it is taken (see GpuCapture).
+
+#[cfg(feature = "graph-debug")]
+use std::sync::atomic::{AtomicU64, Ordering};
+
+#[cfg(feature = "graph-debug")]
+static FRAME_SEQUENCE: AtomicU64 = AtomicU64::new(0);
+
+/// Called as each Frame starts being recorded, with the order it was started in
+#[cfg(feature = "graph-debug")]
+pub(crate) fn begin_frame(sequence: u64) {
+    FRAME_SEQUENCE.store(sequence, Ordering::Relaxed);
+}
+
+#[cfg(not(feature = "graph-debug"))]
+pub(crate) fn begin_frame(_sequence: u64) {}
+
+#[cfg(feature = "graph-debug")]
+pub(crate) fn get_frame_sequence() -> u64 {
+    FRAME_SEQUENCE.load(Ordering::Relaxed)
+}
+
+/// Takes the same arguments as tracing::debug!, without a target. The arguments aren't
+/// evaluated unless the feature is enabled
+#[cfg(feature = "graph-debug")]
+macro_rules! graph_debug {
+    ($($arg:tt)+) => {
+        tracing::debug!(target: "graph_debug", frame = $crate::graph_debug::get_frame_sequence(), $($arg)+)
+    };
+}
+
+#[cfg(not(feature = "graph-debug"))]
+macro_rules! graph_debug {
+    ($($arg:tt)+) => {};
+}
+
+pub(crate) use graph_debug;
@@ -0,0 +1,518 @@
+//! Passes described as data. A [`GraphDocument`] lists passes along with the resources they
+//! read and write, referring to resources, pipelines and fill callbacks by name. Those names
+//! are resolved against a [`DocumentRegistry`] when the document is instantiated, producing
+//! the same pass nodes which would otherwise be built in code.
+//!
+//! ```ron
+//! (passes: [
+//!     Graphics((
+//!         name: "Scene",
+//!         pipeline: Some("scene"),
+//!         fill: "draw_scene",
+//!         render_targets: [(resource: "hdr_color")],
+//!         depth_target: Some((resource: "depth", lifetime: Transient)),
+//!         reads: [(resource: "camera", set: 0, slot: 0, usage: UniformBuffer(range: 128))],
+//!     )),
+//!     Copy((
+//!         name: "Readback",
+//!         fill: "copy_hdr",
+//!         sources: ["hdr_color"],
+//!         dests: ["readback_buffer"],
+//!     )),
+//! ])
+//! ```
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+use std::path::Path;
+use std::rc::Rc;
+use ash::vk;
+use serde::Deserialize;
+use api_types::device::DeviceResource;
+use context::vulkan_render_context::VulkanRenderContext;
+use crate::attachment::AttachmentReference;
+use crate::binding::{BindingInfo, BindingType, BufferBindingInfo, ImageBindingInfo, ResourceBinding, ResourceLifetime};
+use crate::compute_pass_node::ComputePassNode;
+use crate::copy_pass_node::CopyPassNode;
+use crate::graphics_pass_node::GraphicsPassNode;
+use crate::pass_node::FillCallback;
+use crate::pass_type::PassType;
+use crate::pipeline::{ComputePipelineDescription, PipelineDescription};
+
+fn default_samples() -> u32 { 1 }
+
+#[derive(Copy, Clone, Debug, Deserialize)]
+pub enum DocumentStage {
+    Vertex,
+    Fragment,
+    Compute,
+    Transfer
+}
+
+impl DocumentStage {
+    fn to_vk(self) -> vk::PipelineStageFlags {
+        match self {
+            DocumentStage::Vertex => vk::PipelineStageFlags::VERTEX_SHADER,
+            DocumentStage::Fragment => vk::PipelineStageFlags::FRAGMENT_SHADER,
+            DocumentStage::Compute => vk::PipelineStageFlags::COMPUTE_SHADER,
+            DocumentStage::Transfer => vk::PipelineStageFlags::TRANSFER
+        }
+    }
+}
+
+/// How a binding uses its resource. Whether it's read or written is decided by which list
+/// of the pass it appears in
+#[derive(Copy, Clone, Debug, Deserialize)]
+pub enum DocumentUsage {
+    UniformBuffer {
+        #[serde(default)]
+        offset: vk::DeviceSize,
+        range: vk::DeviceSize
+    },
+    StorageBuffer {
+        #[serde(default)]
+        offset: vk::DeviceSize,
+        range: vk::DeviceSize
+    },
+    SampledImage,
+    StorageImage
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct BindingDocument {
+    pub resource: String,
+    pub set: u64,
+    pub slot: u32,
+    pub usage: DocumentUsage,
+    /// Defaults to the fragment stage for graphics passes and the compute stage for
+    /// compute passes
+    #[serde(default)]
+    pub stage: Option<DocumentStage>,
+    #[serde(default)]
+    pub lifetime: ResourceLifetime
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct AttachmentDocument {
+    pub resource: String,
+    #[serde(default = "default_samples")]
+    pub samples: u32,
+    #[serde(default)]
+    pub lifetime: ResourceLifetime
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct GraphicsPassDocument {
+    pub name: String,
+    #[serde(default)]
+    pub pipeline: Option<String>,
+    pub fill: String,
+    #[serde(default)]
+    pub render_targets: Vec<AttachmentDocument>,
+    #[serde(default)]
+    pub depth_target: Option<AttachmentDocument>,
+    #[serde(default)]
+    pub reads: Vec<BindingDocument>,
+    #[serde(default)]
+    pub writes: Vec<BindingDocument>,
+    #[serde(default)]
+    pub renderpass_group: Option<String>,
+    #[serde(default)]
+    pub priority: i32
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct ComputePassDocument {
+    pub name: String,
+    pub pipeline: String,
+    pub fill: String,
+    #[serde(default)]
+    pub reads: Vec<BindingDocument>,
+    #[serde(default)]
+    pub writes: Vec<BindingDocument>,
+    #[serde(default)]
+    pub priority: i32
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct CopyPassDocument {
+    pub name: String,
+    pub fill: String,
+    #[serde(default)]
+    pub sources: Vec<String>,
+    #[serde(default)]
+    pub dests: Vec<String>,
+    #[serde(default)]
+    pub priority: i32
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub enum PassDocument {
+    Graphics(GraphicsPassDocument),
+    Compute(ComputePassDocument),
+    Copy(CopyPassDocument)
+}
+
+impl PassDocument {
+    pub fn get_name(&self) -> &str {
+        match self {
+            PassDocument::Graphics(gd) => { &gd.name },
+            PassDocument::Compute(cd) => { &cd.name },
+            PassDocument::Copy(cd) => { &cd.name }
+        }
+    }
+}
+
+/// A graph loaded from a RON document; see the module documentation for the format
+#[derive(Clone, Debug, Deserialize)]
+pub struct GraphDocument {
+    pub passes: Vec<PassDocument>
+}
+
+/// The resources, pipelines and fill callbacks a document's names resolve to
+#[derive(Default)]
+pub struct DocumentRegistry {
+    resources: HashMap<String, Rc<RefCell<DeviceResource>>>,
+    pipelines: HashMap<String, PipelineDescription>,
+    compute_pipelines: HashMap<String, ComputePipelineDescription>,
+    fill_callbacks: HashMap<String, Rc<FillCallback>>
+}
+
+impl Debug for DocumentRegistry {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DocumentRegistry")
+            .field("resources", &self.resources.keys())
+            .field("pipelines", &self.pipelines.keys())
+            .field("compute pipelines", &self.compute_pipelines.keys())
+            .field("fill callbacks", &self.fill_callbacks.keys())
+            .finish()
+    }
+}
+
+impl DocumentRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Resources are typically re-registered every frame, e.g. for the current swapchain image
+    pub fn register_resource(&mut self, name: &str, resource: Rc<RefCell<DeviceResource>>) {
+        self.resources.insert(name.to_string(), resource);
+    }
+
+    pub fn register_pipeline(&mut self, name: &str, pipeline_description: PipelineDescription) {
+        self.pipelines.insert(name.to_string(), pipeline_description);
+    }
+
+    pub fn register_compute_pipeline(&mut self, name: &str, pipeline_description: ComputePipelineDescription) {
+        self.compute_pipelines.insert(name.to_string(), pipeline_description);
+    }
+
+    /// Every pass naming `name` as its fill callback shares `fill_callback`
+    pub fn register_fill_callback(&mut self, name: &str, fill_callback: Rc<FillCallback>) {
+        self.fill_callbacks.insert(name.to_string(), fill_callback);
+    }
+
+    fn resolve_resource(&self, name: &str) -> Result<Rc<RefCell<DeviceResource>>, &'static str> {
+        self.resources.get(name).cloned().ok_or("Graph document refers to an unregistered resource")
+    }
+
+    fn resolve_fill_callback(&self, name: &str) -> Result<Rc<FillCallback>, &'static str> {
+        self.fill_callbacks.get(name).cloned().ok_or("Graph document refers to an unregistered fill callback")
+    }
+
+    fn resolve_binding(
+        &self,
+        binding: &BindingDocument,
+        default_stage: DocumentStage,
+        write: bool) -> Result<ResourceBinding, &'static str> {
+
+        let (binding_type, access) = match binding.usage {
+            DocumentUsage::UniformBuffer { offset, range } => {
+                if write {
+                    return Err("Uniform buffers can't be written by a pass");
+                }
+                (BindingType::Buffer(BufferBindingInfo { offset, range, layout: None }), vk::AccessFlags::UNIFORM_READ)
+            },
+            DocumentUsage::StorageBuffer { offset, range } => {
+                (BindingType::Buffer(BufferBindingInfo { offset, range, layout: None }), shader_access(write))
+            },
+            DocumentUsage::SampledImage => {
+                if write {
+                    return Err("Sampled images can't be written by a pass");
+                }
+                (BindingType::Image(ImageBindingInfo::new(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)), vk::AccessFlags::SHADER_READ)
+            },
+            DocumentUsage::StorageImage => {
+                (BindingType::Image(ImageBindingInfo::new(vk::ImageLayout::GENERAL)), shader_access(write))
+            }
+        };
+
+        Ok(ResourceBinding {
+            resource: self.resolve_resource(&binding.resource)?,
+            binding_info: BindingInfo {
+                binding_type,
+                set: binding.set,
+                slot: binding.slot,
+                stage: binding.stage.unwrap_or(default_stage).to_vk(),
+                access
+            },
+            lifetime: binding.lifetime
+        })
+    }
+
+    fn resolve_attachment(&self, attachment: &AttachmentDocument) -> Result<AttachmentReference, &'static str> {
+        if !attachment.samples.is_power_of_two() || attachment.samples > 64 {
+            return Err("Attachment sample counts must be a power of two no greater than 64");
+        }
+        let mut attachment_reference = AttachmentReference::new(
+            self.resolve_resource(&attachment.resource)?,
+            vk::SampleCountFlags::from_raw(attachment.samples));
+        attachment_reference.lifetime = attachment.lifetime;
+        Ok(attachment_reference)
+    }
+}
+
+fn shader_access(write: bool) -> vk::AccessFlags {
+    if write {
+        vk::AccessFlags::SHADER_WRITE
+    } else {
+        vk::AccessFlags::SHADER_READ
+    }
+}
+
+fn shared_fill(fill_callback: Rc<FillCallback>) -> Box<FillCallback> {
+    Box::new(move |render_ctx: &VulkanRenderContext, command_buffer: &vk::CommandBuffer| {
+        fill_callback(render_ctx, command_buffer);
+    })
+}
+
+impl GraphDocument {
+    pub fn from_ron(source: &str) -> Result<Self, ron::error::SpannedError> {
+        ron::from_str(source)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let source = std::fs::read_to_string(path)
+            .map_err(|error| format!("Failed to read graph document {}: {}", path.display(), error))?;
+        Self::from_ron(&source)
+            .map_err(|error| format!("Failed to parse graph document {}: {}", path.display(), error))
+    }
+
+    /// Builds a pass node for every pass in the document. The caller adds them to a Frame
+    /// like any other node
+    pub fn instantiate(&self, registry: &DocumentRegistry) -> Result<Vec<PassType>, &'static str> {
+        self.passes.iter().map(|pass| -> Result<PassType, &'static str> {
+            match pass {
+                PassDocument::Graphics(gd) => {
+                    let mut builder = GraphicsPassNode::builder(gd.name.clone())
+                        .priority(gd.priority);
+                    if let Some(pipeline_name) = &gd.pipeline {
+                        let pipeline_description = registry.pipelines.get(pipeline_name)
+                            .ok_or("Graph document refers to an unregistered pipeline")?;
+                        builder = builder.pipeline_description(pipeline_description.clone());
+                    }
+                    if let Some(group_name) = &gd.renderpass_group {
+                        builder = builder.renderpass_group(group_name);
+                    }
+                    for rt in &gd.render_targets {
+                        builder = builder.render_target(registry.resolve_attachment(rt)?);
+                    }
+                    if let Some(dt) = &gd.depth_target {
+                        builder = builder.depth_target(registry.resolve_attachment(dt)?);
+                    }
+                    for read in &gd.reads {
+                        builder = builder.read(registry.resolve_binding(read, DocumentStage::Fragment, false)?);
+                    }
+                    for write in &gd.writes {
+                        builder = builder.write(registry.resolve_binding(write, DocumentStage::Fragment, true)?);
+                    }
+
+                    let fill_callback = registry.resolve_fill_callback(&gd.fill)?;
+                    Ok(PassType::Graphics(builder.fill_commands(shared_fill(fill_callback)).build()?))
+                },
+                PassDocument::Compute(cd) => {
+                    let pipeline_description = registry.compute_pipelines.get(&cd.pipeline)
+                        .ok_or("Graph document refers to an unregistered compute pipeline")?;
+                    let mut builder = ComputePassNode::builder(cd.name.clone())
+                        .pipeline_description(pipeline_description.clone())
+                        .priority(cd.priority);
+                    for read in &cd.reads {
+                        builder = builder.input(registry.resolve_binding(read, DocumentStage::Compute, false)?);
+                    }
+                    for write in &cd.writes {
+                        builder = builder.output(registry.resolve_binding(write, DocumentStage::Compute, true)?);
+                    }
+
+                    let fill_callback = registry.resolve_fill_callback(&cd.fill)?;
+                    Ok(PassType::Compute(builder.fill_commands(shared_fill(fill_callback)).build()?))
+                },
+                PassDocument::Copy(cd) => {
+                    let mut builder = CopyPassNode::builder(cd.name.clone())
+                        .priority(cd.priority);
+                    for source in &cd.sources {
+                        builder = builder.copy_src(registry.resolve_resource(source)?);
+                    }
+                    for dest in &cd.dests {
+                        builder = builder.copy_dst(registry.resolve_resource(dest)?);
+                    }
+
+                    let fill_callback = registry.resolve_fill_callback(&cd.fill)?;
+                    Ok(PassType::Copy(builder.fill_commands(shared_fill(fill_callback)).build()?))
+                }
+            }
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCENE_DOCUMENT: &str = r#"(passes: [
+        Graphics((
+            name: "Scene",
+            pipeline: Some("scene"),
+            fill: "draw_scene",
+            render_targets: [(resource: "hdr_color")],
+            depth_target: Some((resource: "depth", lifetime: Transient)),
+            reads: [(resource: "camera", set: 0, slot: 0, usage: UniformBuffer(range: 128))],
+        )),
+        Compute((
+            name: "Tonemap",
+            pipeline: "tonemap",
+            fill: "dispatch_tonemap",
+            reads: [(resource: "hdr_color", set: 0, slot: 0, usage: SampledImage)],
+            writes: [(resource: "ldr_color", set: 0, slot: 1, usage: StorageImage, stage: Some(Compute))],
+            priority: 2,
+        )),
+        Copy((
+            name: "Readback",
+            fill: "copy_hdr",
+            sources: ["hdr_color"],
+            dests: ["readback_buffer"],
+        )),
+    ])"#;
+
+    #[test]
+    fn parses_every_pass_type() {
+        let document = GraphDocument::from_ron(SCENE_DOCUMENT).expect("Failed to parse graph document");
+        let names: Vec<&str> = document.passes.iter().map(|pass| pass.get_name()).collect();
+        assert_eq!(names, vec!["Scene", "Tonemap", "Readback"]);
+
+        match &document.passes[0] {
+            PassDocument::Graphics(gd) => {
+                assert_eq!(gd.pipeline.as_deref(), Some("scene"));
+                assert_eq!(gd.render_targets.len(), 1);
+                let depth_target = gd.depth_target.as_ref().expect("Depth target wasn't parsed");
+                assert_eq!(depth_target.lifetime, ResourceLifetime::Transient);
+                assert!(matches!(gd.reads[0].usage, DocumentUsage::UniformBuffer { offset: 0, range: 128 }));
+            },
+            _ => panic!("Expected a graphics pass")
+        }
+        match &document.passes[1] {
+            PassDocument::Compute(cd) => {
+                assert_eq!(cd.pipeline, "tonemap");
+                assert_eq!(cd.priority, 2);
+                assert!(matches!(cd.writes[0].stage, Some(DocumentStage::Compute)));
+            },
+            _ => panic!("Expected a compute pass")
+        }
+        match &document.passes[2] {
+            PassDocument::Copy(cd) => {
+                assert_eq!(cd.sources, vec!["hdr_color"]);
+                assert_eq!(cd.dests, vec!["readback_buffer"]);
+            },
+            _ => panic!("Expected a copy pass")
+        }
+    }
+
+    #[test]
+    fn omitted_fields_use_defaults() {
+        let document = GraphDocument::from_ron(r#"(passes: [
+            Graphics((
+                name: "Minimal",
+                fill: "draw",
+                render_targets: [(resource: "color")],
+                reads: [(resource: "lights", set: 1, slot: 2, usage: StorageBuffer(range: 64))],
+            )),
+        ])"#).expect("Failed to parse graph document");
+
+        match &document.passes[0] {
+            PassDocument::Graphics(gd) => {
+                assert!(gd.pipeline.is_none());
+                assert!(gd.depth_target.is_none());
+                assert!(gd.writes.is_empty());
+                assert!(gd.renderpass_group.is_none());
+                assert_eq!(gd.priority, 0);
+                assert_eq!(gd.render_targets[0].samples, 1);
+                assert_eq!(gd.render_targets[0].lifetime, ResourceLifetime::Persistent);
+                assert!(gd.reads[0].stage.is_none());
+                assert!(matches!(gd.reads[0].usage, DocumentUsage::StorageBuffer { offset: 0, range: 64 }));
+            },
+            _ => panic!("Expected a graphics pass")
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_documents() {
+        // missing the fill callback
+        assert!(GraphDocument::from_ron(r#"(passes: [Copy((name: "Copy"))])"#).is_err());
+        // unknown pass type
+        assert!(GraphDocument::from_ron(r#"(passes: [Raytrace((name: "Trace", fill: "trace"))])"#).is_err());
+        // unknown usage
+        assert!(GraphDocument::from_ron(r#"(passes: [Compute((name: "Cull", pipeline: "cull", fill: "cull",
+            reads: [(resource: "draws", set: 0, slot: 0, usage: IndirectBuffer)]))])"#).is_err());
+    }
+
+    #[test]
+    fn unregistered_names_fail_to_instantiate() {
+        let registry = DocumentRegistry::new();
+        let graphics = GraphDocument::from_ron(r#"(passes: [Graphics((name: "Scene", pipeline: Some("scene"), fill: "draw"))])"#)
+            .expect("Failed to parse graph document");
+        assert_eq!(graphics.instantiate(&registry).err(), Some("Graph document refers to an unregistered pipeline"));
+
+        let compute = GraphDocument::from_ron(r#"(passes: [Compute((name: "Cull", pipeline: "cull", fill: "cull"))])"#)
+            .expect("Failed to parse graph document");
+        assert_eq!(compute.instantiate(&registry).err(), Some("Graph document refers to an unregistered compute pipeline"));
+
+        let copy = GraphDocument::from_ron(r#"(passes: [Copy((name: "Copy", fill: "copy", sources: ["missing"]))])"#)
+            .expect("Failed to parse graph document");
+        assert_eq!(copy.instantiate(&registry).err(), Some("Graph document refers to an unregistered resource"));
+
+        let unfilled = GraphDocument::from_ron(r#"(passes: [Copy((name: "Copy", fill: "copy"))])"#)
+            .expect("Failed to parse graph document");
+        assert_eq!(unfilled.instantiate(&registry).err(), Some("Graph document refers to an unregistered fill callback"));
+    }
+
+    #[test]
+    fn bindings_which_cant_be_written_are_rejected() {
+        let registry = DocumentRegistry::new();
+        let binding = |usage: DocumentUsage| BindingDocument {
+            resource: "target".to_string(),
+            set: 0,
+            slot: 0,
+            usage,
+            stage: None,
+            lifetime: ResourceLifetime::Persistent
+        };
+        let uniform = registry.resolve_binding(&binding(DocumentUsage::UniformBuffer { offset: 0, range: 16 }), DocumentStage::Fragment, true);
+        assert_eq!(uniform.err(), Some("Uniform buffers can't be written by a pass"));
+        let sampled = registry.resolve_binding(&binding(DocumentUsage::SampledImage), DocumentStage::Fragment, true);
+        assert_eq!(sampled.err(), Some("Sampled images can't be written by a pass"));
+    }
+
+    #[test]
+    fn attachment_sample_counts_are_validated() {
+        let registry = DocumentRegistry::new();
+        for samples in [0, 3, 128] {
+            let attachment = AttachmentDocument {
+                resource: "color".to_string(),
+                samples,
+                lifetime: ResourceLifetime::Persistent
+            };
+            assert_eq!(registry.resolve_attachment(&attachment).err(),
+                Some("Attachment sample counts must be a power of two no greater than 64"));
+        }
+    }
+}
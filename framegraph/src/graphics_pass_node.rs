@@ -3,11 +3,65 @@ use std::fmt::{Debug, Formatter};
 use std::rc::Rc;
 use ash::vk;
 use api_types::device::{DeviceFramebuffer, DeviceResource};
-use crate::pass_node::{PassNode, FillCallback};
-use crate::binding::{ResourceBinding};
+use api_types::name::Name;
+use crate::pass_node::{PassNode, FillCallback, PassHandle};
+use crate::binding::{BindingType, ResourceBinding, ResourceDependency, ResourceLifetime};
 use context::vulkan_render_context::VulkanRenderContext;
 use crate::attachment::AttachmentReference;
-use crate::pipeline::{PipelineDescription};
+use crate::pipeline::{PipelineDescription, SubpassTargets};
+use crate::node_arena;
+
+/// A vertex buffer the frame graph binds before the node's fill callback runs
+#[derive(Clone, Debug)]
+pub struct VertexBufferBinding {
+    pub resource: Rc<RefCell<DeviceResource>>,
+    pub offset: vk::DeviceSize
+}
+
+/// An index buffer the frame graph binds before the node's fill callback runs
+#[derive(Clone, Debug)]
+pub struct IndexBufferBinding {
+    pub resource: Rc<RefCell<DeviceResource>>,
+    pub offset: vk::DeviceSize,
+    pub index_type: vk::IndexType
+}
+
+/// Renders each draw once per view in `view_mask`, to the array layer of every attachment
+/// with the same index as the view. Needs NegotiatedFeature::Multiview, and shaders pick
+/// their per view state with gl_ViewIndex
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Multiview {
+    pub view_mask: u32,
+    /// Views which are likely to be spatially close, e.g. the two eyes of a stereo pair, so
+    /// the implementation may render them together
+    pub correlation_mask: u32
+}
+
+impl Multiview {
+    /// Views 0 to `view_count` - 1, none of which are correlated
+    pub fn new(view_count: u32) -> Self {
+        assert!(view_count > 0 && view_count <= 32, "Multiview needs between 1 and 32 views, not {}", view_count);
+        Multiview {
+            view_mask: u32::MAX >> (32 - view_count),
+            correlation_mask: 0
+        }
+    }
+
+    /// A correlated left and right eye view
+    pub fn stereo() -> Self {
+        Multiview::new(2).correlated(0b11)
+    }
+
+    pub fn correlated(mut self, correlation_mask: u32) -> Self {
+        self.correlation_mask = correlation_mask;
+        self
+    }
+
+    /// The number of array layers attachments need, one past the highest view
+    pub fn get_layer_count(&self) -> u32 {
+        32 - self.view_mask.leading_zeros()
+    }
+}
 
 pub struct GraphicsPassNode {
     pub pipeline_description: Option<PipelineDescription>,
@@ -15,12 +69,33 @@ pub struct GraphicsPassNode {
     pub depth_target: Option<AttachmentReference>,
     pub inputs: Vec<ResourceBinding>,
     pub outputs: Vec<ResourceBinding>,
-    pub tagged_resources: Vec<Rc<RefCell<DeviceResource>>>,
+    pub input_attachments: Vec<ResourceBinding>,
+    pub renderpass_group: Option<Name>,
+    pub dependencies: Vec<ResourceDependency>,
+    /// Bound to consecutive vertex input bindings starting at 0
+    pub vertex_buffers: Vec<VertexBufferBinding>,
+    pub index_buffer: Option<IndexBufferBinding>,
     pub framebuffer: Option<DeviceFramebuffer>,
+    /// Covers the whole render area if not set
     pub viewport: Option<vk::Viewport>,
+    /// Covers the whole render area if not set
     pub scissor: Option<vk::Rect2D>,
+    /// Dynamic states other than the viewport and scissor which the fill callback sets itself,
+    /// see PassNodeBuilder::sets_dynamic_state
+    pub command_dynamic_states: Vec<vk::DynamicState>,
     pub fill_callback: Box<FillCallback>,
-    name: String
+    /// Set on nodes which do nothing but clear their only output, so the clear can be
+    /// folded into the renderpass of the node which follows it
+    pub clear_value: Option<vk::ClearValue>,
+    /// The aspects of the output a clear node clears
+    pub clear_aspects: vk::ImageAspectFlags,
+    /// See PassNodeBuilder::retained
+    pub retained: Option<u64>,
+    /// See PassNodeBuilder::multiview
+    pub multiview: Option<Multiview>,
+    priority: i32,
+    execute_after: Vec<PassHandle>,
+    name: Name
 }
 
 #[derive(Default)]
@@ -30,11 +105,22 @@ pub struct PassNodeBuilder {
     depth_target: Option<AttachmentReference>,
     inputs: Vec<ResourceBinding>,
     outputs: Vec<ResourceBinding>,
-    tagged_resources: Vec<Rc<RefCell<DeviceResource>>>,
+    input_attachments: Vec<ResourceBinding>,
+    renderpass_group: Option<Name>,
+    dependencies: Vec<ResourceDependency>,
+    vertex_buffers: Vec<VertexBufferBinding>,
+    index_buffer: Option<IndexBufferBinding>,
     fill_callback: Option<Box<FillCallback>>,
     viewport: Option<vk::Viewport>,
     scissor: Option<vk::Rect2D>,
-    name: String
+    command_dynamic_states: Vec<vk::DynamicState>,
+    clear_value: Option<vk::ClearValue>,
+    clear_aspects: vk::ImageAspectFlags,
+    retained: Option<u64>,
+    multiview: Option<Multiview>,
+    priority: i32,
+    execute_after: Vec<PassHandle>,
+    name: Name
 }
 
 impl PassNode for GraphicsPassNode  {
@@ -43,12 +129,19 @@ impl PassNode for GraphicsPassNode  {
         &self.name
     }
 
+    fn get_interned_name(&self) -> Name {
+        self.name
+    }
+
     fn get_reads(&self) -> Vec<u64> {
         let mut reads: Vec<u64> = Vec::new();
-        reads.reserve(self.inputs.len() + self.render_targets.len());
+        reads.reserve(self.inputs.len() + self.input_attachments.len() + self.render_targets.len());
         for input in &self.inputs {
            reads.push(input.resource.borrow().get_handle());
         }
+        for input_attachment in &self.input_attachments {
+            reads.push(input_attachment.resource.borrow().get_handle());
+        }
         // color and depth targets also likely depend on previous writes
         for rt in &self.render_targets {
             reads.push(rt.resource_image.borrow().get_handle());
@@ -56,6 +149,9 @@ impl PassNode for GraphicsPassNode  {
         if let Some(dt) = &self.depth_target {
             reads.push(dt.resource_image.borrow().get_handle());
         }
+        for dependency in &self.dependencies {
+            reads.push(dependency.resource.borrow().get_handle());
+        }
 
         reads
     }
@@ -71,10 +167,39 @@ impl PassNode for GraphicsPassNode  {
         if let Some(dt) = &self.depth_target {
             writes.push(dt.resource_image.borrow().get_handle());
         }
+        for dependency in self.dependencies.iter().filter(|dependency| dependency.is_write()) {
+            writes.push(dependency.resource.borrow().get_handle());
+        }
 
         writes
     }
 
+    fn get_dependencies(&self) -> &[ResourceDependency] {
+        &self.dependencies
+    }
+
+    fn get_transients(&self) -> Vec<u64> {
+        let bindings = self.inputs.iter()
+            .chain(&self.outputs)
+            .chain(&self.input_attachments)
+            .filter(|binding| binding.lifetime == ResourceLifetime::Transient)
+            .map(|binding| binding.resource.borrow().get_handle());
+        let attachments = self.render_targets.iter()
+            .chain(self.depth_target.as_ref())
+            .filter(|attachment| attachment.lifetime == ResourceLifetime::Transient)
+            .map(|attachment| attachment.resource_image.borrow().get_handle());
+
+        bindings.chain(attachments).collect()
+    }
+
+    fn get_priority(&self) -> i32 {
+        self.priority
+    }
+
+    fn get_execute_after(&self) -> &[PassHandle] {
+        &self.execute_after
+    }
+
 }
 
 impl Debug for GraphicsPassNode  {
@@ -86,15 +211,41 @@ impl Debug for GraphicsPassNode  {
 }
 
 impl GraphicsPassNode  {
-    pub fn builder(name: String) -> PassNodeBuilder {
+    pub fn builder(name: impl Into<Name>) -> PassNodeBuilder {
         PassNodeBuilder {
-            name,
+            name: name.into(),
+            render_targets: node_arena::take_attachments(),
+            inputs: node_arena::take_bindings(),
+            outputs: node_arena::take_bindings(),
+            input_attachments: node_arena::take_bindings(),
+            dependencies: node_arena::take_dependencies(),
             ..Default::default()
         }
     }
 
     pub fn get_pipeline_description(&self) -> &Option<PipelineDescription> { &self.pipeline_description }
 
+    /// The extent of the node's render targets, or its depth target if it has none
+    pub(crate) fn get_render_area(&self) -> Option<vk::Extent2D> {
+        self.render_targets.first().or(self.depth_target.as_ref()).map(|target| {
+            let extent = target.resource_image.borrow().get_image().extent;
+            vk::Extent2D { width: extent.width, height: extent.height }
+        })
+    }
+
+    /// The node's attachments as its pipeline sees them. Build checks that they all have the
+    /// same sample count
+    pub(crate) fn get_subpass_targets(&self) -> SubpassTargets {
+        SubpassTargets {
+            color_formats: self.render_targets.iter().map(|target| target.format).collect(),
+            depth_format: self.depth_target.as_ref().map(|target| target.format),
+            input_formats: self.input_attachments.iter().map(|binding| binding.resource.borrow().get_image().format).collect(),
+            samples: self.render_targets.first().or(self.depth_target.as_ref())
+                .map_or(vk::SampleCountFlags::TYPE_1, |target| target.samples),
+            view_mask: self.multiview.map_or(0, |multiview| multiview.view_mask)
+        }
+    }
+
     // pub fn set_framebuffer(&mut self, framebuffer: DeviceFramebuffer) {
     pub fn set_framebuffer(passnode: &mut Self, framebuffer: DeviceFramebuffer) {
         passnode.framebuffer = Some(framebuffer);
@@ -124,6 +275,14 @@ impl GraphicsPassNode  {
         &mut self.outputs
     }
 
+    pub fn get_input_attachments(&self) -> &[ResourceBinding] {
+        &self.input_attachments
+    }
+
+    pub fn get_renderpass_group(&self) -> Option<&str> {
+        self.renderpass_group.as_deref()
+    }
+
     pub fn get_rendertargets_mut(&mut self) -> &mut [AttachmentReference] {
         &mut self.render_targets
     }
@@ -150,11 +309,41 @@ impl PassNodeBuilder {
         self
     }
 
-    pub fn tag(mut self, tagged_resource: Rc<RefCell<DeviceResource>>) -> Self {
-        self.tagged_resources.push(tagged_resource);
+    /// Uses `resource` without binding it to a descriptor, e.g. vertex and index buffers
+    /// (see ResourceDependency)
+    pub fn depends_on(
+        mut self,
+        resource: Rc<RefCell<DeviceResource>>,
+        access: vk::AccessFlags,
+        stage: vk::PipelineStageFlags) -> Self {
+        self.dependencies.push(ResourceDependency::new(resource, access, stage));
         self
     }
 
+    /// Binds `vertex_buffer` to the next vertex input binding, starting at 0, before the fill
+    /// callback runs
+    pub fn vertex_buffer(mut self, vertex_buffer: Rc<RefCell<DeviceResource>>, offset: vk::DeviceSize) -> Self {
+        self.vertex_buffers.push(VertexBufferBinding {
+            resource: vertex_buffer.clone(),
+            offset
+        });
+        self.depends_on(vertex_buffer, vk::AccessFlags::VERTEX_ATTRIBUTE_READ, vk::PipelineStageFlags::VERTEX_INPUT)
+    }
+
+    /// Binds `index_buffer` before the fill callback runs
+    pub fn index_buffer(
+        mut self,
+        index_buffer: Rc<RefCell<DeviceResource>>,
+        offset: vk::DeviceSize,
+        index_type: vk::IndexType) -> Self {
+        self.index_buffer = Some(IndexBufferBinding {
+            resource: index_buffer.clone(),
+            offset,
+            index_type
+        });
+        self.depends_on(index_buffer, vk::AccessFlags::INDEX_READ, vk::PipelineStageFlags::VERTEX_INPUT)
+    }
+
     pub fn read(mut self, input: ResourceBinding) -> Self {
         self.inputs.push(input);
         self
@@ -165,6 +354,22 @@ impl PassNodeBuilder {
         self
     }
 
+    /// Reads an attachment written by an earlier subpass of the same renderpass group.
+    /// The binding's set and slot must match an `input_attachment` descriptor in the
+    /// fragment shader, and its image layout is used for both the subpass reference and
+    /// the descriptor write (typically SHADER_READ_ONLY_OPTIMAL)
+    pub fn input_attachment(mut self, input_attachment: ResourceBinding) -> Self {
+        self.input_attachments.push(input_attachment);
+        self
+    }
+
+    /// Nodes sharing a renderpass group are merged into a single renderpass with one
+    /// subpass per node, in execution order
+    pub fn renderpass_group(mut self, group_name: &str) -> Self {
+        self.renderpass_group = Some(Name::new(group_name));
+        self
+    }
+
     pub fn render_target(mut self, render_target: AttachmentReference) -> Self {
         self.render_targets.push(render_target);
         self
@@ -193,29 +398,174 @@ impl PassNodeBuilder {
         self
     }
 
+    /// Declares that the fill callback sets `dynamic_state` before drawing (e.g. DEPTH_BIAS
+    /// per shadow cascade). The executor only sets the viewport and scissor, so in debug
+    /// builds a node whose pipeline has any other dynamic state it hasn't declared panics
+    pub fn sets_dynamic_state(mut self, dynamic_state: vk::DynamicState) -> Self
+    {
+        self.command_dynamic_states.push(dynamic_state);
+        self
+    }
+
+    /// Marks the node as a standalone clear of the `aspect_mask` aspects of its only output to
+    /// `clear_value`. If the next node to execute renders to the same image, the clear is merged
+    /// into that node's renderpass as a CLEAR load op and this node is skipped
+    pub fn clears(mut self, clear_value: vk::ClearValue, aspect_mask: vk::ImageAspectFlags) -> Self {
+        self.clear_value = Some(clear_value);
+        self.clear_aspects = aspect_mask;
+        self
+    }
+
+    /// Keeps the commands recorded by the fill callback and executes them again on later frames
+    /// instead of calling it, for static content which is expensive to record. The recording
+    /// is redone when `version` changes, and whenever the node's pipeline, renderpass,
+    /// bindings, vertex or index buffers, viewport or scissor differ from when it was made.
+    /// Anything else the fill callback depends on must be covered by `version`.
+    ///
+    /// The fill callback records into a secondary command buffer inside the node's renderpass.
+    /// Recordings are kept by node name, so retained nodes need unique names. A recording is
+    /// discarded on any frame its node isn't executed. Nodes are recorded every frame as usual
+    /// while the render context uses descriptor buffers, and when they bind or draw from a
+    /// buffer suballocated every frame such as a DynamicUploadBuffer.
+    ///
+    /// GPU spans the fill callback opens are no-ops while it records, since the recording
+    /// outlives the frame's query pool (see profiling::suspend_gpu_spans); the span the frame
+    /// graph opens around every node still times each execution. Recordings inherit the first
+    /// subpass of the node's own renderpass, so nodes in a renderpass group can't be retained
+    pub fn retained(mut self, version: u64) -> Self {
+        self.retained = Some(version);
+        self
+    }
+
+    /// Renders to several array layers of the render and depth targets at once, one per view
+    /// in `multiview`. The pipeline description must have the same view mask, see
+    /// PipelineDescription::multiview. Nodes in a renderpass group must all use multiview, or
+    /// none of them
+    pub fn multiview(mut self, multiview: Multiview) -> Self {
+        self.multiview = Some(multiview);
+        self
+    }
+
+    /// Defaults to 0. Higher priorities are recorded earlier when several nodes are ready
+    pub fn priority(mut self, priority: i32) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Executes this node after `node` (the handle Frame::add_node returned for it) even though
+    /// they share no resources, e.g. after a pass resetting GPU timestamps or changing some
+    /// external state. `node` is never culled while this node isn't
+    pub fn after(mut self, node: PassHandle) -> Self {
+        self.execute_after.push(node);
+        self
+    }
+
     pub fn build(mut self) -> Result<GraphicsPassNode, &'static str> {
         assert!(self.fill_callback.is_some(), "No fill callback set");
 
+        if !self.input_attachments.is_empty() && self.renderpass_group.is_none() {
+            return Err("Input attachments can only be used by nodes in a renderpass group");
+        }
+        if self.input_attachments.iter().any(|binding| matches!(binding.binding_info.binding_type, BindingType::Buffer(_))) {
+            return Err("Input attachments must be bound as images");
+        }
+        if self.renderpass_group.is_some() && self.pipeline_description.is_none() {
+            return Err("Nodes in a renderpass group require a pipeline description");
+        }
+        if self.clear_value.is_some() && self.outputs.len() != 1 {
+            return Err("Clear nodes must write exactly one output");
+        }
+        if self.retained.is_some() && (self.pipeline_description.is_none() || self.renderpass_group.is_some()) {
+            return Err("Retained nodes require a pipeline description and can't be part of a renderpass group");
+        }
+        let mut sample_counts = self.render_targets.iter().chain(&self.depth_target).map(|attachment| attachment.samples);
+        if let Some(samples) = sample_counts.next() {
+            if sample_counts.any(|other| other != samples) {
+                return Err("A node's render targets and depth target must all have the same sample count");
+            }
+        }
+        let view_mask = self.multiview.map_or(0, |multiview| multiview.view_mask);
+        if self.pipeline_description.as_ref().is_some_and(|description| description.get_view_mask() != view_mask) {
+            return Err("The pipeline description's view mask must match the node's multiview");
+        }
+
         if self.fill_callback.is_some() {
-            let rt_len = self.render_targets.len();
-            let inputs_len = self.inputs.len();
-            let outputs_len = self.outputs.len();
-            let tagged_resources_len = self.tagged_resources.len();
             Ok(GraphicsPassNode {
                 name: self.name,
                 pipeline_description: self.pipeline_description,
-                render_targets: self.render_targets.into_iter().take(rt_len).collect(),
+                render_targets: self.render_targets,
                 depth_target: self.depth_target,
-                inputs: self.inputs.into_iter().take(inputs_len).collect(),
-                outputs: self.outputs.into_iter().take(outputs_len).collect(),
-                tagged_resources: self.tagged_resources.into_iter().take(tagged_resources_len).collect(),
+                inputs: self.inputs,
+                outputs: self.outputs,
+                input_attachments: self.input_attachments,
+                renderpass_group: self.renderpass_group,
+                dependencies: self.dependencies,
+                vertex_buffers: self.vertex_buffers,
+                index_buffer: self.index_buffer,
                 framebuffer: None,
                 viewport: self.viewport,
                 scissor: self.scissor,
+                command_dynamic_states: self.command_dynamic_states,
+                clear_value: self.clear_value,
+                clear_aspects: self.clear_aspects,
+                retained: self.retained,
+                multiview: self.multiview,
+                priority: self.priority,
+                execute_after: self.execute_after,
                 fill_callback: self.fill_callback.take().unwrap()
             })
         } else {
             Err("PassNodeBuilder was incomplete before building")
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn multiview_covers_the_first_views() {
+        assert_eq!(Multiview::new(1).view_mask, 0b1);
+        assert_eq!(Multiview::new(2).view_mask, 0b11);
+        assert_eq!(Multiview::new(6).view_mask, 0b11_1111);
+        assert_eq!(Multiview::new(2).correlation_mask, 0);
+    }
+
+    #[test]
+    fn multiview_supports_32_views() {
+        let multiview = Multiview::new(32);
+        assert_eq!(multiview.view_mask, u32::MAX);
+        assert_eq!(multiview.get_layer_count(), 32);
+    }
+
+    #[test]
+    #[should_panic(expected = "between 1 and 32 views")]
+    fn multiview_needs_a_view() {
+        Multiview::new(0);
+    }
+
+    #[test]
+    #[should_panic(expected = "between 1 and 32 views")]
+    fn multiview_is_limited_to_32_views() {
+        Multiview::new(33);
+    }
+
+    #[test]
+    fn layer_count_reaches_the_highest_view() {
+        assert_eq!(Multiview::new(1).get_layer_count(), 1);
+        assert_eq!(Multiview::stereo().get_layer_count(), 2);
+        // views needn't be contiguous, but every layer up to the highest is needed
+        let sparse = Multiview { view_mask: 0b1001, correlation_mask: 0 };
+        assert_eq!(sparse.get_layer_count(), 4);
+        let last_only = Multiview { view_mask: 1 << 31, correlation_mask: 0 };
+        assert_eq!(last_only.get_layer_count(), 32);
+    }
+
+    #[test]
+    fn stereo_correlates_both_eyes() {
+        let stereo = Multiview::stereo();
+        assert_eq!(stereo.view_mask, 0b11);
+        assert_eq!(stereo.correlation_mask, 0b11);
+    }
+}
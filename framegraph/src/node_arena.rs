@@ -0,0 +1,293 @@
+//! Recycles the storage of the nodes of dropped Frames, so building a Frame with hundreds of
+//! nodes reuses the previous Frames' allocations instead of making new ones. When a Frame is
+//! dropped its node graph and the binding, attachment, dependency and resource Vecs of its
+//! nodes are cleared and pooled, keeping their capacity; Frame::new and the node builders take
+//! from the pools before allocating. Fill callbacks are still boxed for each node, since their
+//! closures can't be placed in recycled storage.
+//!
+//! The pools belong to the thread Frames are built and dropped on.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+use petgraph::stable_graph::StableDiGraph;
+use api_types::device::DeviceResource;
+use crate::attachment::AttachmentReference;
+use crate::binding::{ResourceBinding, ResourceDependency};
+use crate::pass_type::PassType;
+
+// enough for several frames of a few hundred nodes; anything past this is freed
+const MAX_POOLED_VECS: usize = 4096;
+const MAX_POOLED_GRAPHS: usize = 4;
+
+/// Node storage handed out since the stats were last taken, see FrameStats::node_storage
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct NodeArenaStats {
+    /// Vecs and graphs handed out with the capacity of an earlier Frame's
+    pub reused: u64,
+    /// Vecs and graphs handed out empty, which allocate once they're filled
+    pub fresh: u64,
+    /// Vecs and graphs waiting to be reused
+    pub pooled: usize
+}
+
+struct Pool<T> {
+    free: Vec<T>,
+    limit: usize
+}
+
+impl<T> Pool<T> {
+    fn new(limit: usize) -> Self {
+        Pool {
+            free: Vec::new(),
+            limit
+        }
+    }
+
+    fn take(&mut self, stats: &mut NodeArenaStats, fresh: impl FnOnce() -> T) -> T {
+        match self.free.pop() {
+            Some(storage) => {
+                stats.reused += 1;
+                storage
+            },
+            None => {
+                stats.fresh += 1;
+                fresh()
+            }
+        }
+    }
+
+    /// `storage` must already be empty
+    fn give(&mut self, storage: T) {
+        if self.free.len() < self.limit {
+            self.free.push(storage);
+        }
+    }
+}
+
+fn give_vec<T>(pool: &mut Pool<Vec<T>>, vec: &mut Vec<T>) {
+    // nodes built without a builder may have never allocated
+    if vec.capacity() > 0 {
+        let mut vec = std::mem::take(vec);
+        vec.clear();
+        pool.give(vec);
+    }
+}
+
+struct NodeArena {
+    graphs: Pool<StableDiGraph<PassType, u32>>,
+    bindings: Pool<Vec<ResourceBinding>>,
+    attachments: Pool<Vec<AttachmentReference>>,
+    dependencies: Pool<Vec<ResourceDependency>>,
+    resources: Pool<Vec<Rc<RefCell<DeviceResource>>>>,
+    stats: NodeArenaStats
+}
+
+impl NodeArena {
+    fn new() -> Self {
+        NodeArena {
+            graphs: Pool::new(MAX_POOLED_GRAPHS),
+            bindings: Pool::new(MAX_POOLED_VECS),
+            attachments: Pool::new(MAX_POOLED_VECS),
+            dependencies: Pool::new(MAX_POOLED_VECS),
+            resources: Pool::new(MAX_POOLED_VECS),
+            stats: NodeArenaStats::default()
+        }
+    }
+
+    fn get_pooled(&self) -> usize {
+        self.graphs.free.len() +
+            self.bindings.free.len() +
+            self.attachments.free.len() +
+            self.dependencies.free.len() +
+            self.resources.free.len()
+    }
+}
+
+thread_local! {
+    static NODE_ARENA: RefCell<NodeArena> = RefCell::new(NodeArena::new());
+}
+
+pub(crate) fn take_graph() -> StableDiGraph<PassType, u32> {
+    NODE_ARENA.with(|arena| {
+        let arena = &mut *arena.borrow_mut();
+        arena.graphs.take(&mut arena.stats, StableDiGraph::new)
+    })
+}
+
+pub(crate) fn take_bindings() -> Vec<ResourceBinding> {
+    NODE_ARENA.with(|arena| {
+        let arena = &mut *arena.borrow_mut();
+        arena.bindings.take(&mut arena.stats, Vec::new)
+    })
+}
+
+pub(crate) fn take_attachments() -> Vec<AttachmentReference> {
+    NODE_ARENA.with(|arena| {
+        let arena = &mut *arena.borrow_mut();
+        arena.attachments.take(&mut arena.stats, Vec::new)
+    })
+}
+
+pub(crate) fn take_dependencies() -> Vec<ResourceDependency> {
+    NODE_ARENA.with(|arena| {
+        let arena = &mut *arena.borrow_mut();
+        arena.dependencies.take(&mut arena.stats, Vec::new)
+    })
+}
+
+pub(crate) fn take_resources() -> Vec<Rc<RefCell<DeviceResource>>> {
+    NODE_ARENA.with(|arena| {
+        let arena = &mut *arena.borrow_mut();
+        arena.resources.take(&mut arena.stats, Vec::new)
+    })
+}
+
+/// Storage taken from a graph's nodes, waiting to be emptied and pooled
+#[derive(Default)]
+struct NodeStorage {
+    bindings: Vec<Vec<ResourceBinding>>,
+    attachments: Vec<Vec<AttachmentReference>>,
+    dependencies: Vec<Vec<ResourceDependency>>,
+    resources: Vec<Vec<Rc<RefCell<DeviceResource>>>>
+}
+
+/// Pools the storage of a dropped Frame's graph and its nodes. Dropping the nodes' resources
+/// is the same as dropping the nodes, so this has to wait for the Frame's fence like dropping it
+pub(crate) fn recycle_graph(mut graph: StableDiGraph<PassType, u32>) {
+    let mut storage = NodeStorage::default();
+    for node in graph.node_weights_mut() {
+        match node {
+            PassType::Graphics(gn) => {
+                storage.bindings.push(std::mem::take(&mut gn.inputs));
+                storage.bindings.push(std::mem::take(&mut gn.outputs));
+                storage.bindings.push(std::mem::take(&mut gn.input_attachments));
+                storage.attachments.push(std::mem::take(&mut gn.render_targets));
+                storage.dependencies.push(std::mem::take(&mut gn.dependencies));
+            },
+            PassType::Compute(cn) => {
+                storage.bindings.push(std::mem::take(&mut cn.inputs));
+                storage.bindings.push(std::mem::take(&mut cn.outputs));
+                storage.dependencies.push(std::mem::take(&mut cn.dependencies));
+            },
+            PassType::Copy(cn) => {
+                storage.resources.push(std::mem::take(&mut cn.copy_sources));
+                storage.resources.push(std::mem::take(&mut cn.copy_dests));
+                storage.dependencies.push(std::mem::take(&mut cn.dependencies));
+            },
+            PassType::Present(_) => {}
+        }
+    }
+
+    // The nodes' resources and fill callbacks are dropped before the arena is borrowed, since
+    // dropping a captured value may build or drop nodes itself
+    graph.clear();
+    storage.bindings.iter_mut().for_each(Vec::clear);
+    storage.attachments.iter_mut().for_each(Vec::clear);
+    storage.dependencies.iter_mut().for_each(Vec::clear);
+    storage.resources.iter_mut().for_each(Vec::clear);
+
+    // the arena may already be gone if the Frame is dropped while its thread exits
+    let _ = NODE_ARENA.try_with(|arena| {
+        let arena = &mut *arena.borrow_mut();
+        for mut vec in storage.bindings {
+            give_vec(&mut arena.bindings, &mut vec);
+        }
+        for mut vec in storage.attachments {
+            give_vec(&mut arena.attachments, &mut vec);
+        }
+        for mut vec in storage.dependencies {
+            give_vec(&mut arena.dependencies, &mut vec);
+        }
+        for mut vec in storage.resources {
+            give_vec(&mut arena.resources, &mut vec);
+        }
+        arena.graphs.give(graph);
+    });
+}
+
+/// Returns the stats since they were last taken, and resets them
+pub fn take_stats() -> NodeArenaStats {
+    NODE_ARENA.with(|arena| {
+        let arena = &mut *arena.borrow_mut();
+        let stats = NodeArenaStats {
+            pooled: arena.get_pooled(),
+            ..arena.stats
+        };
+        arena.stats = NodeArenaStats::default();
+        stats
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use super::*;
+    use crate::graphics_pass_node::GraphicsPassNode;
+
+    #[test]
+    fn pooled_vecs_keep_their_capacity() {
+        let mut stats = NodeArenaStats::default();
+        let mut pool: Pool<Vec<u32>> = Pool::new(2);
+
+        let mut vec = pool.take(&mut stats, Vec::new);
+        vec.extend([1, 2, 3]);
+        let capacity = vec.capacity();
+        give_vec(&mut pool, &mut vec);
+        assert!(vec.is_empty());
+
+        let reused = pool.take(&mut stats, Vec::new);
+        assert!(reused.is_empty());
+        assert_eq!(reused.capacity(), capacity);
+        assert_eq!(stats, NodeArenaStats { reused: 1, fresh: 1, pooled: 0 });
+    }
+
+    // re-enters the arena when dropped, like a fill callback capturing a Frame would
+    struct TakesBindingsOnDrop(Rc<Cell<bool>>);
+
+    impl Drop for TakesBindingsOnDrop {
+        fn drop(&mut self) {
+            give_vec_to_arena(take_bindings());
+            self.0.set(true);
+        }
+    }
+
+    fn give_vec_to_arena(mut bindings: Vec<ResourceBinding>) {
+        NODE_ARENA.with(|arena| give_vec(&mut arena.borrow_mut().bindings, &mut bindings));
+    }
+
+    #[test]
+    fn recycling_drops_fill_callbacks_outside_the_arena() {
+        let dropped = Rc::new(Cell::new(false));
+        let mut graph = take_graph();
+        for name in ["first", "second"] {
+            let captured = TakesBindingsOnDrop(dropped.clone());
+            let mut node = GraphicsPassNode::builder(name)
+                .fill_commands(Box::new(move |_render_ctx, _command_buffer| {
+                    let _ = &captured;
+                }))
+                .build()
+                .expect("Failed to build graphics pass node");
+            // so the node has storage worth pooling
+            node.inputs.reserve(4);
+            graph.add_node(PassType::Graphics(node));
+        }
+        take_stats();
+
+        recycle_graph(graph);
+        assert!(dropped.get());
+        // both nodes' inputs and the graph itself
+        assert!(take_stats().pooled >= 3);
+        assert_eq!(take_graph().node_count(), 0);
+    }
+
+    #[test]
+    fn empty_and_excess_vecs_are_not_pooled() {
+        let mut pool: Pool<Vec<u32>> = Pool::new(1);
+        give_vec(&mut pool, &mut Vec::new());
+        assert!(pool.free.is_empty());
+
+        give_vec(&mut pool, &mut vec![1]);
+        give_vec(&mut pool, &mut vec![2]);
+        assert_eq!(pool.free.len(), 1);
+    }
+}
@@ -0,0 +1,679 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+use std::rc::Rc;
+use ash::vk;
+use rayon::prelude::*;
+use api_types::device::DeviceResource;
+use context::vulkan_render_context::VulkanRenderContext;
+use crate::attachment::AttachmentReference;
+use crate::binding::{BindingInfo, ResourceBinding, ResourceLifetime};
+use crate::compute_pass_node::ComputePassNode;
+use crate::copy_pass_node::CopyPassNode;
+use crate::graphics_pass_node::GraphicsPassNode;
+use crate::pass_type::PassType;
+use crate::pipeline::{ComputePipelineDescription, PipelineDescription};
+
+/// Fill callback for pass descriptions, which may be built on another thread
+pub type SendFillCallback = dyn (
+Fn(
+    &VulkanRenderContext,
+    &vk::CommandBuffer
+) + Send
+);
+
+/// A resource binding which refers to its resource by handle (see DeviceResource::get_handle)
+#[derive(Clone, Debug)]
+pub struct HandleBinding {
+    pub handle: u64,
+    pub binding_info: BindingInfo,
+    pub lifetime: ResourceLifetime
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct HandleAttachment {
+    pub handle: u64,
+    pub samples: vk::SampleCountFlags,
+    pub lifetime: ResourceLifetime
+}
+
+/// Resources and pipelines which pass descriptions may refer to. Lives on the thread
+/// which owns the Frame and resolves descriptions into pass nodes.
+#[derive(Default)]
+pub struct PassResourceTable {
+    resources: HashMap<u64, Rc<RefCell<DeviceResource>>>,
+    pipelines: HashMap<String, PipelineDescription>
+}
+
+impl Debug for PassResourceTable {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PassResourceTable")
+            .field("resource count", &self.resources.len())
+            .field("pipeline count", &self.pipelines.len())
+            .finish()
+    }
+}
+
+impl PassResourceTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a resource and returns the handle descriptions should use to refer to it
+    pub fn register_resource(&mut self, resource: Rc<RefCell<DeviceResource>>) -> u64 {
+        let handle = resource.borrow().get_handle();
+        self.resources.insert(handle, resource);
+        handle
+    }
+
+    /// Registers a pipeline description which descriptions refer to by its name
+    pub fn register_pipeline(&mut self, pipeline_description: PipelineDescription) {
+        self.pipelines.insert(pipeline_description.get_name().to_string(), pipeline_description);
+    }
+
+    fn resolve_resource(&self, handle: u64) -> Result<Rc<RefCell<DeviceResource>>, &'static str> {
+        self.resources.get(&handle).cloned().ok_or("Pass description refers to an unregistered resource")
+    }
+
+    fn resolve_binding(&self, binding: &HandleBinding) -> Result<ResourceBinding, &'static str> {
+        Ok(ResourceBinding {
+            resource: self.resolve_resource(binding.handle)?,
+            binding_info: binding.binding_info.clone(),
+            lifetime: binding.lifetime
+        })
+    }
+
+    fn resolve_attachment(&self, attachment: &HandleAttachment) -> Result<AttachmentReference, &'static str> {
+        let mut attachment_reference = AttachmentReference::new(
+            self.resolve_resource(attachment.handle)?,
+            attachment.samples);
+        attachment_reference.lifetime = attachment.lifetime;
+        Ok(attachment_reference)
+    }
+}
+
+/// Send-able equivalent of GraphicsPassNode
+pub struct GraphicsPassDescription {
+    name: String,
+    pipeline: Option<String>,
+    render_targets: Vec<HandleAttachment>,
+    depth_target: Option<HandleAttachment>,
+    inputs: Vec<HandleBinding>,
+    outputs: Vec<HandleBinding>,
+    viewport: Option<vk::Viewport>,
+    scissor: Option<vk::Rect2D>,
+    priority: i32,
+    fill_callback: Box<SendFillCallback>
+}
+
+#[derive(Default)]
+pub struct GraphicsPassDescriptionBuilder {
+    name: String,
+    pipeline: Option<String>,
+    render_targets: Vec<HandleAttachment>,
+    depth_target: Option<HandleAttachment>,
+    inputs: Vec<HandleBinding>,
+    outputs: Vec<HandleBinding>,
+    viewport: Option<vk::Viewport>,
+    scissor: Option<vk::Rect2D>,
+    priority: i32,
+    fill_callback: Option<Box<SendFillCallback>>
+}
+
+impl GraphicsPassDescription {
+    pub fn builder(name: String) -> GraphicsPassDescriptionBuilder {
+        GraphicsPassDescriptionBuilder {
+            name,
+            ..Default::default()
+        }
+    }
+}
+
+impl GraphicsPassDescriptionBuilder {
+    /// Name of a pipeline registered with PassResourceTable::register_pipeline
+    pub fn pipeline(mut self, pipeline_name: &str) -> Self {
+        self.pipeline = Some(pipeline_name.to_string());
+        self
+    }
+
+    pub fn read(mut self, input: HandleBinding) -> Self {
+        self.inputs.push(input);
+        self
+    }
+
+    pub fn write(mut self, output: HandleBinding) -> Self {
+        self.outputs.push(output);
+        self
+    }
+
+    pub fn render_target(mut self, render_target: HandleAttachment) -> Self {
+        self.render_targets.push(render_target);
+        self
+    }
+
+    pub fn depth_target(mut self, depth_target: HandleAttachment) -> Self {
+        self.depth_target = Some(depth_target);
+        self
+    }
+
+    pub fn viewport(mut self, viewport: vk::Viewport) -> Self {
+        self.viewport = Some(viewport);
+        self
+    }
+
+    pub fn scissor(mut self, scissor: vk::Rect2D) -> Self {
+        self.scissor = Some(scissor);
+        self
+    }
+
+    pub fn priority(mut self, priority: i32) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    pub fn fill_commands(mut self, fill_callback: Box<SendFillCallback>) -> Self {
+        self.fill_callback = Some(fill_callback);
+        self
+    }
+
+    pub fn build(self) -> Result<GraphicsPassDescription, &'static str> {
+        match self.fill_callback {
+            Some(fill_callback) => {
+                Ok(GraphicsPassDescription {
+                    name: self.name,
+                    pipeline: self.pipeline,
+                    render_targets: self.render_targets,
+                    depth_target: self.depth_target,
+                    inputs: self.inputs,
+                    outputs: self.outputs,
+                    viewport: self.viewport,
+                    scissor: self.scissor,
+                    priority: self.priority,
+                    fill_callback
+                })
+            },
+            None => {
+                Err("GraphicsPassDescriptionBuilder was incomplete before building")
+            }
+        }
+    }
+}
+
+/// Send-able equivalent of CopyPassNode
+pub struct CopyPassDescription {
+    name: String,
+    copy_sources: Vec<u64>,
+    copy_dests: Vec<u64>,
+    priority: i32,
+    fill_callback: Box<SendFillCallback>
+}
+
+#[derive(Default)]
+pub struct CopyPassDescriptionBuilder {
+    name: String,
+    copy_sources: Vec<u64>,
+    copy_dests: Vec<u64>,
+    priority: i32,
+    fill_callback: Option<Box<SendFillCallback>>
+}
+
+impl CopyPassDescription {
+    pub fn builder(name: String) -> CopyPassDescriptionBuilder {
+        CopyPassDescriptionBuilder {
+            name,
+            ..Default::default()
+        }
+    }
+}
+
+impl CopyPassDescriptionBuilder {
+    pub fn copy_src(mut self, copy_src: u64) -> Self {
+        self.copy_sources.push(copy_src);
+        self
+    }
+
+    pub fn copy_dst(mut self, copy_dst: u64) -> Self {
+        self.copy_dests.push(copy_dst);
+        self
+    }
+
+    pub fn priority(mut self, priority: i32) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    pub fn fill_commands(mut self, fill_callback: Box<SendFillCallback>) -> Self {
+        self.fill_callback = Some(fill_callback);
+        self
+    }
+
+    pub fn build(self) -> Result<CopyPassDescription, &'static str> {
+        match self.fill_callback {
+            Some(fill_callback) => {
+                Ok(CopyPassDescription {
+                    name: self.name,
+                    copy_sources: self.copy_sources,
+                    copy_dests: self.copy_dests,
+                    priority: self.priority,
+                    fill_callback
+                })
+            },
+            None => {
+                Err("CopyPassDescriptionBuilder was incomplete before building")
+            }
+        }
+    }
+}
+
+/// Send-able subset of ComputeDispatch; indirect dispatches refer to a resource and have
+/// to be added to the resolved node instead
+#[derive(Copy, Clone, Debug)]
+enum DescriptionDispatch {
+    Groups { x: u32, y: u32, z: u32 },
+    Extent(vk::Extent3D)
+}
+
+/// Send-able equivalent of ComputePassNode
+pub struct ComputePassDescription {
+    name: String,
+    pipeline: ComputePipelineDescription,
+    inputs: Vec<HandleBinding>,
+    outputs: Vec<HandleBinding>,
+    dispatch: Option<DescriptionDispatch>,
+    priority: i32,
+    fill_callback: Option<Box<SendFillCallback>>
+}
+
+#[derive(Default)]
+pub struct ComputePassDescriptionBuilder {
+    name: String,
+    pipeline: Option<ComputePipelineDescription>,
+    inputs: Vec<HandleBinding>,
+    outputs: Vec<HandleBinding>,
+    dispatch: Option<DescriptionDispatch>,
+    priority: i32,
+    fill_callback: Option<Box<SendFillCallback>>
+}
+
+impl ComputePassDescription {
+    pub fn builder(name: String) -> ComputePassDescriptionBuilder {
+        ComputePassDescriptionBuilder {
+            name,
+            ..Default::default()
+        }
+    }
+}
+
+impl ComputePassDescriptionBuilder {
+    pub fn pipeline_description(mut self, pipeline_description: ComputePipelineDescription) -> Self {
+        self.pipeline = Some(pipeline_description);
+        self
+    }
+
+    pub fn input(mut self, input: HandleBinding) -> Self {
+        self.inputs.push(input);
+        self
+    }
+
+    pub fn output(mut self, output: HandleBinding) -> Self {
+        self.outputs.push(output);
+        self
+    }
+
+    pub fn dispatch(mut self, x: u32, y: u32, z: u32) -> Self {
+        self.dispatch = Some(DescriptionDispatch::Groups { x, y, z });
+        self
+    }
+
+    /// See ComputeDispatch::Extent
+    pub fn dispatch_for_extent(mut self, extent: vk::Extent3D) -> Self {
+        self.dispatch = Some(DescriptionDispatch::Extent(extent));
+        self
+    }
+
+    pub fn priority(mut self, priority: i32) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Optional with a dispatch, see ComputePassNodeBuilder::fill_commands
+    pub fn fill_commands(mut self, fill_callback: Box<SendFillCallback>) -> Self {
+        self.fill_callback = Some(fill_callback);
+        self
+    }
+
+    pub fn build(self) -> Result<ComputePassDescription, &'static str> {
+        if self.fill_callback.is_none() && self.dispatch.is_none() {
+            return Err("ComputePassDescriptionBuilder was incomplete before building");
+        }
+        let pipeline = self.pipeline.ok_or("Compute pass descriptions require a pipeline description")?;
+        Ok(ComputePassDescription {
+            name: self.name,
+            pipeline,
+            inputs: self.inputs,
+            outputs: self.outputs,
+            dispatch: self.dispatch,
+            priority: self.priority,
+            fill_callback: self.fill_callback
+        })
+    }
+}
+
+/// A pass built without touching any Rc/RefCell state, so it can be constructed on a
+/// worker thread and sent to the thread owning the Frame to be resolved into a PassType
+pub enum PassDescription {
+    Graphics(GraphicsPassDescription),
+    Copy(CopyPassDescription),
+    Compute(ComputePassDescription)
+}
+
+impl Debug for PassDescription {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PassDescription")
+            .field("name", &self.get_name())
+            .field("reads", &self.get_reads())
+            .field("writes", &self.get_writes())
+            .finish()
+    }
+}
+
+impl PassDescription {
+    pub fn get_name(&self) -> &str {
+        match self {
+            PassDescription::Graphics(gd) => { &gd.name },
+            PassDescription::Copy(cd) => { &cd.name },
+            PassDescription::Compute(cd) => { &cd.name }
+        }
+    }
+
+    /// Handles read by this pass, matching PassNode::get_reads once resolved
+    pub fn get_reads(&self) -> Vec<u64> {
+        match self {
+            PassDescription::Graphics(gd) => {
+                let mut reads: Vec<u64> = gd.inputs.iter().map(|input| input.handle).collect();
+                reads.extend(gd.render_targets.iter().map(|rt| rt.handle));
+                reads.extend(gd.depth_target.iter().map(|dt| dt.handle));
+                reads
+            },
+            PassDescription::Copy(cd) => { cd.copy_sources.clone() },
+            PassDescription::Compute(cd) => { cd.inputs.iter().map(|input| input.handle).collect() }
+        }
+    }
+
+    /// Handles written by this pass, matching PassNode::get_writes once resolved
+    pub fn get_writes(&self) -> Vec<u64> {
+        match self {
+            PassDescription::Graphics(gd) => {
+                let mut writes: Vec<u64> = gd.outputs.iter().map(|output| output.handle).collect();
+                writes.extend(gd.render_targets.iter().map(|rt| rt.handle));
+                writes.extend(gd.depth_target.iter().map(|dt| dt.handle));
+                writes
+            },
+            PassDescription::Copy(cd) => { cd.copy_dests.clone() },
+            PassDescription::Compute(cd) => { cd.outputs.iter().map(|output| output.handle).collect() }
+        }
+    }
+
+    /// Builds one description per input on the rayon thread pool. Expensive per-pass setup
+    /// (descriptor math, vertex data prep, etc.) belongs in `build`; the returned descriptions
+    /// are in the same order as `inputs` regardless of which worker built them
+    pub fn build_parallel<T, F>(inputs: Vec<T>, build: F) -> Vec<PassDescription>
+        where T: Send, F: Fn(T) -> PassDescription + Sync + Send {
+        inputs.into_par_iter().map(build).collect()
+    }
+
+    pub fn resolve(self, table: &PassResourceTable) -> Result<PassType, &'static str> {
+        match self {
+            PassDescription::Graphics(gd) => {
+                let mut builder = GraphicsPassNode::builder(gd.name)
+                    .priority(gd.priority);
+                if let Some(pipeline_name) = &gd.pipeline {
+                    let pipeline_description = table.pipelines.get(pipeline_name)
+                        .ok_or("Pass description refers to an unregistered pipeline")?;
+                    builder = builder.pipeline_description(pipeline_description.clone());
+                }
+                for rt in &gd.render_targets {
+                    builder = builder.render_target(table.resolve_attachment(rt)?);
+                }
+                if let Some(dt) = &gd.depth_target {
+                    builder = builder.depth_target(table.resolve_attachment(dt)?);
+                }
+                for input in &gd.inputs {
+                    builder = builder.read(table.resolve_binding(input)?);
+                }
+                for output in &gd.outputs {
+                    builder = builder.write(table.resolve_binding(output)?);
+                }
+                if let Some(viewport) = gd.viewport {
+                    builder = builder.viewport(viewport);
+                }
+                if let Some(scissor) = gd.scissor {
+                    builder = builder.scissor(scissor);
+                }
+
+                Ok(PassType::Graphics(builder.fill_commands(gd.fill_callback).build()?))
+            },
+            PassDescription::Copy(cd) => {
+                let mut builder = CopyPassNode::builder(cd.name)
+                    .priority(cd.priority);
+                for source in &cd.copy_sources {
+                    builder = builder.copy_src(table.resolve_resource(*source)?);
+                }
+                for dest in &cd.copy_dests {
+                    builder = builder.copy_dst(table.resolve_resource(*dest)?);
+                }
+
+                Ok(PassType::Copy(builder.fill_commands(cd.fill_callback).build()?))
+            },
+            PassDescription::Compute(cd) => {
+                let mut builder = ComputePassNode::builder(cd.name)
+                    .pipeline_description(cd.pipeline)
+                    .priority(cd.priority);
+                for input in &cd.inputs {
+                    builder = builder.input(table.resolve_binding(input)?);
+                }
+                for output in &cd.outputs {
+                    builder = builder.output(table.resolve_binding(output)?);
+                }
+                builder = match cd.dispatch {
+                    Some(DescriptionDispatch::Groups { x, y, z }) => { builder.dispatch(x, y, z) },
+                    Some(DescriptionDispatch::Extent(extent)) => { builder.dispatch_for_extent(extent) },
+                    None => { builder }
+                };
+                if let Some(fill_callback) = cd.fill_callback {
+                    builder = builder.fill_commands(fill_callback);
+                }
+
+                Ok(PassType::Compute(builder.build()?))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use ash::vk;
+    use crate::binding::{BindingInfo, BindingType, BufferBindingInfo, ImageBindingInfo};
+    use super::*;
+
+    fn assert_send<T: Send>() {}
+
+    fn uniform_binding(handle: u64) -> HandleBinding {
+        HandleBinding {
+            handle,
+            binding_info: BindingInfo {
+                binding_type: BindingType::Buffer(BufferBindingInfo::new(0, 64)),
+                set: 0,
+                slot: 0,
+                stage: vk::PipelineStageFlags::VERTEX_SHADER,
+                access: vk::AccessFlags::SHADER_READ
+            },
+            lifetime: ResourceLifetime::Persistent
+        }
+    }
+
+    #[test]
+    fn descriptions_are_send() {
+        assert_send::<PassDescription>();
+        assert_send::<GraphicsPassDescriptionBuilder>();
+        assert_send::<CopyPassDescriptionBuilder>();
+        assert_send::<ComputePassDescriptionBuilder>();
+    }
+
+    fn storage_binding(handle: u64) -> HandleBinding {
+        HandleBinding {
+            handle,
+            binding_info: BindingInfo {
+                binding_type: BindingType::Buffer(BufferBindingInfo::new(0, 256)),
+                set: 0,
+                slot: 1,
+                stage: vk::PipelineStageFlags::COMPUTE_SHADER,
+                access: vk::AccessFlags::SHADER_WRITE
+            },
+            lifetime: ResourceLifetime::Persistent
+        }
+    }
+
+    #[test]
+    fn compute_description_reads_inputs_and_writes_outputs() {
+        let description = PassDescription::Compute(ComputePassDescription::builder("cull".to_string())
+            .pipeline_description(ComputePipelineDescription::new("cull.comp.spv"))
+            .input(uniform_binding(1))
+            .input(uniform_binding(2))
+            .output(storage_binding(3))
+            .dispatch(8, 8, 1)
+            .build()
+            .expect("Failed to build compute pass description"));
+
+        assert_eq!(description.get_name(), "cull");
+        assert_eq!(description.get_reads(), vec![1, 2]);
+        assert_eq!(description.get_writes(), vec![3]);
+    }
+
+    #[test]
+    fn compute_description_requires_pipeline_and_work() {
+        let without_pipeline = ComputePassDescription::builder("no_pipeline".to_string())
+            .dispatch(1, 1, 1)
+            .build();
+        assert!(without_pipeline.is_err());
+
+        let without_work = ComputePassDescription::builder("no_work".to_string())
+            .pipeline_description(ComputePipelineDescription::new("cull.comp.spv"))
+            .build();
+        assert!(without_work.is_err());
+    }
+
+    #[test]
+    fn resolving_unregistered_resources_fails() {
+        let table = PassResourceTable::new();
+        let compute = PassDescription::Compute(ComputePassDescription::builder("compute".to_string())
+            .pipeline_description(ComputePipelineDescription::new("cull.comp.spv"))
+            .output(storage_binding(7))
+            .dispatch_for_extent(vk::Extent3D { width: 64, height: 64, depth: 1 })
+            .build()
+            .expect("Failed to build compute pass description"));
+        assert_eq!(compute.resolve(&table).err(), Some("Pass description refers to an unregistered resource"));
+
+        let copy = PassDescription::Copy(CopyPassDescription::builder("copy".to_string())
+            .copy_src(7)
+            .fill_commands(Box::new(|_render_ctx, _command_buffer| {}))
+            .build()
+            .expect("Failed to build copy pass description"));
+        assert_eq!(copy.resolve(&table).err(), Some("Pass description refers to an unregistered resource"));
+    }
+
+    #[test]
+    fn resolving_unregistered_pipelines_fails() {
+        let table = PassResourceTable::new();
+        let graphics = PassDescription::Graphics(GraphicsPassDescription::builder("graphics".to_string())
+            .pipeline("missing_pipeline")
+            .fill_commands(Box::new(|_render_ctx, _command_buffer| {}))
+            .build()
+            .expect("Failed to build graphics pass description"));
+        assert_eq!(graphics.resolve(&table).err(), Some("Pass description refers to an unregistered pipeline"));
+    }
+
+    #[test]
+    fn build_descriptions_on_multiple_threads() {
+        let target_handle: u64 = 100;
+        let workers: Vec<thread::JoinHandle<PassDescription>> = (0..4u64).map(|i| {
+            thread::spawn(move || {
+                if i % 2 == 0 {
+                    PassDescription::Graphics(GraphicsPassDescription::builder(format!("graphics_{}", i))
+                        .pipeline("test_pipeline")
+                        .read(uniform_binding(i))
+                        .render_target(HandleAttachment {
+                            handle: target_handle,
+                            samples: vk::SampleCountFlags::TYPE_1,
+                            lifetime: ResourceLifetime::Persistent
+                        })
+                        .fill_commands(Box::new(|_render_ctx, _command_buffer| {}))
+                        .build()
+                        .expect("Failed to build graphics pass description"))
+                } else {
+                    PassDescription::Copy(CopyPassDescription::builder(format!("copy_{}", i))
+                        .copy_src(target_handle)
+                        .copy_dst(i)
+                        .fill_commands(Box::new(|_render_ctx, _command_buffer| {}))
+                        .build()
+                        .expect("Failed to build copy pass description"))
+                }
+            })
+        }).collect();
+
+        // assemble on the main thread in submission order
+        let descriptions: Vec<PassDescription> = workers.into_iter()
+            .map(|worker| worker.join().expect("Pass description worker panicked"))
+            .collect();
+
+        assert_eq!(descriptions.len(), 4);
+        for (i, description) in descriptions.iter().enumerate() {
+            let i = i as u64;
+            if i % 2 == 0 {
+                assert_eq!(description.get_name(), format!("graphics_{}", i));
+                assert_eq!(description.get_reads(), vec![i, target_handle]);
+                assert_eq!(description.get_writes(), vec![target_handle]);
+            } else {
+                assert_eq!(description.get_name(), format!("copy_{}", i));
+                assert_eq!(description.get_reads(), vec![target_handle]);
+                assert_eq!(description.get_writes(), vec![i]);
+            }
+        }
+    }
+
+    #[test]
+    fn build_parallel_preserves_order() {
+        let descriptions = PassDescription::build_parallel((0..64u64).collect(), |i| {
+            PassDescription::Copy(CopyPassDescription::builder(format!("copy_{}", i))
+                .copy_src(i)
+                .copy_dst(i + 1)
+                .fill_commands(Box::new(|_render_ctx, _command_buffer| {}))
+                .build()
+                .expect("Failed to build copy pass description"))
+        });
+
+        for (i, description) in descriptions.iter().enumerate() {
+            assert_eq!(description.get_name(), format!("copy_{}", i));
+            assert_eq!(description.get_reads(), vec![i as u64]);
+        }
+    }
+
+    #[test]
+    fn incomplete_descriptions_fail_to_build() {
+        let result = GraphicsPassDescription::builder("incomplete".to_string())
+            .read(HandleBinding {
+                handle: 0,
+                binding_info: BindingInfo {
+                    binding_type: BindingType::Image(ImageBindingInfo::new(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)),
+                    set: 0,
+                    slot: 1,
+                    stage: vk::PipelineStageFlags::FRAGMENT_SHADER,
+                    access: vk::AccessFlags::SHADER_READ
+                },
+                lifetime: ResourceLifetime::Persistent
+            })
+            .build();
+        assert!(result.is_err());
+    }
+}
@@ -1,5 +1,8 @@
 use std::fmt::{Debug};
 use ash::vk;
+use petgraph::stable_graph::NodeIndex;
+use api_types::name::Name;
+use crate::binding::ResourceDependency;
 use context::vulkan_render_context::VulkanRenderContext;
 
 pub type FillCallback = dyn (
@@ -9,10 +12,43 @@ Fn(
 )
 );
 
+/// Identifies a node added to a Frame (see Frame::add_node), for ordering other nodes after it,
+/// changing or disabling it before the Frame is recorded, or finding its PassTiming. Handles are
+/// only meaningful to the Frame which returned them
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct PassHandle(NodeIndex);
+
+impl PassHandle {
+    pub(crate) fn new(index: NodeIndex) -> Self {
+        PassHandle(index)
+    }
+
+    pub(crate) fn get_index(&self) -> NodeIndex {
+        self.0
+    }
+}
+
 pub trait PassNode {
     fn get_name(&self) -> &str;
 
+    /// The name as used for debug labels, allocation tags and PassTiming, see Name
+    fn get_interned_name(&self) -> Name { Name::new(self.get_name()) }
+
     fn get_reads(&self) -> Vec<u64>;
 
     fn get_writes(&self) -> Vec<u64>;
+
+    /// Resources the node uses without binding them, see ResourceDependency
+    fn get_dependencies(&self) -> &[ResourceDependency] { &[] }
+
+    /// Resources this node's bindings and attachments declare as ResourceLifetime::Transient
+    fn get_transients(&self) -> Vec<u64> { Vec::new() }
+
+    /// Among nodes whose dependencies have all been scheduled, higher priorities execute
+    /// first; ties are broken by the order nodes were added to the Frame
+    fn get_priority(&self) -> i32 { 0 }
+
+    /// Nodes of the same Frame this node executes after without sharing any resources with
+    /// them, see PassNodeBuilder::after
+    fn get_execute_after(&self) -> &[PassHandle] { &[] }
 }
\ No newline at end of file
@@ -0,0 +1,229 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use ash::vk;
+use ash::vk::Handle;
+use gpu_allocator::MemoryLocation;
+use api_types::buffer::BufferCreateInfo;
+use api_types::device::{DeviceResource, DeviceWrapper};
+use api_types::image::{ImageCreateInfo, ImageType};
+use crate::binding::{BindingInfo, BindingType, BufferBindingInfo, ImageBindingInfo, ResourceBinding};
+
+/// A pair of images which alternate between being written and read, e.g. for iterative
+/// compute passes whose results are sampled by the passes following them. Each pass should
+/// bind [`read_binding`](Self::read_binding) and [`write_binding`](Self::write_binding) and
+/// the images then be [`flip`](Self::flip)ped; the frame graph orders the passes and inserts
+/// the barriers between them
+pub struct PingPongImages {
+    images: [Rc<RefCell<DeviceResource>>; 2],
+    write_index: usize
+}
+
+impl std::fmt::Debug for PingPongImages {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PingPongImages")
+            .field("read", &self.get_read().borrow().get_handle())
+            .field("write", &self.get_write().borrow().get_handle())
+            .finish()
+    }
+}
+
+impl PingPongImages {
+    pub fn new(
+        device: Rc<RefCell<DeviceWrapper>>,
+        extent: vk::Extent2D,
+        format: vk::Format,
+        name: &str) -> Self {
+
+        let images = [0, 1].map(|index| {
+            let create_info = ImageCreateInfo::new(
+                vk::ImageCreateInfo::builder()
+                    .image_type(vk::ImageType::TYPE_2D)
+                    .format(format)
+                    .initial_layout(vk::ImageLayout::UNDEFINED)
+                    .extent(vk::Extent3D {
+                        width: extent.width,
+                        height: extent.height,
+                        depth: 1
+                    })
+                    .samples(vk::SampleCountFlags::TYPE_1)
+                    .usage(vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_SRC)
+                    .mip_levels(1)
+                    .array_layers(1)
+                    .build(),
+                format!("{}_{}", name, index),
+                ImageType::Color);
+
+            let mut image = DeviceWrapper::create_image(
+                device.clone(),
+                &create_info,
+                MemoryLocation::GpuOnly);
+
+            let sampler = unsafe {
+                let sampler_create = vk::SamplerCreateInfo::builder()
+                    .mag_filter(vk::Filter::LINEAR)
+                    .min_filter(vk::Filter::LINEAR)
+                    .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+                    .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                    .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                    .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                    .build();
+
+                device.borrow().get().create_sampler(&sampler_create, None)
+                    .expect("Failed to create sampler for ping-pong image")
+            };
+            device.borrow().set_debug_name(vk::ObjectType::SAMPLER, sampler.as_raw(), &format!("{}_{}_sampler", name, index));
+            image.get_image_mut().sampler = Some(sampler);
+
+            Rc::new(RefCell::new(image))
+        });
+
+        PingPongImages {
+            images,
+            write_index: 0
+        }
+    }
+
+    /// The image holding the previous pass's results
+    pub fn get_read(&self) -> &Rc<RefCell<DeviceResource>> {
+        &self.images[1 - self.write_index]
+    }
+
+    /// The image the current pass writes
+    pub fn get_write(&self) -> &Rc<RefCell<DeviceResource>> {
+        &self.images[self.write_index]
+    }
+
+    /// The most recently written image, once the last pass has been flipped
+    pub fn get_latest(&self) -> &Rc<RefCell<DeviceResource>> {
+        self.get_read()
+    }
+
+    /// Samples the read image with its sampler
+    pub fn read_binding(&self, set: u64, slot: u32, stage: vk::PipelineStageFlags) -> ResourceBinding {
+        ResourceBinding::new(
+            self.get_read().clone(),
+            BindingInfo {
+                binding_type: BindingType::Image(ImageBindingInfo::new(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)),
+                set,
+                slot,
+                stage,
+                access: vk::AccessFlags::SHADER_READ
+            })
+    }
+
+    /// Binds the write image as a storage image
+    pub fn write_binding(&self, set: u64, slot: u32, stage: vk::PipelineStageFlags) -> ResourceBinding {
+        ResourceBinding::new(
+            self.get_write().clone(),
+            BindingInfo {
+                binding_type: BindingType::Image(ImageBindingInfo::new(vk::ImageLayout::GENERAL)),
+                set,
+                slot,
+                stage,
+                access: vk::AccessFlags::SHADER_WRITE
+            })
+    }
+
+    /// Swaps the read and write images
+    pub fn flip(&mut self) {
+        self.write_index = 1 - self.write_index;
+    }
+}
+
+/// A pair of storage buffers which alternate between being written and read, e.g. for the
+/// iterations of a reduction where each pass shrinks the previous pass's results. Used the same
+/// way as [`PingPongImages`]
+pub struct PingPongBuffers {
+    buffers: [Rc<RefCell<DeviceResource>>; 2],
+    size: vk::DeviceSize,
+    write_index: usize
+}
+
+impl std::fmt::Debug for PingPongBuffers {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PingPongBuffers")
+            .field("read", &self.get_read().borrow().get_handle())
+            .field("write", &self.get_write().borrow().get_handle())
+            .field("size", &self.size)
+            .finish()
+    }
+}
+
+impl PingPongBuffers {
+    /// Two buffers of `size` bytes
+    pub fn new(
+        device: Rc<RefCell<DeviceWrapper>>,
+        size: vk::DeviceSize,
+        name: &str) -> Self {
+
+        let buffers = [0, 1].map(|index| {
+            let create_info = BufferCreateInfo::new(
+                vk::BufferCreateInfo::builder()
+                    .size(size)
+                    .usage(vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::TRANSFER_SRC | vk::BufferUsageFlags::TRANSFER_DST)
+                    .sharing_mode(vk::SharingMode::EXCLUSIVE)
+                    .build(),
+                format!("{}_{}", name, index));
+
+            Rc::new(RefCell::new(DeviceWrapper::create_buffer(
+                device.clone(),
+                &create_info,
+                MemoryLocation::GpuOnly)))
+        });
+
+        PingPongBuffers {
+            buffers,
+            size,
+            write_index: 0
+        }
+    }
+
+    pub fn get_size(&self) -> vk::DeviceSize { self.size }
+
+    /// The buffer holding the previous pass's results
+    pub fn get_read(&self) -> &Rc<RefCell<DeviceResource>> {
+        &self.buffers[1 - self.write_index]
+    }
+
+    /// The buffer the current pass writes
+    pub fn get_write(&self) -> &Rc<RefCell<DeviceResource>> {
+        &self.buffers[self.write_index]
+    }
+
+    /// The most recently written buffer, once the last pass has been flipped
+    pub fn get_latest(&self) -> &Rc<RefCell<DeviceResource>> {
+        self.get_read()
+    }
+
+    /// Binds the whole read buffer as a storage buffer which is only read
+    pub fn read_binding(&self, set: u64, slot: u32, stage: vk::PipelineStageFlags) -> ResourceBinding {
+        Self::binding(self.get_read(), set, slot, stage, vk::AccessFlags::SHADER_READ)
+    }
+
+    /// Binds the whole write buffer as a storage buffer
+    pub fn write_binding(&self, set: u64, slot: u32, stage: vk::PipelineStageFlags) -> ResourceBinding {
+        Self::binding(self.get_write(), set, slot, stage, vk::AccessFlags::SHADER_WRITE)
+    }
+
+    fn binding(
+        buffer: &Rc<RefCell<DeviceResource>>,
+        set: u64,
+        slot: u32,
+        stage: vk::PipelineStageFlags,
+        access: vk::AccessFlags) -> ResourceBinding {
+        ResourceBinding::new(
+            buffer.clone(),
+            BindingInfo {
+                binding_type: BindingType::Buffer(BufferBindingInfo::new(0, vk::WHOLE_SIZE)),
+                set,
+                slot,
+                stage,
+                access
+            })
+    }
+
+    /// Swaps the read and write buffers
+    pub fn flip(&mut self) {
+        self.write_index = 1 - self.write_index;
+    }
+}
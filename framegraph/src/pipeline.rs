@@ -7,43 +7,171 @@ use std::rc::Rc;
 
 use ash::vk;
 use ash::vk::Handle;
-use api_types::device::{DevicePipeline, DeviceWrapper};
+use api_types::device::{DeviceDescriptorSetLayout, DevicePipeline, DevicePipelineLayout, DeviceWrapper};
+use api_types::device_capabilities::DeviceFeatures;
 use context::render_context::RenderContext;
 
+use crate::binding::BindingFrequency;
+use crate::graph_debug::graph_debug;
 use crate::shader::{Shader, ShaderManager};
+use crate::shader_variant::ShaderVariant;
+use crate::uniform_layout::UniformBlockLayout;
 
 extern crate context;
 use context::vulkan_render_context::VulkanRenderContext;
 use profiling::enter_span;
 
-#[derive(Copy, Clone)]
+/// How a render target's existing color is combined with a pass's output
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum BlendType
 {
     None,
+    /// Straight alpha, e.g. for imgui and other UI
     Alpha,
-    Transparent
+    /// Output is scaled by its alpha and added to the target, e.g. for particles
+    Additive,
+    /// Output color is already multiplied by its alpha
+    Premultiplied,
+    Custom(BlendEquation)
 }
 
-#[derive(Copy, Clone)]
+/// The parameters of a VkPipelineColorBlendAttachmentState with blending enabled. Constant
+/// blend factors read the BLEND_CONSTANTS dynamic state, which the pipeline description
+/// should include
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct BlendEquation
+{
+    pub src_color_blend_factor: vk::BlendFactor,
+    pub dst_color_blend_factor: vk::BlendFactor,
+    pub color_blend_op: vk::BlendOp,
+    pub src_alpha_blend_factor: vk::BlendFactor,
+    pub dst_alpha_blend_factor: vk::BlendFactor,
+    pub alpha_blend_op: vk::BlendOp,
+    pub color_write_mask: vk::ColorComponentFlags
+}
+
+impl Default for BlendEquation
+{
+    /// Writes the output unchanged
+    fn default() -> Self {
+        BlendEquation {
+            src_color_blend_factor: vk::BlendFactor::ONE,
+            dst_color_blend_factor: vk::BlendFactor::ZERO,
+            color_blend_op: vk::BlendOp::ADD,
+            src_alpha_blend_factor: vk::BlendFactor::ONE,
+            dst_alpha_blend_factor: vk::BlendFactor::ZERO,
+            alpha_blend_op: vk::BlendOp::ADD,
+            color_write_mask: vk::ColorComponentFlags::RGBA
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum DepthStencilType
 {
     Disable,
-    Enable
+    /// Tests and writes depth, keeping fragments at or in front of the stored depth
+    Enable,
+    /// Tests and writes depth with the given compare op
+    EnableWithCompare(vk::CompareOp)
+}
+
+impl DepthStencilType
+{
+    /// Depth testing for reverse-Z projections (see util::camera::reverse_z_perspective), where
+    /// the near plane is at depth 1 and the far plane at 0. Depth targets are cleared to 0.0
+    pub const REVERSE_Z: DepthStencilType = DepthStencilType::EnableWithCompare(vk::CompareOp::GREATER_OR_EQUAL);
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DepthBias
+{
+    Disabled,
+    /// A non-zero clamp needs DeviceFeatures::depth_bias_clamp
+    Static {
+        constant_factor: f32,
+        clamp: f32,
+        slope_factor: f32
+    },
+    /// Set with vkCmdSetDepthBias while recording, e.g. per shadow cascade. The DEPTH_BIAS
+    /// dynamic state is added to the pipeline
+    Dynamic
+}
+
+impl Hash for DepthBias
+{
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        if let DepthBias::Static { constant_factor, clamp, slope_factor } = self {
+            constant_factor.to_bits().hash(state);
+            clamp.to_bits().hash(state);
+            slope_factor.to_bits().hash(state);
+        }
+    }
+}
+
+/// The default draws filled, two-sided triangles without depth bias
+#[derive(Copy, Clone, Debug, PartialEq, Hash)]
+pub struct RasterizationState
+{
+    pub cull_mode: vk::CullModeFlags,
+    pub front_face: vk::FrontFace,
+    /// Modes other than FILL need DeviceFeatures::fill_mode_non_solid
+    pub polygon_mode: vk::PolygonMode,
+    pub depth_bias: DepthBias,
+    /// Needs DeviceFeatures::depth_clamp
+    pub depth_clamp: bool
+}
+
+impl Default for RasterizationState
+{
+    fn default() -> Self {
+        RasterizationState {
+            cull_mode: vk::CullModeFlags::NONE,
+            front_face: vk::FrontFace::CLOCKWISE,
+            polygon_mode: vk::PolygonMode::FILL,
+            depth_bias: DepthBias::Disabled,
+            depth_clamp: false
+        }
+    }
 }
 
-#[derive(Copy, Clone)]
-pub enum RasterizationType
+impl RasterizationState
 {
-    Standard
+    pub fn wireframe() -> Self {
+        RasterizationState {
+            polygon_mode: vk::PolygonMode::LINE,
+            ..Default::default()
+        }
+    }
+}
+
+/// The attachments of one subpass of the renderpass a pipeline is used in. A pipeline can
+/// only be used with renderpasses compatible with the one it was created for, which depends
+/// on these for every subpass
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct SubpassTargets {
+    pub color_formats: Vec<vk::Format>,
+    pub depth_format: Option<vk::Format>,
+    pub input_formats: Vec<vk::Format>,
+    /// Shared by the color and depth attachments
+    pub samples: vk::SampleCountFlags,
+    pub view_mask: u32
 }
 
+#[derive(Clone)]
 pub struct PipelineDescription
 {
     vertex_input: vk::PipelineVertexInputStateCreateInfo,
     dynamic_states: Vec<vk::DynamicState>,
-    rasterization: RasterizationType,
+    rasterization: RasterizationState,
     depth_stencil: DepthStencilType,
     blend: BlendType,
+    /// Overrides of `blend` for individual render targets, indexed by render target
+    target_blends: Vec<Option<BlendType>>,
+    topology: vk::PrimitiveTopology,
+    primitive_restart: bool,
+    view_mask: u32,
     name: String,
     vertex_shader: Rc<RefCell<Shader>>,
     fragment_shader: Rc<RefCell<Shader>>
@@ -52,9 +180,94 @@ pub struct PipelineDescription
 impl Hash for PipelineDescription
 {
     fn hash<H: Hasher>(&self, state: &mut H) {
-        // TODO: this is an inadequate hash
-        // will need to actually use some pipeline state for a better hash
+        // the shader manager caches modules by path, so a module handle identifies its shader
+        PipelineStateKey {
+            name: &self.name,
+            vertex_input: &self.vertex_input,
+            dynamic_states: &self.dynamic_states,
+            rasterization: self.rasterization,
+            depth_stencil: self.depth_stencil,
+            blend: self.blend,
+            target_blends: &self.target_blends,
+            topology: self.topology,
+            primitive_restart: self.primitive_restart,
+            view_mask: self.view_mask,
+            shader_modules: [
+                self.vertex_shader.borrow().shader.shader_module,
+                self.fragment_shader.borrow().shader.shader_module]
+        }.hash(state);
+    }
+}
+
+/// Everything in a PipelineDescription which affects the created pipeline, borrowed so it
+/// can be hashed without the shaders themselves
+struct PipelineStateKey<'a>
+{
+    name: &'a str,
+    vertex_input: &'a vk::PipelineVertexInputStateCreateInfo,
+    dynamic_states: &'a [vk::DynamicState],
+    rasterization: RasterizationState,
+    depth_stencil: DepthStencilType,
+    blend: BlendType,
+    target_blends: &'a [Option<BlendType>],
+    topology: vk::PrimitiveTopology,
+    primitive_restart: bool,
+    view_mask: u32,
+    shader_modules: [vk::ShaderModule; 2]
+}
+
+impl Hash for PipelineStateKey<'_>
+{
+    fn hash<H: Hasher>(&self, state: &mut H) {
         self.name.hash(state);
+        hash_vertex_input(self.vertex_input, state);
+        // the order dynamic states are listed in doesn't change the pipeline
+        let mut dynamic_states = self.dynamic_states.to_vec();
+        dynamic_states.sort();
+        dynamic_states.hash(state);
+        self.rasterization.hash(state);
+        self.depth_stencil.hash(state);
+        self.blend.hash(state);
+        self.target_blends.hash(state);
+        self.topology.hash(state);
+        self.primitive_restart.hash(state);
+        self.view_mask.hash(state);
+        self.shader_modules.hash(state);
+    }
+}
+
+fn hash_vertex_input<H: Hasher>(vertex_input: &vk::PipelineVertexInputStateCreateInfo, state: &mut H) {
+    // the descriptions are only guaranteed to outlive the create info while the pipeline is
+    // being fetched, which is the only time descriptions are hashed
+    let (bindings, attributes) = unsafe {
+        let bindings = match vertex_input.p_vertex_binding_descriptions.is_null() {
+            true => &[][..],
+            false => std::slice::from_raw_parts(
+                vertex_input.p_vertex_binding_descriptions,
+                vertex_input.vertex_binding_description_count as usize)
+        };
+        let attributes = match vertex_input.p_vertex_attribute_descriptions.is_null() {
+            true => &[][..],
+            false => std::slice::from_raw_parts(
+                vertex_input.p_vertex_attribute_descriptions,
+                vertex_input.vertex_attribute_description_count as usize)
+        };
+        (bindings, attributes)
+    };
+
+    vertex_input.flags.hash(state);
+    bindings.len().hash(state);
+    for binding in bindings {
+        binding.binding.hash(state);
+        binding.stride.hash(state);
+        binding.input_rate.hash(state);
+    }
+    attributes.len().hash(state);
+    for attribute in attributes {
+        attribute.location.hash(state);
+        attribute.binding.hash(state);
+        attribute.format.hash(state);
+        attribute.offset.hash(state);
     }
 }
 
@@ -63,7 +276,7 @@ impl PipelineDescription
     pub fn new(
         vertex_input: vk::PipelineVertexInputStateCreateInfo,
         dynamic_states: Vec<vk::DynamicState>,
-        rasterization: RasterizationType,
+        rasterization: RasterizationState,
         depth_stencil: DepthStencilType,
         blend: BlendType,
         name: &str,
@@ -76,6 +289,10 @@ impl PipelineDescription
             rasterization,
             depth_stencil,
             blend,
+            target_blends: Vec::new(),
+            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+            primitive_restart: false,
+            view_mask: 0,
             name: name.to_string(),
             vertex_shader,
             fragment_shader
@@ -83,10 +300,103 @@ impl PipelineDescription
     }
 
     pub fn get_name(&self) -> &str { &self.name }
+
+    pub fn get_rasterization(&self) -> &RasterizationState { &self.rasterization }
+
+    /// The pipeline's dynamic states, including DEPTH_BIAS if its depth bias is dynamic
+    pub fn get_dynamic_states(&self) -> Vec<vk::DynamicState> {
+        let mut dynamic_states = self.dynamic_states.clone();
+        if self.rasterization.depth_bias == DepthBias::Dynamic &&
+            !dynamic_states.contains(&vk::DynamicState::DEPTH_BIAS) {
+            dynamic_states.push(vk::DynamicState::DEPTH_BIAS);
+        }
+        dynamic_states
+    }
+
+    /// Blends the render target at `target_index` with `blend` rather than the description's
+    /// blend type. Differing blend types across targets need DeviceFeatures::independent_blend
+    pub fn target_blend(mut self, target_index: usize, blend: BlendType) -> Self {
+        if self.target_blends.len() <= target_index {
+            self.target_blends.resize(target_index + 1, None);
+        }
+        self.target_blends[target_index] = Some(blend);
+        self
+    }
+
+    /// Draws `topology` rather than a triangle list. Point lists need the vertex shader to
+    /// write gl_PointSize. Primitive restart is only supported for strip and fan topologies
+    pub fn topology(mut self, topology: vk::PrimitiveTopology, primitive_restart: bool) -> Self {
+        let restartable = matches!(topology,
+            vk::PrimitiveTopology::LINE_STRIP |
+            vk::PrimitiveTopology::TRIANGLE_STRIP |
+            vk::PrimitiveTopology::TRIANGLE_FAN |
+            vk::PrimitiveTopology::LINE_STRIP_WITH_ADJACENCY |
+            vk::PrimitiveTopology::TRIANGLE_STRIP_WITH_ADJACENCY);
+        assert!(!primitive_restart || restartable,
+            "Pipeline {} enables primitive restart for {:?}, which isn't a strip or fan topology", self.name, topology);
+        self.topology = topology;
+        self.primitive_restart = primitive_restart;
+        self
+    }
+
+    pub fn get_topology(&self) -> vk::PrimitiveTopology { self.topology }
+
+    /// Creates the pipeline for multiview renderpasses rendering the views in `view_mask`,
+    /// which must match the Multiview of the nodes using it. Pipelines are only compatible with
+    /// renderpasses of the same view mask, so this keeps them apart in the pipeline cache
+    pub fn multiview(mut self, view_mask: u32) -> Self {
+        self.view_mask = view_mask;
+        self
+    }
+
+    /// 0 unless the pipeline is for multiview renderpasses
+    pub fn get_view_mask(&self) -> u32 { self.view_mask }
+
+    /// The blend type used for the render target at `target_index`
+    pub fn get_target_blend(&self, target_index: usize) -> BlendType {
+        self.target_blends.get(target_index).copied().flatten().unwrap_or(self.blend)
+    }
+
+    /// The blending of render targets in `formats`. Integer targets, e.g. object IDs for
+    /// picking, can't be blended, so they're written as is whatever blending is set for them
+    pub fn get_target_blends(&self, formats: &[vk::Format]) -> Vec<BlendType> {
+        formats.iter().enumerate()
+            .map(|(target_index, format)| match is_integer_format(*format) {
+                true => BlendType::None,
+                false => self.get_target_blend(target_index)
+            })
+            .collect()
+    }
+}
+
+/// Whether `format` holds unnormalized integers, which render targets can't blend or filter
+pub fn is_integer_format(format: vk::Format) -> bool {
+    matches!(format,
+        vk::Format::R8_UINT | vk::Format::R8_SINT |
+        vk::Format::R8G8_UINT | vk::Format::R8G8_SINT |
+        vk::Format::R8G8B8_UINT | vk::Format::R8G8B8_SINT |
+        vk::Format::B8G8R8_UINT | vk::Format::B8G8R8_SINT |
+        vk::Format::R8G8B8A8_UINT | vk::Format::R8G8B8A8_SINT |
+        vk::Format::B8G8R8A8_UINT | vk::Format::B8G8R8A8_SINT |
+        vk::Format::A8B8G8R8_UINT_PACK32 | vk::Format::A8B8G8R8_SINT_PACK32 |
+        vk::Format::A2R10G10B10_UINT_PACK32 | vk::Format::A2R10G10B10_SINT_PACK32 |
+        vk::Format::A2B10G10R10_UINT_PACK32 | vk::Format::A2B10G10R10_SINT_PACK32 |
+        vk::Format::R16_UINT | vk::Format::R16_SINT |
+        vk::Format::R16G16_UINT | vk::Format::R16G16_SINT |
+        vk::Format::R16G16B16_UINT | vk::Format::R16G16B16_SINT |
+        vk::Format::R16G16B16A16_UINT | vk::Format::R16G16B16A16_SINT |
+        vk::Format::R32_UINT | vk::Format::R32_SINT |
+        vk::Format::R32G32_UINT | vk::Format::R32G32_SINT |
+        vk::Format::R32G32B32_UINT | vk::Format::R32G32B32_SINT |
+        vk::Format::R32G32B32A32_UINT | vk::Format::R32G32B32A32_SINT |
+        vk::Format::R64_UINT | vk::Format::R64_SINT |
+        vk::Format::R64G64_UINT | vk::Format::R64G64_SINT |
+        vk::Format::R64G64B64_UINT | vk::Format::R64G64B64_SINT |
+        vk::Format::R64G64B64A64_UINT | vk::Format::R64G64B64A64_SINT)
 }
 
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct ComputePipelineDescription
 {
     compute_name: String
@@ -107,30 +417,70 @@ impl ComputePipelineDescription {
             compute_name: compute_name.to_string()
         }
     }
+
+    /// Uses the compiled file of `variant` (see ShaderVariant::get_file_name)
+    pub fn from_variant(variant: &ShaderVariant) -> Self {
+        ComputePipelineDescription::new(&variant.get_file_name())
+    }
 }
 
 #[derive(Clone)]
 pub struct Pipeline
 {
-    pub device_pipeline: DevicePipeline
+    pub device_pipeline: DevicePipeline,
+    layout_hash: u64,
+    workgroup_size: Option<[u32; 3]>,
+    // of every stage, by set and binding
+    uniform_blocks: HashMap<(u32, u32), UniformBlockLayout>,
+    descriptor_types: HashMap<(u32, u32), vk::DescriptorType>
 }
 
 impl Debug for Pipeline {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("Pipeline")
             .field("device pipeline", &self.device_pipeline.pipeline.as_raw())
+            .field("layout hash", &self.layout_hash)
+            .field("workgroup size", &self.workgroup_size)
             .finish()
     }
 }
 
 impl Pipeline {
-    pub fn new(device_pipeline: DevicePipeline) -> Pipeline
+    pub fn new(device_pipeline: DevicePipeline, layout_hash: u64) -> Pipeline
     {
         Pipeline {
-            device_pipeline
+            device_pipeline,
+            layout_hash,
+            workgroup_size: None,
+            uniform_blocks: HashMap::new(),
+            descriptor_types: HashMap::new()
         }
     }
 
+    /// The local size reflected from a compute pipeline's shader
+    pub fn get_workgroup_size(&self) -> Option<[u32; 3]> { self.workgroup_size }
+
+    /// The layout of the uniform block at `set` and `binding` in any of the pipeline's shaders
+    pub fn get_uniform_block(&self, set: u32, binding: u32) -> Option<&UniformBlockLayout> {
+        self.uniform_blocks.get(&(set, binding))
+    }
+
+    /// The type reflected for the descriptor at `set` and `binding`, e.g. to tell uniform and
+    /// storage buffers apart
+    pub fn get_descriptor_type(&self, set: u32, binding: u32) -> Option<vk::DescriptorType> {
+        self.descriptor_types.get(&(set, binding)).copied()
+    }
+
+    /// Every descriptor reflected from the pipeline's shaders as (set, binding, type), sorted
+    /// by set and binding
+    pub fn get_descriptor_layout(&self) -> Vec<(u32, u32, vk::DescriptorType)> {
+        sorted_descriptor_layout(&self.descriptor_types)
+    }
+
+    /// Hash of the pipeline's descriptor set layout bindings; pipelines with equal
+    /// hashes share the same VkPipelineLayout
+    pub fn get_layout_hash(&self) -> u64 { self.layout_hash }
+
     pub fn get_pipeline(&self) -> vk::Pipeline {
         self.device_pipeline.pipeline
     }
@@ -140,13 +490,28 @@ impl Pipeline {
     }
 }
 
-#[derive(Debug)]
 pub struct VulkanPipelineManager
 {
     pipeline_cache: HashMap<u64, Rc<RefCell<Pipeline>>>,
+    // layouts are keyed by the content of their bindings, so pipelines built from
+    // different descriptions can share them
+    descriptor_set_layout_cache: HashMap<u64, Rc<DeviceDescriptorSetLayout>>,
+    pipeline_layout_cache: HashMap<u64, Rc<DevicePipelineLayout>>,
+    pipeline_layouts_created: u32,
     shader_manager: ShaderManager
 }
 
+impl Debug for VulkanPipelineManager {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("VulkanPipelineManager")
+            .field("pipeline count", &self.pipeline_cache.len())
+            .field("descriptor set layout count", &self.descriptor_set_layout_cache.len())
+            .field("pipeline layout count", &self.pipeline_layout_cache.len())
+            .field("shader manager", &self.shader_manager)
+            .finish()
+    }
+}
+
 const STENCIL_STATE_KEEP: vk::StencilOpState = vk::StencilOpState {
     fail_op: vk::StencilOp::KEEP,
     pass_op: vk::StencilOp::KEEP,
@@ -157,46 +522,65 @@ const STENCIL_STATE_KEEP: vk::StencilOpState = vk::StencilOpState {
     reference: 0,
 };
 
-fn generate_rasteration_state(rasterization_type: RasterizationType) -> vk::PipelineRasterizationStateCreateInfo
+fn generate_rasteration_state(rasterization: &RasterizationState) -> vk::PipelineRasterizationStateCreateInfo
 {
-    match rasterization_type
-    {
-        RasterizationType::Standard => {
-            vk::PipelineRasterizationStateCreateInfo
-            {
-                s_type: vk::StructureType::PIPELINE_RASTERIZATION_STATE_CREATE_INFO,
-                p_next: std::ptr::null(),
-                flags: vk::PipelineRasterizationStateCreateFlags::empty(),
-                depth_clamp_enable: vk::FALSE,
-                cull_mode: vk::CullModeFlags::NONE,
-                front_face: vk::FrontFace::CLOCKWISE,
-                line_width: 1.0,
-                polygon_mode: vk::PolygonMode::FILL,
-                rasterizer_discard_enable: vk::FALSE,
-                depth_bias_clamp: 0.0,
-                depth_bias_constant_factor: 0.0,
-                depth_bias_enable: vk::FALSE,
-                depth_bias_slope_factor: 0.0,
-            }
-        },
-        _ => {
-            panic!("Invalid Rasterization Type")
-        }
+    // dynamic depth bias still needs to be enabled, only the factors are ignored
+    let (depth_bias_enable, constant_factor, clamp, slope_factor) = match rasterization.depth_bias {
+        DepthBias::Disabled => (false, 0.0, 0.0, 0.0),
+        DepthBias::Static { constant_factor, clamp, slope_factor } => (true, constant_factor, clamp, slope_factor),
+        DepthBias::Dynamic => (true, 0.0, 0.0, 0.0)
+    };
+
+    vk::PipelineRasterizationStateCreateInfo::builder()
+        .depth_clamp_enable(rasterization.depth_clamp)
+        .rasterizer_discard_enable(false)
+        .polygon_mode(rasterization.polygon_mode)
+        .cull_mode(rasterization.cull_mode)
+        .front_face(rasterization.front_face)
+        .line_width(1.0)
+        .depth_bias_enable(depth_bias_enable)
+        .depth_bias_constant_factor(constant_factor)
+        .depth_bias_clamp(clamp)
+        .depth_bias_slope_factor(slope_factor)
+        .build()
+}
+
+/// Panics if `rasterization` uses state the device doesn't support
+fn validate_rasterization_state(device_features: &DeviceFeatures, rasterization: &RasterizationState, name: &str) {
+    assert!(rasterization.polygon_mode == vk::PolygonMode::FILL || device_features.fill_mode_non_solid,
+        "Pipeline {} uses polygon mode {:?}, but fillModeNonSolid is unsupported", name, rasterization.polygon_mode);
+    assert!(!rasterization.depth_clamp || device_features.depth_clamp,
+        "Pipeline {} enables depth clamp, but depthClamp is unsupported", name);
+    if let DepthBias::Static { clamp, .. } = rasterization.depth_bias {
+        assert!(clamp == 0.0 || device_features.depth_bias_clamp,
+            "Pipeline {} clamps its depth bias, but depthBiasClamp is unsupported", name);
     }
 }
 
+/// The states in `declared` which aren't in `set`, in the order they were declared
+pub(crate) fn unset_dynamic_states(declared: &[vk::DynamicState], set: &[vk::DynamicState]) -> Vec<vk::DynamicState> {
+    declared.iter()
+        .filter(|dynamic_state| !set.contains(dynamic_state))
+        .copied()
+        .collect()
+}
+
 fn generate_depth_stencil_state(depth_stencil_type: DepthStencilType) -> vk::PipelineDepthStencilStateCreateInfo
 {
     match depth_stencil_type
     {
-        DepthStencilType::Enable => {
+        DepthStencilType::Enable | DepthStencilType::EnableWithCompare(_) => {
+            let depth_compare_op = match depth_stencil_type {
+                DepthStencilType::EnableWithCompare(compare_op) => compare_op,
+                _ => vk::CompareOp::LESS_OR_EQUAL
+            };
             vk::PipelineDepthStencilStateCreateInfo {
                 s_type: vk::StructureType::PIPELINE_DEPTH_STENCIL_STATE_CREATE_INFO,
                 p_next: std::ptr::null(),
                 flags: vk::PipelineDepthStencilStateCreateFlags::empty(),
                 depth_test_enable: vk::TRUE,
                 depth_write_enable: vk::TRUE,
-                depth_compare_op: vk::CompareOp::LESS_OR_EQUAL,
+                depth_compare_op,
                 depth_bounds_test_enable: vk::FALSE,
                 stencil_test_enable: vk::FALSE,
                 front: STENCIL_STATE_KEEP,
@@ -224,129 +608,190 @@ fn generate_depth_stencil_state(depth_stencil_type: DepthStencilType) -> vk::Pip
     }
 }
 
-fn generate_blend_attachments(blend_type: BlendType) -> [vk::PipelineColorBlendAttachmentState; 1] {
-    match blend_type
+fn generate_blend_attachment(blend_type: BlendType) -> vk::PipelineColorBlendAttachmentState {
+    let equation = match blend_type
     {
         BlendType::None => {
-            // let color_blend_attachment_states = [vk::PipelineColorBlendAttachmentState {
-            //     blend_enable: vk::FALSE,
-            //     // color_write_mask: vk::ColorComponentFlags::all(),
-            //     color_write_mask: vk::ColorComponentFlags::R | vk::ColorComponentFlags::G | vk::ColorComponentFlags::B | vk::ColorComponentFlags::A,
-            //     src_color_blend_factor: vk::BlendFactor::ONE,
-            //     dst_color_blend_factor: vk::BlendFactor::ZERO,
-            //     color_blend_op: vk::BlendOp::ADD,
-            //     src_alpha_blend_factor: vk::BlendFactor::ONE,
-            //     dst_alpha_blend_factor: vk::BlendFactor::ZERO,
-            //     alpha_blend_op: vk::BlendOp::ADD,
-            // }];
-            [vk::PipelineColorBlendAttachmentState::builder()
+            return vk::PipelineColorBlendAttachmentState::builder()
                 .blend_enable(false)
                 .color_blend_op(vk::BlendOp::ADD)
                 .color_write_mask(vk::ColorComponentFlags::RGBA)
-                .build()]
+                .build();
         },
-        BlendType::Transparent => {
-            [vk::PipelineColorBlendAttachmentState::builder()
-                .blend_enable(true)
-                .color_blend_op(vk::BlendOp::ADD)
-                .color_write_mask(vk::ColorComponentFlags::RGBA)
-                .src_color_blend_factor(vk::BlendFactor::SRC_ALPHA)
-                .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
-                .src_alpha_blend_factor(vk::BlendFactor::ONE)
-                .dst_alpha_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
-                .alpha_blend_op(vk::BlendOp::ADD)
-                .build()]
-        }
-        _ => {
-            panic!("Need to implement the rest of the blend states")
-        }
-    }
+        BlendType::Alpha => BlendEquation {
+            src_color_blend_factor: vk::BlendFactor::SRC_ALPHA,
+            dst_color_blend_factor: vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+            src_alpha_blend_factor: vk::BlendFactor::ONE,
+            dst_alpha_blend_factor: vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+            ..Default::default()
+        },
+        BlendType::Additive => BlendEquation {
+            src_color_blend_factor: vk::BlendFactor::SRC_ALPHA,
+            dst_color_blend_factor: vk::BlendFactor::ONE,
+            src_alpha_blend_factor: vk::BlendFactor::ONE,
+            dst_alpha_blend_factor: vk::BlendFactor::ONE,
+            ..Default::default()
+        },
+        BlendType::Premultiplied => BlendEquation {
+            src_color_blend_factor: vk::BlendFactor::ONE,
+            dst_color_blend_factor: vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+            src_alpha_blend_factor: vk::BlendFactor::ONE,
+            dst_alpha_blend_factor: vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+            ..Default::default()
+        },
+        BlendType::Custom(equation) => equation
+    };
+
+    vk::PipelineColorBlendAttachmentState::builder()
+        .blend_enable(true)
+        .src_color_blend_factor(equation.src_color_blend_factor)
+        .dst_color_blend_factor(equation.dst_color_blend_factor)
+        .color_blend_op(equation.color_blend_op)
+        .src_alpha_blend_factor(equation.src_alpha_blend_factor)
+        .dst_alpha_blend_factor(equation.dst_alpha_blend_factor)
+        .alpha_blend_op(equation.alpha_blend_op)
+        .color_write_mask(equation.color_write_mask)
+        .build()
 }
 
-fn generate_blend_state(blend_type: BlendType, attachments: &[vk::PipelineColorBlendAttachmentState]) -> vk::PipelineColorBlendStateCreateInfo
+fn generate_blend_state(attachments: &[vk::PipelineColorBlendAttachmentState]) -> vk::PipelineColorBlendStateCreateInfo
 {
-    match blend_type
-    {
-        BlendType::None => {
-            vk::PipelineColorBlendStateCreateInfo::builder()
-                .logic_op_enable(false)
-                .logic_op(vk::LogicOp::NO_OP)
-                .attachments(attachments)
-                .blend_constants([0.0, 0.0, 0.0, 0.0])
-                .build()
-            // vk::PipelineColorBlendStateCreateInfo {
-            //     s_type: vk::StructureType::PIPELINE_COLOR_BLEND_STATE_CREATE_INFO,
-            //     p_next: std::ptr::null(),
-            //     flags: vk::PipelineColorBlendStateCreateFlags::empty(),
-            //     logic_op_enable: vk::FALSE,
-            //     logic_op: vk::LogicOp::COPY,
-            //     attachment_count: color_blend_attachment_states.len() as u32,
-            //     p_attachments: color_blend_attachment_states.as_ptr(),
-            //     blend_constants: [0.0, 0.0, 0.0, 0.0],
-            // }
-        },
-        BlendType::Transparent => {
-            vk::PipelineColorBlendStateCreateInfo::builder()
-                .attachments(attachments)
-                .blend_constants([1.0, 1.0, 1.0, 1.0])
-                .build()
-        }
-        _ => {
-            panic!("Need to implement the rest of the blend states")
-        }
+    vk::PipelineColorBlendStateCreateInfo::builder()
+        .logic_op_enable(false)
+        .logic_op(vk::LogicOp::NO_OP)
+        .attachments(attachments)
+        .blend_constants([0.0, 0.0, 0.0, 0.0])
+        .build()
+}
+
+// layouts and pipelines must be created for descriptor buffers to be used with them
+fn get_descriptor_set_layout_flags(render_context: &VulkanRenderContext) -> vk::DescriptorSetLayoutCreateFlags {
+    match render_context.uses_descriptor_buffers() {
+        true => vk::DescriptorSetLayoutCreateFlags::DESCRIPTOR_BUFFER_EXT,
+        false => vk::DescriptorSetLayoutCreateFlags::empty()
     }
 }
 
-fn create_descriptor_set_layouts(render_context: &VulkanRenderContext, full_bindings: &HashMap<u32, Vec<vk::DescriptorSetLayoutBinding>>) -> Vec<vk::DescriptorSetLayout> {
+fn get_pipeline_flags(render_context: &VulkanRenderContext) -> vk::PipelineCreateFlags {
+    match render_context.uses_descriptor_buffers() {
+        true => vk::PipelineCreateFlags::DESCRIPTOR_BUFFER_EXT,
+        false => vk::PipelineCreateFlags::empty()
+    }
+}
 
-    let mut descriptor_set_layouts: Vec<vk::DescriptorSetLayout> = Vec::new();
+fn get_descriptor_types(full_bindings: &HashMap<u32, Vec<vk::DescriptorSetLayoutBinding>>) -> HashMap<(u32, u32), vk::DescriptorType> {
+    full_bindings.iter()
+        .flat_map(|(set, bindings)| bindings.iter().map(move |binding| ((*set, binding.binding), binding.descriptor_type)))
+        .collect()
+}
 
-    // first find the highest set
-    let highest_set = {
-        let mut highest = 0;
-        for set in full_bindings.keys() {
-            if *set > highest {
-                highest = *set;
-            }
-        }
-        highest
-    };
-    descriptor_set_layouts.resize((highest_set + 1) as  usize, vk::DescriptorSetLayout::null());
-
-    // then fill the DescriptorSetLayout vector, using null layouts to fill the holes
-    // e.g. if a pipeline explicitly uses sets 0 and 2, set 1 will be a null handle
-    for set in (0..=highest_set) {
-        if let Some(bindings) = full_bindings.get(&set) {
-            let layout_create_info = vk::DescriptorSetLayoutCreateInfo::builder()
-                .bindings(&bindings)
-                .build();
+pub(crate) fn sorted_descriptor_layout(descriptor_types: &HashMap<(u32, u32), vk::DescriptorType>) -> Vec<(u32, u32, vk::DescriptorType)> {
+    let mut layout: Vec<(u32, u32, vk::DescriptorType)> = descriptor_types.iter()
+        .map(|((set, binding), descriptor_type)| (*set, *binding, *descriptor_type))
+        .collect();
+    layout.sort_by_key(|(set, binding, _)| (*set, *binding));
+    layout
+}
 
-            let layout = unsafe {
-                render_context.get_device().borrow().get().create_descriptor_set_layout(
-                    &layout_create_info,
-                    None)
-                    .expect("Failed to create descriptor set layout")
-            };
-            // assert!((*set as usize) <= descriptor_set_layouts.len(), "Holes in used descriptor sets not allowed");
-            descriptor_set_layouts[set as usize] = layout;
-        } else {
-            descriptor_set_layouts[set as  usize] = vk::DescriptorSetLayout::null();
-        }
+fn hash_set_layout_bindings(bindings: &[vk::DescriptorSetLayoutBinding]) -> u64 {
+    // immutable samplers aren't produced by shader reflection, so they aren't hashed
+    let mut hasher = DefaultHasher::new();
+    for binding in bindings {
+        binding.binding.hash(&mut hasher);
+        binding.descriptor_type.hash(&mut hasher);
+        binding.descriptor_count.hash(&mut hasher);
+        binding.stage_flags.hash(&mut hasher);
     }
-
-    descriptor_set_layouts
+    hasher.finish()
 }
 
-
 impl VulkanPipelineManager {
     pub fn new() -> VulkanPipelineManager
     {
         VulkanPipelineManager {
             pipeline_cache: HashMap::new(),
+            descriptor_set_layout_cache: HashMap::new(),
+            pipeline_layout_cache: HashMap::new(),
+            pipeline_layouts_created: 0,
             shader_manager: ShaderManager::new()
         }
     }
 
+    /// Number of distinct pipeline layouts created so far
+    pub fn get_pipeline_layouts_created(&self) -> u32 { self.pipeline_layouts_created }
+
+    /// Drops every cached pipeline so each is recreated on next use, e.g. after the swapchain
+    /// format changes. Pipelines are already keyed by their attachments' sample count, so this
+    /// only releases the ones an old sample count left behind. Layouts are kept
+    pub fn clear_pipelines(&mut self) {
+        self.pipeline_cache.clear();
+    }
+
+    /// Returns the pipeline layout for `full_bindings` along with its hash, creating it (and
+    /// any of its descriptor set layouts) only if no earlier pipeline used identical bindings.
+    /// Sets without bindings become null set layouts, e.g. if a pipeline explicitly uses sets
+    /// 0 and 2, set 1 will be a null handle
+    fn get_pipeline_layout(
+        &mut self,
+        render_context: &VulkanRenderContext,
+        full_bindings: &HashMap<u32, Vec<vk::DescriptorSetLayoutBinding>>,
+        name: &str) -> (u64, Rc<DevicePipelineLayout>) {
+
+        let highest_set = full_bindings.keys().max().cloned().unwrap_or(0);
+
+        let mut layout_hasher = DefaultHasher::new();
+        let mut descriptor_set_layouts: Vec<Option<Rc<DeviceDescriptorSetLayout>>> = Vec::new();
+        for set in 0..=highest_set {
+            match full_bindings.get(&set) {
+                Some(bindings) => {
+                    let mut sorted_bindings = bindings.clone();
+                    sorted_bindings.sort_by_key(|binding| binding.binding);
+                    // the per-frame set is bound for passes with any stages, so the stages of
+                    // one pipeline mustn't make its layout incompatible with the others'
+                    if set as u64 == BindingFrequency::PerFrame.get_set() {
+                        for binding in &mut sorted_bindings {
+                            binding.stage_flags = vk::ShaderStageFlags::ALL;
+                        }
+                    }
+                    let set_layout_hash = hash_set_layout_bindings(&sorted_bindings);
+                    Some(set_layout_hash).hash(&mut layout_hasher);
+                    graph_debug!(set, key = set_layout_hash, hit = self.descriptor_set_layout_cache.contains_key(&set_layout_hash),
+                        "descriptor set layout cache lookup");
+
+                    let set_layout = self.descriptor_set_layout_cache.entry(set_layout_hash).or_insert_with(|| {
+                        let layout_create_info = vk::DescriptorSetLayoutCreateInfo::builder()
+                            .flags(get_descriptor_set_layout_flags(render_context))
+                            .bindings(&sorted_bindings)
+                            .build();
+                        Rc::new(DeviceWrapper::create_descriptor_set_layout(
+                            render_context.get_device(),
+                            &layout_create_info))
+                    });
+                    descriptor_set_layouts.push(Some(set_layout.clone()));
+                },
+                None => {
+                    None::<u64>.hash(&mut layout_hasher);
+                    descriptor_set_layouts.push(None);
+                }
+            }
+        }
+        let layout_hash = layout_hasher.finish();
+        graph_debug!(pipeline = name, key = layout_hash, hit = self.pipeline_layout_cache.contains_key(&layout_hash),
+            "pipeline layout cache lookup");
+
+        let pipeline_layouts_created = &mut self.pipeline_layouts_created;
+        let pipeline_layout = self.pipeline_layout_cache.entry(layout_hash).or_insert_with(|| {
+            *pipeline_layouts_created += 1;
+            log::trace!(target: "pipeline", "Creating pipeline layout {:#x} for {}", layout_hash, name);
+            Rc::new(DeviceWrapper::create_pipeline_layout(
+                render_context.get_device(),
+                descriptor_set_layouts,
+                &format!("{}_layout", name)))
+        });
+
+        (layout_hash, pipeline_layout.clone())
+    }
+
     pub fn create_compute_pipeline(
         &mut self,
         render_context: &VulkanRenderContext,
@@ -356,6 +801,8 @@ impl VulkanPipelineManager {
         pipeline_description.hash(&mut pipeline_hasher);
         let pipeline_key = pipeline_hasher.finish();
         let pipeline_val = self.pipeline_cache.get(&pipeline_key);
+        graph_debug!(pipeline = pipeline_description.compute_name.as_str(), key = pipeline_key, hit = pipeline_val.is_some(),
+            "compute pipeline cache lookup");
         match pipeline_val {
             Some(pipeline) => { pipeline.clone() },
             None => {
@@ -372,18 +819,10 @@ impl VulkanPipelineManager {
                     }
                 }
 
-                let descriptor_set_layouts = create_descriptor_set_layouts(render_context, &full_bindings);
-
-                // let descriptor_sets = render_context.create_descriptor_sets(&descriptor_set_layouts);
-
-                let pipeline_layout = {
-                    let pipeline_layout_create = vk::PipelineLayoutCreateInfo::builder()
-                        .set_layouts(&descriptor_set_layouts);
-                    unsafe {
-                        render_context.get_device().borrow().get().create_pipeline_layout(&pipeline_layout_create, None)
-                            .expect("Failed to create pipeline layout")
-                    }
-                };
+                let (layout_hash, layout) = self.get_pipeline_layout(
+                    render_context,
+                    &full_bindings,
+                    &pipeline_description.compute_name);
 
                 let main_name = std::ffi::CString::new("main").unwrap();
                 let shader_stage = vk::PipelineShaderStageCreateInfo::builder()
@@ -392,28 +831,38 @@ impl VulkanPipelineManager {
                     .stage(vk::ShaderStageFlags::COMPUTE);
 
                 let compute_pipeline_info = vk::ComputePipelineCreateInfo::builder()
+                    .flags(get_pipeline_flags(render_context))
                     .stage(*shader_stage)
-                    .layout(pipeline_layout)
+                    .layout(layout.pipeline_layout)
                     .build();
 
                 let device_pipeline = DeviceWrapper::create_compute_pipeline(
                     render_context.get_device(),
                     &compute_pipeline_info,
-                    pipeline_layout,
-                    descriptor_set_layouts,
+                    layout,
                     &pipeline_description.compute_name);
-                let pipeline = Rc::new(RefCell::new(Pipeline::new(
-                    device_pipeline)));
+                let mut pipeline = Pipeline::new(
+                    device_pipeline,
+                    layout_hash);
+                pipeline.workgroup_size = compute_shader_module.borrow().workgroup_size;
+                pipeline.uniform_blocks = compute_shader_module.borrow().uniform_blocks.clone();
+                pipeline.descriptor_types = get_descriptor_types(&full_bindings);
+                let pipeline = Rc::new(RefCell::new(pipeline));
                 self.pipeline_cache.insert(pipeline_key, pipeline.clone());
                 pipeline
             }
         }
     }
 
+    /// `subpass_targets` describes every subpass of `render_pass`, all of which are part of the
+    /// pipeline's key. Each render target of `subpass` gets a blend attachment state, and the
+    /// pipeline rasterizes with its sample count
     pub fn create_pipeline(
         &mut self,
         render_context: &VulkanRenderContext,
         render_pass: vk::RenderPass,
+        subpass: u32,
+        subpass_targets: &[SubpassTargets],
         pipeline_description: &PipelineDescription) -> Rc<RefCell<Pipeline>> {
         enter_span!(tracing::Level::TRACE, "Create or fetch Pipeline");
 
@@ -421,11 +870,17 @@ impl VulkanPipelineManager {
         //  to avoid needing to calculate a hash for each used pipeline each frame?
         let mut pipeline_hasher = DefaultHasher::new();
         pipeline_description.hash(&mut pipeline_hasher);
+        subpass.hash(&mut pipeline_hasher);
+        subpass_targets.hash(&mut pipeline_hasher);
         let pipeline_key = pipeline_hasher.finish();
         let pipeline_val = self.pipeline_cache.get(&pipeline_key);
+        graph_debug!(pipeline = pipeline_description.get_name(), key = pipeline_key, hit = pipeline_val.is_some(),
+            "pipeline cache lookup");
         match pipeline_val {
             Some(pipeline) => { pipeline.clone() },
             None => {
+                let targets = &subpass_targets[subpass as usize];
+
                 // Need to reconcile descriptor bindings between vertex and fragment stages
                 //  i.e. - Could have duplicate bindings for descriptors used in both stages, or
                 //  bindings only used in a single stage but are part of a larger descriptor set
@@ -457,33 +912,24 @@ impl VulkanPipelineManager {
                     }
                 }
 
-                let descriptor_set_layouts = create_descriptor_set_layouts(render_context, &full_bindings);
-
-                // let descriptor_sets = render_context.create_descriptor_sets(&descriptor_set_layouts);
-
-                let pipeline_layout = {
-                        let pipeline_layout_create = vk::PipelineLayoutCreateInfo::builder()
-                            .set_layouts(&descriptor_set_layouts);
-                        unsafe {
-                            render_context.get_device().borrow().get().create_pipeline_layout(&pipeline_layout_create, None)
-                                .expect("Failed to create pipeline layout")
-                        }
-                };
+                let (layout_hash, layout) = self.get_pipeline_layout(
+                    render_context,
+                    &full_bindings,
+                    pipeline_description.get_name());
 
                 let vertex_input_assembly_state_info = vk::PipelineInputAssemblyStateCreateInfo {
                     s_type: vk::StructureType::PIPELINE_INPUT_ASSEMBLY_STATE_CREATE_INFO,
                     flags: vk::PipelineInputAssemblyStateCreateFlags::empty(),
                     p_next: std::ptr::null(),
-                    primitive_restart_enable: vk::FALSE,
-                    topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+                    primitive_restart_enable: pipeline_description.primitive_restart.into(),
+                    topology: pipeline_description.topology,
                 };
 
-                // TODO: parameterize multisample state
                 let multisample_state_create_info = vk::PipelineMultisampleStateCreateInfo {
                     s_type: vk::StructureType::PIPELINE_MULTISAMPLE_STATE_CREATE_INFO,
                     flags: vk::PipelineMultisampleStateCreateFlags::empty(),
                     p_next: std::ptr::null(),
-                    rasterization_samples: vk::SampleCountFlags::TYPE_1,
+                    rasterization_samples: targets.samples,
                     sample_shading_enable: vk::FALSE,
                     min_sample_shading: 0.0,
                     p_sample_mask: std::ptr::null(),
@@ -525,15 +971,29 @@ impl VulkanPipelineManager {
                     },
                 ];
 
+                validate_rasterization_state(
+                    render_context.get_device().borrow().features(),
+                    &pipeline_description.rasterization,
+                    pipeline_description.get_name());
+
+                let dynamic_states = pipeline_description.get_dynamic_states();
                 let dynamic_state = vk::PipelineDynamicStateCreateInfo::builder()
-                    .dynamic_states(&pipeline_description.dynamic_states);
+                    .dynamic_states(&dynamic_states);
 
-                let rasterization_state = generate_rasteration_state(pipeline_description.rasterization);
+                let rasterization_state = generate_rasteration_state(&pipeline_description.rasterization);
                 let depth_stencil_state = generate_depth_stencil_state(pipeline_description.depth_stencil);
-                let blend_attachments = generate_blend_attachments(pipeline_description.blend);
-                let blend_state = generate_blend_state(pipeline_description.blend, &blend_attachments);
+                let target_blends = pipeline_description.get_target_blends(&targets.color_formats);
+                assert!(target_blends.windows(2).all(|pair| pair[0] == pair[1]) ||
+                    render_context.get_device().borrow().features().independent_blend,
+                    "Pipeline {} blends its render targets differently, but independentBlend is unsupported",
+                    pipeline_description.get_name());
+                let blend_attachments: Vec<vk::PipelineColorBlendAttachmentState> = target_blends.into_iter()
+                    .map(generate_blend_attachment)
+                    .collect();
+                let blend_state = generate_blend_state(&blend_attachments);
 
                 let graphics_pipeline_info = vk::GraphicsPipelineCreateInfo::builder()
+                    .flags(get_pipeline_flags(render_context))
                     .stages(&shader_stages)
                     .input_assembly_state(&vertex_input_assembly_state_info)
                     .vertex_input_state(&pipeline_description.vertex_input)
@@ -545,22 +1005,254 @@ impl VulkanPipelineManager {
                     // .dynamic_state(&pipeline_description.dynamic_state)
                     .dynamic_state(&dynamic_state)
                     // .layout(frag_shader_module.pipeline_layout)
-                    .layout(pipeline_layout)
+                    .layout(layout.pipeline_layout)
                     .render_pass(render_pass)
-                    .subpass(0); // TODO: this shouldn't be static
+                    .subpass(subpass);
                 // .build();
 
                 let device_pipeline = DeviceWrapper::create_pipeline(
                     render_context.get_device(),
                     &graphics_pipeline_info,
-                    pipeline_layout,
-                    descriptor_set_layouts,
+                    layout,
                     pipeline_description.get_name());
-                let pipeline = Rc::new(RefCell::new(Pipeline::new(
-                    device_pipeline)));
+                let mut pipeline = Pipeline::new(
+                    device_pipeline,
+                    layout_hash);
+                // blocks shared by both stages are declared identically by each
+                pipeline.uniform_blocks = pipeline_description.vertex_shader.borrow().uniform_blocks.clone();
+                for (key, block) in &pipeline_description.fragment_shader.borrow().uniform_blocks {
+                    pipeline.uniform_blocks.entry(*key).or_insert_with(|| block.clone());
+                }
+                pipeline.descriptor_types = get_descriptor_types(&full_bindings);
+                let pipeline = Rc::new(RefCell::new(pipeline));
                 self.pipeline_cache.insert(pipeline_key, pipeline.clone());
                 pipeline
             }
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+    use ash::vk;
+    use ash::vk::Handle;
+    use super::*;
+
+    const BINDINGS: [vk::VertexInputBindingDescription; 1] = [vk::VertexInputBindingDescription {
+        binding: 0,
+        stride: 32,
+        input_rate: vk::VertexInputRate::VERTEX
+    }];
+
+    const ATTRIBUTES: [vk::VertexInputAttributeDescription; 2] = [
+        vk::VertexInputAttributeDescription {
+            location: 0,
+            binding: 0,
+            format: vk::Format::R32G32B32_SFLOAT,
+            offset: 0
+        },
+        vk::VertexInputAttributeDescription {
+            location: 1,
+            binding: 0,
+            format: vk::Format::R32G32_SFLOAT,
+            offset: 12
+        }
+    ];
+
+    fn vertex_input(
+        bindings: &[vk::VertexInputBindingDescription],
+        attributes: &[vk::VertexInputAttributeDescription]) -> vk::PipelineVertexInputStateCreateInfo {
+        vk::PipelineVertexInputStateCreateInfo::builder()
+            .vertex_binding_descriptions(bindings)
+            .vertex_attribute_descriptions(attributes)
+            .build()
+    }
+
+    fn hash_key(key: &PipelineStateKey) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    fn shader_modules(vertex: u64, fragment: u64) -> [vk::ShaderModule; 2] {
+        [vk::ShaderModule::from_raw(vertex), vk::ShaderModule::from_raw(fragment)]
+    }
+
+    #[test]
+    fn identical_descriptions_hash_equally() {
+        let dynamic_states = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+        let first_input = vertex_input(&BINDINGS, &ATTRIBUTES);
+        // separate copies of the layouts, so only their contents can match
+        let bindings = BINDINGS;
+        let attributes = ATTRIBUTES;
+        let second_input = vertex_input(&bindings, &attributes);
+
+        let first = PipelineStateKey {
+            name: "gltf-model-draw",
+            vertex_input: &first_input,
+            dynamic_states: &dynamic_states,
+            rasterization: RasterizationState::default(),
+            depth_stencil: DepthStencilType::Enable,
+            blend: BlendType::None,
+            target_blends: &[],
+            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+            primitive_restart: false,
+            view_mask: 0,
+            shader_modules: shader_modules(1, 2)
+        };
+        let reordered_states = [vk::DynamicState::SCISSOR, vk::DynamicState::VIEWPORT];
+        let second = PipelineStateKey {
+            vertex_input: &second_input,
+            dynamic_states: &reordered_states,
+            ..first
+        };
+
+        assert_eq!(hash_key(&first), hash_key(&second));
+    }
+
+    #[test]
+    fn distinct_descriptions_with_the_same_name_hash_differently() {
+        let dynamic_states = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+        let input = vertex_input(&BINDINGS, &ATTRIBUTES);
+        let base = PipelineStateKey {
+            name: "gltf-model-draw",
+            vertex_input: &input,
+            dynamic_states: &dynamic_states,
+            rasterization: RasterizationState::default(),
+            depth_stencil: DepthStencilType::Enable,
+            blend: BlendType::None,
+            target_blends: &[],
+            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+            primitive_restart: false,
+            view_mask: 0,
+            shader_modules: shader_modules(1, 2)
+        };
+
+        let wider_stride = [vk::VertexInputBindingDescription {
+            stride: 48,
+            ..BINDINGS[0]
+        }];
+        let stride_input = vertex_input(&wider_stride, &ATTRIBUTES);
+        let mut other_format = ATTRIBUTES;
+        other_format[1].format = vk::Format::R16G16_SFLOAT;
+        let format_input = vertex_input(&BINDINGS, &other_format);
+        let position_only_input = vertex_input(&BINDINGS, &ATTRIBUTES[..1]);
+        let fewer_states = [vk::DynamicState::VIEWPORT];
+        let custom_blend = BlendEquation {
+            color_write_mask: vk::ColorComponentFlags::RGB,
+            ..Default::default()
+        };
+        let alpha_second_target = [None, Some(BlendType::Alpha)];
+
+        let variants = [
+            PipelineStateKey { vertex_input: &stride_input, ..base },
+            PipelineStateKey { vertex_input: &format_input, ..base },
+            PipelineStateKey { vertex_input: &position_only_input, ..base },
+            PipelineStateKey { dynamic_states: &fewer_states, ..base },
+            PipelineStateKey { rasterization: RasterizationState::wireframe(), ..base },
+            PipelineStateKey { rasterization: RasterizationState { cull_mode: vk::CullModeFlags::BACK, ..base.rasterization }, ..base },
+            PipelineStateKey { rasterization: RasterizationState { depth_bias: DepthBias::Dynamic, ..base.rasterization }, ..base },
+            PipelineStateKey { rasterization: RasterizationState {
+                depth_bias: DepthBias::Static { constant_factor: 1.25, clamp: 0.0, slope_factor: 1.75 },
+                ..base.rasterization
+            }, ..base },
+            PipelineStateKey { depth_stencil: DepthStencilType::Disable, ..base },
+            PipelineStateKey { depth_stencil: DepthStencilType::REVERSE_Z, ..base },
+            PipelineStateKey { blend: BlendType::Alpha, ..base },
+            PipelineStateKey { blend: BlendType::Additive, ..base },
+            PipelineStateKey { blend: BlendType::Premultiplied, ..base },
+            PipelineStateKey { blend: BlendType::Custom(BlendEquation::default()), ..base },
+            PipelineStateKey { blend: BlendType::Custom(custom_blend), ..base },
+            PipelineStateKey { target_blends: &alpha_second_target, ..base },
+            PipelineStateKey { topology: vk::PrimitiveTopology::LINE_LIST, ..base },
+            PipelineStateKey { topology: vk::PrimitiveTopology::TRIANGLE_STRIP, ..base },
+            PipelineStateKey { topology: vk::PrimitiveTopology::TRIANGLE_STRIP, primitive_restart: true, ..base },
+            PipelineStateKey { view_mask: 0b11, ..base },
+            PipelineStateKey { view_mask: 0b111111, ..base },
+            PipelineStateKey { shader_modules: shader_modules(1, 3), ..base },
+            PipelineStateKey { shader_modules: shader_modules(2, 1), ..base }
+        ];
+
+        let mut hashes = vec![hash_key(&base)];
+        hashes.extend(variants.iter().map(hash_key));
+        for (i, hash) in hashes.iter().enumerate() {
+            for other in &hashes[i + 1..] {
+                assert_ne!(hash, other, "Pipeline state {} collided with another description", i);
+            }
+        }
+    }
+
+    #[test]
+    fn renderpass_compatibility_changes_the_subpass_hash() {
+        let hash_targets = |targets: &[SubpassTargets]| {
+            let mut hasher = DefaultHasher::new();
+            targets.hash(&mut hasher);
+            hasher.finish()
+        };
+        let base = SubpassTargets {
+            color_formats: vec![vk::Format::R8G8B8A8_UNORM],
+            samples: vk::SampleCountFlags::TYPE_1,
+            ..Default::default()
+        };
+
+        let variants = [
+            vec![SubpassTargets { depth_format: Some(vk::Format::D32_SFLOAT), ..base.clone() }],
+            vec![SubpassTargets { depth_format: Some(vk::Format::D24_UNORM_S8_UINT), ..base.clone() }],
+            vec![SubpassTargets { samples: vk::SampleCountFlags::TYPE_4, ..base.clone() }],
+            vec![SubpassTargets { view_mask: 0b11, ..base.clone() }],
+            vec![SubpassTargets { color_formats: vec![vk::Format::B8G8R8A8_UNORM], ..base.clone() }],
+            vec![SubpassTargets { input_formats: vec![vk::Format::R8G8B8A8_UNORM], ..base.clone() }],
+            // the same subpass followed by another one
+            vec![base.clone(), base.clone()]
+        ];
+
+        let mut hashes = vec![hash_targets(std::slice::from_ref(&base))];
+        hashes.extend(variants.iter().map(|targets| hash_targets(targets)));
+        for (i, hash) in hashes.iter().enumerate() {
+            for other in &hashes[i + 1..] {
+                assert_ne!(hash, other, "Subpass targets {} collided with another renderpass", i);
+            }
+        }
+    }
+
+    #[test]
+    fn unset_dynamic_states_are_reported_in_order() {
+        let declared = [
+            vk::DynamicState::VIEWPORT,
+            vk::DynamicState::DEPTH_BIAS,
+            vk::DynamicState::SCISSOR,
+            vk::DynamicState::BLEND_CONSTANTS];
+        let set = [vk::DynamicState::SCISSOR, vk::DynamicState::VIEWPORT];
+
+        assert_eq!(unset_dynamic_states(&declared, &set),
+            vec![vk::DynamicState::DEPTH_BIAS, vk::DynamicState::BLEND_CONSTANTS]);
+        assert!(unset_dynamic_states(&declared[..1], &set).is_empty());
+        assert!(unset_dynamic_states(&[], &[]).is_empty());
+    }
+
+    #[test]
+    fn descriptor_layout_is_sorted_by_set_and_binding() {
+        let descriptor_types = HashMap::from([
+            ((1, 0), vk::DescriptorType::STORAGE_BUFFER),
+            ((0, 2), vk::DescriptorType::COMBINED_IMAGE_SAMPLER),
+            ((0, 0), vk::DescriptorType::UNIFORM_BUFFER)]);
+
+        assert_eq!(sorted_descriptor_layout(&descriptor_types), vec![
+            (0, 0, vk::DescriptorType::UNIFORM_BUFFER),
+            (0, 2, vk::DescriptorType::COMBINED_IMAGE_SAMPLER),
+            (1, 0, vk::DescriptorType::STORAGE_BUFFER)]);
+        assert!(sorted_descriptor_layout(&HashMap::new()).is_empty());
+    }
+
+    #[test]
+    fn integer_formats_are_detected() {
+        assert!(is_integer_format(vk::Format::R32_UINT));
+        assert!(is_integer_format(vk::Format::R16G16_SINT));
+        assert!(is_integer_format(vk::Format::A2B10G10R10_UINT_PACK32));
+        assert!(!is_integer_format(vk::Format::R8G8B8A8_UNORM));
+        assert!(!is_integer_format(vk::Format::R32_SFLOAT));
+        assert!(!is_integer_format(vk::Format::D32_SFLOAT));
+    }
+}
@@ -1,24 +1,30 @@
 use std::cell::RefCell;
 use std::rc::Rc;
+use ash::vk;
 use api_types::device::DeviceResource;
+use api_types::name::Name;
 use crate::pass_node::PassNode;
 
 #[derive(Debug)]
 pub struct PresentPassNode {
     pub swapchain_image: Rc<RefCell<DeviceResource>>,
-    name: String
+    /// The layout the image is left in, PRESENT_SRC_KHR unless the image is handed back to an
+    /// external presenter (see context::external_swapchain)
+    pub final_layout: vk::ImageLayout,
+    name: Name
 }
 
 #[derive(Default)]
 pub struct PresentPassNodeBuilder {
-    name: String,
-    swapchain_image: Option<Rc<RefCell<DeviceResource>>>
+    name: Name,
+    swapchain_image: Option<Rc<RefCell<DeviceResource>>>,
+    final_layout: Option<vk::ImageLayout>
 }
 
 impl PresentPassNode {
-    pub fn builder(name: String) -> PresentPassNodeBuilder {
+    pub fn builder(name: impl Into<Name>) -> PresentPassNodeBuilder {
         PresentPassNodeBuilder {
-            name,
+            name: name.into(),
             ..Default::default()
         }
     }
@@ -30,10 +36,16 @@ impl PresentPassNodeBuilder {
         self
     }
 
+    pub fn final_layout(mut self, final_layout: vk::ImageLayout) -> Self {
+        self.final_layout = Some(final_layout);
+        self
+    }
+
     pub fn build(mut self) -> Result<PresentPassNode, &'static str> {
         if let Some(swapchain_image) = self.swapchain_image {
             Ok(PresentPassNode {
                 swapchain_image,
+                final_layout: self.final_layout.unwrap_or(vk::ImageLayout::PRESENT_SRC_KHR),
                 name: self.name
             })
         } else {
@@ -47,6 +59,10 @@ impl PassNode for PresentPassNode {
         &self.name
     }
 
+    fn get_interned_name(&self) -> Name {
+        self.name
+    }
+
     fn get_reads(&self) -> Vec<u64> {
         vec![self.swapchain_image.borrow().get_handle()]
     }
@@ -1,12 +1,17 @@
 use std::cell::RefCell;
+use std::collections::hash_map::DefaultHasher;
 use std::collections::HashMap;
 use std::fmt::{Debug, Formatter};
+use std::hash::{Hash, Hasher};
 use std::rc::Rc;
 
 use ash::{vk};
-use api_types::device::{DeviceRenderpass, DeviceWrapper};
+use api_types::device::{DeviceRenderpass, DeviceResource, DeviceWrapper};
 use profiling::enter_span;
 use crate::attachment::AttachmentReference;
+use crate::binding::{BindingType, ResourceBinding};
+use crate::graphics_pass_node::Multiview;
+use crate::graph_debug::graph_debug;
 
 pub struct StencilAttachmentInfo {
     pub stencil_load_op: vk::AttachmentLoadOp,
@@ -22,14 +27,172 @@ pub struct AttachmentInfo {
     pub stencil_attachment: Option<StencilAttachmentInfo>
 }
 
+/// Attachments used by a single subpass of a renderpass group
+pub struct SubpassAttachments<'a> {
+    pub color_attachments: &'a [AttachmentReference],
+    pub depth_attachment: &'a Option<AttachmentReference>,
+    pub input_attachments: &'a [ResourceBinding],
+    pub multiview: Option<Multiview>
+}
+
+/// A unique attachment across all subpasses of a renderpass group
+struct GroupAttachment {
+    resource: Rc<RefCell<DeviceResource>>,
+    handle: u64,
+    format: vk::Format,
+    samples: vk::SampleCountFlags,
+    initial_layout: vk::ImageLayout,
+    final_layout: vk::ImageLayout,
+    // taken from the attachment's first use in the group
+    load_op: vk::AttachmentLoadOp,
+    clear_value: vk::ClearValue,
+    // whether the clear of its first use covers the stencil
+    clears_stencil: bool,
+    // taken from the attachment's last use as a color or depth attachment
+    store_op: vk::AttachmentStoreOp,
+    first_subpass: usize,
+    last_subpass: usize
+}
+
+fn add_group_attachment(
+    attachments: &mut Vec<GroupAttachment>,
+    resource: &Rc<RefCell<DeviceResource>>,
+    samples: vk::SampleCountFlags,
+    layout: vk::ImageLayout,
+    load_op: vk::AttachmentLoadOp,
+    clear_value: vk::ClearValue,
+    clears_stencil: bool,
+    store_op: Option<vk::AttachmentStoreOp>,
+    subpass_index: usize) -> u32 {
+
+    let handle = resource.borrow().get_handle();
+    match attachments.iter().position(|attachment| attachment.handle == handle) {
+        Some(index) => {
+            let attachment = &mut attachments[index];
+            attachment.final_layout = layout;
+            attachment.last_subpass = subpass_index;
+            if let Some(store_op) = store_op {
+                attachment.store_op = store_op;
+            }
+            index as u32
+        },
+        None => {
+            let format = resource.borrow().get_image().format;
+            attachments.push(GroupAttachment {
+                resource: resource.clone(),
+                handle,
+                format,
+                samples,
+                initial_layout: layout,
+                final_layout: layout,
+                load_op,
+                clear_value,
+                clears_stencil,
+                store_op: store_op.unwrap_or(vk::AttachmentStoreOp::STORE),
+                first_subpass: subpass_index,
+                last_subpass: subpass_index
+            });
+            (attachments.len() - 1) as u32
+        }
+    }
+}
+
+/// Stencil ops for an attachment of `format`. The stencil of a combined depth-stencil format
+/// is stored along with its depth, and loaded along with it unless the depth is cleared
+/// without `clears_stencil` (see AttachmentLoad::ClearDepthStencil), which leaves the stencil
+/// as is. Other formats have no stencil to keep
+fn stencil_ops(
+    format: vk::Format,
+    load_op: vk::AttachmentLoadOp,
+    clears_stencil: bool,
+    store_op: vk::AttachmentStoreOp) -> (vk::AttachmentLoadOp, vk::AttachmentStoreOp) {
+    if !util::image::get_aspect_mask_from_format(format).contains(vk::ImageAspectFlags::STENCIL) {
+        return (vk::AttachmentLoadOp::DONT_CARE, vk::AttachmentStoreOp::DONT_CARE);
+    }
+    match load_op {
+        vk::AttachmentLoadOp::CLEAR if !clears_stencil => (vk::AttachmentLoadOp::LOAD, store_op),
+        _ => (load_op, store_op)
+    }
+}
+
+/// The stages and accesses a subpass uses its color, depth and input attachments with
+fn subpass_attachment_usage(color: bool, depth: bool, input: bool) -> (vk::PipelineStageFlags, vk::AccessFlags) {
+    let mut stages = vk::PipelineStageFlags::empty();
+    let mut access = vk::AccessFlags::empty();
+    if color {
+        stages |= vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT;
+        access |= vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE;
+    }
+    if depth {
+        stages |= vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS;
+        access |= vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE;
+    }
+    if input {
+        stages |= vk::PipelineStageFlags::FRAGMENT_SHADER;
+        access |= vk::AccessFlags::INPUT_ATTACHMENT_READ;
+    }
+    (stages, access)
+}
+
+/// The view mask of each subpass and the renderpass' correlation masks, or empty vectors if
+/// it doesn't use multiview. Either every subpass uses multiview or none do
+fn multiview_masks(multiviews: &[Option<Multiview>]) -> (Vec<u32>, Vec<u32>) {
+    if multiviews.iter().all(|multiview| multiview.is_none()) {
+        return (Vec::new(), Vec::new());
+    }
+    assert!(multiviews.iter().all(|multiview| multiview.is_some()),
+        "Either every subpass of a renderpass uses multiview or none do");
+
+    let view_masks = multiviews.iter().map(|multiview| multiview.unwrap().view_mask).collect();
+    let mut correlation_masks: Vec<u32> = Vec::new();
+    for multiview in multiviews.iter().flatten() {
+        if multiview.correlation_mask != 0 && !correlation_masks.contains(&multiview.correlation_mask) {
+            correlation_masks.push(multiview.correlation_mask);
+        }
+    }
+    (view_masks, correlation_masks)
+}
+
+fn hash_attachment_descs<H: Hasher>(attachment_descs: &[vk::AttachmentDescription], state: &mut H) {
+    attachment_descs.len().hash(state);
+    for desc in attachment_descs {
+        desc.format.hash(state);
+        desc.samples.hash(state);
+        desc.load_op.hash(state);
+        desc.store_op.hash(state);
+        desc.stencil_load_op.hash(state);
+        desc.stencil_store_op.hash(state);
+        desc.initial_layout.hash(state);
+        desc.final_layout.hash(state);
+    }
+}
+
+fn hash_attachment_refs<H: Hasher>(attachment_refs: &[vk::AttachmentReference], state: &mut H) {
+    attachment_refs.len().hash(state);
+    for attachment_ref in attachment_refs {
+        attachment_ref.attachment.hash(state);
+        attachment_ref.layout.hash(state);
+    }
+}
+
+struct CachedRenderpass {
+    renderpass: Rc<RefCell<DeviceRenderpass>>,
+    formats: Vec<vk::Format>
+}
+
+/// Renderpasses are cached by the contents of their attachment descriptions and subpass
+/// references, so any passes with compatible attachments share a renderpass regardless
+/// of their names
 pub struct VulkanRenderpassManager {
-    renderpass_map: HashMap<String, Rc<RefCell<DeviceRenderpass>>>
+    renderpass_map: HashMap<u64, CachedRenderpass>,
+    swapchain_format: Option<vk::Format>
 }
 
 impl Debug for VulkanRenderpassManager {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("VulkanRenderpassManager")
             .field("num renderpasses", &self.renderpass_map.len())
+            .field("swapchain format", &self.swapchain_format)
             .finish()
     }
 }
@@ -38,40 +201,62 @@ impl VulkanRenderpassManager {
 
     pub fn new() -> Self {
         VulkanRenderpassManager {
-            renderpass_map: HashMap::new()
+            renderpass_map: HashMap::new(),
+            swapchain_format: None
         }
     }
 
+    pub fn get_renderpass_count(&self) -> usize { self.renderpass_map.len() }
+
+    /// Evicts every renderpass with an attachment in the previous swapchain format when the
+    /// swapchain is recreated with a different one, since nothing will render to it again
+    pub fn set_swapchain_format(&mut self, format: Option<vk::Format>) {
+        if format == self.swapchain_format {
+            return;
+        }
+
+        if let Some(old_format) = self.swapchain_format {
+            let before = self.renderpass_map.len();
+            self.renderpass_map.retain(|_, cached| !cached.formats.contains(&old_format));
+            log::trace!(target: "renderpass", "Swapchain format changed from {:?} to {:?}, evicted {} renderpasses",
+                old_format,
+                format,
+                before - self.renderpass_map.len());
+        }
+        self.swapchain_format = format;
+    }
+
     pub fn create_or_fetch_renderpass(
         &mut self,
         pass_name: &str,
         color_attachments: &[AttachmentReference],
         depth_attachment: &Option<AttachmentReference>,
+        multiview: Option<Multiview>,
         device: Rc<RefCell<DeviceWrapper>>) -> Rc<RefCell<DeviceRenderpass>> {
         enter_span!(tracing::Level::TRACE, "Create or Fetch Renderpass");
 
-        let renderpass = self.renderpass_map.entry(pass_name.to_string()).or_insert_with_key(|_| {
-            // no cached renderpass found, create it and cache it now
-            let mut attachment_descs: Vec<vk::AttachmentDescription> = Vec::new();
-            let mut color_attachment_refs: Vec<vk::AttachmentReference> = Vec::new();
-            let mut depth_ref: Option<vk::AttachmentReference> = None;
-
+        let mut attachment_descs: Vec<vk::AttachmentDescription> = Vec::new();
+        let mut color_attachment_refs: Vec<vk::AttachmentReference> = Vec::new();
+        let mut depth_ref: Option<vk::AttachmentReference> = None;
+        {
             let mut attachment_index = 0;
             // We (potentially) add the depth target as the first attachment in case
             // we execute a depth-only draw
             if let Some(depth_attachment) = depth_attachment {
                 // assert_eq!(depth_attachment.layout, vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL, "Invalid layout for depth attachment");
                 // attachment_refs.push(vk::AttachmentReference::builder()
-                let mut load_op = vk::AttachmentLoadOp::LOAD;
-                if (depth_attachment.layout == vk::ImageLayout::UNDEFINED) {
-                    load_op = vk::AttachmentLoadOp::DONT_CARE;
-                }
-
+                let (stencil_load_op, stencil_store_op) = stencil_ops(
+                    depth_attachment.format,
+                    depth_attachment.load_op,
+                    depth_attachment.clears_stencil(),
+                    depth_attachment.store_op);
                 attachment_descs.push(vk::AttachmentDescription::builder()
                     .format(depth_attachment.format)
                     .samples(depth_attachment.samples)
-                    .load_op(load_op)
-                    .store_op(vk::AttachmentStoreOp::STORE)
+                    .load_op(depth_attachment.load_op)
+                    .store_op(depth_attachment.store_op)
+                    .stencil_load_op(stencil_load_op)
+                    .stencil_store_op(stencil_store_op)
                     .initial_layout(depth_attachment.layout)
                     // TODO: add support for separateDepthStencilLayouts
                     .final_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
@@ -87,15 +272,11 @@ impl VulkanRenderpassManager {
             }
 
             for color_attachment in color_attachments {
-                let mut load_op = vk::AttachmentLoadOp::LOAD;
-                if (color_attachment.layout == vk::ImageLayout::UNDEFINED) {
-                    load_op = vk::AttachmentLoadOp::DONT_CARE;
-                }
                 attachment_descs.push(vk::AttachmentDescription::builder()
                     .format(color_attachment.format)
                     .samples(color_attachment.samples)
-                    .load_op(load_op)
-                    .store_op(vk::AttachmentStoreOp::STORE)
+                    .load_op(color_attachment.load_op)
+                    .store_op(color_attachment.store_op)
                     .initial_layout(color_attachment.layout)
                     .final_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
                     .build());
@@ -105,7 +286,21 @@ impl VulkanRenderpassManager {
                     .build());
                 attachment_index += 1;
             }
+        }
+
+        let renderpass_key = {
+            let mut hasher = DefaultHasher::new();
+            hash_attachment_descs(&attachment_descs, &mut hasher);
+            // the depth attachment, if any, always comes first
+            depth_ref.is_some().hash(&mut hasher);
+            multiview.hash(&mut hasher);
+            hasher.finish()
+        };
 
+        graph_debug!(pass = pass_name, key = renderpass_key, hit = self.renderpass_map.contains_key(&renderpass_key),
+            "renderpass cache lookup");
+        let cached = self.renderpass_map.entry(renderpass_key).or_insert_with(|| {
+            // no cached renderpass found, create it and cache it now
             let mut subpass = vk::SubpassDescription::builder()
                 .color_attachments(&color_attachment_refs)
                 .flags(vk::SubpassDescriptionFlags::empty())
@@ -123,14 +318,331 @@ impl VulkanRenderpassManager {
                 .dst_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
                 .dependency_flags(vk::DependencyFlags::empty());
 
-            let renderpass_create_info = vk::RenderPassCreateInfo::builder()
+            let (view_masks, correlation_masks) = multiview_masks(std::slice::from_ref(&multiview));
+            let mut multiview_create_info = vk::RenderPassMultiviewCreateInfo::builder()
+                .view_masks(&view_masks)
+                .correlation_masks(&correlation_masks);
+            let mut renderpass_create_info = vk::RenderPassCreateInfo::builder()
                 .flags(vk::RenderPassCreateFlags::empty())
                 .attachments(&attachment_descs)
                 .subpasses(std::slice::from_ref(&subpass))
-                .dependencies(std::slice::from_ref(&subpass_dependency)).build();
+                .dependencies(std::slice::from_ref(&subpass_dependency));
+            if multiview.is_some() {
+                renderpass_create_info = renderpass_create_info.push_next(&mut multiview_create_info);
+            }
+            let renderpass_create_info = renderpass_create_info.build();
+
+            CachedRenderpass {
+                renderpass: Rc::new(RefCell::new(DeviceWrapper::create_renderpass(device, &renderpass_create_info, pass_name))),
+                formats: attachment_descs.iter().map(|desc| desc.format).collect()
+            }
+        });
+        cached.renderpass.clone()
+    }
+
+    /// Creates (or fetches) a renderpass with one subpass per entry in `subpasses`.
+    /// Returns the renderpass along with its attachments and their clear values in framebuffer order
+    pub fn create_or_fetch_subpass_renderpass(
+        &mut self,
+        group_name: &str,
+        subpasses: &[SubpassAttachments],
+        device: Rc<RefCell<DeviceWrapper>>) -> (Rc<RefCell<DeviceRenderpass>>, Vec<Rc<RefCell<DeviceResource>>>, Vec<vk::ClearValue>) {
+        enter_span!(tracing::Level::TRACE, "Create or Fetch Subpass Renderpass");
 
-            Rc::new(RefCell::new(DeviceWrapper::create_renderpass(device, &renderpass_create_info, pass_name)))
-        }).clone();
-        renderpass
+        // Attachments are shared between subpasses, so gather the unique set first
+        // along with the layouts used by each subpass reference
+        let mut attachments: Vec<GroupAttachment> = Vec::new();
+        let mut color_refs: Vec<Vec<vk::AttachmentReference>> = Vec::new();
+        let mut depth_refs: Vec<Option<vk::AttachmentReference>> = Vec::new();
+        let mut input_refs: Vec<Vec<vk::AttachmentReference>> = Vec::new();
+        let multiviews: Vec<Option<Multiview>> = subpasses.iter().map(|subpass| subpass.multiview).collect();
+        let (view_masks, correlation_masks) = multiview_masks(&multiviews);
+        for (subpass_index, subpass) in subpasses.iter().enumerate() {
+            // TODO: add support for separateDepthStencilLayouts
+            let depth_ref = subpass.depth_attachment.as_ref().map(|depth_attachment| {
+                // the first use of an attachment is expected to be in its post-barrier layout
+                let index = add_group_attachment(
+                    &mut attachments,
+                    &depth_attachment.resource_image,
+                    depth_attachment.samples,
+                    depth_attachment.layout,
+                    depth_attachment.load_op,
+                    depth_attachment.get_clear_value(),
+                    depth_attachment.clears_stencil(),
+                    Some(depth_attachment.store_op),
+                    subpass_index);
+                attachments[index as usize].final_layout = vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL;
+                vk::AttachmentReference::builder()
+                    .attachment(index)
+                    .layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+                    .build()
+            });
+            depth_refs.push(depth_ref);
+
+            let mut subpass_color_refs: Vec<vk::AttachmentReference> = Vec::new();
+            for color_attachment in subpass.color_attachments {
+                let index = add_group_attachment(
+                    &mut attachments,
+                    &color_attachment.resource_image,
+                    color_attachment.samples,
+                    color_attachment.layout,
+                    color_attachment.load_op,
+                    color_attachment.get_clear_value(),
+                    false,
+                    Some(color_attachment.store_op),
+                    subpass_index);
+                attachments[index as usize].final_layout = vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL;
+                subpass_color_refs.push(vk::AttachmentReference::builder()
+                    .attachment(index)
+                    .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                    .build());
+            }
+            color_refs.push(subpass_color_refs);
+
+            let mut subpass_input_refs: Vec<vk::AttachmentReference> = Vec::new();
+            for input_attachment in subpass.input_attachments {
+                let layout = match &input_attachment.binding_info.binding_type {
+                    BindingType::Image(image_binding) => image_binding.layout,
+                    BindingType::Buffer(_) => {
+                        unreachable!("GraphicsPassNode's builder only accepts image input attachments")
+                    }
+                };
+                let index = add_group_attachment(
+                    &mut attachments,
+                    &input_attachment.resource,
+                    vk::SampleCountFlags::TYPE_1,
+                    layout,
+                    // input attachments are read, so their contents must be loaded
+                    vk::AttachmentLoadOp::LOAD,
+                    vk::ClearValue::default(),
+                    false,
+                    None,
+                    subpass_index);
+                subpass_input_refs.push(vk::AttachmentReference::builder()
+                    .attachment(index)
+                    .layout(layout)
+                    .build());
+            }
+            input_refs.push(subpass_input_refs);
+        }
+
+        let resources: Vec<Rc<RefCell<DeviceResource>>> = attachments.iter().map(|attachment| {
+            attachment.resource.clone()
+        }).collect();
+        let clear_values: Vec<vk::ClearValue> = attachments.iter().map(|attachment| {
+            attachment.clear_value
+        }).collect();
+
+        let attachment_descs: Vec<vk::AttachmentDescription> = attachments.iter().map(|attachment| {
+            let (stencil_load_op, stencil_store_op) = stencil_ops(attachment.format, attachment.load_op, attachment.clears_stencil, attachment.store_op);
+            vk::AttachmentDescription::builder()
+                .format(attachment.format)
+                .samples(attachment.samples)
+                .load_op(attachment.load_op)
+                .store_op(attachment.store_op)
+                .stencil_load_op(stencil_load_op)
+                .stencil_store_op(stencil_store_op)
+                .initial_layout(attachment.initial_layout)
+                .final_layout(attachment.final_layout)
+                .build()
+        }).collect();
+
+        let renderpass_key = {
+            let mut hasher = DefaultHasher::new();
+            hash_attachment_descs(&attachment_descs, &mut hasher);
+            subpasses.len().hash(&mut hasher);
+            for subpass_index in 0..subpasses.len() {
+                hash_attachment_refs(&color_refs[subpass_index], &mut hasher);
+                hash_attachment_refs(&input_refs[subpass_index], &mut hasher);
+                hash_attachment_refs(depth_refs[subpass_index].as_slice(), &mut hasher);
+            }
+            multiviews.hash(&mut hasher);
+            hasher.finish()
+        };
+
+        graph_debug!(group = group_name, key = renderpass_key, hit = self.renderpass_map.contains_key(&renderpass_key),
+            "subpass renderpass cache lookup");
+        let cached = self.renderpass_map.entry(renderpass_key).or_insert_with(|| {
+
+            // attachments which are used both before and after a subpass need to be
+            // preserved through it
+            let preserve_refs: Vec<Vec<u32>> = (0..subpasses.len()).map(|subpass_index| {
+                attachments.iter().enumerate().filter(|(attachment_index, attachment)| {
+                    let index = *attachment_index as u32;
+                    let used = color_refs[subpass_index].iter().any(|r| r.attachment == index) ||
+                        input_refs[subpass_index].iter().any(|r| r.attachment == index) ||
+                        depth_refs[subpass_index].map_or(false, |r| r.attachment == index);
+                    !used && attachment.first_subpass < subpass_index && attachment.last_subpass > subpass_index
+                }).map(|(attachment_index, _)| attachment_index as u32).collect()
+            }).collect();
+
+            let subpass_descs: Vec<vk::SubpassDescription> = (0..subpasses.len()).map(|subpass_index| {
+                let mut subpass = vk::SubpassDescription::builder()
+                    .color_attachments(&color_refs[subpass_index])
+                    .input_attachments(&input_refs[subpass_index])
+                    .preserve_attachments(&preserve_refs[subpass_index])
+                    .flags(vk::SubpassDescriptionFlags::empty())
+                    .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS);
+                if let Some(depth_ref) = &depth_refs[subpass_index] {
+                    subpass = subpass.depth_stencil_attachment(depth_ref);
+                }
+                subpass.build()
+            }).collect();
+
+            let usage = |subpass_index: usize| subpass_attachment_usage(
+                !color_refs[subpass_index].is_empty(),
+                depth_refs[subpass_index].is_some(),
+                !input_refs[subpass_index].is_empty());
+
+            // the barriers recorded before the renderpass make earlier writes visible, so the
+            // first subpass only has to wait for them at the stages it uses its attachments in
+            let (first_stages, first_access) = usage(0);
+            let mut subpass_dependencies: Vec<vk::SubpassDependency> = vec![vk::SubpassDependency::builder()
+                .src_subpass(vk::SUBPASS_EXTERNAL)
+                .dst_subpass(0)
+                .src_stage_mask(first_stages)
+                .dst_stage_mask(first_stages)
+                .src_access_mask(vk::AccessFlags::NONE)
+                .dst_access_mask(first_access)
+                .dependency_flags(vk::DependencyFlags::empty())
+                .build()];
+            // each subpass may read the attachments written by the previous subpass
+            for subpass_index in 1..subpasses.len() {
+                subpass_dependencies.push(vk::SubpassDependency::builder()
+                    .src_subpass((subpass_index - 1) as u32)
+                    .dst_subpass(subpass_index as u32)
+                    .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT |
+                        vk::PipelineStageFlags::LATE_FRAGMENT_TESTS)
+                    .dst_stage_mask(vk::PipelineStageFlags::FRAGMENT_SHADER |
+                        vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS |
+                        vk::PipelineStageFlags::LATE_FRAGMENT_TESTS |
+                        vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+                    .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE |
+                        vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+                    .dst_access_mask(vk::AccessFlags::INPUT_ATTACHMENT_READ |
+                        vk::AccessFlags::COLOR_ATTACHMENT_READ |
+                        vk::AccessFlags::COLOR_ATTACHMENT_WRITE |
+                        vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ |
+                        vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+                    // multiview subpasses only depend on the same view of the previous subpass
+                    .dependency_flags(match view_masks.is_empty() {
+                        true => vk::DependencyFlags::BY_REGION,
+                        false => vk::DependencyFlags::BY_REGION | vk::DependencyFlags::VIEW_LOCAL
+                    })
+                    .build());
+            }
+            // the last subpass' attachment writes are made available, and the barriers recorded
+            // after the renderpass wait on them
+            let (last_stages, last_access) = usage(subpasses.len() - 1);
+            subpass_dependencies.push(vk::SubpassDependency::builder()
+                .src_subpass((subpasses.len() - 1) as u32)
+                .dst_subpass(vk::SUBPASS_EXTERNAL)
+                .src_access_mask(last_access & (vk::AccessFlags::COLOR_ATTACHMENT_WRITE | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE))
+                .dst_access_mask(vk::AccessFlags::NONE)
+                .src_stage_mask(last_stages)
+                .dst_stage_mask(vk::PipelineStageFlags::BOTTOM_OF_PIPE)
+                .dependency_flags(vk::DependencyFlags::empty())
+                .build());
+
+            let mut multiview_create_info = vk::RenderPassMultiviewCreateInfo::builder()
+                .view_masks(&view_masks)
+                .correlation_masks(&correlation_masks);
+            let mut renderpass_create_info = vk::RenderPassCreateInfo::builder()
+                .flags(vk::RenderPassCreateFlags::empty())
+                .attachments(&attachment_descs)
+                .subpasses(&subpass_descs)
+                .dependencies(&subpass_dependencies);
+            if !view_masks.is_empty() {
+                renderpass_create_info = renderpass_create_info.push_next(&mut multiview_create_info);
+            }
+            let renderpass_create_info = renderpass_create_info.build();
+
+            CachedRenderpass {
+                renderpass: Rc::new(RefCell::new(DeviceWrapper::create_renderpass(device, &renderpass_create_info, group_name))),
+                formats: attachment_descs.iter().map(|desc| desc.format).collect()
+            }
+        });
+
+        (cached.renderpass.clone(), resources, clear_values)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn subpass_usage_covers_each_kind_of_attachment() {
+        let (stages, access) = subpass_attachment_usage(true, false, true);
+        assert_eq!(stages, vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | vk::PipelineStageFlags::FRAGMENT_SHADER);
+        assert_eq!(access, vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE |
+            vk::AccessFlags::INPUT_ATTACHMENT_READ);
+
+        let (stages, access) = subpass_attachment_usage(false, true, false);
+        assert_eq!(stages, vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS);
+        assert_eq!(access, vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE);
+    }
+
+    #[test]
+    fn renderpasses_without_multiview_have_no_masks() {
+        let (view_masks, correlation_masks) = multiview_masks(&[None, None]);
+        assert!(view_masks.is_empty());
+        assert!(correlation_masks.is_empty());
+    }
+
+    #[test]
+    fn each_subpass_has_its_view_mask() {
+        let (view_masks, correlation_masks) = multiview_masks(&[
+            Some(Multiview::new(2)),
+            Some(Multiview::new(32)),
+            Some(Multiview { view_mask: 0b100, correlation_mask: 0 })]);
+        assert_eq!(view_masks, vec![0b11, u32::MAX, 0b100]);
+        // uncorrelated views have no correlation masks
+        assert!(correlation_masks.is_empty());
+    }
+
+    #[test]
+    fn correlation_masks_are_deduplicated() {
+        let (view_masks, correlation_masks) = multiview_masks(&[
+            Some(Multiview::stereo()),
+            Some(Multiview::stereo()),
+            Some(Multiview::new(4).correlated(0b1100)),
+            Some(Multiview::new(4).correlated(0b11))]);
+        assert_eq!(view_masks, vec![0b11, 0b11, 0b1111, 0b1111]);
+        assert_eq!(correlation_masks, vec![0b11, 0b1100]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Either every subpass")]
+    fn multiview_must_cover_every_subpass() {
+        multiview_masks(&[Some(Multiview::stereo()), None]);
+    }
+
+    #[test]
+    fn depth_clears_load_the_stencil() {
+        let ops = stencil_ops(vk::Format::D24_UNORM_S8_UINT, vk::AttachmentLoadOp::CLEAR, false, vk::AttachmentStoreOp::STORE);
+        assert_eq!(ops, (vk::AttachmentLoadOp::LOAD, vk::AttachmentStoreOp::STORE));
+    }
+
+    #[test]
+    fn depth_stencil_clears_clear_the_stencil() {
+        let ops = stencil_ops(vk::Format::D32_SFLOAT_S8_UINT, vk::AttachmentLoadOp::CLEAR, true, vk::AttachmentStoreOp::STORE);
+        assert_eq!(ops, (vk::AttachmentLoadOp::CLEAR, vk::AttachmentStoreOp::STORE));
+    }
+
+    #[test]
+    fn stencil_follows_other_load_ops() {
+        for load_op in [vk::AttachmentLoadOp::LOAD, vk::AttachmentLoadOp::DONT_CARE] {
+            let ops = stencil_ops(vk::Format::D24_UNORM_S8_UINT, load_op, false, vk::AttachmentStoreOp::DONT_CARE);
+            assert_eq!(ops, (load_op, vk::AttachmentStoreOp::DONT_CARE));
+        }
+    }
+
+    #[test]
+    fn formats_without_stencil_dont_keep_it() {
+        for format in [vk::Format::D32_SFLOAT, vk::Format::X8_D24_UNORM_PACK32, vk::Format::D16_UNORM, vk::Format::R8G8B8A8_UNORM] {
+            let ops = stencil_ops(format, vk::AttachmentLoadOp::CLEAR, true, vk::AttachmentStoreOp::STORE);
+            assert_eq!(ops, (vk::AttachmentLoadOp::DONT_CARE, vk::AttachmentStoreOp::DONT_CARE));
+        }
     }
 }
\ No newline at end of file
@@ -0,0 +1,275 @@
+use std::cell::RefCell;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
+use ash::vk;
+use ash::vk::Handle;
+use api_types::deletion_queue::DeferredDestruction;
+use api_types::device::{DeviceResource, DeviceWrapper, ResourceType};
+use api_types::name::Name;
+use context::descriptor_pool_manager::DescriptorPoolConfig;
+use crate::graph_cache::hash_bindings;
+use crate::graphics_pass_node::GraphicsPassNode;
+
+/// A retained node's commands, recorded into a secondary command buffer which later frames
+/// execute instead of running the node's fill callback (see PassNodeBuilder::retained).
+///
+/// The command buffer and its descriptor sets come from pools owned by the recording, since the
+/// render context's pools are reset every frame. Both pools are destroyed once the GPU is done
+/// with the recording after it's dropped
+pub(crate) struct RetainedRecording {
+    command_pool: vk::CommandPool,
+    command_buffer: vk::CommandBuffer,
+    descriptor_pool: vk::DescriptorPool,
+    device: Rc<RefCell<DeviceWrapper>>
+}
+
+impl Drop for RetainedRecording {
+    fn drop(&mut self) {
+        let mut device = self.device.borrow_mut();
+        device.defer_destruction(DeferredDestruction::CommandPool(self.command_pool));
+        device.defer_destruction(DeferredDestruction::DescriptorPool(self.descriptor_pool));
+    }
+}
+
+impl RetainedRecording {
+    /// An empty recording. `max_sets` must be at least the number of descriptor set layouts of
+    /// the node's pipeline
+    pub(crate) fn new(
+        device: Rc<RefCell<DeviceWrapper>>,
+        queue_family_index: u32,
+        max_sets: u32,
+        name: &str) -> Self {
+
+        let (command_pool, command_buffer, descriptor_pool) = {
+            let device = device.borrow();
+            let pool_create_info = vk::CommandPoolCreateInfo::builder()
+                .queue_family_index(queue_family_index)
+                .build();
+            let command_pool = unsafe {
+                device.get().create_command_pool(&pool_create_info, None)
+                    .expect("Failed to create command pool for retained node")
+            };
+            let allocate_info = vk::CommandBufferAllocateInfo::builder()
+                .command_pool(command_pool)
+                .level(vk::CommandBufferLevel::SECONDARY)
+                .command_buffer_count(1)
+                .build();
+            let command_buffer = unsafe {
+                device.get().allocate_command_buffers(&allocate_info)
+                    .expect("Failed to allocate command buffer for retained node")[0]
+            };
+
+            // a single node's sets fit comfortably in a pool of the per-frame pools' initial size
+            let config = DescriptorPoolConfig::default();
+            let descriptor_pool_create_info = vk::DescriptorPoolCreateInfo::builder()
+                .pool_sizes(&config.pool_sizes)
+                .max_sets(max_sets.max(1))
+                .build();
+            let descriptor_pool = unsafe {
+                device.get().create_descriptor_pool(&descriptor_pool_create_info, None)
+                    .expect("Failed to create descriptor pool for retained node")
+            };
+
+            device.set_debug_name(vk::ObjectType::COMMAND_POOL, command_pool.as_raw(), &format!("{}_retained", name));
+            device.set_debug_name(vk::ObjectType::COMMAND_BUFFER, command_buffer.as_raw(), &format!("{}_retained", name));
+            device.set_debug_name(vk::ObjectType::DESCRIPTOR_POOL, descriptor_pool.as_raw(), &format!("{}_retained", name));
+            (command_pool, command_buffer, descriptor_pool)
+        };
+
+        RetainedRecording {
+            command_pool,
+            command_buffer,
+            descriptor_pool,
+            device
+        }
+    }
+
+    pub(crate) fn get_command_buffer(&self) -> vk::CommandBuffer { self.command_buffer }
+
+    /// Sets which live as long as the recording. Named `name` followed by their index in `layouts`
+    pub(crate) fn allocate_descriptor_sets(
+        &self,
+        layouts: &[vk::DescriptorSetLayout],
+        name: &str) -> Vec<vk::DescriptorSet> {
+
+        if layouts.is_empty() {
+            return Vec::new();
+        }
+        let device = self.device.borrow();
+        let allocate_info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(self.descriptor_pool)
+            .set_layouts(layouts)
+            .build();
+        let sets = unsafe {
+            device.get().allocate_descriptor_sets(&allocate_info)
+                .expect("Failed to allocate descriptor sets for retained node")
+        };
+        for (index, set) in sets.iter().enumerate() {
+            device.set_debug_name(vk::ObjectType::DESCRIPTOR_SET, set.as_raw(), &format!("{}_retained_{}", name, index));
+        }
+        sets
+    }
+}
+
+/// Recordings of retained nodes by node name. A recording is discarded as soon as a frame
+/// doesn't execute its node, or when it's replaced by a recording made with a different key
+pub(crate) struct RetainedPasses<R = RetainedRecording> {
+    // each recording with the retained_key it was made with
+    recordings: HashMap<Name, (u64, R)>,
+    // nodes executed since the last call to end_frame
+    used: HashSet<Name>
+}
+
+impl<R> Default for RetainedPasses<R> {
+    fn default() -> Self {
+        RetainedPasses {
+            recordings: HashMap::new(),
+            used: HashSet::new()
+        }
+    }
+}
+
+impl<R> RetainedPasses<R> {
+    /// The recording of `name`, if it was made with `key`
+    pub(crate) fn get(&mut self, name: Name, key: u64) -> Option<&R> {
+        self.used.insert(name);
+        self.recordings.get(&name)
+            .filter(|(recording_key, _)| *recording_key == key)
+            .map(|(_, recording)| recording)
+    }
+
+    /// Replaces any earlier recording of `name`
+    pub(crate) fn insert(&mut self, name: Name, key: u64, recording: R) {
+        self.used.insert(name);
+        self.recordings.insert(name, (key, recording));
+    }
+
+    pub(crate) fn end_frame(&mut self) {
+        let used = std::mem::take(&mut self.used);
+        self.recordings.retain(|name, _| used.contains(name));
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.recordings.clear();
+        self.used.clear();
+    }
+}
+
+fn is_per_frame(resource: &Rc<RefCell<DeviceResource>>) -> bool {
+    matches!(resource.borrow().resource_type.as_ref(), Some(ResourceType::Buffer(buffer)) if buffer.per_frame)
+}
+
+/// Whether a node's recording can be replayed by later frames. Ranges of buffers suballocated
+/// every frame (e.g. uniforms pushed to a DynamicUploadBuffer) move or hold other data the next
+/// frame, so nodes using them are recorded every frame
+pub(crate) fn is_retainable(node: &GraphicsPassNode) -> bool {
+    let per_frame_binding = node.inputs.iter().chain(&node.outputs)
+        .any(|binding| is_per_frame(&binding.resource));
+    let per_frame_geometry = node.vertex_buffers.iter().map(|vertex_buffer| &vertex_buffer.resource)
+        .chain(node.index_buffer.as_ref().map(|index_buffer| &index_buffer.resource))
+        .any(is_per_frame);
+    !per_frame_binding && !per_frame_geometry
+}
+
+/// Hash of everything a retained node's recording depends on besides its fill callback, whose
+/// output is covered by `version`: the renderpass and pipeline it was recorded against, the
+/// resources and layouts written to its descriptors, its vertex and index buffers, and its
+/// dynamic state. The viewport and scissor of nodes without their own cover the render area,
+/// so it's part of the key too
+pub(crate) fn retained_key(
+    node: &GraphicsPassNode,
+    version: u64,
+    renderpass: vk::RenderPass,
+    pipeline: vk::Pipeline) -> u64 {
+
+    let mut hasher = DefaultHasher::new();
+    version.hash(&mut hasher);
+    renderpass.as_raw().hash(&mut hasher);
+    pipeline.as_raw().hash(&mut hasher);
+    hash_bindings(&node.inputs, &mut hasher);
+    hash_bindings(&node.outputs, &mut hasher);
+
+    node.vertex_buffers.len().hash(&mut hasher);
+    for vertex_buffer in &node.vertex_buffers {
+        vertex_buffer.resource.borrow().get_handle().hash(&mut hasher);
+        vertex_buffer.offset.hash(&mut hasher);
+    }
+    node.index_buffer.as_ref().map(|index_buffer| {
+        (index_buffer.resource.borrow().get_handle(), index_buffer.offset, index_buffer.index_type.as_raw())
+    }).hash(&mut hasher);
+
+    node.viewport.map(|viewport| {
+        [viewport.x, viewport.y, viewport.width, viewport.height, viewport.min_depth, viewport.max_depth]
+            .map(f32::to_bits)
+    }).hash(&mut hasher);
+    node.scissor.map(|scissor| {
+        (scissor.offset.x, scissor.offset.y, scissor.extent.width, scissor.extent.height)
+    }).hash(&mut hasher);
+    node.get_render_area().map(|extent| (extent.width, extent.height)).hash(&mut hasher);
+
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recordings_are_replayed_with_the_same_key() {
+        let mut passes: RetainedPasses<u32> = RetainedPasses::default();
+        let name = Name::new("static geometry");
+        assert_eq!(passes.get(name, 1), None);
+        passes.insert(name, 1, 10);
+        assert_eq!(passes.get(name, 1), Some(&10));
+        // a different key means something the recording depends on has changed
+        assert_eq!(passes.get(name, 2), None);
+    }
+
+    #[test]
+    fn rerecording_replaces_the_recording() {
+        let mut passes: RetainedPasses<u32> = RetainedPasses::default();
+        let name = Name::new("static geometry");
+        passes.insert(name, 1, 10);
+        passes.insert(name, 2, 20);
+        assert_eq!(passes.get(name, 1), None);
+        assert_eq!(passes.get(name, 2), Some(&20));
+    }
+
+    #[test]
+    fn recordings_of_nodes_which_didnt_execute_are_discarded() {
+        let mut passes: RetainedPasses<u32> = RetainedPasses::default();
+        let kept = Name::new("kept");
+        let culled = Name::new("culled");
+        passes.insert(kept, 1, 10);
+        passes.insert(culled, 1, 20);
+        passes.end_frame();
+
+        // both executed the frame they were recorded, only one executes the next frame
+        assert_eq!(passes.get(kept, 1), Some(&10));
+        passes.end_frame();
+        assert_eq!(passes.get(kept, 1), Some(&10));
+        assert_eq!(passes.get(culled, 1), None);
+    }
+
+    #[test]
+    fn a_key_mismatch_still_counts_as_executed() {
+        let mut passes: RetainedPasses<u32> = RetainedPasses::default();
+        let name = Name::new("static geometry");
+        passes.insert(name, 1, 10);
+        passes.end_frame();
+        assert_eq!(passes.get(name, 2), None);
+        passes.end_frame();
+        assert_eq!(passes.get(name, 1), Some(&10));
+    }
+
+    #[test]
+    fn clear_discards_every_recording() {
+        let mut passes: RetainedPasses<u32> = RetainedPasses::default();
+        let name = Name::new("static geometry");
+        passes.insert(name, 1, 10);
+        passes.clear();
+        assert_eq!(passes.get(name, 1), None);
+    }
+}
@@ -8,12 +8,15 @@ use ash::vk;
 use rspirv_reflect;
 use rspirv_reflect::BindingCount;
 use api_types::device::{DeviceShader, DeviceWrapper};
+use crate::shader_validation::validate_shader;
+use crate::uniform_layout::{reflect_uniform_blocks, UniformBlockLayout};
 
 fn create_shader_module(device: Rc<RefCell<DeviceWrapper>>, file_name: &str) -> Shader
 {
-    let (reflection_module, shader) = {
+    let (reflection_module, uniform_blocks, shader) = {
         let bytes = fs::read(file_name)
             .expect(&format!("Unable to load shader at {}", file_name));
+        validate_shader(file_name, &bytes);
 
         let reflection_module = rspirv_reflect::Reflection::new_from_spirv(&bytes)
             .expect(&format!("Failed to parse shader for reflection data at {}", file_name));
@@ -28,7 +31,7 @@ fn create_shader_module(device: Rc<RefCell<DeviceWrapper>>, file_name: &str) ->
 
         let shader = DeviceWrapper::create_shader(device, file_name, &create_info);
 
-        (reflection_module, shader)
+        (reflection_module, reflect_uniform_blocks(&bytes), shader)
     };
 
     // TODO: Add support for compute descriptor set bindings (could just use VK_SHADER_STAGE_ALL)
@@ -62,12 +65,17 @@ fn create_shader_module(device: Rc<RefCell<DeviceWrapper>>, file_name: &str) ->
         binding_map.insert(set.0, descriptor_set_bindings);
     }
 
-    Shader::new(shader, binding_map)
+    // compute shaders declare their local size with an execution mode
+    let workgroup_size = reflection_module.get_compute_group_size()
+        .map(|(x, y, z)| [x, y, z]);
+
+    Shader::new(shader, binding_map, workgroup_size, uniform_blocks)
 }
 
 pub fn create_shader_module_from_bytes(device: Rc<RefCell<DeviceWrapper>>, name: &str, bytes: &[u8]) -> Shader
 {
-    let (reflection_module, shader) = {
+    validate_shader(name, bytes);
+    let (reflection_module, uniform_blocks, shader) = {
         let reflection_module = rspirv_reflect::Reflection::new_from_spirv(bytes)
             .expect(&format!("Failed to parse shader for reflection data for {}", name));
 
@@ -81,7 +89,7 @@ pub fn create_shader_module_from_bytes(device: Rc<RefCell<DeviceWrapper>>, name:
 
         let shader = DeviceWrapper::create_shader(device, name, &create_info);
 
-        (reflection_module, shader)
+        (reflection_module, reflect_uniform_blocks(bytes), shader)
     };
 
     // TODO: Add support for compute descriptor set bindings (could just use VK_SHADER_STAGE_ALL)
@@ -115,25 +123,37 @@ pub fn create_shader_module_from_bytes(device: Rc<RefCell<DeviceWrapper>>, name:
         binding_map.insert(set.0, descriptor_set_bindings);
     }
 
-    Shader::new(shader, binding_map)
+    // compute shaders declare their local size with an execution mode
+    let workgroup_size = reflection_module.get_compute_group_size()
+        .map(|(x, y, z)| [x, y, z]);
+
+    Shader::new(shader, binding_map, workgroup_size, uniform_blocks)
 }
 
 #[derive(Clone)]
 pub struct Shader
 {
     pub shader: DeviceShader,
-    pub descriptor_bindings: HashMap<u32, Vec<vk::DescriptorSetLayoutBinding>>
+    pub descriptor_bindings: HashMap<u32, Vec<vk::DescriptorSetLayoutBinding>>,
+    /// The reflected local size of a compute shader
+    pub workgroup_size: Option<[u32; 3]>,
+    /// Uniform block layouts by set and binding
+    pub uniform_blocks: HashMap<(u32, u32), UniformBlockLayout>
 }
 
 impl Shader
 {
     pub fn new(
         shader: DeviceShader,
-        descriptor_bindings: HashMap<u32, Vec<vk::DescriptorSetLayoutBinding>>) -> Shader
+        descriptor_bindings: HashMap<u32, Vec<vk::DescriptorSetLayoutBinding>>,
+        workgroup_size: Option<[u32; 3]>,
+        uniform_blocks: HashMap<(u32, u32), UniformBlockLayout>) -> Shader
     {
         Shader {
             shader,
-            descriptor_bindings
+            descriptor_bindings,
+            workgroup_size,
+            uniform_blocks
         }
     }
 }
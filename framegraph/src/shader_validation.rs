@@ -0,0 +1,114 @@
+//! Checks run on SPIR-V before a shader module is created from it, so a broken module is
+//! reported with the shader's name instead of failing inside pipeline creation with a driver
+//! error. The header is always checked; with the `shader-validation` feature the module is
+//! also parsed and validated by naga, and its entry points and bindings logged.
+
+use crate::uniform_layout::SPIRV_MAGIC;
+
+// magic, version, generator, id bound and schema
+const SPIRV_HEADER_WORDS: usize = 5;
+
+/// Returns why `spirv` can't be a SPIR-V module, if its size or header make that obvious
+pub fn validate_spirv_header(spirv: &[u8]) -> Result<(), String> {
+    if spirv.len() % 4 != 0 {
+        return Err(format!("its size ({} bytes) isn't a whole number of words", spirv.len()));
+    }
+    if spirv.len() < SPIRV_HEADER_WORDS * 4 {
+        return Err(format!("it's too small ({} bytes) to hold a SPIR-V header", spirv.len()));
+    }
+    let magic = u32::from_le_bytes([spirv[0], spirv[1], spirv[2], spirv[3]]);
+    if magic != SPIRV_MAGIC {
+        return match magic.swap_bytes() == SPIRV_MAGIC {
+            true => Err("it's big-endian".to_string()),
+            false => Err(format!("its magic number is {:#010x} rather than {:#010x}", magic, SPIRV_MAGIC))
+        };
+    }
+    let id_bound = u32::from_le_bytes([spirv[12], spirv[13], spirv[14], spirv[15]]);
+    if id_bound == 0 {
+        return Err("its id bound is 0".to_string());
+    }
+
+    Ok(())
+}
+
+/// Panics with `name` if `spirv` isn't a valid module. naga's SPIR-V frontend doesn't support
+/// everything Vulkan does, so modules it can't parse are only warned about; modules it parses
+/// but finds invalid are refused
+pub(crate) fn validate_shader(name: &str, spirv: &[u8]) {
+    if let Err(error) = validate_spirv_header(spirv) {
+        panic!("Shader {} isn't valid SPIR-V: {}", name, error);
+    }
+
+    #[cfg(feature = "shader-validation")]
+    validate_with_naga(name, spirv);
+}
+
+#[cfg(feature = "shader-validation")]
+fn validate_with_naga(name: &str, spirv: &[u8]) {
+    let module = match naga::front::spv::parse_u8_slice(spirv, &naga::front::spv::Options::default()) {
+        Ok(module) => module,
+        Err(error) => {
+            log::warn!(target: "shader", "Skipping validation of shader {}, which naga couldn't parse: {}", name, error);
+            return;
+        }
+    };
+
+    for entry_point in module.entry_points.iter() {
+        log::debug!(target: "shader", "Shader {} entry point: {} ({:?})", name, entry_point.name, entry_point.stage);
+    }
+    for (_, variable) in module.global_variables.iter() {
+        if let Some(binding) = &variable.binding {
+            log::debug!(target: "shader", "Shader {} binding: set {}, binding {} ({}, {:?})",
+                name,
+                binding.group,
+                binding.binding,
+                variable.name.as_deref().unwrap_or("unnamed"),
+                variable.space);
+        }
+    }
+
+    let mut validator = naga::valid::Validator::new(
+        naga::valid::ValidationFlags::all(),
+        naga::valid::Capabilities::all());
+    if let Err(error) = validator.validate(&module) {
+        panic!("Shader {} failed validation: {}", name, error.as_inner());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn to_bytes(words: &[u32]) -> Vec<u8> {
+        words.iter().flat_map(|word| word.to_le_bytes()).collect()
+    }
+
+    #[test]
+    fn header_is_accepted() {
+        let spirv = to_bytes(&[SPIRV_MAGIC, 0x0001_0000, 0, 8, 0]);
+        assert!(validate_spirv_header(&spirv).is_ok());
+    }
+
+    #[test]
+    fn truncated_module_is_refused() {
+        let mut spirv = to_bytes(&[SPIRV_MAGIC, 0x0001_0000, 0, 8, 0]);
+        spirv.pop();
+        assert!(validate_spirv_header(&spirv).is_err());
+        assert!(validate_spirv_header(&spirv[..8]).is_err());
+    }
+
+    #[test]
+    fn wrong_magic_is_refused() {
+        let big_endian = to_bytes(&[SPIRV_MAGIC.swap_bytes(), 0x0001_0000, 0, 8, 0]);
+        assert_eq!(validate_spirv_header(&big_endian), Err("it's big-endian".to_string()));
+
+        let text = b"#version 450\nvoid main() {}\n\0\0\0\0";
+        assert!(validate_spirv_header(&text[..28]).is_err());
+    }
+
+    #[test]
+    fn zero_id_bound_is_refused() {
+        let spirv = to_bytes(&[SPIRV_MAGIC, 0x0001_0000, 0, 0, 0]);
+        assert!(validate_spirv_header(&spirv).is_err());
+    }
+}
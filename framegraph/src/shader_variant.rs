@@ -0,0 +1,168 @@
+//! Variants of a shader compiled with different preprocessor defines, so e.g. a textured and
+//! an untextured material can share one source file. The build scripts compile a variant for
+//! each line of a `<shader>.variants` file next to the shader, listing its defines as `NAME`
+//! or `NAME=value` separated by whitespace; each is written next to the shader's other
+//! variants with the file name given by [`ShaderVariant::get_file_name`]. With the `shaderc`
+//! feature, variants can instead be compiled at runtime with [`compile_variant`].
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::rc::Rc;
+use api_types::device::DeviceWrapper;
+use crate::shader::{create_shader_module_from_bytes, Shader};
+
+/// A shader and the defines it's compiled with. Defines are kept sorted by name, so the same
+/// set of defines always makes the same key
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ShaderVariant {
+    base: String,
+    // defines without a value are None
+    defines: BTreeMap<String, Option<i32>>
+}
+
+impl ShaderVariant {
+    /// `base` is the name of the shader's default variant without its extension, as written
+    /// by the build scripts (e.g. "model-frag" for model.frag)
+    pub fn new(base: &str) -> Self {
+        ShaderVariant {
+            base: base.to_string(),
+            defines: BTreeMap::new()
+        }
+    }
+
+    /// Defines `name` if `enabled`, for shaders checking it with #ifdef
+    pub fn define(mut self, name: &str, enabled: bool) -> Self {
+        match enabled {
+            true => self.defines.insert(name.to_string(), None),
+            false => self.defines.remove(name)
+        };
+        self
+    }
+
+    pub fn define_value(mut self, name: &str, value: i32) -> Self {
+        self.defines.insert(name.to_string(), Some(value));
+        self
+    }
+
+    pub fn get_base(&self) -> &str { &self.base }
+
+    /// The variant's defines, e.g. "SAMPLES=4.TEXTURED". Empty for the default variant
+    pub fn get_key(&self) -> String {
+        self.defines.iter()
+            .map(|(name, value)| match value {
+                Some(value) => format!("{}={}", name, value),
+                None => name.clone()
+            })
+            .collect::<Vec<String>>()
+            .join(".")
+    }
+
+    /// The base name followed by the key, e.g. "model-frag.TEXTURED"
+    pub fn get_name(&self) -> String {
+        match self.defines.is_empty() {
+            true => self.base.clone(),
+            false => format!("{}.{}", self.base, self.get_key())
+        }
+    }
+
+    /// The compiled variant's file name, e.g. "model-frag.TEXTURED.spv". Also usable as the
+    /// name of a ComputePipelineDescription's shader
+    pub fn get_file_name(&self) -> String {
+        format!("{}.spv", self.get_name())
+    }
+
+    /// The defines as preprocessor arguments, e.g. ["-DSAMPLES=4", "-DTEXTURED"]
+    pub fn get_define_args(&self) -> Vec<String> {
+        self.defines.iter()
+            .map(|(name, value)| match value {
+                Some(value) => format!("-D{}={}", name, value),
+                None => format!("-D{}", name)
+            })
+            .collect()
+    }
+}
+
+/// Shader modules created for variants, by variant name
+#[derive(Default)]
+pub struct ShaderVariantCache {
+    shaders: HashMap<String, Rc<RefCell<Shader>>>
+}
+
+impl ShaderVariantCache {
+    pub fn new() -> Self {
+        ShaderVariantCache::default()
+    }
+
+    /// Returns the module for `variant`, creating it from the SPIR-V `spirv` returns if it
+    /// hasn't been created yet
+    pub fn get_or_create<S: AsRef<[u8]>>(
+        &mut self,
+        device: Rc<RefCell<DeviceWrapper>>,
+        variant: &ShaderVariant,
+        spirv: impl FnOnce(&ShaderVariant) -> S) -> Rc<RefCell<Shader>> {
+        let name = variant.get_name();
+        self.shaders.entry(name).or_insert_with_key(|name| {
+            Rc::new(RefCell::new(create_shader_module_from_bytes(device, name, spirv(variant).as_ref())))
+        }).clone()
+    }
+
+    /// The module for `variant`, if it's been created
+    pub fn get(&self, variant: &ShaderVariant) -> Option<Rc<RefCell<Shader>>> {
+        self.shaders.get(&variant.get_name()).cloned()
+    }
+}
+
+/// Compiles the GLSL `source` of a variant's shader with its defines
+#[cfg(feature = "shaderc")]
+pub fn compile_variant(
+    variant: &ShaderVariant,
+    source: &str,
+    kind: shaderc::ShaderKind) -> Result<Vec<u8>, String> {
+    let compiler = shaderc::Compiler::new()
+        .ok_or_else(|| "Failed to create a shaderc compiler".to_string())?;
+    let mut options = shaderc::CompileOptions::new()
+        .ok_or_else(|| "Failed to create shaderc compile options".to_string())?;
+    options.set_target_env(shaderc::TargetEnv::Vulkan, shaderc::EnvVersion::Vulkan1_1 as u32);
+    for (name, value) in &variant.defines {
+        options.add_macro_definition(name, value.map(|value| value.to_string()).as_deref());
+    }
+
+    compiler.compile_into_spirv(source, kind, &variant.get_name(), "main", Some(&options))
+        .map(|artifact| artifact.as_binary_u8().to_vec())
+        .map_err(|error| format!("Failed to compile shader variant {}: {}", variant.get_name(), error))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_variant_uses_the_base_name() {
+        let variant = ShaderVariant::new("model-frag");
+        assert_eq!(variant.get_key(), "");
+        assert_eq!(variant.get_file_name(), "model-frag.spv");
+    }
+
+    #[test]
+    fn key_is_independent_of_define_order() {
+        let first = ShaderVariant::new("model-frag")
+            .define("TEXTURED", true)
+            .define_value("SAMPLES", 4);
+        let second = ShaderVariant::new("model-frag")
+            .define_value("SAMPLES", 4)
+            .define("TEXTURED", true);
+        assert_eq!(first, second);
+        assert_eq!(first.get_key(), "SAMPLES=4.TEXTURED");
+        assert_eq!(first.get_file_name(), "model-frag.SAMPLES=4.TEXTURED.spv");
+        assert_eq!(first.get_define_args(), vec!["-DSAMPLES=4".to_string(), "-DTEXTURED".to_string()]);
+    }
+
+    #[test]
+    fn disabled_defines_are_removed() {
+        let variant = ShaderVariant::new("model-frag")
+            .define("TEXTURED", true)
+            .define("TEXTURED", false)
+            .define("ALPHA_TEST", false);
+        assert_eq!(variant, ShaderVariant::new("model-frag"));
+    }
+}
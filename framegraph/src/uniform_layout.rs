@@ -0,0 +1,369 @@
+use std::collections::{HashMap, HashSet};
+use rspirv::dr::{Instruction, Module, Operand};
+use rspirv::spirv::{Decoration, Op, StorageClass};
+
+pub use framegraph_derive::UniformBlock;
+
+/// A field of a Rust struct uploaded into a uniform buffer, see UniformLayout
+#[derive(Clone, Debug)]
+pub struct UniformField {
+    pub name: &'static str,
+    pub offset: usize,
+    pub size: usize
+}
+
+/// The memory layout of a Rust struct uploaded into a uniform buffer, compared against the
+/// reflected layout of the shader's uniform block in debug builds. Usually generated with
+/// `#[derive(UniformBlock)]`, and attached to a binding with BindingSlot::bind_uniform or
+/// BufferBindingInfo::layout
+#[derive(Clone, Debug)]
+pub struct UniformLayout {
+    pub name: &'static str,
+    pub size: usize,
+    /// In declaration order, which must be the order of the block's members
+    pub fields: &'static [UniformField]
+}
+
+/// A Rust struct with a known uniform layout, usually implemented with `#[derive(UniformBlock)]`
+pub trait UniformBlock {
+    fn layout() -> &'static UniformLayout;
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct UniformMember {
+    pub name: String,
+    pub offset: u32,
+    /// None for members whose size isn't fixed by their decorations, such as structs
+    pub size: Option<u32>
+}
+
+/// A uniform block declared by a shader, as reflected from its SPIR-V
+#[derive(Clone, Debug, PartialEq)]
+pub struct UniformBlockLayout {
+    pub name: String,
+    /// The end of the last member, if its size is known
+    pub size: Option<u32>,
+    pub members: Vec<UniformMember>
+}
+
+/// Describes each way `layout` differs from `block`, one line per mismatch. Members are
+/// matched by position; a struct larger than its block is fine, since only the block's
+/// bytes are read
+pub fn uniform_layout_mismatches(layout: &UniformLayout, block: &UniformBlockLayout) -> Vec<String> {
+    let mut mismatches: Vec<String> = Vec::new();
+    if layout.fields.len() != block.members.len() {
+        mismatches.push(format!("{} has {} fields but block {} has {} members",
+            layout.name, layout.fields.len(), block.name, block.members.len()));
+    }
+
+    for (index, (field, member)) in layout.fields.iter().zip(&block.members).enumerate() {
+        if field.offset != member.offset as usize {
+            mismatches.push(format!("member {} ({} / {}): offset {} in Rust, {} in SPIR-V",
+                index, field.name, member.name, field.offset, member.offset));
+        }
+        if let Some(member_size) = member.size {
+            if field.size != member_size as usize {
+                mismatches.push(format!("member {} ({} / {}): size {} in Rust, {} in SPIR-V",
+                    index, field.name, member.name, field.size, member_size));
+            }
+        }
+    }
+
+    if let Some(block_size) = block.size {
+        if layout.size < block_size as usize {
+            mismatches.push(format!("{} is {} bytes but block {} is {} bytes",
+                layout.name, layout.size, block.name, block_size));
+        }
+    }
+
+    mismatches
+}
+
+pub(crate) const SPIRV_MAGIC: u32 = 0x07230203;
+
+enum SpirvType {
+    Scalar { bytes: u32 },
+    Vector { component: u32, count: u32 },
+    Matrix { count: u32 },
+    Array { length: u32 },
+    Struct { members: Vec<u32> },
+    Pointer { storage_class: StorageClass, pointee: u32 }
+}
+
+/// The types, names and decorations uniform blocks are described by
+#[derive(Default)]
+struct SpirvModule {
+    types: HashMap<u32, SpirvType>,
+    constants: HashMap<u32, u32>,
+    names: HashMap<u32, String>,
+    member_names: HashMap<(u32, u32), String>,
+    member_offsets: HashMap<(u32, u32), u32>,
+    matrix_strides: HashMap<(u32, u32), u32>,
+    array_strides: HashMap<u32, u32>,
+    blocks: HashSet<u32>,
+    descriptor_sets: HashMap<u32, u32>,
+    bindings: HashMap<u32, u32>,
+    // (pointer type, variable) of every Uniform variable
+    variables: Vec<(u32, u32)>
+}
+
+impl SpirvModule {
+    fn new(module: &Module) -> Self {
+        let mut reflected = SpirvModule::default();
+        for instruction in module.all_inst_iter() {
+            reflected.read_instruction(instruction);
+        }
+
+        reflected
+    }
+
+    fn read_instruction(&mut self, instruction: &Instruction) {
+        match (instruction.class.opcode, instruction.result_id, instruction.operands.as_slice()) {
+            (Op::Name, _, [Operand::IdRef(target), Operand::LiteralString(name)]) => {
+                self.names.insert(*target, name.clone());
+            },
+            (Op::MemberName, _, [Operand::IdRef(target), Operand::LiteralInt32(member), Operand::LiteralString(name)]) => {
+                self.member_names.insert((*target, *member), name.clone());
+            },
+            (Op::TypeInt | Op::TypeFloat, Some(result), [Operand::LiteralInt32(width), ..]) => {
+                self.types.insert(result, SpirvType::Scalar { bytes: width / 8 });
+            },
+            (Op::TypeVector, Some(result), [Operand::IdRef(component), Operand::LiteralInt32(count)]) => {
+                self.types.insert(result, SpirvType::Vector { component: *component, count: *count });
+            },
+            (Op::TypeMatrix, Some(result), [_column, Operand::LiteralInt32(count)]) => {
+                self.types.insert(result, SpirvType::Matrix { count: *count });
+            },
+            (Op::TypeArray, Some(result), [_element, Operand::IdRef(length)]) => {
+                self.types.insert(result, SpirvType::Array { length: *length });
+            },
+            (Op::TypeStruct, Some(result), members) => {
+                let members = members.iter().filter_map(|member| match member {
+                    Operand::IdRef(id) => Some(*id),
+                    _ => None
+                }).collect();
+                self.types.insert(result, SpirvType::Struct { members });
+            },
+            (Op::TypePointer, Some(result), [Operand::StorageClass(storage_class), Operand::IdRef(pointee)]) => {
+                self.types.insert(result, SpirvType::Pointer { storage_class: *storage_class, pointee: *pointee });
+            },
+            (Op::Constant, Some(result), [Operand::LiteralInt32(value)]) => {
+                self.constants.insert(result, *value);
+            },
+            (Op::Variable, Some(result), [Operand::StorageClass(StorageClass::Uniform), ..]) => {
+                if let Some(result_type) = instruction.result_type {
+                    self.variables.push((result_type, result));
+                }
+            },
+            (Op::Decorate, _, [Operand::IdRef(target), Operand::Decoration(Decoration::Block)]) => {
+                self.blocks.insert(*target);
+            },
+            (Op::Decorate, _, [Operand::IdRef(target), Operand::Decoration(Decoration::ArrayStride), Operand::LiteralInt32(stride)]) => {
+                self.array_strides.insert(*target, *stride);
+            },
+            (Op::Decorate, _, [Operand::IdRef(target), Operand::Decoration(Decoration::DescriptorSet), Operand::LiteralInt32(set)]) => {
+                self.descriptor_sets.insert(*target, *set);
+            },
+            (Op::Decorate, _, [Operand::IdRef(target), Operand::Decoration(Decoration::Binding), Operand::LiteralInt32(binding)]) => {
+                self.bindings.insert(*target, *binding);
+            },
+            (Op::MemberDecorate, _, [Operand::IdRef(target), Operand::LiteralInt32(member), Operand::Decoration(Decoration::Offset), Operand::LiteralInt32(offset)]) => {
+                self.member_offsets.insert((*target, *member), *offset);
+            },
+            (Op::MemberDecorate, _, [Operand::IdRef(target), Operand::LiteralInt32(member), Operand::Decoration(Decoration::MatrixStride), Operand::LiteralInt32(stride)]) => {
+                self.matrix_strides.insert((*target, *member), *stride);
+            },
+            _ => {}
+        }
+    }
+
+    /// Bytes occupied by a member of type `type_id`. Matrices are assumed to be column-major
+    fn member_size(&self, type_id: u32, matrix_stride: Option<u32>) -> Option<u32> {
+        match self.types.get(&type_id)? {
+            SpirvType::Scalar { bytes } => Some(*bytes),
+            SpirvType::Vector { component, count } => Some(self.member_size(*component, None)? * count),
+            SpirvType::Matrix { count } => Some(matrix_stride? * count),
+            SpirvType::Array { length } => Some(self.array_strides.get(&type_id)? * self.constants.get(length)?),
+            SpirvType::Struct { .. } | SpirvType::Pointer { .. } => None
+        }
+    }
+
+    fn block_layout(&self, struct_id: u32, members: &[u32]) -> UniformBlockLayout {
+        let members: Vec<UniformMember> = members.iter().enumerate().map(|(index, member_type)| {
+            let key = (struct_id, index as u32);
+            UniformMember {
+                name: self.member_names.get(&key).cloned().unwrap_or_default(),
+                offset: self.member_offsets.get(&key).copied().unwrap_or(0),
+                size: self.member_size(*member_type, self.matrix_strides.get(&key).copied())
+            }
+        }).collect();
+        let size = members.last().and_then(|last| last.size.map(|size| last.offset + size));
+
+        UniformBlockLayout {
+            name: self.names.get(&struct_id).cloned().unwrap_or_default(),
+            size,
+            members
+        }
+    }
+}
+
+/// The uniform blocks declared by a SPIR-V module, by set and binding. Empty if the module
+/// can't be parsed
+pub fn reflect_uniform_blocks(spirv: &[u8]) -> HashMap<(u32, u32), UniformBlockLayout> {
+    let module = match rspirv::dr::load_bytes(spirv) {
+        Ok(module) => SpirvModule::new(&module),
+        Err(_) => return HashMap::new()
+    };
+
+    let mut blocks: HashMap<(u32, u32), UniformBlockLayout> = HashMap::new();
+    for (pointer_type, variable) in &module.variables {
+        let (set, binding) = match (module.descriptor_sets.get(variable), module.bindings.get(variable)) {
+            (Some(set), Some(binding)) => (*set, *binding),
+            _ => continue
+        };
+        let struct_id = match module.types.get(pointer_type) {
+            Some(SpirvType::Pointer { storage_class: StorageClass::Uniform, pointee }) => *pointee,
+            _ => continue
+        };
+        // storage buffers declared with the older BufferBlock decoration are also Uniform
+        if !module.blocks.contains(&struct_id) {
+            continue;
+        }
+        if let Some(SpirvType::Struct { members }) = module.types.get(&struct_id) {
+            blocks.insert((set, binding), module.block_layout(struct_id, members));
+        }
+    }
+
+    blocks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn instruction(opcode: Op, operands: &[u32]) -> Vec<u32> {
+        let mut words = vec![((operands.len() as u32 + 1) << 16) | opcode as u32];
+        words.extend_from_slice(operands);
+        words
+    }
+
+    /// `ids` followed by `name` as a nul-terminated literal string
+    fn named(ids: &[u32], name: &str) -> Vec<u32> {
+        let mut bytes = name.as_bytes().to_vec();
+        bytes.push(0);
+        while bytes.len() % 4 != 0 {
+            bytes.push(0);
+        }
+        let mut operands = ids.to_vec();
+        operands.extend(bytes.chunks_exact(4).map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]])));
+        operands
+    }
+
+    /// layout(set = 1, binding = 2) uniform Light { vec3 direction; float intensity; mat4 shadow; }
+    fn light_block_spirv() -> Vec<u8> {
+        let (float, vec3, vec4, mat4, block, pointer, variable) = (1, 2, 3, 4, 5, 6, 7);
+        let mut words = vec![SPIRV_MAGIC, 0x00010000, 0, 8, 0];
+        words.extend(instruction(Op::Name, &named(&[block], "Light")));
+        words.extend(instruction(Op::MemberName, &named(&[block, 0], "direction")));
+        words.extend(instruction(Op::MemberName, &named(&[block, 1], "intensity")));
+        words.extend(instruction(Op::MemberName, &named(&[block, 2], "shadow")));
+        words.extend(instruction(Op::MemberDecorate, &[block, 0, Decoration::Offset as u32, 0]));
+        words.extend(instruction(Op::MemberDecorate, &[block, 1, Decoration::Offset as u32, 12]));
+        words.extend(instruction(Op::MemberDecorate, &[block, 2, Decoration::Offset as u32, 16]));
+        words.extend(instruction(Op::MemberDecorate, &[block, 2, Decoration::MatrixStride as u32, 16]));
+        words.extend(instruction(Op::Decorate, &[block, Decoration::Block as u32]));
+        words.extend(instruction(Op::Decorate, &[variable, Decoration::DescriptorSet as u32, 1]));
+        words.extend(instruction(Op::Decorate, &[variable, Decoration::Binding as u32, 2]));
+        words.extend(instruction(Op::TypeFloat, &[float, 32]));
+        words.extend(instruction(Op::TypeVector, &[vec3, float, 3]));
+        words.extend(instruction(Op::TypeVector, &[vec4, float, 4]));
+        words.extend(instruction(Op::TypeMatrix, &[mat4, vec4, 4]));
+        words.extend(instruction(Op::TypeStruct, &[block, vec3, float, mat4]));
+        words.extend(instruction(Op::TypePointer, &[pointer, StorageClass::Uniform as u32, block]));
+        words.extend(instruction(Op::Variable, &[pointer, variable, StorageClass::Uniform as u32]));
+
+        words.iter().flat_map(|word| word.to_le_bytes()).collect()
+    }
+
+    #[test]
+    fn reflects_block_members() {
+        let blocks = reflect_uniform_blocks(&light_block_spirv());
+        let light = blocks.get(&(1, 2)).expect("Light block wasn't reflected");
+
+        assert_eq!(light.name, "Light");
+        assert_eq!(light.size, Some(80));
+        let members: Vec<(&str, u32, Option<u32>)> = light.members.iter()
+            .map(|member| (member.name.as_str(), member.offset, member.size))
+            .collect();
+        assert_eq!(members, vec![("direction", 0, Some(12)), ("intensity", 12, Some(4)), ("shadow", 16, Some(64))]);
+    }
+
+    #[test]
+    fn matching_layout_has_no_mismatches() {
+        let blocks = reflect_uniform_blocks(&light_block_spirv());
+        let layout = UniformLayout {
+            name: "Light",
+            size: 80,
+            fields: &[
+                UniformField { name: "direction", offset: 0, size: 12 },
+                UniformField { name: "intensity", offset: 12, size: 4 },
+                UniformField { name: "shadow", offset: 16, size: 64 }
+            ]
+        };
+
+        assert!(uniform_layout_mismatches(&layout, &blocks[&(1, 2)]).is_empty());
+    }
+
+    #[test]
+    fn padded_vec3_is_reported() {
+        // a vec3 padded out to 16 bytes on the Rust side pushes every later member back
+        let blocks = reflect_uniform_blocks(&light_block_spirv());
+        let layout = UniformLayout {
+            name: "Light",
+            size: 96,
+            fields: &[
+                UniformField { name: "direction", offset: 0, size: 16 },
+                UniformField { name: "intensity", offset: 16, size: 4 },
+                UniformField { name: "shadow", offset: 32, size: 64 }
+            ]
+        };
+
+        let mismatches = uniform_layout_mismatches(&layout, &blocks[&(1, 2)]);
+        assert_eq!(mismatches, vec![
+            "member 0 (direction / direction): size 16 in Rust, 12 in SPIR-V".to_string(),
+            "member 1 (intensity / intensity): offset 16 in Rust, 12 in SPIR-V".to_string(),
+            "member 2 (shadow / shadow): offset 32 in Rust, 16 in SPIR-V".to_string()
+        ]);
+    }
+
+    #[repr(C)]
+    #[derive(UniformBlock)]
+    #[allow(dead_code)]
+    struct Light {
+        direction: [f32; 3],
+        intensity: f32,
+        shadow: [[f32; 4]; 4]
+    }
+
+    #[test]
+    fn derived_layout_records_each_field() {
+        let layout = Light::layout();
+        assert_eq!(layout.name, "Light");
+        assert_eq!(layout.size, 80);
+        let fields: Vec<(&str, usize, usize)> = layout.fields.iter()
+            .map(|field| (field.name, field.offset, field.size))
+            .collect();
+        assert_eq!(fields, vec![("direction", 0, 12), ("intensity", 12, 4), ("shadow", 16, 64)]);
+    }
+
+    #[test]
+    fn derived_layout_matches_its_block() {
+        let blocks = reflect_uniform_blocks(&light_block_spirv());
+        assert!(uniform_layout_mismatches(Light::layout(), &blocks[&(1, 2)]).is_empty());
+    }
+
+    #[test]
+    fn invalid_spirv_has_no_blocks() {
+        assert!(reflect_uniform_blocks(&[0, 1, 2, 3]).is_empty());
+    }
+}
\ No newline at end of file
@@ -5,154 +5,402 @@ use log::trace;
 
 use petgraph::stable_graph::{NodeIndex, StableDiGraph};
 extern crate multimap;
-use multimap::MultiMap;
 
 extern crate context;
 use context::render_context::{RenderContext};
 
 use ash::vk;
-use crate::frame::Frame;
+use crate::frame::{Frame, FrameDescriptors};
 use crate::frame_graph::FrameGraph;
-use crate::pass_node::PassNode;
-use crate::binding::{ResourceBinding, ImageBindingInfo, BufferBindingInfo, BindingType};
-use crate::graphics_pass_node::{GraphicsPassNode};
-use crate::pipeline::{Pipeline, VulkanPipelineManager};
-use crate::renderpass_manager::VulkanRenderpassManager;
-
-use std::collections::HashMap;
+use crate::node_arena;
+use crate::pass_node::{PassHandle, PassNode};
+use crate::binding::{ResourceBinding, ImageBindingInfo, BufferBindingInfo, BindingInfo, BindingFrequency, BindingType};
+use crate::graphics_pass_node::{GraphicsPassNode, Multiview};
+use crate::pipeline::{unset_dynamic_states, Pipeline, SubpassTargets, VulkanPipelineManager};
+use crate::renderpass_manager::{SubpassAttachments, VulkanRenderpassManager};
+
+use std::collections::{HashMap, HashSet};
 use std::fmt::{Debug, Formatter};
 use std::ops::Deref;
 use std::rc::Rc;
+use std::time::{Duration, Instant};
 use ash::vk::DeviceSize;
 use petgraph::data::DataMap;
-use petgraph::visit::Dfs;
 use api_types::buffer::BufferWrapper;
-use api_types::device::{DeviceWrapper, ResourceType};
+use api_types::device::{DeviceRenderpass, DeviceResource, ResourceError, ResourceType};
+use api_types::device_capabilities::NegotiatedFeature;
 use api_types::image::ImageWrapper;
-use context::vulkan_render_context::VulkanRenderContext;
+use api_types::resource_state::ResourceState;
+use context::descriptor_buffer_manager::{DescriptorBufferManager, DescriptorBufferSets};
+use context::vulkan_render_context::{GraphicsSubmit, VulkanRenderContext};
 use profiling::enter_span;
-use crate::attachment::AttachmentReference;
-use crate::barrier::{BufferBarrier, ImageBarrier};
-use crate::command_list::{CommandList, QueueWait};
-use crate::compute_pass_node::ComputePassNode;
+use crate::attachment::{AttachmentLoad, AttachmentReference};
+use crate::capture::GpuCapture;
+use crate::barrier::{BufferBarrier, ImageBarrier, SubresourceRange};
+use crate::barrier_analysis::{self, BarrierHistory};
+use crate::command_list::{CommandBufferProvider, CommandList, QueueWait, RecordedCommandList};
+use crate::compute_pass_node::{ComputeDispatch, ComputePassNode};
 use crate::copy_pass_node::CopyPassNode;
+use crate::frame_stats::{FrameStats, PassTiming};
+use crate::graph_cache::{graph_fingerprint, CachedGraph, GraphCache, ResourceKeys};
+use crate::graph_core::{self, AccessKind, BarrierRange, GraphNode, NodeLink, ResourceAccess};
+use crate::graph_debug::{self, graph_debug};
 use crate::pass_type::PassType;
+use crate::retained_pass::{is_retainable, retained_key, RetainedPasses, RetainedRecording};
+use crate::uniform_layout::uniform_layout_mismatches;
+
+/// What a ResourceAccess given to graph_core::link refers to, so its transitions can be turned
+/// into barriers and reflected in the layouts the renderpass and descriptors are created with
+enum AccessTarget {
+    /// A descriptor, dependency or input attachment, whose image layout follows its transitions
+    Binding(Rc<RefCell<DeviceResource>>),
+    Copy(Rc<RefCell<DeviceResource>>),
+    DepthTarget(Rc<RefCell<DeviceResource>>),
+    RenderTarget(usize, Rc<RefCell<DeviceResource>>),
+    Swapchain(Rc<RefCell<DeviceResource>>)
+}
 
-#[derive(Clone)]
-struct ResourceUsage {
-    access: vk::AccessFlags,
-    stage: vk::PipelineStageFlags,
-    layout: Option<vk::ImageLayout>
+impl AccessTarget {
+    fn get_resource(&self) -> &Rc<RefCell<DeviceResource>> {
+        match self {
+            AccessTarget::Binding(resource) |
+            AccessTarget::Copy(resource) |
+            AccessTarget::DepthTarget(resource) |
+            AccessTarget::RenderTarget(_, resource) |
+            AccessTarget::Swapchain(resource) => resource
+        }
+    }
 }
 
-fn is_write(access: vk::AccessFlags, stage: vk::PipelineStageFlags) -> bool {
-    let write_access=
-        vk::AccessFlags::COLOR_ATTACHMENT_WRITE |
-            vk::AccessFlags::SHADER_WRITE |
-            vk::AccessFlags::TRANSFER_WRITE |
-            vk::AccessFlags::HOST_WRITE |
-            vk::AccessFlags::MEMORY_WRITE;
+/// How record handles a node using a resource which isn't resolved as the kind of resource it's
+/// used as (e.g. a buffer bound to an image slot)
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ResourceErrorPolicy {
+    /// Panic with the resource and the pass using it
+    #[default]
+    Panic,
+    /// Log the error and remove the node from the frame. Root nodes still panic
+    SkipNode
+}
 
-    let pipeline_write = vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT;
+/// Panics with the pass that used the resource. Nodes are validated before they're linked, so
+/// this is only reached by resources which changed during recording
+fn resource_panic(error: ResourceError, pass: &str) -> ! {
+    panic!("{}", error.in_pass(pass))
+}
 
-    (write_access & access != vk::AccessFlags::NONE) || (pipeline_write & stage != vk::PipelineStageFlags::NONE)
+fn resolves<T>(result: Result<T, ResourceError>, pass: &str) -> Result<(), ResourceError> {
+    result.map(|_| ()).map_err(|error| error.in_pass(pass))
 }
 
-fn link_inputs(inputs: &[ResourceBinding], node_barrier: &mut NodeBarriers, usage_cache: &mut HashMap<u64, ResourceUsage>) {
-    for input in inputs {
-        let handle = input.resource.borrow().get_handle();
+/// The first resource of `node` which isn't resolved as the kind of resource it's used as
+fn validate_node_resources(node: &PassType) -> Result<(), ResourceError> {
+    let pass = node.get_name();
+    match node {
+        PassType::Graphics(gn) => {
+            for binding in gn.inputs.iter().chain(&gn.outputs).chain(&gn.input_attachments) {
+                resolves(binding.validate(), pass)?;
+            }
+            for attachment in gn.render_targets.iter().chain(&gn.depth_target) {
+                resolves(attachment.resource_image.borrow().try_get_image(), pass)?;
+            }
+            for vertex_buffer in &gn.vertex_buffers {
+                resolves(vertex_buffer.resource.borrow().try_get_buffer(), pass)?;
+            }
+            if let Some(index_buffer) = &gn.index_buffer {
+                resolves(index_buffer.resource.borrow().try_get_buffer(), pass)?;
+            }
+        },
+        PassType::Compute(cn) => {
+            for binding in cn.inputs.iter().chain(&cn.outputs) {
+                resolves(binding.validate(), pass)?;
+            }
+        },
+        PassType::Copy(cn) => {
+            for resource in cn.copy_sources.iter().chain(&cn.copy_dests) {
+                resolves(resource.borrow().try_resolved(), pass)?;
+            }
+        },
+        PassType::Present(pn) => {
+            resolves(pn.swapchain_image.borrow().try_get_image(), pass)?;
+        }
+    }
 
-        let mut resource = input.resource.borrow_mut();
-        let resolved_resource = {
-            match &mut resource.resource_type {
-                None => {
-                    panic!("Invalid input binding")
-                }
-                Some(resource) => {
-                    resource
+    for dependency in node.get_dependencies() {
+        resolves(dependency.resource.borrow().try_resolved(), pass)?;
+    }
+
+    Ok(())
+}
+
+fn binding_access(binding: &ResourceBinding, input_attachment: bool, pass: &str) -> (ResourceAccess, AccessTarget) {
+    binding.validate().unwrap_or_else(|error| resource_panic(error, pass));
+    let resource = binding.resource.borrow();
+    let kind = match &binding.binding_info.binding_type {
+        BindingType::Buffer(buffer_binding) => {
+            AccessKind::Buffer { offset: buffer_binding.offset, size: buffer_binding.range }
+        },
+        BindingType::Image(image_binding) => match input_attachment {
+            true => AccessKind::InputAttachment { layout: image_binding.layout },
+            false => {
+                let image = resource.get_image();
+                assert!(image_binding.subresource.clamp(image.mip_levels, image.array_layers).is_some(),
+                    "Pass {} binds {:?} of {}, which has {} mips and {} layers",
+                    pass, image_binding.subresource, resource.get_name(), image.mip_levels, image.array_layers);
+                AccessKind::Image {
+                    layout: image_binding.layout,
+                    subresource: image_binding.subresource,
+                    mip_levels: image.mip_levels,
+                    array_layers: image.array_layers
                 }
             }
-        };
+        }
+    };
 
-        match resolved_resource {
-            ResourceType::Buffer(_) => {
-                // Not implemented yet
-            }
-            ResourceType::Image(resolved_image) => {
-                let last_usage = {
-                    let usage = usage_cache.get(&handle);
-                    match usage {
-                        Some(found_usage) => {found_usage.clone()},
-                        _ => {
-                            ResourceUsage {
-                                access: vk::AccessFlags::NONE,
-                                stage: vk::PipelineStageFlags::ALL_COMMANDS,
-                                layout: Some(resolved_image.layout)
-                            }
-                        }
-                    }
-                };
+    (ResourceAccess {
+        handle: resource.get_handle(),
+        access: binding.binding_info.access,
+        stage: binding.binding_info.stage,
+        kind
+    }, AccessTarget::Binding(binding.resource.clone()))
+}
 
-                // barrier required if:
-                //  * last usage was a write
-                //  * image layout has changed
-                let prev_write = is_write(last_usage.access, last_usage.stage);
+fn copy_access(
+    resource: &Rc<RefCell<DeviceResource>>,
+    access: vk::AccessFlags,
+    initial_stage: vk::PipelineStageFlags,
+    layout: vk::ImageLayout,
+    pass: &str) -> (ResourceAccess, AccessTarget) {
+
+    let borrowed = resource.borrow();
+    // buffers only need an execution and memory dependency while images are also transitioned
+    let kind = match borrowed.try_resolved().unwrap_or_else(|error| resource_panic(error, pass)) {
+        ResourceType::Buffer(buffer) => AccessKind::CopyBuffer { size: buffer.create_info.size, initial_stage },
+        ResourceType::Image(_) => AccessKind::CopyImage { layout, initial_stage }
+    };
 
-                if let BindingType::Image(image_binding) = &input.binding_info.binding_type {
-                    let new_usage = ResourceUsage{
-                        access: input.binding_info.access,
-                        stage: input.binding_info.stage,
-                        layout: Some(image_binding.layout)
-                    };
+    (ResourceAccess {
+        handle: borrowed.get_handle(),
+        access,
+        stage: vk::PipelineStageFlags::TRANSFER,
+        kind
+    }, AccessTarget::Copy(resource.clone()))
+}
 
-                    let layout_changed = {
-                        if let Some(layout) = last_usage.layout {
-                            layout != image_binding.layout
-                        } else {
-                            true
-                        }
-                    };
+fn attachment_access(attachment: &AttachmentReference, access: vk::AccessFlags, layout: vk::ImageLayout) -> ResourceAccess {
+    ResourceAccess {
+        handle: attachment.resource_image.borrow().get_handle(),
+        access,
+        stage: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+        kind: AccessKind::Attachment { layout }
+    }
+}
 
-                    // need a barrier
-                    if layout_changed || prev_write {
-                        let image_barrier = ImageBarrier {
-                            resource: input.resource.clone(),
-                            source_stage: last_usage.stage,
-                            dest_stage: new_usage.stage,
-                            source_access: last_usage.access,
-                            dest_access: new_usage.access,
-                            old_layout: last_usage.layout.expect("Using a non-image for an image transition"),
-                            new_layout: new_usage.layout.unwrap()
-                        };
-                        node_barrier.image_barriers.push(image_barrier);
-                        resolved_image.layout = new_usage.layout.unwrap();
-                    }
+/// Every resource access of a node in the order graph_core::link expects them
+fn get_node_accesses(node: &PassType) -> Vec<(ResourceAccess, AccessTarget)> {
+    let pass = node.get_name();
+    let mut accesses = Vec::new();
+    match node {
+        PassType::Graphics(gn) => {
+            for binding in gn.inputs.iter().chain(&gn.outputs) {
+                accesses.push(binding_access(binding, false, pass));
+            }
+        },
+        PassType::Compute(cn) => {
+            for binding in cn.inputs.iter().chain(&cn.outputs) {
+                accesses.push(binding_access(binding, false, pass));
+            }
+        },
+        PassType::Copy(cn) => {
+            for resource in &cn.copy_sources {
+                accesses.push(copy_access(
+                    resource,
+                    vk::AccessFlags::TRANSFER_READ,
+                    vk::PipelineStageFlags::ALL_COMMANDS,
+                    vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                    pass));
+            }
+            for resource in &cn.copy_dests {
+                accesses.push(copy_access(
+                    resource,
+                    vk::AccessFlags::TRANSFER_WRITE,
+                    vk::PipelineStageFlags::TOP_OF_PIPE,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    pass));
+            }
+        },
+        PassType::Present(pn) => {
+            let swapchain = pn.swapchain_image.borrow();
+            accesses.push((ResourceAccess {
+                handle: swapchain.get_handle(),
+                access: vk::AccessFlags::NONE,
+                stage: vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                kind: AccessKind::Present {
+                    current_layout: swapchain.try_get_image().unwrap_or_else(|error| resource_panic(error, pass)).layout,
+                    final_layout: pn.final_layout
+                }
+            }, AccessTarget::Swapchain(pn.swapchain_image.clone())));
+        }
+    }
 
-                    usage_cache.insert(handle, new_usage);
-                    //image_binding.layout = update_usage(input.handle, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
-                } else {
-                    panic!("Buffer binding used on an image reosurce?");
+    for dependency in node.get_dependencies() {
+        accesses.push(binding_access(&dependency.to_binding(), false, pass));
+    }
+
+    if let PassType::Graphics(gn) = node {
+        for input_attachment in &gn.input_attachments {
+            accesses.push(binding_access(input_attachment, true, pass));
+        }
+        // TODO: handle separate depth and stencil targets
+        if let Some(dt) = &gn.depth_target {
+            accesses.push((attachment_access(
+                dt,
+                vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ,
+                vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL), AccessTarget::DepthTarget(dt.resource_image.clone())));
+        }
+        for (index, rt) in gn.render_targets.iter().enumerate() {
+            accesses.push((attachment_access(
+                rt,
+                vk::AccessFlags::COLOR_ATTACHMENT_WRITE | vk::AccessFlags::COLOR_ATTACHMENT_READ,
+                vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL), AccessTarget::RenderTarget(index, rt.resource_image.clone())));
+        }
+    }
+
+    accesses
+}
+
+impl GraphNode for PassType {
+    fn get_name(&self) -> &str {
+        self.deref().get_name()
+    }
+
+    fn get_reads(&self) -> Vec<u64> {
+        self.deref().get_reads()
+    }
+
+    fn get_writes(&self) -> Vec<u64> {
+        self.deref().get_writes()
+    }
+
+    fn get_overwrites(&self) -> Vec<u64> {
+        match self {
+            // attachments are only read when they're loaded
+            PassType::Graphics(gn) => gn.render_targets.iter().chain(&gn.depth_target)
+                .filter(|attachment| attachment.load_op != vk::AttachmentLoadOp::LOAD)
+                .map(|attachment| attachment.resource_image.borrow().get_handle())
+                .collect(),
+            _ => Vec::new()
+        }
+    }
+
+    fn get_loads(&self) -> Vec<u64> {
+        match self {
+            PassType::Graphics(gn) => gn.render_targets.iter().chain(&gn.depth_target)
+                .filter(|attachment| attachment.load_op == vk::AttachmentLoadOp::LOAD)
+                .map(|attachment| attachment.resource_image.borrow().get_handle())
+                .collect(),
+            _ => Vec::new()
+        }
+    }
+
+    fn get_priority(&self) -> i32 {
+        self.deref().get_priority()
+    }
+
+    fn get_execute_after(&self) -> Vec<NodeIndex> {
+        self.deref().get_execute_after().iter().map(|handle| handle.get_index()).collect()
+    }
+
+    fn get_renderpass_group(&self) -> Option<&str> {
+        get_renderpass_group(self)
+    }
+
+    fn is_present(&self) -> bool {
+        matches!(self, PassType::Present(_))
+    }
+
+    fn get_accesses(&self) -> Vec<ResourceAccess> {
+        get_node_accesses(self).into_iter().map(|(access, _)| access).collect()
+    }
+}
+
+/// Turns the transitions link decided on for a node into its barriers, and updates the layouts
+/// of its resources and attachments to the ones they'll be in when the node executes
+fn apply_node_link(node: &mut PassType, node_link: &NodeLink) -> NodeBarriers {
+    let accesses = get_node_accesses(node);
+    let mut node_barrier = NodeBarriers {
+        image_barriers: vec![],
+        buffer_barriers: vec![]
+    };
+
+    for access_index in &node_link.implicit_transitions {
+        let (access, target) = &accesses[*access_index];
+        if let AccessKind::InputAttachment { layout } = access.kind {
+            target.get_resource().borrow_mut().get_image_mut().layout = layout;
+        }
+    }
+
+    for (access_index, transition) in &node_link.transitions {
+        let (_, target) = &accesses[*access_index];
+        match transition.range {
+            BarrierRange::Buffer { offset, size } => {
+                node_barrier.buffer_barriers.push(BufferBarrier {
+                    resource: target.get_resource().clone(),
+                    source_stage: transition.source_stage,
+                    dest_stage: transition.dest_stage,
+                    source_access: transition.source_access,
+                    dest_access: transition.dest_access,
+                    size: size as usize,
+                    offset: offset as usize
+                });
+            },
+            BarrierRange::Image { old_layout, new_layout, subresource } => {
+                node_barrier.image_barriers.push(ImageBarrier {
+                    resource: target.get_resource().clone(),
+                    source_stage: transition.source_stage,
+                    dest_stage: transition.dest_stage,
+                    source_access: transition.source_access,
+                    dest_access: transition.dest_access,
+                    old_layout,
+                    new_layout,
+                    subresource
+                });
+
+                // The RenderPassManager expects attachment layouts to be in the
+                // post-barrier (i.e. new) layout
+                match (target, &mut *node) {
+                    // an image's layout is only known while all of it is in the same one
+                    (AccessTarget::Binding(resource), _) | (AccessTarget::Swapchain(resource), _) if subresource.is_whole() => {
+                        resource.borrow_mut().get_image_mut().layout = new_layout;
+                    },
+                    (AccessTarget::DepthTarget(_), PassType::Graphics(gn)) => {
+                        if let Some(dt) = gn.get_depth_mut() {
+                            dt.layout = new_layout;
+                        }
+                    },
+                    (AccessTarget::RenderTarget(index, _), PassType::Graphics(gn)) => {
+                        gn.get_rendertargets_mut()[*index].layout = new_layout;
+                    },
+                    _ => {}
                 }
             }
         }
-
     }
+
+    node_barrier
 }
 
 fn resolve_render_targets(
-    attachments: &[AttachmentReference]) -> Vec<ImageWrapper> {
+    attachments: &[AttachmentReference],
+    pass: &str) -> Vec<ImageWrapper> {
     enter_span!(tracing::Level::TRACE, "Resolve RTs");
 
     let mut rts: Vec<ImageWrapper> = Vec::new();
     for attachment in attachments {
         let attachment_image = attachment.resource_image.borrow();
-        let resolved = attachment_image.resource_type.as_ref().expect("Invalid rendertarget provided");
-        if let ResourceType::Image(rt_image) = &resolved {
-            // TODO: do I really want to copy the ImageWrappers here?
-            rts.push(rt_image.clone());
-        } else {
-            panic!("A non-image resource was returned when attempting to resolve a render target");
-        }
+        let rt_image = attachment_image.try_get_image().unwrap_or_else(|error| resource_panic(error, pass));
+        // TODO: do I really want to copy the ImageWrappers here?
+        rts.push(rt_image.clone());
     }
 
     rts
@@ -162,10 +410,12 @@ fn get_descriptor_image_info(
     image: &ImageWrapper,
     binding_info: &ImageBindingInfo) -> (vk::DescriptorImageInfo, vk::DescriptorType) {
 
+    // images bound in GENERAL are storage images even if they have a sampler, since the
+    // same image may be sampled by one pass and written by the next (e.g. PingPongImages)
     let (sampler, descriptor_type) = match image.sampler {
-        Some(s) => {(s, vk::DescriptorType::COMBINED_IMAGE_SAMPLER)}
+        Some(s) if binding_info.layout != vk::ImageLayout::GENERAL => {(s, vk::DescriptorType::COMBINED_IMAGE_SAMPLER)}
         // None => {(vk::Sampler::null(), vk::DescriptorType::SAMPLED_IMAGE)}
-        None => {(vk::Sampler::null(), vk::DescriptorType::STORAGE_IMAGE)}
+        _ => {(vk::Sampler::null(), vk::DescriptorType::STORAGE_IMAGE)}
     };
     let image_info = vk::DescriptorImageInfo::builder()
         .image_view(image.view)
@@ -176,16 +426,21 @@ fn get_descriptor_image_info(
     (image_info, descriptor_type)
 }
 
+/// Buffers are bound as whichever of a uniform or storage buffer the pipeline's shaders declare
 fn get_descriptor_buffer_info(
     buffer: &BufferWrapper,
-    binding: &BufferBindingInfo) -> (vk::DescriptorBufferInfo, vk::DescriptorType) {
+    buffer_binding: &BufferBindingInfo,
+    binding_info: &BindingInfo,
+    pipeline: &Pipeline) -> (vk::DescriptorBufferInfo, vk::DescriptorType) {
 
     let buffer_info = vk::DescriptorBufferInfo::builder()
         .buffer(buffer.buffer)
-        .offset(binding.offset)
-        .range(binding.range)
+        .offset(buffer_binding.offset)
+        .range(buffer_binding.range)
         .build();
-    let descriptor_type = vk::DescriptorType::UNIFORM_BUFFER; // TODO: this could also be a storage buffer
+    let descriptor_type = pipeline.get_descriptor_type(binding_info.set as u32, binding_info.slot)
+        .filter(|descriptor_type| *descriptor_type == vk::DescriptorType::STORAGE_BUFFER)
+        .unwrap_or(vk::DescriptorType::UNIFORM_BUFFER);
 
     (buffer_info, descriptor_type)
 }
@@ -213,12 +468,12 @@ fn resolve_descriptors<'a, 'b>(
     bindings: &[ResourceBinding],
     pipeline: &Pipeline,
     descriptor_sets: &[vk::DescriptorSet],
-    descriptor_updates: &mut DescriptorUpdate) {
+    descriptor_updates: &mut DescriptorUpdate,
+    pass: &str) {
     enter_span!(tracing::Level::TRACE, "Resolve descriptors");
 
     for binding in bindings {
         let binding_ref = binding.resource.borrow();
-        let resolved_binding = binding_ref.resource_type.as_ref().expect("Invalid resource in binding");
         let descriptor_set = descriptor_sets[binding.binding_info.set as usize];
 
         let mut descriptor_write_builder = vk::WriteDescriptorSet::builder()
@@ -226,23 +481,26 @@ fn resolve_descriptors<'a, 'b>(
             .dst_binding(binding.binding_info.slot)
             .dst_array_element(0); // TODO: parameterize
 
-        match (&resolved_binding, &binding.binding_info.binding_type) {
-            (ResourceType::Image(resolved_image), BindingType::Image(image_binding)) => {
+        match &binding.binding_info.binding_type {
+            BindingType::Image(image_binding) => {
+                let resolved_image = binding_ref.try_get_image().unwrap_or_else(|error| resource_panic(error, pass));
                 let (image_info, descriptor_type) = get_descriptor_image_info(resolved_image, image_binding);
                 descriptor_updates.image_infos.push(image_info);
                 descriptor_write_builder = descriptor_write_builder
                     .descriptor_type(descriptor_type)
                     .image_info(std::slice::from_ref(descriptor_updates.image_infos.last().unwrap()));
             },
-            (ResourceType::Buffer(resolved_buffer), BindingType::Buffer(buffer_binding)) => {
-                let (buffer_info, descriptor_type) = get_descriptor_buffer_info(resolved_buffer, buffer_binding);
+            BindingType::Buffer(buffer_binding) => {
+                let resolved_buffer = binding_ref.try_get_buffer().unwrap_or_else(|error| resource_panic(error, pass));
+                let (buffer_info, descriptor_type) = get_descriptor_buffer_info(
+                    resolved_buffer,
+                    buffer_binding,
+                    &binding.binding_info,
+                    pipeline);
                 descriptor_updates.buffer_infos.push(buffer_info);
                 descriptor_write_builder = descriptor_write_builder
                     .descriptor_type(descriptor_type)
                     .buffer_info(std::slice::from_ref(descriptor_updates.buffer_infos.last().unwrap()));
-            },
-            _ => {
-                panic!("Invalid type being resolved");
             }
         }
 
@@ -250,354 +508,1270 @@ fn resolve_descriptors<'a, 'b>(
     }
 }
 
+fn resolve_input_attachment_descriptors(
+    bindings: &[ResourceBinding],
+    descriptor_sets: &[vk::DescriptorSet],
+    descriptor_updates: &mut DescriptorUpdate,
+    pass: &str) {
+    enter_span!(tracing::Level::TRACE, "Resolve input attachment descriptors");
+
+    for binding in bindings {
+        let binding_ref = binding.resource.borrow();
+        let resolved_image = binding_ref.try_get_image().unwrap_or_else(|error| resource_panic(error, pass));
+        let descriptor_set = descriptor_sets[binding.binding_info.set as usize];
+
+        if let BindingType::Image(image_binding) = &binding.binding_info.binding_type {
+            // input attachments are read at the current fragment location, so there's no sampler
+            descriptor_updates.image_infos.push(vk::DescriptorImageInfo::builder()
+                .image_view(resolved_image.view)
+                .image_layout(image_binding.layout)
+                .sampler(vk::Sampler::null())
+                .build());
+            descriptor_updates.descriptor_writes.push(vk::WriteDescriptorSet::builder()
+                .dst_set(descriptor_set)
+                .dst_binding(binding.binding_info.slot)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::INPUT_ATTACHMENT)
+                .image_info(std::slice::from_ref(descriptor_updates.image_infos.last().unwrap()))
+                .build());
+        } else {
+            panic!("Pass \"{}\": input attachments must be bound as images", pass);
+        }
+    }
+}
+
+/// Writes a single binding's descriptor into a pass's descriptor buffer sets
+fn write_buffer_descriptor(
+    manager: &DescriptorBufferManager,
+    sets: &DescriptorBufferSets,
+    pipeline: &Pipeline,
+    binding: &ResourceBinding,
+    input_attachment: bool,
+    pass: &str) {
+
+    let binding_ref = binding.resource.borrow();
+    let set = binding.binding_info.set;
+    let slot = binding.binding_info.slot;
+
+    match &binding.binding_info.binding_type {
+        BindingType::Image(image_binding) => {
+            let resolved_image = binding_ref.try_get_image().unwrap_or_else(|error| resource_panic(error, pass));
+            let (image_info, descriptor_type) = match input_attachment {
+                // input attachments are read at the current fragment location, so there's no sampler
+                true => (vk::DescriptorImageInfo::builder()
+                    .image_view(resolved_image.view)
+                    .image_layout(image_binding.layout)
+                    .sampler(vk::Sampler::null())
+                    .build(), vk::DescriptorType::INPUT_ATTACHMENT),
+                false => get_descriptor_image_info(resolved_image, image_binding)
+            };
+            let data = match descriptor_type {
+                vk::DescriptorType::COMBINED_IMAGE_SAMPLER => vk::DescriptorDataEXT { p_combined_image_sampler: &image_info },
+                vk::DescriptorType::INPUT_ATTACHMENT => vk::DescriptorDataEXT { p_input_attachment_image: &image_info },
+                _ => vk::DescriptorDataEXT { p_storage_image: &image_info }
+            };
+            manager.write(sets, set, slot, &vk::DescriptorGetInfoEXT::builder()
+                .ty(descriptor_type)
+                .data(data)
+                .build());
+        },
+        BindingType::Buffer(buffer_binding) => {
+            let resolved_buffer = binding_ref.try_get_buffer().unwrap_or_else(|error| resource_panic(error, pass));
+            let (buffer_info, descriptor_type) = get_descriptor_buffer_info(
+                resolved_buffer,
+                buffer_binding,
+                &binding.binding_info,
+                pipeline);
+            let address = resolved_buffer.get_device_address()
+                .unwrap_or_else(|| panic!("Buffer {} bound by {} has no device address, which descriptor buffers require",
+                    binding_ref.get_name(), pass));
+            // descriptor buffers don't accept WHOLE_SIZE
+            let range = match buffer_info.range == vk::WHOLE_SIZE {
+                true => resolved_buffer.create_info.size - buffer_info.offset,
+                false => buffer_info.range
+            };
+            let address_info = vk::DescriptorAddressInfoEXT::builder()
+                .address(address + buffer_info.offset)
+                .range(range)
+                .build();
+            let data = match descriptor_type {
+                vk::DescriptorType::STORAGE_BUFFER => vk::DescriptorDataEXT { p_storage_buffer: &address_info },
+                _ => vk::DescriptorDataEXT { p_uniform_buffer: &address_info }
+            };
+            manager.write(sets, set, slot, &vk::DescriptorGetInfoEXT::builder()
+                .ty(descriptor_type)
+                .data(data)
+                .build());
+        }
+    }
+}
+
+/// Panics, listing every mismatch, if a struct uploaded into one of `bindings` doesn't have the
+/// layout of the pipeline's uniform block at the same set and slot
+fn validate_uniform_layouts(pipeline: &Pipeline, bindings: &[&[ResourceBinding]], name: &str) {
+    for binding in bindings.iter().flat_map(|bindings| bindings.iter()) {
+        let info = &binding.binding_info;
+        let layout = match &info.binding_type {
+            BindingType::Buffer(BufferBindingInfo { layout: Some(layout), .. }) => *layout,
+            _ => continue
+        };
+        if let Some(block) = pipeline.get_uniform_block(info.set as u32, info.slot) {
+            let mismatches = uniform_layout_mismatches(layout, block);
+            if !mismatches.is_empty() {
+                panic!("Node {} uploads {} into uniform block {} (set {}, slot {}) with a different layout:\n    {}",
+                    name, layout.name, block.name, info.set, info.slot, mismatches.join("\n    "));
+            }
+        }
+    }
+}
+
+/// Panics if any of `bindings` is at a set and slot the pipeline's shaders don't declare, which
+/// would otherwise index past the pass's descriptor sets or write to the wrong one, or is in a
+/// set nodes can't bind resources in
+fn validate_binding_slots(pipeline: &Pipeline, bindings: &[&[ResourceBinding]], name: &str) {
+    for binding in bindings.iter().flat_map(|bindings| bindings.iter()) {
+        let info = &binding.binding_info;
+        let frequency = BindingFrequency::from_set(info.set);
+        if frequency != BindingFrequency::PerPass {
+            panic!("Node {} binds a resource to set {}, slot {}, which is the {:?} set. Nodes only bind per-pass resources",
+                name, info.set, info.slot, frequency);
+        }
+        if pipeline.get_descriptor_type(info.set as u32, info.slot).is_none() {
+            let layout: Vec<String> = pipeline.get_descriptor_layout().iter()
+                .map(|(set, slot, descriptor_type)| format!("set {}, slot {}: {:?}", set, slot, descriptor_type))
+                .collect();
+            panic!("Node {} binds a resource to set {}, slot {}, which isn't in its pipeline's layout:\n    {}",
+                name, info.set, info.slot, layout.join("\n    "));
+        }
+    }
+}
+
+/// The frame's per-frame bindings which the pipeline declares. Panics if it declares any the
+/// frame doesn't bind
+fn get_per_frame_bindings(pipeline: &Pipeline, per_frame_bindings: &[ResourceBinding], name: &str) -> Vec<ResourceBinding> {
+    let per_frame_set = BindingFrequency::PerFrame.get_set() as u32;
+    let declared_bindings: Vec<ResourceBinding> = pipeline.get_descriptor_layout().iter()
+        .filter(|(set, _, _)| *set == per_frame_set)
+        .map(|(set, slot, descriptor_type)| {
+            per_frame_bindings.iter()
+                .find(|binding| binding.binding_info.slot == *slot)
+                .cloned()
+                .unwrap_or_else(|| panic!("Node {} reads set {}, slot {} ({:?}), which the Frame doesn't bind (see Frame::bind_per_frame)",
+                    name, set, slot, descriptor_type))
+        })
+        .collect();
+    if cfg!(debug_assertions) {
+        validate_uniform_layouts(pipeline, &[&declared_bindings], name);
+    }
+    declared_bindings
+}
+
+/// The pipeline's set layouts, with null layouts for the sets the executor doesn't write for
+/// its passes
+fn get_pass_set_layouts(pipeline: &Pipeline) -> Vec<vk::DescriptorSetLayout> {
+    pipeline.device_pipeline.descriptor_set_layouts.iter().enumerate()
+        .map(|(set, layout)| match BindingFrequency::from_set(set as u64) {
+            BindingFrequency::PerDraw => vk::DescriptorSetLayout::null(),
+            _ => *layout
+        })
+        .collect()
+}
+
+/// Allocates a set with each of `layouts` which isn't null, returning the sets by index with
+/// null handles in place of the others
+fn allocate_sets(
+    layouts: &[vk::DescriptorSetLayout],
+    allocate: impl FnOnce(&[vk::DescriptorSetLayout]) -> Vec<vk::DescriptorSet>) -> Vec<vk::DescriptorSet> {
+
+    let allocated_layouts: Vec<vk::DescriptorSetLayout> = layouts.iter()
+        .filter(|layout| **layout != vk::DescriptorSetLayout::null())
+        .copied()
+        .collect();
+    let mut allocated = allocate(&allocated_layouts).into_iter();
+    layouts.iter()
+        .map(|layout| match *layout == vk::DescriptorSetLayout::null() {
+            true => vk::DescriptorSet::null(),
+            false => allocated.next().expect("Fewer descriptor sets were allocated than requested")
+        })
+        .collect()
+}
+
+/// The frame's per-frame set with `layout`, which is allocated and written by the first pass
+/// whose pipeline declares it
+fn get_per_frame_set(
+    render_context: &mut VulkanRenderContext,
+    pipeline: &Pipeline,
+    layout: vk::DescriptorSetLayout,
+    frame_descriptors: &mut FrameDescriptors,
+    name: &str) -> vk::DescriptorSet {
+
+    if let Some(per_frame_set) = frame_descriptors.per_frame_sets.get(&layout) {
+        return *per_frame_set;
+    }
+
+    let per_frame_bindings = get_per_frame_bindings(pipeline, &frame_descriptors.per_frame_bindings, name);
+    let per_frame_set = render_context.create_descriptor_sets(std::slice::from_ref(&layout), "per_frame")[0];
+    let set_index = BindingFrequency::PerFrame.get_set() as usize;
+    let mut descriptor_sets = vec![vk::DescriptorSet::null(); set_index + 1];
+    descriptor_sets[set_index] = per_frame_set;
+
+    let mut descriptor_updates = DescriptorUpdate::new();
+    resolve_descriptors(&per_frame_bindings, pipeline, &descriptor_sets, &mut descriptor_updates, name);
+    unsafe {
+        render_context.get_device().borrow().get().update_descriptor_sets(
+            &descriptor_updates.descriptor_writes,
+            &[]);
+    }
+
+    frame_descriptors.descriptor_sets.push(per_frame_set);
+    frame_descriptors.per_frame_sets.insert(layout, per_frame_set);
+    per_frame_set
+}
+
+/// Writes and binds a pass's descriptors, through descriptor buffers when the device uses them
+/// and otherwise through descriptor sets, which are added to the frame's. The per-frame set is
+/// only written by the first pass using it, and bound for the others
+fn bind_pass_descriptors(
+    render_context: &mut VulkanRenderContext,
+    command_buffer: vk::CommandBuffer,
+    bind_point: vk::PipelineBindPoint,
+    pipeline: &Pipeline,
+    bindings: &[&[ResourceBinding]],
+    input_attachments: &[ResourceBinding],
+    name: &str,
+    frame_descriptors: &mut FrameDescriptors) {
+    enter_span!(tracing::Level::TRACE, "Update and bind descriptors");
+
+    validate_binding_slots(pipeline, bindings, name);
+    validate_binding_slots(pipeline, &[input_attachments], name);
+    if cfg!(debug_assertions) {
+        validate_uniform_layouts(pipeline, bindings, name);
+    }
+
+    let mut layouts = get_pass_set_layouts(pipeline);
+    let per_frame_set = BindingFrequency::PerFrame.get_set() as usize;
+    let per_frame_layout = layouts.get(per_frame_set).copied()
+        .filter(|layout| *layout != vk::DescriptorSetLayout::null());
+
+    if render_context.uses_descriptor_buffers() {
+        // descriptors are plain copies into the frame's descriptor buffer, so rather than being
+        // shared the per-frame set is staged along with each pass's own sets
+        let per_frame_bindings = match per_frame_layout {
+            Some(_) => get_per_frame_bindings(pipeline, &frame_descriptors.per_frame_bindings, name),
+            None => Vec::new()
+        };
+        let sets = render_context.allocate_descriptor_buffer_sets(&layouts, name);
+        let manager = render_context.get_descriptor_buffer_manager()
+            .expect("Descriptor buffers are in use without a descriptor buffer manager");
+        for binding in bindings.iter().flat_map(|bindings| bindings.iter()).chain(&per_frame_bindings) {
+            write_buffer_descriptor(manager, &sets, pipeline, binding, false, name);
+        }
+        for binding in input_attachments {
+            write_buffer_descriptor(manager, &sets, pipeline, binding, true, name);
+        }
+        manager.bind(command_buffer, bind_point, pipeline.get_pipeline_layout(), &sets);
+        return;
+    }
+
+    if per_frame_layout.is_some() {
+        layouts[per_frame_set] = vk::DescriptorSetLayout::null();
+    }
+    let mut descriptor_sets = allocate_sets(&layouts, |layouts| render_context.create_descriptor_sets(layouts, name));
+    frame_descriptors.descriptor_sets.extend(descriptor_sets.iter().filter(|set| **set != vk::DescriptorSet::null()));
+    if let Some(per_frame_layout) = per_frame_layout {
+        descriptor_sets[per_frame_set] = get_per_frame_set(render_context, pipeline, per_frame_layout, frame_descriptors, name);
+    }
+
+    write_descriptor_sets(
+        render_context,
+        command_buffer,
+        bind_point,
+        pipeline,
+        bindings,
+        input_attachments,
+        &descriptor_sets,
+        name);
+}
+
+/// Writes a pass's descriptors into `descriptor_sets`, which must have been allocated from the
+/// pipeline's layouts, and binds them. Null sets are skipped
+fn write_descriptor_sets(
+    render_context: &VulkanRenderContext,
+    command_buffer: vk::CommandBuffer,
+    bind_point: vk::PipelineBindPoint,
+    pipeline: &Pipeline,
+    bindings: &[&[ResourceBinding]],
+    input_attachments: &[ResourceBinding],
+    descriptor_sets: &[vk::DescriptorSet],
+    name: &str) {
+
+    let mut descriptor_updates = DescriptorUpdate::new();
+    for bindings in bindings {
+        resolve_descriptors(
+            bindings,
+            pipeline,
+            descriptor_sets,
+            &mut descriptor_updates,
+            name);
+    }
+    resolve_input_attachment_descriptors(
+        input_attachments,
+        descriptor_sets,
+        &mut descriptor_updates,
+        name);
+
+    unsafe {
+        // TODO: support descriptor copies?
+        render_context.get_device().borrow().get().update_descriptor_sets(
+            &descriptor_updates.descriptor_writes,
+            &[]);
+        // null sets can't be bound, so the sets either side of them are bound separately
+        let mut first_set = 0;
+        for sets in descriptor_sets.split(|set| *set == vk::DescriptorSet::null()) {
+            if !sets.is_empty() {
+                render_context.get_device().borrow().get().cmd_bind_descriptor_sets(
+                    command_buffer,
+                    bind_point,
+                    pipeline.get_pipeline_layout(),
+                    first_set as u32,
+                    sets,
+                    &[]);
+            }
+            first_set += sets.len() + 1;
+        }
+    }
+}
+
+fn get_framebuffer_extent(attachments: &[ImageWrapper]) -> vk::Extent3D {
+    // Ensure all rendertargets are the same dimensions
+    let mut extent: Option<vk::Extent3D> = None;
+    for attachment in attachments {
+        match extent {
+            Some(extent) => {
+                assert_eq!(extent, attachment.extent, "All framebuffer attachments must be the same dimensions");
+            },
+            None => {
+                extent = Some(attachment.extent.clone());
+            }
+        }
+    }
+    extent.expect("Framebuffer required for renderpass")
+}
+
+/// The layers of a framebuffer over `attachments`. Multiview renderpasses select layers
+/// through their views and need exactly 1, while other renderpasses can reach every layer
+/// all of the attachments have through layered rendering
+fn get_framebuffer_layers(attachments: &[ImageWrapper], multiview: Option<Multiview>, device_multiview: bool) -> u32 {
+    match multiview {
+        Some(multiview) => {
+            assert!(device_multiview, "Multiview rendering needs NegotiatedFeature::Multiview to be enabled");
+            for attachment in attachments {
+                assert!(attachment.array_layers >= multiview.get_layer_count(),
+                    "Multiview attachments need a layer for each of the {} views, but one only has {}",
+                    multiview.get_layer_count(),
+                    attachment.array_layers);
+            }
+            1
+        },
+        None => attachments.iter().map(|attachment| attachment.array_layers).min().unwrap_or(1)
+    }
+}
+
+fn get_renderpass_group(node: &PassType) -> Option<&str> {
+    match node {
+        PassType::Graphics(gn) => gn.get_renderpass_group(),
+        _ => None
+    }
+}
+
+/// Folds each clear node (see PassNodeBuilder::clears) into the node which executes
+/// immediately after it, if that node renders to the cleared image and leaves the
+/// attachment's load up to the framegraph. Merged clear nodes are removed from the graph
+fn merge_clear_passes(
+    nodes: &mut StableDiGraph<PassType, u32>,
+    sorted_nodes: Vec<NodeIndex>) -> Vec<NodeIndex> {
+
+    let mut merged: HashSet<NodeIndex> = HashSet::new();
+    for pair in sorted_nodes.windows(2) {
+        let (clear_handle, clear_load) = match &nodes[pair[0]] {
+            PassType::Graphics(gn) => match gn.clear_value {
+                Some(clear_value) => {
+                    let clear_load = if gn.clear_aspects.contains(vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL) {
+                        AttachmentLoad::ClearDepthStencil(clear_value)
+                    } else if gn.clear_aspects == vk::ImageAspectFlags::STENCIL {
+                        // a renderpass can't clear the stencil without clearing the depth too
+                        continue
+                    } else {
+                        AttachmentLoad::Clear(clear_value)
+                    };
+                    (gn.outputs[0].resource.borrow().get_handle(), clear_load)
+                },
+                None => continue
+            },
+            _ => continue
+        };
+
+        if let PassType::Graphics(next) = &mut nodes[pair[1]] {
+            let attachment = next.render_targets.iter_mut().chain(next.depth_target.as_mut()).find(|attachment| {
+                attachment.resource_image.borrow().get_handle() == clear_handle
+            });
+            if let Some(attachment) = attachment {
+                if matches!(attachment.load, AttachmentLoad::Auto) {
+                    attachment.load = clear_load;
+                    trace!(target: "framegraph", "Merging clear of {} into {}", clear_handle, next.get_name());
+                    merged.insert(pair[0]);
+                }
+            }
+        }
+    }
+
+    if merged.is_empty() {
+        return sorted_nodes;
+    }
+    nodes.retain_nodes(|_graph, node_index| {
+        !merged.contains(&node_index)
+    });
+    sorted_nodes.into_iter().filter(|index| !merged.contains(index)).collect()
+}
+
+fn resolve_load_op(
+    attachment: &AttachmentReference,
+    written: &HashSet<u64>,
+    transient_handles: &HashSet<u64>) -> vk::AttachmentLoadOp {
+
+    match attachment.load {
+        AttachmentLoad::Load => vk::AttachmentLoadOp::LOAD,
+        AttachmentLoad::DontCare => vk::AttachmentLoadOp::DONT_CARE,
+        AttachmentLoad::Clear(_) |
+        AttachmentLoad::ClearDepthStencil(_) => vk::AttachmentLoadOp::CLEAR,
+        AttachmentLoad::Auto => {
+            let resource = attachment.resource_image.borrow();
+            let handle = resource.get_handle();
+            let has_contents = written.contains(&handle) ||
+                (!transient_handles.contains(&handle) && resource.get_image().layout != vk::ImageLayout::UNDEFINED);
+            if has_contents {
+                vk::AttachmentLoadOp::LOAD
+            } else {
+                vk::AttachmentLoadOp::DONT_CARE
+            }
+        }
+    }
+}
+
+/// Resolves the load op of every attachment from whether its previous contents can be read
+/// (see AttachmentLoad::Auto). Must run before linking, while images are still in the
+/// layouts they started the frame in
+fn deduce_load_ops(
+    nodes: &mut StableDiGraph<PassType, u32>,
+    sorted_nodes: &[NodeIndex],
+    transient_handles: &HashSet<u64>) {
+
+    let mut written: HashSet<u64> = HashSet::new();
+    for node_index in sorted_nodes {
+        let node = nodes.node_weight_mut(*node_index).unwrap();
+        if let PassType::Graphics(gn) = node {
+            for attachment in gn.render_targets.iter_mut().chain(gn.depth_target.as_mut()) {
+                attachment.load_op = resolve_load_op(attachment, &written, transient_handles);
+            }
+        }
+        written.extend(node.get_writes());
+    }
+}
+
+/// Discards the contents of transient attachments nothing reads after the node rendering to
+/// them, by storing them with DONT_CARE, and warns about the node's other writes of transient
+/// resources nothing reads, which can't be dropped without changing its shaders. Must run after
+/// deduce_load_ops, since an attachment which isn't loaded doesn't read the earlier contents
+fn eliminate_dead_writes(
+    nodes: &mut StableDiGraph<PassType, u32>,
+    sorted_nodes: &[NodeIndex],
+    transient_handles: &HashSet<u64>) {
+
+    for (node_index, dead) in graph_core::find_dead_writes(nodes, sorted_nodes, transient_handles) {
+        let node = nodes.node_weight_mut(node_index).unwrap();
+        let mut discarded: HashSet<u64> = HashSet::new();
+        if let PassType::Graphics(gn) = node {
+            for attachment in gn.render_targets.iter_mut().chain(gn.depth_target.as_mut()) {
+                let handle = attachment.resource_image.borrow().get_handle();
+                if dead.contains(&handle) {
+                    attachment.store_op = vk::AttachmentStoreOp::DONT_CARE;
+                    discarded.insert(handle);
+                }
+            }
+        }
+        for handle in dead.iter().filter(|handle| !discarded.contains(handle)) {
+            log::warn!(target: "framegraph", "Node {} writes transient resource {}, which nothing reads", node.get_name(), handle);
+        }
+    }
+}
+
+/// Applies the states a Frame's imports were declared in when it was built, now that every
+/// Frame before it has been recorded
+fn apply_initial_states(frame: &Frame, render_context: &VulkanRenderContext) {
+    for import in &frame.imports {
+        if let Some(initial_state) = import.initial_state {
+            let mut resource = import.resource.borrow_mut();
+            let handle = resource.get_handle();
+            if let Some(ResourceType::Image(image)) = resource.resource_type.as_mut() {
+                image.layout = initial_state.layout.expect("Imported images require a current layout");
+            }
+            render_context.update_resource_state(handle, initial_state);
+        }
+    }
+
+    // the layout field isn't kept up to date by every transition, while the tracked state is
+    for resource in frame.get_persistent_resources() {
+        let mut resource = resource.borrow_mut();
+        let previous_state = render_context.get_resource_state(resource.get_handle());
+        if let Some(ResourceType::Image(image)) = resource.resource_type.as_mut() {
+            image.layout = previous_state
+                .and_then(|state| state.layout)
+                .unwrap_or(vk::ImageLayout::UNDEFINED);
+        }
+    }
+}
+
+fn end_command_list(render_context: &VulkanRenderContext, command_buffer: vk::CommandBuffer) {
+    unsafe {
+        render_context.get_device().borrow().get().end_command_buffer(command_buffer)
+            .expect("Failed to finish recording command list");
+    }
+}
+
+/// Requests the command buffer `command_list` should be recorded into from `provider`, and
+/// makes CPU writes visible to the frame's GPU work at the start of the first one
+fn begin_command_list(
+    recorded: &mut Vec<RecordedCommandList>,
+    command_list: &CommandList,
+    render_context: &mut VulkanRenderContext,
+    provider: &mut CommandBufferProvider<'_>) -> vk::CommandBuffer {
+
+    let command_buffer = provider(render_context, recorded.len());
+
+    if recorded.is_empty() {
+        let host_barrier = vk::MemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::HOST_WRITE)
+            .dst_access_mask(vk::AccessFlags::UNIFORM_READ
+                | vk::AccessFlags::INDEX_READ
+                | vk::AccessFlags::VERTEX_ATTRIBUTE_READ)
+            .build();
+
+        unsafe {
+            render_context.get_device().borrow().get().cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::HOST,
+                vk::PipelineStageFlags::VERTEX_INPUT | vk::PipelineStageFlags::VERTEX_SHADER,
+                vk::DependencyFlags::empty(),
+                &[host_barrier],
+                &[],
+                &[]);
+        }
+    }
+
+    // waits can't be on an empty stage mask
+    let wait_stage_mask = command_list.wait.as_ref()
+        .map(|wait| wait.wait_stage_mask)
+        .filter(|stage_mask| !stage_mask.is_empty())
+        .unwrap_or(vk::PipelineStageFlags::ALL_COMMANDS);
+    recorded.push(RecordedCommandList {
+        command_buffer,
+        wait_stage_mask
+    });
+
+    command_buffer
+}
+
+/// Sets the node's viewport and scissor, covering its whole render area unless it has its own.
+/// In debug builds, panics if the node's pipeline has a dynamic state which is set neither here
+/// nor by the fill callback (see PassNodeBuilder::sets_dynamic_state), since drawing without
+/// it is undefined
+fn set_dynamic_state(
+    node: &GraphicsPassNode,
+    render_context: &VulkanRenderContext,
+    command_buffer: &vk::CommandBuffer) {
+
+    let render_area = node.get_render_area();
+    let viewport = node.viewport.or_else(|| render_area.map(|extent| {
+        vk::Viewport::builder()
+            .x(0.0)
+            .y(0.0)
+            .width(extent.width as f32)
+            .height(extent.height as f32)
+            .min_depth(0.0)
+            .max_depth(1.0)
+            .build()
+    }));
+    let scissor = node.scissor.or_else(|| render_area.map(|extent| {
+        vk::Rect2D::builder()
+            .offset(vk::Offset2D { x: 0, y: 0 })
+            .extent(extent)
+            .build()
+    }));
+
+    if cfg!(debug_assertions) {
+        if let Some(pipeline_description) = node.get_pipeline_description() {
+            let mut set_states = node.command_dynamic_states.clone();
+            if viewport.is_some() {
+                set_states.push(vk::DynamicState::VIEWPORT);
+            }
+            if scissor.is_some() {
+                set_states.push(vk::DynamicState::SCISSOR);
+            }
+            let unset_states = unset_dynamic_states(&pipeline_description.get_dynamic_states(), &set_states);
+            assert!(unset_states.is_empty(),
+                "Node {} draws with pipeline {}, whose dynamic states {:?} are never set",
+                node.get_name(), pipeline_description.get_name(), unset_states);
+        }
+    }
+
+    if let Some(viewport) = &viewport {
+        unsafe {
+            render_context.get_device().borrow().get().cmd_set_viewport(
+                *command_buffer,
+                0,
+                std::slice::from_ref(viewport));
+        }
+    }
+
+    if let Some(scissor) = &scissor {
+        unsafe {
+            render_context.get_device().borrow().get().cmd_set_scissor(
+                *command_buffer,
+                0,
+                std::slice::from_ref(scissor));
+        }
+    }
+}
+
+fn get_vk_buffer(resource: &Rc<RefCell<DeviceResource>>) -> vk::Buffer {
+    match resource.borrow().resource_type.as_ref() {
+        Some(ResourceType::Buffer(buffer)) => buffer.buffer,
+        _ => panic!("Vertex, index and indirect buffers must be buffer resources")
+    }
+}
+
+/// Binds the node's vertex and index buffers, so its fill callback only needs to draw
+fn bind_geometry_buffers(
+    node: &GraphicsPassNode,
+    render_context: &VulkanRenderContext,
+    command_buffer: &vk::CommandBuffer) {
+
+    let device = render_context.get_device();
+    let device = device.borrow();
+    if !node.vertex_buffers.is_empty() {
+        let buffers: Vec<vk::Buffer> = node.vertex_buffers.iter()
+            .map(|vertex_buffer| get_vk_buffer(&vertex_buffer.resource))
+            .collect();
+        let offsets: Vec<vk::DeviceSize> = node.vertex_buffers.iter()
+            .map(|vertex_buffer| vertex_buffer.offset)
+            .collect();
+        unsafe {
+            device.get().cmd_bind_vertex_buffers(*command_buffer, 0, &buffers, &offsets);
+        }
+    }
+
+    if let Some(index_buffer) = &node.index_buffer {
+        unsafe {
+            device.get().cmd_bind_index_buffer(
+                *command_buffer,
+                get_vk_buffer(&index_buffer.resource),
+                index_buffer.offset,
+                index_buffer.index_type);
+        }
+    }
+}
+
+/// `pass` names what the barriers were recorded for, should one of their resources fail to resolve
+fn record_barriers(
+    barriers: &NodeBarriers,
+    pass: &str,
+    render_context: &VulkanRenderContext,
+    command_buffer: &vk::CommandBuffer) {
+    enter_span!(tracing::Level::TRACE, "Generate barriers");
+
+    #[cfg(feature = "graph-debug")]
+    for image_barrier in &barriers.image_barriers {
+        graph_debug!(
+            resource = image_barrier.resource.borrow().get_handle(),
+            source_stage = ?image_barrier.source_stage,
+            dest_stage = ?image_barrier.dest_stage,
+            source_access = ?image_barrier.source_access,
+            dest_access = ?image_barrier.dest_access,
+            old_layout = ?image_barrier.old_layout,
+            new_layout = ?image_barrier.new_layout,
+            subresource = ?image_barrier.subresource,
+            "image barrier");
+    }
+    #[cfg(feature = "graph-debug")]
+    for buffer_barrier in &barriers.buffer_barriers {
+        graph_debug!(
+            resource = buffer_barrier.resource.borrow().get_handle(),
+            source_stage = ?buffer_barrier.source_stage,
+            dest_stage = ?buffer_barrier.dest_stage,
+            source_access = ?buffer_barrier.source_access,
+            dest_access = ?buffer_barrier.dest_access,
+            offset = buffer_barrier.offset,
+            size = buffer_barrier.size,
+            "buffer barrier");
+    }
+
+    // Create the source and dest stage masks
+    let mut source_stage = vk::PipelineStageFlags::NONE;
+    let mut dest_stage = vk::PipelineStageFlags::NONE;
+    for image_barrier in &barriers.image_barriers {
+        source_stage |= image_barrier.source_stage;
+        dest_stage |= image_barrier.dest_stage;
+    }
+    for buffer_barrier in &barriers.buffer_barriers {
+        source_stage |= buffer_barrier.source_stage;
+        dest_stage |= buffer_barrier.dest_stage;
+    }
+    // empty stage masks are only valid with synchronization2, which older MoltenVK lacks
+    if !render_context.get_device().borrow().is_feature_enabled(NegotiatedFeature::Synchronization2) {
+        if source_stage.is_empty() {
+            source_stage = vk::PipelineStageFlags::TOP_OF_PIPE;
+        }
+        if dest_stage.is_empty() {
+            dest_stage = vk::PipelineStageFlags::BOTTOM_OF_PIPE;
+        }
+    }
+
+    // translate from our BufferBarrier to Vulkan
+    let transformed_buffer_barriers: Vec<vk::BufferMemoryBarrier> = barriers.buffer_barriers.iter().map(|bb| {
+        let buffer = bb.resource.borrow();
+        let resolved_buffer = buffer.try_get_buffer().unwrap_or_else(|error| resource_panic(error, pass));
+        vk::BufferMemoryBarrier::builder()
+            .buffer(resolved_buffer.buffer)
+            .src_access_mask(bb.source_access)
+            .dst_access_mask(bb.dest_access)
+            .offset(bb.offset as DeviceSize)
+            .size(bb.size as DeviceSize)
+            .src_queue_family_index(render_context.get_graphics_queue_index())
+            .dst_queue_family_index(render_context.get_graphics_queue_index())
+            .build()
+    }).collect();
+
+    // translate from our ImageBarrier to Vulkan
+    let transformed_image_barriers: Vec<vk::ImageMemoryBarrier> = barriers.image_barriers.iter().map(|ib| {
+        let image = ib.resource.borrow();
+        let resolved_image = image.try_get_image().unwrap_or_else(|error| resource_panic(error, pass));
+        let aspect_mask = util::image::get_aspect_mask_from_format(
+            resolved_image.format);
+        let range = ib.subresource.to_vk(aspect_mask);
+        vk::ImageMemoryBarrier::builder()
+            .image(resolved_image.image)
+            .src_access_mask(ib.source_access)
+            .dst_access_mask(ib.dest_access)
+            .old_layout(ib.old_layout)
+            .new_layout(ib.new_layout)
+            .src_queue_family_index(render_context.get_graphics_queue_index())
+            .dst_queue_family_index(render_context.get_graphics_queue_index())
+            .subresource_range(range)
+            .build()
+    }).collect();
+
+    if transformed_image_barriers.len() > 0 || transformed_buffer_barriers.len() > 0 {
+        unsafe {
+            render_context.get_device().borrow().get().cmd_pipeline_barrier(
+                *command_buffer,
+                source_stage,
+                dest_stage,
+                vk::DependencyFlags::empty(),
+                &[],
+                &transformed_buffer_barriers,
+                &transformed_image_barriers);
+        }
+    }
+}
+
+/// Tracks the renderpass of a group of subpass nodes while it's being recorded
+struct ActiveRenderpassGroup {
+    renderpass: Rc<RefCell<DeviceRenderpass>>,
+    // every member's subpass, which the group's pipelines are all keyed by
+    subpass_targets: Vec<SubpassTargets>,
+    subpass_index: u32,
+    subpass_count: u32
+}
+
+#[derive(Default)]
 pub struct NodeBarriers {
-    image_barriers: Vec<ImageBarrier>,
-    buffer_barriers: Vec<BufferBarrier>
+    pub(crate) image_barriers: Vec<ImageBarrier>,
+    pub(crate) buffer_barriers: Vec<BufferBarrier>
 }
 
-impl Debug for NodeBarriers {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("NodeBarriers")
-            .field("image barriers", &self.image_barriers.len())
-            .field("buffer barriers", &self.buffer_barriers.len())
-            .finish()
-    }
-}
+impl Debug for NodeBarriers {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("NodeBarriers")
+            .field("image barriers", &self.image_barriers.len())
+            .field("buffer barriers", &self.buffer_barriers.len())
+            .finish()
+    }
+}
+
+#[derive(Debug)]
+pub struct VulkanFrameGraph {
+    pipeline_manager: VulkanPipelineManager,
+    renderpass_manager: VulkanRenderpassManager,
+    node_barriers: HashMap<NodeIndex, NodeBarriers>,
+    // recorded after the frame's last node, see graph_core::LinkedGraph::final_transitions
+    final_barriers: NodeBarriers,
+    graph_cache: GraphCache,
+    // fill callback time accumulated by the execute_*_node functions for the current node
+    fill_duration: Duration,
+    // layout hash of the pipeline bound by the current node, if it used one
+    pass_layout_hash: Option<u64>,
+    // whether the current node executed its retained recording
+    pass_replayed: bool,
+    retained_passes: RetainedPasses,
+    pass_budget: Option<Duration>,
+    last_frame_stats: FrameStats,
+    resource_error_policy: ResourceErrorPolicy,
+    // transient resources of the previous Frame, see validate_transient_lifetimes
+    previous_transients: HashSet<u64>,
+    capture: GpuCapture,
+    frames_started: u64,
+    // the serial of the last Frame recorded, so Frames built ahead are recorded in order
+    last_recorded: Option<u64>
+}
+
+impl Drop for VulkanFrameGraph {
+    fn drop(&mut self) {
+        println!("Dropping VulkanFrameGraph");
+    }
+}
+
+impl VulkanFrameGraph {
+    pub fn new(
+        renderpass_manager: VulkanRenderpassManager,
+        pipeline_manager: VulkanPipelineManager) -> VulkanFrameGraph {
+
+        VulkanFrameGraph {
+            pipeline_manager,
+            renderpass_manager,
+            node_barriers: HashMap::new(),
+            final_barriers: NodeBarriers::default(),
+            graph_cache: GraphCache::default(),
+            fill_duration: Duration::ZERO,
+            pass_layout_hash: None,
+            pass_replayed: false,
+            retained_passes: RetainedPasses::default(),
+            pass_budget: None,
+            last_frame_stats: FrameStats::default(),
+            resource_error_policy: ResourceErrorPolicy::default(),
+            previous_transients: HashSet::new(),
+            capture: GpuCapture::new(),
+            frames_started: 0,
+            last_recorded: None
+        }
+    }
+
+    /// Logs a warning for every node whose CPU recording time exceeds `budget`
+    pub fn set_pass_budget(&mut self, budget: Option<Duration>) {
+        self.pass_budget = budget;
+    }
+
+    pub fn set_resource_error_policy(&mut self, policy: ResourceErrorPolicy) {
+        self.resource_error_policy = policy;
+    }
+
+    /// See VulkanPipelineManager::clear_pipelines. Retained nodes are recorded again, since
+    /// new pipelines may reuse the handles of the destroyed ones
+    pub fn invalidate_pipelines(&mut self) {
+        self.pipeline_manager.clear_pipelines();
+        self.retained_passes.clear();
+    }
+
+    /// CPU timings for the most recently ended Frame
+    pub fn get_last_frame_stats(&self) -> &FrameStats {
+        &self.last_frame_stats
+    }
+
+    pub fn get_capture(&self) -> &GpuCapture {
+        &self.capture
+    }
+
+    /// Requests RenderDoc captures and labels the captured frame or pass for Nsight
+    pub fn get_capture_mut(&mut self) -> &mut GpuCapture {
+        &mut self.capture
+    }
+
+    /// Compiles and records `frame` into command buffers requested from
+    /// `command_buffer_provider`, for applications which manage command buffers and submission
+    /// themselves. The provider is called with the index of each command list and must return a
+    /// command buffer in the recording state. The returned command buffers are left recording;
+    /// the caller ends them and submits them in order (see RecordedCommandList::wait_stage_mask)
+    #[tracing::instrument(skip(command_buffer_provider))]
+    pub fn record(
+        &mut self,
+        frame: &mut Frame,
+        render_context: &mut VulkanRenderContext,
+        command_buffer_provider: &mut CommandBufferProvider<'_>) -> Vec<RecordedCommandList> {
+
+        frame.end();
+        self.validate_frame_resources(frame);
+        assert!(self.last_recorded.map_or(true, |last_recorded| frame.serial > last_recorded),
+            "Frames must be recorded in the order they were started");
+        self.last_recorded = Some(frame.serial);
+        graph_debug::begin_frame(frame.serial);
+        graph_debug!("recording frame");
+        self.capture.begin_frame();
+        apply_initial_states(frame, render_context);
+
+        // renderpasses for the previous swapchain format won't be used again
+        self.renderpass_manager.set_swapchain_format(
+            render_context.get_swapchain().as_ref().map(|swapchain| swapchain.get_format()));
+
+        let root_indices = frame.get_root_indices().to_vec();
+        let transient_handles = frame.get_transient_handles();
+        graph_core::validate_transient_lifetimes(&frame.nodes, &transient_handles, &self.previous_transients);
+        graph_core::validate_persistent_resources(&frame.get_persistent_handles(), &transient_handles);
+
+        let mut frame_stats = FrameStats::default();
+
+        // compile and link frame, unless an identical graph has already been compiled and linked
+        let compile_start = Instant::now();
+        let command_lists = {
+            let resource_keys = ResourceKeys::new(&frame.nodes, &transient_handles);
+            let fingerprint = graph_fingerprint(&frame.nodes, &root_indices, &resource_keys, render_context);
+            match self.graph_cache.get(fingerprint) {
+                Some(cached) => {
+                    trace!(target: "framegraph", "Reusing compiled graph {:#x}", fingerprint);
+                    graph_debug!(fingerprint, "graph cache hit");
+                    frame_stats.graph_cache_hit = true;
+                    cached.apply(&mut frame.nodes, &mut self.node_barriers, &mut self.final_barriers, &resource_keys, render_context)
+                },
+                None => {
+                    graph_debug!(fingerprint, "graph cache miss");
+                    let sorted_nodes = self.compile(&mut frame.nodes, &root_indices);
+                    let sorted_nodes = merge_clear_passes(&mut frame.nodes, sorted_nodes);
+                    deduce_load_ops(&mut frame.nodes, &sorted_nodes, &transient_handles);
+                    eliminate_dead_writes(&mut frame.nodes, &sorted_nodes, &transient_handles);
+                    graph_core::validate_transient_reads(&frame.nodes, &sorted_nodes, &transient_handles);
+                    let command_lists = self.link(&mut frame.nodes, &sorted_nodes, render_context);
+                    let cached = CachedGraph::capture(
+                        &frame.nodes,
+                        &sorted_nodes,
+                        &command_lists,
+                        &self.node_barriers,
+                        &self.final_barriers,
+                        &resource_keys,
+                        render_context);
+                    self.graph_cache.insert(fingerprint, cached);
+                    command_lists
+                }
+            }
+        };
+        frame_stats.compile_link = compile_start.elapsed();
+        // transient resources created while this frame was built, attributed to the first node
+        // using them as it's recorded
+        let mut untagged_allocations = render_context.get_device().borrow_mut().take_untagged_allocations();
+        untagged_allocations.retain(|handle, _| transient_handles.contains(handle));
+        let untagged_allocated: u64 = untagged_allocations.values().sum();
+        self.previous_transients = transient_handles;
+        let layouts_created_before = self.pipeline_manager.get_pipeline_layouts_created();
+
+        // excute nodes, recording each command list into its own command buffer
+        let mut active_group: Option<ActiveRenderpassGroup> = None;
+        let mut recorded: Vec<RecordedCommandList> = Vec::new();
+        for command_list in command_lists {
+            enter_span!(tracing::Level::TRACE, "Filling command lists");
+            let command_buffer = &begin_command_list(&mut recorded, &command_list, render_context, command_buffer_provider);
+            let frame_label = self.capture.get_frame_label();
+            if let Some(label) = frame_label {
+                render_context.get_device().borrow().push_debug_label(*command_buffer, label);
+            }
+            let mut barrier_history = BarrierHistory::default();
+            for (position, index) in command_list.nodes.iter().enumerate() {
+                enter_span!(tracing::Level::TRACE, "Node", "{}", index.index());
+                let capture_label = self.capture.get_pass_label(frame.nodes[*index].get_name());
+                if let Some(label) = &capture_label {
+                    render_context.get_device().borrow().push_debug_label(*command_buffer, label);
+                }
+                let node_name = frame.nodes[*index].get_interned_name();
+                render_context.get_device().borrow().push_name_debug_label(*command_buffer, node_name);
+                // attributes anything the node's fill callback allocates to the node
+                render_context.get_device().borrow_mut().push_allocation_tag(node_name);
+                let pass_scope = render_context.get_profiler().scope(&node_name, command_buffer);
+
+                let mut pass_timing = PassTiming {
+                    name: node_name,
+                    handle: PassHandle::new(*index),
+                    ..Default::default()
+                };
+                for handle in frame.nodes[*index].get_reads().into_iter().chain(frame.nodes[*index].get_writes()) {
+                    if let Some(size) = untagged_allocations.remove(&handle) {
+                        pass_timing.transient_allocated += size;
+                    }
+                }
+
+                // Prepare and execute resource barriers
+                let barrier_start = Instant::now();
+                let barriers = self.node_barriers.get(index);
+                if let Some(barriers) = barriers {
+                    pass_timing.image_barriers = barriers.image_barriers.len() as u32;
+                    pass_timing.buffer_barriers = barriers.buffer_barriers.len() as u32;
+                    if cfg!(feature = "barrier-analysis") {
+                        pass_timing.redundant_barriers = barrier_analysis::analyze(barriers, &node_name, &mut barrier_history);
+                    }
+                    record_barriers(barriers, &node_name, render_context, command_buffer);
+                }
+                pass_timing.barriers = barrier_start.elapsed();
+
+                let setup_start = Instant::now();
+                self.fill_duration = Duration::ZERO;
+                self.pass_layout_hash = None;
+                self.pass_replayed = false;
+
+                // The renderpass for a group is started by its first node, once all
+                // of the group's barriers have been recorded
+                if active_group.is_none() {
+                    if let Some(group_name) = get_renderpass_group(&frame.nodes[*index]) {
+                        let members: Vec<NodeIndex> = command_list.nodes[position..].iter().take_while(|member| {
+                            get_renderpass_group(&frame.nodes[**member]) == Some(group_name)
+                        }).cloned().collect();
+                        active_group = Some(self.begin_renderpass_group(
+                            &mut frame.nodes,
+                            &members,
+                            render_context,
+                            command_buffer));
+                    }
+                }
+
+                let node = frame.nodes.node_weight_mut(*index).unwrap();
+
+                // prepare pipeline for execution (node's fill callback)
+                {
+                    let node_name = node.get_name();
+                    trace!(target: "framegraph", "Executing node: {node_name}");
+                }
+                match node {
+                    PassType::Graphics(graphics_node) => {
+                        match &mut active_group {
+                            Some(group) => {
+                                self.execute_subpass_node(&mut frame.descriptors, render_context, command_buffer, graphics_node, group);
+                            },
+                            None => {
+                                self.execute_graphics_node(&mut frame.descriptors, render_context, command_buffer, graphics_node);
+                            }
+                        }
+                    },
+                    PassType::Copy(copy_node) => {
+                        self.execute_copy_node(&mut frame.descriptors, render_context, command_buffer, copy_node);
+                    },
+                    PassType::Compute(compute_node) => {
+                        self.execute_compute_node(&mut frame.descriptors, render_context, command_buffer, compute_node);
+                    }
+                    _ => {}
+                }
+
+                if let Some(group) = &active_group {
+                    if group.subpass_index == group.subpass_count {
+                        unsafe {
+                            render_context.get_device().borrow().get().cmd_end_render_pass(*command_buffer);
+                        }
+                        active_group = None;
+                    }
+                }
 
-#[derive(Debug)]
-pub struct VulkanFrameGraph {
-    pipeline_manager: VulkanPipelineManager,
-    renderpass_manager: VulkanRenderpassManager,
-    node_barriers: HashMap<NodeIndex, NodeBarriers>
-}
+                pass_timing.fill = self.fill_duration;
+                pass_timing.pipeline_layout_hash = self.pass_layout_hash;
+                pass_timing.replayed = self.pass_replayed;
+                pass_timing.setup = setup_start.elapsed().saturating_sub(self.fill_duration);
+                if let Some(budget) = self.pass_budget {
+                    if pass_timing.total() > budget {
+                        log::warn!(target: "framegraph",
+                            "Node {} exceeded its CPU budget of {:?}: {:?} (barriers {:?}, setup {:?}, fill {:?})",
+                            pass_timing.name,
+                            budget,
+                            pass_timing.total(),
+                            pass_timing.barriers,
+                            pass_timing.setup,
+                            pass_timing.fill);
+                    }
+                }
+                frame_stats.passes.push(pass_timing);
 
-impl Drop for VulkanFrameGraph {
-    fn drop(&mut self) {
-        println!("Dropping VulkanFrameGraph");
-    }
-}
+                drop(pass_scope);
+                render_context.get_device().borrow_mut().pop_allocation_tag();
+                render_context.get_device().borrow().pop_debug_label(*command_buffer);
+                if capture_label.is_some() {
+                    render_context.get_device().borrow().pop_debug_label(*command_buffer);
+                }
+            }
+            if frame_label.is_some() {
+                render_context.get_device().borrow().pop_debug_label(*command_buffer);
+            }
+        }
 
-impl VulkanFrameGraph {
-    pub fn new(
-        renderpass_manager: VulkanRenderpassManager,
-        pipeline_manager: VulkanPipelineManager) -> VulkanFrameGraph {
+        self.retained_passes.end_frame();
 
-        VulkanFrameGraph {
-            pipeline_manager,
-            renderpass_manager,
-            node_barriers: HashMap::new()
+        // bring images whose parts were left in different layouts back to a single layout
+        if !self.final_barriers.image_barriers.is_empty() {
+            let last_command_buffer = recorded.last().expect("Final barriers without any recorded nodes").command_buffer;
+            record_barriers(&self.final_barriers, "final layout transitions", render_context, &last_command_buffer);
         }
-    }
 
-    #[tracing::instrument]
-    fn compile(&mut self, nodes: &mut StableDiGraph<PassType, u32>, root_index: NodeIndex) -> Vec<NodeIndex>{
-        // create input/output maps to detect graph edges
-        let mut input_map = MultiMap::new();
-        let mut output_map = MultiMap::new();
-        for node_index in nodes.node_indices() {
-            let node = &nodes[node_index];
-            for read in node.get_reads() {
-                input_map.insert(read, node_index);
-            }
-            for write in node.get_writes() {
-                output_map.insert(write, node_index);
-            }
-        }
-
-        // iterate over input map. For each input, find matching outputs and then
-        // generate a graph edge for each pairing
-        for (input, node_index) in input_map.iter() {
-            let find_outputs = output_map.get_vec(input);
-            if let Some(matched_outputs) = find_outputs {
-                // input/output match defines a graph edge
-                for matched_output in matched_outputs {
-                    // use update_edge instead of add_edge to avoid duplicates
-                    // if matched_output.index() != node_index.index() {
-                    if matched_output != node_index {
-                        nodes.update_edge(
-                            *node_index,
-                            *matched_output,
-                            0);
+        // return imported resources to the state their owners expect, and leave exported ones in
+        // the state they were declared to end the frame in
+        if !frame.imports.is_empty() {
+            let mut export_barriers = NodeBarriers {
+                image_barriers: vec![],
+                buffer_barriers: vec![]
+            };
+            for import in &frame.imports {
+                let handle = import.resource.borrow().get_handle();
+                // an exported resource may not have been used yet, leaving its contents undefined
+                let current_state = render_context.get_resource_state(handle)
+                    .unwrap_or(ResourceState {
+                        access: vk::AccessFlags::NONE,
+                        stage: vk::PipelineStageFlags::TOP_OF_PIPE,
+                        layout: Some(vk::ImageLayout::UNDEFINED)
+                    });
+                let mut resource = import.resource.borrow_mut();
+                match resource.resource_type.as_mut().expect("Invalid imported resource") {
+                    ResourceType::Image(image) => {
+                        let final_layout = import.final_state.layout.expect("Imported images require a final layout");
+                        export_barriers.image_barriers.push(ImageBarrier {
+                            resource: import.resource.clone(),
+                            source_stage: current_state.stage,
+                            dest_stage: import.final_state.stage,
+                            source_access: current_state.access,
+                            dest_access: import.final_state.access,
+                            old_layout: current_state.layout.expect("Imported image has no tracked layout"),
+                            new_layout: final_layout,
+                            subresource: SubresourceRange::WHOLE
+                        });
+                        image.layout = final_layout;
+                    },
+                    ResourceType::Buffer(buffer) => {
+                        export_barriers.buffer_barriers.push(BufferBarrier {
+                            resource: import.resource.clone(),
+                            source_stage: current_state.stage,
+                            dest_stage: import.final_state.stage,
+                            source_access: current_state.access,
+                            dest_access: import.final_state.access,
+                            size: buffer.create_info.size as usize,
+                            offset: 0
+                        });
                     }
                 }
+                render_context.update_resource_state(handle, import.final_state);
             }
-        }
 
-        // Use DFS to find all accessible nodes from the root node
-        {
-            let mut retained_nodes: Vec<bool> = Vec::new();
-            retained_nodes.resize(nodes.node_count(), false);
-
-            //let mut dfs = Dfs::new(&nodes, root_index);
-            let mut dfs = Dfs::new(&*nodes, root_index);
-            while let Some(node_id) = dfs.next(&*nodes) {
-                retained_nodes[node_id.index()] = true;
+            if recorded.is_empty() {
+                begin_command_list(&mut recorded, &CommandList::new(), render_context, command_buffer_provider);
             }
+            let last_command_buffer = recorded.last().unwrap().command_buffer;
+            record_barriers(&export_barriers, "imported resource transitions", render_context, &last_command_buffer);
+        }
 
-            nodes.retain_nodes(|_graph, node_index| {
-                retained_nodes[node_index.index()]
-            });
+        frame_stats.pipeline_layouts_created = self.pipeline_manager.get_pipeline_layouts_created() - layouts_created_before;
+
+        // everything tagged since the previous frame ended, i.e. while this frame was built
+        // and recorded
+        let tagged_allocations = render_context.get_device().borrow_mut().take_tagged_allocations();
+        for pass_timing in &mut frame_stats.passes {
+            pass_timing.transient_allocated += tagged_allocations.get(&pass_timing.name).copied().unwrap_or(0);
+        }
+        frame_stats.transient_allocated = tagged_allocations.values().sum::<u64>() + untagged_allocated;
+        frame_stats.transient_watermark = self.last_frame_stats.transient_watermark.max(frame_stats.transient_allocated);
+        frame_stats.node_storage = node_arena::take_stats();
+        if frame_stats.transient_allocated > 0 {
+            trace!(target: "framegraph", "Transient allocations this frame: {} bytes (watermark {} bytes)",
+                frame_stats.transient_allocated,
+                frame_stats.transient_watermark);
         }
+        self.last_frame_stats = frame_stats;
+        self.capture.end_frame();
 
-        // unresolved and unused passes have been removed from the graph,
-        // so now we can use a topological sort to generate an execution order
-        let mut sorted_nodes: Vec<NodeIndex> = Vec::new();
-        {
-            let sort_result = petgraph::algo::toposort(&*nodes, None);
-            match sort_result {
-                Ok(mut sorted_list) => {
-                    // DFS requires we order nodes as input -> output, but for sorting we want output -> input
-                    sorted_list.reverse();
-                    for i in &sorted_list {
-                        log::trace!(target: "framegraph", "Sorted node: {:?}", nodes.node_weight(*i).unwrap().get_name())
-                    }
-                    sorted_nodes = sorted_list;
+        recorded
+    }
+
+    /// Checks every resource used by the frame's nodes resolves as the kind of resource it's
+    /// used as before anything is linked, handling the nodes which don't by the
+    /// ResourceErrorPolicy
+    fn validate_frame_resources(&self, frame: &mut Frame) {
+        let invalid: Vec<(NodeIndex, ResourceError)> = frame.nodes.node_indices()
+            .filter_map(|index| validate_node_resources(&frame.nodes[index]).err().map(|error| (index, error)))
+            .collect();
+        for (index, error) in invalid {
+            match self.resource_error_policy {
+                ResourceErrorPolicy::SkipNode if !frame.get_root_indices().contains(&index) => {
+                    log::error!("{}; skipping the pass", error);
+                    frame.nodes.remove_node(index);
                 },
-                Err(cycle_error) => {
-                    panic!("A cycle was detected in the framegraph: {:?}", cycle_error);
-                }
+                _ => panic!("{}", error)
             }
         }
+    }
 
-        sorted_nodes
+    #[tracing::instrument]
+    fn compile(&mut self, nodes: &mut StableDiGraph<PassType, u32>, root_indices: &[NodeIndex]) -> Vec<NodeIndex>{
+        graph_core::compile(nodes, root_indices)
     }
 
     #[tracing::instrument]
     fn link(
         &mut self,
         nodes: &mut StableDiGraph<PassType, u32>,
-        sorted_nodes: &[NodeIndex]) -> Vec<CommandList> {
-
-        let mut command_lists: Vec<CommandList> = Vec::new();
-        let mut current_list = CommandList::new();
-
-        // All image bindings and attachments require the most recent usage for that resource
-        // in case layout transitions are necessary. Since the graph has already been sorted,
-        // we can just iterate over the sorted nodes to do this
-        let mut usage_cache: HashMap<u64, ResourceUsage> = HashMap::new();
-        for node_index in sorted_nodes {
-            if let Some(node) = nodes.node_weight_mut(*node_index) {
-                let mut node_barrier = NodeBarriers {
-                    image_barriers: vec![],
-                    buffer_barriers: vec![]
-                };
-
-                match node {
-                    PassType::Graphics(gn) => {
-                        link_inputs(gn.get_inputs(), &mut node_barrier, &mut usage_cache);
-                        link_inputs(&gn.outputs, &mut node_barrier, &mut usage_cache);
-
-                        if let Some(dt) = gn.get_depth_mut() {
-                            let handle = dt.resource_image.borrow().get_handle();
-                            let last_usage = usage_cache.get(&handle);
-                            // TODO: handle separate depth and stencil targets
-                            let new_usage = ResourceUsage {
-                                access: vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE |
-                                    vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ,
-                                stage: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
-                                layout: Some(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
-                            };
-                            if let Some(usage) = last_usage {
-                                // The RenderPassManager expects the RT layout to be in the
-                                // post-barrier (i.e. new) layout
-                                dt.layout = new_usage.layout.unwrap();
-
-                                let image_barrier = ImageBarrier {
-                                    resource: dt.resource_image.clone(),
-                                    source_stage: usage.stage,
-                                    dest_stage: new_usage.stage,
-                                    source_access: usage.access,
-                                    dest_access: new_usage.access,
-                                    old_layout: usage.layout.expect("Tried to get image layout from non-image"),
-                                    new_layout: dt.layout
-                                };
-                                node_barrier.image_barriers.push(image_barrier);
-                            }
-                        }
-
-                        for rt in gn.get_rendertargets_mut() {
-                            // rendertargets always write, so if this isn't the first usage of this resource
-                            // then we know we need a barrier
-                            let handle = rt.resource_image.borrow().get_handle();
-                            let last_usage = usage_cache.get(&handle);
-                            let new_usage = ResourceUsage {
-                                access: vk::AccessFlags::COLOR_ATTACHMENT_WRITE | vk::AccessFlags::COLOR_ATTACHMENT_READ,
-                                stage: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
-                                layout: Some(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
-                            };
-                            if let Some(usage) = last_usage {
-                                // The RenderPassManager expects the RT layout to be in the
-                                // post-barrier (i.e. new) layout
-                                rt.layout = new_usage.layout.unwrap();
-
-                                let image_barrier = ImageBarrier {
-                                    resource: rt.resource_image.clone(),
-                                    source_stage: usage.stage,
-                                    dest_stage: new_usage.stage,
-                                    source_access: usage.access,
-                                    dest_access: new_usage.access,
-                                    old_layout: usage.layout.expect("Tried to get image layout from non-image"),
-                                    new_layout: rt.layout
-                                };
-                                node_barrier.image_barriers.push(image_barrier);
-                            }
-
-                            usage_cache.insert(handle, new_usage);
-                        }
-                    }
-                    PassType::Copy(cn) => {
-                        for resource in &cn.copy_sources {
-                            let handle = resource.borrow().get_handle();
-                            let last_usage = {
-                                let usage = usage_cache.get(&handle);
-                                match usage {
-                                    Some(found_usage) => {found_usage.clone()},
-                                    _ => {
-                                        ResourceUsage {
-                                            access: vk::AccessFlags::NONE,
-                                            stage: vk::PipelineStageFlags::ALL_COMMANDS,
-                                            layout: Some(vk::ImageLayout::UNDEFINED)
-                                        }
-                                    }
-                                }
-                            };
-
-                            let new_usage = ResourceUsage{
-                                access: vk::AccessFlags::TRANSFER_READ,
-                                stage: vk::PipelineStageFlags::TRANSFER,
-                                layout: Some(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
-                            };
-
-                            // for copy sources and destinations, a barrier is always required
-                            let image_barrier = ImageBarrier {
-                                resource: resource.clone(),
-                                source_stage: last_usage.stage,
-                                dest_stage: new_usage.stage,
-                                source_access: last_usage.access,
-                                dest_access: new_usage.access,
-                                old_layout: last_usage.layout.expect("Using a non-image for an image transition"),
-                                new_layout: new_usage.layout.unwrap()
-                            };
-                            node_barrier.image_barriers.push(image_barrier);
-                        }
-
-                        for resource in &cn.copy_dests {
-                            let handle = resource.borrow().get_handle();
-                            let last_usage = {
-                                let usage = usage_cache.get(&handle);
-                                match usage {
-                                    Some(found_usage) => {found_usage.clone()},
-                                    _ => {
-                                        ResourceUsage {
-                                            access: vk::AccessFlags::NONE,
-                                            stage: vk::PipelineStageFlags::TOP_OF_PIPE,
-                                            layout: Some(vk::ImageLayout::UNDEFINED)
-                                        }
-                                    }
-                                }
-                            };
-
-                            let new_usage = ResourceUsage{
-                                access: vk::AccessFlags::TRANSFER_WRITE,
-                                stage: vk::PipelineStageFlags::TRANSFER,
-                                layout: Some(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
-                            };
-
-                            // for copy sources and destinations, a barrier is always required
-                            let image_barrier = ImageBarrier {
-                                resource: resource.clone(),
-                                source_stage: last_usage.stage,
-                                dest_stage: new_usage.stage,
-                                source_access: last_usage.access,
-                                dest_access: new_usage.access,
-                                old_layout: last_usage.layout.expect("Using a non-image for an image transition"),
-                                new_layout: new_usage.layout.unwrap()
-                            };
-                            node_barrier.image_barriers.push(image_barrier);
-                        }
-
-                    },
-                    PassType::Compute(cn) => {
-                        link_inputs(&cn.inputs, &mut node_barrier, &mut usage_cache);
-                        link_inputs(&cn.outputs, &mut node_barrier, &mut usage_cache);
-                    }
-                    PassType::Present(pn) => {
-                        // link_inputs(gn.get_inputs(), &mut node_barrier, &mut usage_cache);
-                        let mut swapchain = pn.swapchain_image.borrow_mut();
-                        let handle = swapchain.get_handle();
-                        let mut swapchain_image = swapchain.get_image_mut();
-                        let last_usage = {
-                            let usage = usage_cache.get(&handle);
-                            match usage {
-                                Some(found_usage) => {found_usage.clone()},
-                                _ => {
-                                    ResourceUsage {
-                                        access: vk::AccessFlags::NONE,
-                                        stage: vk::PipelineStageFlags::TOP_OF_PIPE,
-                                        layout: Some(swapchain_image.layout)
-                                    }
-                                }
-                            }
-                        };
-
-                        let new_usage = ResourceUsage {
-                            access: vk::AccessFlags::NONE,
-                            stage: vk::PipelineStageFlags::BOTTOM_OF_PIPE,
-                            layout: Some(vk::ImageLayout::PRESENT_SRC_KHR),
-                        };
-
-                        let present_barrier = ImageBarrier {
-                            resource: pn.swapchain_image.clone(),
-                            source_stage: last_usage.stage,
-                            dest_stage: new_usage.stage,
-                            source_access: last_usage.access,
-                            dest_access: new_usage.access,
-                            old_layout: last_usage.layout.expect("Using a non-image for an image transition"),
-                            new_layout: new_usage.layout.unwrap()
-                        };
-                        node_barrier.image_barriers.push(present_barrier);
-
-                        swapchain_image.layout = new_usage.layout.unwrap();
-                        usage_cache.insert(handle, new_usage);
-
-                        command_lists.push(current_list);
-                        current_list = CommandList::new();
-                        current_list.wait = Some(QueueWait{
-                            wait_stage_mask: vk::PipelineStageFlags::NONE,
-                        });
-                    }
-                }
-
-                current_list.nodes.push(*node_index);
+        sorted_nodes: &[NodeIndex],
+        render_context: &VulkanRenderContext) -> Vec<CommandList> {
+
+        let linked_graph = graph_core::link(&*nodes, sorted_nodes, render_context);
+        for (node_index, node_link) in &linked_graph.nodes {
+            let mut node_barrier = apply_node_link(&mut nodes[*node_index], node_link);
+            if node_link.barrier_node != *node_index {
+                let leader_barriers = self.node_barriers.get_mut(&node_link.barrier_node)
+                    .expect("Renderpass group leader was not linked before its subpasses");
+                leader_barriers.image_barriers.append(&mut node_barrier.image_barriers);
+                leader_barriers.buffer_barriers.append(&mut node_barrier.buffer_barriers);
+            }
+            self.node_barriers.insert(*node_index, node_barrier);
+        }
 
-                self.node_barriers.insert(*node_index, node_barrier);
+        let mut final_barriers = NodeBarriers::default();
+        for (node_index, access_index, transition) in &linked_graph.final_transitions {
+            let (_, target) = get_node_accesses(&nodes[*node_index]).swap_remove(*access_index);
+            if let BarrierRange::Image { old_layout, new_layout, subresource } = transition.range {
+                final_barriers.image_barriers.push(ImageBarrier {
+                    resource: target.get_resource().clone(),
+                    source_stage: transition.source_stage,
+                    dest_stage: transition.dest_stage,
+                    source_access: transition.source_access,
+                    dest_access: transition.dest_access,
+                    old_layout,
+                    new_layout,
+                    subresource
+                });
+                // every part of the image is in the same layout again once these are recorded
+                target.get_resource().borrow_mut().get_image_mut().layout = new_layout;
             }
         }
+        self.final_barriers = final_barriers;
 
-        command_lists.push(current_list);
-        command_lists
+        linked_graph.command_lists
     }
 
     #[tracing::instrument]
     fn execute_copy_node(
         &mut self,
-        descriptor_sets: &mut Vec<vk::DescriptorSet>,
-        descriptor_pool: vk::DescriptorPool,
+        frame_descriptors: &mut FrameDescriptors,
         render_context: &mut VulkanRenderContext,
         command_buffer: &vk::CommandBuffer,
         node: &mut CopyPassNode) {
 
         // Copy node is ez-pz
+        let fill_start = Instant::now();
         node.execute(
             render_context,
             command_buffer);
+        self.fill_duration += fill_start.elapsed();
     }
 
     #[tracing::instrument]
     fn execute_compute_node(
         &mut self,
-        descriptor_sets: &mut Vec<vk::DescriptorSet>,
-        descriptor_pool: vk::DescriptorPool,
+        frame_descriptors: &mut FrameDescriptors,
         render_context: &mut VulkanRenderContext,
         command_buffer: &vk::CommandBuffer,
         node: &mut ComputePassNode) {
@@ -606,6 +1780,7 @@ impl VulkanFrameGraph {
         let pipeline = self.pipeline_manager.create_compute_pipeline(
             render_context,
             &node.pipeline_description);
+        self.pass_layout_hash = Some(pipeline.borrow().get_layout_hash());
 
         // bind pipeline
         unsafe {
@@ -615,54 +1790,49 @@ impl VulkanFrameGraph {
                 pipeline.borrow().get_pipeline());
         }
 
-        // prepare and perform descriptor writes
-        {
-            let mut descriptor_updates = DescriptorUpdate::new();
-
-            // get input and output handles for this pass
-            // let inputs = node.get_inputs();
-            let inputs = &node.inputs;
-            let outputs = &node.outputs;
-
-            resolve_descriptors(
-                inputs,
-                pipeline.borrow().deref(),
-                &[],
-                &mut descriptor_updates);
-            resolve_descriptors(
-                outputs,
-                pipeline.borrow().deref(),
-                &[],
-                &mut descriptor_updates);
-
-            unsafe {
-                // TODO: support descriptor copies?
-                render_context.get_device().borrow().get().update_descriptor_sets(
-                    &descriptor_updates.descriptor_writes,
-                    &[]);
-                // bind descriptorsets
-                // TODO: COMPUTE SUPPORT
-                render_context.get_device().borrow().get().cmd_bind_descriptor_sets(
-                    *command_buffer,
-                    vk::PipelineBindPoint::COMPUTE,
-                    pipeline.borrow().get_pipeline_layout(),
-                    0,
-                    &vec![],
-                    &[]);
-            }
-        };
+        bind_pass_descriptors(
+            render_context,
+            *command_buffer,
+            vk::PipelineBindPoint::COMPUTE,
+            pipeline.borrow().deref(),
+            &[node.inputs.as_slice(), node.outputs.as_slice()],
+            &[],
+            node.get_name(),
+            frame_descriptors);
 
         // execute node
+        let fill_start = Instant::now();
         node.execute(
             render_context,
             command_buffer);
+        self.fill_duration += fill_start.elapsed();
+
+        let dispatch = match &node.dispatch {
+            Some(ComputeDispatch::Extent(extent)) => {
+                let workgroup_size = pipeline.borrow().get_workgroup_size()
+                    .expect("Dispatching for an extent requires a shader with a reflected local size");
+                Some(ComputeDispatch::covering(*extent, workgroup_size))
+            },
+            dispatch => dispatch.clone()
+        };
+        match &dispatch {
+            Some(ComputeDispatch::Groups { x, y, z }) => unsafe {
+                render_context.get_device().borrow().get().cmd_dispatch(*command_buffer, *x, *y, *z);
+            },
+            Some(ComputeDispatch::Indirect { buffer, offset }) => unsafe {
+                render_context.get_device().borrow().get().cmd_dispatch_indirect(
+                    *command_buffer,
+                    get_vk_buffer(buffer),
+                    *offset);
+            },
+            Some(ComputeDispatch::Extent(_)) | None => {}
+        }
     }
 
     #[tracing::instrument]
     fn execute_graphics_node(
         &mut self,
-        descriptor_sets: &mut Vec<vk::DescriptorSet>,
-        descriptor_pool: vk::DescriptorPool,
+        frame_descriptors: &mut FrameDescriptors,
         render_context: &mut VulkanRenderContext,
         command_buffer: &vk::CommandBuffer,
         node: &mut GraphicsPassNode) {
@@ -672,43 +1842,39 @@ impl VulkanFrameGraph {
             // resolve render targets for this node
             let resolved_render_targets = {
                 let render_targets = &node.render_targets;
-                resolve_render_targets(render_targets)
+                resolve_render_targets(render_targets, node.get_name())
             };
 
             let resolved_depth_target = {
                 if let Some(depth_target) = &node.depth_target {
                     // Some(resolve_render_targets(std::slice::from_ref(depth_target))[0].clone())
-                    resolve_render_targets(std::slice::from_ref(depth_target)).pop()
+                    resolve_render_targets(std::slice::from_ref(depth_target), node.get_name()).pop()
                 } else {
                     None
                 }
             };
 
-            // Ensure all rendertargets are the same dimensions
-            let framebuffer_extent = {
-                let mut extent: Option<vk::Extent3D> = None;
-                for rt in &resolved_render_targets {
-                    match extent {
-                        Some(extent) => {
-                            assert_eq!(extent, rt.extent, "All framebuffer attachments must be the same dimensions");
-                        },
-                        None => {
-                            extent = Some(rt.extent.clone());
-                        }
-                    }
-                }
-                extent.expect("Framebuffer required for renderpass")
+            let framebuffer_extent = get_framebuffer_extent(&resolved_render_targets);
+            let framebuffer_layers = {
+                let attachments: Vec<ImageWrapper> = resolved_render_targets.iter().chain(&resolved_depth_target).cloned().collect();
+                let device_multiview = render_context.get_device().borrow().is_feature_enabled(NegotiatedFeature::Multiview);
+                get_framebuffer_layers(&attachments, node.multiview, device_multiview)
             };
 
             let renderpass = self.renderpass_manager.create_or_fetch_renderpass(
                 node.get_name(),
                 &node.render_targets,
                 &node.depth_target,
+                node.multiview,
                 render_context.get_device());
 
-            let pipeline = self.pipeline_manager.create_pipeline(render_context, renderpass.borrow().renderpass.clone(), pipeline_description);
-
-            let mut new_descriptor_sets = render_context.create_descriptor_sets(&pipeline.borrow().device_pipeline.descriptor_set_layouts, descriptor_pool);
+            let pipeline = self.pipeline_manager.create_pipeline(
+                render_context,
+                renderpass.borrow().renderpass.clone(),
+                0,
+                std::slice::from_ref(&node.get_subpass_targets()),
+                pipeline_description);
+            self.pass_layout_hash = Some(pipeline.borrow().get_layout_hash());
 
             // create framebuffer
             // TODO: should cache framebuffer objects to avoid creating the same ones each frame
@@ -716,58 +1882,52 @@ impl VulkanFrameGraph {
                 let framebuffer = render_context.create_framebuffer(
                     renderpass.borrow().renderpass.clone(),
                     &framebuffer_extent,
+                    framebuffer_layers,
                     &resolved_render_targets,
-                    &resolved_depth_target);
+                    &resolved_depth_target,
+                    node.get_name());
                 // Framebuffer needs to be owned by the GraphicsPassNode to ensure it's
                 // destroyed after this frame has rendered
                 node.framebuffer = Some(framebuffer);
                 node.get_framebuffer()
             };
 
-            // TODO: parameterize this per framebuffer attachment
-            let clear_value = vk::ClearValue {
-                color: vk::ClearColorValue {
-                    float32: [0.1, 0.1, 0.1, 1.0]
-                }
-            };
-
-            // prepare and perform descriptor writes
-            {
-                let mut descriptor_updates = DescriptorUpdate::new();
-
-                // get input and output handles for this pass
-                // let inputs = node.get_inputs();
-                let inputs = &node.inputs;
-                let outputs = node.get_outputs();
-
-                resolve_descriptors(
-                    inputs,
-                    pipeline.borrow().deref(),
-                    &new_descriptor_sets,
-                    &mut descriptor_updates);
-                resolve_descriptors(
-                    outputs,
-                    pipeline.borrow().deref(),
-                    &new_descriptor_sets,
-                    &mut descriptor_updates);
-
-                unsafe {
-                    enter_span!(tracing::Level::TRACE, "Update and bind descriptor sets");
-                    // TODO: support descriptor copies?
-                    render_context.get_device().borrow().get().update_descriptor_sets(
-                        &descriptor_updates.descriptor_writes,
-                        &[]);
-                    // bind descriptorsets
-                    // TODO: COMPUTE SUPPORT
-                    render_context.get_device().borrow().get().cmd_bind_descriptor_sets(
+            // one clear value per framebuffer attachment, depth first
+            let clear_values: Vec<vk::ClearValue> = node.depth_target.iter().chain(&node.render_targets).map(|attachment| {
+                attachment.get_clear_value()
+            }).collect();
+
+            // retained nodes bind their own descriptors inside their recording
+            let retained_buffer = match node.retained {
+                Some(version) if !render_context.uses_descriptor_buffers() && is_retainable(node) => {
+                    let key = retained_key(node, version, renderpass.borrow().renderpass, pipeline.borrow().get_pipeline());
+                    match self.retained_passes.get(node.get_interned_name(), key).map(RetainedRecording::get_command_buffer) {
+                        Some(retained_buffer) => {
+                            trace!(target: "framegraph", "Replaying retained node {}", node.get_name());
+                            self.pass_replayed = true;
+                            Some(retained_buffer)
+                        },
+                        None => Some(self.record_retained_node(
+                            render_context,
+                            node,
+                            key,
+                            renderpass.borrow().renderpass,
+                            pipeline.borrow().deref()))
+                    }
+                },
+                _ => {
+                    bind_pass_descriptors(
+                        render_context,
                         *command_buffer,
                         vk::PipelineBindPoint::GRAPHICS,
-                        pipeline.borrow().get_pipeline_layout(),
-                        0,
-                        &new_descriptor_sets,
-                        &[]);
+                        pipeline.borrow().deref(),
+                        &[node.inputs.as_slice(), node.get_outputs()],
+                        &[],
+                        node.get_name(),
+                        frame_descriptors);
+                    None
                 }
-            }
+            };
 
             // begin render pass and bind pipeline
             {
@@ -780,14 +1940,27 @@ impl VulkanFrameGraph {
                             width: framebuffer_extent.width,
                             height: framebuffer_extent.height})
                         .build())
-                    .clear_values(std::slice::from_ref(&clear_value));
+                    .clear_values(&clear_values);
+
+                let subpass_contents = match retained_buffer {
+                    Some(_) => vk::SubpassContents::SECONDARY_COMMAND_BUFFERS,
+                    None => vk::SubpassContents::INLINE
+                };
 
                 unsafe {
                     enter_span!(tracing::Level::TRACE, "Begin renderpass & bind pipeline");
                     render_context.get_device().borrow().get().cmd_begin_render_pass(
                         *command_buffer,
                         &render_pass_begin,
-                        vk::SubpassContents::INLINE);
+                        subpass_contents);
+
+                    if let Some(retained_buffer) = retained_buffer {
+                        render_context.get_device().borrow().get().cmd_execute_commands(
+                            *command_buffer,
+                            std::slice::from_ref(&retained_buffer));
+                        render_context.get_device().borrow().get().cmd_end_render_pass(*command_buffer);
+                        return;
+                    }
 
                     // TODO: add compute support
                     render_context.get_device().borrow().get().cmd_bind_pipeline(
@@ -796,39 +1969,254 @@ impl VulkanFrameGraph {
                         pipeline.borrow().get_pipeline());
                 }
             }
-
-            descriptor_sets.append(&mut new_descriptor_sets);
         }
 
-        if let Some(viewport) = &node.viewport {
+        set_dynamic_state(node, render_context, command_buffer);
+        bind_geometry_buffers(node, render_context, command_buffer);
+
+        // execute this node
+        let fill_start = Instant::now();
+        node.execute(
+            render_context,
+            command_buffer);
+        self.fill_duration += fill_start.elapsed();
+
+        // if we began a render pass and bound a pipeline for this node, end it
+        if active_pipeline.is_some() {
             unsafe {
-                render_context.get_device().borrow().get().cmd_set_viewport(
-                    *command_buffer,
-                    0,
-                    std::slice::from_ref(viewport));
+                render_context.get_device().borrow().get().cmd_end_render_pass(*command_buffer);
             }
         }
+    }
+
+    /// Records a retained node into a new secondary command buffer, replacing any earlier
+    /// recording of it, and returns the command buffer
+    fn record_retained_node(
+        &mut self,
+        render_context: &mut VulkanRenderContext,
+        node: &GraphicsPassNode,
+        key: u64,
+        renderpass: vk::RenderPass,
+        pipeline: &Pipeline) -> vk::CommandBuffer {
+        enter_span!(tracing::Level::TRACE, "Record retained node");
+        trace!(target: "framegraph", "Recording retained node {}", node.get_name());
+
+        let layouts = get_pass_set_layouts(pipeline);
+        let per_frame_set = BindingFrequency::PerFrame.get_set() as usize;
+        assert!(layouts.get(per_frame_set).map_or(true, |layout| *layout == vk::DescriptorSetLayout::null()),
+            "Retained node {} can't read the per-frame set, which is rewritten every frame", node.get_name());
+        let recording = RetainedRecording::new(
+            render_context.get_device(),
+            render_context.get_graphics_queue_index(),
+            layouts.len() as u32,
+            node.get_name());
+        let command_buffer = recording.get_command_buffer();
+
+        // the framebuffer is created anew each frame, so the recording can't name it
+        let inheritance_info = vk::CommandBufferInheritanceInfo::builder()
+            .render_pass(renderpass)
+            .subpass(0)
+            .build();
+        // frames in flight may execute the recording at the same time
+        let begin_info = vk::CommandBufferBeginInfo::builder()
+            .flags(vk::CommandBufferUsageFlags::RENDER_PASS_CONTINUE | vk::CommandBufferUsageFlags::SIMULTANEOUS_USE)
+            .inheritance_info(&inheritance_info)
+            .build();
+        unsafe {
+            let device = render_context.get_device();
+            let device = device.borrow();
+            device.get().begin_command_buffer(command_buffer, &begin_info)
+                .expect("Failed to begin recording retained node");
+            device.get().cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                pipeline.get_pipeline());
+        }
+
+        // secondary command buffers don't inherit any bound state from the primary
+        let bindings = [node.inputs.as_slice(), node.get_outputs()];
+        validate_binding_slots(pipeline, &bindings, node.get_name());
+        if cfg!(debug_assertions) {
+            validate_uniform_layouts(pipeline, &bindings, node.get_name());
+        }
+        let descriptor_sets = allocate_sets(&layouts, |layouts| recording.allocate_descriptor_sets(layouts, node.get_name()));
+        write_descriptor_sets(
+            render_context,
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            pipeline,
+            &bindings,
+            &[],
+            &descriptor_sets,
+            node.get_name());
+        set_dynamic_state(node, render_context, &command_buffer);
+        bind_geometry_buffers(node, render_context, &command_buffer);
+
+        // the recording outlives this frame's query pool, so the node's own GPU spans are left
+        // out of it. The pass's span in the primary command buffer times each execution
+        let fill_start = Instant::now();
+        {
+            let _suspended_spans = profiling::suspend_gpu_spans();
+            node.execute(
+                render_context,
+                &command_buffer);
+        }
+        self.fill_duration += fill_start.elapsed();
+
+        end_command_list(render_context, command_buffer);
+        self.retained_passes.insert(node.get_interned_name(), key, recording);
+        command_buffer
+    }
+
+    #[tracing::instrument]
+    fn begin_renderpass_group(
+        &mut self,
+        nodes: &mut StableDiGraph<PassType, u32>,
+        members: &[NodeIndex],
+        render_context: &mut VulkanRenderContext,
+        command_buffer: &vk::CommandBuffer) -> ActiveRenderpassGroup {
+
+        let (renderpass, attachments, clear_values, multiview, subpass_targets) = {
+            let graphics_nodes: Vec<&GraphicsPassNode> = members.iter().map(|index| {
+                match nodes.node_weight(*index) {
+                    Some(PassType::Graphics(gn)) => gn,
+                    _ => panic!("Renderpass groups may only contain graphics nodes")
+                }
+            }).collect();
+            let subpasses: Vec<SubpassAttachments> = graphics_nodes.iter().map(|gn| {
+                SubpassAttachments {
+                    color_attachments: &gn.render_targets,
+                    depth_attachment: &gn.depth_target,
+                    input_attachments: &gn.input_attachments,
+                    multiview: gn.multiview
+                }
+            }).collect();
+
+            let (renderpass, attachments, clear_values) = self.renderpass_manager.create_or_fetch_subpass_renderpass(
+                graphics_nodes[0].get_renderpass_group().unwrap(),
+                &subpasses,
+                render_context.get_device());
+            // every subpass needs as many layers as the one with the most views
+            let multiview = graphics_nodes.iter().filter_map(|gn| gn.multiview).max_by_key(|multiview| multiview.get_layer_count());
+            let subpass_targets = graphics_nodes.iter().map(|gn| gn.get_subpass_targets()).collect();
+            (renderpass, attachments, clear_values, multiview, subpass_targets)
+        };
+
+        let resolved_attachments: Vec<ImageWrapper> = attachments.iter().map(|attachment| {
+            attachment.borrow().get_image().clone()
+        }).collect();
+        let framebuffer_extent = get_framebuffer_extent(&resolved_attachments);
+        let device_multiview = render_context.get_device().borrow().is_feature_enabled(NegotiatedFeature::Multiview);
+        let framebuffer_layers = get_framebuffer_layers(&resolved_attachments, multiview, device_multiview);
+
+        // attachments are already in framebuffer order, including any depth attachments
+        let group_name = match nodes.node_weight(members[0]) {
+            Some(PassType::Graphics(leader)) => leader.get_renderpass_group().unwrap().to_string(),
+            _ => panic!("Renderpass groups may only contain graphics nodes")
+        };
+        let framebuffer = render_context.create_framebuffer(
+            renderpass.borrow().renderpass.clone(),
+            &framebuffer_extent,
+            framebuffer_layers,
+            &resolved_attachments,
+            &None,
+            &group_name);
+        let framebuffer_handle = framebuffer.get_framebuffer();
+        // The first node of the group owns the framebuffer to ensure it's
+        // destroyed after this frame has rendered
+        if let Some(PassType::Graphics(leader)) = nodes.node_weight_mut(members[0]) {
+            leader.framebuffer = Some(framebuffer);
+        }
+
+        let render_pass_begin = vk::RenderPassBeginInfo::builder()
+            .render_pass(renderpass.borrow().renderpass.clone())
+            .framebuffer(framebuffer_handle)
+            .render_area(vk::Rect2D::builder()
+                .offset(vk::Offset2D{x: 0, y: 0})
+                .extent(vk::Extent2D{
+                    width: framebuffer_extent.width,
+                    height: framebuffer_extent.height})
+                .build())
+            .clear_values(&clear_values);
+
+        unsafe {
+            enter_span!(tracing::Level::TRACE, "Begin renderpass group");
+            render_context.get_device().borrow().get().cmd_begin_render_pass(
+                *command_buffer,
+                &render_pass_begin,
+                vk::SubpassContents::INLINE);
+        }
+
+        ActiveRenderpassGroup {
+            renderpass,
+            subpass_targets,
+            subpass_index: 0,
+            subpass_count: members.len() as u32
+        }
+    }
+
+    #[tracing::instrument(skip(group))]
+    fn execute_subpass_node(
+        &mut self,
+        frame_descriptors: &mut FrameDescriptors,
+        render_context: &mut VulkanRenderContext,
+        command_buffer: &vk::CommandBuffer,
+        node: &mut GraphicsPassNode,
+        group: &mut ActiveRenderpassGroup) {
+
+        // PassNodeBuilder::build rejects these, but the field can still be set afterwards
+        if node.retained.is_some() {
+            log::warn!(target: "framegraph",
+                "Node {} is in a renderpass group, so it's recorded every frame instead of retained", node.get_name());
+        }
 
-        if let Some(scissor) = &node.scissor {
+        if group.subpass_index > 0 {
             unsafe {
-                render_context.get_device().borrow().get().cmd_set_scissor(
+                render_context.get_device().borrow().get().cmd_next_subpass(
                     *command_buffer,
-                    0,
-                    std::slice::from_ref(scissor));
+                    vk::SubpassContents::INLINE);
             }
         }
 
+        let pipeline_description = node.pipeline_description.as_ref()
+            .expect("Nodes in a renderpass group require a pipeline description");
+        let pipeline = self.pipeline_manager.create_pipeline(
+            render_context,
+            group.renderpass.borrow().renderpass.clone(),
+            group.subpass_index,
+            &group.subpass_targets,
+            pipeline_description);
+        self.pass_layout_hash = Some(pipeline.borrow().get_layout_hash());
+
+        unsafe {
+            enter_span!(tracing::Level::TRACE, "Bind pipeline");
+            render_context.get_device().borrow().get().cmd_bind_pipeline(
+                *command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                pipeline.borrow().get_pipeline());
+        }
+
+        bind_pass_descriptors(
+            render_context,
+            *command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            pipeline.borrow().deref(),
+            &[node.inputs.as_slice(), node.get_outputs()],
+            node.get_input_attachments(),
+            node.get_name(),
+            frame_descriptors);
+
+        set_dynamic_state(node, render_context, command_buffer);
+        bind_geometry_buffers(node, render_context, command_buffer);
+
         // execute this node
+        let fill_start = Instant::now();
         node.execute(
             render_context,
             command_buffer);
+        self.fill_duration += fill_start.elapsed();
 
-        // if we began a render pass and bound a pipeline for this node, end it
-        if active_pipeline.is_some() {
-            unsafe {
-                render_context.get_device().borrow().get().cmd_end_render_pass(*command_buffer);
-            }
-        }
+        group.subpass_index += 1;
     }
 
 }
@@ -840,13 +2228,14 @@ impl FrameGraph for VulkanFrameGraph {
     type CB = vk::CommandBuffer;
     type RC = VulkanRenderContext;
     type Index = NodeIndex;
+    type Submit = GraphicsSubmit;
 
     #[tracing::instrument]
-    fn start(
-        &mut self,
-        device: Rc<RefCell<DeviceWrapper>>,
-        descriptor_pool: vk::DescriptorPool) -> Box<Frame> {
-        Box::new(Frame::new(device, descriptor_pool))
+    fn start(&mut self) -> Box<Frame> {
+        let mut frame = Box::new(Frame::new());
+        frame.serial = self.frames_started;
+        self.frames_started += 1;
+        frame
     }
 
     #[tracing::instrument]
@@ -854,150 +2243,49 @@ impl FrameGraph for VulkanFrameGraph {
         &mut self,
         frame: &mut Frame,
         render_context: &mut Self::RC,
-        command_buffer: &Self::CB) {
-
-        frame.end();
-
-        let root_index = frame.get_root_index();
+        command_buffer: &Self::CB) -> Vec<Self::Submit> {
 
-        // compile and link frame
-        let command_lists = {
-            let sorted_nodes = self.compile(&mut frame.nodes, root_index);
-            self.link(&mut frame.nodes, &sorted_nodes)
-        };
-
-        // add a global memory barrier to ensure all CPU writes are accessible
-        // prior to dispatching GPU work
-        {
-            let host_barrier = vk::MemoryBarrier::builder()
-                .src_access_mask(vk::AccessFlags::HOST_WRITE)
-                .dst_access_mask(vk::AccessFlags::UNIFORM_READ
-                    | vk::AccessFlags::INDEX_READ
-                    | vk::AccessFlags::VERTEX_ATTRIBUTE_READ)
+        let first_command_buffer = *command_buffer;
+        let recorded = self.record(frame, render_context, &mut |render_context, index| {
+            if index == 0 {
+                return first_command_buffer;
+            }
+            let command_buffer = render_context.acquire_command_buffer();
+            let begin_info = vk::CommandBufferBeginInfo::builder()
+                .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT)
                 .build();
-
             unsafe {
-                render_context.get_device().borrow().get().cmd_pipeline_barrier(
-                    *command_buffer,
-                    vk::PipelineStageFlags::HOST,
-                    vk::PipelineStageFlags::VERTEX_INPUT | vk::PipelineStageFlags::VERTEX_SHADER,
-                    vk::DependencyFlags::empty(),
-                    &[host_barrier],
-                    &[],
-                    &[]);
+                render_context.get_device().borrow().get().begin_command_buffer(command_buffer, &begin_info)
+                    .expect("Failed to begin recording command list");
             }
-        }
-
-        // excute nodes
-        // let sorted_nodes = &frame.sorted_nodes;
-        for command_list in command_lists {
-            enter_span!(tracing::Level::TRACE, "Filling command lists");
-            for index in &command_list.nodes {
-                enter_span!(tracing::Level::TRACE, "Node", "{}", index.index());
-                let nodes = &mut frame.nodes;
-                let node = nodes.node_weight_mut(*index).unwrap();
-                render_context.get_device().borrow().push_debug_label(*command_buffer, node.get_name());
-
-                // Prepare and execute resource barriers
-                let barriers = self.node_barriers.get(index);
-                if let Some(barriers) = barriers {
-                    enter_span!(tracing::Level::TRACE, "Generate barriers");
-                    // Create the source and dest stage masks
-                    let mut source_stage = vk::PipelineStageFlags::NONE;
-                    let mut dest_stage = vk::PipelineStageFlags::NONE;
-                    for image_barrier in &barriers.image_barriers {
-                        source_stage |= image_barrier.source_stage;
-                        dest_stage |= image_barrier.dest_stage;
-                    }
-                    for buffer_barrier in &barriers.buffer_barriers {
-                        source_stage |= buffer_barrier.source_stage;
-                        dest_stage |= buffer_barrier.dest_stage;
-                    }
-
-                    // translate from our BufferBarrier to Vulkan
-                    let transformed_buffer_barriers: Vec<vk::BufferMemoryBarrier> = barriers.buffer_barriers.iter().map(|bb| {
-                        let buffer = bb.resource.borrow();
-                        let resolved = buffer.resource_type.as_ref().expect("Invalid buffer in BufferBarrier");
-                        if let ResourceType::Buffer(resolved_buffer) = resolved {
-                            vk::BufferMemoryBarrier::builder()
-                                .buffer(resolved_buffer.buffer)
-                                .src_access_mask(bb.source_access)
-                                .dst_access_mask(bb.dest_access)
-                                .offset(bb.offset as DeviceSize)
-                                .size(bb.size as DeviceSize)
-                                .src_queue_family_index(render_context.get_graphics_queue_index())
-                                .dst_queue_family_index(render_context.get_graphics_queue_index())
-                                .build()
-                        } else {
-                            panic!("Non buffer resource in BufferBarrier")
-                        }
-                    }).collect();
-
-                    // translate from our ImageBarrier to Vulkan
-                    let transformed_image_barriers: Vec<vk::ImageMemoryBarrier> = barriers.image_barriers.iter().map(|ib| {
-                        let image = ib.resource.borrow();
-                        let resolved = image.resource_type.as_ref().expect("Invalid image in ImageBarrier");
-                        if let ResourceType::Image(resolved_image) = resolved {
-                            let aspect_mask = util::image::get_aspect_mask_from_format(
-                                resolved_image.format);
-                            // TODO: the range needs to be parameterized
-                            let range = vk::ImageSubresourceRange::builder()
-                                .level_count(1)
-                                .base_mip_level(0)
-                                .layer_count(1)
-                                .base_array_layer(0)
-                                .aspect_mask(aspect_mask)
-                                .build();
-                            vk::ImageMemoryBarrier::builder()
-                                .image(resolved_image.image)
-                                .src_access_mask(ib.source_access)
-                                .dst_access_mask(ib.dest_access)
-                                .old_layout(ib.old_layout)
-                                .new_layout(ib.new_layout)
-                                .src_queue_family_index(render_context.get_graphics_queue_index())
-                                .dst_queue_family_index(render_context.get_graphics_queue_index())
-                                .subresource_range(range)
-                                .build()
-                        } else {
-                            panic!("Non image resource in ImageBarrier")
-                        }
-                    }).collect();
-
-                    if transformed_image_barriers.len() > 0 || transformed_buffer_barriers.len() > 0 {
-                        unsafe {
-                            render_context.get_device().borrow().get().cmd_pipeline_barrier(
-                                *command_buffer,
-                                source_stage,
-                                dest_stage,
-                                vk::DependencyFlags::empty(),
-                                &[],
-                                &transformed_buffer_barriers,
-                                &transformed_image_barriers);
-                        }
-                    }
-                }
-
-                // prepare pipeline for execution (node's fill callback)
-                {
-                    let node_name = node.get_name();
-                    trace!(target: "framegraph", "Executing node: {node_name}");
-                }
-                match node {
-                    PassType::Graphics(graphics_node) => {
-                        self.execute_graphics_node(&mut frame.descriptor_sets, frame.descriptor_pool, render_context, command_buffer, graphics_node);
-                    },
-                    PassType::Copy(copy_node) => {
-                        self.execute_copy_node(&mut frame.descriptor_sets, frame.descriptor_pool, render_context, command_buffer, copy_node);
-                    },
-                    PassType::Compute(compute_node) => {
-                        self.execute_compute_node(&mut frame.descriptor_sets,frame.descriptor_pool, render_context, command_buffer, compute_node);
-                    }
-                    _ => {}
-                }
-
-                render_context.get_device().borrow().pop_debug_label(*command_buffer);
+            command_buffer
+        });
+
+        let mut submits: Vec<GraphicsSubmit> = recorded.iter().map(|command_list| {
+            GraphicsSubmit {
+                command_buffer: command_list.command_buffer,
+                wait_stage_mask: command_list.wait_stage_mask,
+                signal_semaphore: vk::Semaphore::null()
             }
+        }).collect();
+
+        // the caller ends the command buffer it passed in
+        for submit in submits.iter().skip(1) {
+            end_command_list(render_context, submit.command_buffer);
+        }
+        // each command buffer signals the next
+        let chained_count = submits.len().saturating_sub(1);
+        for submit in submits.iter_mut().take(chained_count) {
+            submit.signal_semaphore = render_context.acquire_frame_semaphore();
+        }
+        if submits.is_empty() {
+            submits.push(GraphicsSubmit {
+                command_buffer: first_command_buffer,
+                wait_stage_mask: vk::PipelineStageFlags::ALL_COMMANDS,
+                signal_semaphore: vk::Semaphore::null()
+            });
         }
 
+        submits
     }
 }
\ No newline at end of file
@@ -0,0 +1,186 @@
+use std::collections::HashMap;
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::meta::ParseNestedMeta;
+use syn::punctuated::Punctuated;
+use syn::{parse_macro_input, Data, DeriveInput, Field, Fields, Ident, LitInt, LitStr, Token};
+
+/// The contents of a field's `#[binding(...)]` attribute
+struct SlotAttribute {
+    set: u64,
+    slot: u32,
+    stages: Vec<Ident>,
+    access: Option<Vec<Ident>>
+}
+
+/// Flag names separated by `|`, e.g. `VERTEX_SHADER | FRAGMENT_SHADER`
+fn parse_flags(meta: &ParseNestedMeta) -> syn::Result<Vec<Ident>> {
+    let flags = Punctuated::<Ident, Token![|]>::parse_separated_nonempty(meta.value()?)?;
+    Ok(flags.into_iter().collect())
+}
+
+fn parse_slot_attribute(field: &Field) -> syn::Result<SlotAttribute> {
+    let attribute = field.attrs.iter().find(|attribute| attribute.path().is_ident("binding")).ok_or_else(|| {
+        syn::Error::new_spanned(field, "Shader interface fields require a #[binding(set = .., slot = .., stage = ..)] attribute")
+    })?;
+
+    let mut set: Option<u64> = None;
+    let mut slot: Option<u32> = None;
+    let mut stages: Option<Vec<Ident>> = None;
+    let mut access: Option<Vec<Ident>> = None;
+    attribute.parse_nested_meta(|meta| {
+        if meta.path.is_ident("set") {
+            set = Some(meta.value()?.parse::<LitInt>()?.base10_parse()?);
+        } else if meta.path.is_ident("slot") {
+            slot = Some(meta.value()?.parse::<LitInt>()?.base10_parse()?);
+        } else if meta.path.is_ident("stage") {
+            stages = Some(parse_flags(&meta)?);
+        } else if meta.path.is_ident("access") {
+            access = Some(parse_flags(&meta)?);
+        } else {
+            return Err(meta.error("Expected `set`, `slot`, `stage` or `access`"));
+        }
+        Ok(())
+    })?;
+
+    let missing = |name: &str| syn::Error::new_spanned(attribute, format!("Binding is missing `{}`", name));
+    Ok(SlotAttribute {
+        set: set.ok_or_else(|| missing("set"))?,
+        slot: slot.ok_or_else(|| missing("slot"))?,
+        stages: stages.ok_or_else(|| missing("stage"))?,
+        access
+    })
+}
+
+/// `flags` combined into a single value of `flags_type`. The flag types' `|` isn't const, so
+/// the raw values are combined instead
+fn combine_flags(flags_type: TokenStream2, flags: &[Ident]) -> TokenStream2 {
+    quote! {
+        #flags_type::from_raw(#(#flags_type::#flags.as_raw())|*)
+    }
+}
+
+fn named_fields<'a>(input: &'a DeriveInput, derive_name: &str) -> syn::Result<&'a Punctuated<Field, Token![,]>> {
+    match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => Ok(&fields.named),
+            _ => Err(syn::Error::new_spanned(input, format!("{} requires a struct with named fields", derive_name)))
+        },
+        _ => Err(syn::Error::new_spanned(input, format!("{} can only be derived for structs", derive_name)))
+    }
+}
+
+fn expand(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let fields = named_fields(input, "ShaderInterface")?;
+
+    let mut bound: HashMap<(u64, u32), &Ident> = HashMap::new();
+    let mut slot_names: Vec<Ident> = Vec::new();
+    let mut slots: Vec<TokenStream2> = Vec::new();
+    for field in fields {
+        let attribute = parse_slot_attribute(field)?;
+        let field_name = field.ident.as_ref().unwrap();
+        if let Some(existing) = bound.insert((attribute.set, attribute.slot), field_name) {
+            return Err(syn::Error::new_spanned(field_name, format!(
+                "Set {} slot {} is already bound by `{}`", attribute.set, attribute.slot, existing)));
+        }
+
+        let kind = &field.ty;
+        let set = attribute.set;
+        let slot = attribute.slot;
+        let stage = combine_flags(quote!(::framegraph::ash::vk::PipelineStageFlags), &attribute.stages);
+        let access = match &attribute.access {
+            Some(access) => combine_flags(quote!(::framegraph::ash::vk::AccessFlags), access),
+            None => quote!(<#kind as ::framegraph::binding::SlotKind>::ACCESS)
+        };
+        let slot_name = format_ident!("{}", field_name.to_string().to_uppercase());
+        slots.push(quote! {
+            pub const #slot_name: ::framegraph::binding::BindingSlot = ::framegraph::binding::BindingSlot::new(
+                ::framegraph::binding::BindingInfo {
+                    binding_type: <#kind as ::framegraph::binding::SlotKind>::BINDING_TYPE,
+                    set: #set,
+                    slot: #slot,
+                    stage: #stage,
+                    access: #access
+                });
+        });
+        slot_names.push(slot_name);
+    }
+
+    let name = &input.ident;
+    let (impl_generics, type_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics #name #type_generics #where_clause {
+            #(#slots)*
+
+            /// Every slot of the interface, in declaration order
+            pub const SLOTS: &'static [::framegraph::binding::BindingSlot] = &[#(Self::#slot_names),*];
+        }
+    })
+}
+
+/// Generates a `BindingSlot` constant for each field of a struct describing a shader's
+/// descriptor interface, named after the field in upper case, plus a `SLOTS` constant
+/// listing all of them. Each field's type is the kind of resource it binds (see
+/// `framegraph::binding::SlotKind`), and its `#[binding]` attribute gives the set, slot and
+/// pipeline stages, optionally overriding the kind's access flags:
+///
+/// ```ignore
+/// #[derive(ShaderInterface)]
+/// struct ModelInterface {
+///     #[binding(set = 0, slot = 0, stage = VERTEX_SHADER)]
+///     mvp: UniformBuffer,
+///     #[binding(set = 0, slot = 1, stage = FRAGMENT_SHADER)]
+///     albedo: SampledImage,
+///     #[binding(set = 1, slot = 0, stage = FRAGMENT_SHADER | COMPUTE_SHADER, access = SHADER_READ)]
+///     history: StorageImage
+/// }
+/// ```
+///
+/// Binding two fields to the same set and slot is a compile error
+#[proc_macro_derive(ShaderInterface, attributes(binding))]
+pub fn derive_shader_interface(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(&input).unwrap_or_else(syn::Error::into_compile_error).into()
+}
+
+fn expand_uniform_block(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let fields = named_fields(input, "UniformBlock")?;
+    if !input.generics.params.is_empty() {
+        return Err(syn::Error::new_spanned(&input.generics, "UniformBlock can't be derived for generic structs"));
+    }
+
+    let name = &input.ident;
+    let name_string = LitStr::new(&name.to_string(), name.span());
+    let field_names: Vec<&Ident> = fields.iter().map(|field| field.ident.as_ref().unwrap()).collect();
+    let field_strings: Vec<LitStr> = field_names.iter()
+        .map(|field_name| LitStr::new(&field_name.to_string(), field_name.span()))
+        .collect();
+    let field_types = fields.iter().map(|field| &field.ty);
+    Ok(quote! {
+        impl ::framegraph::uniform_layout::UniformBlock for #name {
+            fn layout() -> &'static ::framegraph::uniform_layout::UniformLayout {
+                static LAYOUT: ::framegraph::uniform_layout::UniformLayout = ::framegraph::uniform_layout::UniformLayout {
+                    name: #name_string,
+                    size: ::core::mem::size_of::<#name>(),
+                    fields: &[#(::framegraph::uniform_layout::UniformField {
+                        name: #field_strings,
+                        offset: ::core::mem::offset_of!(#name, #field_names),
+                        size: ::core::mem::size_of::<#field_types>()
+                    }),*]
+                };
+                &LAYOUT
+            }
+        }
+    })
+}
+
+/// Implements `framegraph::uniform_layout::UniformBlock` for a struct uploaded into uniform
+/// buffers, recording the offset and size of each field. Fields must be declared in the same
+/// order as the members of the shader's uniform block, which debug builds check the struct
+/// against when it's bound with `BindingSlot::bind_uniform`
+#[proc_macro_derive(UniformBlock)]
+pub fn derive_uniform_block(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_uniform_block(&input).unwrap_or_else(syn::Error::into_compile_error).into()
+}
\ No newline at end of file
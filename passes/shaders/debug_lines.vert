@@ -0,0 +1,19 @@
+#version 450
+layout(location = 0) in vec3 aPos;
+layout(location = 1) in vec4 aColor;
+
+out gl_PerVertex {
+    vec4 gl_Position;
+};
+
+layout(set = 0, binding = 0) uniform View {
+    mat4 view_projection;
+} view;
+
+layout(location = 0) out vec4 Color;
+
+void main()
+{
+    Color = aColor;
+    gl_Position = view.view_projection * vec4(aPos, 1.0);
+}
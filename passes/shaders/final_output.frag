@@ -0,0 +1,78 @@
+#version 450
+layout(location = 0) out vec4 fColor;
+
+// see final_output::OutputEncoding and final_output::Tonemap
+const uint ENCODING_LINEAR = 0;
+const uint ENCODING_SRGB = 1;
+const uint ENCODING_HDR10 = 2;
+const uint ENCODING_SCRGB = 3;
+
+const uint TONEMAP_NONE = 0;
+const uint TONEMAP_REINHARD = 1;
+const uint TONEMAP_ACES = 2;
+
+layout(set = 0, binding = 0) uniform Output {
+    uint encoding;
+    uint tonemap;
+    float exposure;
+    // nits of the scene's 1.0, for HDR outputs
+    float paper_white;
+} uOutput;
+
+layout(set = 0, binding = 1) uniform sampler2D sScene;
+
+layout(location = 0) in vec2 vUV;
+
+// column major
+const mat3 BT709_TO_BT2020 = mat3(
+    0.6274, 0.0691, 0.0164,
+    0.3293, 0.9195, 0.0880,
+    0.0433, 0.0114, 0.8956);
+
+vec3 tonemap_aces(vec3 x)
+{
+    // Narkowicz's fit of the ACES filmic curve
+    return clamp((x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14), 0.0, 1.0);
+}
+
+vec3 encode_srgb(vec3 color)
+{
+    vec3 low = color * 12.92;
+    vec3 high = 1.055 * pow(color, vec3(1.0 / 2.4)) - 0.055;
+    return mix(high, low, lessThanEqual(color, vec3(0.0031308)));
+}
+
+// SMPTE ST 2084, from absolute luminance in nits
+vec3 encode_pq(vec3 nits)
+{
+    const float m1 = 0.1593017578125;
+    const float m2 = 78.84375;
+    const float c1 = 0.8359375;
+    const float c2 = 18.8515625;
+    const float c3 = 18.6875;
+    vec3 y = pow(clamp(nits / 10000.0, 0.0, 1.0), vec3(m1));
+    return pow((c1 + c2 * y) / (1.0 + c3 * y), vec3(m2));
+}
+
+void main()
+{
+    vec3 color = max(texture(sScene, vUV).rgb * uOutput.exposure, vec3(0.0));
+
+    if (uOutput.encoding == ENCODING_HDR10) {
+        color = encode_pq(BT709_TO_BT2020 * color * uOutput.paper_white);
+    } else if (uOutput.encoding == ENCODING_SCRGB) {
+        // scRGB's 1.0 is 80 nits
+        color = color * uOutput.paper_white / 80.0;
+    } else {
+        if (uOutput.tonemap == TONEMAP_REINHARD) {
+            color = color / (1.0 + color);
+        } else if (uOutput.tonemap == TONEMAP_ACES) {
+            color = tonemap_aces(color);
+        }
+        if (uOutput.encoding == ENCODING_SRGB) {
+            color = encode_srgb(clamp(color, 0.0, 1.0));
+        }
+    }
+
+    fColor = vec4(color, 1.0);
+}
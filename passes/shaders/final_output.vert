@@ -0,0 +1,14 @@
+#version 450
+
+out gl_PerVertex {
+    vec4 gl_Position;
+};
+
+layout(location = 0) out vec2 vUV;
+
+// a single triangle covering the viewport, without any vertex buffers
+void main()
+{
+    vUV = vec2((gl_VertexIndex << 1) & 2, gl_VertexIndex & 2);
+    gl_Position = vec4(vUV * 2.0 - 1.0, 0.0, 1.0);
+}
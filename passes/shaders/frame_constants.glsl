@@ -0,0 +1,20 @@
+// Constants shared by every pass of a frame, bound by the frame graph in the per-frame set to
+// every pipeline including this (see framegraph::frame_constants::FrameConstants). Shaders
+// including it need #extension GL_GOOGLE_include_directive : require
+
+layout(std140, set=1, binding=0) uniform FrameConstants {
+    mat4 view;
+    mat4 projection;
+    mat4 viewProjection;
+    mat4 inverseViewProjection;
+    vec4 cameraPosition;
+    // of the image the scene is rendered to, in pixels
+    vec2 viewportSize;
+    // in seconds
+    float time;
+    float deltaTime;
+    uint frameIndex;
+    uint padding0;
+    uint padding1;
+    uint padding2;
+} frame;
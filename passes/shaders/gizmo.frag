@@ -0,0 +1,10 @@
+#version 450
+layout(location = 0) out vec4 fColor;
+
+layout(location = 0) in vec4 Color;
+layout(location = 1) flat in uint Id;
+
+void main()
+{
+    fColor = Color;
+}
@@ -0,0 +1,22 @@
+#version 450
+layout(location = 0) in vec3 aPos;
+layout(location = 1) in vec4 aColor;
+layout(location = 2) in uint aId;
+
+out gl_PerVertex {
+    vec4 gl_Position;
+};
+
+layout(set = 0, binding = 0) uniform View {
+    mat4 view_projection;
+} view;
+
+layout(location = 0) out vec4 Color;
+layout(location = 1) flat out uint Id;
+
+void main()
+{
+    Color = aColor;
+    Id = aId;
+    gl_Position = view.view_projection * vec4(aPos, 1.0);
+}
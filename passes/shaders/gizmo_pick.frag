@@ -0,0 +1,13 @@
+#version 450
+layout(location = 0) out vec4 fColor;
+// the handle under each texel, for picking
+layout(location = 1) out uint fId;
+
+layout(location = 0) in vec4 Color;
+layout(location = 1) flat in uint Id;
+
+void main()
+{
+    fColor = Color;
+    fId = Id;
+}
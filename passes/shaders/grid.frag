@@ -0,0 +1,69 @@
+#version 450
+
+// An infinite grid on the y = 0 plane. Lines are anti-aliased by their screen space width and
+// fade out with distance from the camera, and the X and Z axes are drawn in their own colors.
+// Writes the depth of the plane so the grid is hidden behind the scene
+
+layout(std140, set=0, binding=0) uniform GridParams {
+    mat4 viewProjection;
+    mat4 inverseViewProjection;
+    vec4 cameraPosition;
+    vec4 lineColor;
+    float cellSize;
+    // every how many cells a major line is drawn
+    float majorInterval;
+    float fadeDistance;
+    float padding;
+} params;
+
+layout(location = 0) in vec2 vNdc;
+
+layout(location = 0) out vec4 fColor;
+
+// coverage of the lines at every whole multiple of `coord`
+float lineCoverage(vec2 coord) {
+    vec2 width = fwidth(coord);
+    vec2 distanceToLine = abs(fract(coord - 0.5) - 0.5) / width;
+    return 1.0 - min(min(distanceToLine.x, distanceToLine.y), 1.0);
+}
+
+void main()
+{
+    // any point along the texel's ray will do, which keeps this independent of the depth range
+    vec4 farPoint = params.inverseViewProjection * vec4(vNdc, 0.5, 1.0);
+    vec3 origin = params.cameraPosition.xyz;
+    vec3 direction = farPoint.xyz / farPoint.w - origin;
+    float t = -origin.y / direction.y;
+    if (t <= 0.0) {
+        discard;
+    }
+    vec3 position = origin + direction * t;
+
+    vec4 clip = params.viewProjection * vec4(position, 1.0);
+    float depth = clip.z / clip.w;
+    if (depth < 0.0 || depth > 1.0) {
+        discard;
+    }
+    gl_FragDepth = depth;
+
+    vec2 coord = position.xz / params.cellSize;
+    float minor = lineCoverage(coord);
+    float major = lineCoverage(coord / params.majorInterval);
+    vec4 color = vec4(params.lineColor.rgb, params.lineColor.a * max(minor * 0.5, major));
+
+    // the Z axis runs along x = 0 and the X axis along z = 0
+    vec2 axisWidth = fwidth(coord);
+    if (abs(coord.x) < axisWidth.x) {
+        color = vec4(0.2, 0.2, 1.0, 1.0);
+    }
+    if (abs(coord.y) < axisWidth.y) {
+        color = vec4(1.0, 0.2, 0.2, 1.0);
+    }
+
+    float fade = 1.0 - smoothstep(params.fadeDistance * 0.5, params.fadeDistance, distance(position, origin));
+    color.a *= fade;
+    if (color.a <= 0.0) {
+        discard;
+    }
+    fColor = color;
+}
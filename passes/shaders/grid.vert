@@ -0,0 +1,15 @@
+#version 450
+
+out gl_PerVertex {
+    vec4 gl_Position;
+};
+
+layout(location = 0) out vec2 vNdc;
+
+// a single triangle covering the viewport; the fragment shader finds where each texel's view
+// ray meets the ground plane
+void main()
+{
+    vNdc = vec2((gl_VertexIndex << 1) & 2, gl_VertexIndex & 2) * 2.0 - 1.0;
+    gl_Position = vec4(vNdc, 0.0, 1.0);
+}
@@ -0,0 +1,9 @@
+#version 450
+layout(location = 0) out uint fId;
+
+layout(location = 0) flat in uint Id;
+
+void main()
+{
+    fId = Id;
+}
@@ -0,0 +1,22 @@
+#version 450
+layout(location = 0) in vec3 aPos;
+
+out gl_PerVertex {
+    vec4 gl_Position;
+};
+
+layout(std140, set = 0, binding = 0) uniform ObjectIdParams {
+    mat4 modelViewProj;
+    uint id;
+    uint padding0;
+    uint padding1;
+    uint padding2;
+} params;
+
+layout(location = 0) flat out uint Id;
+
+void main()
+{
+    Id = params.id;
+    gl_Position = params.modelViewProj * vec4(aPos, 1.0);
+}
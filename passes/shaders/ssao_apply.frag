@@ -0,0 +1,13 @@
+#version 450
+
+// Outputs the occlusion, which the pass's blend state multiplies into the target
+
+layout(location = 0) in vec2 vUV;
+
+layout(location = 0) out vec4 fragColor;
+
+layout(set = 0, binding = 0) uniform sampler2D aoImage;
+
+void main() {
+    fragColor = vec4(vec3(texture(aoImage, vUV).r), 1.0);
+}
@@ -0,0 +1,15 @@
+#version 450
+layout(location = 0) out vec4 fColor;
+
+// glyph coverage is stored in the red channel
+layout(set=0, binding=1) uniform sampler2D sAtlas;
+
+layout(location = 0) in struct {
+    vec4 Color;
+    vec2 UV;
+} In;
+
+void main()
+{
+    fColor = vec4(In.Color.rgb, In.Color.a * texture(sAtlas, In.UV.st).r);
+}
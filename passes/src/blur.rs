@@ -12,7 +12,8 @@ use framegraph::binding::{BindingInfo, BindingType, ImageBindingInfo, ResourceBi
 use framegraph::compute_pass_node::ComputePassNode;
 use framegraph::pass_type::PassType;
 use framegraph::pipeline::ComputePipelineDescription;
-use profiling::{enter_gpu_span, enter_span};
+use framegraph::shader_variant::ShaderVariant;
+use profiling::enter_span;
 
 pub fn generate_pass(
     device: Rc<RefCell<DeviceWrapper>>,
@@ -21,52 +22,55 @@ pub fn generate_pass(
 
     let image_extent = source.borrow().get_image().extent.clone();
 
+    let blur_usage = vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::TRANSFER_SRC;
+    // the blur shader has a variant for each format it writes
+    let blur_format = device.borrow().formats().require(
+        &[vk::Format::R8G8B8A8_UNORM, vk::Format::R16G16B16A16_SFLOAT],
+        blur_usage,
+        vk::FormatFeatureFlags::BLIT_SRC,
+        "blur_target");
     let blur_target_create_info: ImageCreateInfo = ImageCreateInfo::new(
         vk::ImageCreateInfo::builder()
             .image_type(vk::ImageType::TYPE_2D)
-            .format(vk::Format::R8G8B8A8_UNORM)
+            .format(blur_format)
             .initial_layout(vk::ImageLayout::UNDEFINED)
             .extent(image_extent)
             .samples(vk::SampleCountFlags::TYPE_1)
-            .usage(vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::TRANSFER_SRC)
+            .usage(blur_usage)
             .mip_levels(1)
             .array_layers(1)
             .build(),
         String::from("blur_target"),
         ImageType::Color);
 
-    let source_binding = ResourceBinding {
-        resource: source.clone(),
-        binding_info: BindingInfo {
-            binding_type: BindingType::Image(ImageBindingInfo {
-                layout: vk::ImageLayout::GENERAL
-            }),
+    let source_binding = ResourceBinding::new(
+        source.clone(),
+        BindingInfo {
+            binding_type: BindingType::Image(ImageBindingInfo::new(vk::ImageLayout::GENERAL)),
             set: 0,
             slot: 0,
             stage: vk::PipelineStageFlags::COMPUTE_SHADER,
             access: vk::AccessFlags::SHADER_READ
-        }
-    };
+        });
 
     let blur_target = Rc::new(RefCell::new(DeviceWrapper::create_image(
         device,
         &blur_target_create_info,
         MemoryLocation::GpuOnly)));
 
-    let target_binding = ResourceBinding {
-        resource: blur_target.clone(),
-        binding_info: BindingInfo {
-            binding_type: BindingType::Image(ImageBindingInfo {
-                layout: vk::ImageLayout::GENERAL
-            }),
+    let target_binding = ResourceBinding::new(
+        blur_target.clone(),
+        BindingInfo {
+            binding_type: BindingType::Image(ImageBindingInfo::new(vk::ImageLayout::GENERAL)),
             set: 0,
             slot: 1,
             stage: vk::PipelineStageFlags::COMPUTE_SHADER,
             access: vk::AccessFlags::SHADER_WRITE
-        }
-    };
+        }).transient();
 
-    let pipeline_description = ComputePipelineDescription::new("blur-comp.spv");
+    let shader = ShaderVariant::new("blur-comp")
+        .define("FLOAT16", blur_format == vk::Format::R16G16B16A16_SFLOAT);
+    let pipeline_description = ComputePipelineDescription::new(&shader.get_file_name());
 
     let pass_node = ComputePassNode::builder("blur".to_string())
         .pipeline_description(pipeline_description)
@@ -77,9 +81,7 @@ pub fn generate_pass(
                   command_buffer: &vk::CommandBuffer | {
 
                 enter_span!(tracing::Level::TRACE, "Blur");
-                let device = render_ctx.get_device();
-                let borrowed_device = device.borrow();
-                enter_gpu_span!("Blur GPU", "Passes", borrowed_device.get(), command_buffer, vk::PipelineStageFlags::ALL_GRAPHICS);
+                let _gpu_scope = render_ctx.get_profiler().scope("Blur GPU", command_buffer);
 
                 unsafe {
                     render_ctx.get_device().borrow().get().cmd_dispatch(
@@ -8,49 +8,123 @@ use context::vulkan_render_context::VulkanRenderContext;
 use framegraph::binding::{BindingInfo, BindingType, ImageBindingInfo, ResourceBinding};
 use framegraph::graphics_pass_node::GraphicsPassNode;
 use framegraph::pass_type::PassType;
-use profiling::{enter_gpu_span, enter_span};
+use profiling::enter_span;
 
 pub fn clear(
     target: Rc<RefCell<DeviceResource>>,
     aspect_mask: vk::ImageAspectFlags) -> PassType{
 
-    let target_binding = ResourceBinding {
-        resource: target.clone(),
-        binding_info: BindingInfo {
-            binding_type: BindingType::Image(ImageBindingInfo { layout: vk::ImageLayout::GENERAL}),
+    clear_with_color(target, aspect_mask, [0.0, 0.0, 0.0, 0.0])
+}
+
+/// Like `clear`, with `color` used when clearing a color image
+pub fn clear_with_color(
+    target: Rc<RefCell<DeviceResource>>,
+    aspect_mask: vk::ImageAspectFlags,
+    color: [f32; 4]) -> PassType{
+
+    clear_image(target, aspect_mask, vk::ClearColorValue { float32: color }, DEFAULT_DEPTH_STENCIL)
+}
+
+/// Clears an unsigned integer color image, e.g. a pick target, to `id`
+pub fn clear_ids(
+    target: Rc<RefCell<DeviceResource>>,
+    id: u32) -> PassType{
+
+    clear_image(target, vk::ImageAspectFlags::COLOR, vk::ClearColorValue { uint32: [id, 0, 0, 0] }, DEFAULT_DEPTH_STENCIL)
+}
+
+/// Clears the depth of `target` to `depth`, e.g. 0.0 for reverse-Z (see
+/// DepthStencilType::REVERSE_Z). The stencil of combined depth-stencil formats is left as is
+pub fn clear_depth(
+    target: Rc<RefCell<DeviceResource>>,
+    depth: f32) -> PassType{
+
+    clear_image(
+        target,
+        vk::ImageAspectFlags::DEPTH,
+        vk::ClearColorValue::default(),
+        vk::ClearDepthStencilValue { depth, stencil: 0 })
+}
+
+/// Clears the depth and stencil of `target`, which must have a combined depth-stencil format
+/// such as D24_UNORM_S8_UINT or D32_SFLOAT_S8_UINT
+pub fn clear_depth_stencil(
+    target: Rc<RefCell<DeviceResource>>,
+    depth: f32,
+    stencil: u32) -> PassType{
+
+    let format = target.borrow().get_image().format;
+    assert_eq!(
+        util::image::get_aspect_mask_from_format(format),
+        vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL,
+        "{:?} isn't a combined depth-stencil format", format);
+
+    clear_image(
+        target,
+        vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL,
+        vk::ClearColorValue::default(),
+        vk::ClearDepthStencilValue { depth, stencil })
+}
+
+const DEFAULT_DEPTH_STENCIL: vk::ClearDepthStencilValue = vk::ClearDepthStencilValue {
+    depth: 1.0,
+    stencil: 0
+};
+
+fn clear_image(
+    target: Rc<RefCell<DeviceResource>>,
+    aspect_mask: vk::ImageAspectFlags,
+    clear_color: vk::ClearColorValue,
+    depth_stencil: vk::ClearDepthStencilValue) -> PassType{
+
+    let target_binding = ResourceBinding::new(
+        target.clone(),
+        BindingInfo {
+            binding_type: BindingType::Image(ImageBindingInfo::new(vk::ImageLayout::GENERAL)),
             set: 0,
             slot: 0,
             stage: vk::PipelineStageFlags::TRANSFER,
             access: vk::AccessFlags::TRANSFER_WRITE
-        }
-    };
+        });
 
+    let depth_stencil_aspects = vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL;
     let pass_name = {
         if aspect_mask == vk::ImageAspectFlags::COLOR {
             "Color clear".to_string()
-        } else if aspect_mask & vk::ImageAspectFlags::DEPTH == vk::ImageAspectFlags::DEPTH {
+        } else if aspect_mask == depth_stencil_aspects {
+            "Depth stencil clear".to_string()
+        } else if aspect_mask == vk::ImageAspectFlags::DEPTH {
             "Depth clear".to_string()
+        } else if aspect_mask == vk::ImageAspectFlags::STENCIL {
+            "Stencil clear".to_string()
         } else {
-            panic!("Invalid aspect mask for clear");
+            panic!("Invalid aspect mask for clear: {:?}", aspect_mask);
         }
     };
 
+    let clear_value = if aspect_mask == vk::ImageAspectFlags::COLOR {
+        vk::ClearValue { color: clear_color }
+    } else {
+        vk::ClearValue { depth_stencil }
+    };
+
     let pass_node = GraphicsPassNode::builder(pass_name.clone())
         .write(target_binding)
+        .clears(clear_value, aspect_mask)
         .fill_commands(Box::new(
             move |render_ctx: &VulkanRenderContext,
                   command_buffer: &vk::CommandBuffer | {
 
                 enter_span!(tracing::Level::TRACE, "clear");
-                let device = render_ctx.get_device();
-                let borrowed_device = device.borrow();
-                enter_gpu_span!(&pass_name, "misc", borrowed_device.get(), command_buffer, vk::PipelineStageFlags::ALL_GRAPHICS);
+                let _gpu_scope = render_ctx.get_profiler().scope(&pass_name, command_buffer);
 
                 let range = vk::ImageSubresourceRange::builder()
                     .aspect_mask(aspect_mask)
                     .level_count(1)
                     .base_mip_level(0)
-                    .layer_count(1)
+                    // every layer of layered or multiview targets
+                    .layer_count(vk::REMAINING_ARRAY_LAYERS)
                     .base_array_layer(0)
                     .build();
 
@@ -60,20 +134,15 @@ pub fn clear(
                             *command_buffer,
                             target.borrow().get_image().image,
                             vk::ImageLayout::GENERAL,
-                            &Default::default(),
+                            &clear_color,
                             std::slice::from_ref(&range));
-                    } else if aspect_mask & vk::ImageAspectFlags::DEPTH == vk::ImageAspectFlags::DEPTH {
+                    } else {
                         render_ctx.get_device().borrow().get().cmd_clear_depth_stencil_image(
                             *command_buffer,
                             target.borrow().get_image().image,
                             vk::ImageLayout::GENERAL,
-                            &vk::ClearDepthStencilValue::builder()
-                                .depth(1.0)
-                                .stencil(0)
-                                .build(),
+                            &depth_stencil,
                             std::slice::from_ref(&range));
-                    } else {
-                        panic!("Invalid aspect mask for clear: {:?}", aspect_mask);
                     }
                 };
             }
@@ -0,0 +1,249 @@
+use std::cell::RefCell;
+use std::fmt::{Debug, Formatter};
+use std::rc::Rc;
+
+use ash::vk;
+use glam::{Mat4, Vec3, Vec4};
+use api_types::device::DeviceWrapper;
+use api_types::upload_buffer::DynamicUploadBuffer;
+
+use context::render_context::RenderContext;
+use context::vulkan_render_context::VulkanRenderContext;
+use framegraph::attachment::AttachmentReference;
+use framegraph::binding::{BindingInfo, BindingType, BufferBindingInfo, ResourceBinding};
+use framegraph::graphics_pass_node::GraphicsPassNode;
+use framegraph::pass_type::PassType;
+use framegraph::pipeline::{BlendType, DepthStencilType, PipelineDescription, RasterizationState};
+use framegraph::shader;
+use framegraph::shader::Shader;
+use profiling::enter_span;
+
+#[derive(Copy, Clone, Debug)]
+#[repr(C)]
+struct DebugLineVertex {
+    position: [f32; 3],
+    color: [f32; 4]
+}
+
+const DEBUG_LINE_VERTEX_BINDING: vk::VertexInputBindingDescription = vk::VertexInputBindingDescription {
+    binding: 0,
+    stride: std::mem::size_of::<DebugLineVertex>() as u32,
+    input_rate: vk::VertexInputRate::VERTEX,
+};
+
+const DEBUG_LINE_VERTEX_ATTRIBUTES: [vk::VertexInputAttributeDescription; 2] = [
+    // position
+    vk::VertexInputAttributeDescription {
+        location: 0,
+        binding: 0,
+        format: vk::Format::R32G32B32_SFLOAT,
+        offset: 0,
+    },
+
+    // color
+    vk::VertexInputAttributeDescription {
+        location: 1,
+        binding: 0,
+        format: vk::Format::R32G32B32A32_SFLOAT,
+        offset: 4 * 3,
+    }
+];
+
+/// World-space line segments collected on the CPU over a frame, drawn by [`DebugLineRender`]
+#[derive(Clone, Debug, Default)]
+pub struct DebugLines {
+    vertices: Vec<DebugLineVertex>
+}
+
+impl DebugLines {
+    pub fn new() -> Self {
+        DebugLines {
+            vertices: Vec::new()
+        }
+    }
+
+    pub fn add_line(&mut self, start: Vec3, end: Vec3, color: Vec4) {
+        self.vertices.push(DebugLineVertex { position: start.to_array(), color: color.to_array() });
+        self.vertices.push(DebugLineVertex { position: end.to_array(), color: color.to_array() });
+    }
+
+    /// Adds the edges of the box from `min` to `max` once placed by `transform`, e.g. a
+    /// mesh's local bounds and its model matrix
+    pub fn add_aabb(&mut self, min: Vec3, max: Vec3, transform: &Mat4, color: Vec4) {
+        let corners: [Vec3; 8] = std::array::from_fn(|corner| {
+            transform.transform_point3(Vec3::new(
+                if corner & 1 == 0 { min.x } else { max.x },
+                if corner & 2 == 0 { min.y } else { max.y },
+                if corner & 4 == 0 { min.z } else { max.z }))
+        });
+        self.add_box(&corners, color);
+    }
+
+    /// Adds the edges of the frustum of the camera whose view projection is the inverse of
+    /// `inverse_view_projection`. Depth is expected to range from 0 to 1 in clip space
+    pub fn add_frustum(&mut self, inverse_view_projection: &Mat4, color: Vec4) {
+        let corners: [Vec3; 8] = std::array::from_fn(|corner| {
+            inverse_view_projection.project_point3(Vec3::new(
+                if corner & 1 == 0 { -1.0 } else { 1.0 },
+                if corner & 2 == 0 { -1.0 } else { 1.0 },
+                if corner & 4 == 0 { 0.0 } else { 1.0 }))
+        });
+        self.add_box(&corners, color);
+    }
+
+    /// Corners are indexed by which of their x, y and z (bits 0, 1 and 2) are at the maximum
+    fn add_box(&mut self, corners: &[Vec3; 8], color: Vec4) {
+        for (corner, start) in corners.iter().enumerate() {
+            for axis in [1, 2, 4] {
+                if corner & axis == 0 {
+                    self.add_line(*start, corners[corner | axis], color);
+                }
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.vertices.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vertices.is_empty()
+    }
+
+    pub fn get_line_count(&self) -> usize {
+        self.vertices.len() / 2
+    }
+}
+
+pub struct DebugLineRender {
+    vertex_shader: Rc<RefCell<Shader>>,
+    fragment_shader: Rc<RefCell<Shader>>,
+    /// How lines are tested against the depth target when there is one, e.g.
+    /// DepthStencilType::REVERSE_Z for reverse-Z depth
+    pub depth_test: DepthStencilType
+}
+
+impl Debug for DebugLineRender {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DebugLineRender")
+            .finish()
+    }
+}
+
+impl DebugLineRender {
+    pub fn new(device: Rc<RefCell<DeviceWrapper>>) -> Self {
+        let vert_shader = Rc::new(RefCell::new(
+            shader::create_shader_module_from_bytes(device.clone(), "debug_lines-vert", include_bytes!(concat!(env!("OUT_DIR"), "/shaders/debug_lines-vert.spv")))));
+        let frag_shader = Rc::new(RefCell::new(
+            shader::create_shader_module_from_bytes(device.clone(), "debug_lines-frag", include_bytes!(concat!(env!("OUT_DIR"), "/shaders/debug_lines-frag.spv")))));
+
+        DebugLineRender {
+            vertex_shader: vert_shader,
+            fragment_shader: frag_shader,
+            depth_test: DepthStencilType::Enable
+        }
+    }
+
+    /// Generates a pass drawing `lines` over `render_target`, depth tested against
+    /// `depth_target` if there is one. `viewport` should match the one the scene was drawn
+    /// with, since it may flip Y for `view_projection`. Returns None when there are no lines
+    pub fn generate_pass(
+        &self,
+        lines: &DebugLines,
+        view_projection: &Mat4,
+        viewport: vk::Viewport,
+        render_target: AttachmentReference,
+        depth_target: Option<AttachmentReference>,
+        upload_buffer: &mut DynamicUploadBuffer) -> Option<PassType> {
+
+        enter_span!(tracing::Level::TRACE, "Generate Debug Lines Pass");
+
+        if lines.is_empty() {
+            return None;
+        }
+
+        let upload_resource = upload_buffer.get_buffer();
+
+        let view_offset = {
+            let alignment = upload_buffer.get_uniform_alignment();
+            upload_buffer.push(std::slice::from_ref(&view_projection.to_cols_array()), alignment)
+        };
+        let vertex_offset = upload_buffer.push(
+            &lines.vertices,
+            std::mem::align_of::<DebugLineVertex>() as vk::DeviceSize);
+        let vertex_count = lines.vertices.len() as u32;
+
+        let view_binding = ResourceBinding::new(
+            upload_resource.clone(),
+            BindingInfo {
+                binding_type: BindingType::Buffer(BufferBindingInfo::new(
+                    view_offset,
+                    std::mem::size_of::<[f32; 16]>() as vk::DeviceSize)),
+                set: 0,
+                slot: 0,
+                stage: vk::PipelineStageFlags::VERTEX_SHADER,
+                access: vk::AccessFlags::SHADER_READ,
+            });
+
+        let vertex_input = vk::PipelineVertexInputStateCreateInfo::builder()
+            .vertex_binding_descriptions(std::slice::from_ref(&DEBUG_LINE_VERTEX_BINDING))
+            .vertex_attribute_descriptions(&DEBUG_LINE_VERTEX_ATTRIBUTES)
+            .build();
+
+        let dynamic_states = vec!(vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR);
+
+        let pipeline_description = PipelineDescription::new(
+            vertex_input,
+            dynamic_states,
+            RasterizationState::default(),
+            match depth_target {
+                Some(_) => self.depth_test,
+                None => DepthStencilType::Disable
+            },
+            BlendType::Alpha,
+            "debug_lines",
+            self.vertex_shader.clone(),
+            self.fragment_shader.clone())
+            .topology(vk::PrimitiveTopology::LINE_LIST, false);
+
+        let scissor = {
+            let extent = render_target.resource_image.borrow().get_image().extent;
+            vk::Rect2D::builder()
+                .offset(vk::Offset2D{x: 0, y: 0})
+                .extent(vk::Extent2D{width: extent.width, height: extent.height})
+                .build()
+        };
+
+        let mut pass_builder = GraphicsPassNode::builder("debug_lines".to_string())
+            .pipeline_description(pipeline_description)
+            .render_target(render_target)
+            .read(view_binding)
+            .vertex_buffer(upload_resource, vertex_offset)
+            .viewport(viewport)
+            .scissor(scissor);
+        if let Some(depth_target) = depth_target {
+            pass_builder = pass_builder.depth_target(depth_target);
+        }
+
+        let pass_node = pass_builder
+            .fill_commands(Box::new(
+                move |render_ctx: &VulkanRenderContext,
+                      command_buffer: &vk::CommandBuffer | {
+                    unsafe {
+                        enter_span!(tracing::Level::TRACE, "Debug Lines Draw");
+                        let _gpu_scope = render_ctx.get_profiler().scope("Debug Lines GPU", command_buffer);
+                        render_ctx.get_device().borrow().get().cmd_draw(
+                            *command_buffer,
+                            vertex_count,
+                            1,
+                            0,
+                            0);
+                    }
+                }
+            ))
+            .build()
+            .expect("Failed to create debug lines passnode");
+
+        Some(PassType::Graphics(pass_node))
+    }
+}
@@ -0,0 +1,671 @@
+use std::cell::RefCell;
+use std::f32::consts::TAU;
+use std::fmt::{Debug, Formatter};
+use std::rc::Rc;
+
+use ash::vk;
+use glam::{Mat4, Quat, Vec3, Vec4};
+use api_types::device::DeviceWrapper;
+use api_types::upload_buffer::DynamicUploadBuffer;
+
+use context::render_context::RenderContext;
+use context::vulkan_render_context::VulkanRenderContext;
+use framegraph::attachment::AttachmentReference;
+use framegraph::binding::{BindingInfo, BindingType, BufferBindingInfo, ResourceBinding};
+use framegraph::graphics_pass_node::GraphicsPassNode;
+use framegraph::pass_type::PassType;
+use framegraph::pipeline::{BlendType, DepthStencilType, PipelineDescription, RasterizationState};
+use framegraph::shader;
+use framegraph::shader::Shader;
+use framegraph::uniform_layout::{UniformBlock, UniformLayout};
+use profiling::enter_span;
+
+use crate::picking::NO_PICK_ID;
+
+// gizmo handles take ids from the top of the range, leaving the rest to the application's
+// own pickable objects
+const GIZMO_PICK_ID_BASE: u32 = 0xFFFF_FF00;
+
+// matches the GridParams uniform in grid.frag
+#[repr(C)]
+#[derive(UniformBlock)]
+struct GridParams {
+    view_projection: Mat4,
+    inverse_view_projection: Mat4,
+    camera_position: Vec4,
+    line_color: Vec4,
+    cell_size: f32,
+    major_interval: f32,
+    fade_distance: f32,
+    padding: f32
+}
+
+fn grid_params(settings: &GridSettings, view_projection: &Mat4, camera_position: Vec3) -> GridParams {
+    GridParams {
+        view_projection: *view_projection,
+        inverse_view_projection: view_projection.inverse(),
+        camera_position: camera_position.extend(1.0),
+        line_color: settings.line_color,
+        cell_size: settings.cell_size,
+        major_interval: settings.major_interval,
+        fade_distance: settings.fade_distance,
+        padding: 0.0
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct GridSettings {
+    /// The world space distance between minor lines
+    pub cell_size: f32,
+    /// Every how many cells a major line is drawn
+    pub major_interval: f32,
+    /// How far from the camera the grid has faded out completely
+    pub fade_distance: f32,
+    pub line_color: Vec4
+}
+
+impl Default for GridSettings {
+    fn default() -> Self {
+        GridSettings {
+            cell_size: 1.0,
+            major_interval: 10.0,
+            fade_distance: 100.0,
+            line_color: Vec4::new(0.6, 0.6, 0.6, 0.8)
+        }
+    }
+}
+
+fn uniform_binding(
+    upload_buffer: &DynamicUploadBuffer,
+    offset: vk::DeviceSize,
+    range: vk::DeviceSize,
+    layout: Option<&'static UniformLayout>,
+    stage: vk::PipelineStageFlags) -> ResourceBinding {
+
+    ResourceBinding::new(
+        upload_buffer.get_buffer(),
+        BindingInfo {
+            binding_type: BindingType::Buffer(BufferBindingInfo {
+                offset,
+                range,
+                layout
+            }),
+            set: 0,
+            slot: 0,
+            stage,
+            access: vk::AccessFlags::SHADER_READ
+        })
+}
+
+fn full_scissor(target: &AttachmentReference) -> vk::Rect2D {
+    let extent = target.resource_image.borrow().get_image().extent;
+    vk::Rect2D::builder()
+        .offset(vk::Offset2D{x: 0, y: 0})
+        .extent(vk::Extent2D{width: extent.width, height: extent.height})
+        .build()
+}
+
+/// An infinite ground grid on the y = 0 plane, drawn entirely in the fragment shader by
+/// intersecting each texel's view ray with the plane. Writes the plane's depth, so it should
+/// be drawn after the scene's opaque geometry
+pub struct InfiniteGrid {
+    vertex_shader: Rc<RefCell<Shader>>,
+    fragment_shader: Rc<RefCell<Shader>>,
+    pub settings: GridSettings,
+    /// How the grid is tested against the depth target when there is one, e.g.
+    /// DepthStencilType::REVERSE_Z for reverse-Z depth
+    pub depth_test: DepthStencilType
+}
+
+impl Debug for InfiniteGrid {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("InfiniteGrid")
+            .field("settings", &self.settings)
+            .finish()
+    }
+}
+
+impl InfiniteGrid {
+    pub fn new(device: Rc<RefCell<DeviceWrapper>>) -> Self {
+        let vert_shader = Rc::new(RefCell::new(
+            shader::create_shader_module_from_bytes(device.clone(), "grid-vert", include_bytes!(concat!(env!("OUT_DIR"), "/shaders/grid-vert.spv")))));
+        let frag_shader = Rc::new(RefCell::new(
+            shader::create_shader_module_from_bytes(device.clone(), "grid-frag", include_bytes!(concat!(env!("OUT_DIR"), "/shaders/grid-frag.spv")))));
+
+        InfiniteGrid {
+            vertex_shader: vert_shader,
+            fragment_shader: frag_shader,
+            settings: GridSettings::default(),
+            depth_test: DepthStencilType::Enable
+        }
+    }
+
+    /// Generates a pass drawing the grid over `render_target`, depth tested against
+    /// `depth_target` if there is one. `view_projection` must be a perspective projection of
+    /// the camera at `camera_position`, and `viewport` should match the one the scene was
+    /// drawn with
+    pub fn generate_pass(
+        &self,
+        view_projection: &Mat4,
+        camera_position: Vec3,
+        viewport: vk::Viewport,
+        render_target: AttachmentReference,
+        depth_target: Option<AttachmentReference>,
+        upload_buffer: &mut DynamicUploadBuffer) -> PassType {
+
+        enter_span!(tracing::Level::TRACE, "Generate Grid Pass");
+
+        let params = grid_params(&self.settings, view_projection, camera_position);
+        let params_offset = {
+            let alignment = upload_buffer.get_uniform_alignment();
+            upload_buffer.push(std::slice::from_ref(&params), alignment)
+        };
+        let params_binding = uniform_binding(
+            upload_buffer,
+            params_offset,
+            std::mem::size_of::<GridParams>() as vk::DeviceSize,
+            Some(GridParams::layout()),
+            vk::PipelineStageFlags::FRAGMENT_SHADER);
+
+        let pipeline_description = PipelineDescription::new(
+            vk::PipelineVertexInputStateCreateInfo::default(),
+            vec!(vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR),
+            RasterizationState::default(),
+            match depth_target {
+                Some(_) => self.depth_test,
+                None => DepthStencilType::Disable
+            },
+            BlendType::Alpha,
+            "infinite_grid",
+            self.vertex_shader.clone(),
+            self.fragment_shader.clone());
+
+        let scissor = full_scissor(&render_target);
+        let mut pass_builder = GraphicsPassNode::builder("infinite_grid".to_string())
+            .pipeline_description(pipeline_description)
+            .render_target(render_target)
+            .read(params_binding)
+            .viewport(viewport)
+            .scissor(scissor);
+        if let Some(depth_target) = depth_target {
+            pass_builder = pass_builder.depth_target(depth_target);
+        }
+
+        let pass_node = pass_builder
+            .fill_commands(Box::new(
+                move |render_ctx: &VulkanRenderContext,
+                      command_buffer: &vk::CommandBuffer | {
+                    enter_span!(tracing::Level::TRACE, "Grid Draw");
+                    let _gpu_scope = render_ctx.get_profiler().scope("Grid GPU", command_buffer);
+                    unsafe {
+                        render_ctx.get_device().borrow().get().cmd_draw(
+                            *command_buffer,
+                            3,
+                            1,
+                            0,
+                            0);
+                    }
+                }
+            ))
+            .build()
+            .expect("Failed to create grid passnode");
+
+        PassType::Graphics(pass_node)
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+#[repr(C)]
+struct GizmoVertex {
+    position: [f32; 3],
+    color: [f32; 4],
+    id: u32
+}
+
+const GIZMO_VERTEX_BINDING: vk::VertexInputBindingDescription = vk::VertexInputBindingDescription {
+    binding: 0,
+    stride: std::mem::size_of::<GizmoVertex>() as u32,
+    input_rate: vk::VertexInputRate::VERTEX,
+};
+
+const GIZMO_VERTEX_ATTRIBUTES: [vk::VertexInputAttributeDescription; 3] = [
+    // position
+    vk::VertexInputAttributeDescription {
+        location: 0,
+        binding: 0,
+        format: vk::Format::R32G32B32_SFLOAT,
+        offset: 0,
+    },
+
+    // color
+    vk::VertexInputAttributeDescription {
+        location: 1,
+        binding: 0,
+        format: vk::Format::R32G32B32A32_SFLOAT,
+        offset: 4 * 3,
+    },
+
+    // pick id
+    vk::VertexInputAttributeDescription {
+        location: 2,
+        binding: 0,
+        format: vk::Format::R32_UINT,
+        offset: 4 * 7,
+    }
+];
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum GizmoMode {
+    Translate,
+    Rotate
+}
+
+/// The parts of a gizmo which can be picked and dragged
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum GizmoHandle {
+    TranslateX,
+    TranslateY,
+    TranslateZ,
+    RotateX,
+    RotateY,
+    RotateZ
+}
+
+impl GizmoHandle {
+    const ALL: [GizmoHandle; 6] = [
+        GizmoHandle::TranslateX,
+        GizmoHandle::TranslateY,
+        GizmoHandle::TranslateZ,
+        GizmoHandle::RotateX,
+        GizmoHandle::RotateY,
+        GizmoHandle::RotateZ];
+
+    /// The index of the gizmo's local axis the handle moves along or rotates around
+    pub fn axis_index(&self) -> usize {
+        match self {
+            GizmoHandle::TranslateX | GizmoHandle::RotateX => 0,
+            GizmoHandle::TranslateY | GizmoHandle::RotateY => 1,
+            GizmoHandle::TranslateZ | GizmoHandle::RotateZ => 2
+        }
+    }
+
+    /// The id the handle is drawn into pick targets with
+    pub fn pick_id(&self) -> u32 {
+        GIZMO_PICK_ID_BASE + *self as u32
+    }
+
+    pub fn from_pick_id(id: u32) -> Option<GizmoHandle> {
+        GizmoHandle::ALL.iter().copied().find(|handle| handle.pick_id() == id)
+    }
+}
+
+const AXIS_COLORS: [Vec4; 3] = [
+    Vec4::new(0.9, 0.2, 0.2, 1.0),
+    Vec4::new(0.2, 0.9, 0.2, 1.0),
+    Vec4::new(0.2, 0.3, 0.95, 1.0)];
+const HIGHLIGHT_COLOR: Vec4 = Vec4::new(1.0, 0.85, 0.1, 1.0);
+// segments around the circumference of shafts, cones and ring tubes
+const TUBE_SEGMENTS: usize = 8;
+const RING_SEGMENTS: usize = 48;
+
+/// Triangles for a gizmo's handles, in the gizmo's local space where its axes are one unit long
+struct GizmoMesh {
+    vertices: Vec<GizmoVertex>,
+    color: Vec4,
+    id: u32
+}
+
+impl GizmoMesh {
+    fn add_triangle(&mut self, corners: [Vec3; 3]) {
+        for corner in corners {
+            self.vertices.push(GizmoVertex {
+                position: corner.to_array(),
+                color: self.color.to_array(),
+                id: self.id
+            });
+        }
+    }
+
+    fn add_quad(&mut self, corners: [Vec3; 4]) {
+        self.add_triangle([corners[0], corners[1], corners[2]]);
+        self.add_triangle([corners[0], corners[2], corners[3]]);
+    }
+
+    /// A point on the circle of `radius` spanned by the perpendicular `u` and `v`
+    fn circle_point(u: Vec3, v: Vec3, radius: f32, segment: usize, segments: usize) -> Vec3 {
+        let angle = TAU * segment as f32 / segments as f32;
+        (u * angle.cos() + v * angle.sin()) * radius
+    }
+
+    fn add_shaft(&mut self, axis: Vec3, u: Vec3, v: Vec3, length: f32, radius: f32) {
+        for segment in 0..TUBE_SEGMENTS {
+            let start = Self::circle_point(u, v, radius, segment, TUBE_SEGMENTS);
+            let end = Self::circle_point(u, v, radius, segment + 1, TUBE_SEGMENTS);
+            self.add_quad([start, end, end + axis * length, start + axis * length]);
+        }
+    }
+
+    fn add_cone(&mut self, axis: Vec3, u: Vec3, v: Vec3, base: f32, tip: f32, radius: f32) {
+        let base_center = axis * base;
+        for segment in 0..TUBE_SEGMENTS {
+            let start = base_center + Self::circle_point(u, v, radius, segment, TUBE_SEGMENTS);
+            let end = base_center + Self::circle_point(u, v, radius, segment + 1, TUBE_SEGMENTS);
+            self.add_triangle([start, end, axis * tip]);
+            self.add_triangle([end, start, base_center]);
+        }
+    }
+
+    /// A torus of `radius` around `axis` with a tube of `thickness`
+    fn add_ring(&mut self, axis: Vec3, u: Vec3, v: Vec3, radius: f32, thickness: f32) {
+        let ring_point = |segment: usize, tube_segment: usize| {
+            let center = Self::circle_point(u, v, radius, segment, RING_SEGMENTS);
+            center + Self::circle_point(center.normalize(), axis, thickness, tube_segment, TUBE_SEGMENTS)
+        };
+        for segment in 0..RING_SEGMENTS {
+            for tube_segment in 0..TUBE_SEGMENTS {
+                self.add_quad([
+                    ring_point(segment, tube_segment),
+                    ring_point(segment + 1, tube_segment),
+                    ring_point(segment + 1, tube_segment + 1),
+                    ring_point(segment, tube_segment + 1)]);
+            }
+        }
+    }
+}
+
+/// The triangles of a gizmo's handles for `mode`, scaled by `scale` and placed at `position`
+/// with its axes rotated by `orientation`
+fn gizmo_vertices(
+    mode: GizmoMode,
+    highlight: Option<GizmoHandle>,
+    position: Vec3,
+    orientation: Quat,
+    scale: f32) -> Vec<GizmoVertex> {
+
+    let handles = match mode {
+        GizmoMode::Translate => [GizmoHandle::TranslateX, GizmoHandle::TranslateY, GizmoHandle::TranslateZ],
+        GizmoMode::Rotate => [GizmoHandle::RotateX, GizmoHandle::RotateY, GizmoHandle::RotateZ]
+    };
+
+    let mut mesh = GizmoMesh {
+        vertices: Vec::new(),
+        color: Vec4::ZERO,
+        id: NO_PICK_ID
+    };
+    let axes = [Vec3::X, Vec3::Y, Vec3::Z];
+    for handle in handles {
+        let axis_index = handle.axis_index();
+        let axis = axes[axis_index];
+        let u = axes[(axis_index + 1) % 3];
+        let v = axes[(axis_index + 2) % 3];
+
+        mesh.color = match highlight == Some(handle) {
+            true => HIGHLIGHT_COLOR,
+            false => AXIS_COLORS[axis_index]
+        };
+        mesh.id = handle.pick_id();
+        match mode {
+            GizmoMode::Translate => {
+                mesh.add_shaft(axis, u, v, 0.8, 0.015);
+                mesh.add_cone(axis, u, v, 0.8, 1.0, 0.06);
+            },
+            GizmoMode::Rotate => {
+                mesh.add_ring(axis, u, v, 1.0, 0.015);
+            }
+        }
+    }
+
+    let transform = Mat4::from_scale_rotation_translation(Vec3::splat(scale), orientation, position);
+    for vertex in &mut mesh.vertices {
+        vertex.position = transform.transform_point3(Vec3::from(vertex.position)).to_array();
+    }
+    mesh.vertices
+}
+
+/// Translate and rotate handles drawn on top of the scene. Each handle is also written with its
+/// pick id to an optional pick target, which picking::IdPicker reads back to find the handle
+/// under the cursor. The gizmo keeps the same size on screen however far it is from the camera
+pub struct Gizmo {
+    vertex_shader: Rc<RefCell<Shader>>,
+    fragment_shader: Rc<RefCell<Shader>>,
+    pick_fragment_shader: Rc<RefCell<Shader>>,
+    pub mode: GizmoMode,
+    /// The length of the gizmo's axes relative to its distance from the camera
+    pub size: f32,
+    /// A handle to draw highlighted, e.g. the one being hovered or dragged
+    pub highlight: Option<GizmoHandle>
+}
+
+impl Debug for Gizmo {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Gizmo")
+            .field("mode", &self.mode)
+            .field("size", &self.size)
+            .field("highlight", &self.highlight)
+            .finish()
+    }
+}
+
+impl Gizmo {
+    pub fn new(device: Rc<RefCell<DeviceWrapper>>) -> Self {
+        let vert_shader = Rc::new(RefCell::new(
+            shader::create_shader_module_from_bytes(device.clone(), "gizmo-vert", include_bytes!(concat!(env!("OUT_DIR"), "/shaders/gizmo-vert.spv")))));
+        let frag_shader = Rc::new(RefCell::new(
+            shader::create_shader_module_from_bytes(device.clone(), "gizmo-frag", include_bytes!(concat!(env!("OUT_DIR"), "/shaders/gizmo-frag.spv")))));
+        let pick_frag_shader = Rc::new(RefCell::new(
+            shader::create_shader_module_from_bytes(device.clone(), "gizmo_pick-frag", include_bytes!(concat!(env!("OUT_DIR"), "/shaders/gizmo_pick-frag.spv")))));
+
+        Gizmo {
+            vertex_shader: vert_shader,
+            fragment_shader: frag_shader,
+            pick_fragment_shader: pick_frag_shader,
+            mode: GizmoMode::Translate,
+            size: 0.15,
+            highlight: None
+        }
+    }
+
+    /// Generates a pass drawing the gizmo at `position` with its axes rotated by `orientation`
+    /// over `render_target`, seen from the camera at `camera_position`. If there's a
+    /// `pick_target`, which must be a picking::PICK_ID_FORMAT image of the same extent, the handles'
+    /// pick ids are written into it as well. `viewport` should match the one the scene was
+    /// drawn with
+    pub fn generate_pass(
+        &self,
+        position: Vec3,
+        orientation: Quat,
+        camera_position: Vec3,
+        view_projection: &Mat4,
+        viewport: vk::Viewport,
+        render_target: AttachmentReference,
+        pick_target: Option<AttachmentReference>,
+        upload_buffer: &mut DynamicUploadBuffer) -> PassType {
+
+        enter_span!(tracing::Level::TRACE, "Generate Gizmo Pass");
+
+        let scale = self.size * position.distance(camera_position);
+        let vertices = gizmo_vertices(self.mode, self.highlight, position, orientation, scale);
+
+        let view_offset = {
+            let alignment = upload_buffer.get_uniform_alignment();
+            upload_buffer.push(std::slice::from_ref(&view_projection.to_cols_array()), alignment)
+        };
+        let vertex_offset = upload_buffer.push(
+            &vertices,
+            std::mem::align_of::<GizmoVertex>() as vk::DeviceSize);
+        let vertex_count = vertices.len() as u32;
+        let upload_resource = upload_buffer.get_buffer();
+
+        let view_binding = uniform_binding(
+            upload_buffer,
+            view_offset,
+            std::mem::size_of::<[f32; 16]>() as vk::DeviceSize,
+            None,
+            vk::PipelineStageFlags::VERTEX_SHADER);
+
+        let vertex_input = vk::PipelineVertexInputStateCreateInfo::builder()
+            .vertex_binding_descriptions(std::slice::from_ref(&GIZMO_VERTEX_BINDING))
+            .vertex_attribute_descriptions(&GIZMO_VERTEX_ATTRIBUTES)
+            .build();
+
+        // handles are opaque, and integer pick targets can't be blended anyway
+        let pipeline_description = PipelineDescription::new(
+            vertex_input,
+            vec!(vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR),
+            RasterizationState::default(),
+            DepthStencilType::Disable,
+            BlendType::None,
+            "gizmo",
+            self.vertex_shader.clone(),
+            match pick_target.is_some() {
+                true => self.pick_fragment_shader.clone(),
+                false => self.fragment_shader.clone()
+            });
+
+        let scissor = full_scissor(&render_target);
+        let mut pass_builder = GraphicsPassNode::builder("gizmo".to_string())
+            .pipeline_description(pipeline_description)
+            .render_target(render_target)
+            .read(view_binding)
+            .vertex_buffer(upload_resource, vertex_offset)
+            .viewport(viewport)
+            .scissor(scissor);
+        if let Some(pick_target) = pick_target {
+            pass_builder = pass_builder.render_target(pick_target);
+        }
+
+        let pass_node = pass_builder
+            .fill_commands(Box::new(
+                move |render_ctx: &VulkanRenderContext,
+                      command_buffer: &vk::CommandBuffer | {
+                    enter_span!(tracing::Level::TRACE, "Gizmo Draw");
+                    let _gpu_scope = render_ctx.get_profiler().scope("Gizmo GPU", command_buffer);
+                    unsafe {
+                        render_ctx.get_device().borrow().get().cmd_draw(
+                            *command_buffer,
+                            vertex_count,
+                            1,
+                            0,
+                            0);
+                    }
+                }
+            ))
+            .build()
+            .expect("Failed to create gizmo passnode");
+
+        PassType::Graphics(pass_node)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn handle_ids(vertices: &[GizmoVertex]) -> Vec<u32> {
+        let mut ids: Vec<u32> = vertices.iter().map(|vertex| vertex.id).collect();
+        ids.dedup();
+        ids
+    }
+
+    #[test]
+    fn pick_ids_round_trip() {
+        for handle in GizmoHandle::ALL {
+            assert_eq!(GizmoHandle::from_pick_id(handle.pick_id()), Some(handle));
+        }
+    }
+
+    #[test]
+    fn pick_ids_leave_the_application_its_range() {
+        assert_eq!(GizmoHandle::from_pick_id(NO_PICK_ID), None);
+        assert_eq!(GizmoHandle::from_pick_id(GIZMO_PICK_ID_BASE - 1), None);
+        assert!(GizmoHandle::ALL.iter().all(|handle| handle.pick_id() >= GIZMO_PICK_ID_BASE));
+    }
+
+    #[test]
+    fn handles_use_their_axis() {
+        assert_eq!(GizmoHandle::TranslateX.axis_index(), GizmoHandle::RotateX.axis_index());
+        assert_eq!(GizmoHandle::TranslateY.axis_index(), 1);
+        assert_eq!(GizmoHandle::RotateZ.axis_index(), 2);
+    }
+
+    #[test]
+    fn each_mode_draws_only_its_handles() {
+        let translate = gizmo_vertices(GizmoMode::Translate, None, Vec3::ZERO, Quat::IDENTITY, 1.0);
+        assert_eq!(handle_ids(&translate), vec![
+            GizmoHandle::TranslateX.pick_id(),
+            GizmoHandle::TranslateY.pick_id(),
+            GizmoHandle::TranslateZ.pick_id()]);
+        // a shaft and a cone per axis, each segment of which is two triangles
+        assert_eq!(translate.len(), 3 * 2 * TUBE_SEGMENTS * 2 * 3);
+
+        let rotate = gizmo_vertices(GizmoMode::Rotate, None, Vec3::ZERO, Quat::IDENTITY, 1.0);
+        assert_eq!(handle_ids(&rotate), vec![
+            GizmoHandle::RotateX.pick_id(),
+            GizmoHandle::RotateY.pick_id(),
+            GizmoHandle::RotateZ.pick_id()]);
+        assert_eq!(rotate.len(), 3 * RING_SEGMENTS * TUBE_SEGMENTS * 2 * 3);
+    }
+
+    #[test]
+    fn only_the_highlighted_handle_is_highlighted() {
+        let vertices = gizmo_vertices(GizmoMode::Translate, Some(GizmoHandle::TranslateY), Vec3::ZERO, Quat::IDENTITY, 1.0);
+        for vertex in &vertices {
+            let expected = match GizmoHandle::from_pick_id(vertex.id) {
+                Some(GizmoHandle::TranslateY) => HIGHLIGHT_COLOR,
+                Some(handle) => AXIS_COLORS[handle.axis_index()],
+                None => panic!("Vertex has no handle")
+            };
+            assert_eq!(vertex.color, expected.to_array());
+        }
+    }
+
+    #[test]
+    fn vertices_are_placed_scaled_and_rotated() {
+        let position = Vec3::new(1.0, 2.0, 3.0);
+        // turns the X axis into Y
+        let orientation = Quat::from_rotation_z(std::f32::consts::FRAC_PI_2);
+        let vertices = gizmo_vertices(GizmoMode::Translate, None, position, orientation, 2.0);
+
+        // the tip of the X handle's cone
+        let x_tip = vertices.iter()
+            .filter(|vertex| vertex.id == GizmoHandle::TranslateX.pick_id())
+            .map(|vertex| Vec3::from(vertex.position))
+            .max_by(|a, b| a.distance(position).total_cmp(&b.distance(position)))
+            .unwrap();
+        assert!(x_tip.distance(position + Vec3::Y * 2.0) < 1e-4, "X tip is at {}", x_tip);
+        assert!(vertices.iter().all(|vertex| Vec3::from(vertex.position).distance(position) <= 2.0 + 1e-4));
+    }
+
+    #[test]
+    fn rings_have_the_axis_length_as_radius() {
+        let vertices = gizmo_vertices(GizmoMode::Rotate, None, Vec3::ZERO, Quat::IDENTITY, 1.0);
+        for vertex in vertices.iter().filter(|vertex| vertex.id == GizmoHandle::RotateZ.pick_id()) {
+            let position = Vec3::from(vertex.position);
+            // the Z ring lies around the Z axis, within its tube's thickness of the XY plane
+            assert!(position.z.abs() <= 0.015 + 1e-4);
+            assert!((position.truncate().length() - 1.0).abs() <= 0.015 + 1e-4);
+        }
+    }
+
+    #[test]
+    fn grid_params_invert_the_view_projection() {
+        let view_projection = Mat4::perspective_rh(1.0, 16.0 / 9.0, 0.1, 100.0)
+            * Mat4::look_at_rh(Vec3::new(0.0, 5.0, 10.0), Vec3::ZERO, Vec3::Y);
+        let params = grid_params(&GridSettings::default(), &view_projection, Vec3::new(0.0, 5.0, 10.0));
+        assert!((params.inverse_view_projection * params.view_projection).abs_diff_eq(Mat4::IDENTITY, 1e-4));
+        assert_eq!(params.camera_position, Vec4::new(0.0, 5.0, 10.0, 1.0));
+    }
+
+    #[test]
+    fn grid_params_follow_the_settings() {
+        let settings = GridSettings {
+            cell_size: 0.5,
+            major_interval: 4.0,
+            fade_distance: 30.0,
+            line_color: Vec4::ONE
+        };
+        let params = grid_params(&settings, &Mat4::IDENTITY, Vec3::ZERO);
+        assert_eq!((params.cell_size, params.major_interval, params.fade_distance), (0.5, 4.0, 30.0));
+        assert_eq!(params.line_color, Vec4::ONE);
+    }
+}
@@ -0,0 +1,297 @@
+use std::cell::RefCell;
+use std::fmt::{Debug, Formatter};
+use std::rc::Rc;
+
+use ash::vk;
+use ash::vk::Handle;
+use gpu_allocator::MemoryLocation;
+use api_types::device::{DeviceResource, DeviceWrapper};
+use api_types::format_selector::HDR_COLOR_FORMATS;
+use api_types::image::{ImageCreateInfo, ImageType};
+use api_types::upload_buffer::DynamicUploadBuffer;
+
+use context::render_context::RenderContext;
+use context::vulkan_render_context::VulkanRenderContext;
+use framegraph::attachment::AttachmentReference;
+use framegraph::binding::{BindingInfo, BindingType, BufferBindingInfo, ImageBindingInfo, ResourceBinding};
+use framegraph::uniform_layout::UniformBlock;
+use framegraph::graphics_pass_node::GraphicsPassNode;
+use framegraph::pass_type::PassType;
+use framegraph::pipeline::{BlendType, DepthStencilType, PipelineDescription, RasterizationState};
+use framegraph::shader;
+use framegraph::shader::Shader;
+use profiling::enter_span;
+
+/// The formats scenes are rendered in before being resolved to the swapchain, in order of
+/// preference
+pub const SCENE_FORMATS: &[vk::Format] = HDR_COLOR_FORMATS;
+
+/// How linear scene color is written to the swapchain image
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum OutputEncoding {
+    /// The swapchain format is sRGB, so color is encoded when it's written
+    Linear,
+    /// sRGB is encoded by the shader, for UNORM formats in the SRGB_NONLINEAR color space
+    Srgb,
+    /// BT.2020 primaries with the PQ transfer function
+    Hdr10,
+    /// Linear BT.709 primaries where 1.0 is 80 nits, with values above it for HDR
+    ScRgb
+}
+
+impl OutputEncoding {
+    pub fn for_swapchain(format: vk::Format, color_space: vk::ColorSpaceKHR) -> Self {
+        match color_space {
+            vk::ColorSpaceKHR::HDR10_ST2084_EXT => OutputEncoding::Hdr10,
+            vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT => OutputEncoding::ScRgb,
+            _ => match format {
+                vk::Format::R8G8B8A8_SRGB | vk::Format::B8G8R8A8_SRGB | vk::Format::A8B8G8R8_SRGB_PACK32 => OutputEncoding::Linear,
+                _ => OutputEncoding::Srgb
+            }
+        }
+    }
+
+    pub fn is_hdr(&self) -> bool {
+        matches!(self, OutputEncoding::Hdr10 | OutputEncoding::ScRgb)
+    }
+}
+
+/// Compresses scene color into the displayable range for SDR outputs; HDR outputs aren't tonemapped
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Tonemap {
+    None,
+    Reinhard,
+    Aces
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct OutputSettings {
+    pub tonemap: Tonemap,
+    /// Scene color is multiplied by this before tonemapping
+    pub exposure: f32,
+    /// How bright the scene's 1.0 is in nits on HDR outputs
+    pub paper_white: f32
+}
+
+impl Default for OutputSettings {
+    fn default() -> Self {
+        OutputSettings {
+            tonemap: Tonemap::None,
+            exposure: 1.0,
+            paper_white: 200.0
+        }
+    }
+}
+
+impl OutputSettings {
+    /// Whether a scene in `encoding` can be rendered straight to the swapchain without a final output pass
+    pub fn is_passthrough(&self, encoding: OutputEncoding) -> bool {
+        encoding == OutputEncoding::Linear && self.tonemap == Tonemap::None && self.exposure == 1.0
+    }
+}
+
+// matches the Output uniform in final_output.frag
+#[repr(C)]
+#[derive(UniformBlock)]
+struct OutputUniform {
+    encoding: u32,
+    tonemap: u32,
+    exposure: f32,
+    paper_white: f32
+}
+
+/// Resolves a linear scene image into the swapchain image, converting it for the swapchain's
+/// format and color space
+pub struct FinalOutput {
+    vertex_shader: Rc<RefCell<Shader>>,
+    fragment_shader: Rc<RefCell<Shader>>
+}
+
+impl Debug for FinalOutput {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FinalOutput")
+            .finish()
+    }
+}
+
+impl FinalOutput {
+    pub fn new(device: Rc<RefCell<DeviceWrapper>>) -> FinalOutput {
+        let vert_shader = Rc::new(RefCell::new(
+            shader::create_shader_module_from_bytes(device.clone(), "final_output-vert", include_bytes!(concat!(env!("OUT_DIR"), "/shaders/final_output-vert.spv")))));
+        let frag_shader = Rc::new(RefCell::new(
+            shader::create_shader_module_from_bytes(device.clone(), "final_output-frag", include_bytes!(concat!(env!("OUT_DIR"), "/shaders/final_output-frag.spv")))));
+
+        FinalOutput {
+            vertex_shader: vert_shader,
+            fragment_shader: frag_shader
+        }
+    }
+
+    /// A sampled image in the first of SCENE_FORMATS the device supports, for passes to render
+    /// into before generate_pass resolves it. It can also be rendered to, cleared or blitted to
+    /// like a swapchain image, and can be smaller than the swapchain image it's resolved to.
+    /// Shaders storing to it must declare the image's format
+    pub fn create_scene_target(device: Rc<RefCell<DeviceWrapper>>, extent: vk::Extent2D, name: &str) -> Rc<RefCell<DeviceResource>> {
+        let usage = vk::ImageUsageFlags::COLOR_ATTACHMENT |
+            vk::ImageUsageFlags::SAMPLED |
+            vk::ImageUsageFlags::STORAGE |
+            vk::ImageUsageFlags::TRANSFER_SRC |
+            vk::ImageUsageFlags::TRANSFER_DST;
+        let format = device.borrow().formats().require(
+            SCENE_FORMATS,
+            usage,
+            vk::FormatFeatureFlags::BLIT_SRC | vk::FormatFeatureFlags::BLIT_DST,
+            name);
+        let create_info = ImageCreateInfo::new(
+            vk::ImageCreateInfo::builder()
+                .image_type(vk::ImageType::TYPE_2D)
+                .format(format)
+                .initial_layout(vk::ImageLayout::UNDEFINED)
+                .extent(vk::Extent3D {
+                    width: extent.width,
+                    height: extent.height,
+                    depth: 1
+                })
+                .samples(vk::SampleCountFlags::TYPE_1)
+                .usage(usage)
+                .mip_levels(1)
+                .array_layers(1)
+                .build(),
+            name.to_string(),
+            ImageType::Color);
+
+        let mut image = DeviceWrapper::create_image(
+            device.clone(),
+            &create_info,
+            MemoryLocation::GpuOnly);
+
+        let sampler = unsafe {
+            // the scene is filtered when it's rendered below the swapchain's resolution and
+            // upscaled by the resolve, and sampled at texel centers otherwise
+            let sampler_create = vk::SamplerCreateInfo::builder()
+                .mag_filter(vk::Filter::LINEAR)
+                .min_filter(vk::Filter::LINEAR)
+                .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+                .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .build();
+
+            device.borrow().get().create_sampler(&sampler_create, None)
+                .expect("Failed to create scene target sampler")
+        };
+        device.borrow().set_debug_name(vk::ObjectType::SAMPLER, sampler.as_raw(), &format!("{}_sampler", name));
+        image.get_image_mut().sampler = Some(sampler);
+
+        Rc::new(RefCell::new(image))
+    }
+
+    /// Generates a pass writing `scene`, which must have a sampler, to every texel of
+    /// `swapchain_image` with `encoding`
+    pub fn generate_pass(
+        &self,
+        scene: Rc<RefCell<DeviceResource>>,
+        swapchain_image: AttachmentReference,
+        encoding: OutputEncoding,
+        settings: &OutputSettings,
+        upload_buffer: &mut DynamicUploadBuffer) -> PassType {
+
+        enter_span!(tracing::Level::TRACE, "Generate Final Output Pass");
+
+        assert!(scene.borrow().get_image().get_sampler().is_some(), "The final output scene image must have a sampler");
+
+        let extent = swapchain_image.resource_image.borrow().get_image().extent;
+
+        let output_value = OutputUniform {
+            encoding: match encoding {
+                OutputEncoding::Linear => 0,
+                OutputEncoding::Srgb => 1,
+                OutputEncoding::Hdr10 => 2,
+                OutputEncoding::ScRgb => 3
+            },
+            tonemap: match settings.tonemap {
+                Tonemap::None => 0,
+                Tonemap::Reinhard => 1,
+                Tonemap::Aces => 2
+            },
+            exposure: settings.exposure,
+            paper_white: settings.paper_white
+        };
+        let alignment = upload_buffer.get_uniform_alignment();
+        let output_offset = upload_buffer.push(std::slice::from_ref(&output_value), alignment);
+
+        let output_binding = ResourceBinding::new(
+            upload_buffer.get_buffer().clone(),
+            BindingInfo {
+                binding_type: BindingType::Buffer(BufferBindingInfo{
+                    offset: output_offset,
+                    range: std::mem::size_of::<OutputUniform>() as vk::DeviceSize,
+                    layout: Some(OutputUniform::layout()) }),
+                set: 0,
+                slot: 0,
+                stage: vk::PipelineStageFlags::FRAGMENT_SHADER,
+                access: vk::AccessFlags::SHADER_READ,
+            });
+
+        let scene_binding = ResourceBinding::new(
+            scene.clone(),
+            BindingInfo {
+                binding_type: BindingType::Image(ImageBindingInfo::new(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)),
+                set: 0,
+                slot: 1,
+                stage: vk::PipelineStageFlags::FRAGMENT_SHADER,
+                access: vk::AccessFlags::SHADER_READ
+            });
+
+        let pipeline_description = PipelineDescription::new(
+            vk::PipelineVertexInputStateCreateInfo::default(),
+            vec!(vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR),
+            RasterizationState::default(),
+            DepthStencilType::Disable,
+            BlendType::None,
+            "final_output",
+            self.vertex_shader.clone(),
+            self.fragment_shader.clone());
+
+        let viewport = vk::Viewport::builder()
+            .x(0.0)
+            .y(0.0)
+            .width(extent.width as f32)
+            .height(extent.height as f32)
+            .min_depth(0.0)
+            .max_depth(1.0)
+            .build();
+
+        let scissor = vk::Rect2D::builder()
+            .offset(vk::Offset2D{x: 0, y: 0})
+            .extent(vk::Extent2D{width: extent.width, height: extent.height})
+            .build();
+
+        let pass_node = GraphicsPassNode::builder("final_output".to_string())
+            .pipeline_description(pipeline_description)
+            .render_target(swapchain_image)
+            .read(output_binding)
+            .read(scene_binding)
+            .viewport(viewport)
+            .scissor(scissor)
+            .fill_commands(Box::new(
+                move |render_ctx: &VulkanRenderContext,
+                      command_buffer: &vk::CommandBuffer | {
+                    enter_span!(tracing::Level::TRACE, "Final Output");
+                    let _gpu_scope = render_ctx.get_profiler().scope("Final Output GPU", command_buffer);
+                    unsafe {
+                        render_ctx.get_device().borrow().get().cmd_draw(
+                            *command_buffer,
+                            3,
+                            1,
+                            0,
+                            0);
+                    }
+                }
+            ))
+            .build()
+            .expect("Failed to create final output passnode");
+
+        PassType::Graphics(pass_node)
+    }
+}
@@ -1,25 +1,25 @@
 use std::cell::RefCell;
-use std::ffi::c_void;
 use std::fmt::{Debug, Formatter};
 use std::rc::Rc;
 
 use ash::vk;
 use ash::vk::{DeviceSize, Handle};
-use gpu_allocator::MemoryLocation;
-use imgui::{DrawData, DrawVert, DrawIdx};
-use api_types::buffer::BufferCreateInfo;
+use imgui::{DrawCmd, DrawData, DrawVert, DrawIdx};
 use api_types::device::{DeviceResource, DeviceWrapper, ResourceType};
+use api_types::resource_state::ResourceState;
+use api_types::upload_buffer::DynamicUploadBuffer;
 
 use context::render_context::RenderContext;
 use context::vulkan_render_context::VulkanRenderContext;
 use framegraph::attachment::AttachmentReference;
 use framegraph::binding::{BindingInfo, BindingType, BufferBindingInfo, ImageBindingInfo, ResourceBinding};
+use framegraph::uniform_layout::UniformBlock;
 use framegraph::graphics_pass_node::GraphicsPassNode;
 use framegraph::pass_type::PassType;
-use framegraph::pipeline::{BlendType, DepthStencilType, PipelineDescription, RasterizationType};
+use framegraph::pipeline::{BlendType, DepthStencilType, PipelineDescription, RasterizationState};
 use framegraph::shader;
 use framegraph::shader::Shader;
-use profiling::{enter_gpu_span, enter_span};
+use profiling::enter_span;
 use util::image;
 
 const IMGUI_VERTEX_BINDING: vk::VertexInputBindingDescription = vk::VertexInputBindingDescription{
@@ -54,6 +54,15 @@ const IMGUI_VERTEX_ATTRIBUTES: [vk::VertexInputAttributeDescription; 3] = [
     }
 ];
 
+struct ImguiDrawCommand {
+    scissor: vk::Rect2D,
+    index_count: u32,
+    first_index: u32,
+    vertex_offset: i32
+}
+
+#[repr(C)]
+#[derive(UniformBlock)]
 pub struct DisplayBuffer {
     scale: [f32; 2],
     pos: [f32; 2]
@@ -133,7 +142,8 @@ impl ImguiRender {
 
         font_texture.get_image_mut().sampler = Some(font_sampler);
 
-        // ugly way to update the font_texture layout bookkeeping after the copy completes
+        // let the framegraph know the font texture has already been transitioned, otherwise
+        // its first use would be treated as an initialization and discard the fonts
         if let Some(resolved_font_texture) = font_texture.resource_type.as_mut() {
             if let ResourceType::Image(font_texture_image) = resolved_font_texture {
                 font_texture_image.layout = vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL;
@@ -143,6 +153,11 @@ impl ImguiRender {
         } else {
             panic!("Font texture somehow not valid");
         }
+        device.borrow_mut().update_resource_state(font_texture.get_handle(), ResourceState {
+            access: vk::AccessFlags::SHADER_READ,
+            stage: vk::PipelineStageFlags::FRAGMENT_SHADER,
+            layout: Some(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+        });
 
         unsafe {
             // ensure we've waited for the font buffer -> image copy to be complete
@@ -159,229 +174,195 @@ impl ImguiRender {
         }
     }
 
-    pub fn generate_passes(
+    /// Generates a single pass drawing every draw list in `draw_data`. Vertex and index data
+    /// for all lists is streamed contiguously into the upload buffer and each draw command
+    /// sets its own scissor rect. Returns None when there is nothing to draw.
+    pub fn generate_pass(
         &self,
         draw_data: &DrawData,
         render_target: AttachmentReference,
-        device: Rc<RefCell<DeviceWrapper>>) -> Vec<PassType> {
+        upload_buffer: &mut DynamicUploadBuffer) -> Option<PassType> {
 
-        enter_span!(tracing::Level::TRACE, "Generate Imgui Passes");
+        enter_span!(tracing::Level::TRACE, "Generate Imgui Pass");
 
-        let mut pass_nodes: Vec<PassType> = Vec::new();
-        // one passnode per drawlist
-        pass_nodes.reserve(draw_data.draw_lists_count());
+        if draw_data.total_idx_count <= 0 {
+            return None;
+        }
 
-        // display data (scale and pos) is shared for all draw lists
-        let display_buffer = {
-            let display_create_info = BufferCreateInfo::new(
-                vk::BufferCreateInfo::builder()
-                    .size(std::mem::size_of::<DisplayBuffer>() as vk::DeviceSize)
-                    .usage(vk::BufferUsageFlags::UNIFORM_BUFFER)
-                    .build(),
-                "Imgui_display_buffer".to_string());
-            let display_buffer = DeviceWrapper::create_buffer(
-                device.clone(),
-                &display_create_info,
-                MemoryLocation::CpuToGpu);
-
-            device.borrow().update_buffer(&display_buffer, |mapped_memory: *mut c_void, _size: u64| {
-                let mut display_scale: [f32; 2] = [0.0, 0.0];
-                display_scale[0] = 2.0 / draw_data.display_size[0];
-                display_scale[1] = 2.0 / draw_data.display_size[1];
-
-                let mut display_pos: [f32; 2] = [0.0, 0.0];
-                display_pos[0] = -1.0 - draw_data.display_pos[0] * display_scale[0];
-                display_pos[1] = -1.0 - draw_data.display_pos[1] * display_scale[1];
-
-                let display_value = DisplayBuffer {
-                    scale: display_scale,
-                    pos: display_pos
-                };
+        let upload_resource = upload_buffer.get_buffer();
 
-                unsafe {
-                    core::ptr::copy_nonoverlapping(
-                        &display_value,
-                        mapped_memory as *mut DisplayBuffer,
-                        1);
-                }
-            });
+        // display data (scale and pos) is shared for all draw lists
+        let display_offset = {
+            let mut display_scale: [f32; 2] = [0.0, 0.0];
+            display_scale[0] = 2.0 / draw_data.display_size[0];
+            display_scale[1] = 2.0 / draw_data.display_size[1];
+
+            let mut display_pos: [f32; 2] = [0.0, 0.0];
+            display_pos[0] = -1.0 - draw_data.display_pos[0] * display_scale[0];
+            display_pos[1] = -1.0 - draw_data.display_pos[1] * display_scale[1];
+
+            let display_value = DisplayBuffer {
+                scale: display_scale,
+                pos: display_pos
+            };
 
-            Rc::new(RefCell::new(display_buffer))
+            let alignment = upload_buffer.get_uniform_alignment();
+            upload_buffer.push(std::slice::from_ref(&display_value), alignment)
         };
 
-
-        for draw_list in draw_data.draw_lists() {
-            let vtx_create = BufferCreateInfo::new(vk::BufferCreateInfo::builder()
-                                                       .size((draw_data.total_vtx_count as usize * std::mem::size_of::<DrawVert>()) as vk::DeviceSize)
-                                                       .usage(vk::BufferUsageFlags::VERTEX_BUFFER)
-                                                       .sharing_mode(vk::SharingMode::EXCLUSIVE)
-                                                       .build(),
-                                                   "imgui_vtx_buffer".to_string());
-
-            let vtx_buffer = Rc::new(RefCell::new(DeviceWrapper::create_buffer(
-                device.clone(),
-                &vtx_create,
-                MemoryLocation::CpuToGpu)));
-            let vtx_data = draw_list.vtx_buffer();
-            device.borrow().update_buffer(&vtx_buffer.borrow(), |mapped_memory: *mut c_void, _size: u64| {
+        let extent = render_target.resource_image.borrow().get_image().extent;
+
+        // copy every draw list into one contiguous vertex and index range, and resolve
+        // each draw command to a scissor rect and offsets into those ranges
+        let vtx_size = (draw_data.total_vtx_count as usize * std::mem::size_of::<DrawVert>()) as vk::DeviceSize;
+        let (vtx_offset, vtx_ptr) = upload_buffer.allocate(vtx_size, std::mem::size_of::<DrawVert>() as vk::DeviceSize);
+        let idx_size = (draw_data.total_idx_count as usize * std::mem::size_of::<DrawIdx>()) as vk::DeviceSize;
+        let (idx_offset, idx_ptr) = upload_buffer.allocate(idx_size, std::mem::size_of::<DrawIdx>() as vk::DeviceSize);
+
+        let mut draw_commands: Vec<ImguiDrawCommand> = Vec::new();
+        {
+            let clip_offset = draw_data.display_pos;
+            let clip_scale = draw_data.framebuffer_scale;
+            let mut global_vtx_offset: usize = 0;
+            let mut global_idx_offset: usize = 0;
+            for draw_list in draw_data.draw_lists() {
+                let vtx_data = draw_list.vtx_buffer();
+                let idx_data = draw_list.idx_buffer();
                 unsafe {
                     core::ptr::copy_nonoverlapping(
                         vtx_data.as_ptr(),
-                        mapped_memory as *mut DrawVert,
-                        vtx_data.len()
-                    )
+                        (vtx_ptr as *mut DrawVert).add(global_vtx_offset),
+                        vtx_data.len());
+                    core::ptr::copy_nonoverlapping(
+                        idx_data.as_ptr(),
+                        (idx_ptr as *mut DrawIdx).add(global_idx_offset),
+                        idx_data.len());
                 }
-            });
-
-            let idx_create = BufferCreateInfo::new(vk::BufferCreateInfo::builder()
-                                                       .size((draw_data.total_idx_count as usize * std::mem::size_of::<DrawIdx>()) as vk::DeviceSize)
-                                                       .usage(vk::BufferUsageFlags::INDEX_BUFFER)
-                                                       .sharing_mode(vk::SharingMode::EXCLUSIVE)
-                                                       .build(),
-                                                   "imgui_idx_buffer".to_string());
 
-            let idx_buffer = Rc::new(RefCell::new(DeviceWrapper::create_buffer(
-                device.clone(),
-                &idx_create,
-                MemoryLocation::CpuToGpu)));
+                for command in draw_list.commands() {
+                    if let DrawCmd::Elements { count, cmd_params } = command {
+                        let clip_rect = cmd_params.clip_rect;
+                        let min_x = ((clip_rect[0] - clip_offset[0]) * clip_scale[0]).max(0.0);
+                        let min_y = ((clip_rect[1] - clip_offset[1]) * clip_scale[1]).max(0.0);
+                        let max_x = ((clip_rect[2] - clip_offset[0]) * clip_scale[0]).min(extent.width as f32);
+                        let max_y = ((clip_rect[3] - clip_offset[1]) * clip_scale[1]).min(extent.height as f32);
+                        if max_x <= min_x || max_y <= min_y {
+                            continue;
+                        }
 
-            let idx_data = draw_list.idx_buffer();
-            device.borrow().update_buffer(&idx_buffer.borrow(), |mapped_memory: *mut c_void, _size: u64| {
-                unsafe {
-                    core::ptr::copy_nonoverlapping(
-                        idx_data.as_ptr(),
-                        mapped_memory as *mut DrawIdx,
-                        idx_data.len()
-                    )
+                        draw_commands.push(ImguiDrawCommand {
+                            scissor: vk::Rect2D::builder()
+                                .offset(vk::Offset2D{x: min_x as i32, y: min_y as i32})
+                                .extent(vk::Extent2D{width: (max_x - min_x) as u32, height: (max_y - min_y) as u32})
+                                .build(),
+                            index_count: count as u32,
+                            first_index: (global_idx_offset + cmd_params.idx_offset) as u32,
+                            vertex_offset: (global_vtx_offset + cmd_params.vtx_offset) as i32
+                        });
+                    }
                 }
+
+                global_vtx_offset += vtx_data.len();
+                global_idx_offset += idx_data.len();
+            }
+        }
+
+        let font_binding = ResourceBinding::new(
+            self.font_texture.clone(),
+            BindingInfo {
+                binding_type: BindingType::Image(ImageBindingInfo::new(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)),
+                set: 0,
+                slot: 1,
+                stage: vk::PipelineStageFlags::FRAGMENT_SHADER,
+                access: vk::AccessFlags::SHADER_READ
             });
 
-            let idx_length = idx_data.len() as u32;
-
-            let font_binding = ResourceBinding {
-                resource: self.font_texture.clone(),
-                binding_info: BindingInfo {
-                    binding_type: BindingType::Image(ImageBindingInfo{
-                        layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL
-                    }),
-                    set: 0,
-                    slot: 1,
-                    stage: vk::PipelineStageFlags::FRAGMENT_SHADER,
-                    access: vk::AccessFlags::SHADER_READ
-                }
-            };
+        let vertex_input = vk::PipelineVertexInputStateCreateInfo::builder()
+            .vertex_binding_descriptions(std::slice::from_ref(&IMGUI_VERTEX_BINDING))
+            .vertex_attribute_descriptions(&IMGUI_VERTEX_ATTRIBUTES)
+            .build();
+
+        let dynamic_states = vec!(vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR);
+
+        let pipeline_description = PipelineDescription::new(
+            vertex_input,
+            dynamic_states,
+            RasterizationState::default(),
+            DepthStencilType::Disable,
+            BlendType::Alpha,
+            "imgui",
+            self.vertex_shader.clone(),
+            self.fragment_shader.clone());
+
+        let display_binding = ResourceBinding::new(
+            upload_resource.clone(),
+            BindingInfo {
+                binding_type: BindingType::Buffer(BufferBindingInfo{
+                    offset: display_offset,
+                    range: std::mem::size_of::<DisplayBuffer>() as vk::DeviceSize,
+                    layout: Some(DisplayBuffer::layout()) }),
+                set: 0,
+                slot: 0,
+                stage: vk::PipelineStageFlags::VERTEX_SHADER,
+                access: vk::AccessFlags::SHADER_READ,
+            });
 
-            let vertex_input = vk::PipelineVertexInputStateCreateInfo::builder()
-                .vertex_binding_descriptions(std::slice::from_ref(&IMGUI_VERTEX_BINDING))
-                .vertex_attribute_descriptions(&IMGUI_VERTEX_ATTRIBUTES)
+        let (viewport, scissor) = {
+            let v = vk::Viewport::builder()
+                .x(0.0)
+                .y(0.0)
+                .width(extent.width as f32)
+                .height(extent.height as f32)
+                .min_depth(0.0)
+                .max_depth(1.0)
                 .build();
 
-            let dynamic_states = vec!(vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR);
-
-            let pipeline_description = PipelineDescription::new(
-                vertex_input,
-                dynamic_states,
-                RasterizationType::Standard,
-                DepthStencilType::Disable,
-                BlendType::Transparent,
-                "imgui",
-                self.vertex_shader.clone(),
-                self.fragment_shader.clone());
-
-            let display_binding = ResourceBinding {
-                resource: display_buffer.clone(),
-                binding_info: BindingInfo {
-                    binding_type: BindingType::Buffer(BufferBindingInfo{
-                        offset: 0,
-                        range: std::mem::size_of::<DisplayBuffer>() as vk::DeviceSize }),
-                    set: 0,
-                    slot: 0,
-                    stage: vk::PipelineStageFlags::VERTEX_SHADER,
-                    access: vk::AccessFlags::SHADER_READ,
-                },
-            };
+            let s = vk::Rect2D::builder()
+                .offset(vk::Offset2D{x: 0, y: 0})
+                .extent(vk::Extent2D{width: extent.width, height: extent.height})
+                .build();
 
-            let (viewport, scissor) = {
-                let extent = render_target.resource_image.borrow().get_image().extent;
-                let v = vk::Viewport::builder()
-                    .x(0.0)
-                    .y(0.0)
-                    .width(extent.width as f32)
-                    .height(extent.height as f32)
-                    .min_depth(0.0)
-                    .max_depth(1.0)
-                    .build();
-
-                let s = vk::Rect2D::builder()
-                    .offset(vk::Offset2D{x: 0, y: 0})
-                    .extent(vk::Extent2D{width: extent.width, height: extent.height})
-                    .build();
-
-                (v, s)
-            };
+            (v, s)
+        };
 
-            let pass_node = GraphicsPassNode::builder("imgui".to_string())
-                .pipeline_description(pipeline_description)
-                .render_target(render_target.clone())
-                .read(font_binding)
-                .read(display_binding)
-                .tag(idx_buffer.clone())
-                .tag(vtx_buffer.clone())
-                .viewport(viewport)
-                .scissor(scissor)
-                .fill_commands(Box::new(
-                    move |render_ctx: &VulkanRenderContext,
-                          command_buffer: &vk::CommandBuffer | {
-                        unsafe {
-                            enter_span!(tracing::Level::TRACE, "Imgui Draw");
-                            // let x = render_ctx.get_device().borrow().get()
-                            let device = render_ctx.get_device();
-                            let borrowed_device = device.borrow();
-                            enter_gpu_span!("Imgui Draw GPU", "UI", borrowed_device.get(), command_buffer, vk::PipelineStageFlags::ALL_GRAPHICS);
-                            // set vertex buffer
-                            {
-                                if let ResourceType::Buffer(vb) = &vtx_buffer.borrow().resource_type.as_ref().unwrap() {
-                                    render_ctx.get_device().borrow().get().cmd_bind_vertex_buffers(
-                                        *command_buffer,
-                                        0,
-                                         &[vb.buffer],
-                                        &[0 as vk::DeviceSize]
-                                    );
-                                } else {
-                                    panic!("Invalid vertex buffer for Imgui draw");
-                                }
-                            }
-
-                            // set index buffer
-                            {
-                                if let ResourceType::Buffer(ib) = &idx_buffer.borrow().resource_type.as_ref().unwrap() {
-                                    render_ctx.get_device().borrow().get().cmd_bind_index_buffer(
-                                        *command_buffer,
-                                        ib.buffer,
-                                        0 as vk::DeviceSize,
-                                        vk::IndexType::UINT16
-                                    );
-                                } else {
-                                    panic!("Invalid index buffer for Imgui draw");
-                                }
-                            }
-
-                            render_ctx.get_device().borrow().get().cmd_draw_indexed(
+        let pass_node = GraphicsPassNode::builder("imgui".to_string())
+            .pipeline_description(pipeline_description)
+            .render_target(render_target.clone())
+            .read(font_binding)
+            .read(display_binding)
+            .vertex_buffer(upload_resource.clone(), vtx_offset)
+            .index_buffer(upload_resource.clone(), idx_offset, vk::IndexType::UINT16)
+            .viewport(viewport)
+            .scissor(scissor)
+            .fill_commands(Box::new(
+                move |render_ctx: &VulkanRenderContext,
+                      command_buffer: &vk::CommandBuffer | {
+                    unsafe {
+                        enter_span!(tracing::Level::TRACE, "Imgui Draw");
+                        let device = render_ctx.get_device();
+                        let borrowed_device = device.borrow();
+                        let _gpu_scope = render_ctx.get_profiler().scope("Imgui Draw GPU", command_buffer);
+
+                        for draw_command in &draw_commands {
+                            borrowed_device.get().cmd_set_scissor(
                                 *command_buffer,
-                                idx_length,
-                                1,
-                                0,
                                 0,
+                                std::slice::from_ref(&draw_command.scissor));
+
+                            borrowed_device.get().cmd_draw_indexed(
+                                *command_buffer,
+                                draw_command.index_count,
+                                1,
+                                draw_command.first_index,
+                                draw_command.vertex_offset,
                                 0);
                         }
                     }
-                ))
-                .build()
-                .expect("Failed to create imgui passnode");
-
-            pass_nodes.push(PassType::Graphics(pass_node));
-        }
+                }
+            ))
+            .build()
+            .expect("Failed to create imgui passnode");
 
-        pass_nodes
+        Some(PassType::Graphics(pass_node))
     }
 }
@@ -2,6 +2,16 @@ pub mod blit;
 pub mod imgui_draw;
 pub mod blur;
 pub mod clear;
+pub mod debug_lines;
+pub mod editor;
+pub mod final_output;
+pub mod picking;
+pub mod recorder;
+pub mod reduction;
+pub mod resolve;
+pub mod ssao;
+pub mod taa;
+pub mod text;
 
 extern crate imgui;
 
@@ -0,0 +1,386 @@
+use std::cell::RefCell;
+use std::fmt::{Debug, Formatter};
+use std::rc::Rc;
+
+use ash::vk;
+use glam::Mat4;
+use gpu_allocator::MemoryLocation;
+use api_types::buffer::BufferCreateInfo;
+use api_types::device::{DeviceResource, DeviceWrapper};
+use api_types::upload_buffer::DynamicUploadBuffer;
+
+use context::render_context::RenderContext;
+use context::vulkan_render_context::VulkanRenderContext;
+use framegraph::attachment::AttachmentReference;
+use framegraph::binding::{BindingInfo, BindingType, BufferBindingInfo, ResourceBinding};
+use framegraph::copy_pass_node::CopyPassNode;
+use framegraph::graphics_pass_node::GraphicsPassNode;
+use framegraph::pass_type::PassType;
+use framegraph::pipeline::{BlendType, DepthStencilType, PipelineDescription, RasterizationState};
+use framegraph::shader;
+use framegraph::shader::Shader;
+use framegraph::uniform_layout::UniformBlock;
+use profiling::enter_span;
+
+/// The format of pick targets, which hold the id of whatever was drawn over each texel
+pub const PICK_ID_FORMAT: vk::Format = vk::Format::R32_UINT;
+
+/// The id of texels nothing pickable was drawn over. Pick targets should be cleared to it with
+/// clear::clear_ids each frame
+pub const NO_PICK_ID: u32 = 0;
+
+// matches the ObjectIdParams uniform in object_id.vert
+#[repr(C)]
+#[derive(UniformBlock)]
+struct ObjectIdParams {
+    model_view_proj: Mat4,
+    id: u32,
+    padding0: u32,
+    padding1: u32,
+    padding2: u32
+}
+
+/// A mesh for ObjectIdRender to draw into a pick target. Positions are read from location 0
+/// as three floats, so the mesh's vertex input can usually be passed as is
+pub struct PickableMesh<'a> {
+    pub vertex_buffer: Rc<RefCell<DeviceResource>>,
+    /// Like any vertex input given to a PipelineDescription, the vertex descriptions must
+    /// outlive the frame
+    pub vertex_binding: &'a vk::VertexInputBindingDescription,
+    pub vertex_attributes: &'a [vk::VertexInputAttributeDescription],
+    pub index_buffer: Option<(Rc<RefCell<DeviceResource>>, vk::IndexType)>,
+    pub topology: vk::PrimitiveTopology,
+    /// The number of indices drawn, or vertices if there's no index buffer
+    pub count: u32,
+    pub model_view_proj: Mat4,
+    pub id: u32
+}
+
+/// Draws meshes' object ids into a pick target, the mesh pass's counterpart for picking
+pub struct ObjectIdRender {
+    vertex_shader: Rc<RefCell<Shader>>,
+    fragment_shader: Rc<RefCell<Shader>>,
+    /// How meshes are tested against the depth target, e.g. DepthStencilType::REVERSE_Z for
+    /// reverse-Z depth
+    pub depth_test: DepthStencilType
+}
+
+impl Debug for ObjectIdRender {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ObjectIdRender")
+            .finish()
+    }
+}
+
+impl ObjectIdRender {
+    pub fn new(device: Rc<RefCell<DeviceWrapper>>) -> Self {
+        let vert_shader = Rc::new(RefCell::new(
+            shader::create_shader_module_from_bytes(device.clone(), "object_id-vert", include_bytes!(concat!(env!("OUT_DIR"), "/shaders/object_id-vert.spv")))));
+        let frag_shader = Rc::new(RefCell::new(
+            shader::create_shader_module_from_bytes(device.clone(), "object_id-frag", include_bytes!(concat!(env!("OUT_DIR"), "/shaders/object_id-frag.spv")))));
+
+        ObjectIdRender {
+            vertex_shader: vert_shader,
+            fragment_shader: frag_shader,
+            depth_test: DepthStencilType::Enable
+        }
+    }
+
+    /// Generates a pass per mesh writing its id into `pick_target`, which must be a
+    /// PICK_ID_FORMAT image. Passing the depth target the scene was drawn with means only the
+    /// surfaces visible in the scene are written. `viewport` should match the one the scene
+    /// was drawn with
+    pub fn generate_passes(
+        &self,
+        meshes: &[PickableMesh],
+        viewport: vk::Viewport,
+        pick_target: AttachmentReference,
+        depth_target: AttachmentReference,
+        upload_buffer: &mut DynamicUploadBuffer) -> Vec<PassType> {
+
+        enter_span!(tracing::Level::TRACE, "Generate Object Id Passes");
+
+        assert!(pick_target.format == PICK_ID_FORMAT,
+            "Object ids are written to {:?} pick targets, not {:?}", PICK_ID_FORMAT, pick_target.format);
+
+        let scissor = {
+            let extent = pick_target.resource_image.borrow().get_image().extent;
+            vk::Rect2D::builder()
+                .offset(vk::Offset2D{x: 0, y: 0})
+                .extent(vk::Extent2D{width: extent.width, height: extent.height})
+                .build()
+        };
+
+        meshes.iter().map(|mesh| {
+            let params = ObjectIdParams {
+                model_view_proj: mesh.model_view_proj,
+                id: mesh.id,
+                padding0: 0,
+                padding1: 0,
+                padding2: 0
+            };
+            let params_offset = {
+                let alignment = upload_buffer.get_uniform_alignment();
+                upload_buffer.push(std::slice::from_ref(&params), alignment)
+            };
+            let params_binding = ResourceBinding::new(
+                upload_buffer.get_buffer(),
+                BindingInfo {
+                    binding_type: BindingType::Buffer(BufferBindingInfo {
+                        offset: params_offset,
+                        range: std::mem::size_of::<ObjectIdParams>() as vk::DeviceSize,
+                        layout: Some(ObjectIdParams::layout())
+                    }),
+                    set: 0,
+                    slot: 0,
+                    stage: vk::PipelineStageFlags::VERTEX_SHADER,
+                    access: vk::AccessFlags::UNIFORM_READ
+                });
+
+            let vertex_input = vk::PipelineVertexInputStateCreateInfo::builder()
+                .vertex_binding_descriptions(std::slice::from_ref(mesh.vertex_binding))
+                .vertex_attribute_descriptions(mesh.vertex_attributes)
+                .build();
+
+            let pipeline_description = PipelineDescription::new(
+                vertex_input,
+                vec!(vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR),
+                RasterizationState::default(),
+                self.depth_test,
+                BlendType::None,
+                "object_id",
+                self.vertex_shader.clone(),
+                self.fragment_shader.clone())
+                .topology(mesh.topology, false);
+
+            let mut pass_builder = GraphicsPassNode::builder("object_id".to_string())
+                .pipeline_description(pipeline_description)
+                .render_target(pick_target.clone())
+                .depth_target(depth_target.clone())
+                .read(params_binding)
+                .vertex_buffer(mesh.vertex_buffer.clone(), 0)
+                .viewport(viewport)
+                .scissor(scissor);
+            let indexed = mesh.index_buffer.is_some();
+            if let Some((index_buffer, index_type)) = &mesh.index_buffer {
+                pass_builder = pass_builder.index_buffer(index_buffer.clone(), 0, *index_type);
+            }
+
+            let count = mesh.count;
+            let pass_node = pass_builder
+                .fill_commands(Box::new(
+                    move |render_ctx: &VulkanRenderContext,
+                          command_buffer: &vk::CommandBuffer | {
+                        enter_span!(tracing::Level::TRACE, "Object Id Draw");
+                        let _gpu_scope = render_ctx.get_profiler().scope("Object Id GPU", command_buffer);
+                        let device = render_ctx.get_device();
+                        let borrowed_device = device.borrow();
+                        unsafe {
+                            match indexed {
+                                true => borrowed_device.get().cmd_draw_indexed(*command_buffer, count, 1, 0, 0, 0),
+                                false => borrowed_device.get().cmd_draw(*command_buffer, count, 1, 0, 0)
+                            }
+                        }
+                    }
+                ))
+                .build()
+                .expect("Failed to create object id passnode");
+
+            PassType::Graphics(pass_node)
+        }).collect()
+    }
+}
+
+struct PickSlot {
+    buffer: Rc<RefCell<DeviceResource>>,
+    // whether a pick was copied into this slot since it was last read
+    pending: bool
+}
+
+/// Reads back the id at a texel of a pick target, e.g. to find what's under the cursor. Like
+/// DynamicUploadBuffer, there's one host-visible staging buffer per frame in flight, and each is
+/// only read once the fence of the frame that copied into it has signaled, so the picked id
+/// arrives `frames_in_flight` frames after the pick
+pub struct IdPicker {
+    device: Rc<RefCell<DeviceWrapper>>,
+    slots: Vec<PickSlot>,
+    current_slot: usize,
+    picked: u32
+}
+
+impl Debug for IdPicker {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("IdPicker")
+            .field("slots", &self.slots.len())
+            .field("current slot", &self.current_slot)
+            .field("picked", &self.picked)
+            .finish()
+    }
+}
+
+impl IdPicker {
+    pub fn new(device: Rc<RefCell<DeviceWrapper>>, frames_in_flight: u32) -> Self {
+        assert!(frames_in_flight > 0, "IdPicker requires at least one frame in flight");
+
+        let slots: Vec<PickSlot> = (0..frames_in_flight).map(|i| {
+            let create_info = vk::BufferCreateInfo::builder()
+                .size(std::mem::size_of::<u32>() as vk::DeviceSize)
+                .usage(vk::BufferUsageFlags::TRANSFER_DST)
+                .sharing_mode(vk::SharingMode::EXCLUSIVE)
+                .build();
+            let buffer = DeviceWrapper::create_buffer(
+                device.clone(),
+                &BufferCreateInfo::new(create_info, format!("picker_staging_{}", i)),
+                MemoryLocation::GpuToCpu);
+
+            PickSlot {
+                buffer: Rc::new(RefCell::new(buffer)),
+                pending: false
+            }
+        }).collect();
+
+        IdPicker {
+            device,
+            slots,
+            current_slot: 0,
+            picked: NO_PICK_ID
+        }
+    }
+
+    /// Switches to the staging buffer for `frame_index`, reading back the pick it was last
+    /// used for. `fence` must be the fence signaled by the last submission which used this
+    /// frame index; it is waited on before the pick is read
+    pub fn begin_frame(&mut self, frame_index: u32, fence: vk::Fence) {
+        enter_span!(tracing::Level::TRACE, "Picker readback");
+        self.current_slot = frame_index as usize % self.slots.len();
+        if !self.slots[self.current_slot].pending {
+            return;
+        }
+
+        unsafe {
+            self.device.borrow().get().wait_for_fences(
+                std::slice::from_ref(&fence),
+                true,
+                u64::MAX)
+                .expect("Failed to wait for IdPicker staging fence");
+        }
+        self.picked = self.read_slot(self.current_slot);
+        self.slots[self.current_slot].pending = false;
+    }
+
+    /// Returns a copy pass reading back the id at texel (`x`, `y`) of `pick_target`, which
+    /// needs TRANSFER_SRC usage, or None if the texel is outside of it or this frame already
+    /// picked. The copy writes nothing the rest of the graph reads, so it must be added with
+    /// `Frame::add_root` to survive culling
+    pub fn generate_pass(
+        &mut self,
+        pick_target: Rc<RefCell<DeviceResource>>,
+        x: u32,
+        y: u32) -> Option<PassType> {
+
+        let extent = pick_target.borrow().get_image().extent;
+        if x >= extent.width || y >= extent.height {
+            return None;
+        }
+
+        let slot = &mut self.slots[self.current_slot];
+        if slot.pending {
+            log::warn!(target: "picker", "Only one pick can be read back per frame; skipping pick at ({}, {})", x, y);
+            return None;
+        }
+        slot.pending = true;
+
+        let dest = slot.buffer.clone();
+        let pass_node = CopyPassNode::builder("id_pick".to_string())
+            .copy_src(pick_target.clone())
+            .copy_dst(dest.clone())
+            .fill_commands(Box::new(
+                move |render_ctx: &VulkanRenderContext,
+                      command_buffer: &vk::CommandBuffer| {
+
+                    enter_span!(tracing::Level::TRACE, "Id pick");
+                    let device = render_ctx.get_device();
+                    let borrowed_device = device.borrow();
+
+                    let resolved_source = pick_target.borrow();
+                    let resolved_dest = dest.borrow();
+                    let region = vk::BufferImageCopy::builder()
+                        .buffer_offset(0)
+                        .buffer_row_length(0)
+                        .buffer_image_height(0)
+                        .image_subresource(vk::ImageSubresourceLayers::builder()
+                            .aspect_mask(vk::ImageAspectFlags::COLOR)
+                            .mip_level(0)
+                            .base_array_layer(0)
+                            .layer_count(1)
+                            .build())
+                        .image_offset(vk::Offset3D { x: x as i32, y: y as i32, z: 0 })
+                        .image_extent(vk::Extent3D {
+                            width: 1,
+                            height: 1,
+                            depth: 1
+                        })
+                        .build();
+
+                    unsafe {
+                        borrowed_device.get().cmd_copy_image_to_buffer(
+                            *command_buffer,
+                            resolved_source.get_image().image,
+                            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                            resolved_dest.get_buffer().buffer,
+                            std::slice::from_ref(&region));
+                    }
+            }))
+            .build()
+            .expect("Failed to create id pick passnode");
+
+        Some(PassType::Copy(pass_node))
+    }
+
+    /// The id most recently read back, NO_PICK_ID if nothing pickable was under the texel.
+    /// Gizmo handles can be told apart with editor::GizmoHandle::from_pick_id
+    pub fn get_picked_id(&self) -> u32 { self.picked }
+
+    fn read_slot(&self, slot_index: usize) -> u32 {
+        let buffer = self.slots[slot_index].buffer.borrow();
+        let allocation = buffer.allocation.as_ref().expect("Picker staging buffer has no allocation");
+
+        if !allocation.memory_properties().contains(vk::MemoryPropertyFlags::HOST_COHERENT) {
+            let mapped_range = unsafe {
+                vk::MappedMemoryRange::builder()
+                    .memory(allocation.memory())
+                    .size(vk::WHOLE_SIZE)
+                    .offset(allocation.offset())
+                    .build()
+            };
+            unsafe {
+                self.device.borrow().get().invalidate_mapped_memory_ranges(std::slice::from_ref(&mapped_range))
+                    .expect("Failed to invalidate picker staging memory");
+            }
+        }
+
+        let mapped = allocation.mapped_slice().expect("Picker staging buffer is not host-visible");
+        u32::from_ne_bytes(mapped[..4].try_into().expect("Picker staging buffer is too small"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use framegraph::pipeline::is_integer_format;
+    use framegraph::uniform_layout::{reflect_uniform_blocks, uniform_layout_mismatches};
+    use super::*;
+
+    #[test]
+    fn pick_ids_are_integers() {
+        // ids must be written exactly, so pick targets can't be normalized or blended
+        assert!(is_integer_format(PICK_ID_FORMAT));
+    }
+
+    #[test]
+    fn object_id_params_match_the_shader() {
+        let blocks = reflect_uniform_blocks(include_bytes!(concat!(env!("OUT_DIR"), "/shaders/object_id-vert.spv")));
+        let block = blocks.get(&(0, 0)).expect("object_id.vert has no uniform block at set 0, binding 0");
+        let mismatches = uniform_layout_mismatches(ObjectIdParams::layout(), block);
+        assert!(mismatches.is_empty(), "{}", mismatches.join("\n"));
+    }
+}
@@ -0,0 +1,314 @@
+use std::cell::RefCell;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::rc::Rc;
+use std::sync::mpsc;
+use std::thread::JoinHandle;
+use ash::vk;
+use gpu_allocator::MemoryLocation;
+use api_types::buffer::BufferCreateInfo;
+use api_types::device::{DeviceResource, DeviceWrapper};
+
+use context::render_context::RenderContext;
+use context::vulkan_render_context::VulkanRenderContext;
+use framegraph::copy_pass_node::CopyPassNode;
+use framegraph::pass_type::PassType;
+use profiling::enter_span;
+
+/// Where captured frames are written
+pub enum RecorderOutput {
+    /// Each captured frame is encoded as `{directory}/{prefix}_{index:06}.png`
+    ImageSequence {
+        directory: PathBuf,
+        prefix: String
+    },
+    /// Raw, tightly-packed RGBA8 frames are written to the stdin of the spawned process
+    /// (e.g. `ffmpeg -f rawvideo -pix_fmt rgba -s WxH -i - out.mp4`)
+    Pipe(Command)
+}
+
+struct CapturedFrame {
+    index: u64,
+    pixels: Vec<u8>
+}
+
+struct StagingSlot {
+    buffer: Rc<RefCell<DeviceResource>>,
+    // frame number and capture index of the copy written into this slot, if any
+    pending: Option<(u64, u64)>
+}
+
+/// Captures every Nth frame into a ring of host-visible staging buffers. Slots are only
+/// read back once the frame that wrote them can no longer be in flight, and encoding
+/// happens on a worker thread, so neither the GPU nor the render loop waits on a capture.
+pub struct FrameRecorder {
+    device: Rc<RefCell<DeviceWrapper>>,
+    slots: Vec<StagingSlot>,
+    next_slot: usize,
+    interval: u32,
+    frames_in_flight: u32,
+    frame_number: u64,
+    capture_count: u64,
+    extent: vk::Extent2D,
+    swizzle_bgra: bool,
+    sender: Option<mpsc::Sender<CapturedFrame>>,
+    worker: Option<JoinHandle<()>>
+}
+
+/// Whether frames of `format` need their red and blue channels swapped, or None if
+/// the format can't be recorded
+fn is_bgra(format: vk::Format) -> Option<bool> {
+    match format {
+        vk::Format::B8G8R8A8_UNORM | vk::Format::B8G8R8A8_SRGB => { Some(true) },
+        vk::Format::R8G8B8A8_UNORM | vk::Format::R8G8B8A8_SRGB => { Some(false) },
+        _ => { None }
+    }
+}
+
+fn write_frames(
+    receiver: mpsc::Receiver<CapturedFrame>,
+    output: RecorderOutput,
+    extent: vk::Extent2D) {
+
+    match output {
+        RecorderOutput::ImageSequence { directory, prefix } => {
+            std::fs::create_dir_all(&directory)
+                .expect("Failed to create frame recording directory");
+            for frame in receiver {
+                let path = directory.join(format!("{}_{:06}.png", prefix, frame.index));
+                image::save_buffer(
+                    &path,
+                    &frame.pixels,
+                    extent.width,
+                    extent.height,
+                    image::ExtendedColorType::Rgba8)
+                    .expect("Failed to save recorded frame");
+            }
+        },
+        RecorderOutput::Pipe(mut command) => {
+            let mut child: Child = command
+                .stdin(Stdio::piped())
+                .spawn()
+                .expect("Failed to spawn frame recording process");
+            {
+                let stdin = child.stdin.as_mut().expect("Frame recording process has no stdin");
+                for frame in receiver {
+                    if let Err(error) = stdin.write_all(&frame.pixels) {
+                        log::warn!(target: "recorder", "Frame recording process closed its input: {}", error);
+                        break;
+                    }
+                }
+            }
+            // closing stdin signals the end of the stream to the encoder
+            drop(child.stdin.take());
+            child.wait().expect("Failed to wait for frame recording process");
+        }
+    }
+}
+
+impl FrameRecorder {
+    /// `frames_in_flight` must match the number of frames the application records
+    /// before waiting on a previous frame's fence. Fails if `format` isn't an 8-bit
+    /// RGBA or BGRA format
+    pub fn new(
+        device: Rc<RefCell<DeviceWrapper>>,
+        extent: vk::Extent2D,
+        format: vk::Format,
+        interval: u32,
+        frames_in_flight: u32,
+        output: RecorderOutput) -> Result<Self, String> {
+        assert!(interval > 0, "Frame recording interval must be at least 1");
+
+        let swizzle_bgra = is_bgra(format)
+            .ok_or_else(|| format!("Unsupported format for frame recording: {:?}", format))?;
+        let buffer_size = (extent.width * extent.height * 4) as vk::DeviceSize;
+
+        // one more slot than frames in flight guarantees a free slot even when capturing every frame
+        let slots: Vec<StagingSlot> = (0..frames_in_flight + 1).map(|i| {
+            let create_info = vk::BufferCreateInfo::builder()
+                .size(buffer_size)
+                .usage(vk::BufferUsageFlags::TRANSFER_DST)
+                .sharing_mode(vk::SharingMode::EXCLUSIVE)
+                .build();
+            let buffer = DeviceWrapper::create_buffer(
+                device.clone(),
+                &BufferCreateInfo::new(create_info, format!("recorder_staging_{}", i)),
+                MemoryLocation::GpuToCpu);
+
+            StagingSlot {
+                buffer: Rc::new(RefCell::new(buffer)),
+                pending: None
+            }
+        }).collect();
+
+        let (sender, receiver) = mpsc::channel();
+        let worker = std::thread::spawn(move || {
+            write_frames(receiver, output, extent);
+        });
+
+        Ok(FrameRecorder {
+            device,
+            slots,
+            next_slot: 0,
+            interval,
+            frames_in_flight,
+            frame_number: 0,
+            capture_count: 0,
+            extent,
+            swizzle_bgra,
+            sender: Some(sender),
+            worker: Some(worker)
+        })
+    }
+
+    /// Call once per frame after waiting on the fence of the frame being reused.
+    /// Reads back any captures which have finished on the GPU and hands them to the encoder.
+    pub fn next_frame(&mut self) {
+        enter_span!(tracing::Level::TRACE, "Recorder readback");
+        self.frame_number += 1;
+
+        for slot_index in 0..self.slots.len() {
+            if let Some((frame_number, capture_index)) = self.slots[slot_index].pending {
+                if frame_number + self.frames_in_flight as u64 <= self.frame_number {
+                    self.read_slot(slot_index, capture_index);
+                    self.slots[slot_index].pending = None;
+                }
+            }
+        }
+    }
+
+    /// Returns a copy pass capturing `source` if the current frame should be recorded.
+    /// The copy writes nothing the rest of the graph reads, so it must be added with
+    /// `Frame::add_root` to survive culling.
+    pub fn generate_pass(
+        &mut self,
+        source: Rc<RefCell<DeviceResource>>) -> Option<PassType> {
+
+        // the encoder has exited, so there is nothing left to hand captures to
+        if self.sender.is_none() || self.frame_number % self.interval as u64 != 0 {
+            return None;
+        }
+
+        let slot_index = self.next_slot;
+        if self.slots[slot_index].pending.is_some() {
+            log::warn!(target: "recorder", "No free staging buffer; skipping capture of frame {}", self.frame_number);
+            return None;
+        }
+        self.next_slot = (self.next_slot + 1) % self.slots.len();
+        self.slots[slot_index].pending = Some((self.frame_number, self.capture_count));
+        self.capture_count += 1;
+
+        let dest = self.slots[slot_index].buffer.clone();
+        let extent = self.extent;
+        let pass_node = CopyPassNode::builder("recorder_capture".to_string())
+            .copy_src(source.clone())
+            .copy_dst(dest.clone())
+            .fill_commands(Box::new(
+                move |render_ctx: &VulkanRenderContext,
+                      command_buffer: &vk::CommandBuffer| {
+
+                    enter_span!(tracing::Level::TRACE, "Recorder capture");
+                    let device = render_ctx.get_device();
+                    let borrowed_device = device.borrow();
+                    let _gpu_scope = render_ctx.get_profiler().scope("Recorder capture GPU", command_buffer);
+
+                    let resolved_source = source.borrow();
+                    let resolved_dest = dest.borrow();
+                    let region = vk::BufferImageCopy::builder()
+                        .buffer_offset(0)
+                        .buffer_row_length(0)
+                        .buffer_image_height(0)
+                        .image_subresource(vk::ImageSubresourceLayers::builder()
+                            .aspect_mask(vk::ImageAspectFlags::COLOR)
+                            .mip_level(0)
+                            .base_array_layer(0)
+                            .layer_count(1)
+                            .build())
+                        .image_offset(vk::Offset3D::default())
+                        .image_extent(vk::Extent3D {
+                            width: extent.width,
+                            height: extent.height,
+                            depth: 1
+                        })
+                        .build();
+
+                    unsafe {
+                        borrowed_device.get().cmd_copy_image_to_buffer(
+                            *command_buffer,
+                            resolved_source.get_image().image,
+                            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                            resolved_dest.get_buffer().buffer,
+                            std::slice::from_ref(&region));
+                    }
+            }))
+            .build()
+            .expect("Failed to create Recorder passnode");
+
+        Some(PassType::Copy(pass_node))
+    }
+
+    fn read_slot(&mut self, slot_index: usize, capture_index: u64) {
+        let buffer = self.slots[slot_index].buffer.borrow();
+        let allocation = buffer.allocation.as_ref().expect("Recorder staging buffer has no allocation");
+
+        if !allocation.memory_properties().contains(vk::MemoryPropertyFlags::HOST_COHERENT) {
+            let mapped_range = unsafe {
+                vk::MappedMemoryRange::builder()
+                    .memory(allocation.memory())
+                    .size(vk::WHOLE_SIZE)
+                    .offset(allocation.offset())
+                    .build()
+            };
+            unsafe {
+                self.device.borrow().get().invalidate_mapped_memory_ranges(std::slice::from_ref(&mapped_range))
+                    .expect("Failed to invalidate recorder staging memory");
+            }
+        }
+
+        let frame_size = (self.extent.width * self.extent.height * 4) as usize;
+        let mapped = allocation.mapped_slice().expect("Recorder staging buffer is not host-visible");
+        let mut pixels = mapped[..frame_size].to_vec();
+        if self.swizzle_bgra {
+            for pixel in pixels.chunks_exact_mut(4) {
+                pixel.swap(0, 2);
+            }
+        }
+
+        if let Some(sender) = &self.sender {
+            let sent = sender.send(CapturedFrame {
+                index: capture_index,
+                pixels
+            });
+            if sent.is_err() {
+                log::error!(target: "recorder", "Frame recording worker exited unexpectedly; stopping recording");
+                self.sender = None;
+            }
+        }
+    }
+
+    /// Waits for the device, reads back all outstanding captures and waits for the encoder to finish
+    pub fn finish(mut self) {
+        unsafe {
+            self.device.borrow().get().device_wait_idle()
+                .expect("Failed to wait for device idle when finishing recording");
+        }
+
+        let mut pending: Vec<(usize, u64)> = self.slots.iter().enumerate()
+            .filter_map(|(i, slot)| slot.pending.map(|(_, capture_index)| (i, capture_index)))
+            .collect();
+        pending.sort_by_key(|(_, capture_index)| *capture_index);
+        for (slot_index, capture_index) in pending {
+            self.read_slot(slot_index, capture_index);
+            self.slots[slot_index].pending = None;
+        }
+
+        // dropping the sender ends the worker's receive loop
+        self.sender = None;
+        if let Some(worker) = self.worker.take() {
+            if worker.join().is_err() {
+                log::error!(target: "recorder", "Frame recording worker panicked");
+            }
+        }
+    }
+}
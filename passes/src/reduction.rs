@@ -0,0 +1,292 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use ash::vk;
+use gpu_allocator::MemoryLocation;
+use api_types::buffer::BufferCreateInfo;
+use api_types::device::{DeviceResource, DeviceWrapper};
+
+use context::render_context::RenderContext;
+use context::vulkan_render_context::VulkanRenderContext;
+use framegraph::binding::{BindingInfo, BindingType, BufferBindingInfo, ImageBindingInfo, ResourceBinding};
+use framegraph::compute_pass_node::ComputePassNode;
+use framegraph::copy_pass_node::CopyPassNode;
+use framegraph::pass_type::PassType;
+use framegraph::ping_pong::PingPongBuffers;
+use framegraph::pipeline::ComputePipelineDescription;
+use profiling::enter_span;
+
+// texels reduced by each group of the first pass, and partial results by each later one
+const TILE_SIZE: u32 = 16;
+const GROUP_SIZE: u32 = 256;
+const STATS_SIZE: vk::DeviceSize = 16;
+
+/// Offset of the reduced statistics in the buffer returned by
+/// [`LuminanceReduction::generate_passes`]. They're a vec4 of the minimum and maximum
+/// luminance, the sum of the natural log of every texel's luminance and the texel count, so the
+/// log-average luminance is `exp(z / w)`. The offset is preceded by the number of results
+pub const LUMINANCE_STATS_OFFSET: vk::DeviceSize = 16;
+
+/// Bins of a [`LuminanceHistogram`]
+pub const HISTOGRAM_BINS: usize = 256;
+
+/// Offset of the bins in a [`LuminanceHistogram`]'s buffer. They're preceded by the minimum log2
+/// luminance and the range of log2 luminance the bins cover as floats, then the number of texels
+/// counted as a uint
+pub const HISTOGRAM_BINS_OFFSET: vk::DeviceSize = 16;
+
+fn source_binding(source: &Rc<RefCell<DeviceResource>>) -> ResourceBinding {
+    ResourceBinding::new(
+        source.clone(),
+        BindingInfo {
+            binding_type: BindingType::Image(ImageBindingInfo::new(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)),
+            set: 0,
+            slot: 0,
+            stage: vk::PipelineStageFlags::COMPUTE_SHADER,
+            access: vk::AccessFlags::SHADER_READ
+        })
+}
+
+fn reduction_pass(
+    name: String,
+    shader: &str,
+    input: ResourceBinding,
+    output: ResourceBinding,
+    groups: (u32, u32)) -> PassType {
+
+    let pass_node = ComputePassNode::builder(name.clone())
+        .pipeline_description(ComputePipelineDescription::new(shader))
+        .input(input)
+        .output(output)
+        .fill_commands(Box::new(
+            move |render_ctx: &VulkanRenderContext,
+                  command_buffer: &vk::CommandBuffer| {
+
+                enter_span!(tracing::Level::TRACE, "Reduction");
+                let _gpu_scope = render_ctx.get_profiler().scope(&name, command_buffer);
+
+                unsafe {
+                    render_ctx.get_device().borrow().get().cmd_dispatch(
+                        *command_buffer,
+                        groups.0,
+                        groups.1,
+                        1);
+                }
+            }
+        ))
+        .build()
+        .expect("Failed to create reduction passnode");
+
+    PassType::Compute(pass_node)
+}
+
+/// Reduces the luminance of an image to its minimum, maximum and log-average, e.g. for
+/// auto-exposure. The first pass reduces each 16x16 tile of the image into one of a pair of
+/// ping-pong buffers, then each following pass combines 256 of the previous pass's results
+/// until one remains. The buffers are sized for the extent the reduction is created with and
+/// kept across frames, so it needs recreating when the source is resized
+pub struct LuminanceReduction {
+    buffers: PingPongBuffers,
+    extent: vk::Extent2D,
+    name: String
+}
+
+impl LuminanceReduction {
+    pub fn new(
+        device: Rc<RefCell<DeviceWrapper>>,
+        extent: vk::Extent2D,
+        name: &str) -> Self {
+
+        let tiles = extent.width.div_ceil(TILE_SIZE) * extent.height.div_ceil(TILE_SIZE);
+        let buffers = PingPongBuffers::new(
+            device,
+            LUMINANCE_STATS_OFFSET + tiles as vk::DeviceSize * STATS_SIZE,
+            name);
+
+        LuminanceReduction {
+            buffers,
+            extent,
+            name: name.to_string()
+        }
+    }
+
+    pub fn get_extent(&self) -> vk::Extent2D { self.extent }
+
+    /// The passes reducing `source`, which must be a sampled image of the reduction's extent,
+    /// along with the buffer holding the result once they've executed (see
+    /// [`LUMINANCE_STATS_OFFSET`])
+    pub fn generate_passes(
+        &mut self,
+        source: Rc<RefCell<DeviceResource>>) -> (Vec<PassType>, Rc<RefCell<DeviceResource>>) {
+
+        let source_extent = source.borrow().get_image().extent;
+        assert!(source_extent.width == self.extent.width && source_extent.height == self.extent.height,
+            "Reduction {} was created for a {}x{} image but is reducing a {}x{} image",
+            self.name, self.extent.width, self.extent.height, source_extent.width, source_extent.height);
+
+        let stage = vk::PipelineStageFlags::COMPUTE_SHADER;
+        let tiles = (self.extent.width.div_ceil(TILE_SIZE), self.extent.height.div_ceil(TILE_SIZE));
+        let mut passes = vec![reduction_pass(
+            format!("{}_tiles", self.name),
+            "reduce_luminance-comp.spv",
+            source_binding(&source),
+            self.buffers.write_binding(0, 1, stage),
+            tiles)];
+        self.buffers.flip();
+
+        let mut count = tiles.0 * tiles.1;
+        let mut iteration = 0;
+        while count > 1 {
+            count = count.div_ceil(GROUP_SIZE);
+            passes.push(reduction_pass(
+                format!("{}_{}", self.name, iteration),
+                "reduce_partials-comp.spv",
+                self.buffers.read_binding(0, 0, stage),
+                self.buffers.write_binding(0, 1, stage),
+                (count, 1)));
+            self.buffers.flip();
+            iteration += 1;
+        }
+
+        (passes, self.buffers.get_latest().clone())
+    }
+}
+
+/// The contents of an empty histogram of `texel_count` texels: its header (see
+/// [`HISTOGRAM_BINS_OFFSET`]) followed by zeroed bins
+fn histogram_clear_data(min_log_luminance: f32, max_log_luminance: f32, texel_count: u32) -> Vec<u8> {
+    let clear_size = HISTOGRAM_BINS_OFFSET as usize + HISTOGRAM_BINS * std::mem::size_of::<u32>();
+    let mut clear_data: Vec<u8> = Vec::with_capacity(clear_size);
+    clear_data.extend_from_slice(&min_log_luminance.to_ne_bytes());
+    clear_data.extend_from_slice(&(max_log_luminance - min_log_luminance).to_ne_bytes());
+    clear_data.extend_from_slice(&texel_count.to_ne_bytes());
+    clear_data.resize(clear_size, 0);
+    clear_data
+}
+
+/// A histogram of an image's log2 luminance in [`HISTOGRAM_BINS`] bins, e.g. for auto-exposure
+/// which ignores the darkest and brightest parts of the image. The first bin counts texels too
+/// dark to have a meaningful log, and the others evenly divide the histogram's range, clamping
+/// texels outside of it into the first or last of them. The histogram's buffer is reused every
+/// frame, and cleared by a copy pass before it's filled
+pub struct LuminanceHistogram {
+    buffer: Rc<RefCell<DeviceResource>>,
+    min_log_luminance: f32,
+    max_log_luminance: f32,
+    name: String
+}
+
+impl LuminanceHistogram {
+    pub fn new(
+        device: Rc<RefCell<DeviceWrapper>>,
+        min_log_luminance: f32,
+        max_log_luminance: f32,
+        name: &str) -> Self {
+
+        assert!(max_log_luminance > min_log_luminance, "Histogram {} has an empty luminance range", name);
+        let create_info = BufferCreateInfo::new(
+            vk::BufferCreateInfo::builder()
+                .size(HISTOGRAM_BINS_OFFSET + (HISTOGRAM_BINS * std::mem::size_of::<u32>()) as vk::DeviceSize)
+                .usage(vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::TRANSFER_DST | vk::BufferUsageFlags::TRANSFER_SRC)
+                .sharing_mode(vk::SharingMode::EXCLUSIVE)
+                .build(),
+            name.to_string());
+        let buffer = DeviceWrapper::create_buffer(device, &create_info, MemoryLocation::GpuOnly);
+
+        LuminanceHistogram {
+            buffer: Rc::new(RefCell::new(buffer)),
+            min_log_luminance,
+            max_log_luminance,
+            name: name.to_string()
+        }
+    }
+
+    /// Holds the histogram once the passes from generate_passes have executed (see
+    /// [`HISTOGRAM_BINS_OFFSET`])
+    pub fn get_buffer(&self) -> &Rc<RefCell<DeviceResource>> { &self.buffer }
+
+    pub fn get_log_luminance_range(&self) -> (f32, f32) { (self.min_log_luminance, self.max_log_luminance) }
+
+    /// The passes clearing the histogram and counting the texels of `source`, which must be a
+    /// sampled image
+    pub fn generate_passes(&self, source: Rc<RefCell<DeviceResource>>) -> Vec<PassType> {
+        let source_extent = source.borrow().get_image().extent;
+
+        let clear_data = histogram_clear_data(
+            self.min_log_luminance,
+            self.max_log_luminance,
+            source_extent.width * source_extent.height);
+
+        let clear_name = format!("{}_clear", self.name);
+        let histogram_buffer = self.buffer.borrow().get_buffer().buffer;
+        let clear_node = CopyPassNode::builder(clear_name.clone())
+            .copy_dst(self.buffer.clone())
+            .fill_commands(Box::new(
+                move |render_ctx: &VulkanRenderContext,
+                      command_buffer: &vk::CommandBuffer| {
+
+                    enter_span!(tracing::Level::TRACE, "Clear histogram");
+                    let _gpu_scope = render_ctx.get_profiler().scope(&clear_name, command_buffer);
+
+                    unsafe {
+                        render_ctx.get_device().borrow().get().cmd_update_buffer(
+                            *command_buffer,
+                            histogram_buffer,
+                            0,
+                            &clear_data);
+                    }
+                }
+            ))
+            .build()
+            .expect("Failed to create histogram clear passnode");
+
+        let histogram_binding = ResourceBinding::new(
+            self.buffer.clone(),
+            BindingInfo {
+                binding_type: BindingType::Buffer(BufferBindingInfo::new(0, vk::WHOLE_SIZE)),
+                set: 0,
+                slot: 1,
+                stage: vk::PipelineStageFlags::COMPUTE_SHADER,
+                access: vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE
+            });
+
+        vec![
+            PassType::Copy(clear_node),
+            reduction_pass(
+                self.name.clone(),
+                "luminance_histogram-comp.spv",
+                source_binding(&source),
+                histogram_binding,
+                (source_extent.width.div_ceil(TILE_SIZE), source_extent.height.div_ceil(TILE_SIZE)))
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn word(data: &[u8], offset: usize) -> [u8; 4] {
+        data[offset..offset + 4].try_into().unwrap()
+    }
+
+    #[test]
+    fn clear_data_covers_the_header_and_every_bin() {
+        let data = histogram_clear_data(-10.0, 12.0, 1920 * 1080);
+        assert_eq!(data.len() as vk::DeviceSize, HISTOGRAM_BINS_OFFSET + (HISTOGRAM_BINS * 4) as vk::DeviceSize);
+    }
+
+    #[test]
+    fn clear_data_header_holds_the_range_and_texel_count() {
+        let data = histogram_clear_data(-10.0, 12.0, 1920 * 1080);
+        assert_eq!(f32::from_ne_bytes(word(&data, 0)), -10.0);
+        assert_eq!(f32::from_ne_bytes(word(&data, 4)), 22.0);
+        assert_eq!(u32::from_ne_bytes(word(&data, 8)), 1920 * 1080);
+    }
+
+    #[test]
+    fn clear_data_bins_are_empty() {
+        let data = histogram_clear_data(-10.0, 12.0, 64);
+        assert!(data[HISTOGRAM_BINS_OFFSET as usize..].iter().all(|byte| *byte == 0));
+    }
+}
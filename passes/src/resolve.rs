@@ -0,0 +1,64 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use ash::vk;
+use api_types::device::DeviceResource;
+
+use context::render_context::RenderContext;
+use context::vulkan_render_context::VulkanRenderContext;
+use framegraph::copy_pass_node::CopyPassNode;
+use framegraph::pass_type::PassType;
+use profiling::enter_span;
+
+/// Resolves the multisampled `source` into the single sampled `dest`, which must have the same
+/// format. Covers the smaller of the two images' extents
+pub fn generate_pass(
+    source: Rc<RefCell<DeviceResource>>,
+    dest: Rc<RefCell<DeviceResource>>) -> PassType {
+
+    let pass_node = CopyPassNode::builder("resolve".to_string())
+        .copy_src(source.clone())
+        .copy_dst(dest.clone())
+        .fill_commands(Box::new(
+            move |render_ctx: &VulkanRenderContext,
+                    command_buffer: &vk::CommandBuffer| {
+
+                enter_span!(tracing::Level::TRACE, "Resolve");
+                let _gpu_scope = render_ctx.get_profiler().scope("Resolve GPU", command_buffer);
+
+                let resolved_source = source.borrow();
+                let resolved_dest = dest.borrow();
+                let source_image = resolved_source.get_image();
+                let dest_image = resolved_dest.get_image();
+                debug_assert!(source_image.format == dest_image.format,
+                    "Resolve source {} and dest {} have different formats", resolved_source.get_name(), resolved_dest.get_name());
+
+                let layers = vk::ImageSubresourceLayers::builder()
+                    .layer_count(1)
+                    .base_array_layer(0)
+                    .mip_level(0)
+                    .aspect_mask(vk::ImageAspectFlags::COLOR)
+                    .build();
+                let resolve_region = vk::ImageResolve::builder()
+                    .src_subresource(layers)
+                    .dst_subresource(layers)
+                    .extent(vk::Extent3D {
+                        width: source_image.extent.width.min(dest_image.extent.width),
+                        height: source_image.extent.height.min(dest_image.extent.height),
+                        depth: 1
+                    });
+
+                unsafe {
+                    render_ctx.get_device().borrow().get().cmd_resolve_image(
+                        *command_buffer,
+                        source_image.image,
+                        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                        dest_image.image,
+                        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                        std::slice::from_ref(&resolve_region));
+                }
+        }))
+        .build()
+        .expect("Failed to create Resolve passnode");
+
+        PassType::Copy(pass_node)
+}
\ No newline at end of file
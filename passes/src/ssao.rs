@@ -0,0 +1,345 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use ash::vk;
+use api_types::device::{DeviceResource, DeviceWrapper};
+use api_types::format_selector::HDR_COLOR_FORMATS;
+use api_types::image::ImageType;
+use api_types::upload_buffer::DynamicUploadBuffer;
+
+use context::render_context::RenderContext;
+use context::transient_image_pool::{TransientImageDesc, TransientImagePool};
+use context::vulkan_render_context::VulkanRenderContext;
+use framegraph::attachment::AttachmentReference;
+use framegraph::binding::{BindingInfo, BindingType, BufferBindingInfo, ImageBindingInfo, ResourceBinding, ResourceLifetime};
+use framegraph::compute_pass_node::ComputePassNode;
+use framegraph::graphics_pass_node::GraphicsPassNode;
+use framegraph::pass_type::PassType;
+use framegraph::pipeline::{BlendEquation, BlendType, ComputePipelineDescription, DepthStencilType, PipelineDescription, RasterizationState};
+use framegraph::shader;
+use framegraph::shader::Shader;
+use framegraph::shader_variant::ShaderVariant;
+use framegraph::uniform_layout::UniformBlock;
+use profiling::enter_span;
+
+/// The format of the normal target read by the SSAO pass. Each texel holds the view space
+/// normal of the surface it covers
+pub const NORMAL_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+
+// the occlusion, along with the view space depth the blur passes weigh neighbours by. The
+// shaders writing it have a variant for each format
+const AO_FORMATS: &[vk::Format] = HDR_COLOR_FORMATS;
+
+// matches the SsaoParams uniform in ssao.comp
+#[repr(C)]
+#[derive(UniformBlock)]
+struct SsaoParams {
+    view_from_uv: glam::Mat4,
+    uv_from_view: glam::Mat4,
+    radius: f32,
+    intensity: f32,
+    bias: f32,
+    clear_depth: f32
+}
+
+// matches the BlurParams uniform in ssao_blur.comp
+#[repr(C)]
+#[derive(UniformBlock)]
+struct BlurParams {
+    direction: [i32; 2],
+    sharpness: f32,
+    padding: f32
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct SsaoSettings {
+    /// The view space radius of the hemisphere sampled around each texel
+    pub radius: f32,
+    pub intensity: f32,
+    /// How far in view space a sample must be behind the depth buffer to count as occluded,
+    /// which keeps flat surfaces from occluding themselves
+    pub bias: f32,
+    /// How strongly the blur avoids averaging texels at different depths
+    pub blur_sharpness: f32
+}
+
+impl Default for SsaoSettings {
+    fn default() -> Self {
+        SsaoSettings {
+            radius: 0.5,
+            intensity: 1.0,
+            bias: 0.025,
+            blur_sharpness: 16.0
+        }
+    }
+}
+
+fn image_binding(
+    resource: &Rc<RefCell<DeviceResource>>,
+    layout: vk::ImageLayout,
+    slot: u32,
+    access: vk::AccessFlags,
+    lifetime: ResourceLifetime) -> ResourceBinding {
+    ResourceBinding {
+        resource: resource.clone(),
+        binding_info: BindingInfo {
+            binding_type: BindingType::Image(ImageBindingInfo::new(layout)),
+            set: 0,
+            slot,
+            stage: vk::PipelineStageFlags::COMPUTE_SHADER,
+            access
+        },
+        lifetime
+    }
+}
+
+fn uniform_binding<T: UniformBlock>(
+    upload_buffer: &mut DynamicUploadBuffer,
+    value: &T,
+    stage: vk::PipelineStageFlags) -> ResourceBinding {
+
+    let alignment = upload_buffer.get_uniform_alignment();
+    let offset = upload_buffer.push(std::slice::from_ref(value), alignment);
+    ResourceBinding::new(
+        upload_buffer.get_buffer().clone(),
+        BindingInfo {
+            binding_type: BindingType::Buffer(BufferBindingInfo {
+                offset,
+                range: std::mem::size_of::<T>() as vk::DeviceSize,
+                layout: Some(T::layout())
+            }),
+            set: 0,
+            slot: 0,
+            stage,
+            access: vk::AccessFlags::SHADER_READ
+        })
+}
+
+fn compute_pass(
+    name: &'static str,
+    shader: &str,
+    inputs: Vec<ResourceBinding>,
+    output: ResourceBinding,
+    extent: vk::Extent3D) -> PassType {
+
+    let mut builder = ComputePassNode::builder(name.to_string())
+        .pipeline_description(ComputePipelineDescription::new(shader));
+    for input in inputs {
+        builder = builder.input(input);
+    }
+
+    let pass_node = builder
+        .output(output)
+        .fill_commands(Box::new(
+            move |render_ctx: &VulkanRenderContext,
+                  command_buffer: &vk::CommandBuffer| {
+
+                enter_span!(tracing::Level::TRACE, "SSAO");
+                let _gpu_scope = render_ctx.get_profiler().scope(name, command_buffer);
+
+                unsafe {
+                    render_ctx.get_device().borrow().get().cmd_dispatch(
+                        *command_buffer,
+                        extent.width.div_ceil(8),
+                        extent.height.div_ceil(8),
+                        1);
+                }
+            }
+        ))
+        .build()
+        .expect("Failed to create SSAO passnode");
+
+    PassType::Compute(pass_node)
+}
+
+/// Screen space ambient occlusion from a depth buffer and a normal target (see
+/// NORMAL_FORMAT). The occlusion is computed at full resolution and smoothed by a depth-aware
+/// separable blur, all in images from the transient image pool, so the result only lives for
+/// the frame it's generated in
+pub struct AmbientOcclusion {
+    vertex_shader: Rc<RefCell<Shader>>,
+    apply_shader: Rc<RefCell<Shader>>,
+    pub settings: SsaoSettings
+}
+
+impl AmbientOcclusion {
+    pub fn new(device: Rc<RefCell<DeviceWrapper>>) -> Self {
+        let vertex_shader = Rc::new(RefCell::new(
+            shader::create_shader_module_from_bytes(device.clone(), "final_output-vert", include_bytes!(concat!(env!("OUT_DIR"), "/shaders/final_output-vert.spv")))));
+        let apply_shader = Rc::new(RefCell::new(
+            shader::create_shader_module_from_bytes(device.clone(), "ssao_apply-frag", include_bytes!(concat!(env!("OUT_DIR"), "/shaders/ssao_apply-frag.spv")))));
+
+        AmbientOcclusion {
+            vertex_shader,
+            apply_shader,
+            settings: SsaoSettings::default()
+        }
+    }
+
+    /// The passes computing the occlusion of the scene rendered into `depth` and `normals`,
+    /// which must both be sampled, along with the image holding the occlusion in its red
+    /// channel once they've executed. `projection` is the scene's projection, which must be
+    /// right-handed with the camera looking down -Z, `y_flipped` is whether the scene was
+    /// rendered with a negative viewport height and `clear_depth` is the value `depth` was
+    /// cleared to, i.e. 0.0 for reverse-Z. Texels still holding it are treated as background
+    pub fn generate_passes(
+        &self,
+        device: Rc<RefCell<DeviceWrapper>>,
+        depth: Rc<RefCell<DeviceResource>>,
+        normals: Rc<RefCell<DeviceResource>>,
+        projection: &glam::Mat4,
+        y_flipped: bool,
+        clear_depth: f32,
+        image_pool: &mut TransientImagePool,
+        upload_buffer: &mut DynamicUploadBuffer) -> (Vec<PassType>, Rc<RefCell<DeviceResource>>) {
+
+        enter_span!(tracing::Level::TRACE, "Generate SSAO Passes");
+
+        let extent = depth.borrow().get_image().extent;
+        assert!(depth.borrow().get_image().get_sampler().is_some(), "The SSAO depth image must have a sampler");
+        assert!(normals.borrow().get_image().get_sampler().is_some(), "The SSAO normal image must have a sampler");
+
+        // the blurred occlusion is written back into the first image once the second has
+        // been read from it
+        let ao_usage = vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED;
+        let ao_format = device.borrow().formats().require(AO_FORMATS, ao_usage, vk::FormatFeatureFlags::empty(), "ssao");
+        let shader_file = |base: &str| ShaderVariant::new(base)
+            .define("FLOAT32", ao_format == vk::Format::R32G32B32A32_SFLOAT)
+            .get_file_name();
+        let ao_desc = TransientImageDesc {
+            extent,
+            format: ao_format,
+            usage: ao_usage,
+            samples: vk::SampleCountFlags::TYPE_1,
+            image_type: ImageType::Color
+        };
+        let ao = image_pool.request_image(&ao_desc, "ssao");
+        let ao_blurred = image_pool.request_image(&ao_desc, "ssao_blur");
+
+        // maps texel UVs and depth to normalized device coordinates
+        let ndc_from_uv = glam::Mat4::from_cols(
+            glam::Vec4::new(2.0, 0.0, 0.0, 0.0),
+            glam::Vec4::new(0.0, if y_flipped { -2.0 } else { 2.0 }, 0.0, 0.0),
+            glam::Vec4::new(0.0, 0.0, 1.0, 0.0),
+            glam::Vec4::new(-1.0, if y_flipped { 1.0 } else { -1.0 }, 0.0, 1.0));
+        let ssao_params = SsaoParams {
+            view_from_uv: projection.inverse() * ndc_from_uv,
+            uv_from_view: ndc_from_uv.inverse() * *projection,
+            radius: self.settings.radius,
+            intensity: self.settings.intensity,
+            bias: self.settings.bias,
+            clear_depth
+        };
+
+        let stage = vk::PipelineStageFlags::COMPUTE_SHADER;
+        let read_only = vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL;
+        let mut passes = vec![compute_pass(
+            "ssao",
+            &shader_file("ssao-comp"),
+            vec![
+                uniform_binding(upload_buffer, &ssao_params, stage),
+                image_binding(&depth, read_only, 1, vk::AccessFlags::SHADER_READ, ResourceLifetime::Persistent),
+                image_binding(&normals, read_only, 2, vk::AccessFlags::SHADER_READ, ResourceLifetime::Persistent)],
+            image_binding(&ao, vk::ImageLayout::GENERAL, 3, vk::AccessFlags::SHADER_WRITE, ResourceLifetime::Transient),
+            extent)];
+
+        for (name, direction, source, target) in [
+            ("ssao_blur_horizontal", [1, 0], &ao, &ao_blurred),
+            ("ssao_blur_vertical", [0, 1], &ao_blurred, &ao)] {
+
+            let blur_params = BlurParams {
+                direction,
+                sharpness: self.settings.blur_sharpness,
+                padding: 0.0
+            };
+            passes.push(compute_pass(
+                name,
+                &shader_file("ssao_blur-comp"),
+                vec![
+                    uniform_binding(upload_buffer, &blur_params, stage),
+                    image_binding(source, read_only, 1, vk::AccessFlags::SHADER_READ, ResourceLifetime::Transient)],
+                image_binding(target, vk::ImageLayout::GENERAL, 2, vk::AccessFlags::SHADER_WRITE, ResourceLifetime::Transient),
+                extent));
+        }
+
+        (passes, ao)
+    }
+
+    /// A pass darkening every texel of `target` by the occlusion in `ao`, for scenes without
+    /// a lighting pass to apply it to their ambient light instead
+    pub fn generate_apply_pass(
+        &self,
+        ao: Rc<RefCell<DeviceResource>>,
+        target: AttachmentReference) -> PassType {
+
+        let extent = target.resource_image.borrow().get_image().extent;
+
+        let ao_binding = ResourceBinding::new(
+            ao,
+            BindingInfo {
+                binding_type: BindingType::Image(ImageBindingInfo::new(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)),
+                set: 0,
+                slot: 0,
+                stage: vk::PipelineStageFlags::FRAGMENT_SHADER,
+                access: vk::AccessFlags::SHADER_READ
+            }).transient();
+
+        // multiplies the target's color by the occlusion, leaving its alpha alone
+        let multiply = BlendEquation {
+            src_color_blend_factor: vk::BlendFactor::ZERO,
+            dst_color_blend_factor: vk::BlendFactor::SRC_COLOR,
+            src_alpha_blend_factor: vk::BlendFactor::ZERO,
+            dst_alpha_blend_factor: vk::BlendFactor::ONE,
+            ..BlendEquation::default()
+        };
+        let pipeline_description = PipelineDescription::new(
+            vk::PipelineVertexInputStateCreateInfo::default(),
+            vec!(vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR),
+            RasterizationState::default(),
+            DepthStencilType::Disable,
+            BlendType::Custom(multiply),
+            "ssao_apply",
+            self.vertex_shader.clone(),
+            self.apply_shader.clone());
+
+        let viewport = vk::Viewport::builder()
+            .x(0.0)
+            .y(0.0)
+            .width(extent.width as f32)
+            .height(extent.height as f32)
+            .min_depth(0.0)
+            .max_depth(1.0)
+            .build();
+
+        let scissor = vk::Rect2D::builder()
+            .offset(vk::Offset2D{x: 0, y: 0})
+            .extent(vk::Extent2D{width: extent.width, height: extent.height})
+            .build();
+
+        let pass_node = GraphicsPassNode::builder("ssao_apply".to_string())
+            .pipeline_description(pipeline_description)
+            .render_target(target)
+            .read(ao_binding)
+            .viewport(viewport)
+            .scissor(scissor)
+            .fill_commands(Box::new(
+                move |render_ctx: &VulkanRenderContext,
+                      command_buffer: &vk::CommandBuffer | {
+                    enter_span!(tracing::Level::TRACE, "SSAO Apply");
+                    let _gpu_scope = render_ctx.get_profiler().scope("SSAO Apply GPU", command_buffer);
+                    unsafe {
+                        render_ctx.get_device().borrow().get().cmd_draw(
+                            *command_buffer,
+                            3,
+                            1,
+                            0,
+                            0);
+                    }
+                }
+            ))
+            .build()
+            .expect("Failed to create SSAO apply passnode");
+
+        PassType::Graphics(pass_node)
+    }
+}
@@ -0,0 +1,296 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use ash::vk;
+use ash::vk::Handle;
+use gpu_allocator::MemoryLocation;
+use api_types::device::{DeviceResource, DeviceWrapper};
+use api_types::format_selector::HDR_COLOR_FORMATS;
+use api_types::image::{ImageCreateInfo, ImageType};
+use api_types::resource_state::ResourceState;
+use api_types::upload_buffer::DynamicUploadBuffer;
+
+use context::render_context::RenderContext;
+use context::vulkan_render_context::VulkanRenderContext;
+use framegraph::attachment::AttachmentReference;
+use framegraph::binding::{BindingInfo, BindingType, BufferBindingInfo, ImageBindingInfo, ResourceBinding};
+use framegraph::compute_pass_node::ComputePassNode;
+use framegraph::frame::Frame;
+use framegraph::pass_type::PassType;
+use framegraph::pipeline::ComputePipelineDescription;
+use framegraph::shader_variant::ShaderVariant;
+use framegraph::uniform_layout::UniformBlock;
+use profiling::enter_span;
+use util::camera;
+
+use crate::clear;
+
+/// The formats of the velocity target written alongside the scene in order of preference. Each
+/// texel holds the motion of the surface it covers since the previous frame in UV units, i.e.
+/// its previous position is `uv - velocity`
+pub const VELOCITY_FORMATS: &[vk::Format] = &[
+    vk::Format::R16G16_SFLOAT,
+    vk::Format::R32G32_SFLOAT];
+
+/// The formats of the history targets in order of preference, the format of the resolved
+/// image being the one chosen (see TemporalAntiAliasing::get_history_format)
+pub const HISTORY_FORMATS: &[vk::Format] = HDR_COLOR_FORMATS;
+
+// matches the TaaParams uniform in taa_resolve.comp
+#[repr(C)]
+#[derive(UniformBlock)]
+struct TaaParams {
+    feedback_min: f32,
+    feedback_max: f32,
+    history_valid: u32,
+    padding: u32
+}
+
+fn create_target(
+    device: Rc<RefCell<DeviceWrapper>>,
+    extent: vk::Extent2D,
+    preferences: &[vk::Format],
+    usage: vk::ImageUsageFlags,
+    filter: vk::Filter,
+    name: &str) -> Rc<RefCell<DeviceResource>> {
+
+    let format = device.borrow().formats().require(
+        preferences,
+        usage | vk::ImageUsageFlags::SAMPLED,
+        vk::FormatFeatureFlags::empty(),
+        name);
+    let create_info = ImageCreateInfo::new(
+        vk::ImageCreateInfo::builder()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(format)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .extent(vk::Extent3D {
+                width: extent.width,
+                height: extent.height,
+                depth: 1
+            })
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .usage(usage | vk::ImageUsageFlags::SAMPLED)
+            .mip_levels(1)
+            .array_layers(1)
+            .build(),
+        name.to_string(),
+        ImageType::Color);
+
+    let mut image = DeviceWrapper::create_image(device.clone(), &create_info, MemoryLocation::GpuOnly);
+    let sampler = unsafe {
+        let sampler_create = vk::SamplerCreateInfo::builder()
+            .mag_filter(filter)
+            .min_filter(filter)
+            .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+            .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .build();
+
+        device.borrow().get().create_sampler(&sampler_create, None)
+            .expect("Failed to create TAA target sampler")
+    };
+    device.borrow().set_debug_name(vk::ObjectType::SAMPLER, sampler.as_raw(), &format!("{}_sampler", name));
+    image.get_image_mut().sampler = Some(sampler);
+
+    Rc::new(RefCell::new(image))
+}
+
+fn sampled_binding(resource: &Rc<RefCell<DeviceResource>>, slot: u32) -> ResourceBinding {
+    ResourceBinding::new(
+        resource.clone(),
+        BindingInfo {
+            binding_type: BindingType::Image(ImageBindingInfo::new(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)),
+            set: 0,
+            slot,
+            stage: vk::PipelineStageFlags::COMPUTE_SHADER,
+            access: vk::AccessFlags::SHADER_READ
+        })
+}
+
+/// Temporal anti-aliasing. The scene is rendered with its projection jittered by a different
+/// sub-pixel offset every frame (see get_jitter), and the resolve pass blends each frame into a
+/// history of the previous ones, reprojected with the velocity target and clamped to the
+/// current frame's neighbourhood so disoccluded surfaces don't ghost.
+///
+/// The history is a pair of images which alternate between being read and written, and carry
+/// over between frames as persistent resources. They're sized for the extent TAA is created
+/// with, so it needs recreating when the scene is resized
+pub struct TemporalAntiAliasing {
+    history: [Rc<RefCell<DeviceResource>>; 2],
+    history_format: vk::Format,
+    velocity: Rc<RefCell<DeviceResource>>,
+    extent: vk::Extent2D,
+    // the history written this frame
+    current: usize,
+    frame_index: u32,
+    /// The weight of the history in the resolve, between that for texels whose color changed
+    /// the most and the least since the previous frame
+    pub feedback: (f32, f32),
+    name: String
+}
+
+impl TemporalAntiAliasing {
+    pub fn new(
+        device: Rc<RefCell<DeviceWrapper>>,
+        extent: vk::Extent2D,
+        name: &str) -> Self {
+
+        let history_usage = vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::TRANSFER_SRC;
+        let history = [0, 1].map(|index| create_target(
+            device.clone(),
+            extent,
+            HISTORY_FORMATS,
+            history_usage,
+            vk::Filter::LINEAR,
+            &format!("{}_history_{}", name, index)));
+        let velocity = create_target(
+            device,
+            extent,
+            VELOCITY_FORMATS,
+            vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_DST,
+            vk::Filter::NEAREST,
+            &format!("{}_velocity", name));
+
+        // both histories are created with the same usage, so fall back to the same format
+        let history_format = history[0].borrow().get_image().format;
+
+        TemporalAntiAliasing {
+            history,
+            history_format,
+            velocity,
+            extent,
+            current: 0,
+            frame_index: 0,
+            feedback: (0.88, 0.97),
+            name: name.to_string()
+        }
+    }
+
+    pub fn get_extent(&self) -> vk::Extent2D { self.extent }
+
+    /// The format of the history, and so of the resolved image
+    pub fn get_history_format(&self) -> vk::Format { self.history_format }
+
+    pub fn get_velocity(&self) -> &Rc<RefCell<DeviceResource>> { &self.velocity }
+
+    /// The offset in pixels to jitter this frame's projection by, e.g. with
+    /// Camera::get_jittered_projection. Advances once the frame's passes are generated
+    pub fn get_jitter(&self) -> (f32, f32) {
+        let jitter = camera::halton_jitter(self.frame_index);
+        (jitter.x, jitter.y)
+    }
+
+    /// The velocity target as an additional render target for the passes rendering the scene.
+    /// Its contents are loaded, so the passes writing it must follow clear_velocity_pass
+    pub fn velocity_attachment(&self) -> AttachmentReference {
+        AttachmentReference::new(self.velocity.clone(), vk::SampleCountFlags::TYPE_1)
+    }
+
+    /// Clears the velocity target to no motion, so surfaces which aren't drawn with a velocity
+    /// output are treated as static
+    pub fn clear_velocity_pass(&self) -> PassType {
+        clear::clear(self.velocity.clone(), vk::ImageAspectFlags::COLOR)
+    }
+
+    /// The pass resolving `color`, which must be a sampled image of TAA's extent, into the
+    /// history, along with the resolved image. The resolved image is only valid for this frame;
+    /// it's read as the history by the next one
+    pub fn generate_pass(
+        &mut self,
+        frame: &mut Frame,
+        color: Rc<RefCell<DeviceResource>>,
+        upload_buffer: &mut DynamicUploadBuffer) -> (PassType, Rc<RefCell<DeviceResource>>) {
+
+        enter_span!(tracing::Level::TRACE, "Generate TAA Pass");
+
+        {
+            let color_ref = color.borrow();
+            let color_image = color_ref.get_image();
+            assert!(color_image.get_sampler().is_some(), "The color resolved by {} must have a sampler", self.name);
+            assert!(color_image.extent.width == self.extent.width && color_image.extent.height == self.extent.height,
+                "TAA {} was created for a {}x{} image but is resolving a {}x{} image",
+                self.name, self.extent.width, self.extent.height, color_image.extent.width, color_image.extent.height);
+        }
+
+        let previous = &self.history[1 - self.current];
+        let resolved = self.history[self.current].clone();
+        // the previous frame's history is only blended in if a frame has written it
+        let history_valid = frame.import_persistent(previous);
+        frame.export_resource(resolved.clone(), ResourceState {
+            access: vk::AccessFlags::SHADER_READ,
+            stage: vk::PipelineStageFlags::COMPUTE_SHADER,
+            layout: Some(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+        });
+
+        let params = TaaParams {
+            feedback_min: self.feedback.0,
+            feedback_max: self.feedback.1,
+            history_valid: history_valid as u32,
+            padding: 0
+        };
+        let alignment = upload_buffer.get_uniform_alignment();
+        let params_offset = upload_buffer.push(std::slice::from_ref(&params), alignment);
+
+        let params_binding = ResourceBinding::new(
+            upload_buffer.get_buffer().clone(),
+            BindingInfo {
+                binding_type: BindingType::Buffer(BufferBindingInfo {
+                    offset: params_offset,
+                    range: std::mem::size_of::<TaaParams>() as vk::DeviceSize,
+                    layout: Some(TaaParams::layout())
+                }),
+                set: 0,
+                slot: 0,
+                stage: vk::PipelineStageFlags::COMPUTE_SHADER,
+                access: vk::AccessFlags::SHADER_READ
+            });
+
+        let resolved_binding = ResourceBinding::new(
+            resolved.clone(),
+            BindingInfo {
+                binding_type: BindingType::Image(ImageBindingInfo::new(vk::ImageLayout::GENERAL)),
+                set: 0,
+                slot: 4,
+                stage: vk::PipelineStageFlags::COMPUTE_SHADER,
+                access: vk::AccessFlags::SHADER_WRITE
+            });
+
+        let pass_name = self.name.clone();
+        let extent = self.extent;
+        // the resolve shader has a variant for each history format
+        let shader = ShaderVariant::new("taa_resolve-comp")
+            .define("FLOAT32", self.history_format == vk::Format::R32G32B32A32_SFLOAT);
+        let pass_node = ComputePassNode::builder(self.name.clone())
+            .pipeline_description(ComputePipelineDescription::new(&shader.get_file_name()))
+            .input(params_binding)
+            .input(sampled_binding(&color, 1))
+            .input(sampled_binding(&self.velocity, 2))
+            .input(sampled_binding(previous, 3))
+            .output(resolved_binding)
+            .fill_commands(Box::new(
+                move |render_ctx: &VulkanRenderContext,
+                      command_buffer: &vk::CommandBuffer| {
+
+                    enter_span!(tracing::Level::TRACE, "TAA Resolve");
+                    let _gpu_scope = render_ctx.get_profiler().scope(&pass_name, command_buffer);
+
+                    unsafe {
+                        render_ctx.get_device().borrow().get().cmd_dispatch(
+                            *command_buffer,
+                            extent.width.div_ceil(8),
+                            extent.height.div_ceil(8),
+                            1);
+                    }
+                }
+            ))
+            .build()
+            .expect("Failed to create TAA resolve passnode");
+
+        self.current = 1 - self.current;
+        self.frame_index = self.frame_index.wrapping_add(1);
+
+        (PassType::Compute(pass_node), resolved)
+    }
+}
@@ -0,0 +1,454 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+use std::rc::Rc;
+
+use ash::vk;
+use ash::vk::Handle;
+use api_types::device::{DeviceResource, DeviceWrapper, ResourceType};
+use api_types::upload_buffer::DynamicUploadBuffer;
+
+use context::render_context::RenderContext;
+use context::vulkan_render_context::VulkanRenderContext;
+use framegraph::attachment::AttachmentReference;
+use framegraph::binding::{BindingInfo, BindingType, BufferBindingInfo, ImageBindingInfo, ResourceBinding};
+use framegraph::uniform_layout::UniformBlock;
+use framegraph::graphics_pass_node::GraphicsPassNode;
+use framegraph::pass_type::PassType;
+use framegraph::pipeline::{BlendType, DepthStencilType, PipelineDescription, RasterizationState};
+use framegraph::shader;
+use framegraph::shader::Shader;
+use profiling::enter_span;
+use util::image;
+
+const ATLAS_WIDTH: usize = 512;
+const GLYPH_PADDING: usize = 1;
+/// Glyphs outside of the atlas are drawn as this instead
+const FALLBACK_GLYPH: char = '?';
+
+#[derive(Copy, Clone, Debug)]
+#[repr(C)]
+struct TextVertex {
+    position: [f32; 2],
+    uv: [f32; 2],
+    color: [f32; 4]
+}
+
+#[repr(C)]
+#[derive(UniformBlock)]
+struct TextDisplay {
+    scale: [f32; 2],
+    pos: [f32; 2]
+}
+
+const TEXT_VERTEX_BINDING: vk::VertexInputBindingDescription = vk::VertexInputBindingDescription {
+    binding: 0,
+    stride: std::mem::size_of::<TextVertex>() as u32,
+    input_rate: vk::VertexInputRate::VERTEX,
+};
+
+const TEXT_VERTEX_ATTRIBUTES: [vk::VertexInputAttributeDescription; 3] = [
+    // pos
+    vk::VertexInputAttributeDescription {
+        location: 0,
+        binding: 0,
+        format: vk::Format::R32G32_SFLOAT,
+        offset: 0,
+    },
+
+    // uv
+    vk::VertexInputAttributeDescription {
+        location: 1,
+        binding: 0,
+        format: vk::Format::R32G32_SFLOAT,
+        offset: 4 * 2,
+    },
+
+    // color
+    vk::VertexInputAttributeDescription {
+        location: 2,
+        binding: 0,
+        format: vk::Format::R32G32B32A32_SFLOAT,
+        offset: 4 * 4,
+    }
+];
+
+#[derive(Copy, Clone, Debug)]
+struct Glyph {
+    uv_min: [f32; 2],
+    uv_max: [f32; 2],
+    /// From the pen position on the baseline to the glyph's top left corner
+    offset: [f32; 2],
+    size: [f32; 2],
+    advance: f32
+}
+
+/// Printable ASCII glyphs rasterized at a single pixel size into an R8 coverage texture
+pub struct FontAtlas {
+    glyphs: HashMap<char, Glyph>,
+    ascent: f32,
+    line_height: f32,
+    texture: Rc<RefCell<DeviceResource>>
+}
+
+impl Debug for FontAtlas {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FontAtlas")
+            .field("glyph count", &self.glyphs.len())
+            .field("line height", &self.line_height)
+            .finish()
+    }
+}
+
+impl FontAtlas {
+    /// Bakes the TrueType or OpenType font in `font_bytes` at `pixel_size`
+    pub fn new(
+        device: Rc<RefCell<DeviceWrapper>>,
+        render_context: &VulkanRenderContext,
+        font_bytes: &[u8],
+        pixel_size: f32,
+        name: &str) -> Result<FontAtlas, &'static str> {
+
+        let font = fontdue::Font::from_bytes(font_bytes, fontdue::FontSettings {
+            scale: pixel_size,
+            ..Default::default()
+        })?;
+        let line_metrics = font.horizontal_line_metrics(pixel_size)
+            .ok_or("Font has no horizontal line metrics")?;
+
+        let rasterized: Vec<(char, fontdue::Metrics, Vec<u8>)> = (' '..='~').map(|character| {
+            let (metrics, coverage) = font.rasterize(character, pixel_size);
+            (character, metrics, coverage)
+        }).collect();
+
+        // pack glyphs into rows, starting a new row whenever one is full
+        let mut placements: Vec<(usize, usize)> = Vec::with_capacity(rasterized.len());
+        let (mut x, mut y, mut row_height) = (GLYPH_PADDING, GLYPH_PADDING, 0);
+        for (_, metrics, _) in &rasterized {
+            if metrics.width + 2 * GLYPH_PADDING > ATLAS_WIDTH {
+                return Err("Glyphs are too wide for the font atlas");
+            }
+            if x + metrics.width + GLYPH_PADDING > ATLAS_WIDTH {
+                x = GLYPH_PADDING;
+                y += row_height + GLYPH_PADDING;
+                row_height = 0;
+            }
+            placements.push((x, y));
+            x += metrics.width + GLYPH_PADDING;
+            row_height = row_height.max(metrics.height);
+        }
+        let atlas_height = (y + row_height + GLYPH_PADDING).next_power_of_two();
+
+        let mut pixels = vec![0u8; ATLAS_WIDTH * atlas_height];
+        let mut glyphs = HashMap::with_capacity(rasterized.len());
+        for ((character, metrics, coverage), (x, y)) in rasterized.iter().zip(placements) {
+            for row in 0..metrics.height {
+                let atlas_start = (y + row) * ATLAS_WIDTH + x;
+                pixels[atlas_start..atlas_start + metrics.width]
+                    .copy_from_slice(&coverage[row * metrics.width..(row + 1) * metrics.width]);
+            }
+
+            // fontdue's offsets are y-up from the baseline to the bottom of the glyph
+            glyphs.insert(*character, Glyph {
+                uv_min: [x as f32 / ATLAS_WIDTH as f32, y as f32 / atlas_height as f32],
+                uv_max: [(x + metrics.width) as f32 / ATLAS_WIDTH as f32, (y + metrics.height) as f32 / atlas_height as f32],
+                offset: [metrics.xmin as f32, -(metrics.ymin as f32 + metrics.height as f32)],
+                size: [metrics.width as f32, metrics.height as f32],
+                advance: metrics.advance_width
+            });
+        }
+
+        let atlas_create = vk::ImageCreateInfo::builder()
+            .format(vk::Format::R8_UNORM)
+            .image_type(vk::ImageType::TYPE_2D)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .usage(vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED)
+            .extent(vk::Extent3D::builder()
+                .width(ATLAS_WIDTH as u32)
+                .height(atlas_height as u32)
+                .depth(1)
+                .build())
+            .mip_levels(1)
+            .array_layers(1)
+            .build();
+
+        let mut texture = image::create_from_bytes(
+            device.clone(),
+            render_context,
+            atlas_create,
+            &pixels,
+            name);
+
+        let sampler = unsafe {
+            let sampler_create = vk::SamplerCreateInfo::builder()
+                .mag_filter(vk::Filter::LINEAR)
+                .min_filter(vk::Filter::LINEAR)
+                .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+                .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .build();
+
+            let sampler = device.borrow().get().create_sampler(&sampler_create, None)
+                .expect("Failed to create font atlas sampler");
+            device.borrow().set_debug_name(vk::ObjectType::SAMPLER, sampler.as_raw(), &format!("{}_sampler", name));
+            sampler
+        };
+        texture.get_image_mut().sampler = Some(sampler);
+
+        // create_from_bytes leaves the atlas ready to sample
+        if let Some(ResourceType::Image(atlas_image)) = texture.resource_type.as_mut() {
+            atlas_image.layout = vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL;
+        } else {
+            panic!("Font atlas somehow not an image");
+        }
+
+        Ok(FontAtlas {
+            glyphs,
+            ascent: line_metrics.ascent,
+            line_height: line_metrics.new_line_size,
+            texture: Rc::new(RefCell::new(texture))
+        })
+    }
+
+    pub fn get_line_height(&self) -> f32 { self.line_height }
+
+    /// The width and height `text` covers when drawn
+    pub fn measure(&self, text: &str) -> [f32; 2] {
+        let mut extent = [0.0f32, 0.0f32];
+        let line_count = self.layout(text, [0.0, 0.0], |glyph, pen| {
+            extent[0] = extent[0].max(pen[0] + glyph.advance);
+        });
+        extent[1] = line_count as f32 * self.line_height;
+        extent
+    }
+
+    /// Calls `place` with each glyph of `text` and the pen position on its baseline, with
+    /// `position` at the top left of the first line. Returns the number of lines
+    fn layout(&self, text: &str, position: [f32; 2], mut place: impl FnMut(&Glyph, [f32; 2])) -> usize {
+        let mut pen = [position[0], position[1] + self.ascent];
+        let mut line_count = 1;
+        for character in text.chars() {
+            if character == '\n' {
+                pen = [position[0], pen[1] + self.line_height];
+                line_count += 1;
+                continue;
+            }
+
+            let glyph = self.glyphs.get(&character)
+                .or_else(|| self.glyphs.get(&FALLBACK_GLYPH))
+                .expect("Font atlas is missing its fallback glyph");
+            place(glyph, pen);
+            pen[0] += glyph.advance;
+        }
+        line_count
+    }
+}
+
+struct TextRun {
+    text: String,
+    position: [f32; 2],
+    color: [f32; 4]
+}
+
+/// Strings to be drawn over a frame, positioned in framebuffer pixels
+#[derive(Default)]
+pub struct TextBatch {
+    runs: Vec<TextRun>
+}
+
+impl TextBatch {
+    pub fn new() -> Self {
+        TextBatch {
+            runs: Vec::new()
+        }
+    }
+
+    /// `position` is the top left of the first line of `text`
+    pub fn add(&mut self, text: &str, position: [f32; 2], color: [f32; 4]) {
+        self.runs.push(TextRun {
+            text: text.to_string(),
+            position,
+            color
+        });
+    }
+
+    pub fn clear(&mut self) {
+        self.runs.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.runs.is_empty()
+    }
+}
+
+pub struct TextRender {
+    vertex_shader: Rc<RefCell<Shader>>,
+    fragment_shader: Rc<RefCell<Shader>>,
+    atlas: FontAtlas
+}
+
+impl Debug for TextRender {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TextRender")
+            .field("atlas", &self.atlas)
+            .finish()
+    }
+}
+
+impl TextRender {
+    pub fn new(device: Rc<RefCell<DeviceWrapper>>, atlas: FontAtlas) -> TextRender {
+        let vert_shader = Rc::new(RefCell::new(
+            shader::create_shader_module_from_bytes(device.clone(), "text-vert", include_bytes!(concat!(env!("OUT_DIR"), "/shaders/text-vert.spv")))));
+        let frag_shader = Rc::new(RefCell::new(
+            shader::create_shader_module_from_bytes(device.clone(), "text-frag", include_bytes!(concat!(env!("OUT_DIR"), "/shaders/text-frag.spv")))));
+
+        TextRender {
+            vertex_shader: vert_shader,
+            fragment_shader: frag_shader,
+            atlas
+        }
+    }
+
+    pub fn get_atlas(&self) -> &FontAtlas { &self.atlas }
+
+    /// Generates a single pass drawing every run in `batch` over `render_target`, with glyph
+    /// quads streamed into the upload buffer. Returns None when there is nothing to draw
+    pub fn generate_pass(
+        &self,
+        batch: &TextBatch,
+        render_target: AttachmentReference,
+        upload_buffer: &mut DynamicUploadBuffer) -> Option<PassType> {
+
+        enter_span!(tracing::Level::TRACE, "Generate Text Pass");
+
+        let mut vertices: Vec<TextVertex> = Vec::new();
+        for run in &batch.runs {
+            self.atlas.layout(&run.text, run.position, |glyph, pen| {
+                if glyph.size[0] == 0.0 || glyph.size[1] == 0.0 {
+                    return;
+                }
+
+                let min = [pen[0] + glyph.offset[0], pen[1] + glyph.offset[1]];
+                let max = [min[0] + glyph.size[0], min[1] + glyph.size[1]];
+                let corner = |x: usize, y: usize| TextVertex {
+                    position: [[min[0], max[0]][x], [min[1], max[1]][y]],
+                    uv: [[glyph.uv_min[0], glyph.uv_max[0]][x], [glyph.uv_min[1], glyph.uv_max[1]][y]],
+                    color: run.color
+                };
+                vertices.extend([
+                    corner(0, 0), corner(1, 0), corner(1, 1),
+                    corner(0, 0), corner(1, 1), corner(0, 1)]);
+            });
+        }
+
+        if vertices.is_empty() {
+            return None;
+        }
+
+        let upload_resource = upload_buffer.get_buffer();
+        let extent = render_target.resource_image.borrow().get_image().extent;
+
+        let display_offset = {
+            let display_value = TextDisplay {
+                scale: [2.0 / extent.width as f32, 2.0 / extent.height as f32],
+                pos: [-1.0, -1.0]
+            };
+
+            let alignment = upload_buffer.get_uniform_alignment();
+            upload_buffer.push(std::slice::from_ref(&display_value), alignment)
+        };
+        let vertex_offset = upload_buffer.push(
+            &vertices,
+            std::mem::align_of::<TextVertex>() as vk::DeviceSize);
+        let vertex_count = vertices.len() as u32;
+
+        let display_binding = ResourceBinding::new(
+            upload_resource.clone(),
+            BindingInfo {
+                binding_type: BindingType::Buffer(BufferBindingInfo{
+                    offset: display_offset,
+                    range: std::mem::size_of::<TextDisplay>() as vk::DeviceSize,
+                    layout: Some(TextDisplay::layout()) }),
+                set: 0,
+                slot: 0,
+                stage: vk::PipelineStageFlags::VERTEX_SHADER,
+                access: vk::AccessFlags::SHADER_READ,
+            });
+
+        let atlas_binding = ResourceBinding::new(
+            self.atlas.texture.clone(),
+            BindingInfo {
+                binding_type: BindingType::Image(ImageBindingInfo::new(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)),
+                set: 0,
+                slot: 1,
+                stage: vk::PipelineStageFlags::FRAGMENT_SHADER,
+                access: vk::AccessFlags::SHADER_READ
+            });
+
+        let vertex_input = vk::PipelineVertexInputStateCreateInfo::builder()
+            .vertex_binding_descriptions(std::slice::from_ref(&TEXT_VERTEX_BINDING))
+            .vertex_attribute_descriptions(&TEXT_VERTEX_ATTRIBUTES)
+            .build();
+
+        let dynamic_states = vec!(vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR);
+
+        let pipeline_description = PipelineDescription::new(
+            vertex_input,
+            dynamic_states,
+            RasterizationState::default(),
+            DepthStencilType::Disable,
+            BlendType::Alpha,
+            "text",
+            self.vertex_shader.clone(),
+            self.fragment_shader.clone());
+
+        let (viewport, scissor) = {
+            let v = vk::Viewport::builder()
+                .x(0.0)
+                .y(0.0)
+                .width(extent.width as f32)
+                .height(extent.height as f32)
+                .min_depth(0.0)
+                .max_depth(1.0)
+                .build();
+
+            let s = vk::Rect2D::builder()
+                .offset(vk::Offset2D{x: 0, y: 0})
+                .extent(vk::Extent2D{width: extent.width, height: extent.height})
+                .build();
+
+            (v, s)
+        };
+
+        let pass_node = GraphicsPassNode::builder("text".to_string())
+            .pipeline_description(pipeline_description)
+            .render_target(render_target)
+            .read(atlas_binding)
+            .read(display_binding)
+            .vertex_buffer(upload_resource, vertex_offset)
+            .viewport(viewport)
+            .scissor(scissor)
+            .fill_commands(Box::new(
+                move |render_ctx: &VulkanRenderContext,
+                      command_buffer: &vk::CommandBuffer | {
+                    unsafe {
+                        enter_span!(tracing::Level::TRACE, "Text Draw");
+                        let _gpu_scope = render_ctx.get_profiler().scope("Text Draw GPU", command_buffer);
+                        render_ctx.get_device().borrow().get().cmd_draw(
+                            *command_buffer,
+                            vertex_count,
+                            1,
+                            0,
+                            0);
+                    }
+                }
+            ))
+            .build()
+            .expect("Failed to create text passnode");
+
+        Some(PassType::Graphics(pass_node))
+    }
+}
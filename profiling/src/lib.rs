@@ -1,26 +1,51 @@
-use std::ops::DerefMut;
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::panic::Location;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::{Duration, Instant};
 use tracy_client;
-use std::sync::{Mutex};
 use ash::vk;
+use ash::extensions::ext::CalibratedTimestamps;
 use tracy_client::{GpuContext, GpuContextType, GpuSpan};
 
 struct ClosedGpuSpan {
     span: Option<GpuSpan>,
+    name: String,
     start_query_id: u32,
     end_query_id: u32
 }
 
 impl ClosedGpuSpan {
-    fn new(span: Option<GpuSpan>, start_query_id: u32, end_query_id: u32) -> Self {
+    fn new(span: Option<GpuSpan>, name: String, start_query_id: u32, end_query_id: u32) -> Self {
         ClosedGpuSpan{
             span,
+            name,
             start_query_id,
             end_query_id,
         }
     }
 }
 
+/// How long a single GPU span took to execute
+#[derive(Clone, Debug)]
+pub struct GpuSpanTiming {
+    pub name: String,
+    pub duration: Duration
+}
+
+/// The GPU timings of a queue's spans for a single frame, in the order they were closed
+#[derive(Clone, Debug, Default)]
+pub struct GpuFrameTimings {
+    pub spans: Vec<GpuSpanTiming>,
+    /// From the start of the frame's first span to the end of its last
+    pub total: Duration
+}
+
 pub struct OpenGpuSpan<'a> {
+    frame: &'a FrameSpans,
+    name: String,
     query_id: u32,
     device: &'a ash::Device,
     command_buffer: &'a vk::CommandBuffer,
@@ -33,55 +58,109 @@ pub struct OpenGpuSpan<'a> {
 
 impl Drop for OpenGpuSpan<'_> {
     fn drop(&mut self) {
-        let mut span_mutex = GPU_SPAN_MANAGER.lock().unwrap();
-        match span_mutex.as_mut() {
-            None => {
-                panic!("Attempting to close GPU span before GpuSpanManager was initialized")
-            }
-            Some(span_manager) => {
-                span_manager.close_gpu_span(
-                    std::mem::take(&mut self.span),
-                    self.query_id,
-                    self.command_buffer,
-                    self.device,
-                    self.pipeline_stage);
-            }
+        // opened while spans were suspended
+        if self.span.is_none() {
+            return;
         }
+        self.frame.close_gpu_span(
+            std::mem::take(&mut self.span),
+            std::mem::take(&mut self.name),
+            self.query_id,
+            self.command_buffer,
+            self.device,
+            self.pipeline_stage);
     }
 }
 
-impl<'a> OpenGpuSpan<'a> {
-    fn new(
-        span: GpuSpan,
-        query_id: u32,
-        device: &'a ash::Device,
-        command_buffer: &'a vk::CommandBuffer,
-        pipeline_stage: vk::PipelineStageFlags) -> Self {
+/// A GPU span opened through [`FramegraphProfiler::scope`]. Unlike [`OpenGpuSpan`] it
+/// doesn't borrow the profiler or device, so it can be held across calls which need
+/// mutable access to whatever owns them. It must be dropped before `command_buffer`
+/// finishes recording
+pub struct GpuScope {
+    closed_spans: Arc<Mutex<Vec<ClosedGpuSpan>>>,
+    name: String,
+    query_pool: vk::QueryPool,
+    query_id: u32,
+    command_buffer: vk::CommandBuffer,
+    pipeline_stage: vk::PipelineStageFlags,
+    cmd_write_timestamp: vk::PFN_vkCmdWriteTimestamp,
+    // see OpenGpuSpan::span
+    span: Option<GpuSpan>
+}
 
-        OpenGpuSpan {
-            query_id,
-            device,
-            command_buffer,
-            pipeline_stage,
-            span: Some(span)
+impl Drop for GpuScope {
+    fn drop(&mut self) {
+        let mut span = std::mem::take(&mut self.span);
+        // opened while spans were suspended
+        if span.is_none() {
+            return;
         }
+        span.as_mut().unwrap().end_zone();
+
+        let end_query_id = self.query_id + 1;
+        unsafe {
+            (self.cmd_write_timestamp)(
+                self.command_buffer,
+                self.pipeline_stage,
+                self.query_pool,
+                end_query_id);
+        }
+
+        self.closed_spans.lock().unwrap().push(ClosedGpuSpan::new(
+            span,
+            std::mem::take(&mut self.name),
+            self.query_id,
+            end_query_id,
+        ));
     }
 }
 
-const MAX_QUERIES: u32 = 128;
+const MAX_QUERIES: u32 = 256;
+
+thread_local! {
+    // the number of live SuspendedGpuSpans guards on this thread
+    static SUSPENDED_GPU_SPANS: Cell<u32> = const { Cell::new(0) };
+}
+
+/// Makes every GPU span opened on the current thread a no-op until the returned guard drops.
+/// Command buffers which are recorded once and executed on later frames can't contain
+/// spans, whose timestamps would be written to the query pool of the frame they were
+/// recorded in; spans in the primary command buffer executing them time them instead
+pub fn suspend_gpu_spans() -> SuspendedGpuSpans {
+    SUSPENDED_GPU_SPANS.with(|suspended| suspended.set(suspended.get() + 1));
+    SuspendedGpuSpans { _thread_bound: PhantomData }
+}
+
+fn gpu_spans_suspended() -> bool {
+    SUSPENDED_GPU_SPANS.with(|suspended| suspended.get() > 0)
+}
+
+/// See [`suspend_gpu_spans`]
+pub struct SuspendedGpuSpans {
+    // the suspension only applies to the thread which created the guard
+    _thread_bound: PhantomData<*const ()>
+}
+
+impl Drop for SuspendedGpuSpans {
+    fn drop(&mut self) {
+        SUSPENDED_GPU_SPANS.with(|suspended| suspended.set(suspended.get() - 1));
+    }
+}
 
 struct FrameSpans {
     query_pool: vk::QueryPool,
-    active_spans: Vec<ClosedGpuSpan>,
+    // spans reserve their start and end queries together when opened, so any number of
+    // threads can record spans into the same frame without locking
+    query_index: AtomicU32,
+    closed_spans: Arc<Mutex<Vec<ClosedGpuSpan>>>,
     max_queries: u32,
-    query_index: u32,
     ready: bool,
     data: [i64; MAX_QUERIES as usize]
 }
 
 impl FrameSpans {
-    pub fn reset(&mut self, device: &ash::Device) {
-        self.query_index = 0;
+    fn reset(&mut self, device: &ash::Device) {
+        *self.query_index.get_mut() = 0;
         unsafe {
             device.reset_query_pool(
                 self.query_pool,
@@ -89,214 +168,356 @@ impl FrameSpans {
                 self.max_queries-1
             );
         }
-        self.active_spans.clear();
+        self.closed_spans.lock().unwrap().clear();
         self.ready = true;
     }
 
-    pub fn flush(&mut self, device: &ash::Device) {
-        // if query_index is still 0, we haven't written a query yet
-        if (self.query_index > 0) {
+    /// Uploads the frame's spans to tracy and returns their timings. `timestamp_period` is
+    /// the nanoseconds per timestamp tick
+    fn flush(&mut self, device: &ash::Device, timestamp_period: f32) -> GpuFrameTimings {
+        let mut timings = GpuFrameTimings::default();
+        let query_count = *self.query_index.get_mut();
+        // if query_count is still 0, we haven't written a query yet
+        if query_count > 0 {
             unsafe {
                 device.get_query_pool_results(
                     self.query_pool,
                     0,
-                    self.query_index,
-                    &mut self.data,
+                    query_count,
+                    &mut self.data[..query_count as usize],
                     vk::QueryResultFlags::TYPE_64 | vk::QueryResultFlags::WAIT)
                     .expect("Failed to retrieve query results");
             }
 
-            for active_span in &mut self.active_spans {
-                let start_timestamp = self.data[active_span.start_query_id as usize];
-                let end_timestamp = self.data[active_span.end_query_id as usize];
-
-                let mut gpu_span = None;
-                std::mem::swap(&mut gpu_span, &mut active_span.span);
-
-                match gpu_span {
+            let ticks_to_duration = |ticks: i64| {
+                Duration::from_nanos((ticks.max(0) as f64 * timestamp_period as f64) as u64)
+            };
+            let mut frame_range: Option<(i64, i64)> = None;
+            for closed_span in self.closed_spans.lock().unwrap().iter_mut() {
+                let start_timestamp = self.data[closed_span.start_query_id as usize];
+                let end_timestamp = self.data[closed_span.end_query_id as usize];
+
+                timings.spans.push(GpuSpanTiming {
+                    name: std::mem::take(&mut closed_span.name),
+                    duration: ticks_to_duration(end_timestamp - start_timestamp)
+                });
+                frame_range = Some(match frame_range {
+                    Some((first, last)) => (first.min(start_timestamp), last.max(end_timestamp)),
+                    None => (start_timestamp, end_timestamp)
+                });
+
+                match closed_span.span.take() {
                     None => {
                         panic!("Attempting to upload an invalid GPU span");
                     }
                     Some(span) => {
-                        span.upload_timestamp(start_timestamp as i64, end_timestamp as i64);
+                        span.upload_timestamp(start_timestamp, end_timestamp);
                     }
                 }
             }
+            if let Some((first, last)) = frame_range {
+                timings.total = ticks_to_duration(last - first);
+            }
         }
         self.ready = false;
+        timings
     }
 
-    pub fn new_gpu_span<'a>(
-        &mut self,
+    /// Reserves the span's start and end queries and writes the start timestamp
+    fn begin_span(
+        &self,
         name: &str,
         file: &str,
         function: &str,
         line_number: u32,
         gpu_context: &GpuContext,
-        device: &'a ash::Device,
-        command_buffer: &'a vk::CommandBuffer,
-        pipeline_stage: vk::PipelineStageFlags) -> OpenGpuSpan<'a> {
+        device: &ash::Device,
+        command_buffer: &vk::CommandBuffer,
+        pipeline_stage: vk::PipelineStageFlags) -> (u32, GpuSpan) {
 
         assert!(self.ready, "Attempting to create GPU span before resetting the query pool");
-        assert!(self.query_index < self.max_queries, "Overallocating GPU timespan queries");
+        let query_index = self.query_index.fetch_add(2, Ordering::Relaxed);
+        assert!(query_index + 1 < self.max_queries, "Overallocating GPU timespan queries");
 
         let new_span = gpu_context.span_alloc(name, function, file, line_number)
             .expect("Failed to create new GPU span");
 
-        let query_index = self.query_index;
-
         unsafe {
             device.cmd_write_timestamp(
                 *command_buffer,
                 pipeline_stage,
-                self.query_pool.clone(),
+                self.query_pool,
                 query_index
             );
         }
 
-        self.query_index += 1;
-        OpenGpuSpan::new(
-            new_span,
-            query_index,
+        (query_index, new_span)
+    }
+
+    fn new_gpu_span<'a>(
+        &'a self,
+        name: &str,
+        file: &str,
+        function: &str,
+        line_number: u32,
+        gpu_context: &GpuContext,
+        device: &'a ash::Device,
+        command_buffer: &'a vk::CommandBuffer,
+        pipeline_stage: vk::PipelineStageFlags) -> OpenGpuSpan<'a> {
+
+        if gpu_spans_suspended() {
+            return OpenGpuSpan {
+                frame: self,
+                name: String::new(),
+                query_id: 0,
+                device,
+                command_buffer,
+                pipeline_stage,
+                span: None
+            };
+        }
+        let (query_index, new_span) = self.begin_span(
+            name, file, function, line_number, gpu_context, device, command_buffer, pipeline_stage);
+
+        OpenGpuSpan {
+            frame: self,
+            name: name.to_string(),
+            query_id: query_index,
             device,
             command_buffer,
-            pipeline_stage)
+            pipeline_stage,
+            span: Some(new_span)
+        }
     }
 
-    pub fn close_gpu_span(
-        &mut self,
+    fn new_gpu_scope(
+        &self,
+        name: &str,
+        file: &str,
+        function: &str,
+        line_number: u32,
+        gpu_context: &GpuContext,
+        device: &ash::Device,
+        command_buffer: &vk::CommandBuffer,
+        pipeline_stage: vk::PipelineStageFlags) -> GpuScope {
+
+        let (query_index, new_span) = match gpu_spans_suspended() {
+            true => (0, None),
+            false => {
+                let (query_index, new_span) = self.begin_span(
+                    name, file, function, line_number, gpu_context, device, command_buffer, pipeline_stage);
+                (query_index, Some(new_span))
+            }
+        };
+
+        GpuScope {
+            closed_spans: self.closed_spans.clone(),
+            name: name.to_string(),
+            query_pool: self.query_pool,
+            query_id: query_index,
+            command_buffer: *command_buffer,
+            pipeline_stage,
+            cmd_write_timestamp: device.fp_v1_0().cmd_write_timestamp,
+            span: new_span
+        }
+    }
+
+    fn close_gpu_span(
+        &self,
         mut span: Option<GpuSpan>,
+        name: String,
         start_query_id: u32,
         command_buffer: &vk::CommandBuffer,
         device: &ash::Device,
         pipeline_stage: vk::PipelineStageFlags) {
 
-        assert!(self.ready, "Attempting to close GPU span before resetting the query pool");
-        assert!(self.query_index < self.max_queries, "Overallocating GPU timespan queries");
-
         span.as_mut().unwrap().end_zone();
 
+        let end_query_id = start_query_id + 1;
         unsafe {
             device.cmd_write_timestamp(
                 *command_buffer,
                 pipeline_stage,
-                self.query_pool.clone(),
-                self.query_index
+                self.query_pool,
+                end_query_id
             );
         }
 
-        self.active_spans.push(ClosedGpuSpan::new(
+        self.closed_spans.lock().unwrap().push(ClosedGpuSpan::new(
             span,
+            name,
             start_query_id,
-             self.query_index,
+            end_query_id,
         ));
+    }
+}
+
+/// Reads the current GPU timestamp through VK_EXT_calibrated_timestamps, which
+/// samples the device clock directly instead of waiting on a queue submission
+fn calibrated_gpu_timestamp(device: &ash::Device, calibrated_timestamps: &CalibratedTimestamps) -> (i64, Instant) {
+    let timestamp_info = vk::CalibratedTimestampInfoEXT::builder()
+        .time_domain(vk::TimeDomainEXT::DEVICE)
+        .build();
+
+    let sampled_at = Instant::now();
+    let (timestamps, _) = unsafe {
+        calibrated_timestamps.get_calibrated_timestamps(
+            device.handle(),
+            std::slice::from_ref(&timestamp_info))
+            .expect("Failed to get calibrated GPU timestamp")
+    };
+
+    (timestamps[0] as i64, sampled_at)
+}
 
-        self.query_index += 1;
+/// Writes a timestamp query on `queue` and waits for it. The query executes at some point
+/// between submission and the end of the wait, so the midpoint is used as its CPU time
+fn submitted_gpu_timestamp(
+    device: &ash::Device,
+    query_pool: vk::QueryPool,
+    command_buffer: &vk::CommandBuffer,
+    queue: &vk::Queue) -> (i64, Instant) {
+
+    let mut timestamp_value: i64 = 0;
+    unsafe {
+        device.reset_query_pool(
+            query_pool,
+            0,
+            1
+        );
+
+        let begin_info = vk::CommandBufferBeginInfo::builder()
+            .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT)
+            .build();
+        device.begin_command_buffer(*command_buffer, &begin_info)
+            .expect("Failed to begin profiling command buffer");
+
+        device.cmd_write_timestamp(
+            *command_buffer,
+            vk::PipelineStageFlags::TOP_OF_PIPE,
+            query_pool,
+            0
+        );
+
+        device.end_command_buffer(*command_buffer)
+            .expect("Failed to end profiling command buffer");
+
+        let submit_info = vk::SubmitInfo::builder()
+            .command_buffers(std::slice::from_ref(command_buffer))
+            .build();
+
+        let submitted_at = Instant::now();
+        device.queue_submit(
+            *queue,
+            std::slice::from_ref(&submit_info),
+            vk::Fence::null()
+        ).expect("Failed to submit queue for profiling");
+
+        device.queue_wait_idle(*queue)
+            .expect("Failed to wait for idle for profiling");
+        let completed_at = Instant::now();
+
+        device.get_query_pool_results(
+            query_pool,
+            0,
+            1,
+            std::slice::from_mut(&mut timestamp_value),
+            vk::QueryResultFlags::TYPE_64 | vk::QueryResultFlags::WAIT
+        ).expect("Failed to retrieve initial GPU timestamp");
+
+        (timestamp_value, submitted_at + (completed_at - submitted_at) / 2)
+    }
+}
 
+/// The queue a GPU span's commands execute on. Each queue profiled gets its own tracy
+/// GPU context and query pools, so its work appears on a separate timeline row
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum GpuQueue {
+    Graphics,
+    Compute,
+    Transfer
+}
+
+impl GpuQueue {
+    fn get_context_name(&self) -> &'static str {
+        match self {
+            GpuQueue::Graphics => { "VulkanContext" },
+            GpuQueue::Compute => { "VulkanContext (compute)" },
+            GpuQueue::Transfer => { "VulkanContext (transfer)" }
+        }
     }
 }
 
-pub struct GpuSpanManager {
+struct QueueSpans {
     frames: Vec<FrameSpans>,
     frame_index: usize,
-    gpu_context: GpuContext
+    gpu_context: GpuContext,
+    timestamp_period: f32,
+    // the most recently flushed frame's timings
+    last_timings: GpuFrameTimings
 }
 
-static GPU_SPAN_MANAGER: Mutex<Option<GpuSpanManager>> = Mutex::new(None);
-
-impl GpuSpanManager {
-    pub fn init(
+impl QueueSpans {
+    fn new(
+        gpu_queue: GpuQueue,
         device: &ash::Device,
         timestamp_period: f32,
+        calibrated_timestamps: Option<&CalibratedTimestamps>,
         command_buffer: &vk::CommandBuffer,
         queue: &vk::Queue,
-        num_frames: u32) {
+        num_frames: u32) -> Self {
 
-        unsafe {
-            assert!(GPU_SPAN_MANAGER.lock().unwrap().is_none(), "Can only initialize a single GpuSpanManagera");
+        let mut frames: Vec<FrameSpans> = Vec::new();
 
-            let mut frames: Vec<FrameSpans> = Vec::new();
+        let query_pool_create = vk::QueryPoolCreateInfo::builder()
+            .query_type(vk::QueryType::TIMESTAMP)
+            .query_count(MAX_QUERIES)
+            .build();
 
-            let query_pool_create = vk::QueryPoolCreateInfo::builder()
-                .query_type(vk::QueryType::TIMESTAMP)
-                .query_count(MAX_QUERIES)
-                .build();
-
-            for _i in 0..num_frames {
-                let query_pool = device.create_query_pool(
+        for _i in 0..num_frames {
+            let query_pool = unsafe {
+                device.create_query_pool(
                     &query_pool_create,
                     None
-                ).expect("Failed to create query pool");
-
-                frames.push(FrameSpans {
-                    query_pool,
-                    active_spans: vec![],
-                    max_queries: MAX_QUERIES,
-                    query_index: 0,
-                    ready: false,
-                    data: [0; MAX_QUERIES as usize],
-                })
-            }
-
-            // initial timestamp query
-            let mut timestamp_value: i64 = 0;
-            unsafe {
-                device.reset_query_pool(
-                    frames[0].query_pool,
-                    0,
-                    1
-                );
-
-                let begin_info = vk::CommandBufferBeginInfo::builder()
-                    .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT)
-                    .build();
-                device.begin_command_buffer(*command_buffer, &begin_info);
-
-                device.cmd_write_timestamp(
-                    *command_buffer,
-                    vk::PipelineStageFlags::ALL_GRAPHICS,
-                    frames[0].query_pool.clone(),
-                    0
-                );
-
-                device.end_command_buffer(*command_buffer);
-
-                let submit_info = vk::SubmitInfo::builder()
-                    .command_buffers(std::slice::from_ref(command_buffer))
-                    .build();
-
-                device.queue_submit(
-                    queue.clone(),
-                    std::slice::from_ref(&submit_info),
-                    vk::Fence::null()
-                ).expect("Failed to submit queue for profiling");
-
-                device.device_wait_idle()
-                    .expect("Failed to wait for idle for profiling");
+                ).expect("Failed to create query pool")
+            };
+
+            frames.push(FrameSpans {
+                query_pool,
+                query_index: AtomicU32::new(0),
+                closed_spans: Arc::new(Mutex::new(vec![])),
+                max_queries: MAX_QUERIES,
+                ready: false,
+                data: [0; MAX_QUERIES as usize],
+            })
+        }
 
-                device.get_query_pool_results(
-                    frames[0].query_pool,
-                    0,
-                    1,
-                    std::slice::from_mut(&mut timestamp_value),
-                    vk::QueryResultFlags::TYPE_64 | vk::QueryResultFlags::WAIT
-                ).expect("Failed to retrieve initial GPU timestamp");
+        let (timestamp_value, sampled_at) = match calibrated_timestamps {
+            Some(calibrated_timestamps) => {
+                calibrated_gpu_timestamp(device, calibrated_timestamps)
+            },
+            None => {
+                submitted_gpu_timestamp(device, frames[0].query_pool, command_buffer, queue)
             }
-
-            let tc = tracy_client::Client::start();
-            let gpu_context = tc.new_gpu_context(
-                Some("VulkanContext"),
-                GpuContextType::Vulkan,
-                timestamp_value as i64,
-                timestamp_period)
-                .expect("Failed to create GPU profiling context");
-
-            *GPU_SPAN_MANAGER.lock().unwrap() = Some(GpuSpanManager {
-                frames,
-                frame_index: 0,
-                gpu_context
-            });
-
+        };
+
+        let tc = tracy_client::Client::start();
+        // tracy pairs the GPU timestamp it's given with the CPU time at which the context
+        // is created, so advance the sample by however long has passed since it was taken
+        let elapsed_ticks = (sampled_at.elapsed().as_nanos() as f64 / timestamp_period as f64) as i64;
+        let gpu_context = tc.new_gpu_context(
+            Some(gpu_queue.get_context_name()),
+            GpuContextType::Vulkan,
+            timestamp_value + elapsed_ticks,
+            timestamp_period)
+            .expect("Failed to create GPU profiling context");
+
+        QueueSpans {
+            frames,
+            frame_index: 0,
+            gpu_context,
+            timestamp_period,
+            last_timings: GpuFrameTimings::default()
         }
     }
 
@@ -304,28 +525,111 @@ impl GpuSpanManager {
         self.frame_index = (self.frame_index + 1) % self.frames.len();
         match self.frames.get_mut(self.frame_index) {
             None => {
-                panic!("Attempting to reset GpuSpanManager frame with invalid index");
+                panic!("Attempting to reset GpuProfiler frame with invalid index");
             }
             Some(frame) => {
-                frame.flush(device);
+                let timings = frame.flush(device, self.timestamp_period);
+                // a frame which recorded no spans, e.g. the first use of its queries, keeps
+                // the previous timings
+                if !timings.spans.is_empty() {
+                    self.last_timings = timings;
+                }
                 frame.reset(device);
             }
         }
     }
+}
 
-    fn flush(&mut self, device: &ash::Device) {
-        match self.frames.get_mut(self.frame_index) {
+/// Owns the query pools and tracy GPU contexts for every profiled queue. Spans are opened
+/// through a shared reference so command buffers can be recorded on several threads at
+/// once; only moving to the next frame requires exclusive access.
+pub struct GpuProfiler {
+    queues: HashMap<GpuQueue, QueueSpans>
+}
+
+impl GpuProfiler {
+    /// Creates a profiler for the graphics queue.
+    /// `calibrated_timestamps` should be provided when the device supports
+    /// VK_EXT_calibrated_timestamps with the DEVICE time domain; otherwise the initial
+    /// GPU timestamp is taken with a submitted query
+    pub fn new(
+        device: &ash::Device,
+        timestamp_period: f32,
+        calibrated_timestamps: Option<&CalibratedTimestamps>,
+        command_buffer: &vk::CommandBuffer,
+        queue: &vk::Queue,
+        num_frames: u32) -> Self {
+
+        let mut profiler = GpuProfiler {
+            queues: HashMap::new()
+        };
+        profiler.add_queue(
+            GpuQueue::Graphics,
+            device,
+            timestamp_period,
+            calibrated_timestamps,
+            command_buffer,
+            queue,
+            num_frames);
+
+        profiler
+    }
+
+    /// Adds a timeline for another queue. `command_buffer` must be allocated from a pool
+    /// for `queue`'s family, and that family must support timestamp queries
+    pub fn add_queue(
+        &mut self,
+        gpu_queue: GpuQueue,
+        device: &ash::Device,
+        timestamp_period: f32,
+        calibrated_timestamps: Option<&CalibratedTimestamps>,
+        command_buffer: &vk::CommandBuffer,
+        queue: &vk::Queue,
+        num_frames: u32) {
+
+        assert!(!self.queues.contains_key(&gpu_queue), "GPU queue {:?} is already being profiled", gpu_queue);
+        let queue_spans = QueueSpans::new(
+            gpu_queue,
+            device,
+            timestamp_period,
+            calibrated_timestamps,
+            command_buffer,
+            queue,
+            num_frames);
+        self.queues.insert(gpu_queue, queue_spans);
+    }
+
+    /// Uploads the previous use of the next frame's queries and resets them. The frame's
+    /// fence must have signaled
+    pub fn reset(&mut self, device: &ash::Device) {
+        for queue_spans in self.queues.values_mut() {
+            queue_spans.reset(device);
+        }
+    }
+
+    /// The timings of the most recent frame whose spans on `gpu_queue` have been uploaded by
+    /// reset, which lags the frame being recorded by the number of frames in flight. None if
+    /// the queue isn't profiled
+    pub fn get_last_timings(&self, gpu_queue: GpuQueue) -> Option<&GpuFrameTimings> {
+        self.queues.get(&gpu_queue).map(|queue_spans| &queue_spans.last_timings)
+    }
+
+    fn get_current_frame(&self, gpu_queue: GpuQueue) -> (&FrameSpans, &GpuContext) {
+        let queue_spans = self.queues.get(&gpu_queue)
+            .unwrap_or_else(|| panic!("GPU queue {:?} has not been added to the GpuProfiler", gpu_queue));
+        match queue_spans.frames.get(queue_spans.frame_index) {
             None => {
-                panic!("Attempting to flush GpuSpanManager frame with invalid index");
+                panic!("Attempting to open a GPU span for a frame with invalid index");
             }
             Some(frame) => {
-                frame.flush(device);
+                (frame, &queue_spans.gpu_context)
             }
         }
     }
 
-    fn new_gpu_span<'a>(
-        &mut self,
+    pub fn new_gpu_span<'a>(
+        &'a self,
+        gpu_queue: GpuQueue,
         name: &str,
         file: &str,
         function: &str,
@@ -334,86 +638,100 @@ impl GpuSpanManager {
         command_buffer: &'a vk::CommandBuffer,
         pipeline_stage: vk::PipelineStageFlags) -> OpenGpuSpan<'a> {
 
-        match self.frames.get_mut(self.frame_index) {
-            None => {
-                panic!("Attempting to flush GpuSpanManager frame with invalid index");
-            }
-            Some(frame) => {
-                frame.new_gpu_span(name, file, function, line_number, &self.gpu_context, device, command_buffer, pipeline_stage)
-            }
-        }
+        let (frame, gpu_context) = self.get_current_frame(gpu_queue);
+        frame.new_gpu_span(name, file, function, line_number, gpu_context, device, command_buffer, pipeline_stage)
     }
 
-    fn close_gpu_span(
-        &mut self,
-        span: Option<GpuSpan>,
-        start_query_id: u32,
-        command_buffer: &vk::CommandBuffer,
-        device: &ash::Device,
-        pipeline_stage: vk::PipelineStageFlags) {
-
-        match self.frames.get_mut(self.frame_index) {
-            None => {
-                panic!("Attempting to flush GpuSpanManager frame with invalid index");
-            }
-            Some(frame) => {
-                frame.close_gpu_span(span, start_query_id, command_buffer, device, pipeline_stage);
+    /// Destroys every query pool; the device must be idle
+    pub fn destroy(&mut self, device: &ash::Device) {
+        for queue_spans in self.queues.values() {
+            for frame in &queue_spans.frames {
+                unsafe {
+                    device.destroy_query_pool(frame.query_pool, None);
+                }
             }
         }
+        self.queues.clear();
     }
 }
 
-pub fn reset_span_manager(device: &ash::Device) {
-    let mut span_mutex = GPU_SPAN_MANAGER.lock().unwrap();
-    match span_mutex.as_mut() {
-        None => {
-            panic!("Attempting to enter GPU span before GpuSpanManager was initialized")
-        }
-        Some(span_manager) => {
-            span_manager.reset(device);
+/// Wraps a [`GpuProfiler`] with its own handle to the device, so spans can be opened with
+/// just a name and command buffer. Spans are timestamped at ALL_COMMANDS, which brackets
+/// everything recorded between the guard's creation and its drop
+pub struct FramegraphProfiler {
+    gpu_profiler: GpuProfiler,
+    device: ash::Device
+}
+
+impl FramegraphProfiler {
+    pub fn new(gpu_profiler: GpuProfiler, device: ash::Device) -> Self {
+        FramegraphProfiler {
+            gpu_profiler,
+            device
         }
     }
-}
 
-pub fn new_gpu_span<'a>(
-    name: &str,
-    file: &str,
-    function: &str,
-    line_number: u32,
-    device: &'a ash::Device,
-    command_buffer: &'a vk::CommandBuffer,
-    pipeline_stage: vk::PipelineStageFlags) -> OpenGpuSpan<'a> {
+    /// Opens a span on the graphics queue's timeline, ended when the returned guard drops.
+    /// The span's source location is the caller's
+    #[track_caller]
+    pub fn scope(&self, name: &str, command_buffer: &vk::CommandBuffer) -> GpuScope {
+        self.queue_scope(GpuQueue::Graphics, name, command_buffer)
+    }
 
-    let mut span_mutex = GPU_SPAN_MANAGER.lock().unwrap();
-    match span_mutex.as_mut() {
-        None => {
-            panic!("Attempting to enter GPU span before GpuSpanManager was initialized")
-        }
-        Some(span_manager) => {
-            span_manager.new_gpu_span(name, file, function, line_number, device, command_buffer, pipeline_stage)
-        }
+    /// Opens a span for a command buffer which will be submitted to `gpu_queue`
+    #[track_caller]
+    pub fn queue_scope(&self, gpu_queue: GpuQueue, name: &str, command_buffer: &vk::CommandBuffer) -> GpuScope {
+        let location = Location::caller();
+        let (frame, gpu_context) = self.gpu_profiler.get_current_frame(gpu_queue);
+        frame.new_gpu_scope(
+            name,
+            location.file(),
+            name,
+            location.line(),
+            gpu_context,
+            &self.device,
+            command_buffer,
+            vk::PipelineStageFlags::ALL_COMMANDS)
+    }
+
+    pub fn get_gpu_profiler(&self) -> &GpuProfiler { &self.gpu_profiler }
+
+    pub fn get_gpu_profiler_mut(&mut self) -> &mut GpuProfiler { &mut self.gpu_profiler }
+
+    /// See [`GpuProfiler::reset`]
+    pub fn reset(&mut self) {
+        self.gpu_profiler.reset(&self.device);
+    }
+
+    /// See [`GpuProfiler::destroy`]
+    pub fn destroy(&mut self) {
+        self.gpu_profiler.destroy(&self.device);
     }
 }
 
 #[macro_export]
 macro_rules! init_gpu_profiling {
-    ($device:expr, $period:expr, $cb:expr, $queue:expr, $num_frames:expr) => {
-        profiling::GpuSpanManager::init($device, $period, $cb, $queue, $num_frames);
+    ($device:expr, $period:expr, $calibrated_timestamps:expr, $cb:expr, $queue:expr, $num_frames:expr) => {
+        $crate::GpuProfiler::new($device, $period, $calibrated_timestamps, $cb, $queue, $num_frames)
     }
 }
 
 #[macro_export]
 macro_rules! reset_gpu_profiling {
-    ($device:expr) => {
-        profiling::reset_span_manager($device);
+    ($profiler:expr, $device:expr) => {
+        $profiler.reset($device);
     }
 }
 
 #[macro_export]
 macro_rules! enter_gpu_span {
-    ($name:expr, $function:expr, $device:expr, $command_buffer:expr, $pipeline_stage:expr) => {
-        let _gpu_span = profiling::new_gpu_span($name, file!(), $function, line!(), $device, $command_buffer, $pipeline_stage);
-    }
+    ($profiler:expr, $queue:expr, $name:expr, $function:expr, $device:expr, $command_buffer:expr, $pipeline_stage:expr) => {
+        let _gpu_span = $profiler.new_gpu_span($queue, $name, file!(), $function, line!(), $device, $command_buffer, $pipeline_stage);
+    };
+
+    ($profiler:expr, $name:expr, $function:expr, $device:expr, $command_buffer:expr, $pipeline_stage:expr) => {
+        $crate::enter_gpu_span!($profiler, $crate::GpuQueue::Graphics, $name, $function, $device, $command_buffer, $pipeline_stage)
+    };
 }
 
 // https://docs.vulkan.org/spec/latest/chapters/queries.html#queries-timestamps
@@ -449,4 +767,23 @@ mod tests {
         let result = add(2, 2);
         assert_eq!(result, 4);
     }
+
+    #[test]
+    fn suspended_gpu_spans_nest() {
+        assert!(!gpu_spans_suspended());
+        let outer = suspend_gpu_spans();
+        {
+            let _inner = suspend_gpu_spans();
+            assert!(gpu_spans_suspended());
+        }
+        assert!(gpu_spans_suspended());
+        drop(outer);
+        assert!(!gpu_spans_suspended());
+    }
+
+    #[test]
+    fn suspending_gpu_spans_only_affects_the_current_thread() {
+        let _suspended = suspend_gpu_spans();
+        assert!(!std::thread::spawn(gpu_spans_suspended).join().unwrap());
+    }
 }
@@ -160,11 +160,10 @@ impl VulkanApp {
     fn draw_frame(&mut self, mouse_pos: (f32, f32), mouse_down: bool) {
         let wait_fences = [self.in_flight_fences[self.current_frame]];
 
+        // resources used by the previous submission of this frame are owned by
+        // self.frames[self.current_frame] and released once this fence has signaled
         unsafe
         {
-            self.render_context.get_device().borrow().get()
-                .device_wait_idle()
-                .expect("Error while waiting for device to idle");
             self.render_context.get_device().borrow().get()
                 .wait_for_fences(&wait_fences, true, u64::MAX)
                 .expect("Failed to wait for Fence!");
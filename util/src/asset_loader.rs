@@ -0,0 +1,784 @@
+//! Loads assets without stalling frames: IO and decoding run on worker threads, then each
+//! [`AssetLoader::poll`] uploads whatever has been decoded in one submission with its own
+//! fence. An [`AssetHandle`] becomes ready once its upload has completed on the GPU, so
+//! passes can poll it each frame and draw the asset as soon as it's available.
+//!
+//! On devices with a dedicated transfer queue the uploads run on it, alongside rendering.
+//! Their resources are then released from the transfer family, and acquired by the graphics
+//! family in a small graphics submission once the transfer has completed.
+
+use std::cell::{Ref, RefCell};
+use std::ffi::c_void;
+use std::rc::Rc;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread::JoinHandle;
+
+use ash::vk;
+use ash::vk::Handle;
+use gpu_allocator::MemoryLocation;
+use api_types::buffer::BufferCreateInfo;
+use api_types::device::{DeviceResource, DeviceWrapper};
+use api_types::image::{ImageCreateInfo, ImageType};
+use api_types::resource_state::ResourceState;
+use context::render_context::RenderContext;
+use context::vulkan_render_context::VulkanRenderContext;
+
+/// How much staging memory a single poll uploads before leaving the rest for later polls
+pub const DEFAULT_UPLOAD_BUDGET: vk::DeviceSize = 32 * 1024 * 1024;
+
+#[derive(Debug)]
+pub enum AssetState<T> {
+    /// Being read and decoded on a worker thread
+    Loading,
+    /// Waiting for its upload to complete on the GPU
+    Uploading,
+    Ready(T),
+    Failed(String)
+}
+
+/// Shared with the loader, which fills it in as the asset progresses
+pub struct AssetHandle<T> {
+    name: String,
+    state: Rc<RefCell<AssetState<T>>>
+}
+
+impl<T> Clone for AssetHandle<T> {
+    fn clone(&self) -> Self {
+        AssetHandle {
+            name: self.name.clone(),
+            state: self.state.clone()
+        }
+    }
+}
+
+impl<T> std::fmt::Debug for AssetHandle<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let state = match &*self.state.borrow() {
+            AssetState::Loading => "Loading",
+            AssetState::Uploading => "Uploading",
+            AssetState::Ready(_) => "Ready",
+            AssetState::Failed(_) => "Failed"
+        };
+        f.debug_struct("AssetHandle")
+            .field("name", &self.name)
+            .field("state", &state)
+            .finish()
+    }
+}
+
+impl<T> AssetHandle<T> {
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn is_ready(&self) -> bool {
+        matches!(&*self.state.borrow(), AssetState::Ready(_))
+    }
+
+    /// The asset once its upload has completed
+    pub fn get(&self) -> Option<Ref<T>> {
+        Ref::filter_map(self.state.borrow(), |state| match state {
+            AssetState::Ready(asset) => Some(asset),
+            _ => None
+        }).ok()
+    }
+
+    pub fn get_error(&self) -> Option<String> {
+        match &*self.state.borrow() {
+            AssetState::Failed(error) => Some(error.clone()),
+            _ => None
+        }
+    }
+}
+
+/// Records an asset's uploads into the poll's submission. Resources it returns can't be used
+/// until the asset's handle is ready
+pub struct UploadBatch {
+    device: Rc<RefCell<DeviceWrapper>>,
+    command_buffer: vk::CommandBuffer,
+    recording: bool,
+    staging_buffers: Vec<DeviceResource>,
+    staged_size: vk::DeviceSize,
+    // source and destination families when uploading on the transfer queue
+    ownership_transfer: Option<(u32, u32)>,
+    acquire_barriers: AcquireBarriers
+}
+
+/// The graphics family's halves of the ownership transfers a batch released
+#[derive(Default)]
+struct AcquireBarriers {
+    buffers: Vec<vk::BufferMemoryBarrier>,
+    images: Vec<vk::ImageMemoryBarrier>
+}
+
+impl AcquireBarriers {
+    fn is_empty(&self) -> bool {
+        self.buffers.is_empty() && self.images.is_empty()
+    }
+}
+
+impl UploadBatch {
+    fn begin(&mut self) {
+        if !self.recording {
+            let begin_info = vk::CommandBufferBeginInfo::builder()
+                .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT)
+                .build();
+            unsafe {
+                self.device.borrow().get().begin_command_buffer(self.command_buffer, &begin_info)
+                    .expect("Failed to begin recording asset upload command buffer");
+            }
+            self.recording = true;
+        }
+    }
+
+    fn create_staging_buffer(&mut self, data: &[u8], name: &str) -> vk::Buffer {
+        let staging_create = BufferCreateInfo::new(
+            vk::BufferCreateInfo::builder()
+                .size(data.len() as vk::DeviceSize)
+                .usage(vk::BufferUsageFlags::TRANSFER_SRC)
+                .sharing_mode(vk::SharingMode::EXCLUSIVE)
+                .build(),
+            format!("{}_staging", name));
+        let staging_buffer = DeviceWrapper::create_buffer(
+            self.device.clone(),
+            &staging_create,
+            MemoryLocation::CpuToGpu);
+        self.device.borrow().update_buffer(&staging_buffer, |mapped_memory: *mut c_void, _size: u64| {
+            unsafe {
+                core::ptr::copy_nonoverlapping(
+                    data.as_ptr(),
+                    mapped_memory as *mut u8,
+                    data.len());
+            }
+        });
+
+        let buffer = staging_buffer.get_buffer().buffer;
+        self.staged_size += data.len() as vk::DeviceSize;
+        // kept alive until the submission's fence has signaled
+        self.staging_buffers.push(staging_buffer);
+        buffer
+    }
+
+    /// A GPU-only buffer with `usage` holding `data`
+    pub fn upload_buffer(&mut self, data: &[u8], usage: vk::BufferUsageFlags, name: &str) -> DeviceResource {
+        let buffer_create = BufferCreateInfo::new(
+            vk::BufferCreateInfo::builder()
+                .size(data.len() as vk::DeviceSize)
+                .usage(usage | vk::BufferUsageFlags::TRANSFER_DST)
+                .sharing_mode(vk::SharingMode::EXCLUSIVE)
+                .build(),
+            name.to_string());
+        let buffer = DeviceWrapper::create_buffer(
+            self.device.clone(),
+            &buffer_create,
+            MemoryLocation::GpuOnly);
+
+        let staging_buffer = self.create_staging_buffer(data, name);
+        self.begin();
+        let copy_region = vk::BufferCopy::builder()
+            .src_offset(0)
+            .dst_offset(0)
+            .size(data.len() as vk::DeviceSize)
+            .build();
+        unsafe {
+            self.device.borrow().get().cmd_copy_buffer(
+                self.command_buffer,
+                staging_buffer,
+                buffer.get_buffer().buffer,
+                std::slice::from_ref(&copy_region));
+        }
+
+        if let Some((src_queue_family, dst_queue_family)) = self.ownership_transfer {
+            let ownership_barrier = |src_access_mask, dst_access_mask| {
+                vk::BufferMemoryBarrier::builder()
+                    .buffer(buffer.get_buffer().buffer)
+                    .offset(0)
+                    .size(vk::WHOLE_SIZE)
+                    .src_queue_family_index(src_queue_family)
+                    .dst_queue_family_index(dst_queue_family)
+                    .src_access_mask(src_access_mask)
+                    .dst_access_mask(dst_access_mask)
+                    .build()
+            };
+            let release_barrier = ownership_barrier(vk::AccessFlags::TRANSFER_WRITE, vk::AccessFlags::NONE);
+            unsafe {
+                self.device.borrow().get().cmd_pipeline_barrier(
+                    self.command_buffer,
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                    vk::DependencyFlags::empty(),
+                    &[],
+                    std::slice::from_ref(&release_barrier),
+                    &[]);
+            }
+            self.acquire_barriers.buffers.push(ownership_barrier(vk::AccessFlags::NONE, vk::AccessFlags::MEMORY_READ));
+        }
+
+        buffer
+    }
+
+    /// A GPU-only image created from `image_info` holding `data`, left in
+    /// SHADER_READ_ONLY_OPTIMAL. Only the first mip and layer are filled in
+    pub fn upload_image(&mut self, image_info: vk::ImageCreateInfo, data: &[u8], name: &str) -> DeviceResource {
+        let image_info = vk::ImageCreateInfo {
+            usage: image_info.usage | vk::ImageUsageFlags::TRANSFER_DST,
+            ..image_info
+        };
+        let mut image = DeviceWrapper::create_image(
+            self.device.clone(),
+            &ImageCreateInfo::new(image_info, name.to_string(), ImageType::Color),
+            MemoryLocation::GpuOnly);
+
+        let staging_buffer = self.create_staging_buffer(data, name);
+        self.begin();
+
+        let subresource_range = vk::ImageSubresourceRange::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .base_mip_level(0)
+            .level_count(1)
+            .base_array_layer(0)
+            .layer_count(1)
+            .build();
+        let pre_barrier = vk::ImageMemoryBarrier::builder()
+            .image(image.get_image().image)
+            .old_layout(vk::ImageLayout::UNDEFINED)
+            .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+            .subresource_range(subresource_range)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .src_access_mask(vk::AccessFlags::NONE)
+            .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .build();
+        // with an ownership transfer, this releases the image and an identical layout
+        // transition acquires it in the graphics family
+        let (src_queue_family, dst_queue_family) = self.ownership_transfer
+            .unwrap_or((vk::QUEUE_FAMILY_IGNORED, vk::QUEUE_FAMILY_IGNORED));
+        let post_barrier = |src_access_mask, dst_access_mask| {
+            vk::ImageMemoryBarrier::builder()
+                .image(image.get_image().image)
+                .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                .subresource_range(subresource_range)
+                .src_queue_family_index(src_queue_family)
+                .dst_queue_family_index(dst_queue_family)
+                .src_access_mask(src_access_mask)
+                .dst_access_mask(dst_access_mask)
+                .build()
+        };
+        let (release_barrier, post_barrier_dst_stage) = match self.ownership_transfer {
+            Some(_) => {
+                self.acquire_barriers.images.push(post_barrier(vk::AccessFlags::NONE, vk::AccessFlags::SHADER_READ));
+                (post_barrier(vk::AccessFlags::TRANSFER_WRITE, vk::AccessFlags::NONE), vk::PipelineStageFlags::BOTTOM_OF_PIPE)
+            },
+            None => {
+                (post_barrier(vk::AccessFlags::TRANSFER_WRITE, vk::AccessFlags::SHADER_READ),
+                 vk::PipelineStageFlags::VERTEX_SHADER | vk::PipelineStageFlags::FRAGMENT_SHADER)
+            }
+        };
+        let copy_region = vk::BufferImageCopy::builder()
+            .buffer_offset(0)
+            .buffer_row_length(0)
+            .buffer_image_height(0)
+            .image_subresource(vk::ImageSubresourceLayers::builder()
+                .aspect_mask(vk::ImageAspectFlags::COLOR)
+                .mip_level(0)
+                .base_array_layer(0)
+                .layer_count(1)
+                .build())
+            .image_offset(vk::Offset3D { x: 0, y: 0, z: 0 })
+            .image_extent(image_info.extent)
+            .build();
+
+        unsafe {
+            let device = self.device.borrow();
+            device.get().cmd_pipeline_barrier(
+                self.command_buffer,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                std::slice::from_ref(&pre_barrier));
+            device.get().cmd_copy_buffer_to_image(
+                self.command_buffer,
+                staging_buffer,
+                image.get_image().image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                std::slice::from_ref(&copy_region));
+            device.get().cmd_pipeline_barrier(
+                self.command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                post_barrier_dst_stage,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                std::slice::from_ref(&release_barrier));
+        }
+
+        // let the framegraph know this image has already been transitioned
+        self.device.borrow_mut().update_resource_state(image.get_handle(), ResourceState {
+            access: vk::AccessFlags::SHADER_READ,
+            stage: vk::PipelineStageFlags::VERTEX_SHADER | vk::PipelineStageFlags::FRAGMENT_SHADER,
+            layout: Some(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+        });
+        image.get_image_mut().layout = vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL;
+
+        image
+    }
+
+    /// Bytes staged so far in this poll
+    pub fn get_staged_size(&self) -> vk::DeviceSize {
+        self.staged_size
+    }
+}
+
+/// An asset on its way through the loader, type-erased so they can be polled together
+trait PendingAsset {
+    /// Uploads the asset into `batch` if it's been decoded, returning what finishes it once
+    /// the batch has completed. None while it's still decoding
+    fn try_upload(&mut self, batch: &mut UploadBatch) -> Option<Box<dyn FnOnce()>>;
+}
+
+struct Pending<D, T> {
+    receiver: mpsc::Receiver<Result<D, String>>,
+    upload: Option<Box<dyn FnOnce(D, &mut UploadBatch) -> T>>,
+    handle: AssetHandle<T>
+}
+
+impl<D, T: 'static> PendingAsset for Pending<D, T> {
+    fn try_upload(&mut self, batch: &mut UploadBatch) -> Option<Box<dyn FnOnce()>> {
+        let decoded = match self.receiver.try_recv() {
+            Ok(decoded) => decoded,
+            Err(mpsc::TryRecvError::Empty) => return None,
+            Err(mpsc::TryRecvError::Disconnected) => Err("Asset decoding panicked".to_string())
+        };
+
+        match decoded {
+            Ok(decoded) => {
+                let upload = self.upload.take().expect("Asset was already uploaded");
+                let asset = upload(decoded, batch);
+                *self.handle.state.borrow_mut() = AssetState::Uploading;
+                let state = self.handle.state.clone();
+                Some(Box::new(move || {
+                    *state.borrow_mut() = AssetState::Ready(asset);
+                }))
+            },
+            Err(error) => {
+                log::warn!(target: "asset_loader", "Failed to load {}: {}", self.handle.name, error);
+                *self.handle.state.borrow_mut() = AssetState::Failed(error);
+                Some(Box::new(|| {}))
+            }
+        }
+    }
+}
+
+/// Handing uploaded resources over from the transfer family to the graphics family
+struct QueueOwnershipTransfer {
+    graphics_queue: vk::Queue,
+    graphics_queue_family: u32,
+    transfer_queue_family: u32,
+    // for the acquiring submissions
+    graphics_command_pool: vk::CommandPool
+}
+
+/// What a batch is recorded and submitted with, reused once its fence has signaled
+#[derive(Copy, Clone)]
+struct Submission {
+    command_buffer: vk::CommandBuffer,
+    // only with an ownership transfer
+    acquire_command_buffer: Option<vk::CommandBuffer>,
+    fence: vk::Fence
+}
+
+struct InFlightUpload {
+    submission: Submission,
+    // released once the uploads have completed
+    _staging_buffers: Vec<DeviceResource>,
+    completions: Vec<Box<dyn FnOnce()>>,
+    // submitted to the graphics queue once the transfer has completed
+    acquire_barriers: Option<AcquireBarriers>
+}
+
+type Job = Box<dyn FnOnce() + Send>;
+
+struct WorkerPool {
+    sender: Option<mpsc::Sender<Job>>,
+    threads: Vec<JoinHandle<()>>
+}
+
+impl WorkerPool {
+    fn new(worker_count: usize) -> Self {
+        assert!(worker_count > 0, "The asset loader needs at least one worker thread");
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let threads = (0..worker_count).map(|index| {
+            let receiver = receiver.clone();
+            std::thread::Builder::new()
+                .name(format!("asset_loader_{}", index))
+                .spawn(move || loop {
+                    // the lock is released before running the job
+                    let job = receiver.lock().expect("Asset loader job queue was poisoned").recv();
+                    match job {
+                        Ok(job) => job(),
+                        // the pool has been dropped
+                        Err(_) => break
+                    }
+                })
+                .expect("Failed to spawn asset loader worker thread")
+        }).collect();
+
+        WorkerPool {
+            sender: Some(sender),
+            threads
+        }
+    }
+
+    fn execute(&self, job: Job) {
+        self.sender.as_ref().unwrap().send(job)
+            .expect("Asset loader worker threads have exited");
+    }
+}
+
+impl Drop for WorkerPool {
+    fn drop(&mut self) {
+        self.sender = None;
+        for thread in self.threads.drain(..) {
+            // a panicking job has already been reported through its asset
+            let _ = thread.join();
+        }
+    }
+}
+
+pub struct AssetLoader {
+    device: Rc<RefCell<DeviceWrapper>>,
+    // the transfer queue if there is one, otherwise the graphics queue
+    queue: vk::Queue,
+    command_pool: vk::CommandPool,
+    ownership_transfer: Option<QueueOwnershipTransfer>,
+    workers: WorkerPool,
+    pending: Vec<Box<dyn PendingAsset>>,
+    in_flight: Vec<InFlightUpload>,
+    free_submissions: Vec<Submission>,
+    submission_count: usize,
+    upload_budget: vk::DeviceSize
+}
+
+impl std::fmt::Debug for AssetLoader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AssetLoader")
+            .field("pending", &self.pending.len())
+            .field("in flight", &self.in_flight.len())
+            .field("transfer queue", &self.ownership_transfer.is_some())
+            .field("upload budget", &self.upload_budget)
+            .finish()
+    }
+}
+
+fn create_command_pool(device: &DeviceWrapper, queue_family_index: u32, name: &str) -> vk::CommandPool {
+    let create_info = vk::CommandPoolCreateInfo::builder()
+        .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER)
+        .queue_family_index(queue_family_index)
+        .build();
+    let command_pool = unsafe {
+        device.get().create_command_pool(&create_info, None)
+            .expect("Failed to create asset loader command pool")
+    };
+    device.set_debug_name(vk::ObjectType::COMMAND_POOL, command_pool.as_raw(), name);
+    command_pool
+}
+
+fn allocate_command_buffer(device: &DeviceWrapper, command_pool: vk::CommandPool, name: &str) -> vk::CommandBuffer {
+    let allocate_info = vk::CommandBufferAllocateInfo::builder()
+        .command_pool(command_pool)
+        .level(vk::CommandBufferLevel::PRIMARY)
+        .command_buffer_count(1)
+        .build();
+    let command_buffer = unsafe {
+        device.get().allocate_command_buffers(&allocate_info)
+            .expect("Failed to allocate asset loader command buffer")[0]
+    };
+    device.set_debug_name(vk::ObjectType::COMMAND_BUFFER, command_buffer.as_raw(), name);
+    command_buffer
+}
+
+impl AssetLoader {
+    /// Uploads are submitted to the render context's transfer queue if it has one, otherwise
+    /// to its graphics queue
+    pub fn new(render_context: &VulkanRenderContext, worker_count: usize) -> Self {
+        let device = render_context.get_device();
+        let graphics_queue_family = render_context.get_graphics_queue_index();
+
+        let (queue, command_pool, ownership_transfer) = match (render_context.get_transfer_queue(), render_context.get_transfer_queue_index()) {
+            (Some(transfer_queue), Some(transfer_queue_family)) => {
+                let ownership_transfer = QueueOwnershipTransfer {
+                    graphics_queue: render_context.get_graphics_queue(),
+                    graphics_queue_family,
+                    transfer_queue_family,
+                    graphics_command_pool: create_command_pool(&device.borrow(), graphics_queue_family, "asset_loader_acquire_command_pool")
+                };
+                (transfer_queue,
+                 create_command_pool(&device.borrow(), transfer_queue_family, "asset_loader_command_pool"),
+                 Some(ownership_transfer))
+            },
+            _ => {
+                (render_context.get_graphics_queue(),
+                 create_command_pool(&device.borrow(), graphics_queue_family, "asset_loader_command_pool"),
+                 None)
+            }
+        };
+
+        AssetLoader {
+            device,
+            queue,
+            command_pool,
+            ownership_transfer,
+            workers: WorkerPool::new(worker_count),
+            pending: Vec::new(),
+            in_flight: Vec::new(),
+            free_submissions: Vec::new(),
+            submission_count: 0,
+            upload_budget: DEFAULT_UPLOAD_BUDGET
+        }
+    }
+
+    /// Whether uploads run on a dedicated transfer queue
+    pub fn uses_transfer_queue(&self) -> bool {
+        self.ownership_transfer.is_some()
+    }
+
+    /// Limits how much a single poll uploads. An asset larger than the budget is still
+    /// uploaded, on its own
+    pub fn set_upload_budget(&mut self, upload_budget: vk::DeviceSize) {
+        self.upload_budget = upload_budget;
+    }
+
+    /// Runs `decode` on a worker thread, then `upload` on this thread during the first poll
+    /// after it has finished. The handle is ready once the uploads `upload` recorded have
+    /// completed, or has failed if `decode` returned an error or panicked
+    pub fn load<D, T, Decode, Upload>(&mut self, name: &str, decode: Decode, upload: Upload) -> AssetHandle<T>
+        where D: Send + 'static,
+              T: 'static,
+              Decode: FnOnce() -> Result<D, String> + Send + 'static,
+              Upload: FnOnce(D, &mut UploadBatch) -> T + 'static {
+
+        log::trace!(target: "asset_loader", "Loading {}", name);
+        let (sender, receiver) = mpsc::channel();
+        self.workers.execute(Box::new(move || {
+            // the asset may have been dropped along with the loader
+            let _ = sender.send(decode());
+        }));
+
+        let handle = AssetHandle {
+            name: name.to_string(),
+            state: Rc::new(RefCell::new(AssetState::Loading))
+        };
+        self.pending.push(Box::new(Pending {
+            receiver,
+            upload: Some(Box::new(upload)),
+            handle: handle.clone()
+        }));
+        handle
+    }
+
+    /// Assets which haven't become ready or failed yet
+    pub fn get_pending_count(&self) -> usize {
+        self.pending.len() + self.in_flight.iter().map(|upload| upload.completions.len()).sum::<usize>()
+    }
+
+    /// Finishes assets whose uploads have completed, then uploads newly decoded assets within
+    /// the upload budget. Call once per frame; it never waits on the GPU
+    pub fn poll(&mut self) {
+        self.retire_completed();
+
+        if self.pending.is_empty() {
+            return;
+        }
+
+        let submission = self.acquire_submission();
+        let mut batch = UploadBatch {
+            device: self.device.clone(),
+            command_buffer: submission.command_buffer,
+            recording: false,
+            staging_buffers: Vec::new(),
+            staged_size: 0,
+            ownership_transfer: self.ownership_transfer.as_ref()
+                .map(|transfer| (transfer.transfer_queue_family, transfer.graphics_queue_family)),
+            acquire_barriers: AcquireBarriers::default()
+        };
+
+        let mut completions: Vec<Box<dyn FnOnce()>> = Vec::new();
+        let mut index = 0;
+        while index < self.pending.len() && batch.staged_size < self.upload_budget {
+            match self.pending[index].try_upload(&mut batch) {
+                Some(completion) => {
+                    completions.push(completion);
+                    // keeps assets in the order they were requested
+                    self.pending.remove(index);
+                },
+                None => {
+                    index += 1;
+                }
+            }
+        }
+
+        if !batch.recording {
+            // nothing was uploaded, but failures can be reported straight away
+            for completion in completions {
+                completion();
+            }
+            self.free_submissions.push(submission);
+            return;
+        }
+
+        unsafe {
+            let device = self.device.borrow();
+            if self.ownership_transfer.is_none() {
+                // makes the uploads visible to everything submitted after this; with an
+                // ownership transfer the release barriers already have
+                let memory_barrier = vk::MemoryBarrier::builder()
+                    .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                    .dst_access_mask(vk::AccessFlags::MEMORY_READ)
+                    .build();
+                device.get().cmd_pipeline_barrier(
+                    submission.command_buffer,
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::PipelineStageFlags::ALL_COMMANDS,
+                    vk::DependencyFlags::empty(),
+                    std::slice::from_ref(&memory_barrier),
+                    &[],
+                    &[]);
+            }
+            device.get().end_command_buffer(submission.command_buffer)
+                .expect("Failed to finish recording asset upload command buffer");
+
+            let submit = vk::SubmitInfo::builder()
+                .command_buffers(std::slice::from_ref(&submission.command_buffer))
+                .build();
+            device.get().queue_submit(self.queue, std::slice::from_ref(&submit), submission.fence)
+                .expect("Failed to submit asset uploads");
+        }
+        log::trace!(target: "asset_loader", "Submitted {} bytes of uploads for {} assets", batch.staged_size, completions.len());
+
+        let acquire_barriers = match batch.acquire_barriers.is_empty() {
+            true => None,
+            false => Some(batch.acquire_barriers)
+        };
+        self.in_flight.push(InFlightUpload {
+            submission,
+            _staging_buffers: batch.staging_buffers,
+            completions,
+            acquire_barriers
+        });
+    }
+
+    fn retire_completed(&mut self) {
+        let in_flight = std::mem::take(&mut self.in_flight);
+        for mut upload in in_flight {
+            let signaled = unsafe {
+                self.device.borrow().get().get_fence_status(upload.submission.fence)
+                    .expect("Failed to query asset upload fence")
+            };
+            if !signaled {
+                self.in_flight.push(upload);
+                continue;
+            }
+
+            unsafe {
+                self.device.borrow().get().reset_fences(std::slice::from_ref(&upload.submission.fence))
+                    .expect("Failed to reset asset upload fence");
+            }
+
+            // the transfer has completed, but the graphics family still has to acquire
+            // the resources before they're ready
+            if let Some(acquire_barriers) = upload.acquire_barriers.take() {
+                self.submit_acquire(&upload.submission, &acquire_barriers);
+                self.in_flight.push(upload);
+                continue;
+            }
+
+            for completion in upload.completions {
+                completion();
+            }
+            self.free_submissions.push(upload.submission);
+        }
+    }
+
+    fn submit_acquire(&self, submission: &Submission, acquire_barriers: &AcquireBarriers) {
+        let ownership_transfer = self.ownership_transfer.as_ref().expect("Acquiring uploads without an ownership transfer");
+        let command_buffer = submission.acquire_command_buffer.expect("Submission has no acquire command buffer");
+        let device = self.device.borrow();
+        unsafe {
+            device.get().reset_command_buffer(command_buffer, vk::CommandBufferResetFlags::empty())
+                .expect("Failed to reset asset acquire command buffer");
+            let begin_info = vk::CommandBufferBeginInfo::builder()
+                .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT)
+                .build();
+            device.get().begin_command_buffer(command_buffer, &begin_info)
+                .expect("Failed to begin recording asset acquire command buffer");
+            device.get().cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                vk::PipelineStageFlags::ALL_COMMANDS,
+                vk::DependencyFlags::empty(),
+                &[],
+                &acquire_barriers.buffers,
+                &acquire_barriers.images);
+            device.get().end_command_buffer(command_buffer)
+                .expect("Failed to finish recording asset acquire command buffer");
+
+            let submit = vk::SubmitInfo::builder()
+                .command_buffers(std::slice::from_ref(&command_buffer))
+                .build();
+            device.get().queue_submit(ownership_transfer.graphics_queue, std::slice::from_ref(&submit), submission.fence)
+                .expect("Failed to submit asset acquires");
+        }
+    }
+
+    fn acquire_submission(&mut self) -> Submission {
+        if let Some(submission) = self.free_submissions.pop() {
+            unsafe {
+                self.device.borrow().get().reset_command_buffer(submission.command_buffer, vk::CommandBufferResetFlags::empty())
+                    .expect("Failed to reset asset upload command buffer");
+            }
+            return submission;
+        }
+
+        let device = self.device.borrow();
+        let index = self.submission_count;
+        self.submission_count += 1;
+        let fence = unsafe {
+            device.get().create_fence(&vk::FenceCreateInfo::builder().build(), None)
+                .expect("Failed to create asset upload fence")
+        };
+        device.set_debug_name(vk::ObjectType::FENCE, fence.as_raw(), &format!("asset_upload_fence_{}", index));
+
+        Submission {
+            command_buffer: allocate_command_buffer(&device, self.command_pool, &format!("asset_upload_{}", index)),
+            acquire_command_buffer: self.ownership_transfer.as_ref().map(|transfer| {
+                allocate_command_buffer(&device, transfer.graphics_command_pool, &format!("asset_acquire_{}", index))
+            }),
+            fence
+        }
+    }
+}
+
+impl Drop for AssetLoader {
+    fn drop(&mut self) {
+        let device = self.device.borrow();
+        unsafe {
+            let fences: Vec<vk::Fence> = self.in_flight.iter().map(|upload| upload.submission.fence).collect();
+            if !fences.is_empty() {
+                device.get().wait_for_fences(&fences, true, u64::MAX)
+                    .expect("Failed to wait for asset uploads");
+            }
+            for fence in fences.into_iter().chain(self.free_submissions.iter().map(|submission| submission.fence)) {
+                device.get().destroy_fence(fence, None);
+            }
+            // also frees the command buffers
+            device.get().destroy_command_pool(self.command_pool, None);
+            if let Some(ownership_transfer) = &self.ownership_transfer {
+                device.get().destroy_command_pool(ownership_transfer.graphics_command_pool, None);
+            }
+        }
+    }
+}
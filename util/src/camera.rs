@@ -1,4 +1,56 @@
 use glm;
+
+/// Sub-pixel offsets cycled through by halton_jitter
+pub const JITTER_SAMPLES: u32 = 8;
+
+fn halton(mut index: u32, base: u32) -> f32 {
+    let mut fraction = 1.0;
+    let mut result = 0.0;
+    while index > 0 {
+        fraction /= base as f32;
+        result += fraction * (index % base) as f32;
+        index /= base;
+    }
+    result
+}
+
+/// The offset in pixels, within [-0.5, 0.5], to jitter the projection by on `frame_index` for
+/// temporal anti-aliasing. Follows the (2, 3) Halton sequence, skipping its first point since
+/// it's always the origin
+pub fn halton_jitter(frame_index: u32) -> glm::Vec2 {
+    let index = frame_index % JITTER_SAMPLES + 1;
+    glm::vec2(halton(index, 2) - 0.5, halton(index, 3) - 0.5)
+}
+
+/// A right-handed perspective projection mapping the near plane to depth 1 and the far plane
+/// to depth 0. Floating point depth is most precise close to 0, which balances out the
+/// perspective divide crowding distant depths together, so there's much less z-fighting at
+/// long view distances. Needs a GREATER_OR_EQUAL depth test and depth cleared to 0.0
+pub fn reverse_z_perspective(aspect: f32, vertical_fov: f32, near: f32, far: f32) -> glm::Mat4 {
+    glm::perspective_rh_zo(aspect, vertical_fov, far, near)
+}
+
+/// Like reverse_z_perspective with the far plane at infinity
+pub fn infinite_reverse_z_perspective(aspect: f32, vertical_fov: f32, near: f32) -> glm::Mat4 {
+    let focal_length = 1.0 / (vertical_fov * 0.5).tan();
+    glm::Mat4::new(
+        focal_length / aspect, 0.0, 0.0, 0.0,
+        0.0, focal_length, 0.0, 0.0,
+        0.0, 0.0, 0.0, near,
+        0.0, 0.0, -1.0, 0.0)
+}
+
+/// Converts a projection with OpenGL's [-1, 1] depth range, like those Camera is created with,
+/// to reverse-Z (see reverse_z_perspective)
+pub fn to_reverse_z(projection: &glm::Mat4) -> glm::Mat4 {
+    let remap_depth = glm::Mat4::new(
+        1.0, 0.0, 0.0, 0.0,
+        0.0, 1.0, 0.0, 0.0,
+        0.0, 0.0, -0.5, 0.5,
+        0.0, 0.0, 0.0, 1.0);
+    remap_depth * projection
+}
+
 #[derive(Clone)]
 pub struct Camera {
     pub projection: glm::TMat4<f32>,
@@ -52,4 +104,135 @@ impl Camera {
     pub fn get_view(&self) -> glm::Mat4 {
         self.view.try_inverse().unwrap()
     }
-}
\ No newline at end of file
+
+    /// The projection offset by `jitter` pixels of a `width` by `height` target, so successive
+    /// frames sample different points within each pixel (see halton_jitter)
+    pub fn get_jittered_projection(&self, jitter: &glm::Vec2, width: u32, height: u32) -> glm::Mat4 {
+        let offset = glm::vec3(2.0 * jitter.x / width as f32, 2.0 * jitter.y / height as f32, 0.0);
+        glm::translation(&offset) * self.projection
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(actual: f32, expected: f32) {
+        assert!((actual - expected).abs() < 1e-4, "{} isn't {}", actual, expected);
+    }
+
+    /// The NDC depth of a point `distance` in front of a camera looking down -Z
+    fn depth_at(projection: &glm::Mat4, distance: f32) -> f32 {
+        let clip = projection * glm::vec4(0.0, 0.0, -distance, 1.0);
+        clip.z / clip.w
+    }
+
+    #[test]
+    fn reverse_z_maps_near_to_one_and_far_to_zero() {
+        let projection = reverse_z_perspective(16.0 / 9.0, 1.0, 0.1, 100.0);
+        assert_near(depth_at(&projection, 0.1), 1.0);
+        assert_near(depth_at(&projection, 100.0), 0.0);
+    }
+
+    #[test]
+    fn reverse_z_depth_decreases_with_distance() {
+        let projection = reverse_z_perspective(16.0 / 9.0, 1.0, 0.1, 100.0);
+        let depths: Vec<f32> = [0.1, 1.0, 10.0, 50.0, 100.0].iter()
+            .map(|distance| depth_at(&projection, *distance))
+            .collect();
+        assert!(depths.windows(2).all(|pair| pair[0] > pair[1]), "Depths {:?} don't decrease", depths);
+    }
+
+    #[test]
+    fn infinite_reverse_z_approaches_zero_at_infinity() {
+        let projection = infinite_reverse_z_perspective(16.0 / 9.0, 1.0, 0.1);
+        assert_near(depth_at(&projection, 0.1), 1.0);
+        assert_near(depth_at(&projection, 1.0), 0.1);
+        assert!(depth_at(&projection, 1.0e6) > 0.0);
+        assert_near(depth_at(&projection, 1.0e6), 0.0);
+    }
+
+    #[test]
+    fn infinite_reverse_z_matches_the_finite_projection_in_x_and_y() {
+        let finite = reverse_z_perspective(16.0 / 9.0, 1.0, 0.1, 100.0);
+        let infinite = infinite_reverse_z_perspective(16.0 / 9.0, 1.0, 0.1);
+        let point = glm::vec4(0.7, -1.3, -5.0, 1.0);
+        let (finite_clip, infinite_clip) = (finite * point, infinite * point);
+        assert_near(finite_clip.x / finite_clip.w, infinite_clip.x / infinite_clip.w);
+        assert_near(finite_clip.y / finite_clip.w, infinite_clip.y / infinite_clip.w);
+    }
+
+    #[test]
+    fn converted_projection_is_reverse_z() {
+        // Camera's projections have OpenGL's [-1, 1] depth range
+        let projection = glm::perspective(16.0 / 9.0, 1.0, 0.1, 100.0);
+        assert_near(depth_at(&projection, 0.1), -1.0);
+        assert_near(depth_at(&projection, 100.0), 1.0);
+
+        let converted = to_reverse_z(&projection);
+        assert_near(depth_at(&converted, 0.1), 1.0);
+        assert_near(depth_at(&converted, 100.0), 0.0);
+    }
+
+    #[test]
+    fn jitter_follows_the_halton_sequence() {
+        // index 1 of the (2, 3) sequence is (1/2, 1/3) and index 2 is (1/4, 2/3)
+        let first = halton_jitter(0);
+        assert_near(first.x, 0.0);
+        assert_near(first.y, 1.0 / 3.0 - 0.5);
+        let second = halton_jitter(1);
+        assert_near(second.x, -0.25);
+        assert_near(second.y, 2.0 / 3.0 - 0.5);
+    }
+
+    #[test]
+    fn jitter_stays_within_the_pixel() {
+        for frame_index in 0..JITTER_SAMPLES {
+            let jitter = halton_jitter(frame_index);
+            assert!((-0.5..=0.5).contains(&jitter.x) && (-0.5..=0.5).contains(&jitter.y),
+                "Jitter {} of frame {} is outside the pixel", jitter, frame_index);
+        }
+    }
+
+    #[test]
+    fn jitter_never_repeats_within_a_cycle() {
+        for a in 0..JITTER_SAMPLES {
+            for b in (a + 1)..JITTER_SAMPLES {
+                assert_ne!(halton_jitter(a), halton_jitter(b), "Frames {} and {} share a jitter", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn jitter_cycles_every_sample_count() {
+        for frame_index in 0..JITTER_SAMPLES {
+            assert_eq!(halton_jitter(frame_index), halton_jitter(frame_index + JITTER_SAMPLES));
+            assert_eq!(halton_jitter(frame_index), halton_jitter(frame_index + 5 * JITTER_SAMPLES));
+        }
+    }
+
+    #[test]
+    fn jittered_projection_offsets_by_whole_pixels() {
+        let camera = Camera::new(
+            16.0 / 9.0,
+            1.0,
+            0.1,
+            100.0,
+            &glm::vec3(0.0, 0.0, 5.0),
+            &glm::vec3(0.0, 0.0, 0.0),
+            &glm::vec3(0.0, 1.0, 0.0));
+        let point = glm::vec4(0.3, -0.2, -4.0, 1.0);
+        let (width, height) = (1920, 1080);
+
+        let to_ndc = |projection: &glm::Mat4| {
+            let clip = projection * point;
+            glm::vec2(clip.x / clip.w, clip.y / clip.w)
+        };
+        let unjittered = to_ndc(&camera.projection);
+        let jittered = to_ndc(&camera.get_jittered_projection(&glm::vec2(0.5, -0.25), width, height));
+
+        // NDC spans 2 units across the target
+        assert_near((jittered.x - unjittered.x) * width as f32 / 2.0, 0.5);
+        assert_near((jittered.y - unjittered.y) * height as f32 / 2.0, -0.25);
+    }
+}
@@ -0,0 +1,209 @@
+use glm;
+use crate::camera::Camera;
+
+// keeps look_at away from a degenerate up vector
+const MAX_PITCH: f32 = std::f32::consts::FRAC_PI_2 - 0.01;
+
+/// What a camera controller consumes each frame, independent of the windowing library
+#[derive(Copy, Clone, Debug, Default)]
+pub struct CameraInput {
+    /// Movement along the camera's right, up and forward axes, each from -1 to 1
+    pub movement: glm::Vec3,
+    /// Mouse movement in pixels since the last frame; only applied while `rotating`
+    pub look_delta: glm::Vec2,
+    pub rotating: bool,
+    /// Scroll wheel lines since the last frame, positive away from the user
+    pub zoom_delta: f32,
+    /// Moves faster while held
+    pub boost: bool
+}
+
+pub trait CameraController {
+    fn update(&mut self, input: &CameraInput, delta_time: f32);
+
+    /// The camera's world transform, i.e. the inverse of its view matrix
+    fn get_transform(&self) -> glm::Mat4;
+
+    fn apply(&self, camera: &mut Camera) {
+        camera.view = self.get_transform();
+    }
+}
+
+/// How far to move towards a target this frame for a smoothing time constant in seconds,
+/// where 0 jumps straight to the target
+fn smoothing_factor(smoothing: f32, delta_time: f32) -> f32 {
+    if smoothing <= 0.0 {
+        1.0
+    } else {
+        1.0 - (-delta_time / smoothing).exp()
+    }
+}
+
+fn get_forward(yaw: f32, pitch: f32) -> glm::Vec3 {
+    glm::Vec3::new(
+        -yaw.sin() * pitch.cos(),
+        pitch.sin(),
+        -yaw.cos() * pitch.cos())
+}
+
+/// Yaw and pitch of a forward vector, the inverse of get_forward
+fn get_yaw_pitch(forward: &glm::Vec3) -> (f32, f32) {
+    let forward = glm::normalize(forward);
+    ((-forward.x).atan2(-forward.z), forward.y.clamp(-1.0, 1.0).asin())
+}
+
+fn look_at_transform(eye: &glm::Vec3, forward: &glm::Vec3) -> glm::Mat4 {
+    glm::look_at(eye, &(eye + forward), &glm::Vec3::new(0.0, 1.0, 0.0))
+        .try_inverse()
+        .expect("Camera look-at transform isn't invertible")
+}
+
+/// Rotates around and zooms towards a target point, e.g. for inspecting a model. Movement
+/// pans the target in the camera's plane
+pub struct OrbitController {
+    pub target: glm::Vec3,
+    pub distance: f32,
+    pub yaw: f32,
+    pub pitch: f32,
+    /// Radians per pixel of mouse movement
+    pub rotate_speed: f32,
+    /// Fraction of the distance zoomed per scroll line
+    pub zoom_speed: f32,
+    /// Fraction of the distance panned per second
+    pub pan_speed: f32,
+    pub min_distance: f32,
+    /// Time constant in seconds for the camera to catch up with its input
+    pub smoothing: f32,
+    current_target: glm::Vec3,
+    current_distance: f32,
+    current_yaw: f32,
+    current_pitch: f32
+}
+
+impl OrbitController {
+    pub fn new(target: glm::Vec3, distance: f32, yaw: f32, pitch: f32) -> Self {
+        OrbitController {
+            target,
+            distance,
+            yaw,
+            pitch,
+            rotate_speed: 0.005,
+            zoom_speed: 0.1,
+            pan_speed: 0.5,
+            min_distance: 0.01,
+            smoothing: 0.08,
+            current_target: target,
+            current_distance: distance,
+            current_yaw: yaw,
+            current_pitch: pitch
+        }
+    }
+
+    /// Orbits `target` from `eye`
+    pub fn looking_at(eye: &glm::Vec3, target: &glm::Vec3) -> Self {
+        let offset = target - eye;
+        let (yaw, pitch) = get_yaw_pitch(&offset);
+        OrbitController::new(*target, glm::length(&offset), yaw, pitch)
+    }
+}
+
+impl CameraController for OrbitController {
+    fn update(&mut self, input: &CameraInput, delta_time: f32) {
+        if input.rotating {
+            self.yaw -= input.look_delta.x * self.rotate_speed;
+            self.pitch = (self.pitch - input.look_delta.y * self.rotate_speed).clamp(-MAX_PITCH, MAX_PITCH);
+        }
+        self.distance = (self.distance * (1.0 - self.zoom_speed).powf(input.zoom_delta)).max(self.min_distance);
+
+        let forward = get_forward(self.current_yaw, self.current_pitch);
+        let up = glm::Vec3::new(0.0, 1.0, 0.0);
+        let right = glm::normalize(&glm::cross(&forward, &up));
+        let camera_up = glm::cross(&right, &forward);
+        let pan = right * input.movement.x + camera_up * input.movement.y + forward * input.movement.z;
+        self.target += pan * self.distance * self.pan_speed * delta_time;
+
+        let factor = smoothing_factor(self.smoothing, delta_time);
+        self.current_target += (self.target - self.current_target) * factor;
+        self.current_distance += (self.distance - self.current_distance) * factor;
+        self.current_yaw += (self.yaw - self.current_yaw) * factor;
+        self.current_pitch += (self.pitch - self.current_pitch) * factor;
+    }
+
+    fn get_transform(&self) -> glm::Mat4 {
+        let forward = get_forward(self.current_yaw, self.current_pitch);
+        let eye = self.current_target - forward * self.current_distance;
+        look_at_transform(&eye, &forward)
+    }
+}
+
+/// First person movement, looking around while rotating. Scrolling changes the move speed
+pub struct FlyController {
+    pub position: glm::Vec3,
+    pub yaw: f32,
+    pub pitch: f32,
+    /// World units per second
+    pub move_speed: f32,
+    /// Radians per pixel of mouse movement
+    pub look_speed: f32,
+    pub boost_multiplier: f32,
+    /// Time constant in seconds for the camera to catch up with its input
+    pub smoothing: f32,
+    velocity: glm::Vec3,
+    current_yaw: f32,
+    current_pitch: f32
+}
+
+impl FlyController {
+    pub fn new(position: glm::Vec3, yaw: f32, pitch: f32, move_speed: f32) -> Self {
+        FlyController {
+            position,
+            yaw,
+            pitch,
+            move_speed,
+            look_speed: 0.003,
+            boost_multiplier: 4.0,
+            smoothing: 0.08,
+            velocity: glm::Vec3::zeros(),
+            current_yaw: yaw,
+            current_pitch: pitch
+        }
+    }
+
+    /// Starts from the position and direction of a camera's world transform
+    pub fn from_transform(transform: &glm::Mat4, move_speed: f32) -> Self {
+        let position = glm::Vec3::new(transform[(0, 3)], transform[(1, 3)], transform[(2, 3)]);
+        let forward = -glm::Vec3::new(transform[(0, 2)], transform[(1, 2)], transform[(2, 2)]);
+        let (yaw, pitch) = get_yaw_pitch(&forward);
+        FlyController::new(position, yaw, pitch, move_speed)
+    }
+}
+
+impl CameraController for FlyController {
+    fn update(&mut self, input: &CameraInput, delta_time: f32) {
+        if input.rotating {
+            self.yaw -= input.look_delta.x * self.look_speed;
+            self.pitch = (self.pitch - input.look_delta.y * self.look_speed).clamp(-MAX_PITCH, MAX_PITCH);
+        }
+        self.move_speed *= 1.1f32.powf(input.zoom_delta);
+
+        let factor = smoothing_factor(self.smoothing, delta_time);
+        self.current_yaw += (self.yaw - self.current_yaw) * factor;
+        self.current_pitch += (self.pitch - self.current_pitch) * factor;
+
+        let forward = get_forward(self.current_yaw, self.current_pitch);
+        let up = glm::Vec3::new(0.0, 1.0, 0.0);
+        let right = glm::normalize(&glm::cross(&forward, &up));
+        let camera_up = glm::cross(&right, &forward);
+        let speed = match input.boost {
+            true => self.move_speed * self.boost_multiplier,
+            false => self.move_speed
+        };
+        let target_velocity = (right * input.movement.x + camera_up * input.movement.y + forward * input.movement.z) * speed;
+        self.velocity += (target_velocity - self.velocity) * factor;
+        self.position += self.velocity * delta_time;
+    }
+
+    fn get_transform(&self) -> glm::Mat4 {
+        look_at_transform(&self.position, &get_forward(self.current_yaw, self.current_pitch))
+    }
+}
@@ -0,0 +1,218 @@
+use std::time::Duration;
+
+/// How DynamicResolution reacts to the GPU frame time
+#[derive(Clone, Debug)]
+pub struct DynamicResolutionSettings {
+    /// The GPU frame time to hold
+    pub target_frame_time: Duration,
+    /// The bounds of the scale applied to each dimension of the render resolution
+    pub min_scale: f32,
+    pub max_scale: f32,
+    /// The fraction of the target the smoothed frame time must drop below before the scale
+    /// is raised, so a scale which only just fits isn't raised straight back over the target
+    pub headroom: f32,
+    /// The number of consecutive frames the frame time must be over (or under) the target
+    /// before the scale is changed
+    pub hysteresis_frames: u32,
+    /// How much the scale changes by at a time
+    pub step: f32,
+    /// The weight of each new frame time in the smoothed frame time
+    pub smoothing: f32
+}
+
+impl Default for DynamicResolutionSettings {
+    fn default() -> Self {
+        DynamicResolutionSettings {
+            target_frame_time: Duration::from_micros(16_600),
+            min_scale: 0.5,
+            max_scale: 1.0,
+            headroom: 0.1,
+            hysteresis_frames: 10,
+            step: 0.05,
+            smoothing: 0.1
+        }
+    }
+}
+
+/// Scales the internal render resolution to hold a target GPU frame time. The scale moves in
+/// fixed steps between the settings' bounds, and only once the frame time has stayed over the
+/// target, or under it by the headroom, for the hysteresis frames. It's applied by handing it
+/// to VulkanRenderContext::set_resolution_scale
+pub struct DynamicResolution {
+    pub settings: DynamicResolutionSettings,
+    enabled: bool,
+    // the number of steps below max_scale
+    level: u32,
+    smoothed_frame_time: Option<Duration>,
+    frames_over: u32,
+    frames_under: u32
+}
+
+impl DynamicResolution {
+    pub fn new(settings: DynamicResolutionSettings) -> Self {
+        DynamicResolution {
+            settings,
+            enabled: false,
+            level: 0,
+            smoothed_frame_time: None,
+            frames_over: 0,
+            frames_under: 0
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool { self.enabled }
+
+    /// Disabling returns the scale to max_scale
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.level = 0;
+        }
+        self.frames_over = 0;
+        self.frames_under = 0;
+    }
+
+    /// The scale applied to each dimension of the render resolution
+    pub fn get_scale(&self) -> f32 {
+        let settings = &self.settings;
+        (settings.max_scale - self.level as f32 * settings.step).max(settings.min_scale)
+    }
+
+    pub fn get_smoothed_frame_time(&self) -> Option<Duration> { self.smoothed_frame_time }
+
+    /// Feeds in the GPU time of the latest frame to be read back, returning whether the scale
+    /// changed
+    pub fn update(&mut self, frame_time: Duration) -> bool {
+        let smoothed = match self.smoothed_frame_time {
+            Some(smoothed) => smoothed.mul_f32(1.0 - self.settings.smoothing) + frame_time.mul_f32(self.settings.smoothing),
+            None => frame_time
+        };
+        self.smoothed_frame_time = Some(smoothed);
+
+        if !self.enabled {
+            return false;
+        }
+
+        let target = self.settings.target_frame_time;
+        if smoothed > target {
+            self.frames_over += 1;
+            self.frames_under = 0;
+        } else if smoothed < target.mul_f32(1.0 - self.settings.headroom) {
+            self.frames_under += 1;
+            self.frames_over = 0;
+        } else {
+            self.frames_over = 0;
+            self.frames_under = 0;
+        }
+
+        let hysteresis = self.settings.hysteresis_frames.max(1);
+        let previous_scale = self.get_scale();
+        if self.frames_over >= hysteresis {
+            if previous_scale > self.settings.min_scale {
+                self.level += 1;
+            }
+            self.frames_over = 0;
+        } else if self.frames_under >= hysteresis {
+            self.level = self.level.saturating_sub(1);
+            self.frames_under = 0;
+        }
+
+        self.get_scale() != previous_scale
+    }
+}
+
+impl Default for DynamicResolution {
+    fn default() -> Self {
+        DynamicResolution::new(DynamicResolutionSettings::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TARGET: Duration = Duration::from_millis(10);
+
+    fn controller(hysteresis_frames: u32) -> DynamicResolution {
+        let mut dynamic_resolution = DynamicResolution::new(DynamicResolutionSettings {
+            target_frame_time: TARGET,
+            min_scale: 0.5,
+            max_scale: 1.0,
+            headroom: 0.2,
+            hysteresis_frames,
+            step: 0.25,
+            // no smoothing, so each update sees exactly the frame time it's given
+            smoothing: 1.0
+        });
+        dynamic_resolution.set_enabled(true);
+        dynamic_resolution
+    }
+
+    #[test]
+    fn scale_drops_after_hysteresis_frames_over_target() {
+        let mut dynamic_resolution = controller(3);
+        assert!(!dynamic_resolution.update(Duration::from_millis(15)));
+        assert!(!dynamic_resolution.update(Duration::from_millis(15)));
+        assert_eq!(dynamic_resolution.get_scale(), 1.0);
+        assert!(dynamic_resolution.update(Duration::from_millis(15)));
+        assert_eq!(dynamic_resolution.get_scale(), 0.75);
+    }
+
+    #[test]
+    fn frames_within_headroom_reset_hysteresis() {
+        let mut dynamic_resolution = controller(3);
+        dynamic_resolution.update(Duration::from_millis(15));
+        dynamic_resolution.update(Duration::from_millis(15));
+        // between the headroom and the target, so neither raised nor lowered
+        dynamic_resolution.update(Duration::from_millis(9));
+        dynamic_resolution.update(Duration::from_millis(15));
+        dynamic_resolution.update(Duration::from_millis(15));
+        assert_eq!(dynamic_resolution.get_scale(), 1.0);
+    }
+
+    #[test]
+    fn scale_rises_only_below_headroom() {
+        let mut dynamic_resolution = controller(1);
+        dynamic_resolution.update(Duration::from_millis(15));
+        dynamic_resolution.update(Duration::from_millis(15));
+        assert_eq!(dynamic_resolution.get_scale(), 0.5);
+
+        // under the target, but not by the headroom
+        assert!(!dynamic_resolution.update(Duration::from_millis(9)));
+        assert_eq!(dynamic_resolution.get_scale(), 0.5);
+        assert!(dynamic_resolution.update(Duration::from_millis(7)));
+        assert_eq!(dynamic_resolution.get_scale(), 0.75);
+    }
+
+    #[test]
+    fn scale_stays_within_bounds() {
+        let mut dynamic_resolution = controller(1);
+        for _ in 0..10 {
+            dynamic_resolution.update(Duration::from_millis(30));
+        }
+        assert_eq!(dynamic_resolution.get_scale(), 0.5);
+        // a single step back up from the minimum, however long it was over the target
+        dynamic_resolution.update(Duration::from_millis(1));
+        assert_eq!(dynamic_resolution.get_scale(), 0.75);
+        for _ in 0..10 {
+            dynamic_resolution.update(Duration::from_millis(1));
+        }
+        assert_eq!(dynamic_resolution.get_scale(), 1.0);
+    }
+
+    #[test]
+    fn disabled_controller_only_smooths() {
+        let mut dynamic_resolution = controller(1);
+        dynamic_resolution.settings.smoothing = 0.5;
+        dynamic_resolution.update(Duration::from_millis(30));
+        dynamic_resolution.update(Duration::from_millis(30));
+        assert_eq!(dynamic_resolution.get_scale(), 0.5);
+
+        dynamic_resolution.set_enabled(false);
+        assert_eq!(dynamic_resolution.get_scale(), 1.0);
+        assert!(!dynamic_resolution.update(Duration::from_millis(10)));
+        assert_eq!(dynamic_resolution.get_scale(), 1.0);
+        let smoothed = dynamic_resolution.get_smoothed_frame_time().expect("No smoothed frame time");
+        assert!((smoothed.as_secs_f32() - 0.02).abs() < 1e-6);
+    }
+}
@@ -10,6 +10,7 @@ use image::DynamicImage::*;
 use api_types::buffer::BufferCreateInfo;
 use api_types::device::{DeviceResource, DeviceWrapper, ResourceType};
 use api_types::image::{ImageCreateInfo, ImageType};
+use api_types::resource_state::ResourceState;
 use context::vulkan_render_context::VulkanRenderContext;
 
 pub fn create_from_bytes(
@@ -177,6 +178,13 @@ pub fn create_from_bytes(
                 .expect("Error when waiting for buffer->image copy");
         }
 
+        // let the framegraph know this image has already been transitioned
+        render_context.update_resource_state(image.get_handle(), ResourceState {
+            access: vk::AccessFlags::SHADER_READ,
+            stage: vk::PipelineStageFlags::VERTEX_SHADER,
+            layout: Some(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+        });
+
         image
     }
 }
@@ -241,6 +249,7 @@ pub fn create_from_uri(
 pub fn get_aspect_mask_from_format(format: vk::Format) -> vk::ImageAspectFlags {
     match format {
         vk::Format::D16_UNORM |
+        vk::Format::X8_D24_UNORM_PACK32 |
         vk::Format::D32_SFLOAT => {
             vk::ImageAspectFlags::DEPTH
         },
@@ -260,3 +269,20 @@ pub fn get_aspect_mask_from_format(format: vk::Format) -> vk::ImageAspectFlags {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn depth_formats_have_a_depth_aspect() {
+        for format in [vk::Format::D16_UNORM, vk::Format::X8_D24_UNORM_PACK32, vk::Format::D32_SFLOAT] {
+            assert_eq!(get_aspect_mask_from_format(format), vk::ImageAspectFlags::DEPTH, "{:?}", format);
+        }
+        for format in [vk::Format::D16_UNORM_S8_UINT, vk::Format::D24_UNORM_S8_UINT, vk::Format::D32_SFLOAT_S8_UINT] {
+            assert_eq!(get_aspect_mask_from_format(format), vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL, "{:?}", format);
+        }
+        assert_eq!(get_aspect_mask_from_format(vk::Format::S8_UINT), vk::ImageAspectFlags::STENCIL);
+        assert_eq!(get_aspect_mask_from_format(vk::Format::R16G16B16A16_SFLOAT), vk::ImageAspectFlags::COLOR);
+    }
+}
@@ -1,5 +1,9 @@
+pub mod asset_loader;
 pub mod camera;
+pub mod camera_controller;
+pub mod dynamic_resolution;
 pub mod math;
+pub mod transform_history;
 pub mod image;
 
 extern crate nalgebra_glm as glm;
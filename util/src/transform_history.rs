@@ -0,0 +1,29 @@
+use std::collections::HashMap;
+use glm;
+
+/// The model-view-projection each object was drawn with in the previous frame, for passes
+/// writing motion vectors. Objects are identified by any id which is stable across frames
+#[derive(Default)]
+pub struct TransformHistory {
+    previous: HashMap<u64, glm::Mat4>,
+    current: HashMap<u64, glm::Mat4>
+}
+
+impl TransformHistory {
+    pub fn new() -> Self {
+        TransformHistory::default()
+    }
+
+    /// Records the transform `id` is drawn with this frame, returning the one it was drawn with
+    /// in the previous frame. Objects which weren't drawn then are treated as not having moved
+    pub fn record(&mut self, id: u64, model_view_projection: &glm::Mat4) -> glm::Mat4 {
+        self.current.insert(id, *model_view_projection);
+        self.previous.get(&id).copied().unwrap_or(*model_view_projection)
+    }
+
+    /// Makes this frame's transforms the previous ones. Objects which weren't drawn this frame
+    /// are forgotten
+    pub fn end_frame(&mut self) {
+        self.previous = std::mem::take(&mut self.current);
+    }
+}